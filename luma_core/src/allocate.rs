@@ -1,11 +1,152 @@
-use alloc::alloc::{Layout, alloc};
+use crate::cache::{DCFlushRange, DCInvalidateRange};
+use crate::mem::PhysAddr;
+use alloc::alloc::{Allocator, Global, Layout, alloc, handle_alloc_error};
 use alloc::boxed::Box;
+use core::ops::{Deref, DerefMut};
 use core::pin::Pin;
+use core::ptr::NonNull;
 use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 const CACHELINE: usize = 32;
 
+/// Who a [`DmaBuffer`] currently belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Owner {
+    /// The processor, which goes through the data cache.
+    Cpu,
+    /// The hardware, which reads and writes memory directly.
+    Device,
+}
+
+/// A buffer the hardware reads or writes by DMA, aligned to a cache line and padded to a whole
+/// one, so that nothing else shares its cache lines.
+///
+/// It belongs either to the processor or to the hardware.  Handing it over with
+/// [`DmaBuffer::give_to_device`] flushes what the processor wrote out of the cache, and accessing
+/// its contents takes it back, invalidating the cache so that what the hardware wrote gets read.
+pub struct DmaBuffer<T: Copy, A: Allocator = Global> {
+    pointer: NonNull<T>,
+    length: usize,
+    on_device: AtomicBool,
+    allocator: A,
+}
+
+unsafe impl<T: Copy + Send, A: Allocator + Send> Send for DmaBuffer<T, A> {}
+unsafe impl<T: Copy + Sync, A: Allocator + Sync> Sync for DmaBuffer<T, A> {}
+
+impl<T: Copy + Default> DmaBuffer<T> {
+    /// Allocate a buffer of ``length`` default values on the heap.
+    pub fn new(length: usize) -> DmaBuffer<T> {
+        DmaBuffer::new_in(length, Global)
+    }
+}
+
+impl<T: Copy + Default, A: Allocator> DmaBuffer<T, A> {
+    /// Allocate a buffer of ``length`` default values with ``allocator``, such as a
+    /// [`crate::heap::Arena`] for hardware which only reaches one of them.
+    pub fn new_in(length: usize, allocator: A) -> DmaBuffer<T, A> {
+        let layout = Self::layout(length);
+        let pointer = match allocator.allocate(layout) {
+            Ok(pointer) => pointer.cast::<T>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        for i in 0..length {
+            unsafe { pointer.add(i).write(T::default()) };
+        }
+        DmaBuffer {
+            pointer,
+            length,
+            on_device: AtomicBool::new(false),
+            allocator,
+        }
+    }
+}
+
+impl<T: Copy, A: Allocator> DmaBuffer<T, A> {
+    /// # Panics:
+    /// This function will panic if ``length`` values don’t fit in memory.
+    fn layout(length: usize) -> Layout {
+        Layout::array::<T>(length)
+            .and_then(|layout| layout.align_to(CACHELINE))
+            .expect("DMA buffer too large")
+            .pad_to_align()
+    }
+
+    /// Get the amount of values in this buffer, whoever it belongs to.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whether this buffer holds no value.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Get who this buffer currently belongs to.
+    pub fn owner(&self) -> Owner {
+        if self.on_device.load(Ordering::Acquire) {
+            Owner::Device
+        } else {
+            Owner::Cpu
+        }
+    }
+
+    /// Hand this buffer over to the hardware, flushing what the processor wrote to it, and get
+    /// its physical address for the hardware registers.
+    pub fn give_to_device(&mut self) -> PhysAddr {
+        let size = Self::layout(self.length).size();
+        if !self.on_device.swap(true, Ordering::AcqRel) && size != 0 {
+            unsafe { DCFlushRange(self.pointer.as_ptr() as *const u32, size as u32) };
+        }
+        PhysAddr::of(self.pointer.as_ptr())
+    }
+
+    /// Take this buffer back from the hardware, dropping its cache lines so that what the
+    /// hardware wrote gets read.  Accessing its contents does that already.
+    pub fn take_from_device(&self) {
+        let size = Self::layout(self.length).size();
+        if self.on_device.swap(false, Ordering::AcqRel) && size != 0 {
+            unsafe { DCInvalidateRange(self.pointer.as_ptr() as *const u32, size as u32) };
+        }
+    }
+
+    /// Return the raw pointer to this buffer, without taking it back from the hardware.
+    pub fn as_ptr(&self) -> *const T {
+        self.pointer.as_ptr()
+    }
+
+    /// Return the raw mutable pointer to this buffer, without taking it back from the hardware.
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.pointer.as_ptr()
+    }
+}
+
+impl<T: Copy, A: Allocator> Deref for DmaBuffer<T, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.take_from_device();
+        unsafe { slice::from_raw_parts(self.pointer.as_ptr(), self.length) }
+    }
+}
+
+impl<T: Copy, A: Allocator> DerefMut for DmaBuffer<T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.take_from_device();
+        unsafe { slice::from_raw_parts_mut(self.pointer.as_ptr(), self.length) }
+    }
+}
+
+impl<T: Copy, A: Allocator> Drop for DmaBuffer<T, A> {
+    fn drop(&mut self) {
+        let layout = Self::layout(self.length);
+        unsafe { self.allocator.deallocate(self.pointer.cast(), layout) };
+    }
+}
+
 /// Allocate a slice aligned to a cacheline, and return it pinned.
+#[deprecated = "use DmaBuffer, which also keeps the cache coherent with the hardware"]
 pub fn alloc_aligned<T: Copy>(size: usize) -> Pin<Box<[T]>> {
     let layout = Layout::array::<T>(size)
         .unwrap()
@@ -18,6 +159,7 @@ pub fn alloc_aligned<T: Copy>(size: usize) -> Pin<Box<[T]>> {
 }
 
 /// Allocate an array aligned to a cacheline, and return it pinned.
+#[deprecated = "use DmaBuffer, which also keeps the cache coherent with the hardware"]
 pub fn alloc_array_aligned<const LENGTH: usize>() -> Pin<Box<[u8; LENGTH]>> {
     let layout = Layout::from_size_align(LENGTH, CACHELINE).unwrap();
     let ptr = unsafe { alloc(layout) };
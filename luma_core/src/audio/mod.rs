@@ -0,0 +1,258 @@
+//! ``audio`` module of ``luma_core``.
+//!
+//! Contains functions for the Audio Interface, or AI, and for the DSP DMA which feeds it with
+//! 16-bit big-endian stereo PCM samples from main memory.
+
+use crate::allocate::DmaBuffer;
+use crate::dsp::{self, DspControl};
+use crate::interrupt;
+use crate::io::{read16, read32, write16, write32};
+use crate::mem::PhysAddr;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+pub use luma_formats::audio::{SampleRate, adpcm, mixer, ogg, vorbis, wav};
+pub use mixer::{Mixer, Sound, VoiceId};
+pub use vorbis::Vorbis;
+pub use wav::Wav;
+
+const AI_CONTROL: PhysAddr = PhysAddr::new(0x0d00_6c00);
+const AI_VOLUME: PhysAddr = PhysAddr::new(0x0d00_6c04);
+const AI_SAMPLE_COUNTER: PhysAddr = PhysAddr::new(0x0d00_6c08);
+const AI_INTERRUPT_TIMING: PhysAddr = PhysAddr::new(0x0d00_6c0c);
+
+const DSP_DMA_START_HIGH: PhysAddr = PhysAddr::new(0x0c00_5030);
+const DSP_DMA_START_LOW: PhysAddr = PhysAddr::new(0x0c00_5032);
+const DSP_DMA_CONTROL: PhysAddr = PhysAddr::new(0x0c00_5036);
+const DSP_DMA_BLOCKS_LEFT: PhysAddr = PhysAddr::new(0x0c00_503a);
+
+bitflags::bitflags! {
+    struct AiControl: u32 {
+        /// Play the streamed (disc) audio.
+        const PLAY = 1 << 0;
+        /// Streaming sample rate, 48 kHz when set and 32 kHz otherwise.
+        const STREAM_48KHZ = 1 << 1;
+        const INTERRUPT_MASK = 1 << 2;
+        const INTERRUPT = 1 << 3;
+        const INTERRUPT_VALID = 1 << 4;
+        const SAMPLE_COUNTER_RESET = 1 << 5;
+        /// DMA sample rate, 32 kHz when set and 48 kHz otherwise.
+        const DMA_32KHZ = 1 << 6;
+    }
+}
+
+/// Reset the AI, mute the streamed audio, and route the AI DMA interrupt to this module.
+///
+/// This must be called after [`crate::interrupt::init`].
+pub fn init() {
+    let control = AiControl::from_bits_retain(read32(AI_CONTROL))
+        .difference(AiControl::INTERRUPT_VALID | AiControl::INTERRUPT_MASK | AiControl::PLAY);
+    write32(AI_CONTROL, control.bits());
+    write32(AI_VOLUME, 0);
+    write32(AI_INTERRUPT_TIMING, 0);
+    write32(
+        AI_CONTROL,
+        (control | AiControl::SAMPLE_COUNTER_RESET).bits(),
+    );
+
+    set_dsp_sample_rate(SampleRate::Khz32);
+    set_stream_sample_rate(SampleRate::Khz48);
+
+    dsp::init_interrupt();
+    DspControl::update(|control| control.insert(DspControl::AI_INTERRUPT_MASK));
+}
+
+fn update_control(f: impl FnOnce(&mut AiControl)) {
+    interrupt::free(|| {
+        // Writing the interrupt status back would acknowledge it.
+        let mut control =
+            AiControl::from_bits_retain(read32(AI_CONTROL)).difference(AiControl::INTERRUPT);
+        f(&mut control);
+        write32(AI_CONTROL, control.bits());
+    })
+}
+
+/// Set the sample rate of the DSP DMA playback.
+pub fn set_dsp_sample_rate(rate: SampleRate) {
+    update_control(|control| control.set(AiControl::DMA_32KHZ, rate == SampleRate::Khz32));
+}
+
+/// Get the sample rate of the DSP DMA playback.
+pub fn dsp_sample_rate() -> SampleRate {
+    if AiControl::from_bits_retain(read32(AI_CONTROL)).contains(AiControl::DMA_32KHZ) {
+        SampleRate::Khz32
+    } else {
+        SampleRate::Khz48
+    }
+}
+
+/// Set the sample rate of the streamed (disc) audio.
+pub fn set_stream_sample_rate(rate: SampleRate) {
+    update_control(|control| control.set(AiControl::STREAM_48KHZ, rate == SampleRate::Khz48));
+}
+
+/// Set the volume of the streamed (disc) audio, for each channel.
+pub fn set_stream_volume(left: u8, right: u8) {
+    write32(AI_VOLUME, ((right as u32) << 8) | left as u32);
+}
+
+/// Get the volume of the streamed (disc) audio, for each channel.
+pub fn stream_volume() -> (u8, u8) {
+    let volume = read32(AI_VOLUME);
+    (volume as u8, (volume >> 8) as u8)
+}
+
+/// Start or stop playing the streamed (disc) audio.
+pub fn set_stream_playing(playing: bool) {
+    update_control(|control| control.set(AiControl::PLAY, playing));
+}
+
+/// Get the amount of streamed samples played since the last reset.
+pub fn stream_sample_count() -> u32 {
+    read32(AI_SAMPLE_COUNTER)
+}
+
+/// Reset the streamed sample counter.
+pub fn reset_stream_sample_count() {
+    update_control(|control| control.insert(AiControl::SAMPLE_COUNTER_RESET));
+}
+
+/// Make the DMA fetch a buffer of ``length`` bytes at physical ``address`` the next time it wraps
+/// around.  Both must be multiples of 32.
+fn set_dma_buffer(address: PhysAddr, length: u32) {
+    let address = address.value();
+    write16(DSP_DMA_START_HIGH, (address >> 16) as u16);
+    write16(DSP_DMA_START_LOW, (address & 0xffe0) as u16);
+    let control = read16(DSP_DMA_CONTROL) & 0x8000;
+    write16(DSP_DMA_CONTROL, control | (length >> 5) as u16);
+}
+
+fn set_dma_enabled(enabled: bool) {
+    let control = read16(DSP_DMA_CONTROL) & 0x7fff;
+    write16(DSP_DMA_CONTROL, control | ((enabled as u16) << 15));
+}
+
+/// Get the amount of 32-byte blocks left to play in the current DMA buffer.
+pub fn dma_blocks_left() -> u16 {
+    read16(DSP_DMA_BLOCKS_LEFT) & 0x7fff
+}
+
+/// Callback filling a buffer with interleaved stereo samples, left first.
+pub type Refill = dyn FnMut(&mut [i16]) + Send;
+
+/// State of the ring of buffers, only ever touched from the AI DMA interrupt once started.
+struct Ring {
+    buffers: Vec<DmaBuffer<i16>>,
+    /// The buffer the DMA will fetch after the one currently playing.
+    queued: usize,
+    refill: Box<Refill>,
+}
+
+impl Ring {
+    fn fill(&mut self, index: usize) {
+        let buffer = &mut self.buffers[index];
+        (self.refill)(buffer);
+        buffer.give_to_device();
+    }
+
+    fn queue(&mut self, index: usize) {
+        let buffer = &mut self.buffers[index];
+        set_dma_buffer(buffer.give_to_device(), buffer.len() as u32 * 2);
+        self.queued = index;
+    }
+}
+
+static RING: AtomicPtr<Ring> = AtomicPtr::new(ptr::null_mut());
+
+/// The DMA latched the queued buffer and started playing it, queue the next one and refill the
+/// one after, which isn’t going to be played before the next interrupt.
+pub(crate) fn on_dma_interrupt() {
+    let ring = RING.load(Ordering::Acquire);
+    if ring.is_null() {
+        return;
+    }
+    let ring = unsafe { &mut *ring };
+    let count = ring.buffers.len();
+    let next = (ring.queued + 1) % count;
+    ring.queue(next);
+    ring.fill((next + 1) % count);
+}
+
+/// Errors which can be encountered while starting a [`Playback`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackError {
+    /// Another playback is already running, there is only one DMA.
+    AlreadyRunning,
+}
+
+/// Playback of a ring of PCM buffers through the DSP DMA, refilled from the AI DMA interrupt.
+///
+/// Playback stops when this gets dropped.
+pub struct Playback {
+    ring: *mut Ring,
+}
+
+impl Playback {
+    /// Start playing ``buffers`` buffers of ``frames`` stereo frames each, at the given rate.
+    ///
+    /// ``refill`` is called with interrupts disabled, once per buffer, to produce the samples;
+    /// the latency is thus about ``buffers - 1`` buffers.
+    ///
+    /// # Panics:
+    /// This function will panic if there are less than three buffers, or if ``frames`` isn’t a
+    /// multiple of 8 (a buffer must be a whole amount of 32-byte blocks).
+    pub fn start(
+        rate: SampleRate,
+        buffers: usize,
+        frames: usize,
+        refill: impl FnMut(&mut [i16]) + Send + 'static,
+    ) -> Result<Playback, PlaybackError> {
+        assert!(buffers >= 3, "Playback needs at least three buffers");
+        assert!(
+            frames > 0 && frames.is_multiple_of(8),
+            "Buffers must be multiples of 32 bytes"
+        );
+        assert!(frames * 4 <= 0x7fff * 32, "Buffers are too big for the DMA");
+
+        let ring = Box::new(Ring {
+            buffers: (0..buffers).map(|_| DmaBuffer::new(frames * 2)).collect(),
+            queued: 0,
+            refill: Box::new(refill),
+        });
+        let ring = Box::into_raw(ring);
+        if RING
+            .compare_exchange(ptr::null_mut(), ring, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            drop(unsafe { Box::from_raw(ring) });
+            return Err(PlaybackError::AlreadyRunning);
+        }
+
+        set_dsp_sample_rate(rate);
+        interrupt::free(|| {
+            let ring = unsafe { &mut *ring };
+            ring.fill(0);
+            ring.fill(1);
+            // Start with the first buffer, the interrupt fires right away and queues the second.
+            ring.queue(0);
+            set_dma_enabled(true);
+        });
+
+        Ok(Playback { ring })
+    }
+
+    /// Stop the playback, equivalent to dropping it.
+    pub fn stop(self) {}
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        interrupt::free(|| {
+            set_dma_enabled(false);
+            RING.store(ptr::null_mut(), Ordering::Release);
+        });
+        drop(unsafe { Box::from_raw(self.ring) });
+    }
+}
@@ -0,0 +1,7 @@
+//! ``block`` module of ``luma_core``.
+//!
+//! Contains the [`BlockDevice`] trait, implemented by the storage drivers and used by the
+//! filesystems.  It lives in ``luma_fat``, so that the filesystem can be tested on the host
+//! against disk images.
+
+pub use luma_fat::block::BlockDevice;
@@ -0,0 +1,690 @@
+//! ``bluetooth`` module of ``luma_core``.
+//!
+//! Contains a minimal Bluetooth host, talking HCI to the controller owned by the IOS USB module
+//! at ``/dev/usb/oh1``, and L2CAP to the connected devices.  It only does what Wii Remotes need:
+//! accepting connections from known devices, pairing new ones with the SYNC PIN, and opening
+//! the HID channels.
+//!
+//! The host only goes through the [`Transport`] trait, so it can be driven by something else
+//! than the hardware.
+
+use crate::ios::{self, Handle, IosError, Mode, Pending};
+use crate::sysconf::SysConf;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::mem::ManuallyDrop;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+pub use luma_formats::bluetooth::{hci, l2cap};
+
+use hci::{AclPacket, Address, Event, LinkKey};
+use l2cap::{Link, LinkEvent, PSM_HID_CONTROL, PSM_HID_INTERRUPT, Reassembly};
+
+/// The Bluetooth controller, as exposed by IOS.
+const DEVICE: &str = "/dev/usb/oh1/57e/305";
+
+const IOCTL_CONTROL: u32 = 0;
+const IOCTL_BULK: u32 = 1;
+const IOCTL_INTERRUPT: u32 = 2;
+
+const ENDPOINT_EVENTS: u8 = 0x81;
+const ENDPOINT_ACL_IN: u8 = 0x82;
+const ENDPOINT_ACL_OUT: u8 = 0x02;
+
+/// Control request carrying an HCI command: class request to the interface.
+const REQUEST_TYPE_COMMAND: u8 = 0x20;
+
+/// Biggest event, with its two byte header, rounded up to a cache line.
+const EVENT_SIZE: usize = 288;
+
+/// Biggest ACL packet we accept, rounded up to a cache line.
+const ACL_SIZE: usize = 1024;
+
+/// Classes of device of the Wii Remote and the Wii Remote Plus.
+const WII_REMOTE_CLASSES: [u32; 2] = [0x00_2504, 0x00_0508];
+
+/// How long to look for Wii Remotes when pairing, in units of 1.28 s.
+const INQUIRY_LENGTH: u8 = 8;
+
+/// How long to page a device before giving up, in 0.625 ms slots.
+const PAGE_TIMEOUT: u16 = 0x2000;
+
+/// The way HCI packets reach the controller.
+pub trait Transport {
+    /// Send a command packet, failing with [`IosError::Invalid`] if it is too long.
+    fn send_command(&mut self, packet: &[u8]) -> Result<(), IosError>;
+
+    /// Send an ACL data packet, failing with [`IosError::Invalid`] if it is too long.
+    fn send_acl(&mut self, packet: &[u8]) -> Result<(), IosError>;
+
+    /// Copy the next event packet into ``buffer`` and return its length, if one arrived.
+    fn receive_event(&mut self, buffer: &mut [u8]) -> Option<usize>;
+
+    /// Copy the next ACL data packet into ``buffer`` and return its length, if one arrived.
+    fn receive_acl(&mut self, buffer: &mut [u8]) -> Option<usize>;
+}
+
+/// Buffers IOS reads from and writes to while transfers are in flight.
+#[repr(C, align(32))]
+struct Buffers {
+    event: [u8; EVENT_SIZE],
+    acl_in: [u8; ACL_SIZE],
+    outgoing: [u8; ACL_SIZE],
+    event_endpoint: [u8; 32],
+    event_length: [u8; 32],
+    acl_endpoint: [u8; 32],
+    acl_length: [u8; 32],
+}
+
+static BUFFERS: AtomicPtr<Buffers> = AtomicPtr::new(ptr::null_mut());
+static OPEN: AtomicBool = AtomicBool::new(false);
+
+/// The Bluetooth controller of the Wii, through IOS.
+///
+/// Only one can be open at a time, since they all share the same buffers.
+pub struct Oh1 {
+    /// Closed on drop before the buffers get released, cancelling the transfers in flight.
+    handle: ManuallyDrop<Handle>,
+    buffers: &'static mut Buffers,
+    event: Option<Pending>,
+    acl: Option<Pending>,
+}
+
+impl Oh1 {
+    /// Open the controller, and start listening for events and ACL data.
+    ///
+    /// Fails with [`IosError::Busy`] if it is open already.
+    pub fn open() -> Result<Oh1, IosError> {
+        if OPEN.swap(true, Ordering::AcqRel) {
+            return Err(IosError::Busy);
+        }
+        let handle = match Handle::open(DEVICE, Mode::None) {
+            Ok(handle) => ManuallyDrop::new(handle),
+            Err(err) => {
+                OPEN.store(false, Ordering::Release);
+                return Err(err);
+            }
+        };
+        if BUFFERS.load(Ordering::Acquire).is_null() {
+            // Never freed, IOS may still write to them after a transfer got forgotten.
+            let buffers = Box::leak(Box::new(Buffers {
+                event: [0; EVENT_SIZE],
+                acl_in: [0; ACL_SIZE],
+                outgoing: [0; ACL_SIZE],
+                event_endpoint: [0; 32],
+                event_length: [0; 32],
+                acl_endpoint: [0; 32],
+                acl_length: [0; 32],
+            }));
+            BUFFERS.store(buffers, Ordering::Release);
+        }
+        let buffers = unsafe { &mut *BUFFERS.load(Ordering::Acquire) };
+        buffers.event_endpoint[0] = ENDPOINT_EVENTS;
+        buffers.event_length[..2].copy_from_slice(&(EVENT_SIZE as u16).to_be_bytes());
+        buffers.acl_endpoint[0] = ENDPOINT_ACL_IN;
+        buffers.acl_length[..2].copy_from_slice(&(ACL_SIZE as u16).to_be_bytes());
+
+        let mut oh1 = Oh1 {
+            handle,
+            buffers,
+            event: None,
+            acl: None,
+        };
+        oh1.submit_event()?;
+        oh1.submit_acl()?;
+        Ok(oh1)
+    }
+
+    fn submit_event(&mut self) -> Result<(), IosError> {
+        let buffers = &mut *self.buffers;
+        let pending = unsafe {
+            self.handle.ioctlv_async(
+                IOCTL_INTERRUPT,
+                &[&buffers.event_endpoint[..1], &buffers.event_length[..2]],
+                &mut [&mut buffers.event],
+                None,
+            )
+        }?;
+        self.event = Some(pending);
+        Ok(())
+    }
+
+    fn submit_acl(&mut self) -> Result<(), IosError> {
+        let buffers = &mut *self.buffers;
+        let pending = unsafe {
+            self.handle.ioctlv_async(
+                IOCTL_BULK,
+                &[&buffers.acl_endpoint[..1], &buffers.acl_length[..2]],
+                &mut [&mut buffers.acl_in],
+                None,
+            )
+        }?;
+        self.acl = Some(pending);
+        Ok(())
+    }
+
+    /// Copy out what a completed transfer got, and return it to ``buffer``.
+    fn take(pending: &mut Option<Pending>, data: &[u8], buffer: &mut [u8]) -> Option<usize> {
+        let result = pending.as_ref()?.poll()?;
+        *pending = None;
+        let length = (result.ok()? as usize).min(data.len()).min(buffer.len());
+        ios::invalidate(data);
+        buffer[..length].copy_from_slice(&data[..length]);
+        Some(length)
+    }
+}
+
+impl Drop for Oh1 {
+    fn drop(&mut self) {
+        self.event = None;
+        self.acl = None;
+        unsafe { ManuallyDrop::drop(&mut self.handle) };
+        OPEN.store(false, Ordering::Release);
+    }
+}
+
+impl Transport for Oh1 {
+    fn send_command(&mut self, packet: &[u8]) -> Result<(), IosError> {
+        let length = packet.len() as u16;
+        let outgoing = self
+            .buffers
+            .outgoing
+            .get_mut(..packet.len())
+            .ok_or(IosError::Invalid)?;
+        outgoing.copy_from_slice(packet);
+        // The setup packet is little-endian, as on the wire.
+        self.handle.ioctlv(
+            IOCTL_CONTROL,
+            &[
+                &[REQUEST_TYPE_COMMAND],
+                &[0],
+                &0u16.to_le_bytes(),
+                &0u16.to_le_bytes(),
+                &length.to_le_bytes(),
+                &[0],
+            ],
+            &mut [outgoing],
+        )?;
+        Ok(())
+    }
+
+    fn send_acl(&mut self, packet: &[u8]) -> Result<(), IosError> {
+        let length = packet.len() as u16;
+        let outgoing = self
+            .buffers
+            .outgoing
+            .get_mut(..packet.len())
+            .ok_or(IosError::Invalid)?;
+        outgoing.copy_from_slice(packet);
+        self.handle.ioctlv(
+            IOCTL_BULK,
+            &[&[ENDPOINT_ACL_OUT], &length.to_be_bytes()],
+            &mut [outgoing],
+        )?;
+        Ok(())
+    }
+
+    fn receive_event(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let length = Oh1::take(&mut self.event, &self.buffers.event, buffer);
+        if self.event.is_none() {
+            // Nothing is listening anymore if this fails, leave it to the next poll.
+            let _ = self.submit_event();
+        }
+        length
+    }
+
+    fn receive_acl(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let length = Oh1::take(&mut self.acl, &self.buffers.acl_in, buffer);
+        if self.acl.is_none() {
+            let _ = self.submit_acl();
+        }
+        length
+    }
+}
+
+/// Get the addresses of the devices registered with the console, as saved in the ``BT.DINF``
+/// item of SYSCONF.
+pub fn registered_devices(sysconf: &SysConf) -> Vec<Address> {
+    let Some(devices) = sysconf.get("BT.DINF") else {
+        return Vec::new();
+    };
+    // A count, followed by ten addresses each with a 64 bytes name.
+    let count = devices.first().map_or(0, |&count| count.min(10) as usize);
+    devices
+        .get(1..)
+        .unwrap_or(&[])
+        .chunks_exact(70)
+        .take(count)
+        .map(|device| {
+            let mut address = [0; 6];
+            address.copy_from_slice(&device[..6]);
+            Address::from_be_bytes(address)
+        })
+        .collect()
+}
+
+/// Something which happened on the host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostEvent {
+    /// The controller got initialized and is accepting connections.
+    Ready,
+    /// A device got connected.
+    Connected { handle: u16, address: Address },
+    /// A device got disconnected, along with all of its channels.
+    Disconnected { handle: u16 },
+    /// A device got paired, and its link key stored in the controller.
+    Paired { address: Address },
+    /// A channel got opened.
+    ChannelOpened { handle: u16, psm: u16 },
+    /// A channel got closed.
+    ChannelClosed { handle: u16, psm: u16 },
+    /// Data got received on a channel.
+    Data {
+        handle: u16,
+        psm: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// Where pairing is at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pairing {
+    Idle,
+    Inquiry,
+    Connecting(Address),
+}
+
+struct Connection {
+    handle: u16,
+    address: Address,
+    /// We created it, so we have to authenticate and open the channels.
+    outgoing: bool,
+    link: Link,
+    reassembly: Reassembly,
+}
+
+/// A Bluetooth host, over a [`Transport`].
+pub struct Host<T: Transport = Oh1> {
+    transport: T,
+    buffer: Box<[u8; ACL_SIZE]>,
+    commands: VecDeque<Vec<u8>>,
+    /// Commands the controller is ready to accept.
+    command_credits: u8,
+    acl: VecDeque<Vec<u8>>,
+    acl_mtu: usize,
+    /// ACL packets the controller has room for.
+    acl_credits: u16,
+    address: Address,
+    ready: bool,
+    keys: Vec<(Address, LinkKey)>,
+    known: Vec<Address>,
+    connections: Vec<Connection>,
+    pairing: Pairing,
+}
+
+impl Host<Oh1> {
+    /// Open the Bluetooth controller of the Wii, and start initializing it.
+    pub fn new() -> Result<Host<Oh1>, IosError> {
+        Ok(Host::with_transport(Oh1::open()?))
+    }
+}
+
+impl<T: Transport> Host<T> {
+    /// Drive a controller through another transport, resetting it first.
+    pub fn with_transport(transport: T) -> Host<T> {
+        let mut host = Host {
+            transport,
+            buffer: Box::new([0; ACL_SIZE]),
+            commands: VecDeque::new(),
+            command_credits: 1,
+            acl: VecDeque::new(),
+            acl_mtu: 27,
+            acl_credits: 0,
+            address: Address::default(),
+            ready: false,
+            keys: Vec::new(),
+            known: Vec::new(),
+            connections: Vec::new(),
+            pairing: Pairing::Idle,
+        };
+        host.command(hci::reset());
+        host.command(hci::read_bd_addr());
+        host.command(hci::read_buffer_size());
+        host.command(hci::clear_event_filters());
+        host.command(hci::read_all_stored_link_keys());
+        host.command(hci::write_page_timeout(PAGE_TIMEOUT));
+        host.command(hci::write_scan_enable(hci::SCAN_PAGE));
+        host
+    }
+
+    /// Accept connections from these devices, on top of the ones the controller has a link key
+    /// for and of every Wii Remote.
+    pub fn with_known_devices(mut self, devices: &[Address]) -> Host<T> {
+        self.known.extend_from_slice(devices);
+        self
+    }
+
+    /// Get the address of the controller, once it is ready.
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Whether the controller got initialized.
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Whether a Wii Remote is being looked for.
+    pub fn is_pairing(&self) -> bool {
+        self.pairing != Pairing::Idle
+    }
+
+    /// Look for a Wii Remote whose SYNC button got pressed, and pair with the first one found.
+    pub fn start_pairing(&mut self) {
+        if self.pairing == Pairing::Idle {
+            self.pairing = Pairing::Inquiry;
+            self.command(hci::inquiry(INQUIRY_LENGTH));
+        }
+    }
+
+    fn command(&mut self, packet: Vec<u8>) {
+        self.commands.push_back(packet);
+        self.flush_commands();
+    }
+
+    fn flush_commands(&mut self) {
+        while self.command_credits > 0 {
+            let Some(packet) = self.commands.pop_front() else {
+                break;
+            };
+            if self.transport.send_command(&packet).is_ok() {
+                self.command_credits -= 1;
+            }
+        }
+    }
+
+    fn flush_acl(&mut self) {
+        while self.acl_credits > 0 {
+            let Some(packet) = self.acl.pop_front() else {
+                break;
+            };
+            if self.transport.send_acl(&packet).is_ok() {
+                self.acl_credits -= 1;
+            }
+        }
+    }
+
+    fn send_frames(&mut self, handle: u16, frames: Vec<Vec<u8>>) {
+        for frame in frames {
+            self.acl
+                .extend(hci::acl_packets(handle, &frame, self.acl_mtu));
+        }
+        self.flush_acl();
+    }
+
+    /// Send ``data`` on the channel for ``psm``, returning false if it isn’t open.
+    pub fn send(&mut self, handle: u16, psm: u16, data: &[u8]) -> bool {
+        let Some(frame) = self
+            .connection(handle)
+            .and_then(|connection| connection.link.send(psm, data))
+        else {
+            return false;
+        };
+        self.send_frames(handle, alloc::vec![frame]);
+        true
+    }
+
+    /// Disconnect a device.
+    pub fn disconnect(&mut self, handle: u16) {
+        self.command(hci::disconnect(handle, hci::REASON_USER_ENDED));
+    }
+
+    fn connection(&self, handle: u16) -> Option<&Connection> {
+        self.connections
+            .iter()
+            .find(|connection| connection.handle == handle)
+    }
+
+    fn key(&self, address: Address) -> Option<LinkKey> {
+        self.keys
+            .iter()
+            .find(|(known, _)| *known == address)
+            .map(|(_, key)| *key)
+    }
+
+    fn is_acceptable(&self, address: Address, class: u32) -> bool {
+        WII_REMOTE_CLASSES.contains(&class)
+            || self.known.contains(&address)
+            || self.key(address).is_some()
+    }
+
+    /// Handle everything the controller sent since the last call, pushing what happened onto
+    /// ``events``.
+    pub fn poll(&mut self, events: &mut Vec<HostEvent>) {
+        while let Some(length) = self.transport.receive_event(&mut self.buffer[..]) {
+            let packet = self.buffer[..length].to_vec();
+            if let Some(event) = Event::parse(&packet) {
+                self.handle_event(event, events);
+            }
+        }
+        while let Some(length) = self.transport.receive_acl(&mut self.buffer[..]) {
+            let packet = self.buffer[..length].to_vec();
+            if let Some(packet) = AclPacket::parse(&packet) {
+                self.handle_acl(packet, events);
+            }
+        }
+        self.flush_commands();
+        self.flush_acl();
+    }
+
+    fn handle_event(&mut self, event: Event, events: &mut Vec<HostEvent>) {
+        match event {
+            Event::CommandComplete {
+                credits,
+                opcode,
+                parameters,
+            } => {
+                self.command_credits = credits;
+                self.command_complete(opcode, parameters, events);
+            }
+            Event::CommandStatus {
+                status,
+                credits,
+                opcode,
+            } => {
+                self.command_credits = credits;
+                if status != 0 && opcode == hci::CREATE_CONNECTION {
+                    self.pairing = Pairing::Idle;
+                }
+            }
+            Event::NumberOfCompletedPackets(records) => {
+                for (_, count) in records.completed_packets() {
+                    self.acl_credits = self.acl_credits.saturating_add(count);
+                }
+            }
+            Event::ReturnLinkKeys(records) => {
+                for (address, key) in records.link_keys() {
+                    self.store_key(address, key);
+                }
+            }
+            Event::ConnectionRequest {
+                address,
+                class,
+                link_type,
+            } => {
+                // Only ACL links, which are type 1.
+                if link_type == 1 && self.is_acceptable(address, class) {
+                    self.command(hci::accept_connection_request(address));
+                } else {
+                    self.command(hci::reject_connection_request(
+                        address,
+                        hci::REASON_UNACCEPTABLE_ADDRESS,
+                    ));
+                }
+            }
+            Event::ConnectionComplete {
+                status,
+                handle,
+                address,
+                ..
+            } => {
+                let outgoing = self.pairing == Pairing::Connecting(address);
+                if outgoing {
+                    self.pairing = Pairing::Idle;
+                }
+                if status != 0 {
+                    return;
+                }
+                self.connections.push(Connection {
+                    handle,
+                    address,
+                    outgoing,
+                    link: Link::new().with_accepted(&[PSM_HID_CONTROL, PSM_HID_INTERRUPT]),
+                    reassembly: Reassembly::new(),
+                });
+                events.push(HostEvent::Connected { handle, address });
+                if outgoing {
+                    self.command(hci::authentication_requested(handle));
+                }
+            }
+            Event::DisconnectionComplete { status, handle, .. } => {
+                if status == 0 {
+                    self.connections
+                        .retain(|connection| connection.handle != handle);
+                    events.push(HostEvent::Disconnected { handle });
+                }
+            }
+            Event::LinkKeyRequest { address } => match self.key(address) {
+                Some(key) => self.command(hci::link_key_request_reply(address, &key)),
+                None => self.command(hci::link_key_request_negative_reply(address)),
+            },
+            Event::PinCodeRequest { address } => {
+                // Wii Remotes paired with their SYNC button expect the address of the host.
+                let pin = self.address.0;
+                self.command(hci::pin_code_request_reply(address, &pin));
+            }
+            Event::LinkKeyNotification { address, key } => {
+                self.store_key(address, key);
+                self.command(hci::write_stored_link_key(address, &key));
+                events.push(HostEvent::Paired { address });
+            }
+            Event::AuthenticationComplete { status, handle } => {
+                let Some(index) = self
+                    .connections
+                    .iter()
+                    .position(|connection| connection.handle == handle)
+                else {
+                    return;
+                };
+                if status != 0 {
+                    self.disconnect(handle);
+                } else if self.connections[index].outgoing {
+                    let mut frames = Vec::new();
+                    self.connections[index]
+                        .link
+                        .connect(PSM_HID_CONTROL, &mut frames);
+                    self.send_frames(handle, frames);
+                }
+            }
+            Event::InquiryResult(responses) => {
+                if self.pairing != Pairing::Inquiry {
+                    return;
+                }
+                let found = responses.iter().find(|response| {
+                    WII_REMOTE_CLASSES.contains(&response.class)
+                        && !self
+                            .connections
+                            .iter()
+                            .any(|connection| connection.address == response.address)
+                });
+                if let Some(response) = found {
+                    self.pairing = Pairing::Connecting(response.address);
+                    self.command(hci::inquiry_cancel());
+                    self.command(hci::create_connection(
+                        response.address,
+                        response.page_scan_mode,
+                        response.clock_offset,
+                    ));
+                }
+            }
+            Event::InquiryComplete { .. } => {
+                if self.pairing == Pairing::Inquiry {
+                    self.pairing = Pairing::Idle;
+                }
+            }
+            Event::Other { .. } => (),
+        }
+    }
+
+    fn command_complete(&mut self, opcode: u16, parameters: &[u8], events: &mut Vec<HostEvent>) {
+        if parameters.first() != Some(&0) {
+            return;
+        }
+        match opcode {
+            hci::READ_BD_ADDR if parameters.len() >= 7 => {
+                self.address.0.copy_from_slice(&parameters[1..7]);
+            }
+            hci::READ_BUFFER_SIZE if parameters.len() >= 8 => {
+                // Packets go through a buffer of our own, whatever the controller accepts.
+                let mtu = u16::from_le_bytes([parameters[1], parameters[2]]) as usize;
+                self.acl_mtu = mtu.min(ACL_SIZE - 4);
+                self.acl_credits = u16::from_le_bytes([parameters[4], parameters[5]]);
+            }
+            hci::WRITE_SCAN_ENABLE if !self.ready => {
+                self.ready = true;
+                events.push(HostEvent::Ready);
+            }
+            _ => (),
+        }
+    }
+
+    fn store_key(&mut self, address: Address, key: LinkKey) {
+        match self.keys.iter_mut().find(|(known, _)| *known == address) {
+            Some(entry) => entry.1 = key,
+            None => self.keys.push((address, key)),
+        }
+    }
+
+    fn handle_acl(&mut self, packet: AclPacket, events: &mut Vec<HostEvent>) {
+        let Some(connection) = self
+            .connections
+            .iter_mut()
+            .find(|connection| connection.handle == packet.handle)
+        else {
+            return;
+        };
+        let handle = connection.handle;
+        let outgoing = connection.outgoing;
+        let Connection {
+            link, reassembly, ..
+        } = connection;
+        let Some(frame) = reassembly.push(packet.start, packet.data) else {
+            return;
+        };
+
+        let mut frames = Vec::new();
+        let mut link_events = Vec::new();
+        link.receive(frame, &mut frames, &mut link_events);
+        for event in link_events {
+            events.push(match event {
+                LinkEvent::Opened(psm) => {
+                    // Once the control channel is up, the interrupt one can follow.
+                    if psm == PSM_HID_CONTROL && outgoing {
+                        link.connect(PSM_HID_INTERRUPT, &mut frames);
+                    }
+                    HostEvent::ChannelOpened { handle, psm }
+                }
+                LinkEvent::Closed(psm) => HostEvent::ChannelClosed { handle, psm },
+                LinkEvent::Data(psm, data) => HostEvent::Data {
+                    handle,
+                    psm,
+                    data: data.to_vec(),
+                },
+            });
+        }
+        reassembly.clear();
+        self.send_frames(handle, frames);
+    }
+}
@@ -0,0 +1,170 @@
+//! ``executable`` module of ``luma_core::devserver``.
+//!
+//! Contains the parsing of the executables the host pushes, either DOL files or big-endian
+//! 32 bits PowerPC ELF files, into the segments to lay out in MEM1.  Nothing in here does any
+//! I/O.
+
+use super::DevError;
+use crate::mem::{CachedAddr, MEM1_SIZE, MEM1_START, PhysAddr};
+use alloc::vec::Vec;
+
+/// Where executables may be laid out, past the globals of the low MEM1.
+const LOAD_START: u32 = CachedAddr::new(0x8000_3400).value();
+const LOAD_END: u32 = MEM1_START.cached().value() + MEM1_SIZE;
+
+const DOL_HEADER_SIZE: usize = 0x100;
+const DOL_SECTIONS: usize = 18;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELF_CLASS_32: u8 = 1;
+const ELF_DATA_BIG_ENDIAN: u8 = 2;
+const ELF_MACHINE_PPC: u16 = 20;
+const ELF_HEADER_SIZE: usize = 0x34;
+const ELF_PROGRAM_HEADER_SIZE: usize = 0x20;
+const PT_LOAD: u32 = 1;
+
+/// How many segments an executable may have, which is plenty for any linker script.
+pub const MAX_SEGMENTS: usize = 32;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Get the cached address of ``address``, which linkers sometimes give as a physical one.
+/// Anything else is left as is, for [`Executable::check`] to reject if it is outside of MEM1.
+fn cached(address: u32) -> u32 {
+    let physical = MEM1_START.value()..MEM1_START.value() + MEM1_SIZE;
+    if physical.contains(&address) {
+        PhysAddr::new(address).cached().value()
+    } else {
+        address
+    }
+}
+
+/// A part of an executable, copied to its address then followed by zeroes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// Where it gets laid out, as a cached address.
+    pub address: u32,
+    /// Where its contents are in the executable.
+    pub offset: usize,
+    pub file_size: usize,
+    /// How much room it takes once laid out, the rest past ``file_size`` being zeroed.
+    pub memory_size: usize,
+}
+
+/// An executable, ready to be laid out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Executable {
+    /// Where to jump once laid out, as a cached address.
+    pub entry: u32,
+    /// The segments, to lay out in order.
+    pub segments: Vec<Segment>,
+}
+
+impl Executable {
+    /// Parse an ELF or a DOL file, checking that it fits in MEM1 without touching the globals.
+    pub fn parse(data: &[u8]) -> Result<Executable, DevError> {
+        let executable = if data.starts_with(&ELF_MAGIC) {
+            parse_elf(data)?
+        } else {
+            parse_dol(data)?
+        };
+        executable.check(data.len())?;
+        Ok(executable)
+    }
+
+    fn check(&self, size: usize) -> Result<(), DevError> {
+        if self.segments.is_empty() || self.segments.len() > MAX_SEGMENTS {
+            return Err(DevError::BadExecutable);
+        }
+        for segment in &self.segments {
+            let contents = segment.offset.checked_add(segment.file_size);
+            let end = (segment.address as u64) + (segment.memory_size as u64);
+            if contents.is_none_or(|end| end > size)
+                || segment.file_size > segment.memory_size
+                || segment.address < LOAD_START
+                || end > LOAD_END as u64
+            {
+                return Err(DevError::BadExecutable);
+            }
+        }
+        if !(LOAD_START..LOAD_END).contains(&self.entry) {
+            return Err(DevError::BadExecutable);
+        }
+        Ok(())
+    }
+}
+
+/// Parse a DOL file: the text and data sections, with the BSS zeroed before them as it may
+/// cover some of them.
+fn parse_dol(data: &[u8]) -> Result<Executable, DevError> {
+    if data.len() < DOL_HEADER_SIZE {
+        return Err(DevError::BadExecutable);
+    }
+    let mut segments = Vec::new();
+    let bss_size = u32_at(data, 0xdc) as usize;
+    if bss_size != 0 {
+        segments.push(Segment {
+            address: cached(u32_at(data, 0xd8)),
+            offset: 0,
+            file_size: 0,
+            memory_size: bss_size,
+        });
+    }
+    for section in 0..DOL_SECTIONS {
+        let size = u32_at(data, 0x90 + section * 4) as usize;
+        if size == 0 {
+            continue;
+        }
+        segments.push(Segment {
+            address: cached(u32_at(data, 0x48 + section * 4)),
+            offset: u32_at(data, section * 4) as usize,
+            file_size: size,
+            memory_size: size,
+        });
+    }
+    Ok(Executable {
+        entry: cached(u32_at(data, 0xe0)),
+        segments,
+    })
+}
+
+/// Parse an ELF file, from its loadable program headers.
+fn parse_elf(data: &[u8]) -> Result<Executable, DevError> {
+    if data.len() < ELF_HEADER_SIZE
+        || data[4] != ELF_CLASS_32
+        || data[5] != ELF_DATA_BIG_ENDIAN
+        || u16_at(data, 0x12) != ELF_MACHINE_PPC
+        || (u16_at(data, 0x2a) as usize) < ELF_PROGRAM_HEADER_SIZE
+    {
+        return Err(DevError::BadExecutable);
+    }
+    let headers = u32_at(data, 0x1c) as usize;
+    let header_size = u16_at(data, 0x2a) as usize;
+    let count = u16_at(data, 0x2c) as usize;
+    let table = count
+        .checked_mul(header_size)
+        .and_then(|size| headers.checked_add(size))
+        .and_then(|end| data.get(headers..end))
+        .ok_or(DevError::BadExecutable)?;
+
+    let segments = table
+        .chunks_exact(header_size)
+        .filter(|header| u32_at(header, 0) == PT_LOAD && u32_at(header, 0x14) != 0)
+        .map(|header| Segment {
+            address: cached(u32_at(header, 0x08)),
+            offset: u32_at(header, 0x04) as usize,
+            file_size: u32_at(header, 0x10) as usize,
+            memory_size: u32_at(header, 0x14) as usize,
+        })
+        .collect();
+    Ok(Executable {
+        entry: cached(u32_at(data, 0x18)),
+        segments,
+    })
+}
@@ -0,0 +1,338 @@
+//! ``devserver`` module of ``luma_core``.
+//!
+//! Contains a tiny HTTP server for development, behind the ``dev-server`` feature, which
+//! replaces the wiiload workflow: once [`crate::net::init`] brought the network up, a
+//! [`DevServer`] lets the host follow the log and push new builds without touching the SD card.
+//!
+//! - ``GET /log`` streams everything [`crate::println!`] prints, until the next client asks
+//!   for it: ``curl -N http://wii:8080/log``.
+//! - ``POST /run`` takes an ELF or DOL file, stores it into MEM2 and relaunches it in place of
+//!   the running app: ``curl --data-binary @app.elf http://wii:8080/run``.
+
+use crate::cache::{DCFlushRange, ICInvalidateRange};
+use crate::heap::{self, Arena};
+use crate::net::{self, NetError, TcpListener, TcpStream, socket};
+use crate::register::{mfmsr, mtmsr};
+use crate::stm;
+use alloc::format;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::convert::Infallible;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::sync::atomic::{AtomicI32, AtomicPtr, Ordering};
+use embedded_io::{Read, Write};
+
+mod executable;
+
+pub use executable::{Executable, Segment};
+
+global_asm!(include_str!("../../asm/trampoline.S"));
+
+unsafe extern "C" {
+    fn __relaunch_trampoline(table: *const u32, count: u32, entry: u32) -> !;
+    static __relaunch_trampoline_end: u8;
+}
+
+/// Port the server listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 8080;
+
+/// Largest executable the host may push.
+pub const MAX_EXECUTABLE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest request head, up to the empty line.
+const MAX_HEAD_SIZE: usize = 4096;
+
+/// Where the trampoline and the table of segments go at the start of the MEM2 region, ahead of
+/// the executable.
+const TRAMPOLINE_ROOM: usize = 0x400;
+const TABLE_ROOM: usize = executable::MAX_SEGMENTS * 16;
+const IMAGE_OFFSET: usize = TRAMPOLINE_ROOM + TABLE_ROOM;
+
+const MSR_EE: u32 = 0x8000;
+
+const USAGE: &str = "GET /log to follow the log, POST /run with an ELF or DOL to relaunch\n";
+
+/// The socket of the client following the log, if any.
+static LOG_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// The region of MEM2 executables get stored into, taken the first time one gets pushed.
+static REGION: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// An error of the development server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DevError {
+    /// The request isn’t one the server understands.
+    BadRequest,
+    /// The executable is larger than [`MAX_EXECUTABLE_SIZE`].
+    TooLarge,
+    /// The executable is neither a valid ELF nor a valid DOL, or doesn’t fit in MEM1.
+    BadExecutable,
+    Net(NetError),
+}
+
+impl From<NetError> for DevError {
+    fn from(error: NetError) -> DevError {
+        DevError::Net(error)
+    }
+}
+
+/// A development server, answering requests as [`DevServer::poll`] gets called.
+#[derive(Debug)]
+pub struct DevServer {
+    listener: TcpListener,
+}
+
+impl DevServer {
+    /// Listen on ``port``, [`DEFAULT_PORT`] being the usual one.
+    ///
+    /// The network has to be brought up with [`net::init`] first.
+    pub fn bind(port: u16) -> Result<DevServer, DevError> {
+        let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(DevServer { listener })
+    }
+
+    /// Answer the clients waiting, if any, without waiting for more, which is meant to be done
+    /// from the main loop.  This doesn’t return once an executable got pushed.
+    ///
+    /// Errors of a client only end its connection, only those of the server itself get
+    /// returned.
+    pub fn poll(&self) -> Result<(), DevError> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(NetError::WouldBlock) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            };
+            stream.set_nonblocking(false)?;
+            let _ = self.answer(stream);
+        }
+    }
+
+    /// Answer clients forever, only returning on error.
+    pub fn serve(self) -> Result<Infallible, DevError> {
+        self.listener.set_nonblocking(false)?;
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            let _ = self.answer(stream);
+        }
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> Result<(), DevError> {
+        let mut received = Vec::new();
+        let end = read_head(&mut stream, &mut received)?;
+        let Some(request) = Request::parse(&received[..end]) else {
+            respond(&mut stream, "400 Bad Request", "bad request\n")?;
+            return Err(DevError::BadRequest);
+        };
+        match (request.method, request.path) {
+            ("GET", "/") => respond(&mut stream, "200 OK", USAGE),
+            ("GET", "/log") => {
+                stream.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n")?;
+                follow_log(stream);
+                Ok(())
+            }
+            ("POST" | "PUT", "/run") => {
+                let Some(length) = request.content_length else {
+                    respond(&mut stream, "411 Length Required", USAGE)?;
+                    return Err(DevError::BadRequest);
+                };
+                if length > MAX_EXECUTABLE_SIZE {
+                    respond(&mut stream, "413 Payload Too Large", "too large\n")?;
+                    return Err(DevError::TooLarge);
+                }
+                if request.expect_continue {
+                    stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+                }
+                let image = receive(&mut stream, &received[end + 4..], length)?;
+                match Executable::parse(image) {
+                    Ok(executable) => {
+                        respond(&mut stream, "200 OK", "relaunching\n")?;
+                        drop(stream);
+                        relaunch(&executable, image)
+                    }
+                    Err(error) => {
+                        respond(&mut stream, "400 Bad Request", "bad executable\n")?;
+                        Err(error)
+                    }
+                }
+            }
+            _ => respond(&mut stream, "404 Not Found", USAGE),
+        }
+    }
+}
+
+/// A request, as much of it as the server cares about.
+struct Request<'a> {
+    method: &'a str,
+    path: &'a str,
+    content_length: Option<usize>,
+    /// Whether the client waits to be told to go on before sending the body, as curl does
+    /// with large ones.
+    expect_continue: bool,
+}
+
+impl<'a> Request<'a> {
+    /// Parse the head of a request, without its final empty line.
+    fn parse(head: &'a [u8]) -> Option<Request<'a>> {
+        let head = core::str::from_utf8(head).ok()?;
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split(' ');
+        let method = request_line.next()?;
+        let path = request_line.next()?;
+        let mut request = Request {
+            method,
+            path,
+            content_length: None,
+            expect_continue: false,
+        };
+        for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                request.content_length = value.parse().ok();
+            } else if name.eq_ignore_ascii_case("expect") {
+                request.expect_continue = value.eq_ignore_ascii_case("100-continue");
+            }
+        }
+        Some(request)
+    }
+}
+
+/// Read until the empty line ending the head of a request, returning where it is.  Whatever
+/// got read past it is the start of the body.
+fn read_head(stream: &mut TcpStream, received: &mut Vec<u8>) -> Result<usize, DevError> {
+    let mut chunk = [0; 512];
+    loop {
+        if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(end);
+        }
+        if received.len() > MAX_HEAD_SIZE {
+            return Err(DevError::BadRequest);
+        }
+        match stream.read(&mut chunk)? {
+            0 => return Err(DevError::BadRequest),
+            read => received.extend_from_slice(&chunk[..read]),
+        }
+    }
+}
+
+/// Answer with a plain text ``body``.
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> Result<(), DevError> {
+    let response = format!(
+        "HTTP/1.0 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes())?;
+    Ok(())
+}
+
+/// Get the region of MEM2 executables get stored into, taking it the first time.
+fn region() -> *mut u8 {
+    let region = REGION.load(Ordering::Acquire);
+    if !region.is_null() {
+        return region;
+    }
+    let region = heap::take(Arena::Mem2, IMAGE_OFFSET + MAX_EXECUTABLE_SIZE);
+    REGION.store(region, Ordering::Release);
+    region
+}
+
+/// Receive a body of ``length`` bytes into MEM2, ``start`` being what got read along with the
+/// head.
+fn receive(
+    stream: &mut TcpStream,
+    start: &[u8],
+    length: usize,
+) -> Result<&'static mut [u8], DevError> {
+    let image = unsafe { core::slice::from_raw_parts_mut(region().add(IMAGE_OFFSET), length) };
+    let start = &start[..start.len().min(length)];
+    image[..start.len()].copy_from_slice(start);
+    stream
+        .read_exact(&mut image[start.len()..])
+        .map_err(|_| DevError::BadRequest)?;
+    Ok(image)
+}
+
+/// Send the log to ``stream`` from now on, instead of to the previous client.
+fn follow_log(stream: TcpStream) {
+    let previous = LOG_FD.swap(stream.as_raw_fd(), Ordering::AcqRel);
+    // Closed by send_log() once the client goes away, or by the next one.
+    core::mem::forget(stream);
+    if previous >= 0 {
+        close_log(previous);
+    }
+    crate::set_log_hook(Some(send_log));
+}
+
+fn close_log(fd: i32) {
+    if let Ok(top) = net::top() {
+        let _ = socket::close(&*top, fd);
+    }
+}
+
+fn send_log(message: &str) {
+    let fd = LOG_FD.load(Ordering::Acquire);
+    if fd < 0 {
+        return;
+    }
+    let Ok(top) = net::top() else {
+        return;
+    };
+    let mut message = message.as_bytes();
+    while !message.is_empty() {
+        match socket::send_to(&*top, fd, message, 0, None) {
+            Ok(sent) if sent > 0 => message = &message[sent..],
+            // The client went away.
+            _ => {
+                if LOG_FD
+                    .compare_exchange(fd, -1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    close_log(fd);
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Replace the running app with ``executable``, whose contents are in ``image``.
+///
+/// The segments get laid out by a trampoline copied ahead of ``image`` in MEM2, since they
+/// overwrite everything else.
+fn relaunch(executable: &Executable, image: &[u8]) -> ! {
+    // Nothing may be left for IOS to write to once the new app runs.
+    let _ = stm::release_event_hook();
+    let log = LOG_FD.swap(-1, Ordering::AcqRel);
+    if log >= 0 {
+        close_log(log);
+    }
+    net::deinit();
+    mtmsr(mfmsr() & !MSR_EE);
+
+    let region = region();
+    let table = unsafe { region.add(TRAMPOLINE_ROOM) } as *mut u32;
+    for (index, segment) in executable.segments.iter().enumerate() {
+        let entry = [
+            segment.address,
+            image[segment.offset..].as_ptr() as u32,
+            segment.file_size as u32,
+            (segment.memory_size - segment.file_size) as u32,
+        ];
+        unsafe { table.add(index * 4).cast::<[u32; 4]>().write(entry) };
+    }
+
+    let trampoline = __relaunch_trampoline as *const u8;
+    let size = unsafe { (&raw const __relaunch_trampoline_end).offset_from(trampoline) } as usize;
+    assert!(size <= TRAMPOLINE_ROOM, "the trampoline doesn’t fit");
+    unsafe {
+        core::ptr::copy_nonoverlapping(trampoline, region, size);
+        DCFlushRange(region as *const u32, IMAGE_OFFSET as u32);
+        ICInvalidateRange(region as *const u32, size as u32);
+        let trampoline = core::mem::transmute::<
+            *mut u8,
+            unsafe extern "C" fn(*const u32, u32, u32) -> !,
+        >(region);
+        trampoline(table, executable.segments.len() as u32, executable.entry)
+    }
+}
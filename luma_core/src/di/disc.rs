@@ -0,0 +1,185 @@
+//! ``disc`` module of ``luma_core::di``.
+//!
+//! Contains the parsing of the structures laid out on GameCube and Wii discs: the disc header,
+//! the partition table and the headers of the partitions.  Nothing in here does any I/O.
+
+use alloc::vec::Vec;
+
+/// Where the partition table of Wii discs starts.
+pub const TABLE_OFFSET: u64 = 0x40000;
+
+/// Size of the start of the partition table, locating the four groups of partitions.
+pub const TABLE_SIZE: usize = 32;
+
+/// Size of each partition entry in a group.
+pub const TABLE_ENTRY_SIZE: usize = 8;
+
+/// Size of the disc header, up to the offsets of the FST.
+pub const DISC_HEADER_SIZE: usize = 0x440;
+
+/// Size of the header of a partition, up to the location of its data.
+pub const PARTITION_HEADER_SIZE: usize = 0x2c0;
+
+/// Size of a cluster of partition data, and of the data left once decrypted.
+const CLUSTER_SIZE: u64 = 0x8000;
+const CLUSTER_DATA_SIZE: u64 = 0x7c00;
+
+const WII_MAGIC: u32 = 0x5d1c_9ea3;
+const GAMECUBE_MAGIC: u32 = 0xc233_9f3d;
+
+fn word(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// What a disc says about itself in its first bytes, as returned by
+/// [`super::Drive::read_disc_id`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiscId {
+    /// The game code followed by the maker code, such as ``RSPE01``.
+    pub code: [u8; 6],
+    /// Which disc of the game this is, counting from zero.
+    pub disc: u8,
+    pub version: u8,
+    /// Whether this is a Wii disc, with a partition table and encrypted partitions.
+    pub wii: bool,
+    /// Whether this is a GameCube disc, read as is.
+    pub gamecube: bool,
+}
+
+impl DiscId {
+    /// Parse the first 32 bytes of a disc.
+    pub fn parse(data: &[u8]) -> Option<DiscId> {
+        if data.len() < 32 {
+            return None;
+        }
+        Some(DiscId {
+            code: data[0..6].try_into().unwrap(),
+            disc: data[6],
+            version: data[7],
+            wii: word(data, 0x18) == WII_MAGIC,
+            gamecube: word(data, 0x1c) == GAMECUBE_MAGIC,
+        })
+    }
+
+    /// Get the game and maker codes, or an empty string if they aren’t ASCII.
+    pub fn code(&self) -> &str {
+        match core::str::from_utf8(&self.code) {
+            Ok(code) if code.is_ascii() => code,
+            _ => "",
+        }
+    }
+}
+
+/// The header at the start of a disc, and at the start of the data of each Wii partition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscHeader {
+    pub id: DiscId,
+    pub title: [u8; 64],
+    /// Where the main executable is, in bytes.
+    pub dol_offset: u64,
+    /// Where the FST is, in bytes, see [`super::fst::Fst`].
+    pub fst_offset: u64,
+    pub fst_size: u64,
+}
+
+impl DiscHeader {
+    /// Parse a disc header, of which [`DISC_HEADER_SIZE`] bytes are needed.
+    pub fn parse(data: &[u8]) -> Option<DiscHeader> {
+        if data.len() < DISC_HEADER_SIZE {
+            return None;
+        }
+        let id = DiscId::parse(data)?;
+        // Wii discs count in words, to go past 4 GiB.
+        let shift = if id.wii { 2 } else { 0 };
+        let offset = |at: usize| (word(data, at) as u64) << shift;
+        Some(DiscHeader {
+            id,
+            title: data[0x20..0x60].try_into().unwrap(),
+            dol_offset: offset(0x420),
+            fst_offset: offset(0x424),
+            fst_size: offset(0x428),
+        })
+    }
+
+    /// Get the title of the game, without its padding.
+    pub fn title(&self) -> &str {
+        let end = self.title.iter().position(|&byte| byte == 0);
+        let title = &self.title[..end.unwrap_or(self.title.len())];
+        core::str::from_utf8(title).unwrap_or("")
+    }
+}
+
+/// What a Wii partition holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionKind {
+    /// The game itself.
+    Data,
+    /// A system update.
+    Update,
+    /// A channel to install.
+    Channel,
+    Other(u32),
+}
+
+/// A partition of a Wii disc, as listed in the partition table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Partition {
+    /// Where its header is, in bytes.
+    pub offset: u64,
+    pub kind: PartitionKind,
+}
+
+/// Parse the start of the partition table into how many partitions each group holds and where
+/// the entries of the group are, in bytes.
+pub fn parse_groups(data: &[u8]) -> Option<[(u32, u64); 4]> {
+    if data.len() < TABLE_SIZE {
+        return None;
+    }
+    Some(core::array::from_fn(|group| {
+        let count = word(data, group * 8);
+        let offset = (word(data, group * 8 + 4) as u64) << 2;
+        (count, offset)
+    }))
+}
+
+/// Parse the entries of a group of partitions.
+pub fn parse_partitions(data: &[u8]) -> Vec<Partition> {
+    data.chunks_exact(TABLE_ENTRY_SIZE)
+        .map(|entry| Partition {
+            offset: (word(entry, 0) as u64) << 2,
+            kind: match word(entry, 4) {
+                0 => PartitionKind::Data,
+                1 => PartitionKind::Update,
+                2 => PartitionKind::Channel,
+                kind => PartitionKind::Other(kind),
+            },
+        })
+        .collect()
+}
+
+/// The header of a Wii partition, holding its ticket and locating its encrypted data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartitionHeader {
+    /// Where the data is, in bytes from the start of the partition.
+    pub data_offset: u64,
+    /// How much encrypted data there is, in bytes.
+    pub data_size: u64,
+}
+
+impl PartitionHeader {
+    /// Parse a partition header, of which [`PARTITION_HEADER_SIZE`] bytes are needed.
+    pub fn parse(data: &[u8]) -> Option<PartitionHeader> {
+        if data.len() < PARTITION_HEADER_SIZE {
+            return None;
+        }
+        Some(PartitionHeader {
+            data_offset: (word(data, 0x2b8) as u64) << 2,
+            data_size: (word(data, 0x2bc) as u64) << 2,
+        })
+    }
+
+    /// Get how much data there is once decrypted, as read through an opened partition.
+    pub fn decrypted_size(&self) -> u64 {
+        self.data_size / CLUSTER_SIZE * CLUSTER_DATA_SIZE
+    }
+}
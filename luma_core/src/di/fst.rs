@@ -0,0 +1,192 @@
+//! ``fst`` module of ``luma_core::di``.
+//!
+//! Contains the parsing of the file system table of a disc, which lists the files and
+//! directories on it along with where the files are.  Nothing in here does any I/O.
+
+use super::DiError;
+use alloc::vec::Vec;
+
+const ENTRY_SIZE: usize = 12;
+
+/// The file system table of a disc, or of a Wii partition, checked once when parsed so that
+/// walking it later can’t fail.
+#[derive(Clone, Debug)]
+pub struct Fst {
+    data: Vec<u8>,
+    count: usize,
+    /// How much the offsets of files are shifted, Wii discs counting them in words.
+    shift: u32,
+}
+
+impl Fst {
+    /// Parse a table read off a disc, which is a Wii one if ``wii``.
+    pub fn parse(data: Vec<u8>, wii: bool) -> Result<Fst, DiError> {
+        if data.len() < ENTRY_SIZE || data[0] != 1 {
+            return Err(DiError::Corrupted);
+        }
+        let count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+        if count == 0 || count > data.len() / ENTRY_SIZE {
+            return Err(DiError::Corrupted);
+        }
+        let fst = Fst {
+            data,
+            count,
+            shift: if wii { 2 } else { 0 },
+        };
+        for index in 1..count {
+            fst.check(index)?;
+        }
+        Ok(fst)
+    }
+
+    fn word(&self, index: usize, field: usize) -> u32 {
+        let offset = index * ENTRY_SIZE + field * 4;
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn is_dir(&self, index: usize) -> bool {
+        self.data[index * ENTRY_SIZE] != 0
+    }
+
+    /// Get the index of the entry following a directory and everything in it.
+    fn next(&self, index: usize) -> usize {
+        self.word(index, 2) as usize
+    }
+
+    fn name(&self, index: usize) -> &[u8] {
+        let start = self.count * ENTRY_SIZE + (self.word(index, 0) & 0x00ff_ffff) as usize;
+        let names = &self.data[start..];
+        // Checked in parse().
+        let end = names.iter().position(|&byte| byte == 0).unwrap_or(0);
+        &names[..end]
+    }
+
+    fn check(&self, index: usize) -> Result<(), DiError> {
+        let start = self.count * ENTRY_SIZE + (self.word(index, 0) & 0x00ff_ffff) as usize;
+        let terminated = self
+            .data
+            .get(start..)
+            .is_some_and(|names| names.contains(&0));
+        // Directories end after themselves, so that walking them always ends.
+        let nested =
+            !self.is_dir(index) || (self.next(index) > index && self.next(index) <= self.count);
+        if terminated && nested {
+            Ok(())
+        } else {
+            Err(DiError::Corrupted)
+        }
+    }
+
+    /// Get the root directory.
+    pub fn root(&self) -> Entry<'_> {
+        Entry {
+            fst: self,
+            index: 0,
+        }
+    }
+
+    /// Find the entry at ``path``, whose components are separated by slashes and compared
+    /// without regard to ASCII case, as the games themselves do.
+    pub fn lookup(&self, path: &str) -> Option<Entry<'_>> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self.root(), |entry, component| entry.find(component))
+    }
+}
+
+/// A file or a directory of an [`Fst`].
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<'a> {
+    fst: &'a Fst,
+    index: usize,
+}
+
+impl<'a> Entry<'a> {
+    /// Get the name of the entry as stored, which is Shift JIS on some Japanese discs.
+    pub fn name_bytes(&self) -> &'a [u8] {
+        match self.index {
+            0 => &[],
+            index => self.fst.name(index),
+        }
+    }
+
+    /// Get the name of the entry, or an empty string if it isn’t valid UTF-8.
+    pub fn name(&self) -> &'a str {
+        core::str::from_utf8(self.name_bytes()).unwrap_or("")
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.fst.is_dir(self.index)
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    /// Get where the contents of a file are, in bytes, or zero for a directory.
+    pub fn offset(&self) -> u64 {
+        if self.is_dir() {
+            0
+        } else {
+            (self.fst.word(self.index, 1) as u64) << self.fst.shift
+        }
+    }
+
+    /// Get the size of a file, in bytes, or zero for a directory.
+    pub fn size(&self) -> u64 {
+        if self.is_dir() {
+            0
+        } else {
+            self.fst.word(self.index, 2) as u64
+        }
+    }
+
+    /// Iterate over the children of a directory, or nothing for a file.
+    pub fn entries(&self) -> Entries<'a> {
+        let (next, end) = if self.is_dir() {
+            (self.index + 1, self.fst.next(self.index))
+        } else {
+            (0, 0)
+        };
+        Entries {
+            fst: self.fst,
+            next,
+            end,
+        }
+    }
+
+    /// Find the child of a directory called ``name``, without regard to ASCII case.
+    pub fn find(&self, name: &str) -> Option<Entry<'a>> {
+        self.entries()
+            .find(|entry| entry.name_bytes().eq_ignore_ascii_case(name.as_bytes()))
+    }
+}
+
+/// Iterator over the children of a directory, see [`Entry::entries`].
+#[derive(Clone, Debug)]
+pub struct Entries<'a> {
+    fst: &'a Fst,
+    next: usize,
+    end: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        if self.next >= self.end {
+            return None;
+        }
+        let index = self.next;
+        // Skip over everything in a directory, to get to its next sibling.
+        self.next = if self.fst.is_dir(index) {
+            self.fst.next(index)
+        } else {
+            index + 1
+        };
+        Some(Entry {
+            fst: self.fst,
+            index,
+        })
+    }
+}
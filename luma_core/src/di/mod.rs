@@ -0,0 +1,352 @@
+//! ``di`` module of ``luma_core``.
+//!
+//! Contains a driver for the disc drive, through the DVD interface IOS exposes at ``/dev/di``.
+//! GameCube discs are read as they are, while Wii discs are read through one of their
+//! partitions, which IOS decrypts once opened.  The [`disc`] and [`fst`] modules locate the
+//! partitions and the files on a disc.
+//!
+//! Reads go through a bounce buffer in MEM2 unless the caller’s buffer is aligned to a cache
+//! line and the position to a word, as the drive only transfers whole cache lines.
+
+pub mod disc;
+pub mod fst;
+
+use crate::block::BlockDevice;
+use crate::heap::{self, Arena};
+use crate::ios::{self, Aligned, Buffer, Handle, IosError, Mode};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use disc::{DiscHeader, DiscId, Partition, PartitionHeader};
+use fst::Fst;
+
+const DEVICE: &str = "/dev/di";
+
+const IOCTL_READ_DISC_ID: u8 = 0x70;
+const IOCTL_READ: u8 = 0x71;
+const IOCTL_RESET: u8 = 0x8a;
+const IOCTL_OPEN_PARTITION: u8 = 0x8b;
+const IOCTL_CLOSE_PARTITION: u8 = 0x8c;
+const IOCTL_UNENCRYPTED_READ: u8 = 0x8d;
+const IOCTL_REQUEST_ERROR: u8 = 0xe0;
+
+/// What DI returns when a command succeeded, anything else meaning the drive has an error.
+const RESULT_SUCCESS: i32 = 1;
+
+/// Sense key and additional sense code of the drive error for a missing disc.
+const ERROR_NO_MEDIUM: u32 = 0x0002_3a00;
+
+/// Size of the TMD returned when opening a partition.
+const TMD_SIZE: usize = 0x49e4;
+
+/// Size of a sector of the disc.
+pub const BLOCK_SIZE: usize = 2048;
+
+/// Size of a dual layer disc, the largest there is.
+pub const MAX_DISC_SIZE: u64 = 0x1_fb4e_0000;
+
+/// Largest FST we agree to read, far above what any disc has.
+const MAX_FST_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Most partitions we agree to read from each group of the table.
+const MAX_PARTITIONS: u32 = 64;
+
+const BOUNCE_SIZE: usize = 32 * 1024;
+
+/// Errors which can be encountered while using the disc drive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiError {
+    /// The drive got opened already.
+    Busy,
+    /// There is no disc in the drive.
+    NoDisc,
+    /// No partition is opened to read from.
+    NoPartition,
+    /// The data asked for goes past the end of the disc or of the partition.
+    OutOfRange,
+    /// Discs can’t be written to.
+    ReadOnly,
+    /// The disc holds nonsense where a table should be.
+    Corrupted,
+    /// The drive failed, with the error it reported.
+    Drive(u32),
+    /// The partition couldn’t be opened, with the error ES reported.
+    Es(i32),
+    /// IOS rejected a request.
+    Ios(IosError),
+}
+
+impl From<IosError> for DiError {
+    fn from(error: IosError) -> DiError {
+        DiError::Ios(error)
+    }
+}
+
+static OPEN: AtomicBool = AtomicBool::new(false);
+static BOUNCE: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Build the command block every DI ioctl takes, the command followed by its arguments.
+fn command_block(command: u8, arguments: &[u32]) -> Aligned<32> {
+    let mut block = Aligned([0; 32]);
+    block.0[0] = command;
+    for (bytes, argument) in block.0[4..].chunks_exact_mut(4).zip(arguments) {
+        bytes.copy_from_slice(&argument.to_be_bytes());
+    }
+    block
+}
+
+/// Ask the drive why the last command failed.
+fn request_error(handle: &Handle) -> DiError {
+    let block = command_block(IOCTL_REQUEST_ERROR, &[]);
+    let mut output = Aligned([0; 32]);
+    if let Err(error) = handle.ioctl(IOCTL_REQUEST_ERROR as u32, &block.0, &mut output.0) {
+        return error.into();
+    }
+    let error = u32::from_be_bytes(output.0[..4].try_into().unwrap());
+    match error & 0x00ff_ff00 {
+        ERROR_NO_MEDIUM => DiError::NoDisc,
+        _ => DiError::Drive(error),
+    }
+}
+
+/// Send ``command`` with its arguments, writing what it returns to ``output``.
+fn command(
+    handle: &Handle,
+    command: u8,
+    arguments: &[u32],
+    output: &mut [u8],
+) -> Result<(), DiError> {
+    let block = command_block(command, arguments);
+    match handle.ioctl(command as u32, &block.0, output)? {
+        RESULT_SUCCESS => Ok(()),
+        _ => Err(request_error(handle)),
+    }
+}
+
+/// The disc drive, and the disc in it.
+pub struct Drive {
+    handle: Handle,
+    id: DiscId,
+    /// The decrypted size of the opened partition, if any.
+    partition: Option<u64>,
+    bounce: &'static mut [u8],
+}
+
+impl Drive {
+    /// Reset the drive, spin the disc up and read its ID, so that it is ready to be read.
+    pub fn open() -> Result<Drive, DiError> {
+        if OPEN.swap(true, Ordering::AcqRel) {
+            return Err(DiError::Busy);
+        }
+        let handle = match Handle::open(DEVICE, Mode::Read) {
+            Ok(handle) => handle,
+            Err(error) => {
+                OPEN.store(false, Ordering::Release);
+                return Err(error.into());
+            }
+        };
+
+        if BOUNCE.load(Ordering::Acquire).is_null() {
+            BOUNCE.store(heap::take(Arena::Mem2, BOUNCE_SIZE), Ordering::Release);
+        }
+        let bounce =
+            unsafe { slice::from_raw_parts_mut(BOUNCE.load(Ordering::Acquire), BOUNCE_SIZE) };
+
+        let mut drive = Drive {
+            handle,
+            id: DiscId::default(),
+            partition: None,
+            bounce,
+        };
+        drive.reset(true)?;
+        drive.id = drive.read_disc_id()?;
+        Ok(drive)
+    }
+
+    /// Reset the drive, and spin the disc up if ``spin_up``.  The disc ID has to be read again
+    /// before anything else, see [`Drive::read_disc_id`].
+    pub fn reset(&mut self, spin_up: bool) -> Result<(), DiError> {
+        let mut output = Aligned([0; 32]);
+        self.partition = None;
+        command(&self.handle, IOCTL_RESET, &[spin_up as u32], &mut output.0)
+    }
+
+    /// Read the ID of the disc, which the drive wants done before reading anything else.
+    pub fn read_disc_id(&mut self) -> Result<DiscId, DiError> {
+        let mut output = Aligned([0; 32]);
+        command(&self.handle, IOCTL_READ_DISC_ID, &[], &mut output.0)?;
+        let id = DiscId::parse(&output.0).ok_or(DiError::Corrupted)?;
+        self.id = id;
+        Ok(id)
+    }
+
+    /// Get the ID of the disc, as last read.
+    pub fn id(&self) -> &DiscId {
+        &self.id
+    }
+
+    /// Read ``buffer`` from the disc as is, starting at ``offset`` bytes.  This is how
+    /// GameCube discs and the tables of Wii discs get read.
+    pub fn read_unencrypted(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), DiError> {
+        if offset + buffer.len() as u64 > MAX_DISC_SIZE {
+            return Err(DiError::OutOfRange);
+        }
+        self.read_with(IOCTL_UNENCRYPTED_READ, offset, buffer)
+    }
+
+    /// Read ``buffer`` from the decrypted data of the opened partition, starting at ``offset``
+    /// bytes.
+    pub fn read(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), DiError> {
+        let size = self.partition.ok_or(DiError::NoPartition)?;
+        if offset + buffer.len() as u64 > size {
+            return Err(DiError::OutOfRange);
+        }
+        self.read_with(IOCTL_READ, offset, buffer)
+    }
+
+    /// Read from the opened partition if any, or from the disc as is otherwise.
+    fn read_any(&mut self, offset: u64, buffer: &mut [u8]) -> Result<(), DiError> {
+        match self.partition {
+            Some(_) => self.read(offset, buffer),
+            None => self.read_unencrypted(offset, buffer),
+        }
+    }
+
+    fn read_with(&mut self, ioctl: u8, offset: u64, buffer: &mut [u8]) -> Result<(), DiError> {
+        if offset.is_multiple_of(4) && ios::is_aligned(buffer) {
+            let arguments = [buffer.len() as u32, (offset >> 2) as u32];
+            return command(&self.handle, ioctl, &arguments, buffer);
+        }
+
+        let mut done = 0;
+        while done < buffer.len() {
+            let position = offset + done as u64;
+            let start = position & !31;
+            let skip = (position - start) as usize;
+            let count = (buffer.len() - done).min(BOUNCE_SIZE - skip);
+            let length = (skip + count).next_multiple_of(32);
+            let arguments = [length as u32, (start >> 2) as u32];
+            command(&self.handle, ioctl, &arguments, &mut self.bounce[..length])?;
+            buffer[done..done + count].copy_from_slice(&self.bounce[skip..skip + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    /// List the partitions of a Wii disc.
+    pub fn partitions(&mut self) -> Result<Vec<Partition>, DiError> {
+        let mut groups = [0; disc::TABLE_SIZE];
+        self.read_unencrypted(disc::TABLE_OFFSET, &mut groups)?;
+        let mut partitions = Vec::new();
+        for (count, offset) in disc::parse_groups(&groups).ok_or(DiError::Corrupted)? {
+            if count > MAX_PARTITIONS {
+                return Err(DiError::Corrupted);
+            }
+            let mut table = vec![0; count as usize * disc::TABLE_ENTRY_SIZE];
+            self.read_unencrypted(offset, &mut table)?;
+            partitions.extend(disc::parse_partitions(&table));
+        }
+        Ok(partitions)
+    }
+
+    /// Open ``partition`` to read from it, closing the one opened before if any.  IOS checks
+    /// its ticket and TMD on the way.
+    pub fn open_partition(&mut self, partition: &Partition) -> Result<(), DiError> {
+        if self.partition.is_some() {
+            self.close_partition()?;
+        }
+        let mut header = [0; disc::PARTITION_HEADER_SIZE];
+        self.read_unencrypted(partition.offset, &mut header)?;
+        let header = PartitionHeader::parse(&header).ok_or(DiError::Corrupted)?;
+
+        let block = command_block(IOCTL_OPEN_PARTITION, &[(partition.offset >> 2) as u32]);
+        let mut tmd = Buffer::new(TMD_SIZE);
+        let mut es_error = Buffer::new(32);
+        // Without a ticket or certificates, IOS uses the ones in the partition header.
+        let result = self.handle.ioctlv(
+            IOCTL_OPEN_PARTITION as u32,
+            &[&block.0, &[], &[]],
+            &mut [&mut tmd, &mut es_error],
+        )?;
+        if result != RESULT_SUCCESS {
+            return match i32::from_be_bytes(es_error[..4].try_into().unwrap()) {
+                0 => Err(request_error(&self.handle)),
+                error => Err(DiError::Es(error)),
+            };
+        }
+        self.partition = Some(header.decrypted_size());
+        Ok(())
+    }
+
+    /// Close the opened partition, going back to reading the disc as is.
+    pub fn close_partition(&mut self) -> Result<(), DiError> {
+        let mut output = Aligned([0; 32]);
+        self.partition = None;
+        command(&self.handle, IOCTL_CLOSE_PARTITION, &[], &mut output.0)
+    }
+
+    /// Read the header of the opened partition, or of the disc if none is.
+    pub fn disc_header(&mut self) -> Result<DiscHeader, DiError> {
+        let mut header = [0; disc::DISC_HEADER_SIZE];
+        self.read_any(0, &mut header)?;
+        DiscHeader::parse(&header).ok_or(DiError::Corrupted)
+    }
+
+    /// Read the FST of the opened partition, or of the disc if none is.
+    pub fn fst(&mut self) -> Result<Fst, DiError> {
+        let header = self.disc_header()?;
+        if header.fst_size > MAX_FST_SIZE {
+            return Err(DiError::Corrupted);
+        }
+        let mut data = vec![0; header.fst_size as usize];
+        self.read_any(header.fst_offset, &mut data)?;
+        Fst::parse(data, header.id.wii)
+    }
+
+    /// Read the contents of ``file`` from ``position`` into ``buffer``, returning how many
+    /// bytes got read, which is less than asked for past the end of the file.  The partition
+    /// the FST came from must still be opened.
+    pub fn read_file(
+        &mut self,
+        file: &fst::Entry,
+        position: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, DiError> {
+        let remaining = file.size().saturating_sub(position);
+        let count = buffer.len().min(remaining as usize);
+        self.read_any(file.offset() + position, &mut buffer[..count])?;
+        Ok(count)
+    }
+}
+
+impl BlockDevice for Drive {
+    type Error = DiError;
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    /// Get the amount of blocks in the opened partition, or on the largest disc if none is.
+    fn block_count(&self) -> u64 {
+        self.partition.unwrap_or(MAX_DISC_SIZE) / BLOCK_SIZE as u64
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), DiError> {
+        self.read_any(block * BLOCK_SIZE as u64, buffer)
+    }
+
+    fn write_blocks(&mut self, _block: u64, _buffer: &[u8]) -> Result<(), DiError> {
+        Err(DiError::ReadOnly)
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for Drive {
+    fn drop(&mut self) {
+        OPEN.store(false, Ordering::Release);
+    }
+}
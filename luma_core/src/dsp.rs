@@ -0,0 +1,231 @@
+//! ``dsp`` module of ``luma_core``.
+//!
+//! Contains the control of the audio DSP: resetting it, booting microcode through its ROM
+//! loader, and exchanging mails with it.  The mail protocol itself lives in
+//! [`luma_formats::dsp`].
+
+use crate::audio;
+use crate::cache::DCFlushRange;
+use crate::interrupt::{self, Interrupt};
+use crate::io::{read16, write16};
+use crate::mem::PhysAddr;
+use crate::processor::ppc_nop;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+pub use luma_formats::dsp::{DspError, DspRegisters, DspState, Mailbox, Ucode};
+
+const DSP_BASE: PhysAddr = PhysAddr::new(0x0c00_5000);
+
+const CONTROL: u32 = 0x0a;
+
+bitflags::bitflags! {
+    /// The DSP control and status register, whose interrupt status bits are cleared by writing
+    /// them back, and therefore must never be written back by accident.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub(crate) struct DspControl: u16 {
+        const RESET = 1 << 0;
+        const PI_INTERRUPT = 1 << 1;
+        const HALT = 1 << 2;
+        const AI_INTERRUPT = 1 << 3;
+        const AI_INTERRUPT_MASK = 1 << 4;
+        const ARAM_INTERRUPT = 1 << 5;
+        const ARAM_INTERRUPT_MASK = 1 << 6;
+        const DSP_INTERRUPT = 1 << 7;
+        const DSP_INTERRUPT_MASK = 1 << 8;
+        const DMA_BUSY = 1 << 9;
+        /// Start from the ROM instead of IRAM when leaving reset.
+        const BOOT_ROM = 1 << 11;
+        const INTERRUPTS = Self::AI_INTERRUPT.bits() | Self::ARAM_INTERRUPT.bits() | Self::DSP_INTERRUPT.bits();
+    }
+}
+
+impl DspControl {
+    pub(crate) fn read() -> Self {
+        Self::from_bits_retain(read16(DSP_BASE + CONTROL))
+    }
+
+    pub(crate) fn write(self) {
+        write16(DSP_BASE + CONTROL, self.bits())
+    }
+
+    /// Update some bits, without acknowledging any pending interrupt.
+    pub(crate) fn update(f: impl FnOnce(&mut Self)) {
+        interrupt::free(|| {
+            let mut control = Self::read().difference(Self::INTERRUPTS);
+            f(&mut control);
+            control.write();
+        })
+    }
+}
+
+static MAIL_RECEIVED: AtomicBool = AtomicBool::new(false);
+static MAIL_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Route the DSP interrupt to this module, which is shared between the AI DMA, the ARAM DMA and
+/// the DSP itself.
+pub(crate) fn init_interrupt() {
+    interrupt::set_handler(Interrupt::Dsp, Some(on_interrupt));
+    interrupt::unmask(Interrupt::Dsp);
+}
+
+fn on_interrupt(_: Interrupt) {
+    let control = DspControl::read();
+    let acknowledged = control.difference(DspControl::INTERRUPTS);
+    if control.contains(DspControl::AI_INTERRUPT) {
+        (acknowledged | DspControl::AI_INTERRUPT).write();
+        audio::on_dma_interrupt();
+    }
+    if control.contains(DspControl::ARAM_INTERRUPT) {
+        (acknowledged | DspControl::ARAM_INTERRUPT).write();
+    }
+    if control.contains(DspControl::DSP_INTERRUPT) {
+        (acknowledged | DspControl::DSP_INTERRUPT).write();
+        MAIL_RECEIVED.store(true, Ordering::Release);
+
+        let callback = MAIL_CALLBACK.load(Ordering::Acquire);
+        if !callback.is_null() {
+            let callback = unsafe { core::mem::transmute::<*mut (), fn()>(callback) };
+            callback();
+        }
+    }
+}
+
+/// Set a callback called from the DSP interrupt, which the microcode usually raises after
+/// sending a mail, returning the previous one.
+pub fn set_mail_callback(callback: Option<fn()>) -> Option<fn()> {
+    let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+    let old = MAIL_CALLBACK.swap(new, Ordering::AcqRel);
+    if old.is_null() {
+        None
+    } else {
+        Some(unsafe { core::mem::transmute::<*mut (), fn()>(old) })
+    }
+}
+
+/// The actual DSP registers.
+#[derive(Debug)]
+pub struct Hardware;
+
+impl DspRegisters for Hardware {
+    fn read16(&mut self, offset: u32) -> u16 {
+        read16(DSP_BASE + offset)
+    }
+
+    fn write16(&mut self, offset: u32, value: u16) {
+        write16(DSP_BASE + offset, value)
+    }
+
+    fn pause(&mut self) {
+        ppc_nop();
+    }
+}
+
+/// The audio DSP.
+pub struct Dsp {
+    mailbox: Mailbox<Hardware>,
+    state: DspState,
+}
+
+impl Dsp {
+    /// Take control of the DSP, assuming it is halted, and route its interrupt to this module.
+    ///
+    /// This must be called after [`crate::interrupt::init`].
+    pub fn new() -> Dsp {
+        init_interrupt();
+        DspControl::update(|control| control.insert(DspControl::DSP_INTERRUPT_MASK));
+        Dsp {
+            mailbox: Mailbox::new(Hardware),
+            state: DspState::Halted,
+        }
+    }
+
+    /// Check whether the DSP raised its interrupt since the last call, meaning it most likely sent
+    /// a mail.
+    pub fn take_interrupt(&self) -> bool {
+        MAIL_RECEIVED.swap(false, Ordering::AcqRel)
+    }
+
+    /// Get the state of the DSP.
+    pub fn state(&self) -> DspState {
+        self.state
+    }
+
+    /// Hold the DSP in reset.
+    pub fn halt(&mut self) {
+        DspControl::update(|control| control.insert(DspControl::RESET | DspControl::HALT));
+        self.state = DspState::Halted;
+    }
+
+    /// Reset the DSP and let it run its ROM, waiting for a task.
+    pub fn reset(&mut self) {
+        DspControl::update(|control| {
+            control.insert(DspControl::RESET | DspControl::HALT | DspControl::BOOT_ROM)
+        });
+        DspControl::update(|control| control.remove(DspControl::RESET | DspControl::HALT));
+        self.state = DspState::Booting;
+    }
+
+    /// Check whether the DSP has read the last mail sent to it.
+    pub fn can_send_mail(&mut self) -> bool {
+        self.mailbox.can_send_mail()
+    }
+
+    /// Send a mail to the DSP, failing if it hasn’t read the previous one yet.
+    pub fn try_send_mail(&mut self, mail: u32) -> Result<(), DspError> {
+        self.mailbox.try_send_mail(mail)
+    }
+
+    /// Send a mail to the DSP, waiting for it to read the previous one first.
+    pub fn send_mail(&mut self, mail: u32) -> Result<(), DspError> {
+        self.mailbox.send_mail(mail)
+    }
+
+    /// Check whether the DSP sent a mail which hasn’t been read yet.
+    pub fn has_mail(&mut self) -> bool {
+        self.mailbox.has_mail()
+    }
+
+    /// Read the mail sent by the DSP, if any.
+    pub fn recv_mail(&mut self) -> Option<u32> {
+        self.mailbox.recv_mail()
+    }
+
+    /// Wait for the DSP to send a mail, and read it.
+    pub fn wait_mail(&mut self) -> Result<u32, DspError> {
+        self.mailbox.wait_mail()
+    }
+
+    /// Reset the DSP and have its ROM DMA ``ucode`` to IRAM and run it, holding the DSP in reset
+    /// again if that fails.
+    ///
+    /// Once this returns, the microcode is running and mails are its own protocol.
+    pub fn load_ucode(&mut self, ucode: &Ucode) -> Result<(), DspError> {
+        if self.state != DspState::Booting {
+            self.reset();
+        }
+        let iram = ucode.iram;
+        unsafe { DCFlushRange(iram.as_ptr() as *const u32, iram.len() as u32) };
+        let address = PhysAddr::of(iram.as_ptr());
+        if let Err(err) = self.mailbox.load_ucode(ucode, address.value()) {
+            self.halt();
+            return Err(err);
+        }
+        self.state = DspState::Running;
+        Ok(())
+    }
+
+    /// Check that the microcode is running, for protocols built on top of this.
+    pub fn ensure_running(&self) -> Result<(), DspError> {
+        match self.state {
+            DspState::Running => Ok(()),
+            state => Err(DspError::BadState(state)),
+        }
+    }
+}
+
+impl Default for Dsp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
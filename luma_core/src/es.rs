@@ -0,0 +1,282 @@
+//! ``es`` module of ``luma_core``.
+//!
+//! Contains a client for the ES module of IOS at ``/dev/es``, which knows about the titles
+//! installed on the NAND along with their tickets and TMDs, and launches them.
+//!
+//! Titles are named by a 64 bits ID, whose upper half is their kind (1 for system titles,
+//! 0x10001 for channels, and so on) and whose lower half is usually a four letters code.
+
+use crate::fs::nand::{self, FsError};
+use crate::ios::{Aligned, Buffer, Handle, IosError, Mode};
+use crate::stm;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+
+pub(crate) const DEVICE: &str = "/dev/es";
+
+pub(crate) const IOCTL_LAUNCH: u32 = 0x08;
+const IOCTL_GET_TITLE_COUNT: u32 = 0x0e;
+const IOCTL_GET_TITLES: u32 = 0x0f;
+const IOCTL_GET_VIEW_COUNT: u32 = 0x12;
+const IOCTL_GET_VIEWS: u32 = 0x13;
+const IOCTL_GET_DATA_DIR: u32 = 0x1d;
+const IOCTL_GET_TITLE_ID: u32 = 0x20;
+const IOCTL_GET_STORED_TMD_SIZE: u32 = 0x34;
+const IOCTL_GET_STORED_TMD: u32 = 0x35;
+
+/// Title ID of the System Menu.
+pub const SYSTEM_MENU: u64 = 0x0000_0001_0000_0002;
+
+/// Title ID of the Homebrew Channel, ``LULZ``.
+pub const HOMEBREW_CHANNEL: u64 = 0x0001_0001_4c55_4c5a;
+
+/// Title ID of versions of the Homebrew Channel before 1.0.7, ``HAXX``.
+pub const HOMEBREW_CHANNEL_LEGACY: u64 = 0x0001_0001_4841_5858;
+
+/// Size of a ticket view, as returned by ES.
+pub const TICKET_VIEW_SIZE: usize = 0xd8;
+
+/// Size of the path of a data directory, as returned by ES.
+const DATA_DIR_SIZE: usize = 30;
+
+/// Signature type of TMDs signed with RSA-2048, the only kind there is.
+const SIGNATURE_RSA_2048: u32 = 0x0001_0001;
+
+/// Where the contents start in a TMD, and the size of each.
+const TMD_CONTENTS: usize = 0x1e4;
+const CONTENT_SIZE: usize = 36;
+
+fn device() -> Result<Handle, IosError> {
+    Handle::open(DEVICE, Mode::None)
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// What ES tells about a ticket, without its title key.
+#[derive(Clone, Copy)]
+pub struct TicketView(pub [u8; TICKET_VIEW_SIZE]);
+
+impl TicketView {
+    pub fn ticket_id(&self) -> u64 {
+        u64_at(&self.0, 0x04)
+    }
+
+    /// Get the ID of the console the ticket is for, or zero if it is for any.
+    pub fn console_id(&self) -> u32 {
+        u32_at(&self.0, 0x0c)
+    }
+
+    pub fn title_id(&self) -> u64 {
+        u64_at(&self.0, 0x10)
+    }
+}
+
+/// A content of a title, as listed in its TMD.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Content {
+    /// The ID of the content, also the name of its file on the NAND.
+    pub id: u32,
+    pub index: u16,
+    /// Whether the content is shared with other titles, among other flags.
+    pub kind: u16,
+    pub size: u64,
+    /// The SHA-1 of the decrypted content.
+    pub hash: [u8; 20],
+}
+
+/// The title metadata of a title, describing it and listing its contents.
+#[derive(Clone, Debug)]
+pub struct Tmd {
+    data: Vec<u8>,
+}
+
+impl Tmd {
+    /// Parse a signed TMD, checking that all of its contents are there.
+    pub fn parse(data: Vec<u8>) -> Option<Tmd> {
+        if data.len() < TMD_CONTENTS || u32_at(&data, 0) != SIGNATURE_RSA_2048 {
+            return None;
+        }
+        let count = u16_at(&data, 0x1de) as usize;
+        if data.len() < TMD_CONTENTS + count * CONTENT_SIZE {
+            return None;
+        }
+        Some(Tmd { data })
+    }
+
+    /// Get the whole TMD, as signed.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Get the title ID of the IOS the title runs on.
+    pub fn ios(&self) -> u64 {
+        u64_at(&self.data, 0x184)
+    }
+
+    pub fn title_id(&self) -> u64 {
+        u64_at(&self.data, 0x18c)
+    }
+
+    pub fn group_id(&self) -> u16 {
+        u16_at(&self.data, 0x198)
+    }
+
+    pub fn region(&self) -> u16 {
+        u16_at(&self.data, 0x19c)
+    }
+
+    /// Get the hardware the title may access, such as DVD video playback.
+    pub fn access_rights(&self) -> u32 {
+        u32_at(&self.data, 0x1d8)
+    }
+
+    pub fn title_version(&self) -> u16 {
+        u16_at(&self.data, 0x1dc)
+    }
+
+    /// Get the index of the content booted when launching the title.
+    pub fn boot_index(&self) -> u16 {
+        u16_at(&self.data, 0x1e0)
+    }
+
+    /// Iterate over the contents of the title.
+    pub fn contents(&self) -> impl Iterator<Item = Content> + '_ {
+        let count = u16_at(&self.data, 0x1de) as usize;
+        self.data[TMD_CONTENTS..TMD_CONTENTS + count * CONTENT_SIZE]
+            .chunks_exact(CONTENT_SIZE)
+            .map(|content| Content {
+                id: u32_at(content, 0),
+                index: u16_at(content, 4),
+                kind: u16_at(content, 6),
+                size: u64_at(content, 8),
+                hash: content[16..36].try_into().unwrap(),
+            })
+    }
+}
+
+/// Get the title ID of the running title, which for apps started from the Homebrew Channel is
+/// the one of the channel.
+pub fn title_id() -> Result<u64, IosError> {
+    let mut output = Aligned([0; 8]);
+    device()?.ioctlv(IOCTL_GET_TITLE_ID, &[], &mut [&mut output.0])?;
+    Ok(u64::from_be_bytes(output.0))
+}
+
+/// List the titles installed on the NAND.
+pub fn titles() -> Result<Vec<u64>, IosError> {
+    let es = device()?;
+    let mut count = Aligned([0; 4]);
+    es.ioctlv(IOCTL_GET_TITLE_COUNT, &[], &mut [&mut count.0])?;
+    let count = u32::from_be_bytes(count.0);
+
+    let mut titles = Buffer::new(count as usize * 8);
+    let input = Aligned(count.to_be_bytes());
+    es.ioctlv(IOCTL_GET_TITLES, &[&input.0], &mut [&mut titles])?;
+    Ok(titles
+        .chunks_exact(8)
+        .map(|title| u64::from_be_bytes(title.try_into().unwrap()))
+        .collect())
+}
+
+/// Read the TMD of an installed title.
+pub fn tmd(title: u64) -> Result<Tmd, IosError> {
+    let es = device()?;
+    let title_id = Aligned(title.to_be_bytes());
+    let mut size = Aligned([0; 4]);
+    es.ioctlv(
+        IOCTL_GET_STORED_TMD_SIZE,
+        &[&title_id.0],
+        &mut [&mut size.0],
+    )?;
+
+    let mut tmd = Buffer::new(u32::from_be_bytes(size.0) as usize);
+    es.ioctlv(
+        IOCTL_GET_STORED_TMD,
+        &[&title_id.0, &size.0],
+        &mut [&mut tmd],
+    )?;
+    Tmd::parse(tmd.to_vec()).ok_or(IosError::Invalid)
+}
+
+/// List the views of the tickets of a title, of which there may be several.
+pub fn ticket_views(title: u64) -> Result<Vec<TicketView>, IosError> {
+    let es = device()?;
+    let title_id = Aligned(title.to_be_bytes());
+    let mut count = Aligned([0; 4]);
+    es.ioctlv(IOCTL_GET_VIEW_COUNT, &[&title_id.0], &mut [&mut count.0])?;
+    let views = u32::from_be_bytes(count.0) as usize;
+    if views == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut views = Buffer::new(views * TICKET_VIEW_SIZE);
+    es.ioctlv(IOCTL_GET_VIEWS, &[&title_id.0, &count.0], &mut [&mut views])?;
+    Ok(views
+        .chunks_exact(TICKET_VIEW_SIZE)
+        .map(|view| TicketView(view.try_into().unwrap()))
+        .collect())
+}
+
+/// Read the whole ticket of a title off the NAND, which IOS only allows to some titles.
+pub fn ticket(title: u64) -> Result<Vec<u8>, FsError> {
+    nand::read(&format!(
+        "/ticket/{:08x}/{:08x}.tik",
+        title >> 32,
+        title as u32
+    ))
+}
+
+/// Get the path of the data directory of a title on the NAND, where its saves are.
+pub fn data_dir(title: u64) -> Result<String, IosError> {
+    let title_id = Aligned(title.to_be_bytes());
+    let mut path = Aligned([0; 32]);
+    device()?.ioctlv(
+        IOCTL_GET_DATA_DIR,
+        &[&title_id.0],
+        &mut [&mut path.0[..DATA_DIR_SIZE]],
+    )?;
+    // A path filling the whole buffer comes without a NUL.
+    let path = &path.0[..DATA_DIR_SIZE];
+    let end = path.iter().position(|&byte| byte == 0);
+    core::str::from_utf8(&path[..end.unwrap_or(DATA_DIR_SIZE)])
+        .map(String::from)
+        .map_err(|_| IosError::Invalid)
+}
+
+/// Get the path of the data directory of the running title.
+pub fn current_data_dir() -> Result<String, IosError> {
+    data_dir(title_id()?)
+}
+
+/// Launch an installed title, which replaces the current app along with the running IOS.
+///
+/// This only returns if IOS refused to, for instance if the title isn’t installed or has no
+/// ticket.
+pub fn launch_title(title: u64) -> Result<Infallible, IosError> {
+    // Any of the tickets will do, the first one gets used.
+    let view = ticket_views(title)?
+        .into_iter()
+        .next()
+        .ok_or(IosError::NotFound)?;
+    let view = Aligned(view.0);
+    let title_id = Aligned(title.to_be_bytes());
+    let es = device()?;
+
+    // IOS doesn’t launch anything while someone is waiting for the buttons.
+    stm::release_event_hook()?;
+    // On success IOS reloads itself and resets the PowerPC, so this never completes.
+    es.ioctlv::<[u8]>(IOCTL_LAUNCH, &[&title_id.0, &view.0], &mut [])?;
+    Err(IosError::Invalid)
+}
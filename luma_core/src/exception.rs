@@ -0,0 +1,196 @@
+//! ``exception`` module of ``luma_core``.
+//!
+//! Contains the installation of the processor exception vectors, and the dispatching of every
+//! exception to a handler written in Rust.
+
+use crate::cache::{DCFlushRange, ICInvalidateRange};
+use crate::mem::PhysAddr;
+use crate::processor::ppc_halt;
+use crate::{DolphinHle, register};
+use core::arch::global_asm;
+use core::fmt::Write;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+global_asm!(include_str!("../asm/exception.S"));
+
+// Load the vector template from global assembly.
+unsafe extern "C" {
+    static __exception_vector_start: u8;
+    static __exception_vector_patch: u8;
+    static __exception_vector_end: u8;
+}
+
+/// The exceptions of the Broadway processor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exception {
+    SystemReset = 0,
+    MachineCheck = 1,
+    Dsi = 2,
+    Isi = 3,
+    External = 4,
+    Alignment = 5,
+    Program = 6,
+    FloatingPointUnavailable = 7,
+    Decrementer = 8,
+    SystemCall = 9,
+    Trace = 10,
+    PerformanceMonitor = 11,
+    InstructionBreakpoint = 12,
+    SystemManagement = 13,
+    Thermal = 14,
+}
+
+impl Exception {
+    const ALL: [Exception; 15] = [
+        Exception::SystemReset,
+        Exception::MachineCheck,
+        Exception::Dsi,
+        Exception::Isi,
+        Exception::External,
+        Exception::Alignment,
+        Exception::Program,
+        Exception::FloatingPointUnavailable,
+        Exception::Decrementer,
+        Exception::SystemCall,
+        Exception::Trace,
+        Exception::PerformanceMonitor,
+        Exception::InstructionBreakpoint,
+        Exception::SystemManagement,
+        Exception::Thermal,
+    ];
+
+    /// Offset of the vector of this exception in low memory.
+    pub const fn vector(self) -> u32 {
+        match self {
+            Exception::SystemReset => 0x0100,
+            Exception::MachineCheck => 0x0200,
+            Exception::Dsi => 0x0300,
+            Exception::Isi => 0x0400,
+            Exception::External => 0x0500,
+            Exception::Alignment => 0x0600,
+            Exception::Program => 0x0700,
+            Exception::FloatingPointUnavailable => 0x0800,
+            Exception::Decrementer => 0x0900,
+            Exception::SystemCall => 0x0c00,
+            Exception::Trace => 0x0d00,
+            Exception::PerformanceMonitor => 0x0f00,
+            Exception::InstructionBreakpoint => 0x1300,
+            Exception::SystemManagement => 0x1400,
+            Exception::Thermal => 0x1700,
+        }
+    }
+}
+
+/// The context of the code which got interrupted by an exception.
+///
+/// Every field is restored when the handler returns, so a handler can for instance skip the
+/// faulting instruction by advancing ``srr0``.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    back_chain: u32,
+    lr_save: u32,
+    pub gpr: [u32; 32],
+    pub cr: u32,
+    pub lr: u32,
+    pub ctr: u32,
+    pub xer: u32,
+    /// Address of the interrupted instruction (or of the next one, depending on the exception).
+    pub srr0: u32,
+    /// MSR of the interrupted code.
+    pub srr1: u32,
+    pub fpr: [f64; 32],
+    pub fpscr: u64,
+}
+
+/// Handler called with external interrupts disabled whenever the exception happens.
+pub type ExceptionHandler = fn(Exception, &mut ExceptionFrame);
+
+static HANDLERS: [AtomicPtr<()>; 15] = [const { AtomicPtr::new(ptr::null_mut()) }; 15];
+
+/// Copy the vector template to every exception vector, and install the default handlers.
+///
+/// Unhandled exceptions print the interrupted context and halt, except for the decrementer
+/// which just gets rearmed.
+pub fn init() {
+    let start = unsafe { &__exception_vector_start } as *const u8;
+    let patch = unsafe { &__exception_vector_patch } as *const u8;
+    let end = unsafe { &__exception_vector_end } as *const u8;
+    let size = end as usize - start as usize;
+    let patch_offset = patch as usize - start as usize;
+
+    set_handler(Exception::Decrementer, Some(rearm_decrementer));
+
+    for exception in Exception::ALL {
+        let vector = PhysAddr::new(exception.vector())
+            .cached()
+            .as_mut_ptr::<u8>();
+        unsafe {
+            ptr::copy_nonoverlapping(start, vector, size);
+            // li r3, exception
+            let li = 0x3860_0000 | exception as u32;
+            (vector.add(patch_offset) as *mut u32).write_volatile(li);
+            DCFlushRange(vector as *const u32, 0x100);
+            ICInvalidateRange(vector as *const u32, 0x100);
+        }
+    }
+}
+
+/// Set the handler of an exception, returning the previous one.
+pub fn set_handler(
+    exception: Exception,
+    handler: Option<ExceptionHandler>,
+) -> Option<ExceptionHandler> {
+    let new = handler.map_or(ptr::null_mut(), |handler| handler as *mut ());
+    let old = HANDLERS[exception as usize].swap(new, Ordering::AcqRel);
+    if old.is_null() {
+        None
+    } else {
+        Some(unsafe { core::mem::transmute::<*mut (), ExceptionHandler>(old) })
+    }
+}
+
+fn rearm_decrementer(_: Exception, _: &mut ExceptionFrame) {
+    register::mtdec(0x7fff_ffff);
+}
+
+/// Called from the assembly vectors, with the context saved in ``frame``.
+#[unsafe(no_mangle)]
+extern "C" fn __exception_dispatch(exception: u32, frame: &mut ExceptionFrame) {
+    let exception = Exception::ALL[exception as usize];
+    let handler = HANDLERS[exception as usize].load(Ordering::Acquire);
+    if handler.is_null() {
+        unhandled(exception, frame);
+    }
+    let handler = unsafe { core::mem::transmute::<*mut (), ExceptionHandler>(handler) };
+    handler(exception, frame);
+}
+
+fn unhandled(exception: Exception, frame: &ExceptionFrame) -> ! {
+    let mut out = DolphinHle;
+    let _ = writeln!(
+        out,
+        "Unhandled {:?} exception at {:08x}, MSR {:08x}",
+        exception, frame.srr0, frame.srr1
+    );
+    for (i, gprs) in frame.gpr.chunks(4).enumerate() {
+        let _ = writeln!(
+            out,
+            "r{:<2} {:08x} {:08x} {:08x} {:08x}",
+            i * 4,
+            gprs[0],
+            gprs[1],
+            gprs[2],
+            gprs[3]
+        );
+    }
+    let _ = writeln!(
+        out,
+        "LR {:08x} CTR {:08x} CR {:08x} XER {:08x}",
+        frame.lr, frame.ctr, frame.cr, frame.xer
+    );
+    ppc_halt();
+    unreachable!()
+}
@@ -0,0 +1,8 @@
+//! ``fat`` module of ``luma_core``.
+//!
+//! Contains the FAT12, FAT16, FAT32 and exFAT filesystem of ``luma_fat``, used on top of the
+//! SD card and USB mass storage drivers through [`crate::block::BlockDevice`].
+
+pub use luma_fat::{
+    Attributes, DirEntry, FatError, FatKind, File, FileSystem, Metadata, ReadDir, Timestamp,
+};
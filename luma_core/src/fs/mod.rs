@@ -0,0 +1,513 @@
+//! ``fs`` module of ``luma_core``.
+//!
+//! Contains a virtual filesystem, giving access to the filesystems of every device through
+//! paths starting with the name they got mounted as, such as ``sd:/saves/slot1.bin`` or
+//! ``nand:/shared2/sys/SYSCONF``, so that the rest of the program doesn’t care where a file is.
+//!
+//! The SD card, USB drive and NAND get mounted as ``sd``, ``usb`` and ``nand`` by
+//! [`mount_sd`], [`mount_usb`] and [`mount_nand`], the bundled romfs image as ``romfs`` by
+//! [`mount_romfs`], and anything implementing [`Filesystem`] can be mounted with [`mount`].
+//! Files are read and written through the ``embedded-io`` traits.
+
+mod mount;
+pub mod nand;
+
+pub use mount::{Nand, mount_nand, mount_romfs, mount_sd, mount_usb};
+
+use crate::fat::FatError;
+use crate::interrupt::Mutex;
+use crate::romfs::RomFsError;
+use crate::sdio::SdError;
+use crate::usb::storage::StorageError;
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use embedded_io::{ErrorKind, ErrorType, Read, Seek, SeekFrom, Write};
+use nand::FsError;
+
+/// Errors which can be encountered while using the virtual filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VfsError {
+    /// Nothing is mounted under the name the path starts with.
+    NotMounted,
+    /// The path lacks a mount point, or a name can’t be used on that filesystem.
+    Invalid,
+    /// The file or directory doesn’t exist.
+    NotFound,
+    /// The file, directory or mount point already exists.
+    AlreadyExists,
+    /// A directory was expected.
+    NotADirectory,
+    /// A file was expected.
+    IsADirectory,
+    /// The directory to remove isn’t empty.
+    NotEmpty,
+    /// The file wasn’t opened for this, or the running title isn’t allowed to do it.
+    PermissionDenied,
+    /// The filesystem or its device can’t be written to.
+    ReadOnly,
+    /// There is no space left on the filesystem.
+    NoSpace,
+    /// The file would grow past what the filesystem supports.
+    TooLarge,
+    /// The filesystem can’t do this, or isn’t one we know about.
+    Unsupported,
+    /// The filesystem is corrupted.
+    Corrupted,
+    /// Any other error of ISFS.
+    Nand(FsError),
+    /// The SD card failed.
+    Sd(SdError),
+    /// The USB drive failed.
+    Usb(StorageError),
+}
+
+impl From<FsError> for VfsError {
+    fn from(error: FsError) -> VfsError {
+        match error {
+            FsError::Invalid | FsError::NameTooLong | FsError::TooDeep => VfsError::Invalid,
+            FsError::PermissionDenied => VfsError::PermissionDenied,
+            FsError::Corrupted => VfsError::Corrupted,
+            FsError::AlreadyExists => VfsError::AlreadyExists,
+            FsError::NotFound => VfsError::NotFound,
+            FsError::NoSpace | FsError::NoInodes => VfsError::NoSpace,
+            FsError::NotEmpty => VfsError::NotEmpty,
+            error => VfsError::Nand(error),
+        }
+    }
+}
+
+impl From<SdError> for VfsError {
+    fn from(error: SdError) -> VfsError {
+        VfsError::Sd(error)
+    }
+}
+
+impl From<StorageError> for VfsError {
+    fn from(error: StorageError) -> VfsError {
+        match error {
+            StorageError::ReadOnly => VfsError::ReadOnly,
+            error => VfsError::Usb(error),
+        }
+    }
+}
+
+impl<E: Into<VfsError>> From<FatError<E>> for VfsError {
+    fn from(error: FatError<E>) -> VfsError {
+        match error {
+            FatError::Device(error) => error.into(),
+            FatError::NoFilesystem | FatError::Unsupported => VfsError::Unsupported,
+            FatError::Corrupted => VfsError::Corrupted,
+            FatError::NotFound => VfsError::NotFound,
+            FatError::AlreadyExists => VfsError::AlreadyExists,
+            FatError::NotADirectory => VfsError::NotADirectory,
+            FatError::IsADirectory => VfsError::IsADirectory,
+            FatError::NotEmpty => VfsError::NotEmpty,
+            FatError::InvalidName | FatError::Invalid => VfsError::Invalid,
+            FatError::NoSpace => VfsError::NoSpace,
+            FatError::TooLarge => VfsError::TooLarge,
+            FatError::ReadOnly => VfsError::ReadOnly,
+        }
+    }
+}
+
+impl From<RomFsError> for VfsError {
+    fn from(error: RomFsError) -> VfsError {
+        match error {
+            RomFsError::BadMagic => VfsError::NotFound,
+            RomFsError::UnsupportedVersion => VfsError::Unsupported,
+            RomFsError::Corrupted => VfsError::Corrupted,
+        }
+    }
+}
+
+impl embedded_io::Error for VfsError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            VfsError::NotMounted | VfsError::NotFound => ErrorKind::NotFound,
+            VfsError::Invalid | VfsError::TooLarge => ErrorKind::InvalidInput,
+            VfsError::AlreadyExists => ErrorKind::AlreadyExists,
+            VfsError::PermissionDenied | VfsError::ReadOnly => ErrorKind::PermissionDenied,
+            VfsError::NoSpace => ErrorKind::OutOfMemory,
+            VfsError::Unsupported => ErrorKind::Unsupported,
+            VfsError::Corrupted => ErrorKind::InvalidData,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Information about a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    /// The size of a file, directories have none.
+    pub len: u64,
+    pub directory: bool,
+    pub read_only: bool,
+}
+
+impl Metadata {
+    /// Whether this is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.directory
+    }
+
+    /// Whether this is a file.
+    pub fn is_file(&self) -> bool {
+        !self.directory
+    }
+}
+
+/// An entry of a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// How to open a file, see [`File::open_with`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+impl OpenOptions {
+    /// Options opening nothing, at least reading or writing has to be added.
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// Allow reading the file.
+    pub fn with_read(mut self, read: bool) -> OpenOptions {
+        self.read = read;
+        self
+    }
+
+    /// Allow writing the file.
+    pub fn with_write(mut self, write: bool) -> OpenOptions {
+        self.write = write;
+        self
+    }
+
+    /// Write at the end of the file, wherever the position is.  This implies writing.
+    pub fn with_append(mut self, append: bool) -> OpenOptions {
+        self.append = append;
+        self
+    }
+
+    /// Empty the file once opened.  This needs writing.
+    pub fn with_truncate(mut self, truncate: bool) -> OpenOptions {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Create the file if it doesn’t exist.  This needs writing.
+    pub fn with_create(mut self, create: bool) -> OpenOptions {
+        self.create = create;
+        self
+    }
+
+    /// Create the file, failing if it exists.  This needs writing.
+    pub fn with_create_new(mut self, create_new: bool) -> OpenOptions {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Whether the file can be read.
+    pub fn read(&self) -> bool {
+        self.read
+    }
+
+    /// Whether the file can be written.
+    pub fn write(&self) -> bool {
+        self.write || self.append
+    }
+
+    /// Whether writes go to the end of the file.
+    pub fn append(&self) -> bool {
+        self.append
+    }
+
+    /// Whether the file gets emptied once opened.
+    pub fn truncate(&self) -> bool {
+        self.truncate
+    }
+
+    /// Whether the file gets created if it doesn’t exist.
+    pub fn create(&self) -> bool {
+        self.create || self.create_new
+    }
+
+    /// Whether opening fails if the file exists.
+    pub fn create_new(&self) -> bool {
+        self.create_new
+    }
+
+    fn check(&self) -> Result<(), VfsError> {
+        let modifies = self.truncate || self.create || self.create_new;
+        if !(self.read || self.write()) || (modifies && !self.write()) {
+            return Err(VfsError::Invalid);
+        }
+        Ok(())
+    }
+}
+
+/// A filesystem which can be mounted, paths given to it being relative to where it got
+/// mounted and starting with ``/``.
+pub trait Filesystem {
+    /// Open the file at ``path`` the way ``options`` tell, which already got checked to make
+    /// sense.  The file keeps the filesystem alive.
+    fn open(
+        self: Rc<Self>,
+        path: &str,
+        options: &OpenOptions,
+    ) -> Result<Box<dyn FileHandle>, VfsError>;
+
+    /// Get information about the file or directory at ``path``.
+    fn metadata(&self, path: &str) -> Result<Metadata, VfsError>;
+
+    /// List the entries of the directory at ``path``.
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, VfsError>;
+
+    /// Create an empty directory at ``path``.
+    fn create_dir(&self, _path: &str) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Remove the file or empty directory at ``path``.
+    fn remove(&self, _path: &str) -> Result<(), VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Write everything modified back to the device.
+    fn flush(&self) -> Result<(), VfsError> {
+        Ok(())
+    }
+}
+
+/// A file open on a [`Filesystem`].
+pub trait FileHandle {
+    /// Read from the current position into ``buffer``, returning how many bytes got read.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, VfsError>;
+
+    /// Write ``buffer`` at the current position, returning how many bytes got written.
+    fn write(&mut self, _buffer: &[u8]) -> Result<usize, VfsError> {
+        Err(VfsError::ReadOnly)
+    }
+
+    /// Move the current position, returning the new one.
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, VfsError>;
+
+    /// Make sure everything written reached the device.
+    fn flush(&mut self) -> Result<(), VfsError> {
+        Ok(())
+    }
+
+    /// Get information about the file.
+    fn metadata(&self) -> Result<Metadata, VfsError>;
+
+    /// Shrink or grow the file to ``len``.
+    fn set_len(&mut self, _len: u64) -> Result<(), VfsError> {
+        Err(VfsError::Unsupported)
+    }
+}
+
+struct Mount {
+    name: String,
+    filesystem: Rc<dyn Filesystem>,
+}
+
+/// The filesystems mounted.
+struct Mounts(Vec<Mount>);
+
+// Filesystems are only ever used from the main program, never from interrupt handlers, and
+// there is nothing else running on the single core.
+unsafe impl Send for Mounts {}
+
+static MOUNTS: Mutex<Mounts> = Mutex::new(Mounts(Vec::new()));
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+}
+
+/// Make ``filesystem`` available under ``name``, which has to be made of lower case letters and
+/// digits, so that ``name:/`` is its root.
+pub fn mount(name: &str, filesystem: Rc<dyn Filesystem>) -> Result<(), VfsError> {
+    if !is_valid_name(name) {
+        return Err(VfsError::Invalid);
+    }
+    MOUNTS.lock(|mounts| {
+        if mounts.0.iter().any(|mount| mount.name == name) {
+            return Err(VfsError::AlreadyExists);
+        }
+        mounts.0.push(Mount {
+            name: name.to_string(),
+            filesystem,
+        });
+        Ok(())
+    })
+}
+
+/// Flush and forget the filesystem mounted under ``name``.  Files still open on it keep it
+/// alive until they get dropped.
+pub fn unmount(name: &str) -> Result<(), VfsError> {
+    let mount = MOUNTS.lock(|mounts| {
+        let index = mounts.0.iter().position(|mount| mount.name == name);
+        index.map(|index| mounts.0.remove(index))
+    });
+    mount.ok_or(VfsError::NotMounted)?.filesystem.flush()
+}
+
+/// Whether something is mounted under ``name``.
+pub fn is_mounted(name: &str) -> bool {
+    MOUNTS.lock(|mounts| mounts.0.iter().any(|mount| mount.name == name))
+}
+
+/// Get the filesystem ``path`` is on, and the path in it.
+fn resolve(path: &str) -> Result<(Rc<dyn Filesystem>, &str), VfsError> {
+    let (name, path) = path.split_once(':').ok_or(VfsError::Invalid)?;
+    if !path.starts_with('/') {
+        return Err(VfsError::Invalid);
+    }
+    let filesystem = MOUNTS.lock(|mounts| {
+        let mount = mounts.0.iter().find(|mount| mount.name == name);
+        mount.map(|mount| mount.filesystem.clone())
+    });
+    Ok((filesystem.ok_or(VfsError::NotMounted)?, path))
+}
+
+/// Get information about the file or directory at ``path``.
+pub fn metadata(path: &str) -> Result<Metadata, VfsError> {
+    let (filesystem, path) = resolve(path)?;
+    filesystem.metadata(path)
+}
+
+/// List the entries of the directory at ``path``.
+pub fn read_dir(path: &str) -> Result<Vec<DirEntry>, VfsError> {
+    let (filesystem, path) = resolve(path)?;
+    filesystem.read_dir(path)
+}
+
+/// Create an empty directory at ``path``, whose parent must exist.
+pub fn create_dir(path: &str) -> Result<(), VfsError> {
+    let (filesystem, path) = resolve(path)?;
+    filesystem.create_dir(path)
+}
+
+/// Remove the file or empty directory at ``path``.
+pub fn remove(path: &str) -> Result<(), VfsError> {
+    let (filesystem, path) = resolve(path)?;
+    filesystem.remove(path)
+}
+
+/// Read the whole file at ``path``.
+pub fn read(path: &str) -> Result<Vec<u8>, VfsError> {
+    let mut file = File::open(path)?;
+    let mut data = vec![0; file.metadata()?.len as usize];
+    let mut done = 0;
+    while done < data.len() {
+        match file.read(&mut data[done..])? {
+            0 => break,
+            read => done += read,
+        }
+    }
+    data.truncate(done);
+    Ok(data)
+}
+
+/// Replace the content of the file at ``path`` with ``data``, creating it if needed.
+pub fn write(path: &str, data: &[u8]) -> Result<(), VfsError> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.flush()
+}
+
+/// A file open on any of the filesystems mounted.
+pub struct File {
+    handle: Box<dyn FileHandle>,
+    options: OpenOptions,
+}
+
+impl File {
+    /// Open the existing file at ``path`` for reading.
+    pub fn open(path: &str) -> Result<File, VfsError> {
+        File::open_with(path, &OpenOptions::new().with_read(true))
+    }
+
+    /// Create an empty file at ``path``, or truncate the existing one, and open it for writing.
+    pub fn create(path: &str) -> Result<File, VfsError> {
+        let options = OpenOptions::new()
+            .with_write(true)
+            .with_create(true)
+            .with_truncate(true);
+        File::open_with(path, &options)
+    }
+
+    /// Open the file at ``path`` the way ``options`` tell.
+    pub fn open_with(path: &str, options: &OpenOptions) -> Result<File, VfsError> {
+        options.check()?;
+        let (filesystem, path) = resolve(path)?;
+        Ok(File {
+            handle: filesystem.open(path, options)?,
+            options: *options,
+        })
+    }
+
+    /// Get information about the file.
+    pub fn metadata(&self) -> Result<Metadata, VfsError> {
+        self.handle.metadata()
+    }
+
+    /// Shrink or grow the file to ``len``, filling it with zeroes.  The position is left as
+    /// is.
+    pub fn set_len(&mut self, len: u64) -> Result<(), VfsError> {
+        if !self.options.write() {
+            return Err(VfsError::PermissionDenied);
+        }
+        self.handle.set_len(len)
+    }
+}
+
+impl ErrorType for File {
+    type Error = VfsError;
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, VfsError> {
+        if !self.options.read() {
+            return Err(VfsError::PermissionDenied);
+        }
+        self.handle.read(buf)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, VfsError> {
+        if !self.options.write() {
+            return Err(VfsError::PermissionDenied);
+        }
+        if self.options.append() {
+            self.handle.seek(SeekFrom::End(0))?;
+        }
+        self.handle.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), VfsError> {
+        self.handle.flush()
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, VfsError> {
+        self.handle.seek(pos)
+    }
+}
@@ -0,0 +1,320 @@
+//! ``mount`` module of ``luma_core::fs``.
+//!
+//! Contains the [`Filesystem`] implementations of the NAND, of FAT volumes and of romfs images,
+//! and the mounting of the devices of the console.
+
+use super::nand::{self, FsError, Permissions};
+use super::{DirEntry, FileHandle, Filesystem, Metadata, OpenOptions, VfsError, mount};
+use crate::block::BlockDevice;
+use crate::fat::{self, Attributes, FatError, FileSystem};
+use crate::ios::Mode;
+use crate::romfs::{self, RomFs};
+use crate::sdio::SdCard;
+use crate::usb::storage::MassStorage;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use embedded_io::{Read, Seek, SeekFrom, Write};
+
+/// Mount the FAT volume of the SD card as ``sd``.
+pub fn mount_sd() -> Result<(), VfsError> {
+    let fs = FileSystem::new(SdCard::open()?)?;
+    mount("sd", Rc::new(fs))
+}
+
+/// Mount the FAT volume of the first USB drive as ``usb``.
+pub fn mount_usb() -> Result<(), VfsError> {
+    let fs = FileSystem::new(MassStorage::open()?)?;
+    mount("usb", Rc::new(fs))
+}
+
+/// Mount the NAND as ``nand``, whose files the running title may or may not be allowed to
+/// access.
+pub fn mount_nand() -> Result<(), VfsError> {
+    mount("nand", Rc::new(Nand))
+}
+
+/// Mount the romfs image bundled into the executable as ``romfs``, failing with
+/// [`VfsError::NotFound`] if there is none.
+pub fn mount_romfs() -> Result<(), VfsError> {
+    mount("romfs", Rc::new(romfs::get()?))
+}
+
+fn fat_metadata(metadata: &fat::Metadata) -> Metadata {
+    Metadata {
+        len: metadata.len,
+        directory: metadata.is_dir(),
+        read_only: metadata.attributes.contains(Attributes::READ_ONLY),
+    }
+}
+
+impl<D> Filesystem for FileSystem<D>
+where
+    D: BlockDevice + 'static,
+    D::Error: Into<VfsError>,
+{
+    fn open(
+        self: Rc<Self>,
+        path: &str,
+        options: &OpenOptions,
+    ) -> Result<Box<dyn FileHandle>, VfsError> {
+        let exists = match FileSystem::metadata(&self, path) {
+            Ok(metadata) if metadata.is_dir() => return Err(VfsError::IsADirectory),
+            Ok(_) => true,
+            Err(FatError::NotFound) => false,
+            Err(error) => return Err(error.into()),
+        };
+        let file = match (exists, options.create_new(), options.create()) {
+            (true, true, _) => return Err(VfsError::AlreadyExists),
+            (false, _, false) => return Err(VfsError::NotFound),
+            (false, _, true) => self.create_shared(path)?,
+            (true, _, _) if options.truncate() => self.create_shared(path)?,
+            (true, _, _) => self.open_shared(path)?,
+        };
+        Ok(Box::new(file))
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, VfsError> {
+        Ok(fat_metadata(&FileSystem::metadata(self, path)?))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, VfsError> {
+        FileSystem::read_dir(self, path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok(DirEntry {
+                    metadata: fat_metadata(&entry.metadata),
+                    name: entry.name,
+                })
+            })
+            .collect()
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), VfsError> {
+        Ok(FileSystem::create_dir(self, path)?)
+    }
+
+    fn remove(&self, path: &str) -> Result<(), VfsError> {
+        Ok(FileSystem::remove(self, path)?)
+    }
+
+    fn flush(&self) -> Result<(), VfsError> {
+        Ok(FileSystem::flush(self)?)
+    }
+}
+
+impl<D> FileHandle for fat::File<'static, D>
+where
+    D: BlockDevice + 'static,
+    D::Error: Into<VfsError>,
+{
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        Ok(Read::read(self, buffer)?)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, VfsError> {
+        Ok(Write::write(self, buffer)?)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, VfsError> {
+        Ok(Seek::seek(self, position)?)
+    }
+
+    fn flush(&mut self) -> Result<(), VfsError> {
+        Ok(Write::flush(self)?)
+    }
+
+    fn metadata(&self) -> Result<Metadata, VfsError> {
+        Ok(fat_metadata(&fat::File::metadata(self)))
+    }
+
+    fn set_len(&mut self, len: u64) -> Result<(), VfsError> {
+        Ok(fat::File::set_len(self, len)?)
+    }
+}
+
+/// The filesystem of the NAND, as seen through ISFS.
+pub struct Nand;
+
+impl Filesystem for Nand {
+    fn open(
+        self: Rc<Self>,
+        path: &str,
+        options: &OpenOptions,
+    ) -> Result<Box<dyn FileHandle>, VfsError> {
+        let mode = match (options.read(), options.write()) {
+            (true, true) => Mode::ReadWrite,
+            (false, true) => Mode::Write,
+            _ => Mode::Read,
+        };
+        let file = if options.create_new() {
+            nand::create_file(path, Permissions::default())?;
+            nand::File::open(path, mode)?
+        } else if options.create() && options.truncate() {
+            // Opened for reading and writing, which ISFS is fine with even if only writing
+            // got asked for.
+            nand::File::create(path)?
+        } else {
+            if options.create() {
+                match nand::create_file(path, Permissions::default()) {
+                    Err(FsError::AlreadyExists) => (),
+                    result => result?,
+                }
+            }
+            let file = nand::File::open(path, mode)?;
+            if options.truncate() && !file.is_empty()? {
+                // ISFS can’t truncate files, only replace them.
+                return Err(VfsError::Unsupported);
+            }
+            file
+        };
+        Ok(Box::new(file))
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, VfsError> {
+        // ISFS doesn’t tell files and directories apart, only listing one of them works.
+        match nand::read_dir(path) {
+            Ok(_) => Ok(Metadata {
+                len: 0,
+                directory: true,
+                read_only: false,
+            }),
+            Err(FsError::NotFound) => Err(VfsError::NotFound),
+            Err(_) => {
+                let file = nand::File::open(path, Mode::Read)?;
+                Ok(Metadata {
+                    len: file.len()? as u64,
+                    directory: false,
+                    read_only: false,
+                })
+            }
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, VfsError> {
+        let parent = path.trim_end_matches('/');
+        nand::read_dir(path)?
+            .into_iter()
+            .map(|name| {
+                let metadata = self.metadata(&format!("{parent}/{name}"))?;
+                Ok(DirEntry { name, metadata })
+            })
+            .collect()
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), VfsError> {
+        Ok(nand::create_dir(path, Permissions::default())?)
+    }
+
+    fn remove(&self, path: &str) -> Result<(), VfsError> {
+        // ISFS would delete a directory along with everything in it.
+        if self.metadata(path)?.is_dir() && !nand::read_dir(path)?.is_empty() {
+            return Err(VfsError::NotEmpty);
+        }
+        Ok(nand::delete(path)?)
+    }
+}
+
+impl FileHandle for nand::File {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        Ok(Read::read(self, buffer)?)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, VfsError> {
+        Ok(Write::write(self, buffer)?)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, VfsError> {
+        Ok(Seek::seek(self, position)?)
+    }
+
+    fn metadata(&self) -> Result<Metadata, VfsError> {
+        Ok(Metadata {
+            len: self.len()? as u64,
+            directory: false,
+            read_only: false,
+        })
+    }
+}
+
+fn romfs_metadata(entry: &romfs::Entry) -> Metadata {
+    Metadata {
+        len: entry.data().len() as u64,
+        directory: entry.is_dir(),
+        read_only: true,
+    }
+}
+
+impl Filesystem for RomFs<'static> {
+    fn open(
+        self: Rc<Self>,
+        path: &str,
+        options: &OpenOptions,
+    ) -> Result<Box<dyn FileHandle>, VfsError> {
+        if options.write() {
+            return Err(VfsError::ReadOnly);
+        }
+        let entry = self.lookup(path).ok_or(VfsError::NotFound)?;
+        if entry.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        Ok(Box::new(RomFile {
+            data: entry.data(),
+            position: 0,
+        }))
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, VfsError> {
+        let entry = self.lookup(path).ok_or(VfsError::NotFound)?;
+        Ok(romfs_metadata(&entry))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, VfsError> {
+        let entry = self.lookup(path).ok_or(VfsError::NotFound)?;
+        if !entry.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        Ok(entry
+            .entries()
+            .map(|entry| DirEntry {
+                name: entry.name().into(),
+                metadata: romfs_metadata(&entry),
+            })
+            .collect())
+    }
+}
+
+/// A file of a romfs image, read straight from it.
+struct RomFile {
+    data: &'static [u8],
+    position: u64,
+}
+
+impl FileHandle for RomFile {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, VfsError> {
+        let start = self.position.min(self.data.len() as u64) as usize;
+        let count = buffer.len().min(self.data.len() - start);
+        buffer[..count].copy_from_slice(&self.data[start..start + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> Result<u64, VfsError> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => (self.data.len() as u64).checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(VfsError::Invalid)?;
+        Ok(self.position)
+    }
+
+    fn metadata(&self) -> Result<Metadata, VfsError> {
+        Ok(Metadata {
+            len: self.data.len() as u64,
+            directory: false,
+            read_only: true,
+        })
+    }
+}
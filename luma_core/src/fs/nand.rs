@@ -0,0 +1,446 @@
+//! ``nand`` module of ``luma_core::fs``.
+//!
+//! Contains a client for ISFS, the filesystem IOS keeps on the NAND.  Paths are absolute, such
+//! as ``/shared2/sys/SYSCONF``, and which of them can be accessed depends on the permissions of
+//! the running title.
+//!
+//! Directories and attributes are handled through ``/dev/fs``, while files are opened as IOS
+//! resources of their own.
+
+use crate::ios::{self, Buffer, Handle, IosError, MAX_PATH, Mode, Whence};
+use alloc::string::String;
+use alloc::vec::Vec;
+use embedded_io::{ErrorKind, ErrorType, Read, Seek, SeekFrom, Write};
+
+const DEVICE: &str = "/dev/fs";
+
+const IOCTL_GET_STATS: u32 = 0x02;
+const IOCTL_CREATE_DIR: u32 = 0x03;
+const IOCTL_READ_DIR: u32 = 0x04;
+const IOCTL_SET_ATTRIBUTES: u32 = 0x05;
+const IOCTL_GET_ATTRIBUTES: u32 = 0x06;
+const IOCTL_DELETE: u32 = 0x07;
+const IOCTL_RENAME: u32 = 0x08;
+const IOCTL_CREATE_FILE: u32 = 0x09;
+const IOCTL_GET_FILE_STATS: u32 = 0x0b;
+const IOCTL_GET_USAGE: u32 = 0x0c;
+
+/// Longest name of a file or directory, ISFS only has 8.3 names.
+const MAX_NAME: usize = 12;
+
+/// Size of the attributes as exchanged with ISFS.
+const ATTRIBUTES_SIZE: usize = 0x4a;
+
+/// Most data read or written at once when going through a bounce buffer.
+const BOUNCE_SIZE: usize = 8 * 1024;
+
+/// Errors which can be encountered while using ISFS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsError {
+    /// An argument got rejected, or the path isn’t absolute.
+    Invalid,
+    /// The title isn’t allowed to do this.
+    PermissionDenied,
+    /// The filesystem is corrupted.
+    Corrupted,
+    /// The file or directory already exists.
+    AlreadyExists,
+    /// The file or directory doesn’t exist.
+    NotFound,
+    /// Too many files are open already.
+    TooManyOpenFiles,
+    /// There is no space left on the NAND.
+    NoSpace,
+    /// There are no inodes left on the NAND.
+    NoInodes,
+    /// A name is longer than 12 characters, or the path longer than 63.
+    NameTooLong,
+    /// The file is already open.
+    AlreadyOpen,
+    /// The NAND failed to be read or written.
+    Io,
+    /// The directory to delete isn’t empty.
+    NotEmpty,
+    /// The path goes deeper than eight directories.
+    TooDeep,
+    /// The file is in use.
+    Busy,
+    /// Any other error returned by IOS.
+    Ios(IosError),
+}
+
+impl From<IosError> for FsError {
+    fn from(error: IosError) -> FsError {
+        match error.code() {
+            -101 => FsError::Invalid,
+            -102 => FsError::PermissionDenied,
+            -103 => FsError::Corrupted,
+            -105 => FsError::AlreadyExists,
+            -106 => FsError::NotFound,
+            -107 => FsError::TooManyOpenFiles,
+            -108 => FsError::NoSpace,
+            -109 => FsError::NoInodes,
+            -110 => FsError::NameTooLong,
+            -111 => FsError::AlreadyOpen,
+            -114 => FsError::Io,
+            -115 => FsError::NotEmpty,
+            -116 => FsError::TooDeep,
+            -118 => FsError::Busy,
+            _ => match error {
+                IosError::PermissionDenied => FsError::PermissionDenied,
+                IosError::AlreadyExists => FsError::AlreadyExists,
+                IosError::Invalid => FsError::Invalid,
+                IosError::NotFound => FsError::NotFound,
+                IosError::Busy => FsError::Busy,
+                error => FsError::Ios(error),
+            },
+        }
+    }
+}
+
+impl embedded_io::Error for FsError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            FsError::Invalid | FsError::NameTooLong | FsError::TooDeep => ErrorKind::InvalidInput,
+            FsError::PermissionDenied => ErrorKind::PermissionDenied,
+            FsError::Corrupted => ErrorKind::InvalidData,
+            FsError::AlreadyExists => ErrorKind::AlreadyExists,
+            FsError::NotFound => ErrorKind::NotFound,
+            FsError::NoSpace | FsError::NoInodes => ErrorKind::OutOfMemory,
+            FsError::Ios(IosError::OutOfMemory) => ErrorKind::OutOfMemory,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Copy ``path`` into the fixed size buffer ISFS expects, nul-terminated.
+fn path_buffer(path: &str) -> Result<[u8; MAX_PATH], FsError> {
+    if path.len() >= MAX_PATH {
+        return Err(FsError::NameTooLong);
+    }
+    let mut buffer = [0; MAX_PATH];
+    buffer[..path.len()].copy_from_slice(path.as_bytes());
+    Ok(buffer)
+}
+
+fn device() -> Result<Handle, FsError> {
+    Ok(Handle::open(DEVICE, Mode::None)?)
+}
+
+/// Who can access a file or directory, and how.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    pub owner: Mode,
+    pub group: Mode,
+    pub other: Mode,
+}
+
+impl Default for Permissions {
+    /// Everyone can read and write, as with most save data.
+    fn default() -> Permissions {
+        Permissions {
+            owner: Mode::ReadWrite,
+            group: Mode::ReadWrite,
+            other: Mode::ReadWrite,
+        }
+    }
+}
+
+fn mode(bits: u8) -> Mode {
+    match bits & 3 {
+        0 => Mode::None,
+        1 => Mode::Read,
+        2 => Mode::Write,
+        _ => Mode::ReadWrite,
+    }
+}
+
+/// The owner and permissions of a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attributes {
+    pub owner_id: u32,
+    pub group_id: u16,
+    pub permissions: Permissions,
+    /// Opaque flags, kept along with the file.
+    pub attributes: u8,
+}
+
+impl Attributes {
+    fn encode(&self, path: &str) -> Result<[u8; ATTRIBUTES_SIZE], FsError> {
+        let mut buffer = [0; ATTRIBUTES_SIZE];
+        buffer[0..4].copy_from_slice(&self.owner_id.to_be_bytes());
+        buffer[4..6].copy_from_slice(&self.group_id.to_be_bytes());
+        buffer[6..6 + MAX_PATH].copy_from_slice(&path_buffer(path)?);
+        buffer[6 + MAX_PATH] = self.permissions.owner as u8;
+        buffer[7 + MAX_PATH] = self.permissions.group as u8;
+        buffer[8 + MAX_PATH] = self.permissions.other as u8;
+        buffer[9 + MAX_PATH] = self.attributes;
+        Ok(buffer)
+    }
+
+    fn decode(buffer: &[u8; ATTRIBUTES_SIZE]) -> Attributes {
+        Attributes {
+            owner_id: u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]),
+            group_id: u16::from_be_bytes([buffer[4], buffer[5]]),
+            permissions: Permissions {
+                owner: mode(buffer[6 + MAX_PATH]),
+                group: mode(buffer[7 + MAX_PATH]),
+                other: mode(buffer[8 + MAX_PATH]),
+            },
+            attributes: buffer[9 + MAX_PATH],
+        }
+    }
+}
+
+/// Space taken by a directory and everything below it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Usage {
+    /// Amount of 16 KiB blocks.
+    pub blocks: u32,
+    pub inodes: u32,
+}
+
+/// Space taken and left on the whole NAND.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stats {
+    /// Size of a cluster, in bytes.
+    pub cluster_size: u32,
+    pub free_clusters: u32,
+    pub used_clusters: u32,
+    pub bad_clusters: u32,
+    pub reserved_clusters: u32,
+    pub free_inodes: u32,
+    pub used_inodes: u32,
+}
+
+fn u32_at(buffer: &[u8], index: usize) -> u32 {
+    let bytes = &buffer[index * 4..index * 4 + 4];
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn create(ioctl: u32, path: &str, permissions: Permissions) -> Result<(), FsError> {
+    let attributes = Attributes {
+        // Both get replaced by those of the running title.
+        owner_id: 0,
+        group_id: 0,
+        permissions,
+        attributes: 0,
+    };
+    let input = Buffer::from_slice(&attributes.encode(path)?);
+    device()?.ioctl(ioctl, &input, &mut [])?;
+    Ok(())
+}
+
+/// Create an empty file at ``path``.
+pub fn create_file(path: &str, permissions: Permissions) -> Result<(), FsError> {
+    create(IOCTL_CREATE_FILE, path, permissions)
+}
+
+/// Create an empty directory at ``path``, whose parent must exist.
+pub fn create_dir(path: &str, permissions: Permissions) -> Result<(), FsError> {
+    create(IOCTL_CREATE_DIR, path, permissions)
+}
+
+/// Delete the file or the empty directory at ``path``.
+pub fn delete(path: &str) -> Result<(), FsError> {
+    let input = Buffer::from_slice(&path_buffer(path)?);
+    device()?.ioctl(IOCTL_DELETE, &input, &mut [])?;
+    Ok(())
+}
+
+/// Move the file or directory at ``from`` to ``to``.
+pub fn rename(from: &str, to: &str) -> Result<(), FsError> {
+    let mut input = Buffer::new(MAX_PATH * 2);
+    input[..MAX_PATH].copy_from_slice(&path_buffer(from)?);
+    input[MAX_PATH..].copy_from_slice(&path_buffer(to)?);
+    device()?.ioctl(IOCTL_RENAME, &input, &mut [])?;
+    Ok(())
+}
+
+/// List the names of the files and directories in the directory at ``path``.
+pub fn read_dir(path: &str) -> Result<Vec<String>, FsError> {
+    let device = device()?;
+    let path = Buffer::from_slice(&path_buffer(path)?);
+    let mut count = Buffer::new(4);
+    device.ioctlv(IOCTL_READ_DIR, &[&path], &mut [&mut count])?;
+    if u32_at(&count, 0) == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Each name takes at most 13 bytes, nul included.
+    let mut names = Buffer::new(u32_at(&count, 0) as usize * (MAX_NAME + 1));
+    let mut listed = Buffer::new(4);
+    device.ioctlv(
+        IOCTL_READ_DIR,
+        &[&path, &count],
+        &mut [&mut names, &mut listed],
+    )?;
+    Ok(names
+        .split(|&byte| byte == 0)
+        .take(u32_at(&listed, 0) as usize)
+        .map(|name| String::from_utf8_lossy(name).into_owned())
+        .collect())
+}
+
+/// Get the owner and permissions of the file or directory at ``path``.
+pub fn attributes(path: &str) -> Result<Attributes, FsError> {
+    let input = Buffer::from_slice(&path_buffer(path)?);
+    let mut output = Buffer::new(ATTRIBUTES_SIZE);
+    device()?.ioctl(IOCTL_GET_ATTRIBUTES, &input, &mut output)?;
+    let mut attributes = [0; ATTRIBUTES_SIZE];
+    attributes.copy_from_slice(&output);
+    Ok(Attributes::decode(&attributes))
+}
+
+/// Change the owner and permissions of the file or directory at ``path``.
+pub fn set_attributes(path: &str, attributes: &Attributes) -> Result<(), FsError> {
+    let input = Buffer::from_slice(&attributes.encode(path)?);
+    device()?.ioctl(IOCTL_SET_ATTRIBUTES, &input, &mut [])?;
+    Ok(())
+}
+
+/// Get the space taken by the directory at ``path``.
+pub fn usage(path: &str) -> Result<Usage, FsError> {
+    let path = Buffer::from_slice(&path_buffer(path)?);
+    let mut blocks = Buffer::new(4);
+    let mut inodes = Buffer::new(4);
+    device()?.ioctlv(IOCTL_GET_USAGE, &[&path], &mut [&mut blocks, &mut inodes])?;
+    Ok(Usage {
+        blocks: u32_at(&blocks, 0),
+        inodes: u32_at(&inodes, 0),
+    })
+}
+
+/// Get the space taken and left on the NAND.
+pub fn stats() -> Result<Stats, FsError> {
+    let mut output = Buffer::new(28);
+    device()?.ioctl(IOCTL_GET_STATS, &[], &mut output)?;
+    Ok(Stats {
+        cluster_size: u32_at(&output, 0),
+        free_clusters: u32_at(&output, 1),
+        used_clusters: u32_at(&output, 2),
+        bad_clusters: u32_at(&output, 3),
+        reserved_clusters: u32_at(&output, 4),
+        free_inodes: u32_at(&output, 5),
+        used_inodes: u32_at(&output, 6),
+    })
+}
+
+/// Read the whole file at ``path``.
+pub fn read(path: &str) -> Result<Vec<u8>, FsError> {
+    let file = File::open(path, Mode::Read)?;
+    let mut data = Buffer::new(file.len()? as usize);
+    // ISFS reads everything asked for at once, up to the end of the file.
+    let read = file.handle.read(&mut data)?;
+    Ok(data[..read].to_vec())
+}
+
+/// A file on the NAND, closed when dropped.
+///
+/// ISFS only reads into and writes from buffers aligned to a cache line, any other buffer goes
+/// through a copy.
+pub struct File {
+    handle: Handle,
+    bounce: Option<Buffer>,
+}
+
+impl File {
+    /// Open the existing file at ``path``.
+    pub fn open(path: &str, mode: Mode) -> Result<File, FsError> {
+        if path.len() >= MAX_PATH {
+            return Err(FsError::NameTooLong);
+        }
+        Ok(File {
+            handle: Handle::open(path, mode)?,
+            bounce: None,
+        })
+    }
+
+    /// Create an empty file at ``path`` and open it for reading and writing, replacing any
+    /// existing one since ISFS can’t truncate files.
+    pub fn create(path: &str) -> Result<File, FsError> {
+        match create_file(path, Permissions::default()) {
+            Err(FsError::AlreadyExists) => {
+                delete(path)?;
+                create_file(path, Permissions::default())?;
+            }
+            result => result?,
+        }
+        File::open(path, Mode::ReadWrite)
+    }
+
+    fn file_stats(&self) -> Result<(u32, u32), FsError> {
+        let mut output = Buffer::new(8);
+        self.handle.ioctl(IOCTL_GET_FILE_STATS, &[], &mut output)?;
+        Ok((u32_at(&output, 0), u32_at(&output, 1)))
+    }
+
+    /// Get the size of the file.
+    pub fn len(&self) -> Result<u32, FsError> {
+        Ok(self.file_stats()?.0)
+    }
+
+    /// Whether the file is empty.
+    pub fn is_empty(&self) -> Result<bool, FsError> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Get the current position in the file.
+    pub fn position(&self) -> Result<u32, FsError> {
+        Ok(self.file_stats()?.1)
+    }
+}
+
+impl ErrorType for File {
+    type Error = FsError;
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FsError> {
+        if ios::is_aligned(buf) {
+            return Ok(self.handle.read(buf)?);
+        }
+        let length = buf.len().min(BOUNCE_SIZE);
+        let bounce = self.bounce.get_or_insert_with(|| Buffer::new(BOUNCE_SIZE));
+        let read = self.handle.read(&mut bounce[..length])?;
+        buf[..read].copy_from_slice(&bounce[..read]);
+        Ok(read)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FsError> {
+        if ios::is_aligned(buf) {
+            return Ok(self.handle.write(buf)?);
+        }
+        let length = buf.len().min(BOUNCE_SIZE);
+        let bounce = &mut self.bounce.get_or_insert_with(|| Buffer::new(BOUNCE_SIZE))[..length];
+        bounce.copy_from_slice(&buf[..length]);
+        Ok(self.handle.write(bounce)?)
+    }
+
+    /// ISFS writes through, there is nothing to flush.
+    fn flush(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, FsError> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (
+                i32::try_from(offset).map_err(|_| FsError::Invalid)?,
+                Whence::Start,
+            ),
+            SeekFrom::Current(offset) => (
+                i32::try_from(offset).map_err(|_| FsError::Invalid)?,
+                Whence::Current,
+            ),
+            SeekFrom::End(offset) => (
+                i32::try_from(offset).map_err(|_| FsError::Invalid)?,
+                Whence::End,
+            ),
+        };
+        Ok(self.handle.seek(offset, whence)? as u64)
+    }
+}
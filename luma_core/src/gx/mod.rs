@@ -0,0 +1,244 @@
+//! ``gx`` module of ``luma_core``.
+//!
+//! Contains functions for the Graphics eXecutor, or GX, which is driven through the command FIFO.
+
+use crate::allocate::DmaBuffer;
+use crate::heap::Arena;
+use crate::io::{read16, write16, write32};
+use crate::mem::PhysAddr;
+use crate::{mfspr, mtspr};
+use core::arch::asm;
+use core::sync::atomic::{AtomicU32, Ordering};
+use luma_formats::gx::{Fifo, GenMode};
+
+pub mod pe;
+pub mod tev;
+pub mod texture;
+pub mod tpl;
+
+pub use luma_formats::gx::encode;
+pub use pe::*;
+pub use tev::*;
+pub use texture::*;
+
+/// The write-gather pipe, every write to it gets appended to the CPU FIFO.
+const WGPIPE: PhysAddr = PhysAddr::new(0x0c00_8000);
+
+/// Base of the Command Processor registers.
+const CP_BASE: PhysAddr = PhysAddr::new(0x0c00_0000);
+
+/// Base of the Processor Interface registers.
+const PI_BASE: PhysAddr = PhysAddr::new(0x0c00_3000);
+
+/// Minimum size of the command FIFO, as enforced by the hardware.
+pub const FIFO_MINSIZE: usize = 64 * 1024;
+
+/// Shadow of the ``genMode`` BP register.
+static GEN_MODE: AtomicU32 = AtomicU32::new(GenMode::new().bits());
+
+/// The write-gather pipe, as a [`Fifo`] for the command encoders of ``luma_formats``.
+struct Wgpipe;
+
+impl Fifo for Wgpipe {
+    #[inline(always)]
+    fn write_u8(&mut self, value: u8) {
+        write_u8(value);
+    }
+
+    #[inline(always)]
+    fn write_u16(&mut self, value: u16) {
+        write_u16(value);
+    }
+
+    #[inline(always)]
+    fn write_u32(&mut self, value: u32) {
+        write_u32(value);
+    }
+}
+
+/// Append a byte to the command FIFO.
+#[inline(always)]
+pub fn write_u8(value: u8) {
+    unsafe { WGPIPE.uncached().as_mut_ptr::<u8>().write_volatile(value) }
+}
+
+/// Append a 16-bit value to the command FIFO.
+#[inline(always)]
+pub fn write_u16(value: u16) {
+    unsafe { WGPIPE.uncached().as_mut_ptr::<u16>().write_volatile(value) }
+}
+
+/// Append a 32-bit value to the command FIFO.
+#[inline(always)]
+pub fn write_u32(value: u32) {
+    unsafe { WGPIPE.uncached().as_mut_ptr::<u32>().write_volatile(value) }
+}
+
+/// Append a 32-bit floating value to the command FIFO.
+#[inline(always)]
+pub fn write_f32(value: f32) {
+    write_u32(value.to_bits());
+}
+
+/// Load a Blitting Processor register, ``value`` holds the register in its top 8 bits.
+#[inline(always)]
+pub fn load_bp_reg(value: u32) {
+    luma_formats::gx::load_bp_reg(&mut Wgpipe, value);
+}
+
+/// Load a Command Processor register.
+#[inline(always)]
+pub fn load_cp_reg(register: u8, value: u32) {
+    luma_formats::gx::load_cp_reg(&mut Wgpipe, register, value);
+}
+
+/// Load a single Transform Unit register.
+#[inline(always)]
+pub fn load_xf_reg(register: u16, value: u32) {
+    luma_formats::gx::load_xf_reg(&mut Wgpipe, register, value);
+}
+
+/// Load ``values.len()`` consecutive Transform Unit registers, starting at ``register``.
+pub fn load_xf_regs(register: u16, values: &[u32]) {
+    luma_formats::gx::load_xf_regs(&mut Wgpipe, register, values);
+}
+
+/// Push whatever is left in the write-gather pipe out to memory.
+///
+/// The pipe only bursts out once 32 bytes have been gathered, so pad it with NOPs.
+pub fn flush() {
+    for _ in 0..8 {
+        write_u32(0);
+    }
+    unsafe { asm!("sync", options(nostack)) };
+}
+
+/// Update the shadow of ``genMode`` with ``update``, and load it.
+fn update_gen_mode(update: impl FnOnce(&mut GenMode)) {
+    let mut gen_mode = GenMode::from_bits(GEN_MODE.load(Ordering::Relaxed));
+    update(&mut gen_mode);
+    GEN_MODE.store(gen_mode.bits(), Ordering::Relaxed);
+    load_bp_reg(gen_mode.bits());
+}
+
+/// Set the number of TEV stages used when rendering, between 1 and 16.
+pub fn set_num_tev_stages(count: u8) {
+    update_gen_mode(|gen_mode| {
+        gen_mode.with_num_tev_stages(count);
+    });
+}
+
+/// Set the number of texture coordinates generated for each vertex, up to 8.
+pub fn set_num_tex_gens(count: u8) {
+    update_gen_mode(|gen_mode| {
+        gen_mode.with_num_tex_gens(count);
+    });
+    load_xf_reg(0x103f, count as u32);
+}
+
+/// Set the number of colour channels output by the lighting stage, up to 2.
+pub fn set_num_chans(count: u8) {
+    update_gen_mode(|gen_mode| {
+        gen_mode.with_num_chans(count);
+    });
+    load_xf_reg(0x1009, count as u32);
+}
+
+/// An 8-bit per component RGBA colour.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Color {
+        Color { r, g, b, a }
+    }
+}
+
+/// A struct representing the command FIFO, shared between the CPU, which writes commands to it
+/// through the write-gather pipe, and the GP, which reads them back.
+pub struct Gx {
+    fifo: DmaBuffer<u8, Arena>,
+}
+
+impl Gx {
+    /// Allocate a command FIFO of ``size`` bytes, link it to both the CPU and the GP, and enable
+    /// the PE interrupts used by [`draw_done`] and [`set_draw_sync`].
+    ///
+    /// This must be called after [`crate::interrupt::init`].
+    ///
+    /// # Panics:
+    /// This function will panic if ``size`` is smaller than [`FIFO_MINSIZE`] or is not a
+    /// multiple of 32.
+    pub fn init(size: usize) -> Gx {
+        assert!(size >= FIFO_MINSIZE, "FIFO is too small");
+        assert!(
+            size.is_multiple_of(32),
+            "FIFO size must be a multiple of 32"
+        );
+
+        // The CPU writes the FIFO through the write-gather pipe, leave nothing of it in the cache.
+        let mut fifo = DmaBuffer::new_in(size, Arena::Mem1);
+        let base = fifo.give_to_device().value();
+        let end = base + size as u32 - 4;
+        let high_watermark = size as u32 - 16 * 1024;
+        let low_watermark = (size as u32 >> 1) & !0x1f;
+
+        unsafe {
+            // Stop the GP from reading while we move its FIFO around.
+            write16(CP_BASE + 0x02, 0);
+            // Clear the overflow and underflow interrupts.
+            write16(CP_BASE + 0x04, 0x3);
+
+            set_cp_fifo_reg(0x20, base);
+            set_cp_fifo_reg(0x24, end);
+            set_cp_fifo_reg(0x28, high_watermark);
+            set_cp_fifo_reg(0x2c, low_watermark);
+            set_cp_fifo_reg(0x30, 0);
+            set_cp_fifo_reg(0x34, base);
+            set_cp_fifo_reg(0x38, base);
+
+            // Point the CPU at the same FIFO.
+            write32(PI_BASE + 0x0c, base);
+            write32(PI_BASE + 0x10, end);
+            write32(PI_BASE + 0x14, base & 0x1fff_ffe0);
+        }
+
+        // Enable the write-gather pipe (HID2[WPE]), and make it target the FIFO (WPAR).
+        mtspr!(WGPIPE.value(), 921);
+        let hid2 = mfspr!(920);
+        mtspr!(hid2 | 0x4000_0000, 920);
+        unsafe { asm!("sync", options(nostack)) };
+
+        // Enable GP reads, in linked mode.
+        write16(CP_BASE + 0x02, 0x0011);
+
+        pe::init();
+
+        load_bp_reg(GEN_MODE.load(Ordering::Relaxed));
+        flush();
+
+        Gx { fifo }
+    }
+
+    /// Get the size of the command FIFO.
+    pub fn fifo_size(&self) -> usize {
+        self.fifo.len()
+    }
+
+    /// Whether the GP has consumed everything the CPU wrote to the FIFO so far.
+    pub fn is_idle(&self) -> bool {
+        // Bit 2 is ``GP read idle``, bit 3 is ``command idle``.
+        (read16(CP_BASE) & 0x000c) == 0x000c
+    }
+}
+
+/// Write a 32-bit FIFO register as its low and high 16-bit halves.
+unsafe fn set_cp_fifo_reg(offset: u32, value: u32) {
+    write16(CP_BASE + offset, value as u16);
+    write16(CP_BASE + offset + 2, (value >> 16) as u16);
+}
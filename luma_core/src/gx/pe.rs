@@ -0,0 +1,136 @@
+//! ``pe`` module of ``luma_core::gx``.
+//!
+//! Contains the synchronization between the CPU and the GPU, through the finish and token
+//! interrupts raised by the Pixel Engine, or PE.
+
+use super::{flush, load_bp_reg};
+use crate::interrupt::{self, Interrupt};
+use crate::io::{read16, write16};
+use crate::mem::PhysAddr;
+use crate::processor::ppc_nop;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering};
+
+/// Interrupt control register, bits 0 and 1 enable the token and finish interrupts, bits 2 and 3
+/// are their (write one to clear) status.
+const PE_INTERRUPT: PhysAddr = PhysAddr::new(0x0c00_100a);
+
+/// The last token the GPU went through.
+const PE_TOKEN: PhysAddr = PhysAddr::new(0x0c00_100e);
+
+/// Stored into [`LAST_TOKEN`] while a token is on its way, tokens being only 16-bit.
+const NO_TOKEN: u32 = u32::MAX;
+
+static FINISHED: AtomicBool = AtomicBool::new(true);
+static LAST_TOKEN: AtomicU32 = AtomicU32::new(NO_TOKEN);
+static FINISH_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static TOKEN_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Enable both PE interrupts, and route them to this module.
+pub(super) fn init() {
+    interrupt::set_handler(Interrupt::PeToken, Some(on_token));
+    interrupt::set_handler(Interrupt::PeFinish, Some(on_finish));
+    write16(PE_INTERRUPT, 0x000f);
+    interrupt::unmask(Interrupt::PeToken);
+    interrupt::unmask(Interrupt::PeFinish);
+}
+
+fn on_token(_: Interrupt) {
+    let token = read16(PE_TOKEN);
+    write16(PE_INTERRUPT, (read16(PE_INTERRUPT) & 0x3) | 0x4);
+    LAST_TOKEN.store(token as u32, Ordering::Release);
+
+    let callback = TOKEN_CALLBACK.load(Ordering::Acquire);
+    if !callback.is_null() {
+        let callback = unsafe { core::mem::transmute::<*mut (), fn(u16)>(callback) };
+        callback(token);
+    }
+}
+
+fn on_finish(_: Interrupt) {
+    write16(PE_INTERRUPT, (read16(PE_INTERRUPT) & 0x3) | 0x8);
+    FINISHED.store(true, Ordering::Release);
+
+    let callback = FINISH_CALLBACK.load(Ordering::Acquire);
+    if !callback.is_null() {
+        let callback = unsafe { core::mem::transmute::<*mut (), fn()>(callback) };
+        callback();
+    }
+}
+
+/// Insert a token in the command stream, the token interrupt fires once the GPU reaches it.
+pub fn set_draw_sync(token: u16) {
+    interrupt::free(|| {
+        // Handle the interrupt of a previous token first if it is still pending, since it may
+        // be the same token and would otherwise land after the reset below.
+        if read16(PE_INTERRUPT) & 0x4 != 0 {
+            on_token(Interrupt::PeToken);
+        }
+        // Forget the previous token, for wait_draw_sync() to only return once the GPU reaches
+        // this one.
+        LAST_TOKEN.store(NO_TOKEN, Ordering::Release);
+        load_bp_reg(0x4800_0000 | token as u32);
+        load_bp_reg(0x4700_0000 | token as u32);
+        flush();
+    })
+}
+
+/// Get the last token the GPU went through.
+pub fn read_draw_sync() -> u16 {
+    read16(PE_TOKEN)
+}
+
+/// Wait until the GPU has gone through ``token``, which must be the last one passed to
+/// [`set_draw_sync`].
+///
+/// This must not be called with external interrupts disabled.
+pub fn wait_draw_sync(token: u16) {
+    while LAST_TOKEN.load(Ordering::Acquire) != token as u32 {
+        ppc_nop();
+    }
+}
+
+/// Ask the GPU to raise the finish interrupt once every command so far has been executed.
+pub fn set_draw_done() {
+    FINISHED.store(false, Ordering::Release);
+    load_bp_reg(0x4500_0002);
+    flush();
+}
+
+/// Wait for the finish interrupt requested by [`set_draw_done`].
+///
+/// This must not be called with external interrupts disabled.
+pub fn wait_draw_done() {
+    while !FINISHED.load(Ordering::Acquire) {
+        ppc_nop();
+    }
+}
+
+/// Wait until the GPU has executed every command sent so far, after which its vertex buffers,
+/// textures and EFB copies can be safely reused.
+pub fn draw_done() {
+    set_draw_done();
+    wait_draw_done();
+}
+
+/// Set a callback called from the token interrupt with the token, returning the previous one.
+pub fn set_draw_sync_callback(callback: Option<fn(u16)>) -> Option<fn(u16)> {
+    let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+    let old = TOKEN_CALLBACK.swap(new, Ordering::AcqRel);
+    if old.is_null() {
+        None
+    } else {
+        Some(unsafe { core::mem::transmute::<*mut (), fn(u16)>(old) })
+    }
+}
+
+/// Set a callback called from the finish interrupt, returning the previous one.
+pub fn set_draw_done_callback(callback: Option<fn()>) -> Option<fn()> {
+    let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+    let old = FINISH_CALLBACK.swap(new, Ordering::AcqRel);
+    if old.is_null() {
+        None
+    } else {
+        Some(unsafe { core::mem::transmute::<*mut (), fn()>(old) })
+    }
+}
@@ -0,0 +1,66 @@
+//! ``tev`` module of ``luma_core::gx``.
+//!
+//! Contains the configuration of the TExture EnVironment, or TEV, the fixed-function combiner
+//! which computes the colour of each pixel from textures, rasterized colours and constants.
+
+use super::{Color, TexMap, load_bp_reg};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+pub use luma_formats::gx::tev::{
+    ChannelId, TevAlphaArg, TevAlphaEnv, TevBias, TevColorArg, TevColorEnv, TevMode, TevOp, TevReg,
+    TevScale, TevStage,
+};
+
+/// Shadows of the ``RAS1_TREF`` registers, each holding the order of two stages.
+static TEV_ORDERS: [AtomicU32; 8] = [const { AtomicU32::new(0) }; 8];
+
+/// Set the colour combiner of a TEV stage.
+pub fn set_tev_color_env(stage: TevStage, env: &TevColorEnv) {
+    load_bp_reg(env.bp_reg(stage));
+}
+
+/// Set the alpha combiner of a TEV stage.
+pub fn set_tev_alpha_env(stage: TevStage, env: &TevAlphaEnv) {
+    load_bp_reg(env.bp_reg(stage));
+}
+
+/// Select the texture coordinate, the texture map and the rasterized colour used by a TEV stage.
+///
+/// Passing ``None`` as the texture map disables texturing for that stage.
+pub fn set_tev_order(stage: TevStage, tex_coord: u8, tex_map: Option<TexMap>, channel: ChannelId) {
+    let pair = stage as usize / 2;
+    let order = TEV_ORDERS[pair].load(Ordering::Relaxed);
+    let value = luma_formats::gx::tev::tev_order_reg(order, stage, tex_coord, tex_map, channel);
+    TEV_ORDERS[pair].store(value & 0x00ff_ffff, Ordering::Relaxed);
+    load_bp_reg(value);
+}
+
+/// Configure both combiners of a TEV stage from one of the [`TevMode`] presets.
+pub fn set_tev_op(stage: TevStage, mode: TevMode) {
+    let (color_env, alpha_env) = mode.envs(stage);
+    set_tev_color_env(stage, &color_env);
+    set_tev_alpha_env(stage, &alpha_env);
+}
+
+/// Set the value of one of the TEV registers, which can then be used as a combiner input.
+pub fn set_tev_color(reg: TevReg, color: Color) {
+    set_tev_color_s10(
+        reg,
+        [
+            color.r as i16,
+            color.g as i16,
+            color.b as i16,
+            color.a as i16,
+        ],
+    );
+}
+
+/// Set the value of one of the TEV registers, using signed 11-bit components in RGBA order.
+pub fn set_tev_color_s10(reg: TevReg, color: [i16; 4]) {
+    let (ra, bg) = luma_formats::gx::tev::tev_color_regs(reg, color);
+    load_bp_reg(ra);
+    // The BG half has to be written three times for the value to stick.
+    load_bp_reg(bg);
+    load_bp_reg(bg);
+    load_bp_reg(bg);
+}
@@ -0,0 +1,221 @@
+//! ``texture`` module of ``luma_core::gx``.
+//!
+//! Contains texture objects, palettes (TLUTs) and the functions to load them into texture maps.
+
+use super::load_bp_reg;
+use crate::cache::DCFlushRange;
+use crate::mem::PhysAddr;
+use core::marker::PhantomData;
+
+pub use luma_formats::gx::texture::*;
+
+/// A texture object, describing an image in main memory and how it is to be sampled.
+///
+/// The image data has to stay alive for as long as the GPU may sample from it.
+pub struct TexObj<'a> {
+    mode0: u32,
+    mode1: u32,
+    image0: u32,
+    image3: u32,
+    format: TexFmt,
+    tlut: Option<(Tlut, TlutFmt)>,
+    _data: PhantomData<&'a [u8]>,
+}
+
+impl<'a> TexObj<'a> {
+    /// Describe a ``width`` × ``height`` texture stored in ``data`` in the given format, already
+    /// tiled as the GPU expects (see [`super::encode`]).  The data is flushed out of the d-cache.
+    ///
+    /// Indexed formats also need a palette, set with [`TexObj::with_tlut`].
+    ///
+    /// # Panics:
+    /// This function will panic if ``data`` isn’t aligned on 32 bytes, is too small for the
+    /// given format and size, or if the size is bigger than 1024 × 1024.
+    pub fn new(data: &'a [u8], width: u16, height: u16, format: TexFmt) -> TexObj<'a> {
+        assert!(
+            (data.as_ptr() as usize).is_multiple_of(32),
+            "Texture data must be 32-byte aligned"
+        );
+        assert!((1..=1024).contains(&width) && (1..=1024).contains(&height));
+        assert!(data.len() >= format.size(width as usize, height as usize));
+
+        unsafe { DCFlushRange(data.as_ptr() as *const u32, data.len() as u32) };
+
+        let mut mode0 = 0;
+        mode0 = bitfrob::u32_with_value(0, 1, mode0, WrapMode::Repeat as u32);
+        mode0 = bitfrob::u32_with_value(2, 3, mode0, WrapMode::Repeat as u32);
+        mode0 = bitfrob::u32_with_bit(4, mode0, true);
+        mode0 = bitfrob::u32_with_value(5, 7, mode0, TexFilter::Linear.to_hw());
+
+        let mut image0 = 0;
+        image0 = bitfrob::u32_with_value(0, 9, image0, width as u32 - 1);
+        image0 = bitfrob::u32_with_value(10, 19, image0, height as u32 - 1);
+        image0 = bitfrob::u32_with_value(20, 23, image0, format as u32);
+
+        TexObj {
+            mode0,
+            mode1: 0,
+            image0,
+            image3: PhysAddr::of(data.as_ptr()).value() >> 5,
+            format,
+            tlut: None,
+            _data: PhantomData,
+        }
+    }
+
+    /// Get the format of this texture.
+    pub fn format(&self) -> TexFmt {
+        self.format
+    }
+
+    /// Get the width of this texture.
+    pub fn width(&self) -> u16 {
+        bitfrob::u32_get_value(0, 9, self.image0) as u16 + 1
+    }
+
+    /// Get the height of this texture.
+    pub fn height(&self) -> u16 {
+        bitfrob::u32_get_value(10, 19, self.image0) as u16 + 1
+    }
+
+    /// Set how texture coordinates outside of [0, 1] get wrapped, on each axis.
+    pub fn with_wrap(&mut self, wrap_s: WrapMode, wrap_t: WrapMode) -> &mut Self {
+        self.mode0 = bitfrob::u32_with_value(0, 1, self.mode0, wrap_s as u32);
+        self.mode0 = bitfrob::u32_with_value(2, 3, self.mode0, wrap_t as u32);
+        self
+    }
+
+    /// Set the filters used when the texture gets minified or magnified.
+    ///
+    /// # Panics:
+    /// This function will panic if ``mag`` is a mipmap filter.
+    pub fn with_filter(&mut self, min: TexFilter, mag: TexFilter) -> &mut Self {
+        let mag = match mag {
+            TexFilter::Near => false,
+            TexFilter::Linear => true,
+            _ => panic!("Magnification can’t use mipmaps"),
+        };
+        self.mode0 = bitfrob::u32_with_bit(4, self.mode0, mag);
+        self.mode0 = bitfrob::u32_with_value(5, 7, self.mode0, min.to_hw());
+        self
+    }
+
+    /// Make this texture use ``levels`` mipmap levels, stored right after the base level.
+    pub fn with_mipmap(&mut self, levels: u8) -> &mut Self {
+        assert!((1..=11).contains(&levels));
+        self.with_lod(0.0, (levels - 1) as f32, 0.0)
+    }
+
+    /// Set the LOD range and bias, in mipmap levels.
+    pub fn with_lod(&mut self, min_lod: f32, max_lod: f32, bias: f32) -> &mut Self {
+        // min and max are u4.4 fixed point, bias is s2.5.
+        let min_lod = (min_lod.clamp(0.0, 10.0) * 16.0) as u32;
+        let max_lod = (max_lod.clamp(0.0, 10.0) * 16.0) as u32;
+        let bias = (bias.clamp(-4.0, 3.99) * 32.0) as i32 as u32 & 0xff;
+        self.mode1 = bitfrob::u32_with_value(0, 7, self.mode1, min_lod);
+        self.mode1 = bitfrob::u32_with_value(8, 15, self.mode1, max_lod);
+        self.mode0 = bitfrob::u32_with_value(9, 16, self.mode0, bias);
+        self
+    }
+
+    /// Select between edge LOD and diagonal LOD computation.
+    pub fn with_edge_lod(&mut self, edge_lod: bool) -> &mut Self {
+        self.mode0 = bitfrob::u32_with_bit(8, self.mode0, !edge_lod);
+        self
+    }
+
+    /// Set the palette used by an indexed texture.
+    ///
+    /// # Panics:
+    /// This function will panic if the texture format isn’t indexed.
+    pub fn with_tlut(&mut self, tlut: Tlut, format: TlutFmt) -> &mut Self {
+        assert!(self.format.is_indexed(), "Only indexed textures use a TLUT");
+        self.tlut = Some((tlut, format));
+        self
+    }
+}
+
+/// A palette in main memory, to be loaded into one of the TLUT slots of TMEM.
+///
+/// The entries have to stay alive until [`load_tlut`] has been processed by the GPU.
+pub struct TlutObj<'a> {
+    address: u32,
+    entries: u16,
+    format: TlutFmt,
+    _data: PhantomData<&'a [u8]>,
+}
+
+impl<'a> TlutObj<'a> {
+    /// Describe a palette made of 16-bit big-endian entries in the given format (see
+    /// [`super::encode::encode_tlut`]).  The entries are flushed out of the d-cache.
+    ///
+    /// # Panics:
+    /// This function will panic if ``data`` isn’t aligned on 32 bytes, or if it doesn’t hold a
+    /// multiple of 16 entries between 16 and 256.
+    pub fn new(data: &'a [u8], format: TlutFmt) -> TlutObj<'a> {
+        assert!(
+            (data.as_ptr() as usize).is_multiple_of(32),
+            "TLUT must be 32-byte aligned"
+        );
+        let entries = data.len() / 2;
+        assert!(
+            entries.is_multiple_of(16) && (16..=256).contains(&entries),
+            "TLUT must have a multiple of 16 entries, up to 256"
+        );
+
+        unsafe { DCFlushRange(data.as_ptr() as *const u32, data.len() as u32) };
+
+        TlutObj {
+            address: PhysAddr::of(data.as_ptr()).value(),
+            entries: entries as u16,
+            format,
+            _data: PhantomData,
+        }
+    }
+
+    /// Get the format of the entries of this palette.
+    pub fn format(&self) -> TlutFmt {
+        self.format
+    }
+
+    /// Get the amount of entries in this palette.
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+}
+
+/// Load a texture object into one of the texture maps.
+pub fn load_tex_obj(obj: &TexObj, map: TexMap) {
+    let (even, odd) = tmem_regions(map, obj.format);
+
+    load_bp_reg(map.bp_reg(0x80) | obj.mode0);
+    load_bp_reg(map.bp_reg(0x84) | obj.mode1);
+    load_bp_reg(map.bp_reg(0x88) | obj.image0);
+    load_bp_reg(map.bp_reg(0x8c) | tmem_region_reg(even));
+    load_bp_reg(map.bp_reg(0x90) | tmem_region_reg(odd));
+    load_bp_reg(map.bp_reg(0x94) | obj.image3);
+
+    if let Some((tlut, format)) = obj.tlut {
+        let mut value = 0;
+        value = bitfrob::u32_with_value(0, 9, value, tlut.tmem_offset());
+        value = bitfrob::u32_with_value(10, 11, value, format as u32);
+        load_bp_reg(map.bp_reg(0x98) | value);
+    }
+}
+
+/// Copy a palette from main memory into one of the TLUT slots of TMEM.
+pub fn load_tlut(obj: &TlutObj, tlut: Tlut) {
+    let mut count = 0;
+    count = bitfrob::u32_with_value(0, 9, count, tlut.tmem_offset());
+    count = bitfrob::u32_with_value(10, 20, count, obj.entries as u32 / 16);
+
+    load_bp_reg((0x64 << 24) | (obj.address >> 5));
+    load_bp_reg((0x65 << 24) | count);
+}
+
+/// Invalidate every texture cache region, this must be done whenever texture data in main memory
+/// gets modified after having been sampled.
+pub fn invalidate_tex_all() {
+    load_bp_reg(0x6600_1000);
+    load_bp_reg(0x6600_1100);
+}
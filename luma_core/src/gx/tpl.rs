@@ -0,0 +1,38 @@
+//! ``tpl`` module of ``luma_core::gx``.
+//!
+//! Contains the construction of texture and TLUT objects out of the images of a TPL file, as
+//! parsed by ``luma_formats``.
+
+use super::texture::{TexObj, TlutObj};
+
+pub use luma_formats::gx::tpl::*;
+
+impl<'a> TexObj<'a> {
+    /// Build a texture object sampling a TPL image with its authored settings.
+    ///
+    /// Indexed images still need their palette loaded into a TLUT slot, and set with
+    /// [`TexObj::with_tlut`].
+    ///
+    /// # Panics:
+    /// This function will panic if the image data isn’t aligned on 32 bytes, which happens when
+    /// the file itself isn’t loaded at an aligned address.
+    pub fn from_tpl(image: &TplImage<'a>) -> TexObj<'a> {
+        let mut obj = TexObj::new(image.data, image.width, image.height, image.format);
+        obj.with_wrap(image.wrap_s, image.wrap_t)
+            .with_filter(image.min_filter, image.mag_filter)
+            .with_lod(image.min_lod as f32, image.max_lod as f32, image.lod_bias)
+            .with_edge_lod(image.edge_lod);
+        obj
+    }
+}
+
+impl<'a> TlutObj<'a> {
+    /// Build a TLUT object from a TPL palette, ready to be loaded into a TLUT slot.
+    ///
+    /// # Panics:
+    /// This function will panic if the palette isn’t aligned on 32 bytes, or if it doesn’t hold
+    /// a multiple of 16 entries between 16 and 256.
+    pub fn from_tpl(palette: &TplPalette<'a>) -> TlutObj<'a> {
+        TlutObj::new(palette.data, palette.format)
+    }
+}
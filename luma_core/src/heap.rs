@@ -0,0 +1,162 @@
+//! ``heap`` module of ``luma_core``.
+//!
+//! Contains the heaps the program allocates from, one out of what the loader left of MEM1 and
+//! one out of what it left of MEM2.  The global allocator takes from MEM1 first and falls back to
+//! MEM2, and an [`Arena`] is an [`Allocator`] taking from only one of them, for memory which has
+//! to be in a given one: IOS only reads and writes MEM2, while some DMA only reaches MEM1.
+
+use crate::interrupt::Mutex;
+use crate::mem::{CachedAddr, PhysAddr};
+use core::alloc::{AllocError, Allocator, GlobalAlloc, Layout};
+use core::ops::Range;
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap as Free;
+
+/// End of the usable part of MEM1, as set by the loader.
+const MEM1_ARENA_HIGH: CachedAddr = CachedAddr::new(0x8000_0034);
+
+/// Start and end of the usable part of MEM2, as set by the loader.  IOS keeps what follows.
+const MEM2_ARENA_LOW: CachedAddr = CachedAddr::new(0x8000_3124);
+const MEM2_ARENA_HIGH: CachedAddr = CachedAddr::new(0x8000_3128);
+
+/// Bounds of each arena if the loader didn’t set them.
+const MEM1_END: u32 = 0x8180_0000;
+const MEM2_DEFAULT: Range<u32> = 0x9000_0800..0x933e_0000;
+const MEM2_END: u32 = 0x9400_0000;
+
+const CACHELINE: u32 = 32;
+
+static MEM1: Mutex<Free> = Mutex::new(Free::empty());
+static MEM2: Mutex<Free> = Mutex::new(Free::empty());
+
+/// One of the two memories of the Wii.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arena {
+    /// 24 MiB, where the program runs.
+    Mem1,
+    /// 64 MiB, of which IOS keeps the end.
+    Mem2,
+}
+
+impl Arena {
+    /// Get the arena ``pointer`` was allocated from.
+    fn of(pointer: *const u8) -> Arena {
+        if PhysAddr::of(pointer).is_mem2() {
+            Arena::Mem2
+        } else {
+            Arena::Mem1
+        }
+    }
+
+    fn heap(self) -> &'static Mutex<Free> {
+        match self {
+            Arena::Mem1 => &MEM1,
+            Arena::Mem2 => &MEM2,
+        }
+    }
+}
+
+/// Read the arena bound stored at ``global``, falling back to ``default`` if the loader left
+/// nothing sensible there.
+fn read_bound(global: CachedAddr, valid: Range<u32>, default: u32) -> u32 {
+    let bound = unsafe { global.as_ptr::<u32>().read_volatile() };
+    if valid.contains(&bound) {
+        bound
+    } else {
+        default
+    }
+}
+
+/// Give the heaps what the loader left of MEM1 and MEM2, MEM1 being used from ``mem1_start``,
+/// where the program and its stack end.
+///
+/// # Safety
+/// This must be called once, before anything gets allocated, and nothing else may use the
+/// arenas afterwards.
+pub unsafe fn init(mem1_start: *mut u8) {
+    let mem1_low = (mem1_start as u32).next_multiple_of(CACHELINE);
+    let mem1_high = read_bound(MEM1_ARENA_HIGH, mem1_low + 1..MEM1_END + 1, MEM1_END);
+
+    let mem2_low = read_bound(
+        MEM2_ARENA_LOW,
+        MEM2_DEFAULT.start..MEM2_END,
+        MEM2_DEFAULT.start,
+    );
+    let mem2_low = mem2_low.next_multiple_of(CACHELINE);
+    let mem2_high = read_bound(
+        MEM2_ARENA_HIGH,
+        mem2_low + 1..MEM2_END + 1,
+        MEM2_DEFAULT.end,
+    );
+
+    for (arena, low, high) in [
+        (Arena::Mem1, mem1_low, mem1_high),
+        (Arena::Mem2, mem2_low, mem2_high),
+    ] {
+        arena.heap().lock(|heap| unsafe {
+            heap.init(CachedAddr::new(low).as_mut_ptr(), (high - low) as usize)
+        });
+    }
+}
+
+/// Allocate memory for ``layout`` out of ``arena``, if there is enough left.
+pub fn allocate(arena: Arena, layout: Layout) -> Option<NonNull<u8>> {
+    arena
+        .heap()
+        .lock(|heap| heap.allocate_first_fit(layout).ok())
+}
+
+/// Give back memory allocated by [`allocate`], from whichever arena it came.
+///
+/// # Safety
+/// ``pointer`` must have been allocated by this module with ``layout``, and not been deallocated
+/// since.
+pub unsafe fn deallocate(pointer: NonNull<u8>, layout: Layout) {
+    Arena::of(pointer.as_ptr())
+        .heap()
+        .lock(|heap| unsafe { heap.deallocate(pointer, layout) })
+}
+
+/// Get how many bytes are left in ``arena``, which may not all be contiguous.
+pub fn available(arena: Arena) -> usize {
+    arena.heap().lock(|heap| heap.free())
+}
+
+/// Allocate ``size`` bytes aligned to a cache line out of ``arena``, to be kept for good.
+pub(crate) fn take(arena: Arena, size: usize) -> *mut u8 {
+    let layout = Layout::from_size_align(size, CACHELINE as usize).unwrap();
+    match allocate(arena, layout) {
+        Some(pointer) => pointer.as_ptr(),
+        None => alloc::alloc::handle_alloc_error(layout),
+    }
+}
+
+unsafe impl Allocator for Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let pointer = allocate(*self, layout).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(pointer, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, pointer: NonNull<u8>, layout: Layout) {
+        unsafe { deallocate(pointer, layout) }
+    }
+}
+
+/// The global allocator, taking from MEM1 and then from MEM2 once MEM1 is full.
+///
+/// It has to be installed by the runtime, after calling [`init`].
+pub struct Heap;
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        allocate(Arena::Mem1, layout)
+            .or_else(|| allocate(Arena::Mem2, layout))
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        if let Some(pointer) = NonNull::new(pointer) {
+            unsafe { deallocate(pointer, layout) }
+        }
+    }
+}
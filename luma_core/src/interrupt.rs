@@ -0,0 +1,171 @@
+//! ``interrupt`` module of ``luma_core``.
+//!
+//! Contains the dispatching of the external interrupts raised by the Processor Interface, or PI,
+//! on behalf of every other piece of hardware.
+
+use crate::exception::{self, Exception, ExceptionFrame};
+use crate::io::{read32, write32};
+use crate::mem::PhysAddr;
+use crate::register::{mfmsr, mtmsr};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// Interrupt cause register, a bit is set for each pending interrupt.
+const PI_INTSR: PhysAddr = PhysAddr::new(0x0c00_3000);
+
+/// Interrupt mask register, only the interrupts set here get delivered to the processor.
+const PI_INTMR: PhysAddr = PhysAddr::new(0x0c00_3004);
+
+/// External interrupt enable bit of the MSR.
+const MSR_EE: u32 = 0x8000;
+
+/// The sources of external interrupts, as seen by the PI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Interrupt {
+    GpError = 0,
+    ResetSwitch = 1,
+    Di = 2,
+    Si = 3,
+    Exi = 4,
+    Ai = 5,
+    Dsp = 6,
+    Mem = 7,
+    Vi = 8,
+    PeToken = 9,
+    PeFinish = 10,
+    Cp = 11,
+    Debug = 12,
+    Hsp = 13,
+    Hollywood = 14,
+}
+
+impl Interrupt {
+    const ALL: [Interrupt; 15] = [
+        Interrupt::GpError,
+        Interrupt::ResetSwitch,
+        Interrupt::Di,
+        Interrupt::Si,
+        Interrupt::Exi,
+        Interrupt::Ai,
+        Interrupt::Dsp,
+        Interrupt::Mem,
+        Interrupt::Vi,
+        Interrupt::PeToken,
+        Interrupt::PeFinish,
+        Interrupt::Cp,
+        Interrupt::Debug,
+        Interrupt::Hsp,
+        Interrupt::Hollywood,
+    ];
+
+    const fn mask(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Handler called with external interrupts disabled whenever the interrupt is pending.  It is
+/// responsible for acknowledging the interrupt on its device.
+pub type InterruptHandler = fn(Interrupt);
+
+static HANDLERS: [AtomicPtr<()>; 15] = [const { AtomicPtr::new(ptr::null_mut()) }; 15];
+
+/// Mask every interrupt source, route the external interrupt exception to the PI dispatcher, and
+/// enable external interrupts on the processor.
+///
+/// This must be called after [`exception::init`].
+pub fn init() {
+    write32(PI_INTMR, 0);
+    write32(PI_INTSR, 0xffff_ffff);
+    exception::set_handler(Exception::External, Some(dispatch));
+    mtmsr(mfmsr() | MSR_EE);
+}
+
+/// Set the handler of an interrupt, returning the previous one.
+///
+/// The interrupt still has to be unmasked with [`unmask`] to be delivered.
+pub fn set_handler(
+    interrupt: Interrupt,
+    handler: Option<InterruptHandler>,
+) -> Option<InterruptHandler> {
+    let new = handler.map_or(ptr::null_mut(), |handler| handler as *mut ());
+    let old = HANDLERS[interrupt as usize].swap(new, Ordering::AcqRel);
+    if old.is_null() {
+        None
+    } else {
+        Some(unsafe { core::mem::transmute::<*mut (), InterruptHandler>(old) })
+    }
+}
+
+/// Let an interrupt reach the processor.
+pub fn unmask(interrupt: Interrupt) {
+    free(|| write32(PI_INTMR, read32(PI_INTMR) | interrupt.mask()));
+}
+
+/// Prevent an interrupt from reaching the processor.
+pub fn mask(interrupt: Interrupt) {
+    free(|| write32(PI_INTMR, read32(PI_INTMR) & !interrupt.mask()));
+}
+
+/// Run ``f`` with external interrupts disabled, restoring them afterwards if they were enabled.
+pub fn free<R>(f: impl FnOnce() -> R) -> R {
+    let msr = mfmsr();
+    mtmsr(msr & !MSR_EE);
+    let result = f();
+    if msr & MSR_EE != 0 {
+        mtmsr(mfmsr() | MSR_EE);
+    }
+    result
+}
+
+/// A value shared between interrupt handlers and the rest of the program, only ever accessed
+/// with external interrupts disabled.
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Run ``f`` on the value, with external interrupts disabled.
+    ///
+    /// # Panics:
+    /// This function will panic if called again from within ``f``.
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        free(|| {
+            assert!(
+                !self.locked.swap(true, Ordering::Acquire),
+                "Mutex locked recursively"
+            );
+            let result = f(unsafe { &mut *self.value.get() });
+            self.locked.store(false, Ordering::Release);
+            result
+        })
+    }
+}
+
+fn dispatch(_: Exception, _: &mut ExceptionFrame) {
+    let pending = read32(PI_INTSR) & read32(PI_INTMR);
+    for interrupt in Interrupt::ALL {
+        if pending & interrupt.mask() == 0 {
+            continue;
+        }
+        let handler = HANDLERS[interrupt as usize].load(Ordering::Acquire);
+        if handler.is_null() {
+            // Nobody is going to acknowledge it, mask it instead of looping forever.
+            write32(PI_INTMR, read32(PI_INTMR) & !interrupt.mask());
+        } else {
+            let handler = unsafe { core::mem::transmute::<*mut (), InterruptHandler>(handler) };
+            handler(interrupt);
+        }
+    }
+}
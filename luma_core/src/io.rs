@@ -2,11 +2,12 @@
 //!
 //! Contains functions for basic I/O.
 
+use crate::mem::PhysAddr;
 use core::arch::asm;
 
-/// Read a 32-bit value from an address.
+/// Read a 32-bit value from a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn read32(address: u32) -> u32 {
+pub fn read32(address: PhysAddr) -> u32 {
     // Define an output variable.
     let mut register;
 
@@ -14,7 +15,7 @@ pub fn read32(address: u32) -> u32 {
     unsafe {
         asm!("lwz {0},0({1}) ; sync",
             lateout(reg) register, 
-            in(reg) (0xc000_0000 | address),
+            in(reg) address.uncached().value(),
             options(nostack));
     }
 
@@ -22,20 +23,20 @@ pub fn read32(address: u32) -> u32 {
     register
 }
 
-/// Write a 32-bit value to an address.
+/// Write a 32-bit value to a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn write32(address: u32, value: u32) {
+pub fn write32(address: PhysAddr, value: u32) {
     // Run the assembly instruction.
     unsafe {
         asm!("stw {0},0({1}) ; eieio", 
-            in(reg) value, in(reg) (0xc000_0000 | address), 
+            in(reg) value, in(reg) address.uncached().value(), 
             options(nostack));
     }
 }
 
-/// Read a 16-bit value from an address.
+/// Read a 16-bit value from a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn read16(address: u32) -> u16 {
+pub fn read16(address: PhysAddr) -> u16 {
     // Define an output variable.
     let mut register;
 
@@ -43,7 +44,7 @@ pub fn read16(address: u32) -> u16 {
     unsafe {
         asm!("lhz {0},0({1}) ; sync", 
             lateout(reg) register,
-            in(reg) (0xc000_0000 | address), 
+            in(reg) address.uncached().value(), 
             options(nostack));
     }
 
@@ -51,20 +52,20 @@ pub fn read16(address: u32) -> u16 {
     register
 }
 
-/// Write a 16-bit value to an address.
+/// Write a 16-bit value to a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn write16(address: u32, value: u16) {
+pub fn write16(address: PhysAddr, value: u16) {
     // Run the assembly instruction.
     unsafe {
         asm!("sth {0},0({1}) ; eieio",
-            in(reg) value, in(reg) (0xc000_0000 | address), 
+            in(reg) value, in(reg) address.uncached().value(), 
             options(nostack));
     }
 }
 
-/// Read a 8-bit value from an address.
+/// Read a 8-bit value from a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn read8(address: u32) -> u8 {
+pub fn read8(address: PhysAddr) -> u8 {
     // Define an output variable.
     let mut register;
 
@@ -72,7 +73,7 @@ pub fn read8(address: u32) -> u8 {
     unsafe {
         asm!("lbz {0},0({1}) ; sync",
             lateout(reg) register,
-            in(reg) (0xc000_0000 | address),
+            in(reg) address.uncached().value(),
             options(nostack));
     }
 
@@ -80,24 +81,24 @@ pub fn read8(address: u32) -> u8 {
     register
 }
 
-/// Write a 8-bit value to an address.
+/// Write a 8-bit value to a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn write8(address: u32, value: u8) {
+pub fn write8(address: PhysAddr, value: u8) {
     // Run the assembly instruction.
     unsafe {
         asm!("stb {0},0({1}) ; eieio",
-            in(reg) value, in(reg) (0xc000_0000 | address),
+            in(reg) value, in(reg) address.uncached().value(),
             options(nostack));
     }
 }
 
-/// Write a 32-bit floating value to an address.
+/// Write a 32-bit floating value to a physical address, through its uncached mapping.
 #[inline(always)]
-pub fn writef32(address: u32, value: f32) {
+pub fn writef32(address: PhysAddr, value: f32) {
     // Run the assembly instruction.
     unsafe {
         asm!("stfs {0},0({1}) ; eieio",
-            in(freg) value, in(reg) (0xc000_0000 | address),
+            in(freg) value, in(reg) address.uncached().value(),
             options(nostack));
     }
 }
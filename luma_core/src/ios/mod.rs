@@ -0,0 +1,693 @@
+//! ``ios`` module of ``luma_core``.
+//!
+//! Contains a client for IOS, the operating system running on the Starlet coprocessor.  IOS owns
+//! most of the hardware of the Wii and exposes it as devices, which are opened by path and then
+//! talked to with requests sent over IPC.
+//!
+//! Requests are kept in a small pool carved out of MEM2, and their replies are collected either
+//! from the IPC interrupt or by whoever is waiting on them.
+
+use crate::allocate::DmaBuffer;
+use crate::cache::{DCFlushRange, DCInvalidateRange};
+use crate::es;
+use crate::heap::{self, Arena};
+use crate::interrupt::{self, Interrupt};
+use crate::io::{read32, write32};
+use crate::ipc::{IpcMessageAddress, PpcIpcControl};
+use crate::mem::{PhysAddr, UncachedAddr};
+use crate::stm;
+use core::mem::{ManuallyDrop, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU8, Ordering};
+
+pub(crate) use luma_formats::ios::Aligned;
+pub use luma_formats::ios::{IosError, is_aligned};
+
+/// Hollywood interrupts pending for the PowerPC, cleared by writing them back.
+const HW_PPCIRQFLAG: PhysAddr = PhysAddr::new(0x0d00_0030);
+
+/// Hollywood interrupts delivered to the PowerPC, through the PI.
+const HW_PPCIRQMASK: PhysAddr = PhysAddr::new(0x0d00_0034);
+
+const IRQ_IPC: u32 = 1 << 30;
+
+/// Number and revision of the running IOS, written by IOS once started.  Read uncached, as it
+/// changes behind the back of the PowerPC on reload.
+const IOS_VERSION: *mut u32 = UncachedAddr::new(0xc000_3140).as_mut_ptr();
+
+/// Upper half of the title ID of every IOS.
+const IOS_TITLE: u64 = 0x0000_0001_0000_0000;
+
+/// Amount of requests which can be in flight at the same time.
+const SLOTS: usize = 16;
+
+/// Room left in a request for its path or its ioctlv vectors.
+const PAYLOAD_SIZE: usize = 224;
+
+/// Longest path accepted by [`Handle::open`], including its terminating nul.
+pub const MAX_PATH: usize = 64;
+
+/// Most buffers an ioctlv can take.
+pub const MAX_VECTORS: usize = PAYLOAD_SIZE / 8;
+
+const COMMAND_OPEN: u32 = 1;
+const COMMAND_CLOSE: u32 = 2;
+const COMMAND_READ: u32 = 3;
+const COMMAND_WRITE: u32 = 4;
+const COMMAND_SEEK: u32 = 5;
+const COMMAND_IOCTL: u32 = 6;
+const COMMAND_IOCTLV: u32 = 7;
+
+const STATE_FREE: u8 = 0;
+const STATE_PENDING: u8 = 1;
+const STATE_DONE: u8 = 2;
+/// Still pending, but nobody is going to look at the result.
+const STATE_ORPHANED: u8 = 3;
+
+/// A request as IOS sees it, followed by the path or the vectors it points to.
+#[repr(C, align(32))]
+struct Slot {
+    command: u32,
+    result: i32,
+    fd: i32,
+    args: [u32; 5],
+    payload: [u8; PAYLOAD_SIZE],
+}
+
+static POOL: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+static STATES: [AtomicU8; SLOTS] = [const { AtomicU8::new(STATE_FREE) }; SLOTS];
+static CALLBACKS: [AtomicPtr<()>; SLOTS] = [const { AtomicPtr::new(ptr::null_mut()) }; SLOTS];
+
+/// Callback called from the IPC interrupt once an asynchronous request completed.
+pub type Callback = fn(Result<i32, IosError>);
+
+/// Take the request pool out of MEM2, and route the IPC interrupt to this module.
+///
+/// This gets called by the first request, after [`crate::interrupt::init`] has been called by the
+/// runtime.
+pub fn init() {
+    if !POOL.load(Ordering::Acquire).is_null() {
+        return;
+    }
+
+    let pool = heap::take(Arena::Mem2, SLOTS * size_of::<Slot>());
+    POOL.store(pool as *mut Slot, Ordering::Release);
+
+    // Acknowledge anything left over by the loader, and get interrupted on replies.
+    let mut control = PpcIpcControl::new();
+    control
+        .with_acknowledge(true)
+        .with_reply(true)
+        .with_reply_interrupt(true);
+    control.write();
+    write32(HW_PPCIRQFLAG, IRQ_IPC);
+    interrupt::set_handler(Interrupt::Hollywood, Some(on_interrupt));
+    interrupt::free(|| write32(HW_PPCIRQMASK, read32(HW_PPCIRQMASK) | IRQ_IPC));
+    interrupt::unmask(Interrupt::Hollywood);
+}
+
+/// Get the number of the running IOS, such as 58.
+pub fn version() -> u16 {
+    (unsafe { IOS_VERSION.read_volatile() } >> 16) as u16
+}
+
+/// Get the revision of the running IOS.
+pub fn revision() -> u16 {
+    unsafe { IOS_VERSION.read_volatile() as u16 }
+}
+
+/// Replace the running IOS with IOS ``number``, which has to be installed, and wait for it to be
+/// ready to take requests.
+///
+/// Every handle opened before becomes invalid, and requests still in flight fail, so anything
+/// talking to IOS should be dropped first.  The STM event hook gets released, and has to be set
+/// up again with [`stm::init`].
+///
+/// This only returns an error if the running IOS refused to go away, in which case it keeps
+/// running.
+pub fn reload(number: u16) -> Result<(), IosError> {
+    let title = IOS_TITLE | number as u64;
+    // Any of the tickets will do, the first one gets used.
+    let view = es::ticket_views(title)?
+        .into_iter()
+        .next()
+        .ok_or(IosError::NotFound)?;
+    let view = Aligned(view.0);
+    let title_id = Aligned(title.to_be_bytes());
+    // Closing it would go to the next IOS, which never opened it.
+    let es = ManuallyDrop::new(Handle::open(es::DEVICE, Mode::None)?);
+
+    // IOS doesn’t launch anything while someone is waiting for the buttons.
+    stm::release_event_hook()?;
+    unsafe { IOS_VERSION.write_volatile(0) };
+    let pending = unsafe {
+        es.ioctlv_async::<[u8]>(es::IOCTL_LAUNCH, &[&title_id.0, &view.0], &mut [], None)
+    }?;
+
+    // The request only gets a reply if it got refused, the running IOS goes away otherwise and
+    // the next one writes its version once started.
+    while version() == 0 {
+        interrupt::free(process_reply);
+        if let Some(Err(error)) = pending.poll() {
+            return Err(error);
+        }
+    }
+    // It then acknowledges once ready to take requests.
+    while !PpcIpcControl::read().acknowledge() {}
+    interrupt::free(|| {
+        let mut acknowledge = PpcIpcControl::new();
+        acknowledge
+            .with_acknowledge(true)
+            .with_reply(true)
+            .with_reply_interrupt(true);
+        acknowledge.write();
+        write32(HW_PPCIRQFLAG, IRQ_IPC);
+        abandon_requests();
+    });
+    drop(pending);
+    Ok(())
+}
+
+/// Fail every request still in flight, which the previous IOS took away with it.
+fn abandon_requests() {
+    for (index, state) in STATES.iter().enumerate() {
+        CALLBACKS[index].store(ptr::null_mut(), Ordering::Release);
+        match state.load(Ordering::Acquire) {
+            STATE_PENDING => {
+                unsafe { (*slot(index)).result = IosError::NotFound.code() };
+                state.store(STATE_DONE, Ordering::Release);
+            }
+            STATE_ORPHANED => state.store(STATE_FREE, Ordering::Release),
+            _ => (),
+        }
+    }
+}
+
+fn on_interrupt(_: Interrupt) {
+    process_reply();
+}
+
+fn slot(index: usize) -> *mut Slot {
+    unsafe { POOL.load(Ordering::Acquire).add(index) }
+}
+
+/// Make the current content of ``data`` visible to IOS.
+fn flush(data: &[u8]) {
+    if !data.is_empty() {
+        unsafe { DCFlushRange(data.as_ptr() as *const u32, data.len() as u32) };
+    }
+}
+
+/// Drop the cached content of ``data``, so that what IOS wrote gets read.
+pub(crate) fn invalidate(data: &[u8]) {
+    if !data.is_empty() {
+        unsafe { DCInvalidateRange(data.as_ptr() as *const u32, data.len() as u32) };
+    }
+}
+
+fn buffer_address(data: &[u8]) -> u32 {
+    if data.is_empty() {
+        0
+    } else {
+        PhysAddr::of(data.as_ptr()).value()
+    }
+}
+
+/// Collect the reply IOS posted, if any.  This must be called with external interrupts disabled.
+fn process_reply() {
+    let control = PpcIpcControl::read();
+    if !control.reply() {
+        return;
+    }
+    let address = IpcMessageAddress::read_arm().address();
+
+    let mut acknowledge = PpcIpcControl::new();
+    acknowledge.with_reply(true).with_reply_interrupt(true);
+    acknowledge.write();
+    write32(HW_PPCIRQFLAG, IRQ_IPC);
+
+    let pool = PhysAddr::of(POOL.load(Ordering::Acquire)).value();
+    let offset = address.wrapping_sub(pool) as usize;
+    if offset < SLOTS * size_of::<Slot>() && offset.is_multiple_of(size_of::<Slot>()) {
+        complete(offset / size_of::<Slot>());
+    }
+
+    // Let IOS post the next reply.
+    let mut relaunch = PpcIpcControl::new();
+    relaunch.with_relaunch(true).with_reply_interrupt(true);
+    relaunch.write();
+}
+
+fn complete(index: usize) {
+    let slot = slot(index);
+    unsafe { DCInvalidateRange(slot as *const u32, size_of::<Slot>() as u32) };
+    let result = unsafe { (*slot).result };
+
+    let callback = CALLBACKS[index].swap(ptr::null_mut(), Ordering::AcqRel);
+    if !callback.is_null() {
+        let callback = unsafe { core::mem::transmute::<*mut (), Callback>(callback) };
+        callback(IosError::check(result));
+    }
+
+    let state = STATES[index].swap(STATE_DONE, Ordering::AcqRel);
+    if state == STATE_ORPHANED {
+        STATES[index].store(STATE_FREE, Ordering::Release);
+    }
+}
+
+/// A request being filled, before it gets sent.
+struct Request {
+    index: usize,
+}
+
+impl Request {
+    fn new(command: u32, fd: i32) -> Result<Request, IosError> {
+        init();
+        let index = (0..SLOTS)
+            .find(|&index| {
+                STATES[index]
+                    .compare_exchange(
+                        STATE_FREE,
+                        STATE_PENDING,
+                        Ordering::AcqRel,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            })
+            .ok_or(IosError::OutOfMemory)?;
+        let slot = slot(index);
+        unsafe {
+            (*slot).command = command;
+            (*slot).result = 0;
+            (*slot).fd = fd;
+            (*slot).args = [0; 5];
+        }
+        Ok(Request { index })
+    }
+
+    fn slot(&mut self) -> &mut Slot {
+        unsafe { &mut *slot(self.index) }
+    }
+
+    fn send(self, callback: Option<Callback>) -> Pending {
+        let index = self.index;
+        let slot = slot(index);
+        CALLBACKS[index].store(
+            callback.map_or(ptr::null_mut(), |callback| callback as *mut ()),
+            Ordering::Release,
+        );
+        unsafe { DCFlushRange(slot as *const u32, size_of::<Slot>() as u32) };
+
+        interrupt::free(|| {
+            let mut message = IpcMessageAddress::new();
+            message.with_address(PhysAddr::of(slot));
+            message.write_ppc();
+
+            let mut control = PpcIpcControl::new();
+            control.with_execute(true).with_reply_interrupt(true);
+            control.write();
+
+            // IOS acknowledges every request before working on it, replies can still come in
+            // meanwhile.
+            while !PpcIpcControl::read().acknowledge() {
+                process_reply();
+            }
+            let mut acknowledge = PpcIpcControl::new();
+            acknowledge
+                .with_acknowledge(true)
+                .with_reply_interrupt(true);
+            acknowledge.write();
+        });
+
+        Pending { index }
+    }
+}
+
+/// A request sent to IOS, which may not have completed yet.
+///
+/// Dropping it before completion doesn’t cancel the request, it only stops caring about its
+/// result.
+pub struct Pending {
+    index: usize,
+}
+
+impl Pending {
+    /// Get the result of the request if it completed.
+    pub fn poll(&self) -> Option<Result<i32, IosError>> {
+        if STATES[self.index].load(Ordering::Acquire) != STATE_DONE {
+            return None;
+        }
+        Some(IosError::check(unsafe { (*slot(self.index)).result }))
+    }
+
+    /// Whether the request completed.
+    pub fn is_done(&self) -> bool {
+        STATES[self.index].load(Ordering::Acquire) == STATE_DONE
+    }
+
+    /// Wait for the request to complete, and get its result.
+    pub fn wait(self) -> Result<i32, IosError> {
+        loop {
+            if let Some(result) = self.poll() {
+                return result;
+            }
+            // Don’t rely on the interrupt, external interrupts may well be disabled here.
+            interrupt::free(process_reply);
+        }
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let state = &STATES[self.index];
+        if state
+            .compare_exchange(
+                STATE_PENDING,
+                STATE_ORPHANED,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            state.store(STATE_FREE, Ordering::Release);
+        }
+    }
+}
+
+/// The mode a resource gets opened with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Mode {
+    None = 0,
+    Read = 1,
+    Write = 2,
+    ReadWrite = 3,
+}
+
+/// Where [`Handle::seek`] starts from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Whence {
+    Start = 0,
+    Current = 1,
+    End = 2,
+}
+
+/// A zeroed buffer on the MEM2 heap, aligned to a cache line and padded to a whole one, so that
+/// IOS can read and write it without anything else sharing its cache lines.
+pub struct Buffer {
+    data: DmaBuffer<u8, Arena>,
+}
+
+impl Buffer {
+    /// Allocate a buffer of ``length`` bytes.
+    pub fn new(length: usize) -> Buffer {
+        Buffer {
+            data: DmaBuffer::new_in(length, Arena::Mem2),
+        }
+    }
+
+    /// Allocate a buffer holding a copy of ``data``.
+    pub fn from_slice(data: &[u8]) -> Buffer {
+        let mut buffer = Buffer::new(data.len());
+        buffer.copy_from_slice(data);
+        buffer
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+/// Somewhere IOS writes the result of a request to.
+///
+/// A [`Buffer`] gets handed over to IOS while the request runs, and taken back once it completed,
+/// while the cache of a plain slice gets flushed before and invalidated after.
+pub trait Output {
+    /// Hand this over to IOS, and get the physical address and the length it will write at.
+    fn give_to_device(&mut self) -> (u32, u32);
+
+    /// Take this back from IOS once it wrote to it.
+    fn take_from_device(&mut self);
+}
+
+impl Output for [u8] {
+    fn give_to_device(&mut self) -> (u32, u32) {
+        flush(self);
+        (buffer_address(self), self.len() as u32)
+    }
+
+    fn take_from_device(&mut self) {
+        invalidate(self);
+    }
+}
+
+impl<const N: usize> Output for [u8; N] {
+    fn give_to_device(&mut self) -> (u32, u32) {
+        self.as_mut_slice().give_to_device()
+    }
+
+    fn take_from_device(&mut self) {
+        self.as_mut_slice().take_from_device();
+    }
+}
+
+impl Output for Buffer {
+    fn give_to_device(&mut self) -> (u32, u32) {
+        if self.data.is_empty() {
+            (0, 0)
+        } else {
+            (self.data.give_to_device().value(), self.data.len() as u32)
+        }
+    }
+
+    fn take_from_device(&mut self) {
+        self.data.take_from_device();
+    }
+}
+
+/// Whether IOS can DMA into ``data`` directly, which takes it to be aligned and in MEM2.
+pub(crate) fn is_dma_safe(data: &[u8]) -> bool {
+    is_aligned(data) && PhysAddr::of(data.as_ptr()).is_mem2()
+}
+
+/// An open IOS resource, closed when dropped.
+///
+/// Output buffers get their cache lines invalidated once IOS wrote them, so they should be aligned
+/// to a cache line and padded to a whole one, see [`Buffer`].
+#[derive(Debug)]
+pub struct Handle {
+    fd: i32,
+}
+
+impl Handle {
+    /// Open the resource at ``path``.
+    ///
+    /// # Panics:
+    /// This function will panic if ``path`` is longer than [`MAX_PATH`] minus one.
+    pub fn open(path: &str, mode: Mode) -> Result<Handle, IosError> {
+        assert!(path.len() < MAX_PATH, "IOS path too long: {path}");
+        let mut request = Request::new(COMMAND_OPEN, 0)?;
+        let slot = request.slot();
+        slot.payload[..path.len()].copy_from_slice(path.as_bytes());
+        slot.payload[path.len()] = 0;
+        slot.args[0] = PhysAddr::of(slot.payload.as_ptr()).value();
+        slot.args[1] = mode as u32;
+        let fd = request.send(None).wait()?;
+        Ok(Handle { fd })
+    }
+
+    /// Wrap a file descriptor returned by IOS some other way, which will get closed on drop.
+    ///
+    /// # Safety
+    /// Nothing else must be closing this descriptor.
+    pub unsafe fn from_raw(fd: i32) -> Handle {
+        Handle { fd }
+    }
+
+    /// Get the file descriptor IOS gave to this resource.
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    /// Close the resource, and get the error IOS returned if any.
+    pub fn close(self) -> Result<(), IosError> {
+        let fd = self.fd;
+        core::mem::forget(self);
+        Request::new(COMMAND_CLOSE, fd)?.send(None).wait()?;
+        Ok(())
+    }
+
+    /// Read from the resource into ``buffer``, returning how many bytes got read.
+    pub fn read<O: Output + ?Sized>(&self, buffer: &mut O) -> Result<usize, IosError> {
+        let result = unsafe { self.read_async(buffer, None) }?.wait();
+        buffer.take_from_device();
+        Ok(result? as usize)
+    }
+
+    /// Write ``buffer`` to the resource, returning how many bytes got written.
+    pub fn write(&self, buffer: &[u8]) -> Result<usize, IosError> {
+        let pending = unsafe { self.write_async(buffer, None) }?;
+        Ok(pending.wait()? as usize)
+    }
+
+    /// Move the position of the resource, returning the new one.
+    pub fn seek(&self, offset: i32, whence: Whence) -> Result<u32, IosError> {
+        let mut request = Request::new(COMMAND_SEEK, self.fd)?;
+        let slot = request.slot();
+        slot.args[0] = offset as u32;
+        slot.args[1] = whence as u32;
+        Ok(request.send(None).wait()? as u32)
+    }
+
+    /// Send ``ioctl`` with an input and an output buffer.
+    pub fn ioctl<O: Output + ?Sized>(
+        &self,
+        ioctl: u32,
+        input: &[u8],
+        output: &mut O,
+    ) -> Result<i32, IosError> {
+        let result = unsafe { self.ioctl_async(ioctl, input, output, None) }?.wait();
+        output.take_from_device();
+        result
+    }
+
+    /// Send ``ioctl`` with several input buffers followed by several output ones.
+    ///
+    /// # Panics:
+    /// This function will panic if there are more than [`MAX_VECTORS`] buffers.
+    pub fn ioctlv<O: Output + ?Sized>(
+        &self,
+        ioctl: u32,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut O],
+    ) -> Result<i32, IosError> {
+        let result = unsafe { self.ioctlv_async(ioctl, inputs, outputs, None) }?.wait();
+        for output in outputs.iter_mut() {
+            output.take_from_device();
+        }
+        result
+    }
+
+    /// Start reading into ``buffer``, calling ``callback`` from the IPC interrupt once done.
+    ///
+    /// # Safety
+    /// ``buffer`` must stay valid until the request completed, whatever happens to the returned
+    /// [`Pending`], and it must be taken back with [`Output::take_from_device`] before reading
+    /// it.
+    pub unsafe fn read_async<O: Output + ?Sized>(
+        &self,
+        buffer: &mut O,
+        callback: Option<Callback>,
+    ) -> Result<Pending, IosError> {
+        let mut request = Request::new(COMMAND_READ, self.fd)?;
+        let slot = request.slot();
+        let (address, length) = buffer.give_to_device();
+        slot.args[0] = address;
+        slot.args[1] = length;
+        Ok(request.send(callback))
+    }
+
+    /// Start writing ``buffer``, calling ``callback`` from the IPC interrupt once done.
+    ///
+    /// # Safety
+    /// ``buffer`` must stay valid and unchanged until the request completed, whatever happens to
+    /// the returned [`Pending`].
+    pub unsafe fn write_async(
+        &self,
+        buffer: &[u8],
+        callback: Option<Callback>,
+    ) -> Result<Pending, IosError> {
+        flush(buffer);
+        let mut request = Request::new(COMMAND_WRITE, self.fd)?;
+        let slot = request.slot();
+        slot.args[0] = buffer_address(buffer);
+        slot.args[1] = buffer.len() as u32;
+        Ok(request.send(callback))
+    }
+
+    /// Start sending ``ioctl``, calling ``callback`` from the IPC interrupt once done.
+    ///
+    /// # Safety
+    /// Both buffers must stay valid until the request completed, whatever happens to the returned
+    /// [`Pending`], and ``output`` must be taken back with [`Output::take_from_device`] before
+    /// reading it.
+    pub unsafe fn ioctl_async<O: Output + ?Sized>(
+        &self,
+        ioctl: u32,
+        input: &[u8],
+        output: &mut O,
+        callback: Option<Callback>,
+    ) -> Result<Pending, IosError> {
+        flush(input);
+        let mut request = Request::new(COMMAND_IOCTL, self.fd)?;
+        let slot = request.slot();
+        let (address, length) = output.give_to_device();
+        slot.args = [
+            ioctl,
+            buffer_address(input),
+            input.len() as u32,
+            address,
+            length,
+        ];
+        Ok(request.send(callback))
+    }
+
+    /// Start sending ``ioctl`` with several buffers, calling ``callback`` from the IPC interrupt
+    /// once done.
+    ///
+    /// # Safety
+    /// Every buffer must stay valid until the request completed, whatever happens to the returned
+    /// [`Pending`], and the outputs must be taken back with [`Output::take_from_device`] before
+    /// reading them.
+    ///
+    /// # Panics:
+    /// This function will panic if there are more than [`MAX_VECTORS`] buffers.
+    pub unsafe fn ioctlv_async<O: Output + ?Sized>(
+        &self,
+        ioctl: u32,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut O],
+        callback: Option<Callback>,
+    ) -> Result<Pending, IosError> {
+        assert!(
+            inputs.len() + outputs.len() <= MAX_VECTORS,
+            "Too many ioctlv buffers"
+        );
+        let mut request = Request::new(COMMAND_IOCTLV, self.fd)?;
+        let slot = request.slot();
+        let input_vectors = inputs.iter().map(|input| {
+            flush(input);
+            (buffer_address(input), input.len() as u32)
+        });
+        let output_vectors = outputs.iter_mut().map(|output| output.give_to_device());
+        for (i, (address, length)) in input_vectors.chain(output_vectors).enumerate() {
+            slot.payload[i * 8..i * 8 + 4].copy_from_slice(&address.to_be_bytes());
+            slot.payload[i * 8 + 4..i * 8 + 8].copy_from_slice(&length.to_be_bytes());
+        }
+        slot.args[0] = ioctl;
+        slot.args[1] = inputs.len() as u32;
+        slot.args[2] = outputs.len() as u32;
+        slot.args[3] = PhysAddr::of(slot.payload.as_ptr()).value();
+        Ok(request.send(callback))
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Ok(request) = Request::new(COMMAND_CLOSE, self.fd) {
+            let _ = request.send(None).wait();
+        }
+    }
+}
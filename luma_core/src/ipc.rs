@@ -1,5 +1,7 @@
+use crate::mem::PhysAddr;
+
 //bits 0..=31 = physical address of ipc request
-const HW_IPC_PPCMSG: *mut u32 = 0xCD00_0000 as *mut u32;
+const HW_IPC_PPCMSG: *mut u32 = PhysAddr::new(0x0d00_0000).uncached().as_mut_ptr();
 
 //bit 0 = X1 | Execute IPC request
 //bit 1 = Y2 | Acknowledge IPC request
@@ -8,10 +10,10 @@ const HW_IPC_PPCMSG: *mut u32 = 0xCD00_0000 as *mut u32;
 //bit 4 = IY1 | IPC request reply send out IPC interrupt
 //bit 5 = IY2 | IPC request acknowledge sends out IPC interrupt
 
-const HW_IPC_PPCCTRL: *mut u32 = 0xCD00_0004 as *mut u32;
+const HW_IPC_PPCCTRL: *mut u32 = PhysAddr::new(0x0d00_0004).uncached().as_mut_ptr();
 
 //bits 0..=31 = physical address of ipc request
-const HW_IPC_ARMMSG: *mut u32 = 0xCD00_0008 as *mut u32;
+const HW_IPC_ARMMSG: *mut u32 = PhysAddr::new(0x0d00_0008).uncached().as_mut_ptr();
 
 //bit 0 = Y1 | IPC request reply available
 //bit 1 = X2 | Relauch IPC
@@ -19,7 +21,7 @@ const HW_IPC_ARMMSG: *mut u32 = 0xCD00_0008 as *mut u32;
 //bit 3 = Y2 | Acknowledge IPC request
 //bit 4 = IX1 | Execute ipc request send IPC interrupt
 //bit 5 = IX2 | Relaunch IPC sends IPC interrupt
-const HW_IPC_ARMCTRL: *mut u32 = 0xCD00_000C as *mut u32;
+const HW_IPC_ARMCTRL: *mut u32 = PhysAddr::new(0x0d00_000c).uncached().as_mut_ptr();
 
 /// IPC Message Address (for BOTH ARM AND PPC)
 #[repr(transparent)]
@@ -51,15 +53,11 @@ impl IpcMessageAddress {
     }
 
     /// # Panics:
-    /// This function will panic if `address` is not in the MEM2 physical address space
-    /// (0x1000_0000 - 0x13FF_FFFF)
-    pub fn with_address(&mut self, address: u32) -> &mut Self {
-        assert!(
-            (0x1000_0000..0x1400_0000).contains(&address),
-            "Address must be in physical space"
-        );
-
-        self.0 = bitfrob::u32_with_value(0, 31, self.0, address);
+    /// This function will panic if `address` is not in MEM2 (0x1000_0000 - 0x13FF_FFFF)
+    pub fn with_address(&mut self, address: PhysAddr) -> &mut Self {
+        assert!(address.is_mem2(), "Address must be in MEM2");
+
+        self.0 = bitfrob::u32_with_value(0, 31, self.0, address.value());
         self
     }
 }
@@ -82,56 +80,56 @@ impl PpcIpcControl {
     }
 
     pub fn execute(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 0)
+        bitfrob::u32_get_bit(0, self.0)
     }
 
     pub fn with_execute(&mut self, execute: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 0, execute);
+        self.0 = bitfrob::u32_with_bit(0, self.0, execute);
         self
     }
 
     pub fn acknowledge(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 1)
+        bitfrob::u32_get_bit(1, self.0)
     }
 
     pub fn with_acknowledge(&mut self, acknowledge: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 1, acknowledge);
+        self.0 = bitfrob::u32_with_bit(1, self.0, acknowledge);
         self
     }
 
     pub fn reply(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 2)
+        bitfrob::u32_get_bit(2, self.0)
     }
 
     pub fn with_reply(&mut self, reply: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 2, reply);
+        self.0 = bitfrob::u32_with_bit(2, self.0, reply);
         self
     }
 
     pub fn relaunch(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 3)
+        bitfrob::u32_get_bit(3, self.0)
     }
 
     pub fn with_relaunch(&mut self, relaunch: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 3, relaunch);
+        self.0 = bitfrob::u32_with_bit(3, self.0, relaunch);
         self
     }
 
     pub fn reply_interrupt(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 4)
+        bitfrob::u32_get_bit(4, self.0)
     }
 
     pub fn with_reply_interrupt(&mut self, reply_interrupt: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 4, reply_interrupt);
+        self.0 = bitfrob::u32_with_bit(4, self.0, reply_interrupt);
         self
     }
 
     pub fn acknowledge_interrupt(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 5)
+        bitfrob::u32_get_bit(5, self.0)
     }
 
     pub fn with_acknowledge_interrupt(&mut self, acknowledge_interrupt: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 5, acknowledge_interrupt);
+        self.0 = bitfrob::u32_with_bit(5, self.0, acknowledge_interrupt);
         self
     }
 }
@@ -154,56 +152,56 @@ impl ArmIpcControl {
     }
 
     pub fn execute(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 2)
+        bitfrob::u32_get_bit(2, self.0)
     }
 
     pub fn with_execute(&mut self, execute: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 2, execute);
+        self.0 = bitfrob::u32_with_bit(2, self.0, execute);
         self
     }
 
     pub fn acknowledge(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 3)
+        bitfrob::u32_get_bit(3, self.0)
     }
 
     pub fn with_acknowledge(&mut self, acknowledge: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 3, acknowledge);
+        self.0 = bitfrob::u32_with_bit(3, self.0, acknowledge);
         self
     }
 
     pub fn reply(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 0)
+        bitfrob::u32_get_bit(0, self.0)
     }
 
     pub fn with_reply(&mut self, reply: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 0, reply);
+        self.0 = bitfrob::u32_with_bit(0, self.0, reply);
         self
     }
 
     pub fn relaunch(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 1)
+        bitfrob::u32_get_bit(1, self.0)
     }
 
     pub fn with_relaunch(&mut self, relaunch: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 1, relaunch);
+        self.0 = bitfrob::u32_with_bit(1, self.0, relaunch);
         self
     }
 
     pub fn execute_interrupt(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 4)
+        bitfrob::u32_get_bit(4, self.0)
     }
 
     pub fn with_execute_interrupt(&mut self, execute_interrupt: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 4, execute_interrupt);
+        self.0 = bitfrob::u32_with_bit(4, self.0, execute_interrupt);
         self
     }
 
     pub fn relaunch_interrupt(&self) -> bool {
-        bitfrob::u32_get_bit(self.0, 5)
+        bitfrob::u32_get_bit(5, self.0)
     }
 
     pub fn with_relaunch_interrupt(&mut self, relaunch_interrupt: bool) -> &mut Self {
-        self.0 = bitfrob::u32_with_bit(self.0, 5, relaunch_interrupt);
+        self.0 = bitfrob::u32_with_bit(5, self.0, relaunch_interrupt);
         self
     }
 }
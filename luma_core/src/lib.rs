@@ -12,6 +12,8 @@ extern crate alloc;
 use alloc::string::ToString;
 use core::arch::asm;
 use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
 
 // Broadway Processor Utilities
 pub mod processor;
@@ -28,10 +30,22 @@ pub mod loadstore;
 // Broadway I/O Utilities
 pub mod io;
 
+// Memory Map
+pub mod mem;
+
 // Broadway Cache Subsystem
 pub mod cache;
 
-// Helper functions to allocate aligned memory on the heap
+// Broadway Exception Subsystem
+pub mod exception;
+
+// External Interrupt Subsystem
+pub mod interrupt;
+
+// MEM1 and MEM2 Heaps
+pub mod heap;
+
+// DMA buffers and helper functions to allocate aligned memory on the heap
 pub mod allocate;
 
 // VI Subsystem
@@ -40,6 +54,70 @@ pub mod vi;
 //IPC Subsystem
 pub mod ipc;
 
+// IOS Subsystem
+pub mod ios;
+
+// Virtual Filesystem
+pub mod fs;
+
+// Block Device Abstraction
+pub mod block;
+
+// SD Card Subsystem
+pub mod sdio;
+
+// FAT Filesystem
+pub mod fat;
+
+// USB Subsystem
+pub mod usb;
+
+// DVD Interface
+pub mod di;
+
+// Bundled Read-Only Filesystem
+pub mod romfs;
+
+// System Configuration
+pub mod sysconf;
+
+// STM Subsystem
+pub mod stm;
+
+// ES Subsystem
+pub mod es;
+
+// Network Subsystem
+pub mod net;
+
+// Development Server
+#[cfg(feature = "dev-server")]
+pub mod devserver;
+
+// Power and Reset Utilities
+pub mod system;
+
+// GX Subsystem
+pub mod gx;
+
+// Audio Subsystem
+pub mod audio;
+
+// DSP Subsystem
+pub mod dsp;
+
+// SI Subsystem
+pub mod si;
+
+// GameCube Controller Input
+pub mod pad;
+
+// Bluetooth Subsystem
+pub mod bluetooth;
+
+// Wii Remote Input
+pub mod wpad;
+
 /// Do nothing, this is for Dolphin’s use until we get actual USB Gecko support.
 ///
 /// This function must exist and its symbol must be kept in order to get HLE debugging in Dolphin.
@@ -52,7 +130,18 @@ extern "C" fn __write_console(_unused: u32, message: *const u8, size: *const u32
     unsafe { asm!("/* {0} {1} */", in(reg) message, in(reg) size) };
 }
 
-/// Implements Write using Dolphin’s HLE.
+/// A function called with everything [`println!`] prints, see [`set_log_hook`].
+pub type LogHook = fn(&str);
+
+static LOG_HOOK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Also send everything [`println!`] prints to ``hook``, or stop if ``None``.
+pub fn set_log_hook(hook: Option<LogHook>) {
+    let hook = hook.map_or(ptr::null_mut(), |hook| hook as *mut ());
+    LOG_HOOK.store(hook, Ordering::Release);
+}
+
+/// Implements Write using Dolphin’s HLE, along with the hook set by [`set_log_hook`].
 pub struct DolphinHle;
 
 impl fmt::Write for DolphinHle {
@@ -60,6 +149,11 @@ impl fmt::Write for DolphinHle {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         let len = s.len() as u32;
         __write_console(0, s.as_ptr(), &len as *const u32);
+        let hook = LOG_HOOK.load(Ordering::Acquire);
+        if !hook.is_null() {
+            let hook = unsafe { core::mem::transmute::<*mut (), LogHook>(hook) };
+            hook(s);
+        }
         Ok(())
     }
 
@@ -0,0 +1,211 @@
+//! ``mem`` module of ``luma_core``.
+//!
+//! Contains the memory map of the Wii, and the three ways the processor addresses the same
+//! memory: physical addresses, as seen by the hardware and IOS, and cached or uncached virtual
+//! ones, which the BATs map onto the first 512 MiB of physical memory.
+//!
+//! | Physical                      | Cached        | Uncached      |                      |
+//! |-------------------------------|---------------|---------------|----------------------|
+//! | ``0x0000_0000..0x0180_0000``  | ``0x8000_0000`` | ``0xc000_0000`` | MEM1, 24 MiB       |
+//! | ``0x0c00_0000..0x0e00_0000``  |               | ``0xcc00_0000`` | Hardware registers |
+//! | ``0x1000_0000..0x1400_0000``  | ``0x9000_0000`` | ``0xd000_0000`` | MEM2, 64 MiB       |
+//!
+//! The constructors are ``const`` and check their argument, so addresses built in a ``const``
+//! are checked at compile time.
+
+use core::fmt;
+use core::ops::Add;
+
+/// Where MEM1 starts, and its size.
+pub const MEM1_START: PhysAddr = PhysAddr::new(0x0000_0000);
+pub const MEM1_SIZE: u32 = 0x0180_0000;
+
+/// Where MEM2 starts, and its size.
+pub const MEM2_START: PhysAddr = PhysAddr::new(0x1000_0000);
+pub const MEM2_SIZE: u32 = 0x0400_0000;
+
+/// How much physical memory the BATs map, which every address has to be in.
+const MAPPED_SIZE: u32 = 0x2000_0000;
+
+const CACHED_BASE: u32 = 0x8000_0000;
+const UNCACHED_BASE: u32 = 0xc000_0000;
+
+/// A physical address, as given to the hardware and to IOS.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct PhysAddr(u32);
+
+impl PhysAddr {
+    /// # Panics:
+    /// This function will panic if ``address`` isn’t in the first 512 MiB, which the processor
+    /// can’t reach.
+    pub const fn new(address: u32) -> PhysAddr {
+        assert!(address < MAPPED_SIZE, "Address must be in physical space");
+        PhysAddr(address)
+    }
+
+    /// Get the physical address of what ``pointer`` points to, which may be cached or
+    /// uncached.
+    ///
+    /// # Panics:
+    /// This function will panic if ``pointer`` isn’t a cached nor an uncached address.
+    pub fn of<T: ?Sized>(pointer: *const T) -> PhysAddr {
+        let address = pointer as *const u8 as usize as u32;
+        match address {
+            CACHED_BASE.. if address < CACHED_BASE + MAPPED_SIZE => CachedAddr(address).physical(),
+            UNCACHED_BASE.. if address < UNCACHED_BASE + MAPPED_SIZE => {
+                UncachedAddr(address).physical()
+            }
+            _ => panic!("Pointer must be cached or uncached"),
+        }
+    }
+
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    pub const fn is_mem1(self) -> bool {
+        self.0 < MEM1_START.0 + MEM1_SIZE
+    }
+
+    pub const fn is_mem2(self) -> bool {
+        self.0 >= MEM2_START.0 && self.0 < MEM2_START.0 + MEM2_SIZE
+    }
+
+    pub const fn cached(self) -> CachedAddr {
+        CachedAddr(self.0 | CACHED_BASE)
+    }
+
+    pub const fn uncached(self) -> UncachedAddr {
+        UncachedAddr(self.0 | UNCACHED_BASE)
+    }
+}
+
+impl Add<u32> for PhysAddr {
+    type Output = PhysAddr;
+
+    fn add(self, offset: u32) -> PhysAddr {
+        PhysAddr::new(self.0 + offset)
+    }
+}
+
+impl fmt::Debug for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PhysAddr({:#010x})", self.0)
+    }
+}
+
+/// A virtual address going through the caches, at which code runs and data usually lives.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct CachedAddr(u32);
+
+impl CachedAddr {
+    /// # Panics:
+    /// This function will panic if ``address`` isn’t a cached address.
+    pub const fn new(address: u32) -> CachedAddr {
+        assert!(
+            address >= CACHED_BASE && address < CACHED_BASE + MAPPED_SIZE,
+            "Address must be cached"
+        );
+        CachedAddr(address)
+    }
+
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    pub const fn physical(self) -> PhysAddr {
+        PhysAddr(self.0 - CACHED_BASE)
+    }
+
+    pub const fn uncached(self) -> UncachedAddr {
+        self.physical().uncached()
+    }
+
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as usize as *const T
+    }
+
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as usize as *mut T
+    }
+}
+
+impl Add<u32> for CachedAddr {
+    type Output = CachedAddr;
+
+    fn add(self, offset: u32) -> CachedAddr {
+        CachedAddr::new(self.0 + offset)
+    }
+}
+
+impl fmt::Debug for CachedAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CachedAddr({:#010x})", self.0)
+    }
+}
+
+/// A virtual address bypassing the caches, at which hardware registers are accessed and data
+/// shared with the hardware can be without flushing.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct UncachedAddr(u32);
+
+impl UncachedAddr {
+    /// # Panics:
+    /// This function will panic if ``address`` isn’t an uncached address.
+    pub const fn new(address: u32) -> UncachedAddr {
+        assert!(
+            address >= UNCACHED_BASE && address < UNCACHED_BASE + MAPPED_SIZE,
+            "Address must be uncached"
+        );
+        UncachedAddr(address)
+    }
+
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    pub const fn physical(self) -> PhysAddr {
+        PhysAddr(self.0 - UNCACHED_BASE)
+    }
+
+    pub const fn cached(self) -> CachedAddr {
+        self.physical().cached()
+    }
+
+    pub const fn as_ptr<T>(self) -> *const T {
+        self.0 as usize as *const T
+    }
+
+    pub const fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as usize as *mut T
+    }
+}
+
+impl Add<u32> for UncachedAddr {
+    type Output = UncachedAddr;
+
+    fn add(self, offset: u32) -> UncachedAddr {
+        UncachedAddr::new(self.0 + offset)
+    }
+}
+
+impl fmt::Debug for UncachedAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "UncachedAddr({:#010x})", self.0)
+    }
+}
+
+impl From<CachedAddr> for PhysAddr {
+    fn from(address: CachedAddr) -> PhysAddr {
+        address.physical()
+    }
+}
+
+impl From<UncachedAddr> for PhysAddr {
+    fn from(address: UncachedAddr) -> PhysAddr {
+        address.physical()
+    }
+}
@@ -0,0 +1,184 @@
+//! ``net`` module of ``luma_core``.
+//!
+//! Contains the network stack of IOS, brought up by [`init`] and then used through
+//! [`TcpStream`], [`TcpListener`] and [`UdpSocket`], which work much like the ones of ``std``.
+//!
+//! Bringing it up first has ``/dev/net/kd/request`` let go of the network, which WiiConnect24
+//! would otherwise hold, then starts the sockets of ``/dev/net/ip/top`` and waits for the
+//! console to get an address, with the configuration of the System Menu as read through
+//! ``/dev/net/ncd/manage``, see [`ncd`].
+//!
+//! The requests of the sockets are marshalled in [`socket`], over the [`Device`] trait rather
+//! than directly over a [`Handle`], so that they can be checked against a mock of IOS.
+
+use crate::ios::{Aligned, Buffer, Handle, IosError, Mode};
+use crate::mem::PhysAddr;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::ManuallyDrop;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::sync::atomic::{AtomicI32, Ordering};
+
+pub mod ncd;
+mod tcp;
+mod udp;
+
+pub use luma_formats::net::{Device, NetError, socket};
+pub use socket::PollFd;
+pub use tcp::{TcpListener, TcpStream};
+pub use udp::UdpSocket;
+
+const KD_REQUEST: &str = "/dev/net/kd/request";
+const IP_TOP: &str = "/dev/net/ip/top";
+
+const IOCTL_KD_STARTUP: u32 = 0x06;
+
+/// Returned by [`IOCTL_KD_STARTUP`] while WiiConnect24 is still busy.
+const KD_BUSY: i32 = -29;
+const KD_RETRIES: u32 = 10;
+
+/// How many times the address is asked for before giving up, which takes a few seconds.
+const ADDRESS_RETRIES: u32 = 1_000_000;
+
+static TOP_FD: AtomicI32 = AtomicI32::new(-1);
+
+impl Device for Handle {
+    type Buffer = Buffer;
+
+    fn ioctl(&self, ioctl: u32, input: &[u8], output: &mut [u8]) -> Result<i32, IosError> {
+        Handle::ioctl(self, ioctl, input, output)
+    }
+
+    fn ioctlv(
+        &self,
+        ioctl: u32,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<i32, IosError> {
+        Handle::ioctlv(self, ioctl, inputs, outputs)
+    }
+
+    fn buffer(&self, length: usize) -> Buffer {
+        Buffer::new(length)
+    }
+
+    fn address(&self, data: &[u8]) -> u32 {
+        PhysAddr::of(data.as_ptr()).value()
+    }
+}
+
+/// Get the handle of ``/dev/net/ip/top``, opened by [`init`] and kept until [`deinit`].
+pub(crate) fn top() -> Result<ManuallyDrop<Handle>, NetError> {
+    let fd = TOP_FD.load(Ordering::Acquire);
+    if fd < 0 {
+        return Err(NetError::NotInitialized);
+    }
+    Ok(ManuallyDrop::new(unsafe { Handle::from_raw(fd) }))
+}
+
+/// Have WiiConnect24 let go of the network, so that the sockets can get started.
+fn kd_startup() -> Result<(), NetError> {
+    let kd = Handle::open(KD_REQUEST, Mode::None).map_err(NetError::Ios)?;
+    let mut output = Aligned([0; 32]);
+    for _ in 0..KD_RETRIES {
+        kd.ioctl(IOCTL_KD_STARTUP, &[], &mut output.0)
+            .map_err(NetError::Ios)?;
+        // The result is in the output, the request itself always succeeds.
+        match i32::from_be_bytes(output.0[..4].try_into().unwrap()) {
+            KD_BUSY => continue,
+            0 => return Ok(()),
+            code => return Err(NetError::Socket(-code)),
+        }
+    }
+    Err(NetError::Ios(IosError::Busy))
+}
+
+/// Bring the network up, unless this got done already, returning the address of the console.
+///
+/// This waits for the console to get an address, failing with [`NetError::NotConnected`]
+/// after a few seconds without one.
+pub fn init() -> Result<Ipv4Addr, NetError> {
+    if TOP_FD.load(Ordering::Acquire) < 0 {
+        kd_startup()?;
+        let top = Handle::open(IP_TOP, Mode::None).map_err(NetError::Ios)?;
+        socket::startup(&top)?;
+        TOP_FD.store(top.fd(), Ordering::Release);
+        core::mem::forget(top);
+    }
+    for _ in 0..ADDRESS_RETRIES {
+        let address = local_ip();
+        if address != Err(NetError::NotConnected) {
+            return address;
+        }
+    }
+    Err(NetError::NotConnected)
+}
+
+/// Close ``/dev/net/ip/top`` until [`init`] gets called again, which leaves the sockets still
+/// open unusable.
+pub fn deinit() {
+    let fd = TOP_FD.swap(-1, Ordering::AcqRel);
+    if fd >= 0 {
+        let _ = unsafe { Handle::from_raw(fd) }.close();
+    }
+}
+
+/// Get the address of the console, failing with [`NetError::NotConnected`] until it got one.
+pub fn local_ip() -> Result<Ipv4Addr, NetError> {
+    match socket::host_id(&*top()?)? {
+        Ipv4Addr::UNSPECIFIED => Err(NetError::NotConnected),
+        address => Ok(address),
+    }
+}
+
+/// Resolve ``host``, either a name or an IPv4 address, into the addresses it has, each with
+/// ``port`` as its port.
+pub fn resolve(host: &str, port: u16) -> Result<Vec<SocketAddrV4>, NetError> {
+    if let Ok(address) = host.parse::<Ipv4Addr>() {
+        return Ok(vec![SocketAddrV4::new(address, port)]);
+    }
+    socket::get_addr_info(&*top()?, host, port)
+}
+
+/// Wait up to ``timeout`` milliseconds, or forever if negative, for something to happen to
+/// any of ``sockets``, whose [`PollFd::revents`] get filled, returning how many got events.
+pub fn poll(sockets: &mut [PollFd], timeout: i64) -> Result<usize, NetError> {
+    socket::poll(&*top()?, sockets, timeout)
+}
+
+/// A socket of ``/dev/net/ip/top``, closed on drop.
+#[derive(Debug)]
+struct Socket {
+    fd: i32,
+}
+
+impl Socket {
+    fn new(kind: u32) -> Result<Socket, NetError> {
+        let fd = socket::socket(&*top()?, kind)?;
+        Ok(Socket { fd })
+    }
+
+    fn local_addr(&self) -> Result<SocketAddrV4, NetError> {
+        socket::local_address(&*top()?, self.fd)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        socket::set_nonblocking(&*top()?, self.fd, nonblocking)
+    }
+
+    fn send_to(&self, data: &[u8], to: Option<SocketAddrV4>) -> Result<usize, NetError> {
+        socket::send_to(&*top()?, self.fd, data, 0, to)
+    }
+
+    fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, Option<SocketAddrV4>), NetError> {
+        socket::recv_from(&*top()?, self.fd, buffer, 0)
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Ok(top) = top() {
+            let _ = socket::close(&*top, self.fd);
+        }
+    }
+}
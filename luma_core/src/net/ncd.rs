@@ -0,0 +1,95 @@
+//! ``ncd`` module of ``luma_core::net``.
+//!
+//! Contains a client for ``/dev/net/ncd/manage``, the network configuration daemon of IOS,
+//! which knows about the network interface and the connections set up in the System Menu.
+
+use super::NetError;
+use crate::ios::{Aligned, Buffer, Handle, Mode};
+use alloc::vec::Vec;
+
+const DEVICE: &str = "/dev/net/ncd/manage";
+
+const IOCTLV_READ_CONFIG: u32 = 0x05;
+const IOCTLV_GET_LINK_STATUS: u32 = 0x07;
+const IOCTLV_GET_MAC_ADDRESS: u32 = 0x08;
+
+/// Size of the network configuration, as stored in ``/shared2/sys/net/02/config.dat``.
+pub const CONFIG_SIZE: usize = 0x1b5c;
+
+/// The state of the network interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// The interface is still being brought up.
+    Busy,
+    /// No connection is set up.
+    None,
+    Wired,
+    /// The wireless connection is set up but not associated.
+    WirelessDown,
+    WirelessUp,
+    Other(u32),
+}
+
+impl Link {
+    /// Whether packets can go through.
+    pub fn is_up(&self) -> bool {
+        matches!(self, Link::Wired | Link::WirelessUp)
+    }
+}
+
+fn device() -> Result<Handle, NetError> {
+    Handle::open(DEVICE, Mode::None).map_err(NetError::Ios)
+}
+
+/// Check the status NCD writes at the start of its first output.
+fn check(status: &[u8]) -> Result<(), NetError> {
+    match i32::from_be_bytes(status[..4].try_into().unwrap()) {
+        0 => Ok(()),
+        code => Err(NetError::Socket(-code)),
+    }
+}
+
+/// Get the state of the network interface.
+pub fn link_status() -> Result<Link, NetError> {
+    let mut status = Aligned([0; 32]);
+    device()?
+        .ioctlv(IOCTLV_GET_LINK_STATUS, &[], &mut [&mut status.0])
+        .map_err(NetError::Ios)?;
+    check(&status.0)?;
+    let link = u32::from_be_bytes(status.0[4..8].try_into().unwrap());
+    Ok(match link {
+        1 => Link::Busy,
+        2 => Link::None,
+        3 => Link::Wired,
+        4 => Link::WirelessDown,
+        5 => Link::WirelessUp,
+        link => Link::Other(link),
+    })
+}
+
+/// Get the MAC address of the network interface.
+pub fn mac_address() -> Result<[u8; 6], NetError> {
+    let mut status = Aligned([0; 32]);
+    let mut address = Aligned([0; 32]);
+    device()?
+        .ioctlv(
+            IOCTLV_GET_MAC_ADDRESS,
+            &[],
+            &mut [&mut status.0[..], &mut address.0[..6]],
+        )
+        .map_err(NetError::Ios)?;
+    check(&status.0)?;
+    Ok(address.0[..6].try_into().unwrap())
+}
+
+/// Read the network configuration, holding the three connections set up in the System Menu,
+/// [`CONFIG_SIZE`] bytes long.
+pub fn read_config() -> Result<Vec<u8>, NetError> {
+    let mut config = Buffer::new(CONFIG_SIZE);
+    let mut status = Buffer::new(32);
+    device()?
+        .ioctlv(IOCTLV_READ_CONFIG, &[], &mut [&mut config, &mut status])
+        .map_err(NetError::Ios)?;
+    check(&status)?;
+    Ok(config.to_vec())
+}
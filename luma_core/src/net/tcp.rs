@@ -0,0 +1,143 @@
+//! ``tcp`` module of ``luma_core::net``.
+//!
+//! Contains TCP connections and listeners, read and written through the ``embedded-io``
+//! traits.
+
+use super::{NetError, Socket, socket, top};
+use core::net::SocketAddrV4;
+use embedded_io::{ErrorType, Read, Write};
+
+/// How many connections may wait to be accepted by a [`TcpListener`].
+const BACKLOG: u32 = 8;
+
+/// Which halves of a connection [`TcpStream::shutdown`] closes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shutdown {
+    Read,
+    Write,
+    Both,
+}
+
+/// A TCP connection, closed on drop.
+#[derive(Debug)]
+pub struct TcpStream {
+    socket: Socket,
+}
+
+impl TcpStream {
+    /// Connect to ``address``.
+    pub fn connect(address: SocketAddrV4) -> Result<TcpStream, NetError> {
+        let socket = Socket::new(socket::SOCK_STREAM)?;
+        socket::connect(&*top()?, socket.fd, address)?;
+        Ok(TcpStream { socket })
+    }
+
+    /// Connect to ``port`` on ``host``, trying each of its addresses in turn.
+    pub fn connect_host(host: &str, port: u16) -> Result<TcpStream, NetError> {
+        let mut error = NetError::NotFound;
+        for address in super::resolve(host, port)? {
+            match TcpStream::connect(address) {
+                Ok(stream) => return Ok(stream),
+                Err(failure) => error = failure,
+            }
+        }
+        Err(error)
+    }
+
+    /// Get the address of the other end of the connection.
+    pub fn peer_addr(&self) -> Result<SocketAddrV4, NetError> {
+        socket::peer_address(&*top()?, self.socket.fd)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddrV4, NetError> {
+        self.socket.local_addr()
+    }
+
+    /// Close the reading half, the writing half or both halves of the connection.
+    pub fn shutdown(&self, how: Shutdown) -> Result<(), NetError> {
+        let how = match how {
+            Shutdown::Read => 0,
+            Shutdown::Write => 1,
+            Shutdown::Both => 2,
+        };
+        socket::shutdown(&*top()?, self.socket.fd, how)
+    }
+
+    /// Make reads and writes fail with [`NetError::WouldBlock`] instead of waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    /// Get the descriptor of the socket, to [`super::poll`] it.
+    pub fn as_raw_fd(&self) -> i32 {
+        self.socket.fd
+    }
+}
+
+impl ErrorType for TcpStream {
+    type Error = NetError;
+}
+
+impl Read for TcpStream {
+    /// Read what got received, zero meaning that the other end closed the connection.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, NetError> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        Ok(self.socket.recv_from(buffer)?.0)
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, data: &[u8]) -> Result<usize, NetError> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        self.socket.send_to(data, None)
+    }
+
+    /// Do nothing, IOS sends everything as soon as it can.
+    fn flush(&mut self) -> Result<(), NetError> {
+        Ok(())
+    }
+}
+
+/// A TCP socket listening for connections, closed on drop.
+#[derive(Debug)]
+pub struct TcpListener {
+    socket: Socket,
+}
+
+impl TcpListener {
+    /// Listen on ``address``, whose address is usually [`core::net::Ipv4Addr::UNSPECIFIED`].
+    pub fn bind(address: SocketAddrV4) -> Result<TcpListener, NetError> {
+        let socket = Socket::new(socket::SOCK_STREAM)?;
+        let top = top()?;
+        socket::bind(&*top, socket.fd, address)?;
+        socket::listen(&*top, socket.fd, BACKLOG)?;
+        Ok(TcpListener { socket })
+    }
+
+    /// Wait for a connection, returning it along with the address of its other end.
+    pub fn accept(&self) -> Result<(TcpStream, SocketAddrV4), NetError> {
+        let (fd, address) = socket::accept(&*top()?, self.socket.fd)?;
+        let stream = TcpStream {
+            socket: Socket { fd },
+        };
+        Ok((stream, address))
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddrV4, NetError> {
+        self.socket.local_addr()
+    }
+
+    /// Make [`TcpListener::accept`] fail with [`NetError::WouldBlock`] instead of waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    /// Get the descriptor of the socket, to [`super::poll`] it.
+    pub fn as_raw_fd(&self) -> i32 {
+        self.socket.fd
+    }
+}
@@ -0,0 +1,62 @@
+//! ``udp`` module of ``luma_core::net``.
+//!
+//! Contains UDP sockets, sending and receiving datagrams.
+
+use super::{NetError, Socket, socket, top};
+use core::net::SocketAddrV4;
+
+/// A UDP socket, closed on drop.
+#[derive(Debug)]
+pub struct UdpSocket {
+    socket: Socket,
+}
+
+impl UdpSocket {
+    /// Create a socket bound to ``address``, whose port may be zero to get any free one.
+    pub fn bind(address: SocketAddrV4) -> Result<UdpSocket, NetError> {
+        let socket = Socket::new(socket::SOCK_DGRAM)?;
+        socket::bind(&*top()?, socket.fd, address)?;
+        Ok(UdpSocket { socket })
+    }
+
+    /// Set where [`UdpSocket::send`] sends to, and only receive from there.
+    pub fn connect(&self, address: SocketAddrV4) -> Result<(), NetError> {
+        socket::connect(&*top()?, self.socket.fd, address)
+    }
+
+    /// Send a datagram to ``address``, returning how much of it got sent.
+    pub fn send_to(&self, data: &[u8], address: SocketAddrV4) -> Result<usize, NetError> {
+        self.socket.send_to(data, Some(address))
+    }
+
+    /// Send a datagram to where the socket is connected.
+    pub fn send(&self, data: &[u8]) -> Result<usize, NetError> {
+        self.socket.send_to(data, None)
+    }
+
+    /// Receive a datagram, returning its size and who sent it.  What doesn’t fit in ``buffer``
+    /// is lost.
+    pub fn recv_from(&self, buffer: &mut [u8]) -> Result<(usize, SocketAddrV4), NetError> {
+        let (size, from) = self.socket.recv_from(buffer)?;
+        Ok((size, from.ok_or(NetError::Invalid)?))
+    }
+
+    /// Receive a datagram from where the socket is connected.
+    pub fn recv(&self, buffer: &mut [u8]) -> Result<usize, NetError> {
+        Ok(self.socket.recv_from(buffer)?.0)
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddrV4, NetError> {
+        self.socket.local_addr()
+    }
+
+    /// Make sending and receiving fail with [`NetError::WouldBlock`] instead of waiting.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<(), NetError> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    /// Get the descriptor of the socket, to [`super::poll`] it.
+    pub fn as_raw_fd(&self) -> i32 {
+        self.socket.fd
+    }
+}
@@ -0,0 +1,268 @@
+//! ``pad`` module of ``luma_core``.
+//!
+//! Contains the input of the GameCube controllers plugged into the SI ports, which get polled
+//! by the SI every frame once connected.
+
+use crate::si::{self, ChannelStatus, SiError};
+
+/// Device type bits identifying a GameCube controller.
+const TYPE_MASK: u16 = 0x1800;
+const TYPE_GAMECUBE: u16 = 0x0800;
+const TYPE_STANDARD: u16 = 0x0100;
+const TYPE_NO_MOTOR: u16 = 0x2000;
+
+/// Ask for the origin of the sticks and triggers, taken when the controller got plugged in.
+const COMMAND_ORIGIN: u8 = 0x41;
+
+/// Make the current state the new origin, and reply with it.
+const COMMAND_CALIBRATE: u8 = 0x42;
+
+/// Poll the buttons, both sticks and both triggers, in mode 3, with the rumble motor in the
+/// lowest two bits.
+const COMMAND_POLL: u32 = 0x40_0300;
+
+/// Set in the buttons when the controller wants its origin to be read again, for instance
+/// after it got reset by holding X, Y and Start.
+const NEEDS_ORIGIN: u16 = 0x2000;
+
+bitflags::bitflags! {
+    /// The digital buttons of a GameCube controller.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Buttons: u16 {
+        const LEFT = 1 << 0;
+        const RIGHT = 1 << 1;
+        const DOWN = 1 << 2;
+        const UP = 1 << 3;
+        const Z = 1 << 4;
+        /// The right trigger, fully pressed.
+        const R = 1 << 5;
+        /// The left trigger, fully pressed.
+        const L = 1 << 6;
+        const A = 1 << 8;
+        const B = 1 << 9;
+        const X = 1 << 10;
+        const Y = 1 << 11;
+        const START = 1 << 12;
+    }
+}
+
+/// The position of an analog stick relative to its origin, positive towards the right and the
+/// top.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stick {
+    pub x: i8,
+    pub y: i8,
+}
+
+/// Raw analog values, in the order they are received.
+#[derive(Clone, Copy, Debug, Default)]
+struct Analog {
+    stick: [u8; 2],
+    c_stick: [u8; 2],
+    triggers: [u8; 2],
+}
+
+impl Analog {
+    fn from_bytes(bytes: &[u8]) -> Analog {
+        Analog {
+            stick: [bytes[0], bytes[1]],
+            c_stick: [bytes[2], bytes[3]],
+            triggers: [bytes[4], bytes[5]],
+        }
+    }
+}
+
+fn relative(value: u8, origin: u8) -> i8 {
+    (value as i16 - origin as i16).clamp(i8::MIN as i16, i8::MAX as i16) as i8
+}
+
+/// A GameCube controller port.
+///
+/// [`Pad::update`] must be called once per frame, it takes care of noticing when a controller
+/// gets plugged in or out.  [`si::init`] must have been called before.
+pub struct Pad {
+    port: u8,
+    connected: bool,
+    rumble_capable: bool,
+    rumble: bool,
+    origin: Analog,
+    analog: Analog,
+    buttons: Buttons,
+    previous: Buttons,
+}
+
+impl Pad {
+    /// Create the controller on port ``port``, from 0 to 3.
+    ///
+    /// # Panics:
+    /// This function will panic if the port doesn’t exist.
+    pub fn new(port: u8) -> Pad {
+        assert!(port < si::CHANNELS, "Invalid controller port {port}");
+        Pad {
+            port,
+            connected: false,
+            rumble_capable: false,
+            rumble: false,
+            origin: Analog::default(),
+            analog: Analog::default(),
+            buttons: Buttons::empty(),
+            previous: Buttons::empty(),
+        }
+    }
+
+    /// Create the controllers of all four ports.
+    pub fn all() -> [Pad; 4] {
+        [Pad::new(0), Pad::new(1), Pad::new(2), Pad::new(3)]
+    }
+
+    /// Get the port of this controller.
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// Read the last state polled by the SI, or look for a newly plugged controller.
+    pub fn update(&mut self) {
+        self.previous = self.buttons;
+
+        if !self.connected {
+            if self.connect().is_err() {
+                self.disconnect();
+            }
+            return;
+        }
+
+        let status = si::channel_status(self.port);
+        if status.intersects(ChannelStatus::ERRORS) {
+            si::clear_errors(self.port);
+            if status.contains(ChannelStatus::NO_RESPONSE) {
+                self.disconnect();
+                return;
+            }
+        }
+        if !status.contains(ChannelStatus::READ_READY) {
+            return;
+        }
+
+        let Some([high, low]) = si::read_poll_response(self.port) else {
+            self.disconnect();
+            return;
+        };
+        let buttons = (high >> 16) as u16;
+        if buttons & NEEDS_ORIGIN != 0 && self.read_origin(&[COMMAND_ORIGIN]).is_err() {
+            self.disconnect();
+            return;
+        }
+        self.buttons = Buttons::from_bits_truncate(buttons);
+        let [_, _, x, y] = high.to_be_bytes();
+        let [c_x, c_y, l, r] = low.to_be_bytes();
+        self.analog = Analog::from_bytes(&[x, y, c_x, c_y, l, r]);
+    }
+
+    fn connect(&mut self) -> Result<(), SiError> {
+        let (device, _) = si::identify(self.port)?;
+        if device & (TYPE_MASK | TYPE_STANDARD) != TYPE_GAMECUBE | TYPE_STANDARD {
+            return Err(SiError::NoResponse);
+        }
+        self.rumble_capable = device & TYPE_NO_MOTOR == 0;
+        self.read_origin(&[COMMAND_ORIGIN])?;
+        self.analog = self.origin;
+        self.rumble = false;
+        si::set_poll_command(self.port, COMMAND_POLL);
+        si::set_polling(self.port, true);
+        self.connected = true;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) {
+        if self.connected {
+            si::set_polling(self.port, false);
+        }
+        self.connected = false;
+        self.rumble = false;
+        self.buttons = Buttons::empty();
+        self.origin = Analog::default();
+        self.analog = Analog::default();
+    }
+
+    fn read_origin(&mut self, command: &[u8]) -> Result<(), SiError> {
+        let mut response = [0; 10];
+        si::transfer(self.port, command, &mut response)?;
+        self.origin = Analog::from_bytes(&response[2..8]);
+        Ok(())
+    }
+
+    /// Make the current position of the sticks and triggers their new origin.
+    pub fn calibrate(&mut self) -> Result<(), SiError> {
+        if !self.connected {
+            return Err(SiError::NoResponse);
+        }
+        self.read_origin(&[COMMAND_CALIBRATE, 0, 0])?;
+        self.analog = self.origin;
+        Ok(())
+    }
+
+    /// Whether a controller is plugged into this port.
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Get the buttons currently held.
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Get the buttons which got pressed since the previous update.
+    pub fn pressed(&self) -> Buttons {
+        self.buttons.difference(self.previous)
+    }
+
+    /// Get the buttons which got released since the previous update.
+    pub fn released(&self) -> Buttons {
+        self.previous.difference(self.buttons)
+    }
+
+    /// Get the position of the main stick.
+    pub fn stick(&self) -> Stick {
+        Stick {
+            x: relative(self.analog.stick[0], self.origin.stick[0]),
+            y: relative(self.analog.stick[1], self.origin.stick[1]),
+        }
+    }
+
+    /// Get the position of the C stick.
+    pub fn c_stick(&self) -> Stick {
+        Stick {
+            x: relative(self.analog.c_stick[0], self.origin.c_stick[0]),
+            y: relative(self.analog.c_stick[1], self.origin.c_stick[1]),
+        }
+    }
+
+    /// Get how far the left trigger is pressed, from its origin.
+    pub fn left_trigger(&self) -> u8 {
+        self.analog.triggers[0].saturating_sub(self.origin.triggers[0])
+    }
+
+    /// Get how far the right trigger is pressed, from its origin.
+    pub fn right_trigger(&self) -> u8 {
+        self.analog.triggers[1].saturating_sub(self.origin.triggers[1])
+    }
+
+    /// Whether the controller has a rumble motor, WaveBirds for instance don’t.
+    pub fn has_rumble(&self) -> bool {
+        self.connected && self.rumble_capable
+    }
+
+    /// Start or stop the rumble motor, which gets stopped when the controller is unplugged.
+    pub fn set_rumble(&mut self, enabled: bool) {
+        if !self.has_rumble() || self.rumble == enabled {
+            return;
+        }
+        self.rumble = enabled;
+        si::set_poll_command(self.port, COMMAND_POLL | enabled as u32);
+    }
+
+    /// Whether the rumble motor is running.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
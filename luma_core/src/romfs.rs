@@ -0,0 +1,48 @@
+//! ``romfs`` module of ``luma_core``.
+//!
+//! Contains access to the romfs image bundled into the executable with [`include_romfs!`],
+//! whose files are read in place as ``&'static [u8]`` without any copy.  The image is packed
+//! at build time with ``luma_romfs``, and can also be mounted as ``romfs`` with
+//! [`crate::fs::mount_romfs`].
+
+pub use luma_romfs::{Entries, Entry, RomFs, RomFsError};
+
+// Bounds of the .romfs section, see linker.ld.
+unsafe extern "C" {
+    static __romfs_start: u8;
+    static __romfs_end: u8;
+}
+
+/// Bundle the romfs image at ``path`` into the executable, relative to the current file as
+/// with ``include_bytes!``.  Only one image may be bundled.
+///
+/// ```ignore
+/// luma_core::include_romfs!(concat!(env!("OUT_DIR"), "/assets.romfs"));
+/// ```
+#[macro_export]
+macro_rules! include_romfs {
+    ($path:expr) => {
+        const _: () = {
+            #[used]
+            #[unsafe(link_section = ".romfs")]
+            static ROMFS: [u8; include_bytes!($path).len()] = *include_bytes!($path);
+        };
+    };
+}
+
+/// Get the bundled image, which is empty if there is none.
+pub fn image() -> &'static [u8] {
+    let start = &raw const __romfs_start;
+    let end = &raw const __romfs_end;
+    unsafe { core::slice::from_raw_parts(start, end as usize - start as usize) }
+}
+
+/// Parse the bundled image, failing with [`RomFsError::BadMagic`] if there is none.
+pub fn get() -> Result<RomFs<'static>, RomFsError> {
+    RomFs::parse(image())
+}
+
+/// Get the contents of the file at ``path`` in the bundled image.
+pub fn read(path: &str) -> Option<&'static [u8]> {
+    get().ok()?.read(path)
+}
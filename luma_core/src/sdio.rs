@@ -0,0 +1,554 @@
+//! ``sdio`` module of ``luma_core``.
+//!
+//! Contains a driver for the front SD card slot, through the SD host controller IOS exposes at
+//! ``/dev/sdio/slot0``.  IOS only forwards commands and host controller register accesses, the
+//! SD protocol itself is done here.
+//!
+//! Data gets transferred by DMA, directly into the caller’s buffer when it sits in MEM2 aligned
+//! to a cache line, and through a bounce buffer in MEM2 otherwise.
+
+use crate::block::BlockDevice;
+use crate::heap::{self, Arena};
+use crate::ios::{self, Aligned, Handle, IosError, Mode};
+use crate::mem::PhysAddr;
+use crate::processor::ppc_nop;
+use core::slice;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+const DEVICE: &str = "/dev/sdio/slot0";
+
+const IOCTL_WRITE_HCR: u32 = 0x01;
+const IOCTL_READ_HCR: u32 = 0x02;
+const IOCTL_RESET_CARD: u32 = 0x04;
+const IOCTL_SET_CLOCK: u32 = 0x06;
+const IOCTL_SEND_COMMAND: u32 = 0x07;
+const IOCTL_GET_STATUS: u32 = 0x0b;
+
+/// Host controller registers, as in the SD host controller specification.
+const HCR_HOST_CONTROL: u8 = 0x28;
+const HCR_POWER_CONTROL: u8 = 0x29;
+const HCR_CLOCK_CONTROL: u8 = 0x2c;
+const HCR_TIMEOUT_CONTROL: u8 = 0x2e;
+const HCR_SOFTWARE_RESET: u8 = 0x2f;
+const HCR_INTERRUPT_STATUS_ENABLE: u8 = 0x34;
+const HCR_INTERRUPT_SIGNAL_ENABLE: u8 = 0x38;
+
+const HOST_CONTROL_4BIT: u32 = 0x02;
+
+/// Status bits returned by IOS.
+const STATUS_CARD_INSERTED: u32 = 0x0000_0001;
+const STATUS_CARD_INITIALIZED: u32 = 0x0001_0000;
+const STATUS_CARD_SDHC: u32 = 0x0010_0000;
+
+const CMD_GO_IDLE: u8 = 0;
+const CMD_ALL_SEND_CID: u8 = 2;
+const CMD_SEND_RCA: u8 = 3;
+const CMD_SELECT: u8 = 7;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SEND_CSD: u8 = 9;
+const CMD_SET_BLOCK_LENGTH: u8 = 16;
+const CMD_READ_BLOCK: u8 = 17;
+const CMD_READ_MULTIPLE_BLOCKS: u8 = 18;
+const CMD_WRITE_BLOCK: u8 = 24;
+const CMD_WRITE_MULTIPLE_BLOCKS: u8 = 25;
+const CMD_APP: u8 = 55;
+const ACMD_SET_BUS_WIDTH: u8 = 6;
+const ACMD_SEND_OP_COND: u8 = 41;
+
+/// Kinds of command, the broadcast ones going to every card.
+const TYPE_BROADCAST: u32 = 0;
+const TYPE_ADDRESSED: u32 = 3;
+
+/// Voltage window and high capacity support, as sent with ACMD41.
+const OP_COND_SDHC: u32 = 0x4030_0000;
+const OCR_READY: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+
+/// Check pattern and 2.7-3.6V, as sent with CMD8.
+const IF_COND: u32 = 0x1aa;
+
+/// Size of a block, the only one SDHC cards support.
+pub const BLOCK_SIZE: usize = 512;
+
+/// Size of the bounce buffer, in blocks.
+const BOUNCE_BLOCKS: usize = 128;
+
+/// Amount of register polls or ACMD41 retries before giving up on the card.
+const TIMEOUT: u32 = 10_000;
+
+/// The kind of response expected from a command, as numbered by IOS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+enum Response {
+    None = 0,
+    R1 = 1,
+    R1b = 2,
+    R2 = 3,
+    R3 = 4,
+    R5 = 6,
+    R6 = 7,
+}
+
+/// Errors which can be encountered while using the SD card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdError {
+    /// There is no card in the slot.
+    NoCard,
+    /// The card didn’t answer as an SD card should, or took too long to.
+    Unsupported,
+    /// The card got opened already.
+    Busy,
+    /// The blocks asked for go past the end of the card.
+    OutOfRange,
+    /// IOS rejected a request.
+    Ios(IosError),
+}
+
+impl From<IosError> for SdError {
+    fn from(error: IosError) -> SdError {
+        SdError::Ios(error)
+    }
+}
+
+static OPEN: AtomicBool = AtomicBool::new(false);
+static BOUNCE: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Get the amount of 512 bytes blocks on a card from its CSD register.
+fn capacity(csd: u128) -> u64 {
+    let bits = |high: u32, low: u32| (csd >> low) as u64 & ((1 << (high - low + 1)) - 1);
+    match bits(127, 126) {
+        // Version 2, SDHC and SDXC, counted in 512 KiB units.
+        1 => (bits(69, 48) + 1) * 1024,
+        // Version 1, standard capacity.
+        _ => {
+            let size = bits(73, 62) + 1;
+            let multiplier = 1 << (bits(49, 47) + 2);
+            let block_length = 1 << bits(83, 80);
+            size * multiplier * block_length / BLOCK_SIZE as u64
+        }
+    }
+}
+
+/// The SD card in the front slot.
+pub struct SdCard {
+    handle: Handle,
+    rca: u16,
+    sdhc: bool,
+    blocks: u64,
+    bounce: &'static mut [u8],
+}
+
+impl SdCard {
+    /// Initialize the card in the slot, and get it ready to be read and written.
+    pub fn open() -> Result<SdCard, SdError> {
+        if OPEN.swap(true, Ordering::AcqRel) {
+            return Err(SdError::Busy);
+        }
+        let handle = match Handle::open(DEVICE, Mode::ReadWrite) {
+            Ok(handle) => handle,
+            Err(error) => {
+                OPEN.store(false, Ordering::Release);
+                return Err(error.into());
+            }
+        };
+
+        if BOUNCE.load(Ordering::Acquire).is_null() {
+            BOUNCE.store(
+                heap::take(Arena::Mem2, BOUNCE_BLOCKS * BLOCK_SIZE),
+                Ordering::Release,
+            );
+        }
+        let bounce = unsafe {
+            slice::from_raw_parts_mut(BOUNCE.load(Ordering::Acquire), BOUNCE_BLOCKS * BLOCK_SIZE)
+        };
+
+        let mut card = SdCard {
+            handle,
+            rca: 0,
+            sdhc: false,
+            blocks: 0,
+            bounce,
+        };
+        card.init()?;
+        Ok(card)
+    }
+
+    fn init(&mut self) -> Result<(), SdError> {
+        self.reset_card()?;
+        let status = self.status()?;
+        if status & STATUS_CARD_INSERTED == 0 {
+            return Err(SdError::NoCard);
+        }
+        if status & STATUS_CARD_INITIALIZED == 0 {
+            // IOS gave up on this card, reopening the slot makes it forget about it.
+            self.handle = Handle::open(DEVICE, Mode::ReadWrite)?;
+            self.init_manually()?;
+        } else {
+            self.sdhc = status & STATUS_CARD_SDHC != 0;
+        }
+
+        let host_control = self.read_hcr(HCR_HOST_CONTROL, 1)? & 0xff;
+        self.write_hcr(HCR_HOST_CONTROL, 1, host_control | HOST_CONTROL_4BIT)?;
+        self.set_clock(1)?;
+
+        // The CSD can only be read while the card isn’t selected.
+        let csd = self.command(
+            CMD_SEND_CSD,
+            TYPE_ADDRESSED,
+            Response::R2,
+            (self.rca as u32) << 16,
+            None,
+        )?;
+        self.blocks = capacity(
+            ((csd[3] as u128) << 96
+                | (csd[2] as u128) << 64
+                | (csd[1] as u128) << 32
+                | csd[0] as u128)
+                << 8,
+        );
+
+        self.select()?;
+        let result = self.configure();
+        self.deselect()?;
+        result
+    }
+
+    /// Do what IOS didn’t, which happens with SDHC cards on IOS versions predating them.
+    fn init_manually(&mut self) -> Result<(), SdError> {
+        self.write_hcr(HCR_SOFTWARE_RESET, 1, 7)?;
+        self.wait_hcr(HCR_SOFTWARE_RESET, 1, 7, false)?;
+        self.write_hcr(HCR_INTERRUPT_STATUS_ENABLE, 4, 0x013f_00c3)?;
+        self.write_hcr(HCR_INTERRUPT_SIGNAL_ENABLE, 4, 0x013f_00c3)?;
+
+        // Power at 3.3V, then the internal clock, and the card clock once that one is stable.
+        self.write_hcr(HCR_POWER_CONTROL, 1, 0x0e)?;
+        self.write_hcr(HCR_POWER_CONTROL, 1, 0x0f)?;
+        self.write_hcr(HCR_CLOCK_CONTROL, 2, 0)?;
+        self.write_hcr(HCR_CLOCK_CONTROL, 2, 0x101)?;
+        self.wait_hcr(HCR_CLOCK_CONTROL, 2, 0x02, true)?;
+        self.write_hcr(HCR_CLOCK_CONTROL, 2, 0x107)?;
+        self.write_hcr(HCR_TIMEOUT_CONTROL, 1, 0x0e)?;
+
+        self.command(CMD_GO_IDLE, TYPE_BROADCAST, Response::None, 0, None)?;
+        let response = self.command(
+            CMD_SEND_IF_COND,
+            TYPE_BROADCAST,
+            Response::R6,
+            IF_COND,
+            None,
+        )?;
+        if response[0] & 0xff != IF_COND & 0xff {
+            return Err(SdError::Unsupported);
+        }
+
+        let mut ocr = 0;
+        for _ in 0..TIMEOUT {
+            self.command(CMD_APP, TYPE_ADDRESSED, Response::R1, 0, None)?;
+            ocr = self.command(
+                ACMD_SEND_OP_COND,
+                TYPE_BROADCAST,
+                Response::R3,
+                OP_COND_SDHC,
+                None,
+            )?[0];
+            if ocr & OCR_READY != 0 {
+                break;
+            }
+        }
+        if ocr & OCR_READY == 0 {
+            return Err(SdError::Unsupported);
+        }
+        self.sdhc = ocr & OCR_HIGH_CAPACITY != 0;
+
+        self.command(CMD_ALL_SEND_CID, TYPE_BROADCAST, Response::R2, 0, None)?;
+        let response = self.command(CMD_SEND_RCA, TYPE_BROADCAST, Response::R5, 0, None)?;
+        self.rca = (response[0] >> 16) as u16;
+        Ok(())
+    }
+
+    /// Set the block length and the bus width, which must be done while selected.
+    fn configure(&mut self) -> Result<(), SdError> {
+        self.command(
+            CMD_SET_BLOCK_LENGTH,
+            TYPE_ADDRESSED,
+            Response::R1,
+            BLOCK_SIZE as u32,
+            None,
+        )?;
+        self.command(
+            CMD_APP,
+            TYPE_ADDRESSED,
+            Response::R1,
+            (self.rca as u32) << 16,
+            None,
+        )?;
+        // 2 is for a 4-bit bus.
+        self.command(ACMD_SET_BUS_WIDTH, TYPE_ADDRESSED, Response::R1, 2, None)?;
+        Ok(())
+    }
+
+    fn select(&mut self) -> Result<(), SdError> {
+        self.command(
+            CMD_SELECT,
+            TYPE_ADDRESSED,
+            Response::R1b,
+            (self.rca as u32) << 16,
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn deselect(&mut self) -> Result<(), SdError> {
+        self.command(CMD_SELECT, TYPE_ADDRESSED, Response::R1b, 0, None)?;
+        Ok(())
+    }
+
+    fn reset_card(&mut self) -> Result<(), SdError> {
+        let mut output = Aligned([0; 32]);
+        self.handle
+            .ioctl(IOCTL_RESET_CARD, &[], &mut output.0[..4])?;
+        self.rca = u16::from_be_bytes([output.0[0], output.0[1]]);
+        Ok(())
+    }
+
+    /// Get the status of the slot, as IOS sees it.
+    fn status(&mut self) -> Result<u32, SdError> {
+        let mut output = Aligned([0; 32]);
+        self.handle
+            .ioctl(IOCTL_GET_STATUS, &[], &mut output.0[..4])?;
+        Ok(u32::from_be_bytes([
+            output.0[0],
+            output.0[1],
+            output.0[2],
+            output.0[3],
+        ]))
+    }
+
+    fn set_clock(&mut self, clock: u32) -> Result<(), SdError> {
+        let input = Aligned(clock.to_be_bytes());
+        self.handle.ioctl(IOCTL_SET_CLOCK, &input.0, &mut [])?;
+        Ok(())
+    }
+
+    fn hcr_query(register: u8, size: u8, value: u32) -> Aligned<24> {
+        let mut query = Aligned([0; 24]);
+        query.0[3] = register;
+        query.0[15] = size;
+        query.0[16..20].copy_from_slice(&value.to_be_bytes());
+        query
+    }
+
+    fn write_hcr(&mut self, register: u8, size: u8, value: u32) -> Result<(), SdError> {
+        let query = SdCard::hcr_query(register, size, value);
+        self.handle.ioctl(IOCTL_WRITE_HCR, &query.0, &mut [])?;
+        Ok(())
+    }
+
+    fn read_hcr(&mut self, register: u8, size: u8) -> Result<u32, SdError> {
+        let query = SdCard::hcr_query(register, size, 0);
+        let mut output = Aligned([0; 32]);
+        self.handle
+            .ioctl(IOCTL_READ_HCR, &query.0, &mut output.0[..4])?;
+        Ok(u32::from_be_bytes([
+            output.0[0],
+            output.0[1],
+            output.0[2],
+            output.0[3],
+        ]))
+    }
+
+    /// Wait for the bits of ``mask`` in a register to be all clear, or any set if ``set``.
+    fn wait_hcr(&mut self, register: u8, size: u8, mask: u32, set: bool) -> Result<(), SdError> {
+        for _ in 0..TIMEOUT {
+            if (self.read_hcr(register, size)? & mask != 0) == set {
+                return Ok(());
+            }
+            ppc_nop();
+        }
+        Err(SdError::Unsupported)
+    }
+
+    /// Send a command, with ``blocks`` blocks of data from or to ``data``, and get the response.
+    fn command(
+        &mut self,
+        command: u8,
+        kind: u32,
+        response: Response,
+        argument: u32,
+        data: Option<(&mut [u8], u32)>,
+    ) -> Result<[u32; 4], SdError> {
+        let mut request = Aligned([0; 64]);
+        let (address, blocks) = data.as_ref().map_or((0, 0), |(data, blocks)| {
+            (PhysAddr::of(data.as_ptr()).value(), *blocks)
+        });
+        let fields = [
+            command as u32,
+            kind,
+            response as u32,
+            argument,
+            blocks,
+            BLOCK_SIZE as u32,
+            address,
+            data.is_some() as u32,
+            0,
+        ];
+        for (bytes, field) in request.0.chunks_exact_mut(4).zip(fields) {
+            bytes.copy_from_slice(&field.to_be_bytes());
+        }
+
+        let mut reply = Aligned([0; 32]);
+        match data {
+            Some((data, blocks)) => {
+                let data = &mut data[..blocks as usize * BLOCK_SIZE];
+                self.handle.ioctlv(
+                    IOCTL_SEND_COMMAND,
+                    &[&request.0[..36], data],
+                    &mut [&mut reply.0[..16]],
+                )?;
+                // Reads write into the second input.
+                ios::invalidate(data);
+            }
+            // IOS wants SDHC commands as an ioctlv, data or not.
+            None if self.sdhc => {
+                self.handle.ioctlv(
+                    IOCTL_SEND_COMMAND,
+                    &[&request.0[..36], &[]],
+                    &mut [&mut reply.0[..16]],
+                )?;
+            }
+            None => {
+                self.handle
+                    .ioctl(IOCTL_SEND_COMMAND, &request.0[..36], &mut reply.0[..16])?;
+            }
+        }
+
+        let mut words = [0; 4];
+        for (word, bytes) in words.iter_mut().zip(reply.0.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Ok(words)
+    }
+
+    /// Whether the card is a high capacity one, addressed in blocks instead of bytes.
+    pub fn is_sdhc(&self) -> bool {
+        self.sdhc
+    }
+
+    fn check_range(&self, block: u64, length: usize) -> Result<u32, SdError> {
+        assert!(
+            length.is_multiple_of(BLOCK_SIZE),
+            "SD buffer isn’t a whole amount of blocks"
+        );
+        let count = (length / BLOCK_SIZE) as u64;
+        if block + count > self.blocks {
+            return Err(SdError::OutOfRange);
+        }
+        Ok(count as u32)
+    }
+
+    fn address(&self, block: u64) -> u32 {
+        if self.sdhc {
+            block as u32
+        } else {
+            (block * BLOCK_SIZE as u64) as u32
+        }
+    }
+
+    /// Transfer ``count`` blocks starting at ``block``, from or to a DMA-safe buffer.
+    fn transfer(
+        &mut self,
+        write: bool,
+        block: u64,
+        count: u32,
+        data: &mut [u8],
+    ) -> Result<(), SdError> {
+        let command = match (write, count) {
+            (false, 1) => CMD_READ_BLOCK,
+            (false, _) => CMD_READ_MULTIPLE_BLOCKS,
+            (true, 1) => CMD_WRITE_BLOCK,
+            (true, _) => CMD_WRITE_MULTIPLE_BLOCKS,
+        };
+        let address = self.address(block);
+        self.command(
+            command,
+            TYPE_ADDRESSED,
+            Response::R1,
+            address,
+            Some((data, count)),
+        )?;
+        Ok(())
+    }
+
+    fn read(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), SdError> {
+        if ios::is_dma_safe(buffer) {
+            let count = (buffer.len() / BLOCK_SIZE) as u32;
+            return self.transfer(false, block, count, buffer);
+        }
+        let bounce = core::mem::take(&mut self.bounce);
+        let mut result = Ok(());
+        for (i, chunk) in buffer.chunks_mut(BOUNCE_BLOCKS * BLOCK_SIZE).enumerate() {
+            let count = (chunk.len() / BLOCK_SIZE) as u32;
+            result = self.transfer(false, block + (i * BOUNCE_BLOCKS) as u64, count, bounce);
+            if result.is_err() {
+                break;
+            }
+            chunk.copy_from_slice(&bounce[..chunk.len()]);
+        }
+        self.bounce = bounce;
+        result
+    }
+
+    /// Writes always go through the bounce buffer, the controller only needs to read it.
+    fn write(&mut self, block: u64, buffer: &[u8]) -> Result<(), SdError> {
+        let bounce = core::mem::take(&mut self.bounce);
+        let mut result = Ok(());
+        for (i, chunk) in buffer.chunks(BOUNCE_BLOCKS * BLOCK_SIZE).enumerate() {
+            let count = (chunk.len() / BLOCK_SIZE) as u32;
+            bounce[..chunk.len()].copy_from_slice(chunk);
+            result = self.transfer(true, block + (i * BOUNCE_BLOCKS) as u64, count, bounce);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.bounce = bounce;
+        result
+    }
+}
+
+impl BlockDevice for SdCard {
+    type Error = SdError;
+
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), SdError> {
+        if self.check_range(block, buffer.len())? == 0 {
+            return Ok(());
+        }
+        self.select()?;
+        let result = self.read(block, buffer);
+        self.deselect()?;
+        result
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<(), SdError> {
+        if self.check_range(block, buffer.len())? == 0 {
+            return Ok(());
+        }
+        self.select()?;
+        let result = self.write(block, buffer);
+        self.deselect()?;
+        result
+    }
+}
+
+impl Drop for SdCard {
+    fn drop(&mut self) {
+        OPEN.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,244 @@
+//! ``si`` module of ``luma_core``.
+//!
+//! Contains functions for the Serial Interface, or SI, which talks to the devices plugged into
+//! the four GameCube controller ports, either by sending them arbitrary commands or by polling
+//! them on its own at a fixed rate.
+
+use crate::io::{read32, write32};
+use crate::mem::PhysAddr;
+
+const SI_BASE: PhysAddr = PhysAddr::new(0x0d00_6400);
+
+/// Each channel has an output buffer, holding the polling command, and two input buffers,
+/// holding the last polling response.
+const CHANNEL_STRIDE: u32 = 0x0c;
+const OUTPUT_BUFFER: u32 = 0x00;
+const INPUT_BUFFER_HIGH: u32 = 0x04;
+const INPUT_BUFFER_LOW: u32 = 0x08;
+
+const POLL: u32 = 0x30;
+const COMMUNICATION: u32 = 0x34;
+const STATUS: u32 = 0x38;
+const EXI_CLOCK_LOCK: u32 = 0x3c;
+const IO_BUFFER: u32 = 0x80;
+
+/// Size of the I/O buffer, which limits the length of commands and responses.
+pub const IO_BUFFER_SIZE: usize = 128;
+
+/// Amount of channels, one per controller port.
+pub const CHANNELS: u8 = 4;
+
+/// Set in the high input buffer when the last poll failed.
+const INPUT_ERROR: u32 = 1 << 31;
+
+/// Amount of register polls before giving up on a transfer.
+const TIMEOUT: u32 = 1_000_000;
+
+/// Command asking a device for its type and status.
+const COMMAND_IDENTIFY: u8 = 0x00;
+
+bitflags::bitflags! {
+    struct Communication: u32 {
+        const START = 1 << 0;
+        const CHANNEL = 0b11 << 1;
+        const INPUT_LENGTH = 0x7f << 8;
+        const OUTPUT_LENGTH = 0x7f << 16;
+        const READ_STATUS_INTERRUPT_MASK = 1 << 27;
+        const READ_STATUS_INTERRUPT = 1 << 28;
+        const ERROR = 1 << 29;
+        const TRANSFER_INTERRUPT_MASK = 1 << 30;
+        const TRANSFER_INTERRUPT = 1 << 31;
+    }
+}
+
+bitflags::bitflags! {
+    /// The status of a channel, as found in the status register.  Its error bits are cleared by
+    /// writing them back.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct ChannelStatus: u8 {
+        const UNDERRUN = 1 << 0;
+        const OVERRUN = 1 << 1;
+        const COLLISION = 1 << 2;
+        const NO_RESPONSE = 1 << 3;
+        /// The output buffer hasn’t been copied to the hardware yet.
+        const WRITE_PENDING = 1 << 4;
+        /// The input buffers got a new polling response since they were last read.
+        const READ_READY = 1 << 5;
+        const ERRORS = Self::UNDERRUN.bits() | Self::OVERRUN.bits() | Self::COLLISION.bits() | Self::NO_RESPONSE.bits();
+    }
+}
+
+/// Copy every output buffer to the hardware.
+const STATUS_WRITE: u32 = 1 << 31;
+
+/// Errors which can be encountered while talking to a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SiError {
+    /// Nothing answered, usually because nothing is plugged in.
+    NoResponse,
+    /// The device answered while a command was still being sent.
+    Collision,
+    /// The device sent more data than expected.
+    Overrun,
+    /// The device sent less data than expected.
+    Underrun,
+    /// The transfer never completed.
+    Timeout,
+}
+
+impl SiError {
+    fn from_status(status: ChannelStatus) -> Option<SiError> {
+        if status.contains(ChannelStatus::NO_RESPONSE) {
+            Some(SiError::NoResponse)
+        } else if status.contains(ChannelStatus::COLLISION) {
+            Some(SiError::Collision)
+        } else if status.contains(ChannelStatus::OVERRUN) {
+            Some(SiError::Overrun)
+        } else if status.contains(ChannelStatus::UNDERRUN) {
+            Some(SiError::Underrun)
+        } else {
+            None
+        }
+    }
+}
+
+fn channel_register(channel: u8, register: u32) -> PhysAddr {
+    assert!(channel < CHANNELS, "Invalid SI channel {channel}");
+    SI_BASE + channel as u32 * CHANNEL_STRIDE + register
+}
+
+fn status_shift(channel: u8) -> u32 {
+    24 - 8 * channel as u32
+}
+
+/// Stop polling every channel, clear any pending error, and poll twice per frame once enabled.
+pub fn init() {
+    write32(SI_BASE + POLL, 0);
+    write32(
+        SI_BASE + COMMUNICATION,
+        Communication::TRANSFER_INTERRUPT.bits(),
+    );
+    write32(SI_BASE + STATUS, 0x0f0f_0f0f);
+    // Let the EXI clock run at full speed, this lock only matters to the GameCube IPL.
+    write32(SI_BASE + EXI_CLOCK_LOCK, 0);
+    set_sampling(246, 2);
+}
+
+/// Get the status of a channel.
+pub fn channel_status(channel: u8) -> ChannelStatus {
+    let status = read32(SI_BASE + STATUS) >> status_shift(channel);
+    ChannelStatus::from_bits_truncate(status as u8)
+}
+
+/// Clear the error bits of a channel, returning the error they reported if any.
+pub fn clear_errors(channel: u8) -> Option<SiError> {
+    let status = channel_status(channel) & ChannelStatus::ERRORS;
+    write32(
+        SI_BASE + STATUS,
+        (status.bits() as u32) << status_shift(channel),
+    );
+    SiError::from_status(status)
+}
+
+/// Send ``command`` to the device on ``channel`` and wait for it to fill ``response``.
+///
+/// # Panics:
+/// This function will panic if either of them is empty or bigger than [`IO_BUFFER_SIZE`].
+pub fn transfer(channel: u8, command: &[u8], response: &mut [u8]) -> Result<(), SiError> {
+    assert!((1..=IO_BUFFER_SIZE).contains(&command.len()));
+    assert!((1..=IO_BUFFER_SIZE).contains(&response.len()));
+    clear_errors(channel);
+
+    for (i, word) in command.chunks(4).enumerate() {
+        let mut bytes = [0; 4];
+        bytes[..word.len()].copy_from_slice(word);
+        write32(
+            SI_BASE + IO_BUFFER + i as u32 * 4,
+            u32::from_be_bytes(bytes),
+        );
+    }
+
+    // The lengths are 7-bit, with 0 meaning 128.
+    let mut communication = Communication::from_bits_retain(read32(SI_BASE + COMMUNICATION))
+        .difference(Communication::CHANNEL | Communication::INPUT_LENGTH)
+        .difference(Communication::OUTPUT_LENGTH | Communication::READ_STATUS_INTERRUPT);
+    communication |= Communication::from_bits_retain(
+        (channel as u32) << 1
+            | (response.len() as u32 & 0x7f) << 8
+            | (command.len() as u32 & 0x7f) << 16,
+    );
+    communication |= Communication::TRANSFER_INTERRUPT | Communication::START;
+    write32(SI_BASE + COMMUNICATION, communication.bits());
+
+    let mut timeout = TIMEOUT;
+    while Communication::from_bits_retain(read32(SI_BASE + COMMUNICATION))
+        .contains(Communication::START)
+    {
+        timeout -= 1;
+        if timeout == 0 {
+            return Err(SiError::Timeout);
+        }
+    }
+
+    if Communication::from_bits_retain(read32(SI_BASE + COMMUNICATION))
+        .contains(Communication::ERROR)
+        && let Some(error) = clear_errors(channel)
+    {
+        return Err(error);
+    }
+
+    for (i, word) in response.chunks_mut(4).enumerate() {
+        let bytes = read32(SI_BASE + IO_BUFFER + i as u32 * 4).to_be_bytes();
+        word.copy_from_slice(&bytes[..word.len()]);
+    }
+    Ok(())
+}
+
+/// Ask the device on ``channel`` for its 16-bit type, along with a status byte specific to
+/// that type.
+pub fn identify(channel: u8) -> Result<(u16, u8), SiError> {
+    let mut response = [0; 3];
+    transfer(channel, &[COMMAND_IDENTIFY], &mut response)?;
+    Ok((u16::from_be_bytes([response[0], response[1]]), response[2]))
+}
+
+/// Set how often the enabled channels get polled: ``times`` per frame, every ``lines`` video
+/// lines.
+pub fn set_sampling(lines: u16, times: u8) {
+    let poll = read32(SI_BASE + POLL) & 0xff;
+    write32(
+        SI_BASE + POLL,
+        poll | (lines as u32 & 0x3ff) << 16 | (times as u32) << 8,
+    );
+}
+
+/// Start or stop polling a channel with the command in its output buffer.
+pub fn set_polling(channel: u8, enabled: bool) {
+    assert!(channel < CHANNELS, "Invalid SI channel {channel}");
+    let bit = 1 << (7 - channel);
+    let poll = read32(SI_BASE + POLL) & !bit;
+    write32(SI_BASE + POLL, poll | if enabled { bit } else { 0 });
+}
+
+/// Set the command sent to the device on ``channel`` when it gets polled.  It is made of three
+/// bytes, the highest one being the command itself.
+pub fn set_poll_command(channel: u8, command: u32) {
+    write32(
+        channel_register(channel, OUTPUT_BUFFER),
+        command & 0x00ff_ffff,
+    );
+    write32(SI_BASE + STATUS, STATUS_WRITE);
+}
+
+/// Read the response to the last poll of ``channel``, or ``None`` if it failed.
+///
+/// This clears [`ChannelStatus::READ_READY`].
+pub fn read_poll_response(channel: u8) -> Option<[u32; 2]> {
+    let high = read32(channel_register(channel, INPUT_BUFFER_HIGH));
+    let low = read32(channel_register(channel, INPUT_BUFFER_LOW));
+    if high & INPUT_ERROR != 0 {
+        None
+    } else {
+        Some([high, low])
+    }
+}
@@ -0,0 +1,169 @@
+//! ``stm`` module of ``luma_core``.
+//!
+//! Contains a driver for the IOS state transition manager, which owns the Reset and Power
+//! buttons of the console as well as turning it off or resetting it.
+//!
+//! The buttons get reported through ``/dev/stm/eventhook``, whose single request only completes
+//! once a button got pressed, while everything else goes through ``/dev/stm/immediate``.
+
+use crate::ios::{self, Handle, IosError, Mode};
+use alloc::boxed::Box;
+use core::mem::ManuallyDrop;
+use core::ptr;
+use core::sync::atomic::{AtomicI32, AtomicPtr, Ordering};
+
+const EVENT_HOOK: &str = "/dev/stm/eventhook";
+const IMMEDIATE: &str = "/dev/stm/immediate";
+
+const IOCTL_EVENT_HOOK: u32 = 0x1000;
+const IOCTL_HOT_RESET: u32 = 0x2001;
+const IOCTL_SHUTDOWN: u32 = 0x2003;
+const IOCTL_IDLE: u32 = 0x2004;
+const IOCTL_RELEASE_EVENT_HOOK: u32 = 0x3002;
+
+/// Events the hook completes with.
+const EVENT_RESET: u32 = 0x0002_0000;
+const EVENT_POWER: u32 = 0x0000_0800;
+
+/// A callback called from the IPC interrupt when a button got pressed.
+pub type ButtonCallback = fn();
+
+/// The buffers of a request, STM always wants them to be 32 bytes long.
+#[repr(C, align(32))]
+struct Buffers {
+    input: [u8; 32],
+    output: [u8; 32],
+}
+
+static EVENT_FD: AtomicI32 = AtomicI32::new(-1);
+static EVENT_BUFFER: AtomicPtr<Buffers> = AtomicPtr::new(ptr::null_mut());
+static RESET_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+static POWER_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Open the event hook and start waiting for the buttons, unless this got done already.
+pub fn init() -> Result<(), IosError> {
+    if EVENT_FD.load(Ordering::Acquire) >= 0 {
+        return Ok(());
+    }
+    let handle = Handle::open(EVENT_HOOK, Mode::None)?;
+    if EVENT_BUFFER.load(Ordering::Acquire).is_null() {
+        // Never freed, IOS may still write to it after the hook got released.
+        let buffer = Box::leak(Box::new(Buffers {
+            input: [0; 32],
+            output: [0; 32],
+        }));
+        EVENT_BUFFER.store(buffer, Ordering::Release);
+    }
+    EVENT_FD.store(handle.fd(), Ordering::Release);
+    core::mem::forget(handle);
+    arm()
+}
+
+/// Send the event hook request, to be completed on the next button press.
+fn arm() -> Result<(), IosError> {
+    let fd = EVENT_FD.load(Ordering::Acquire);
+    let handle = ManuallyDrop::new(unsafe { Handle::from_raw(fd) });
+    let buffer = unsafe { &mut *EVENT_BUFFER.load(Ordering::Acquire) };
+    // Nobody waits on it, the result gets delivered to the callback.
+    unsafe {
+        handle.ioctl_async(
+            IOCTL_EVENT_HOOK,
+            &buffer.input,
+            &mut buffer.output,
+            Some(on_event),
+        )
+    }?;
+    Ok(())
+}
+
+fn on_event(result: Result<i32, IosError>) {
+    if result.is_err() {
+        return;
+    }
+    let buffer = unsafe { &*EVENT_BUFFER.load(Ordering::Acquire) };
+    ios::invalidate(&buffer.output);
+    let event = u32::from_be_bytes([
+        buffer.output[0],
+        buffer.output[1],
+        buffer.output[2],
+        buffer.output[3],
+    ]);
+    // The hook got released, don’t wait for another event.
+    if event == 0 {
+        return;
+    }
+    let _ = arm();
+
+    let callback = if event & EVENT_RESET != 0 {
+        RESET_CALLBACK.load(Ordering::Acquire)
+    } else if event & EVENT_POWER != 0 {
+        POWER_CALLBACK.load(Ordering::Acquire)
+    } else {
+        ptr::null_mut()
+    };
+    if !callback.is_null() {
+        let callback = unsafe { core::mem::transmute::<*mut (), ButtonCallback>(callback) };
+        callback();
+    }
+}
+
+fn set_callback(slot: &AtomicPtr<()>, callback: Option<ButtonCallback>) -> Result<(), IosError> {
+    slot.store(
+        callback.map_or(ptr::null_mut(), |callback| callback as *mut ()),
+        Ordering::Release,
+    );
+    init()
+}
+
+/// Call ``callback`` whenever the Reset button gets pressed, or stop if ``None``.
+///
+/// It gets called from the IPC interrupt, so it must neither allocate nor talk to IOS; setting a
+/// flag for the main loop to act on is the way to go.
+pub fn on_reset(callback: Option<ButtonCallback>) -> Result<(), IosError> {
+    set_callback(&RESET_CALLBACK, callback)
+}
+
+/// Call ``callback`` whenever the Power button gets pressed, or stop if ``None``.
+///
+/// Once the Power button got pressed, the console turns itself off on its own after a few
+/// seconds, [`shutdown`] should be called before that happens.  The same restrictions as
+/// [`on_reset`] apply to ``callback``.
+pub fn on_power(callback: Option<ButtonCallback>) -> Result<(), IosError> {
+    set_callback(&POWER_CALLBACK, callback)
+}
+
+fn immediate(ioctl: u32) -> Result<i32, IosError> {
+    let handle = Handle::open(IMMEDIATE, Mode::None)?;
+    let mut buffers = Buffers {
+        input: [0; 32],
+        output: [0; 32],
+    };
+    handle.ioctl(ioctl, &buffers.input, &mut buffers.output)
+}
+
+/// Stop waiting for the buttons, which IOS requires before launching another title.
+pub fn release_event_hook() -> Result<(), IosError> {
+    let fd = EVENT_FD.swap(-1, Ordering::AcqRel);
+    if fd < 0 {
+        return Ok(());
+    }
+    let result = immediate(IOCTL_RELEASE_EVENT_HOOK);
+    // The hook completes with no event, then it can be closed.
+    drop(unsafe { Handle::from_raw(fd) });
+    result.map(|_| ())
+}
+
+/// Turn the console off to standby, with its LED going red.
+pub fn shutdown() -> Result<(), IosError> {
+    immediate(IOCTL_SHUTDOWN).map(|_| ())
+}
+
+/// Put the console in idle mode, in which WiiConnect24 keeps running.
+pub fn idle() -> Result<(), IosError> {
+    immediate(IOCTL_IDLE).map(|_| ())
+}
+
+/// Reset the whole console, as if the Reset button got pressed without anything handling it.
+pub fn hot_reset() -> Result<(), IosError> {
+    immediate(IOCTL_HOT_RESET).map(|_| ())
+}
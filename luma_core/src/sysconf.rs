@@ -0,0 +1,6 @@
+//! ``sysconf`` module of ``luma_core``.
+//!
+//! Contains the SYSCONF parser of ``luma_formats``, read off the NAND with
+//! [`crate::fs::nand::read`] at [`PATH`].
+
+pub use luma_formats::sysconf::{PATH, SysConf, SysConfError};
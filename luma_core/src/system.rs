@@ -0,0 +1,51 @@
+//! ``system`` module of ``luma_core``.
+//!
+//! Contains the ways of leaving a luma app: turning the console off, resetting it, or going
+//! back to the System Menu or to the Homebrew Channel.
+
+use crate::es;
+use crate::ios::IosError;
+use crate::processor::ppc_halt;
+use crate::stm;
+use core::convert::Infallible;
+
+/// Turn the console off to standby.
+///
+/// This only returns if IOS refused to.
+pub fn shutdown() -> Result<Infallible, IosError> {
+    stm::shutdown()?;
+    // Wait for IOS to act on it.
+    loop {
+        ppc_halt();
+    }
+}
+
+/// Reset the console, which then boots the System Menu as when turned on.
+///
+/// This only returns if IOS refused to.
+pub fn reboot() -> Result<Infallible, IosError> {
+    stm::hot_reset()?;
+    // Wait for IOS to act on it.
+    loop {
+        ppc_halt();
+    }
+}
+
+/// Go back to the System Menu, by having IOS launch it.
+///
+/// This only returns if IOS refused to, for instance if the System Menu isn’t installed or has no
+/// ticket.
+pub fn return_to_menu() -> Result<Infallible, IosError> {
+    es::launch_title(es::SYSTEM_MENU)
+}
+
+/// Go back to the Homebrew Channel, by having IOS launch whichever version of it is installed.
+///
+/// This only returns if IOS refused to, or if the Homebrew Channel isn’t installed.
+pub fn return_to_homebrew_channel() -> Result<Infallible, IosError> {
+    let title = [es::HOMEBREW_CHANNEL, es::HOMEBREW_CHANNEL_LEGACY]
+        .into_iter()
+        .find(|&title| es::ticket_views(title).is_ok_and(|views| !views.is_empty()))
+        .ok_or(IosError::NotFound)?;
+    es::launch_title(title)
+}
@@ -0,0 +1,625 @@
+//! ``usb`` module of ``luma_core``.
+//!
+//! Contains a client for the USB host stack of IOS, through ``/dev/usb/ven`` on the IOS versions
+//! which have it and ``/dev/usb/oh0`` on the older ones, and a mass storage driver on top of it.
+//!
+//! Data gets transferred directly from and to the caller’s buffer when it sits in MEM2 aligned
+//! to a cache line, and through a bounce buffer in MEM2 otherwise.  Drivers only go through the
+//! [`Transport`] trait, so they can be driven by something else than the hardware.
+
+use crate::heap::{self, Arena};
+use crate::interrupt::Mutex;
+use crate::ios::{self, Aligned, Buffer, Handle, IosError, Mode, Pending};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec::Vec;
+use core::mem::ManuallyDrop;
+use core::slice;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+pub mod storage;
+
+pub use luma_formats::usb::scsi;
+
+const VEN: &str = "/dev/usb/ven";
+const OH0: &str = "/dev/usb/oh0";
+
+const VEN_IOCTL_GET_VERSION: u32 = 0;
+const VEN_IOCTL_GET_DEVICE_CHANGE: u32 = 1;
+const VEN_IOCTL_ATTACH_FINISH: u32 = 6;
+const VEN_IOCTL_SUSPEND_RESUME: u32 = 16;
+const VEN_IOCTL_CONTROL: u32 = 18;
+const VEN_IOCTL_BULK: u32 = 21;
+
+const OH0_IOCTL_CONTROL: u32 = 0;
+const OH0_IOCTL_BULK: u32 = 1;
+const OH0_IOCTL_GET_DEVICE_LIST: u32 = 12;
+
+/// Version of the interface ``/dev/usb/ven`` has to report for us to use it.
+const VEN_VERSION: u32 = 0x0005_0001;
+
+/// Most devices either host reports at once.
+const MAX_DEVICES: usize = 32;
+
+/// Size of a device entry of ``/dev/usb/ven`` and ``/dev/usb/oh0``.
+const VEN_ENTRY_SIZE: usize = 12;
+const OH0_ENTRY_SIZE: usize = 8;
+
+/// Biggest transfer each host does at once, also the size of the bounce buffer.
+const VEN_MAX_TRANSFER: usize = 16 * 1024;
+const OH0_MAX_TRANSFER: usize = 4 * 1024;
+
+/// Standard requests, as in the USB specification.
+const REQUEST_CLEAR_FEATURE: u8 = 0x01;
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const REQUEST_GET_CONFIGURATION: u8 = 0x08;
+const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+
+const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+const DESCRIPTOR_INTERFACE: u8 = 0x04;
+const DESCRIPTOR_ENDPOINT: u8 = 0x05;
+
+/// Bits of ``bmRequestType``.
+pub const REQUEST_IN: u8 = 0x80;
+pub const REQUEST_CLASS: u8 = 0x20;
+pub const RECIPIENT_INTERFACE: u8 = 0x01;
+pub const RECIPIENT_ENDPOINT: u8 = 0x02;
+
+/// Bit of an endpoint address set for those sending data to the host.
+pub const ENDPOINT_IN: u8 = 0x80;
+
+/// Transfer type of bulk endpoints.
+pub const TRANSFER_BULK: u8 = 0x02;
+
+/// The way requests reach a USB device.
+pub trait Transport {
+    /// Send a request to the control endpoint, with ``data`` going the way ``request_type``
+    /// tells, and return how many bytes got transferred.
+    fn control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &mut [u8],
+    ) -> Result<usize, IosError>;
+
+    /// Receive up to ``data.len()`` bytes from a bulk endpoint, returning how many arrived.
+    fn bulk_in(&mut self, endpoint: u8, data: &mut [u8]) -> Result<usize, IosError>;
+
+    /// Send ``data`` to a bulk endpoint, returning how many bytes got sent.
+    fn bulk_out(&mut self, endpoint: u8, data: &[u8]) -> Result<usize, IosError>;
+
+    /// Clear the halt of an endpoint which stalled.
+    fn clear_halt(&mut self, endpoint: u8) -> Result<(), IosError> {
+        self.control(
+            RECIPIENT_ENDPOINT,
+            REQUEST_CLEAR_FEATURE,
+            FEATURE_ENDPOINT_HALT,
+            endpoint as u16,
+            &mut [],
+        )?;
+        Ok(())
+    }
+}
+
+/// An endpoint of an interface, as described by the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    /// The number of the endpoint, with [`ENDPOINT_IN`] set if it sends data to the host.
+    pub address: u8,
+    /// The transfer type in the low two bits, such as [`TRANSFER_BULK`].
+    pub attributes: u8,
+    pub max_packet_size: u16,
+}
+
+impl Endpoint {
+    /// Whether this is a bulk endpoint.
+    pub fn is_bulk(&self) -> bool {
+        self.attributes & 0x03 == TRANSFER_BULK
+    }
+
+    /// Whether this endpoint sends data to the host.
+    pub fn is_in(&self) -> bool {
+        self.address & ENDPOINT_IN != 0
+    }
+}
+
+/// An interface of a device, in its first alternate setting, as described by the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub number: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<Endpoint>,
+}
+
+/// Get the interfaces listed in a whole configuration descriptor, only keeping their first
+/// alternate setting.
+pub fn parse_interfaces(configuration: &[u8]) -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut skipping = false;
+    let mut rest = configuration;
+    while rest.len() >= 2 {
+        let length = rest[0] as usize;
+        if length < 2 || length > rest.len() {
+            break;
+        }
+        let descriptor = &rest[..length];
+        rest = &rest[length..];
+        match descriptor[1] {
+            DESCRIPTOR_INTERFACE if length >= 9 => {
+                skipping = descriptor[3] != 0;
+                if !skipping {
+                    interfaces.push(Interface {
+                        number: descriptor[2],
+                        class: descriptor[5],
+                        subclass: descriptor[6],
+                        protocol: descriptor[7],
+                        endpoints: Vec::new(),
+                    });
+                }
+            }
+            DESCRIPTOR_ENDPOINT if length >= 7 && !skipping => {
+                if let Some(interface) = interfaces.last_mut() {
+                    interface.endpoints.push(Endpoint {
+                        address: descriptor[2],
+                        attributes: descriptor[3],
+                        max_packet_size: u16::from_le_bytes([descriptor[4], descriptor[5]]),
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+    interfaces
+}
+
+/// Which host a device sits behind, and how it is known there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Id {
+    /// A single interface of a device, known by the identifier ``/dev/usb/ven`` gave it.
+    Ven { id: i32, interface: u8 },
+    /// A whole device, opened by its vendor and product.
+    Oh0,
+}
+
+/// A device plugged in, as reported by [`devices`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub vendor: u16,
+    pub product: u16,
+    id: Id,
+}
+
+impl DeviceInfo {
+    /// Open the device to talk to it.
+    pub fn open(&self) -> Result<Device, IosError> {
+        Device::open(*self)
+    }
+}
+
+/// The buffers of the device change request of ``/dev/usb/ven``.
+#[repr(C, align(32))]
+struct ChangeBuffers {
+    entries: [u8; MAX_DEVICES * VEN_ENTRY_SIZE],
+}
+
+/// The state kept about ``/dev/usb/ven``, which only reports devices when they change.
+struct Ven {
+    handle: Handle,
+    /// Never freed, IOS may still write to it once we stopped waiting.
+    buffers: &'static mut ChangeBuffers,
+    change: Option<Pending>,
+    devices: Vec<DeviceInfo>,
+}
+
+enum Host {
+    Ven(Ven),
+    Oh0(Handle),
+}
+
+static HOST: Mutex<Option<Host>> = Mutex::new(None);
+
+impl Ven {
+    fn open() -> Result<Ven, IosError> {
+        let handle = Handle::open(VEN, Mode::None)?;
+        let mut version = Aligned([0; 32]);
+        handle.ioctl(VEN_IOCTL_GET_VERSION, &[], &mut version.0)?;
+        if u32::from_be_bytes(version.0[..4].try_into().unwrap()) != VEN_VERSION {
+            return Err(IosError::NotFound);
+        }
+        let buffers = Box::leak(Box::new(ChangeBuffers {
+            entries: [0; MAX_DEVICES * VEN_ENTRY_SIZE],
+        }));
+        let mut ven = Ven {
+            handle,
+            buffers,
+            change: None,
+            devices: Vec::new(),
+        };
+        // The first request completes right away, with the devices already plugged in.
+        ven.submit()?;
+        let count = ven.change.take().unwrap().wait()?;
+        ven.changed(count)?;
+        Ok(ven)
+    }
+
+    /// Ask to be told about the next change of devices.
+    fn submit(&mut self) -> Result<(), IosError> {
+        let pending = unsafe {
+            self.handle.ioctl_async(
+                VEN_IOCTL_GET_DEVICE_CHANGE,
+                &[],
+                &mut self.buffers.entries,
+                None,
+            )
+        }?;
+        self.change = Some(pending);
+        Ok(())
+    }
+
+    /// Take in the ``count`` devices now plugged in, and wait for the next change.
+    fn changed(&mut self, count: i32) -> Result<(), IosError> {
+        ios::invalidate(&self.buffers.entries);
+        self.devices = self
+            .buffers
+            .entries
+            .chunks_exact(VEN_ENTRY_SIZE)
+            .take(count as usize)
+            .map(|entry| DeviceInfo {
+                vendor: u16::from_be_bytes([entry[4], entry[5]]),
+                product: u16::from_be_bytes([entry[6], entry[7]]),
+                id: Id::Ven {
+                    id: i32::from_be_bytes(entry[0..4].try_into().unwrap()),
+                    interface: entry[10],
+                },
+            })
+            .collect();
+        self.handle.ioctl(VEN_IOCTL_ATTACH_FINISH, &[], &mut [])?;
+        self.submit()
+    }
+
+    fn update(&mut self) -> Result<(), IosError> {
+        let result = match &self.change {
+            Some(pending) => pending.poll(),
+            None => return self.submit(),
+        };
+        match result {
+            Some(result) => {
+                self.change = None;
+                self.changed(result?)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn oh0_devices(handle: &Handle, class: u8) -> Result<Vec<DeviceInfo>, IosError> {
+    let mut count = Buffer::new(1);
+    let mut entries = Buffer::new(MAX_DEVICES * OH0_ENTRY_SIZE);
+    handle.ioctlv(
+        OH0_IOCTL_GET_DEVICE_LIST,
+        &[&[MAX_DEVICES as u8], &[class]],
+        &mut [&mut count, &mut entries],
+    )?;
+    Ok(entries
+        .chunks_exact(OH0_ENTRY_SIZE)
+        .take(count[0] as usize)
+        .map(|entry| DeviceInfo {
+            vendor: u16::from_be_bytes([entry[4], entry[5]]),
+            product: u16::from_be_bytes([entry[6], entry[7]]),
+            id: Id::Oh0,
+        })
+        .collect())
+}
+
+/// Get the devices plugged in which have an interface of ``class``.
+///
+/// ``/dev/usb/ven`` reports each interface of every device whatever its class, so devices of
+/// another class may be listed too, [`Device::interfaces`] tells them apart.
+pub fn devices(class: u8) -> Result<Vec<DeviceInfo>, IosError> {
+    HOST.lock(|host| {
+        if host.is_none() {
+            *host = Some(match Ven::open() {
+                Ok(ven) => Host::Ven(ven),
+                Err(_) => Host::Oh0(Handle::open(OH0, Mode::None)?),
+            });
+        }
+        match host.as_mut().unwrap() {
+            Host::Ven(ven) => {
+                ven.update()?;
+                Ok(ven.devices.clone())
+            }
+            Host::Oh0(handle) => oh0_devices(handle, class),
+        }
+    })
+}
+
+/// A bounce buffer given back by a closed device, for the next one to use.
+static SPARE_BOUNCE: AtomicPtr<u8> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Size of every bounce buffer, enough for the biggest transfer of either host.
+const BOUNCE_SIZE: usize = VEN_MAX_TRANSFER;
+
+enum Channel {
+    /// The descriptor of ``/dev/usb/ven`` shared by every device, which isn’t ours to close.
+    Ven {
+        handle: ManuallyDrop<Handle>,
+        id: i32,
+    },
+    Oh0(Handle),
+}
+
+/// An open USB device, or a single interface of it behind ``/dev/usb/ven``.
+pub struct Device {
+    info: DeviceInfo,
+    channel: Channel,
+    /// The parameters of a ``/dev/usb/ven`` request.
+    message: Aligned<64>,
+    bounce: &'static mut [u8],
+}
+
+impl Device {
+    fn open(info: DeviceInfo) -> Result<Device, IosError> {
+        let channel = match info.id {
+            Id::Ven { id, .. } => {
+                let fd = HOST.lock(|host| match host {
+                    Some(Host::Ven(ven)) => Ok(ven.handle.fd()),
+                    _ => Err(IosError::NotFound),
+                })?;
+                Channel::Ven {
+                    handle: ManuallyDrop::new(unsafe { Handle::from_raw(fd) }),
+                    id,
+                }
+            }
+            Id::Oh0 => {
+                let path = format!("{OH0}/{:x}/{:x}", info.vendor, info.product);
+                Channel::Oh0(Handle::open(&path, Mode::None)?)
+            }
+        };
+
+        let mut bounce = SPARE_BOUNCE.swap(core::ptr::null_mut(), Ordering::AcqRel);
+        if bounce.is_null() {
+            bounce = heap::take(Arena::Mem2, BOUNCE_SIZE);
+        }
+        let bounce = unsafe { slice::from_raw_parts_mut(bounce, BOUNCE_SIZE) };
+
+        let mut device = Device {
+            info,
+            channel,
+            message: Aligned([0; 64]),
+            bounce,
+        };
+        // Devices behind ``/dev/usb/ven`` start suspended.
+        device.suspend_resume(true)?;
+        device.configure()?;
+        Ok(device)
+    }
+
+    fn suspend_resume(&mut self, resume: bool) -> Result<(), IosError> {
+        if let Channel::Ven { handle, id } = &self.channel {
+            let mut input = Aligned([0; 32]);
+            input.0[0..4].copy_from_slice(&id.to_be_bytes());
+            input.0[8..12].copy_from_slice(&(resume as u32).to_be_bytes());
+            handle.ioctl(VEN_IOCTL_SUSPEND_RESUME, &input.0, &mut [])?;
+        }
+        Ok(())
+    }
+
+    /// Select the first configuration, unless the device already is configured.
+    fn configure(&mut self) -> Result<(), IosError> {
+        let mut configuration = [0];
+        self.control(
+            REQUEST_IN,
+            REQUEST_GET_CONFIGURATION,
+            0,
+            0,
+            &mut configuration,
+        )?;
+        if configuration[0] == 0 {
+            let mut header = [0; 9];
+            self.get_configuration_descriptor(&mut header)?;
+            let value = header[5] as u16;
+            self.control(0, REQUEST_SET_CONFIGURATION, value, 0, &mut [])?;
+        }
+        Ok(())
+    }
+
+    fn get_configuration_descriptor(&mut self, data: &mut [u8]) -> Result<usize, IosError> {
+        let value = (DESCRIPTOR_CONFIGURATION as u16) << 8;
+        self.control(REQUEST_IN, REQUEST_GET_DESCRIPTOR, value, 0, data)
+    }
+
+    /// Get the vendor and product identifiers of the device.
+    pub fn info(&self) -> DeviceInfo {
+        self.info
+    }
+
+    /// Get the interfaces of the first configuration which can be used through this device.
+    pub fn interfaces(&mut self) -> Result<Vec<Interface>, IosError> {
+        let mut header = [0; 9];
+        self.get_configuration_descriptor(&mut header)?;
+        let mut configuration = alloc::vec![0; u16::from_le_bytes([header[2], header[3]]) as usize];
+        let length = self.get_configuration_descriptor(&mut configuration)?;
+        let mut interfaces = parse_interfaces(&configuration[..length]);
+        if let Id::Ven { interface, .. } = self.info.id {
+            interfaces.retain(|candidate| candidate.number == interface);
+        }
+        Ok(interfaces)
+    }
+
+    fn max_transfer(&self) -> usize {
+        match self.channel {
+            Channel::Ven { .. } => VEN_MAX_TRANSFER,
+            Channel::Oh0(_) => OH0_MAX_TRANSFER,
+        }
+    }
+
+    /// Do a single control transfer from or to ``data``, which has to be DMA-safe.
+    fn control_direct(
+        &mut self,
+        setup: [u8; 8],
+        data: &mut [u8],
+        input: bool,
+    ) -> Result<usize, IosError> {
+        let result = match &self.channel {
+            Channel::Ven { handle, id } => {
+                let message = &mut self.message.0;
+                message.fill(0);
+                message[0..4].copy_from_slice(&id.to_be_bytes());
+                message[8..16].copy_from_slice(&setup);
+                message[16..20].copy_from_slice(&(data.as_ptr() as u32).to_be_bytes());
+                if input {
+                    handle.ioctlv(VEN_IOCTL_CONTROL, &[message], &mut [data])
+                } else {
+                    handle.ioctlv::<[u8]>(VEN_IOCTL_CONTROL, &[message, data], &mut [])
+                }
+            }
+            // The data always goes in the output vector, whichever way it travels.
+            Channel::Oh0(handle) => handle.ioctlv(
+                OH0_IOCTL_CONTROL,
+                &[
+                    &setup[0..1],
+                    &setup[1..2],
+                    &setup[2..4],
+                    &setup[4..6],
+                    &setup[6..8],
+                    &[0],
+                ],
+                &mut [data],
+            ),
+        }?;
+        Ok(result as usize)
+    }
+
+    /// Do a single bulk transfer from or to ``data``, which has to be DMA-safe.
+    fn bulk_direct(&mut self, endpoint: u8, data: &mut [u8]) -> Result<usize, IosError> {
+        let length = data.len() as u16;
+        let result = match &self.channel {
+            Channel::Ven { handle, id } => {
+                let message = &mut self.message.0;
+                message.fill(0);
+                message[0..4].copy_from_slice(&id.to_be_bytes());
+                message[8..12].copy_from_slice(&(data.as_ptr() as u32).to_be_bytes());
+                message[12..14].copy_from_slice(&length.to_be_bytes());
+                message[18] = endpoint;
+                if endpoint & ENDPOINT_IN != 0 {
+                    handle.ioctlv(VEN_IOCTL_BULK, &[message], &mut [data])
+                } else {
+                    handle.ioctlv::<[u8]>(VEN_IOCTL_BULK, &[message, data], &mut [])
+                }
+            }
+            Channel::Oh0(handle) => handle.ioctlv(
+                OH0_IOCTL_BULK,
+                &[&[endpoint], &length.to_be_bytes()],
+                &mut [data],
+            ),
+        }?;
+        Ok(result as usize)
+    }
+}
+
+impl Transport for Device {
+    fn control(
+        &mut self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &mut [u8],
+    ) -> Result<usize, IosError> {
+        if data.len() > self.max_transfer() {
+            return Err(IosError::Invalid);
+        }
+        // The setup packet is little-endian, as on the wire.
+        let mut setup = [request_type, request, 0, 0, 0, 0, 0, 0];
+        setup[2..4].copy_from_slice(&value.to_le_bytes());
+        setup[4..6].copy_from_slice(&index.to_le_bytes());
+        setup[6..8].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        let input = request_type & REQUEST_IN != 0;
+
+        let bounce = core::mem::take(&mut self.bounce);
+        let length = data.len();
+        if !input {
+            bounce[..length].copy_from_slice(data);
+        }
+        let result = self.control_direct(setup, &mut bounce[..length], input);
+        if let Ok(transferred) = result
+            && input
+        {
+            let transferred = transferred.min(length);
+            data[..transferred].copy_from_slice(&bounce[..transferred]);
+        }
+        self.bounce = bounce;
+        result
+    }
+
+    fn bulk_in(&mut self, endpoint: u8, data: &mut [u8]) -> Result<usize, IosError> {
+        let max_transfer = self.max_transfer();
+        let mut done = 0;
+        let total = data.len();
+        while done < total {
+            let chunk = &mut data[done..(done + max_transfer).min(total)];
+            let length = chunk.len();
+            let received = if ios::is_dma_safe(chunk) {
+                self.bulk_direct(endpoint, chunk)?
+            } else {
+                let bounce = core::mem::take(&mut self.bounce);
+                let result = self.bulk_direct(endpoint, &mut bounce[..length]);
+                if let Ok(received) = result {
+                    let received = received.min(length);
+                    chunk[..received].copy_from_slice(&bounce[..received]);
+                }
+                self.bounce = bounce;
+                result?
+            };
+            done += received.min(length);
+            // A short packet ends the transfer.
+            if received < length {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    /// Data always goes through the bounce buffer, the host only needs to read it.
+    fn bulk_out(&mut self, endpoint: u8, data: &[u8]) -> Result<usize, IosError> {
+        let bounce = core::mem::take(&mut self.bounce);
+        let mut result = Ok(0);
+        for chunk in data.chunks(self.max_transfer()) {
+            bounce[..chunk.len()].copy_from_slice(chunk);
+            match self.bulk_direct(endpoint, &mut bounce[..chunk.len()]) {
+                Ok(sent) => {
+                    result = result.map(|done| done + sent);
+                    if sent < chunk.len() {
+                        break;
+                    }
+                }
+                Err(error) => {
+                    result = Err(error);
+                    break;
+                }
+            }
+        }
+        self.bounce = bounce;
+        result
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let _ = self.suspend_resume(false);
+        let bounce = core::mem::take(&mut self.bounce);
+        // Should there be one spare already, this one is lost for good.
+        let _ = SPARE_BOUNCE.compare_exchange(
+            core::ptr::null_mut(),
+            bounce.as_mut_ptr(),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+}
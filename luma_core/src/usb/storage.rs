@@ -0,0 +1,385 @@
+//! ``storage`` module of ``luma_core::usb``.
+//!
+//! Contains a driver for USB mass storage devices speaking SCSI over the bulk-only transport,
+//! such as flash drives and hard drives, exposed as a [`BlockDevice`].
+
+use super::scsi::{self, Capacity, Command, CommandStatus, Direction, Inquiry, Sense, Status};
+use super::{Device, Interface, RECIPIENT_INTERFACE, REQUEST_CLASS, REQUEST_IN, Transport};
+use crate::block::BlockDevice;
+use crate::ios::IosError;
+
+/// Interface class, subclass and protocol of SCSI devices using the bulk-only transport.
+pub const CLASS_MASS_STORAGE: u8 = 0x08;
+pub const SUBCLASS_SCSI: u8 = 0x06;
+pub const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+/// Class requests of the bulk-only transport.
+const REQUEST_RESET: u8 = 0xff;
+const REQUEST_GET_MAX_LUN: u8 = 0xfe;
+
+/// Biggest amount of data a single READ(10) or WRITE(10) command transfers.
+const MAX_COMMAND_SIZE: usize = 64 * 1024;
+
+/// Amount of TEST UNIT READY commands sent before giving up on a medium getting ready, which
+/// may take a few seconds for hard drives to spin up.
+const READY_RETRIES: u32 = 1000;
+
+/// Errors which can be encountered while using a mass storage device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageError {
+    /// No mass storage device is plugged in.
+    NoDevice,
+    /// The device isn’t a SCSI disk, or one bigger than READ(10) can address.
+    Unsupported,
+    /// There is no medium, or it didn’t get ready in time.
+    NotReady,
+    /// The medium is write protected.
+    ReadOnly,
+    /// The blocks asked for go past the end of the medium.
+    OutOfRange,
+    /// The device failed a command for the reason given.
+    Sense(Sense),
+    /// The device didn’t follow the bulk-only transport, and got reset.
+    Protocol,
+    /// IOS rejected a request, or the transfer failed.
+    Ios(IosError),
+}
+
+impl From<IosError> for StorageError {
+    fn from(error: IosError) -> StorageError {
+        StorageError::Ios(error)
+    }
+}
+
+/// The data of a command, going whichever way it does.
+enum Data<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+
+/// A mass storage device, or rather the first logical unit of it with a medium in.
+pub struct MassStorage<T: Transport = Device> {
+    transport: T,
+    interface: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+    lun: u8,
+    tag: u32,
+    inquiry: Inquiry,
+    block_size: usize,
+    blocks: u64,
+    read_only: bool,
+}
+
+impl MassStorage<Device> {
+    /// Open the first mass storage device plugged in with a medium in.
+    pub fn open() -> Result<MassStorage<Device>, StorageError> {
+        let mut error = StorageError::NoDevice;
+        for info in super::devices(CLASS_MASS_STORAGE)? {
+            let Ok(mut device) = info.open() else {
+                continue;
+            };
+            let Ok(interfaces) = device.interfaces() else {
+                continue;
+            };
+            if let Some(interface) = interfaces.iter().find(|&interface| is_supported(interface)) {
+                match MassStorage::new(device, interface) {
+                    Ok(storage) => return Ok(storage),
+                    Err(failure) => error = failure,
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+/// Whether ``interface`` is one of a device this driver handles.
+pub fn is_supported(interface: &Interface) -> bool {
+    interface.class == CLASS_MASS_STORAGE
+        && interface.subclass == SUBCLASS_SCSI
+        && interface.protocol == PROTOCOL_BULK_ONLY
+}
+
+impl<T: Transport> MassStorage<T> {
+    /// Start using ``interface`` of the device behind ``transport``, and get the first of its
+    /// logical units with a medium in ready to be read and written.
+    pub fn new(transport: T, interface: &Interface) -> Result<MassStorage<T>, StorageError> {
+        if !is_supported(interface) {
+            return Err(StorageError::Unsupported);
+        }
+        let bulk = |input: bool| {
+            interface
+                .endpoints
+                .iter()
+                .find(|endpoint| endpoint.is_bulk() && endpoint.is_in() == input)
+                .map(|endpoint| endpoint.address)
+                .ok_or(StorageError::Unsupported)
+        };
+        let mut storage = MassStorage {
+            transport,
+            interface: interface.number,
+            endpoint_in: bulk(true)?,
+            endpoint_out: bulk(false)?,
+            lun: 0,
+            tag: 0,
+            inquiry: Inquiry::parse(&[0; scsi::INQUIRY_SIZE]).unwrap(),
+            block_size: 0,
+            blocks: 0,
+            read_only: false,
+        };
+
+        let mut error = StorageError::NotReady;
+        for lun in 0..=storage.max_lun() {
+            storage.lun = lun;
+            match storage.init() {
+                Ok(()) => return Ok(storage),
+                Err(failure) => error = failure,
+            }
+        }
+        Err(error)
+    }
+
+    /// Get the highest logical unit number, devices with a single one being allowed to stall.
+    fn max_lun(&mut self) -> u8 {
+        let mut max_lun = [0];
+        let request_type = REQUEST_IN | REQUEST_CLASS | RECIPIENT_INTERFACE;
+        let index = self.interface as u16;
+        match self
+            .transport
+            .control(request_type, REQUEST_GET_MAX_LUN, 0, index, &mut max_lun)
+        {
+            Ok(1) => max_lun[0].min(15),
+            _ => 0,
+        }
+    }
+
+    fn init(&mut self) -> Result<(), StorageError> {
+        let mut data = [0; scsi::INQUIRY_SIZE];
+        self.execute(&Command::inquiry(), Data::In(&mut data))?;
+        self.inquiry = Inquiry::parse(&data).ok_or(StorageError::Protocol)?;
+        if !self.inquiry.is_block_device() {
+            return Err(StorageError::Unsupported);
+        }
+
+        self.wait_ready()?;
+
+        let mut data = [0; scsi::CAPACITY_SIZE];
+        self.execute(&Command::read_capacity(), Data::In(&mut data))?;
+        let capacity = Capacity::parse(&data).ok_or(StorageError::Protocol)?;
+        if capacity.last_block == u32::MAX
+            || !capacity.block_size.is_power_of_two()
+            || !(512..=MAX_COMMAND_SIZE as u32).contains(&capacity.block_size)
+        {
+            return Err(StorageError::Unsupported);
+        }
+        self.block_size = capacity.block_size as usize;
+        self.blocks = capacity.blocks();
+
+        // Not every device implements it, those which don’t are taken to be writable.
+        let mut header = [0; scsi::MODE_SENSE_SIZE];
+        if self
+            .execute(&Command::mode_sense(), Data::In(&mut header))
+            .is_ok()
+        {
+            self.read_only = scsi::is_write_protected(&header).unwrap_or(false);
+        }
+        Ok(())
+    }
+
+    /// Wait for the medium to be ready, which also consumes the unit attention devices report
+    /// after having been reset or had their medium changed.
+    fn wait_ready(&mut self) -> Result<(), StorageError> {
+        for _ in 0..READY_RETRIES {
+            match self.execute(&Command::test_unit_ready(), Data::None) {
+                Ok(()) => return Ok(()),
+                Err(StorageError::NotReady) => (),
+                Err(StorageError::Sense(sense)) if sense.key == scsi::SENSE_UNIT_ATTENTION => (),
+                Err(error) => return Err(error),
+            }
+        }
+        Err(StorageError::NotReady)
+    }
+
+    /// Get what the device says about itself.
+    pub fn inquiry(&self) -> &Inquiry {
+        &self.inquiry
+    }
+
+    /// Get the logical unit in use.
+    pub fn lun(&self) -> u8 {
+        self.lun
+    }
+
+    /// Give back the transport.
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    /// Run ``command``, and ask why if it failed.
+    fn execute(&mut self, command: &Command, data: Data) -> Result<(), StorageError> {
+        match self.transfer(command, data)? {
+            Status::Passed => Ok(()),
+            Status::Failed => {
+                let mut data = [0; scsi::SENSE_SIZE];
+                if self.transfer(&Command::request_sense(), Data::In(&mut data))? != Status::Passed
+                {
+                    return Err(StorageError::Protocol);
+                }
+                let sense = Sense::parse(&data).ok_or(StorageError::Protocol)?;
+                Err(match sense.key {
+                    scsi::SENSE_NOT_READY => StorageError::NotReady,
+                    scsi::SENSE_DATA_PROTECT => StorageError::ReadOnly,
+                    _ => StorageError::Sense(sense),
+                })
+            }
+            Status::PhaseError => {
+                self.reset_recovery();
+                Err(StorageError::Protocol)
+            }
+        }
+    }
+
+    /// Send ``command``, transfer its data, and get its status, as the bulk-only transport
+    /// does.
+    fn transfer(&mut self, command: &Command, data: Data) -> Result<Status, StorageError> {
+        let length = match &data {
+            Data::None => 0,
+            Data::In(data) => data.len(),
+            Data::Out(data) => data.len(),
+        };
+        let direction = match data {
+            Data::None => Direction::None,
+            Data::In(_) => Direction::In,
+            Data::Out(_) => Direction::Out,
+        };
+        assert!(
+            command.transfer_length() as usize == length && command.direction() == direction,
+            "SCSI command data mismatch"
+        );
+
+        self.tag = self.tag.wrapping_add(1);
+        let block = command.wrap(self.tag, self.lun);
+        if self.transport.bulk_out(self.endpoint_out, &block) != Ok(block.len()) {
+            self.reset_recovery();
+            return Err(StorageError::Protocol);
+        }
+
+        // A stalled data stage still gets a status, once the endpoint got cleared.
+        let result = match data {
+            Data::None => Ok(0),
+            Data::In(data) => self.transport.bulk_in(self.endpoint_in, data),
+            Data::Out(data) => self.transport.bulk_out(self.endpoint_out, data),
+        };
+        if result.is_err() {
+            let endpoint = match direction {
+                Direction::Out => self.endpoint_out,
+                _ => self.endpoint_in,
+            };
+            self.transport.clear_halt(endpoint)?;
+        }
+
+        let status = self.receive_status()?;
+        if status.tag != self.tag {
+            self.reset_recovery();
+            return Err(StorageError::Protocol);
+        }
+        // A failed command tells why through its sense data rather than the transfer error.
+        if status.status == Status::Passed {
+            result?;
+        }
+        Ok(status.status)
+    }
+
+    /// Receive a status wrapper, trying again once should the endpoint have stalled.
+    fn receive_status(&mut self) -> Result<CommandStatus, StorageError> {
+        let mut data = [0; scsi::CSW_SIZE];
+        let mut result = self.transport.bulk_in(self.endpoint_in, &mut data);
+        if result.is_err() {
+            self.transport.clear_halt(self.endpoint_in)?;
+            result = self.transport.bulk_in(self.endpoint_in, &mut data);
+        }
+        let status = match result {
+            Ok(length) => CommandStatus::parse(&data[..length]),
+            Err(_) => None,
+        };
+        status.ok_or_else(|| {
+            self.reset_recovery();
+            StorageError::Protocol
+        })
+    }
+
+    /// Get the device back to a known state after it broke the protocol.
+    fn reset_recovery(&mut self) {
+        let request_type = REQUEST_CLASS | RECIPIENT_INTERFACE;
+        let index = self.interface as u16;
+        // Nothing more can be done if this fails, the next command will fail as well.
+        let _ = self
+            .transport
+            .control(request_type, REQUEST_RESET, 0, index, &mut []);
+        let _ = self.transport.clear_halt(self.endpoint_in);
+        let _ = self.transport.clear_halt(self.endpoint_out);
+    }
+
+    fn check_range(&self, block: u64, length: usize) -> Result<(), StorageError> {
+        assert!(
+            length.is_multiple_of(self.block_size),
+            "USB buffer isn’t a whole amount of blocks"
+        );
+        if block + (length / self.block_size) as u64 > self.blocks {
+            return Err(StorageError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Get the command transferring ``length`` bytes starting at ``block``.
+    fn command(&self, write: bool, block: u64, length: usize) -> Command {
+        let count = (length / self.block_size) as u16;
+        if write {
+            Command::write(block as u32, count, self.block_size as u32)
+        } else {
+            Command::read(block as u32, count, self.block_size as u32)
+        }
+    }
+}
+
+impl<T: Transport> BlockDevice for MassStorage<T> {
+    type Error = StorageError;
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), StorageError> {
+        self.check_range(block, buffer.len())?;
+        let size = MAX_COMMAND_SIZE / self.block_size * self.block_size;
+        for (i, chunk) in buffer.chunks_mut(size).enumerate() {
+            let start = block + (i * size / self.block_size) as u64;
+            let command = self.command(false, start, chunk.len());
+            self.execute(&command, Data::In(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<(), StorageError> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        self.check_range(block, buffer.len())?;
+        let size = MAX_COMMAND_SIZE / self.block_size * self.block_size;
+        for (i, chunk) in buffer.chunks(size).enumerate() {
+            let start = block + (i * size / self.block_size) as u64;
+            let command = self.command(true, start, chunk.len());
+            self.execute(&command, Data::Out(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
@@ -2,25 +2,28 @@
 //!
 //! Contains functions for basic video access.
 
-use crate::allocate::alloc_aligned;
+use crate::allocate::DmaBuffer;
+use crate::heap::Arena;
 use crate::io::{read16, write16, write32};
-use alloc::boxed::Box;
-use core::pin::Pin;
+use crate::mem::PhysAddr;
 use core::slice::{Chunks, ChunksMut};
 
 /// A struct representing the eXternal FrameBuffer, or XFB.  It represents the image that will be
 /// sent to the screen, in YUYV format.  It must be allocated as contiguous physical memory.
+///
+/// The VI reads it straight from memory, so what gets drawn through the cache only shows up once
+/// [`Xfb::flush`] has been called.
 pub struct Xfb {
-    data: Pin<Box<[u16]>>,
+    data: DmaBuffer<u16, Arena>,
     width: usize,
     height: usize,
 }
 
 impl Xfb {
-    /// Allocate an XFB with the given width and height.
+    /// Allocate an XFB with the given width and height in MEM1.
     pub fn allocate(width: usize, height: usize) -> Xfb {
         let stride = width;
-        let data = alloc_aligned(stride * height);
+        let data = DmaBuffer::new_in(stride * height, Arena::Mem1);
         Xfb {
             data,
             width,
@@ -61,6 +64,12 @@ impl Xfb {
         self.data.chunks_mut(stride)
     }
 
+    /// Hand this XFB over to the VI, making what got drawn so far visible, and get its physical
+    /// address.
+    pub fn flush(&mut self) -> PhysAddr {
+        self.data.give_to_device()
+    }
+
     /// Return the raw pointer to this XFB.
     pub fn as_ptr(&self) -> *const u16 {
         self.data.as_ptr()
@@ -72,7 +81,7 @@ impl Xfb {
     }
 }
 
-const BASE: u32 = 0xcc00_2000;
+const BASE: PhysAddr = PhysAddr::new(0x0c00_2000);
 
 bitflags::bitflags! {
     pub struct ConfigureFlags: u16 {
@@ -136,10 +145,9 @@ unsafe fn set_burst_blanking_interval_2(be2: u32, bs2: u32, be4: u32, bs4: u32)
     write32(BASE + 0x18, (be4 << 21) | (bs4 << 16) | (be2 << 5) | bs2);
 }
 
-unsafe fn set_xfb(addr: u32, xfb: &Xfb, bottom: bool) {
+unsafe fn set_xfb(addr: PhysAddr, xfb: &mut Xfb, bottom: bool) {
     let stride = xfb.stride_in_u8() as u32;
-    let xfb = xfb.as_ptr();
-    let mut xfb = xfb as u32;
+    let mut xfb = xfb.flush().value();
     let shift;
     if bottom {
         xfb += stride;
@@ -153,22 +161,22 @@ unsafe fn set_xfb(addr: u32, xfb: &Xfb, bottom: bool) {
     write32(addr, ((shift as u32) << 28) | xfb);
 }
 
-unsafe fn set_top_xfb(xfb: &Xfb) {
+unsafe fn set_top_xfb(xfb: &mut Xfb) {
     unsafe { set_xfb(BASE + 0x1c, xfb, false) };
 }
 
-unsafe fn set_bottom_xfb(xfb: &Xfb) {
+unsafe fn set_bottom_xfb(xfb: &mut Xfb) {
     unsafe { set_xfb(BASE + 0x24, xfb, true) };
 }
 
 /*
 /// Used for stereoscopy.
-unsafe fn set_top_right_xfb(xfb: &Xfb) {
+unsafe fn set_top_right_xfb(xfb: &mut Xfb) {
     set_xfb(BASE + 0x20, xfb);
 }
 
 /// Used for stereoscopy.
-unsafe fn set_bottom_right_xfb(xfb: &Xfb) {
+unsafe fn set_bottom_right_xfb(xfb: &mut Xfb) {
     set_xfb(BASE + 0x28, xfb);
 }
 */
@@ -218,7 +226,7 @@ unsafe fn set_border() {
     write16(BASE + 0x74, 0x0000);
 }
 
-unsafe fn setup_interlaced(width: usize, height: usize, xfb: &Xfb) {
+unsafe fn setup_interlaced(width: usize, height: usize, xfb: &mut Xfb) {
     unsafe {
         set_vertical_timing(height as u16, 6);
         configure(ConfigureFlags::PAL | ConfigureFlags::INTERLACED | ConfigureFlags::ENABLE);
@@ -246,8 +254,8 @@ pub struct Vi {
 
 impl Vi {
     /// Setup the VI with the given XFB.
-    pub fn setup(xfb: Xfb) -> Vi {
-        unsafe { setup_interlaced(xfb.width(), xfb.height(), &xfb) };
+    pub fn setup(mut xfb: Xfb) -> Vi {
+        unsafe { setup_interlaced(xfb.width(), xfb.height(), &mut xfb) };
         Vi { xfb }
     }
 
@@ -0,0 +1,410 @@
+//! ``wpad`` module of ``luma_core``.
+//!
+//! Contains the input of the Wii Remotes, connected over the [`bluetooth`](crate::bluetooth)
+//! host.  Remotes registered with the console reconnect when any of their buttons gets pressed,
+//! new ones get paired by pressing their SYNC button after [`Wpad::start_pairing`].
+
+use crate::bluetooth::l2cap::PSM_HID_INTERRUPT;
+use crate::bluetooth::{self, Host, HostEvent, Oh1, Transport};
+use crate::ios::IosError;
+use crate::sysconf::SysConf;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+pub use luma_formats::wpad::report;
+
+use report::{Accel, Buttons, Extension, ExtensionKind, IrDot, Report};
+
+/// Most Wii Remotes connected at the same time.
+pub const MAX_REMOTES: usize = 4;
+
+/// Battery level reported by a remote with fresh batteries.
+const BATTERY_FULL: u8 = 0xc8;
+
+/// A register access, only one being in flight at a time.
+#[derive(Clone, Debug)]
+enum Operation {
+    Write(u32, Vec<u8>),
+    Read(u32, u16),
+    /// Ask for the data reports matching what got enabled, once the previous accesses are done.
+    SetMode,
+}
+
+/// A connected Wii Remote.
+pub struct Wiimote {
+    handle: u16,
+    /// Both HID channels are open, reports can be exchanged.
+    ready: bool,
+    rumble: bool,
+    ir: bool,
+    /// Mode the IR camera got started in, if it is.
+    ir_mode: Option<u8>,
+    operations: VecDeque<Operation>,
+    in_flight: bool,
+    buttons: Buttons,
+    previous: Buttons,
+    accel: Accel,
+    dots: [Option<IrDot>; 4],
+    extension_kind: Option<ExtensionKind>,
+    extension: Option<Extension>,
+    battery: u8,
+}
+
+impl Wiimote {
+    fn new(handle: u16) -> Wiimote {
+        Wiimote {
+            handle,
+            ready: false,
+            rumble: false,
+            ir: false,
+            ir_mode: None,
+            operations: VecDeque::new(),
+            in_flight: false,
+            buttons: Buttons::empty(),
+            previous: Buttons::empty(),
+            accel: Accel::default(),
+            dots: [None; 4],
+            extension_kind: None,
+            extension: None,
+            battery: 0,
+        }
+    }
+
+    /// Get the buttons currently held.
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Get the buttons which got pressed since the previous update.
+    pub fn pressed(&self) -> Buttons {
+        self.buttons.difference(self.previous)
+    }
+
+    /// Get the buttons which got released since the previous update.
+    pub fn released(&self) -> Buttons {
+        self.previous.difference(self.buttons)
+    }
+
+    /// Get the last state of the accelerometer.
+    pub fn accel(&self) -> Accel {
+        self.accel
+    }
+
+    /// Get the light sources seen by the IR camera, once enabled with [`Wpad::set_ir`].
+    pub fn ir(&self) -> &[Option<IrDot>; 4] {
+        &self.dots
+    }
+
+    /// Get the kind of the extension plugged in, once it got identified.
+    pub fn extension_kind(&self) -> Option<ExtensionKind> {
+        self.extension_kind
+    }
+
+    /// Get the last state of the extension, if a supported one is plugged in.
+    pub fn extension(&self) -> Option<Extension> {
+        self.extension
+    }
+
+    /// Get the battery level, from 0 to 100.
+    pub fn battery(&self) -> u8 {
+        (self.battery.min(BATTERY_FULL) as u16 * 100 / BATTERY_FULL as u16) as u8
+    }
+
+    /// Whether the rumble motor is running.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    fn mode(&self) -> u8 {
+        match (self.ir, self.extension_kind.is_some()) {
+            (true, true) => report::MODE_ACCEL_IR_EXTENSION,
+            (true, false) => report::MODE_ACCEL_IR,
+            (false, true) => report::MODE_ACCEL_EXTENSION,
+            (false, false) => report::MODE_ACCEL,
+        }
+    }
+
+    /// The IR camera only fits in basic mode next to extension data.
+    fn ir_mode(&self) -> u8 {
+        if self.extension_kind.is_some() {
+            report::IR_BASIC
+        } else {
+            report::IR_EXTENDED
+        }
+    }
+
+    /// Queue the accesses updating the reports, after the IR mode if it has to change.
+    fn queue_mode(&mut self) {
+        if self.ir && self.ir_mode != Some(self.ir_mode()) {
+            let mode = self.ir_mode();
+            self.ir_mode = Some(mode);
+            for (address, data) in report::ir_init(mode) {
+                self.operations
+                    .push_back(Operation::Write(address, data.to_vec()));
+            }
+        }
+        self.operations.push_back(Operation::SetMode);
+    }
+
+    /// Send the next accesses, until one needs to be waited for.
+    fn pump<T: Transport>(&mut self, host: &mut Host<T>) {
+        while !self.in_flight {
+            let Some(operation) = self.operations.pop_front() else {
+                break;
+            };
+            match operation {
+                Operation::Write(address, data) => {
+                    self.send(host, &report::write_registers(address, &data, self.rumble));
+                    self.in_flight = true;
+                }
+                Operation::Read(address, size) => {
+                    self.send(host, &report::read_registers(address, size, self.rumble));
+                    self.in_flight = true;
+                }
+                Operation::SetMode => {
+                    self.send(host, &report::set_mode(self.mode(), false, self.rumble));
+                }
+            }
+        }
+    }
+
+    fn send<T: Transport>(&self, host: &mut Host<T>, report: &[u8]) {
+        host.send(self.handle, PSM_HID_INTERRUPT, report);
+    }
+
+    fn receive<T: Transport>(&mut self, host: &mut Host<T>, report: Report) {
+        match report {
+            Report::Status {
+                buttons,
+                flags,
+                battery,
+            } => {
+                self.buttons = buttons;
+                self.battery = battery;
+                let plugged = flags & report::STATUS_EXTENSION != 0;
+                if plugged && self.extension_kind.is_none() {
+                    for (address, value) in report::EXTENSION_INIT {
+                        self.operations
+                            .push_back(Operation::Write(address, alloc::vec![value]));
+                    }
+                    self.operations
+                        .push_back(Operation::Read(report::EXTENSION_ID, 6));
+                } else {
+                    if !plugged {
+                        self.extension_kind = None;
+                        self.extension = None;
+                    }
+                    // A status report always stops the data reports until the mode gets set
+                    // again.
+                    self.queue_mode();
+                }
+            }
+            Report::Acknowledge {
+                buttons, report, ..
+            } => {
+                self.buttons = buttons;
+                if report == report::REPORT_WRITE {
+                    self.in_flight = false;
+                }
+            }
+            Report::ReadData {
+                buttons,
+                error,
+                address,
+                data,
+            } => {
+                self.buttons = buttons;
+                self.in_flight = false;
+                if address == report::EXTENSION_ID as u16 {
+                    self.extension_kind = match data.try_into() {
+                        Ok(id) if error == 0 => Some(ExtensionKind::identify(id)),
+                        _ => None,
+                    };
+                    self.queue_mode();
+                }
+            }
+            Report::Data {
+                buttons,
+                accel,
+                ir,
+                extension,
+            } => {
+                self.buttons = buttons;
+                if let Some(accel) = accel {
+                    self.accel = accel;
+                }
+                if !ir.is_empty() {
+                    self.dots = report::parse_ir(ir);
+                }
+                self.extension = self.extension_kind.and_then(|kind| kind.parse(extension));
+            }
+            Report::Other(_) => (),
+        }
+        self.pump(host);
+    }
+}
+
+/// The Wii Remotes, along with the Bluetooth host they are connected through.
+pub struct Wpad<T: Transport = Oh1> {
+    host: Host<T>,
+    remotes: [Option<Wiimote>; MAX_REMOTES],
+    events: Vec<HostEvent>,
+}
+
+impl Wpad<Oh1> {
+    /// Start the Bluetooth controller of the Wii, accepting the remotes registered in
+    /// ``sysconf`` on top of the ones it knows of.
+    pub fn new(sysconf: Option<&SysConf>) -> Result<Wpad<Oh1>, IosError> {
+        let devices = sysconf.map(bluetooth::registered_devices);
+        let host = Host::new()?.with_known_devices(devices.as_deref().unwrap_or(&[]));
+        Ok(Wpad::with_host(host))
+    }
+}
+
+impl<T: Transport> Wpad<T> {
+    /// Use an already created host.
+    pub fn with_host(host: Host<T>) -> Wpad<T> {
+        Wpad {
+            host,
+            remotes: [const { None }; MAX_REMOTES],
+            events: Vec::new(),
+        }
+    }
+
+    /// Get the Bluetooth host.
+    pub fn host(&mut self) -> &mut Host<T> {
+        &mut self.host
+    }
+
+    /// Handle what the remotes sent since the previous update, this must be called once per
+    /// frame.
+    pub fn update(&mut self) {
+        for remote in self.remotes.iter_mut().flatten() {
+            remote.previous = remote.buttons;
+        }
+
+        self.host.poll(&mut self.events);
+        let mut events = core::mem::take(&mut self.events);
+        for event in events.drain(..) {
+            self.handle(event);
+        }
+        self.events = events;
+    }
+
+    fn index(&self, handle: u16) -> Option<usize> {
+        self.remotes.iter().position(|remote| {
+            remote
+                .as_ref()
+                .is_some_and(|remote| remote.handle == handle)
+        })
+    }
+
+    fn handle(&mut self, event: HostEvent) {
+        match event {
+            HostEvent::Connected { handle, .. } => {
+                match self.remotes.iter_mut().find(|remote| remote.is_none()) {
+                    Some(slot) => *slot = Some(Wiimote::new(handle)),
+                    None => self.host.disconnect(handle),
+                }
+            }
+            HostEvent::Disconnected { handle } => {
+                if let Some(index) = self.index(handle) {
+                    self.remotes[index] = None;
+                }
+            }
+            HostEvent::ChannelOpened {
+                handle,
+                psm: PSM_HID_INTERRUPT,
+            } => {
+                let Some(index) = self.index(handle) else {
+                    return;
+                };
+                let Some(remote) = &mut self.remotes[index] else {
+                    return;
+                };
+                remote.ready = true;
+                remote.send(&mut self.host, &report::set_leds(1 << index, remote.rumble));
+                remote.send(&mut self.host, &report::request_status(remote.rumble));
+            }
+            HostEvent::ChannelClosed {
+                handle,
+                psm: PSM_HID_INTERRUPT,
+            } => self.host.disconnect(handle),
+            HostEvent::Data {
+                handle,
+                psm: PSM_HID_INTERRUPT,
+                data,
+            } => {
+                let Some(index) = self.index(handle) else {
+                    return;
+                };
+                if let (Some(remote), Some(report)) =
+                    (&mut self.remotes[index], Report::parse(&data))
+                {
+                    remote.receive(&mut self.host, report);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Get the remote using the player ``index``, as lit on its LEDs, if connected.
+    pub fn remote(&self, index: usize) -> Option<&Wiimote> {
+        self.remotes
+            .get(index)?
+            .as_ref()
+            .filter(|remote| remote.ready)
+    }
+
+    fn remote_mut(&mut self, index: usize) -> Option<&mut Wiimote> {
+        self.remotes
+            .get_mut(index)?
+            .as_mut()
+            .filter(|remote| remote.ready)
+    }
+
+    /// Start or stop the rumble motor of a remote.
+    pub fn set_rumble(&mut self, index: usize, enabled: bool) {
+        let Some(remote) = self.remotes.get_mut(index).and_then(Option::as_mut) else {
+            return;
+        };
+        if !remote.ready || remote.rumble == enabled {
+            return;
+        }
+        remote.rumble = enabled;
+        // Any report carries the rumble, the LEDs one has no other side effect.
+        remote.send(&mut self.host, &report::set_leds(1 << index, enabled));
+    }
+
+    /// Start or stop the IR camera of a remote.
+    pub fn set_ir(&mut self, index: usize, enabled: bool) {
+        let Some(remote) = self.remotes.get_mut(index).and_then(Option::as_mut) else {
+            return;
+        };
+        if !remote.ready || remote.ir == enabled {
+            return;
+        }
+        remote.ir = enabled;
+        for enable in report::set_ir(enabled, remote.rumble) {
+            remote.send(&mut self.host, &enable);
+        }
+        if !enabled {
+            remote.ir_mode = None;
+            remote.dots = [None; 4];
+        }
+        remote.queue_mode();
+        remote.pump(&mut self.host);
+    }
+
+    /// Look for a new remote whose SYNC button got pressed, and pair with it.
+    pub fn start_pairing(&mut self) {
+        self.host.start_pairing();
+    }
+
+    /// Disconnect a remote, which turns it off.
+    pub fn disconnect(&mut self, index: usize) {
+        if let Some(handle) = self.remote_mut(index).map(|remote| remote.handle) {
+            self.host.disconnect(handle);
+        }
+    }
+}
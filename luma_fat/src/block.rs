@@ -0,0 +1,68 @@
+//! ``block`` module of ``luma_fat``.
+//!
+//! Contains the [`BlockDevice`] trait, implemented by the storage drivers and used by the
+//! filesystems, so that either side can be swapped for something else, such as a disk image.
+
+use core::fmt::Debug;
+
+/// A storage device read and written in whole blocks.
+pub trait BlockDevice {
+    /// Errors returned by the device.
+    type Error: Debug;
+
+    /// Get the size of a block, in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Get the amount of blocks on the device.
+    fn block_count(&self) -> u64;
+
+    /// Read as many blocks as fit in ``buffer``, starting at ``block``.
+    ///
+    /// # Panics:
+    /// This function may panic if ``buffer`` isn’t a whole amount of blocks.
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Write the blocks in ``buffer``, starting at ``block``.
+    ///
+    /// # Panics:
+    /// This function may panic if ``buffer`` isn’t a whole amount of blocks.
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<(), Self::Error>;
+
+    /// Make sure everything written reached the device.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Whether the device can’t be written to.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+}
+
+impl<T: BlockDevice + ?Sized> BlockDevice for &mut T {
+    type Error = T::Error;
+
+    fn block_size(&self) -> usize {
+        (**self).block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        (**self).block_count()
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        (**self).read_blocks(block, buffer)
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<(), Self::Error> {
+        (**self).write_blocks(block, buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        (**self).flush()
+    }
+
+    fn is_read_only(&self) -> bool {
+        (**self).is_read_only()
+    }
+}
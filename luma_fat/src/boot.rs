@@ -0,0 +1,185 @@
+//! ``boot`` module of ``luma_fat``.
+//!
+//! Contains the parsing of the boot sector of a volume, and of the partition table in front of
+//! it if any.
+
+/// The flavours of FAT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatKind {
+    Fat12,
+    Fat16,
+    Fat32,
+    ExFat,
+}
+
+/// Partition types which may hold a FAT or exFAT volume.
+const PARTITION_TYPES: [u8; 7] = [0x01, 0x04, 0x06, 0x07, 0x0b, 0x0c, 0x0e];
+
+/// The layout of a volume, in sectors relative to its boot sector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Volume {
+    pub kind: FatKind,
+    pub sector_size: usize,
+    pub sectors_per_cluster: u32,
+    pub fat_start: u64,
+    pub fat_sectors: u64,
+    pub fat_count: u32,
+    /// The root directory of FAT12 and FAT16, which sits between the FATs and the data.
+    pub root_start: u64,
+    pub root_sectors: u64,
+    /// The first cluster of the root directory of FAT32 and exFAT.
+    pub root_cluster: u32,
+    pub data_start: u64,
+    pub cluster_count: u32,
+    pub fs_info: Option<u64>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u32_at(data, offset) as u64 | (u32_at(data, offset + 4) as u64) << 32
+}
+
+impl Volume {
+    /// Parse a boot sector, returning ``None`` if it isn’t one of a FAT or exFAT volume.
+    pub fn parse(sector: &[u8]) -> Option<Volume> {
+        if sector.len() < 512 || u16_at(sector, 510) != 0xaa55 {
+            return None;
+        }
+        if &sector[3..11] == b"EXFAT   " {
+            Volume::parse_exfat(sector)
+        } else {
+            Volume::parse_fat(sector)
+        }
+    }
+
+    fn parse_fat(sector: &[u8]) -> Option<Volume> {
+        if sector[0] != 0xeb && sector[0] != 0xe9 {
+            return None;
+        }
+        let sector_size = u16_at(sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as u32;
+        let reserved = u16_at(sector, 14) as u64;
+        let fat_count = sector[16] as u32;
+        let root_entries = u16_at(sector, 17) as u64;
+        if !sector_size.is_power_of_two()
+            || !(512..=4096).contains(&sector_size)
+            || !sectors_per_cluster.is_power_of_two()
+            || reserved == 0
+            || fat_count == 0
+        {
+            return None;
+        }
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32) as u64,
+            total => total as u64,
+        };
+        let fat_sectors = match u16_at(sector, 22) {
+            0 => u32_at(sector, 36) as u64,
+            size => size as u64,
+        };
+        let root_sectors = (root_entries * 32).div_ceil(sector_size as u64);
+        let root_start = reserved + fat_count as u64 * fat_sectors;
+        let data_start = root_start + root_sectors;
+        let cluster_count = total_sectors.checked_sub(data_start)? / sectors_per_cluster as u64;
+
+        // The amount of clusters is the only thing telling the flavours apart.
+        let kind = match cluster_count {
+            0..4085 => FatKind::Fat12,
+            4085..65525 => FatKind::Fat16,
+            _ => FatKind::Fat32,
+        };
+        let (root_cluster, fs_info) = if kind == FatKind::Fat32 {
+            let fs_info = match u16_at(sector, 48) {
+                0 | 0xffff => None,
+                fs_info => Some(fs_info as u64),
+            };
+            (u32_at(sector, 44), fs_info)
+        } else {
+            (0, None)
+        };
+        if fat_sectors == 0 || (kind != FatKind::Fat32 && root_sectors == 0) {
+            return None;
+        }
+        Some(Volume {
+            kind,
+            sector_size,
+            sectors_per_cluster,
+            fat_start: reserved,
+            fat_sectors,
+            fat_count,
+            root_start,
+            root_sectors,
+            root_cluster,
+            data_start,
+            cluster_count: cluster_count.min(0x0fff_fff4) as u32,
+            fs_info,
+        })
+    }
+
+    fn parse_exfat(sector: &[u8]) -> Option<Volume> {
+        let sector_shift = sector[108] as u32;
+        let cluster_shift = sector[109] as u32;
+        if !(9..=12).contains(&sector_shift) || sector_shift + cluster_shift > 25 {
+            return None;
+        }
+        let fat_start = u32_at(sector, 80) as u64;
+        let fat_sectors = u32_at(sector, 84) as u64;
+        let data_start = u32_at(sector, 88) as u64;
+        let cluster_count = u32_at(sector, 92);
+        if fat_start == 0 || fat_sectors == 0 || data_start == 0 || u64_at(sector, 72) == 0 {
+            return None;
+        }
+        Some(Volume {
+            kind: FatKind::ExFat,
+            sector_size: 1 << sector_shift,
+            sectors_per_cluster: 1 << cluster_shift,
+            fat_start,
+            fat_sectors,
+            // A second FAT only exists for transactional exFAT, the first one is the one used.
+            fat_count: 1,
+            root_start: 0,
+            root_sectors: 0,
+            root_cluster: u32_at(sector, 96),
+            data_start,
+            cluster_count,
+            fs_info: None,
+        })
+    }
+
+    /// Get the size of a cluster, in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.sector_size * self.sectors_per_cluster as usize
+    }
+
+    /// Get the first sector of ``cluster``.
+    pub fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster as u64
+    }
+
+    /// Whether ``cluster`` is one holding data.
+    pub fn is_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster - 2 < self.cluster_count
+    }
+}
+
+/// Get the first sectors of the partitions of an MBR which may hold a FAT or exFAT volume.
+pub(super) fn partitions(sector: &[u8]) -> impl Iterator<Item = u64> + '_ {
+    let valid = sector.len() >= 512 && u16_at(sector, 510) == 0xaa55;
+    (0..4)
+        .filter(move |_| valid)
+        .map(move |i| &sector[446 + i * 16..462 + i * 16])
+        .filter(|entry| PARTITION_TYPES.contains(&entry[4]) && u32_at(entry, 8) != 0)
+        .map(|entry| u32_at(entry, 8) as u64)
+}
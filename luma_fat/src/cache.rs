@@ -0,0 +1,170 @@
+//! ``cache`` module of ``luma_fat``.
+//!
+//! Contains a write-back cache of the most recently used sectors, through which all metadata
+//! goes.  File data in whole sectors bypasses it.
+
+use crate::block::BlockDevice;
+use alloc::vec;
+use alloc::vec::Vec;
+
+struct Slot {
+    sector: u64,
+    data: Vec<u8>,
+    dirty: bool,
+    used: u64,
+}
+
+pub(super) struct Cache {
+    sector_size: usize,
+    /// First block of the volume on the device.
+    offset: u64,
+    capacity: usize,
+    slots: Vec<Slot>,
+    tick: u64,
+}
+
+impl Cache {
+    pub fn new(sector_size: usize, offset: u64, capacity: usize) -> Cache {
+        Cache {
+            sector_size,
+            offset,
+            capacity,
+            slots: Vec::with_capacity(capacity),
+            tick: 0,
+        }
+    }
+
+    fn find(&self, sector: u64) -> Option<usize> {
+        self.slots.iter().position(|slot| slot.sector == sector)
+    }
+
+    fn write_back<D: BlockDevice>(
+        device: &mut D,
+        offset: u64,
+        slot: &mut Slot,
+    ) -> Result<(), D::Error> {
+        if slot.dirty {
+            device.write_blocks(offset + slot.sector, &slot.data)?;
+            slot.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Get the slot holding ``sector``, reading it from the device if ``load``.
+    fn slot<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u64,
+        load: bool,
+    ) -> Result<&mut Slot, D::Error> {
+        self.tick += 1;
+        let index = match self.find(sector) {
+            Some(index) => index,
+            None => {
+                let index = if self.slots.len() < self.capacity {
+                    self.slots.push(Slot {
+                        sector,
+                        data: vec![0; self.sector_size],
+                        dirty: false,
+                        used: 0,
+                    });
+                    self.slots.len() - 1
+                } else {
+                    let (index, _) = self
+                        .slots
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, slot)| slot.used)
+                        .unwrap();
+                    Cache::write_back(device, self.offset, &mut self.slots[index])?;
+                    index
+                };
+                let slot = &mut self.slots[index];
+                slot.sector = sector;
+                if load {
+                    if let Err(error) = device.read_blocks(self.offset + sector, &mut slot.data) {
+                        // Don’t keep garbage around under this sector number.
+                        self.slots.swap_remove(index);
+                        return Err(error);
+                    }
+                } else {
+                    slot.data.fill(0);
+                }
+                index
+            }
+        };
+        let slot = &mut self.slots[index];
+        slot.used = self.tick;
+        Ok(slot)
+    }
+
+    /// Get the content of ``sector``.
+    pub fn read<D: BlockDevice>(&mut self, device: &mut D, sector: u64) -> Result<&[u8], D::Error> {
+        Ok(&self.slot(device, sector, true)?.data)
+    }
+
+    /// Get the content of ``sector`` to modify it.
+    pub fn write<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u64,
+    ) -> Result<&mut [u8], D::Error> {
+        let slot = self.slot(device, sector, true)?;
+        slot.dirty = true;
+        Ok(&mut slot.data)
+    }
+
+    /// Get a zeroed sector to replace ``sector`` with, without reading it first.
+    pub fn replace<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u64,
+    ) -> Result<&mut [u8], D::Error> {
+        let slot = self.slot(device, sector, false)?;
+        slot.data.fill(0);
+        slot.dirty = true;
+        Ok(&mut slot.data)
+    }
+
+    fn overlapping(&self, sector: u64, length: usize) -> impl Iterator<Item = usize> + '_ {
+        let end = sector + (length / self.sector_size) as u64;
+        (0..self.slots.len()).filter(move |&i| (sector..end).contains(&self.slots[i].sector))
+    }
+
+    /// Read whole sectors straight into ``buffer``, past the cache.
+    pub fn read_direct<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u64,
+        buffer: &mut [u8],
+    ) -> Result<(), D::Error> {
+        // The device has to be up to date first.
+        let overlapping: Vec<usize> = self.overlapping(sector, buffer.len()).collect();
+        for index in overlapping {
+            Cache::write_back(device, self.offset, &mut self.slots[index])?;
+        }
+        device.read_blocks(self.offset + sector, buffer)
+    }
+
+    /// Write whole sectors straight from ``buffer``, past the cache.
+    pub fn write_direct<D: BlockDevice>(
+        &mut self,
+        device: &mut D,
+        sector: u64,
+        buffer: &[u8],
+    ) -> Result<(), D::Error> {
+        // Whatever is cached for these sectors is about to be stale.
+        let end = sector + (buffer.len() / self.sector_size) as u64;
+        self.slots
+            .retain(|slot| !(sector..end).contains(&slot.sector));
+        device.write_blocks(self.offset + sector, buffer)
+    }
+
+    /// Write every modified sector back to the device.
+    pub fn flush<D: BlockDevice>(&mut self, device: &mut D) -> Result<(), D::Error> {
+        for slot in &mut self.slots {
+            Cache::write_back(device, self.offset, slot)?;
+        }
+        device.flush()
+    }
+}
@@ -0,0 +1,294 @@
+//! ``dir`` module of ``luma_fat``.
+//!
+//! Contains the encoding of directory entries: 8.3 entries preceded by long name entries on
+//! FAT, and sets of a file entry, a stream extension entry and name entries on exFAT.
+
+use super::name::{self, UpcaseTable};
+use super::{Attributes, Timestamp};
+use alloc::vec::Vec;
+
+/// Size of a directory entry.
+pub(super) const ENTRY_SIZE: usize = 32;
+
+pub(super) type RawEntry = [u8; ENTRY_SIZE];
+
+/// First byte of an entry ending a directory.
+pub(super) const END: u8 = 0x00;
+
+/// First byte of a deleted FAT entry.
+pub(super) const DELETED: u8 = 0xe5;
+
+/// Attributes of a long name entry.
+pub(super) const LONG_NAME: u8 = 0x0f;
+
+/// Flag of a long name entry telling it is the last one, stored first.
+const LAST_LONG_ENTRY: u8 = 0x40;
+
+/// UTF-16 units of a name held in one long name entry.
+const LONG_ENTRY_UNITS: usize = 13;
+
+/// Offsets of the UTF-16 units held in a long name entry.
+const LONG_ENTRY_OFFSETS: [usize; LONG_ENTRY_UNITS] =
+    [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// Bit of the type of exFAT entries telling they are in use.
+pub(super) const IN_USE: u8 = 0x80;
+
+pub(super) const EXFAT_BITMAP: u8 = 0x81;
+pub(super) const EXFAT_UPCASE: u8 = 0x82;
+pub(super) const EXFAT_FILE: u8 = 0x85;
+const EXFAT_STREAM: u8 = 0xc0;
+const EXFAT_NAME: u8 = 0xc1;
+
+/// UTF-16 units of a name held in one exFAT name entry.
+const NAME_ENTRY_UNITS: usize = 15;
+
+/// Stream extension flag telling clusters can be allocated.
+const ALLOCATION_POSSIBLE: u8 = 0x01;
+
+/// Stream extension flag telling the clusters are contiguous and not in the FAT.
+const NO_FAT_CHAIN: u8 = 0x02;
+
+pub(super) fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+pub(super) fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+pub(super) fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u32_at(data, offset) as u64 | (u32_at(data, offset + 4) as u64) << 32
+}
+
+/// What an entry says about a file or directory, whatever the flavour.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Info {
+    pub attributes: Attributes,
+    pub created: Timestamp,
+    pub modified: Timestamp,
+    pub first_cluster: u32,
+    pub size: u64,
+    /// Size of the data actually written, past which only zeroes are read.  It is only ever
+    /// shorter than ``size`` on exFAT, for space allocated ahead of time.
+    pub valid_size: u64,
+    /// Whether the clusters follow each other without going through the FAT, only on exFAT.
+    pub contiguous: bool,
+}
+
+/// A FAT 8.3 entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct ShortEntry {
+    pub name: [u8; 11],
+    pub case: u8,
+    pub info: Info,
+}
+
+impl ShortEntry {
+    pub fn parse(entry: &RawEntry) -> ShortEntry {
+        let mut name = [0; 11];
+        name.copy_from_slice(&entry[..11]);
+        ShortEntry {
+            name,
+            case: entry[12],
+            info: Info {
+                attributes: Attributes::from_bits_retain(entry[11] as u16),
+                created: Timestamp::from_fat(u16_at(entry, 16), u16_at(entry, 14)),
+                modified: Timestamp::from_fat(u16_at(entry, 24), u16_at(entry, 22)),
+                first_cluster: (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32,
+                size: u32_at(entry, 28) as u64,
+                valid_size: u32_at(entry, 28) as u64,
+                contiguous: false,
+            },
+        }
+    }
+
+    pub fn encode(&self) -> RawEntry {
+        let mut entry = [0; ENTRY_SIZE];
+        let info = &self.info;
+        let (created_date, created_time) = info.created.to_fat();
+        let (modified_date, modified_time) = info.modified.to_fat();
+        entry[..11].copy_from_slice(&self.name);
+        entry[11] = info.attributes.bits() as u8;
+        entry[12] = self.case;
+        entry[14..16].copy_from_slice(&created_time.to_le_bytes());
+        entry[16..18].copy_from_slice(&created_date.to_le_bytes());
+        entry[18..20].copy_from_slice(&modified_date.to_le_bytes());
+        entry[20..22].copy_from_slice(&((info.first_cluster >> 16) as u16).to_le_bytes());
+        entry[22..24].copy_from_slice(&modified_time.to_le_bytes());
+        entry[24..26].copy_from_slice(&modified_date.to_le_bytes());
+        entry[26..28].copy_from_slice(&(info.first_cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&(info.size as u32).to_le_bytes());
+        entry
+    }
+}
+
+/// Get the long name entries for ``name``, in the order they are stored.
+pub(super) fn encode_long_name(name: &[u16], checksum: u8) -> Vec<RawEntry> {
+    let count = name.len().div_ceil(LONG_ENTRY_UNITS);
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut entry = [0; ENTRY_SIZE];
+            entry[0] = (i + 1) as u8 | if i + 1 == count { LAST_LONG_ENTRY } else { 0 };
+            entry[11] = LONG_NAME;
+            entry[13] = checksum;
+            for (j, &offset) in LONG_ENTRY_OFFSETS.iter().enumerate() {
+                // The name is nul-terminated if it doesn’t fill the entry, then padded.
+                let unit = match (i * LONG_ENTRY_UNITS + j).cmp(&name.len()) {
+                    core::cmp::Ordering::Less => name[i * LONG_ENTRY_UNITS + j],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                entry[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Gathers the long name entries in front of an 8.3 entry.
+#[derive(Default)]
+pub(super) struct LongName {
+    units: Vec<u16>,
+    checksum: u8,
+    /// The order of the entry expected next, zero if there is no valid name going on.
+    next: u8,
+    /// Amount of entries gathered.
+    pub count: u32,
+}
+
+impl LongName {
+    pub fn reset(&mut self) {
+        self.next = 0;
+        self.count = 0;
+    }
+
+    pub fn push(&mut self, entry: &RawEntry) {
+        let order = entry[0] & !LAST_LONG_ENTRY;
+        if entry[0] & LAST_LONG_ENTRY != 0 {
+            if order == 0 || order as usize * LONG_ENTRY_UNITS > name::MAX_NAME + LONG_ENTRY_UNITS {
+                self.reset();
+                return;
+            }
+            self.units.clear();
+            self.units.resize(order as usize * LONG_ENTRY_UNITS, 0);
+            self.checksum = entry[13];
+            self.count = 0;
+        } else if self.next == 0 || order != self.next || entry[13] != self.checksum {
+            self.reset();
+            return;
+        }
+        for (j, &offset) in LONG_ENTRY_OFFSETS.iter().enumerate() {
+            self.units[(order as usize - 1) * LONG_ENTRY_UNITS + j] = u16_at(entry, offset);
+        }
+        self.next = order - 1;
+        self.count += 1;
+    }
+
+    /// Get the name if it was complete and belongs to the 8.3 entry ``short``.
+    pub fn finish(&mut self, short: &[u8; 11]) -> Option<&[u16]> {
+        if self.count == 0 || self.next != 0 || self.checksum != name::checksum(short) {
+            self.reset();
+            return None;
+        }
+        let length = self
+            .units
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.units.len());
+        Some(&self.units[..length])
+    }
+}
+
+/// A set of exFAT entries describing a file or directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct EntrySet {
+    pub name: Vec<u16>,
+    pub info: Info,
+}
+
+impl EntrySet {
+    /// Get the amount of entries ``name`` needs, file entry included.
+    pub fn entry_count(name: &[u16]) -> usize {
+        2 + name.len().div_ceil(NAME_ENTRY_UNITS)
+    }
+
+    /// Parse the entries of a set, the file entry first, checking their checksum.
+    pub fn parse(entries: &[RawEntry]) -> Option<EntrySet> {
+        let (file, secondary) = entries.split_first()?;
+        let (stream, names) = secondary.split_first()?;
+        if file[0] != EXFAT_FILE || stream[0] != EXFAT_STREAM || file[1] as usize != secondary.len()
+        {
+            return None;
+        }
+        if name::set_checksum(entries.as_flattened()) != u16_at(file, 2) {
+            return None;
+        }
+        let length = stream[3] as usize;
+        let mut name = Vec::with_capacity(length);
+        for entry in names.iter().take_while(|entry| entry[0] == EXFAT_NAME) {
+            for j in 0..NAME_ENTRY_UNITS {
+                name.push(u16_at(entry, 2 + j * 2));
+            }
+        }
+        if name.len() < length {
+            return None;
+        }
+        name.truncate(length);
+        Some(EntrySet {
+            name,
+            info: Info {
+                attributes: Attributes::from_bits_retain(u16_at(file, 4)),
+                created: Timestamp::from_exfat(u32_at(file, 8)),
+                modified: Timestamp::from_exfat(u32_at(file, 12)),
+                first_cluster: u32_at(stream, 20),
+                size: u64_at(stream, 24),
+                valid_size: u64_at(stream, 8),
+                contiguous: stream[1] & NO_FAT_CHAIN != 0,
+            },
+        })
+    }
+
+    pub fn encode(&self, upcase: &UpcaseTable) -> Vec<RawEntry> {
+        let count = EntrySet::entry_count(&self.name);
+        let mut entries = alloc::vec![[0; ENTRY_SIZE]; count];
+        let info = &self.info;
+
+        let file = &mut entries[0];
+        file[0] = EXFAT_FILE;
+        file[1] = (count - 1) as u8;
+        file[4..6].copy_from_slice(&info.attributes.bits().to_le_bytes());
+        file[8..12].copy_from_slice(&info.created.to_exfat().to_le_bytes());
+        file[12..16].copy_from_slice(&info.modified.to_exfat().to_le_bytes());
+        file[16..20].copy_from_slice(&info.modified.to_exfat().to_le_bytes());
+
+        let stream = &mut entries[1];
+        stream[0] = EXFAT_STREAM;
+        stream[1] = ALLOCATION_POSSIBLE | if info.contiguous { NO_FAT_CHAIN } else { 0 };
+        stream[3] = self.name.len() as u8;
+        stream[4..6].copy_from_slice(&upcase.hash(&self.name).to_le_bytes());
+        stream[8..16].copy_from_slice(&info.valid_size.to_le_bytes());
+        stream[20..24].copy_from_slice(&info.first_cluster.to_le_bytes());
+        stream[24..32].copy_from_slice(&info.size.to_le_bytes());
+
+        for (entry, units) in entries[2..]
+            .iter_mut()
+            .zip(self.name.chunks(NAME_ENTRY_UNITS))
+        {
+            entry[0] = EXFAT_NAME;
+            for (j, unit) in units.iter().enumerate() {
+                entry[2 + j * 2..4 + j * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+
+        let checksum = name::set_checksum(entries.as_flattened());
+        entries[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+        entries
+    }
+}
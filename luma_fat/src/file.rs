@@ -0,0 +1,336 @@
+//! ``file`` module of ``luma_fat``.
+//!
+//! Contains the reading and writing of file data, and the [`File`] handle doing it through the
+//! ``embedded-io`` traits.
+
+use super::dir::Info;
+use super::{FatError, FatKind, FileSystem, Inner, Metadata, Node, Result};
+use crate::block::BlockDevice;
+use alloc::rc::Rc;
+use core::ops::Deref;
+use embedded_io::{ErrorType, Read, Seek, SeekFrom, Write};
+
+/// The cluster last reached in a file, as its index in the chain and its number, so that
+/// reading on doesn’t follow the chain from the start again.
+type Cursor = Option<(u32, u32)>;
+
+impl<D: BlockDevice> Inner<D> {
+    /// Get the ``n``-th cluster of a file, which has to have it.
+    fn file_cluster(&mut self, info: &Info, cursor: &mut Cursor, n: u32) -> Result<u32, D> {
+        if info.contiguous {
+            return Ok(info.first_cluster + n);
+        }
+        let (mut index, mut cluster) = match *cursor {
+            Some((index, cluster)) if index <= n => (index, cluster),
+            _ => (0, info.first_cluster),
+        };
+        while index < n {
+            cluster = self.next_cluster(cluster)?.ok_or(FatError::Corrupted)?;
+            index += 1;
+        }
+        *cursor = Some((n, cluster));
+        Ok(cluster)
+    }
+
+    /// Get how many whole sectors from ``position`` can be transferred at once, up to
+    /// ``sectors``, following the clusters for as long as they are next to each other.
+    fn sector_run(
+        &mut self,
+        info: &Info,
+        cursor: &mut Cursor,
+        position: u64,
+        sectors: usize,
+    ) -> Result<usize, D> {
+        let sector_size = self.volume.sector_size;
+        let cluster_size = self.volume.cluster_size() as u64;
+        let mut n = (position / cluster_size) as u32;
+        let mut cluster = self.file_cluster(info, cursor, n)?;
+        let mut run = (cluster_size - position % cluster_size) as usize / sector_size;
+        while run < sectors {
+            let next = self.file_cluster(info, cursor, n + 1)?;
+            if next != cluster + 1 {
+                break;
+            }
+            n += 1;
+            cluster = next;
+            run += self.volume.sectors_per_cluster as usize;
+        }
+        Ok(run.min(sectors))
+    }
+
+    /// Get the sector holding ``position`` in a file.
+    fn file_sector(&mut self, info: &Info, cursor: &mut Cursor, position: u64) -> Result<u64, D> {
+        let cluster_size = self.volume.cluster_size() as u64;
+        let cluster = self.file_cluster(info, cursor, (position / cluster_size) as u32)?;
+        Ok(self.volume.cluster_sector(cluster)
+            + position % cluster_size / self.volume.sector_size as u64)
+    }
+
+    fn read_file(
+        &mut self,
+        info: &Info,
+        cursor: &mut Cursor,
+        position: u64,
+        buffer: &mut [u8],
+    ) -> Result<usize, D> {
+        if position >= info.size {
+            return Ok(0);
+        }
+        let sector_size = self.volume.sector_size;
+        let length = buffer.len().min((info.size - position) as usize);
+        let mut done = 0;
+        while done < length {
+            let position = position + done as u64;
+            if position >= info.valid_size {
+                // Space allocated ahead of time, which was never written.
+                buffer[done..length].fill(0);
+                break;
+            }
+            let remaining = (length - done).min((info.valid_size - position) as usize);
+            let sector = self.file_sector(info, cursor, position)?;
+            let offset = (position % sector_size as u64) as usize;
+            if offset == 0 && remaining >= sector_size {
+                let run = self.sector_run(info, cursor, position, remaining / sector_size)?;
+                let chunk = &mut buffer[done..done + run * sector_size];
+                self.cache
+                    .read_direct(&mut self.device, sector, chunk)
+                    .map_err(FatError::Device)?;
+                done += chunk.len();
+            } else {
+                let count = remaining.min(sector_size - offset);
+                let content = self.read_sector(sector)?;
+                buffer[done..done + count].copy_from_slice(&content[offset..offset + count]);
+                done += count;
+            }
+        }
+        Ok(length)
+    }
+
+    /// Write ``data`` at ``position`` in a file, or zeroes if there is none, allocating its
+    /// clusters as needed.
+    fn write_file(
+        &mut self,
+        info: &mut Info,
+        cursor: &mut Cursor,
+        position: u64,
+        data: Option<&[u8]>,
+        length: u64,
+    ) -> Result<(), D> {
+        if length == 0 {
+            return Ok(());
+        }
+        let end = position.checked_add(length).ok_or(FatError::TooLarge)?;
+        if self.volume.kind != FatKind::ExFat && end > u32::MAX as u64 {
+            return Err(FatError::TooLarge);
+        }
+        if position > info.valid_size {
+            // Whatever lies in between has to read as zeroes.
+            let valid_size = info.valid_size;
+            self.write_file(info, cursor, valid_size, None, position - valid_size)?;
+        }
+        let clusters = end.div_ceil(self.volume.cluster_size() as u64);
+        // Space allocated ahead of time is kept.
+        if clusters > self.chain_length(info)? as u64 {
+            self.resize(info, clusters as u32)?;
+        }
+        info.size = info.size.max(end);
+
+        let sector_size = self.volume.sector_size;
+        let mut done = 0;
+        while done < length as usize {
+            let position = position + done as u64;
+            let remaining = length as usize - done;
+            let sector = self.file_sector(info, cursor, position)?;
+            let offset = (position % sector_size as u64) as usize;
+            match data {
+                Some(data) if offset == 0 && remaining >= sector_size => {
+                    let run = self.sector_run(info, cursor, position, remaining / sector_size)?;
+                    let chunk = &data[done..done + run * sector_size];
+                    self.cache
+                        .write_direct(&mut self.device, sector, chunk)
+                        .map_err(FatError::Device)?;
+                    done += chunk.len();
+                }
+                _ => {
+                    let count = remaining.min(sector_size - offset);
+                    // A whole sector doesn’t need to be read before being replaced.
+                    let content = if count == sector_size {
+                        self.cache.replace(&mut self.device, sector)
+                    } else {
+                        self.cache.write(&mut self.device, sector)
+                    }
+                    .map_err(FatError::Device)?;
+                    match data {
+                        Some(data) => content[offset..offset + count]
+                            .copy_from_slice(&data[done..done + count]),
+                        None => content[offset..offset + count].fill(0),
+                    }
+                    done += count;
+                }
+            }
+        }
+        info.valid_size = info.valid_size.max(end);
+        Ok(())
+    }
+
+    fn set_file_len(&mut self, info: &mut Info, cursor: &mut Cursor, len: u64) -> Result<(), D> {
+        self.check_writable()?;
+        if len < info.size {
+            let clusters = len.div_ceil(self.volume.cluster_size() as u64);
+            self.resize(info, clusters as u32)?;
+            info.size = len;
+            info.valid_size = info.valid_size.min(len);
+            *cursor = None;
+        } else if len > info.size {
+            let size = info.size;
+            if self.volume.kind == FatKind::ExFat {
+                // Past the valid size it already reads as zeroes, only clusters are needed.
+                let clusters = len.div_ceil(self.volume.cluster_size() as u64);
+                self.resize(info, clusters as u32)?;
+                info.size = len;
+            } else {
+                self.write_file(info, cursor, size, None, len - size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The way a [`File`] holds on to its filesystem.
+pub(super) enum FsRef<'a, D: BlockDevice> {
+    Borrowed(&'a FileSystem<D>),
+    /// Keeps the filesystem alive for as long as the file is open, see
+    /// [`FileSystem::open_shared`].
+    Shared(Rc<FileSystem<D>>),
+}
+
+impl<D: BlockDevice> Deref for FsRef<'_, D> {
+    type Target = FileSystem<D>;
+
+    fn deref(&self) -> &FileSystem<D> {
+        match self {
+            FsRef::Borrowed(fs) => fs,
+            FsRef::Shared(fs) => fs,
+        }
+    }
+}
+
+/// A file of a [`FileSystem`], open for reading and writing.
+///
+/// Its entry gets updated when flushed or dropped.  Opening the same file twice while writing
+/// to it leaves one of the handles with a stale view of it.
+pub struct File<'a, D: BlockDevice> {
+    fs: FsRef<'a, D>,
+    node: Node,
+    position: u64,
+    cursor: Cursor,
+    dirty: bool,
+}
+
+impl<'a, D: BlockDevice> File<'a, D> {
+    pub(super) fn new(fs: FsRef<'a, D>, node: Node) -> File<'a, D> {
+        File {
+            fs,
+            node,
+            position: 0,
+            cursor: None,
+            dirty: false,
+        }
+    }
+
+    /// Get the size of the file.
+    pub fn len(&self) -> u64 {
+        self.node.info.size
+    }
+
+    /// Whether the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the current position in the file.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get information about the file.
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(&self.node.info)
+    }
+
+    /// Shrink or grow the file to ``len``, filling it with zeroes.  The position is left as
+    /// is.
+    pub fn set_len(&mut self, len: u64) -> core::result::Result<(), FatError<D::Error>> {
+        let mut inner = self.fs.inner.borrow_mut();
+        self.dirty = true;
+        inner.set_file_len(&mut self.node.info, &mut self.cursor, len)
+    }
+
+    /// Cut the file at the current position.
+    pub fn truncate(&mut self) -> core::result::Result<(), FatError<D::Error>> {
+        self.set_len(self.position.min(self.len()))
+    }
+}
+
+impl<D: BlockDevice> ErrorType for File<'_, D> {
+    type Error = FatError<D::Error>;
+}
+
+impl<D: BlockDevice> Read for File<'_, D> {
+    fn read(&mut self, buf: &mut [u8]) -> core::result::Result<usize, Self::Error> {
+        let mut inner = self.fs.inner.borrow_mut();
+        let read = inner.read_file(&self.node.info, &mut self.cursor, self.position, buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<D: BlockDevice> Write for File<'_, D> {
+    fn write(&mut self, buf: &[u8]) -> core::result::Result<usize, Self::Error> {
+        let mut inner = self.fs.inner.borrow_mut();
+        inner.check_writable()?;
+        self.dirty = true;
+        let length = buf.len() as u64;
+        inner.write_file(
+            &mut self.node.info,
+            &mut self.cursor,
+            self.position,
+            Some(buf),
+            length,
+        )?;
+        self.position += length;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> core::result::Result<(), Self::Error> {
+        let mut inner = self.fs.inner.borrow_mut();
+        if self.dirty {
+            self.node.info.modified = (inner.clock)();
+            inner.update_entry(&self.node)?;
+            self.dirty = false;
+        }
+        inner.flush()
+    }
+}
+
+impl<D: BlockDevice> Seek for File<'_, D> {
+    fn seek(&mut self, pos: SeekFrom) -> core::result::Result<u64, Self::Error> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len().checked_add_signed(offset),
+        };
+        // Seeking past the end is fine, the gap gets filled with zeroes when writing there.
+        self.position = position.ok_or(FatError::Invalid)?;
+        Ok(self.position)
+    }
+}
+
+impl<D: BlockDevice> Drop for File<'_, D> {
+    fn drop(&mut self) {
+        if self.dirty {
+            // There is no way to report a failure from here, call flush() to know about it.
+            let _ = self.flush();
+        }
+    }
+}
@@ -0,0 +1,1047 @@
+//! ``luma_fat`` is a FAT12, FAT16, FAT32 and exFAT filesystem on top of any [`BlockDevice`],
+//! such as an SD card or a disk image.  The volume is either the whole device or the first FAT
+//! partition of its MBR.
+//!
+//! Paths are relative to the root of the volume, use ``/`` as separator and are compared
+//! ignoring the case, like on every other system.  Long names are supported on all flavours.
+//!
+//! Metadata goes through a small write-back cache of sectors, so [`FileSystem::flush`] has to be
+//! called, or the filesystem dropped, before the device is removed.  File data of whole sectors
+//! goes straight to the device.
+//!
+//! Nothing in here depends on the console, so it works on the host as well, where the tests run
+//! it against disk image files.
+#![no_std]
+
+extern crate alloc;
+
+pub mod block;
+mod boot;
+mod cache;
+mod dir;
+mod file;
+mod name;
+mod table;
+
+pub use boot::FatKind;
+pub use file::File;
+
+use crate::block::BlockDevice;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use boot::Volume;
+use cache::Cache;
+use core::cell::RefCell;
+use core::fmt::Debug;
+use dir::{END, ENTRY_SIZE, EntrySet, Info, LongName, RawEntry, ShortEntry};
+use embedded_io::ErrorKind;
+use file::FsRef;
+use name::UpcaseTable;
+
+/// Amount of sectors kept in the cache.
+const CACHE_SECTORS: usize = 16;
+
+/// Errors which can be encountered while using a FAT filesystem.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatError<E> {
+    /// The device failed.
+    Device(E),
+    /// There is no FAT or exFAT volume on the device.
+    NoFilesystem,
+    /// The volume uses sectors of another size than the blocks of the device.
+    Unsupported,
+    /// The filesystem is corrupted.
+    Corrupted,
+    /// The file or directory doesn’t exist.
+    NotFound,
+    /// The file or directory already exists.
+    AlreadyExists,
+    /// A directory was expected.
+    NotADirectory,
+    /// A file was expected.
+    IsADirectory,
+    /// The directory to remove isn’t empty.
+    NotEmpty,
+    /// The name can’t be used on FAT.
+    InvalidName,
+    /// An argument got rejected.
+    Invalid,
+    /// There is no space left on the volume, or in the root directory of FAT12 and FAT16.
+    NoSpace,
+    /// The file would grow past 4 GiB, which only exFAT supports.
+    TooLarge,
+    /// The device can’t be written to.
+    ReadOnly,
+}
+
+impl<E: Debug> embedded_io::Error for FatError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            FatError::Corrupted => ErrorKind::InvalidData,
+            FatError::NotFound => ErrorKind::NotFound,
+            FatError::AlreadyExists => ErrorKind::AlreadyExists,
+            FatError::InvalidName | FatError::Invalid | FatError::TooLarge => {
+                ErrorKind::InvalidInput
+            }
+            FatError::NoSpace => ErrorKind::OutOfMemory,
+            FatError::ReadOnly => ErrorKind::PermissionDenied,
+            FatError::Unsupported => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+type Result<T, D> = core::result::Result<T, FatError<<D as BlockDevice>::Error>>;
+
+bitflags::bitflags! {
+    /// The attributes of a file or directory.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Attributes: u16 {
+        const READ_ONLY = 1 << 0;
+        const HIDDEN = 1 << 1;
+        const SYSTEM = 1 << 2;
+        const VOLUME_ID = 1 << 3;
+        const DIRECTORY = 1 << 4;
+        const ARCHIVE = 1 << 5;
+    }
+}
+
+/// A date and time as stored by FAT, in local time and to two seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// The time given to files when no clock got set, 2000-01-01 00:00:00.
+    pub const DEFAULT: Timestamp = Timestamp {
+        year: 2000,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+
+    fn from_fat(date: u16, time: u16) -> Timestamp {
+        Timestamp {
+            year: 1980 + (date >> 9),
+            month: (date >> 5 & 0xf) as u8,
+            day: (date & 0x1f) as u8,
+            hour: (time >> 11) as u8,
+            minute: (time >> 5 & 0x3f) as u8,
+            second: (time & 0x1f) as u8 * 2,
+        }
+    }
+
+    fn to_fat(self) -> (u16, u16) {
+        let date =
+            (self.year.clamp(1980, 2107) - 1980) << 9 | (self.month as u16) << 5 | self.day as u16;
+        let time = (self.hour as u16) << 11 | (self.minute as u16) << 5 | (self.second as u16 / 2);
+        (date, time)
+    }
+
+    fn from_exfat(timestamp: u32) -> Timestamp {
+        Timestamp::from_fat((timestamp >> 16) as u16, timestamp as u16)
+    }
+
+    fn to_exfat(self) -> u32 {
+        let (date, time) = self.to_fat();
+        (date as u32) << 16 | time as u32
+    }
+}
+
+/// Information about a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub attributes: Attributes,
+    /// The size of a file, directories have none.
+    pub len: u64,
+    pub created: Timestamp,
+    pub modified: Timestamp,
+}
+
+impl Metadata {
+    fn new(info: &Info) -> Metadata {
+        Metadata {
+            attributes: info.attributes,
+            len: if info.attributes.contains(Attributes::DIRECTORY) {
+                0
+            } else {
+                info.size
+            },
+            created: info.created,
+            modified: info.modified,
+        }
+    }
+
+    /// Whether this is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(Attributes::DIRECTORY)
+    }
+
+    /// Whether this is a file.
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+}
+
+/// An entry of a directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// The clusters of a directory, used to find its entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Dir {
+    /// The root directory of FAT12 and FAT16, outside of the clusters.
+    FixedRoot,
+    Clusters {
+        first: u32,
+        contiguous: bool,
+        size: u64,
+    },
+}
+
+/// Where the entries of a file or directory are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Location {
+    dir: Dir,
+    /// Index of the first entry, a long name one or the exFAT file one.
+    index: u32,
+    count: u32,
+}
+
+/// A file or directory, along with where it is described unless it is the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Node {
+    info: Info,
+    location: Option<Location>,
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        self.info.attributes.contains(Attributes::DIRECTORY)
+    }
+}
+
+/// An entry found in a directory.
+struct Found {
+    name: Vec<u16>,
+    node: Node,
+}
+
+struct Inner<D: BlockDevice> {
+    device: D,
+    cache: Cache,
+    volume: Volume,
+    upcase: UpcaseTable,
+    /// Clusters holding the allocation bitmap of exFAT.
+    bitmap: Vec<u32>,
+    /// Where to start looking for a free cluster.
+    next_free: u32,
+    /// Amount of free clusters, as kept in the FSInfo sector of FAT32.
+    free_count: Option<u32>,
+    info_dirty: bool,
+    clock: fn() -> Timestamp,
+}
+
+fn default_clock() -> Timestamp {
+    Timestamp::DEFAULT
+}
+
+impl<D: BlockDevice> Inner<D> {
+    fn read_sector(&mut self, sector: u64) -> Result<&[u8], D> {
+        self.cache
+            .read(&mut self.device, sector)
+            .map_err(FatError::Device)
+    }
+
+    fn write_sector(&mut self, sector: u64) -> Result<&mut [u8], D> {
+        self.cache
+            .write(&mut self.device, sector)
+            .map_err(FatError::Device)
+    }
+
+    fn check_writable(&self) -> Result<(), D> {
+        if self.device.is_read_only() {
+            return Err(FatError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn is_exfat(&self) -> bool {
+        self.volume.kind == FatKind::ExFat
+    }
+
+    fn root(&self) -> Node {
+        Node {
+            info: Info {
+                attributes: Attributes::DIRECTORY,
+                created: Timestamp::DEFAULT,
+                modified: Timestamp::DEFAULT,
+                first_cluster: self.volume.root_cluster,
+                size: 0,
+                valid_size: 0,
+                contiguous: false,
+            },
+            location: None,
+        }
+    }
+
+    fn dir(&self, node: &Node) -> Dir {
+        match self.volume.kind {
+            FatKind::Fat12 | FatKind::Fat16 if node.location.is_none() => Dir::FixedRoot,
+            _ => Dir::Clusters {
+                first: node.info.first_cluster,
+                contiguous: node.info.contiguous,
+                size: node.info.size,
+            },
+        }
+    }
+
+    /// Read the exFAT allocation bitmap and upcase table locations from the root directory.
+    fn load_exfat(&mut self) -> Result<(), D> {
+        let root = self.dir(&self.root());
+        let mut index = 0;
+        let mut upcase = None;
+        while let Some(entry) = self.read_entry(&root, index)? {
+            match entry[0] {
+                END => break,
+                dir::EXFAT_BITMAP if self.bitmap.is_empty() => {
+                    let clusters =
+                        self.clusters_of(dir::u32_at(&entry, 20), dir::u64_at(&entry, 24))?;
+                    self.bitmap = clusters;
+                }
+                dir::EXFAT_UPCASE => {
+                    upcase = Some((dir::u32_at(&entry, 20), dir::u64_at(&entry, 24)));
+                }
+                _ => (),
+            }
+            index += 1;
+        }
+        if self.bitmap.is_empty() {
+            return Err(FatError::Corrupted);
+        }
+        if let Some((first, length)) = upcase {
+            let mut data = Vec::with_capacity(length as usize);
+            let sector_size = self.volume.sector_size;
+            'clusters: for cluster in self.clusters_of(first, length)? {
+                let start = self.volume.cluster_sector(cluster);
+                for sector in start..start + self.volume.sectors_per_cluster as u64 {
+                    let remaining = length as usize - data.len();
+                    if remaining == 0 {
+                        break 'clusters;
+                    }
+                    let content = self.read_sector(sector)?;
+                    data.extend_from_slice(&content[..remaining.min(sector_size)]);
+                }
+            }
+            self.upcase = UpcaseTable::parse(&data);
+        }
+        Ok(())
+    }
+
+    /// Read the free cluster hints of the FSInfo sector of FAT32.
+    fn load_fs_info(&mut self) -> Result<(), D> {
+        let Some(sector) = self.volume.fs_info else {
+            return Ok(());
+        };
+        let content = self.read_sector(sector)?;
+        if dir::u32_at(content, 0) != 0x4161_5252 || dir::u32_at(content, 484) != 0x6141_7272 {
+            self.volume.fs_info = None;
+            return Ok(());
+        }
+        let free_count = dir::u32_at(content, 488);
+        let next_free = dir::u32_at(content, 492);
+        if free_count <= self.volume.cluster_count {
+            self.free_count = Some(free_count);
+        }
+        if self.volume.is_cluster(next_free) {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), D> {
+        if self.info_dirty {
+            if let Some(sector) = self.volume.fs_info {
+                let free_count = self.free_count.unwrap_or(u32::MAX);
+                let next_free = self.next_free;
+                let content = self.write_sector(sector)?;
+                content[488..492].copy_from_slice(&free_count.to_le_bytes());
+                content[492..496].copy_from_slice(&next_free.to_le_bytes());
+            }
+            self.info_dirty = false;
+        }
+        self.cache.flush(&mut self.device).map_err(FatError::Device)
+    }
+
+    /// Get the sector and offset of the entry at ``index`` in ``dir``, if it is in there.
+    fn entry_position(&mut self, dir: &Dir, index: u32) -> Result<Option<(u64, usize)>, D> {
+        let sector_size = self.volume.sector_size as u64;
+        let offset = index as u64 * ENTRY_SIZE as u64;
+        let sector = match *dir {
+            Dir::FixedRoot => {
+                if offset >= self.volume.root_sectors * sector_size {
+                    return Ok(None);
+                }
+                self.volume.root_start + offset / sector_size
+            }
+            Dir::Clusters {
+                first,
+                contiguous,
+                size,
+            } => {
+                let cluster_size = self.volume.cluster_size() as u64;
+                if first == 0 || (contiguous && offset >= size) {
+                    return Ok(None);
+                }
+                let Some(cluster) =
+                    self.cluster_at(first, contiguous, (offset / cluster_size) as u32)?
+                else {
+                    return Ok(None);
+                };
+                self.volume.cluster_sector(cluster) + offset % cluster_size / sector_size
+            }
+        };
+        Ok(Some((sector, (offset % sector_size) as usize)))
+    }
+
+    fn read_entry(&mut self, dir: &Dir, index: u32) -> Result<Option<RawEntry>, D> {
+        let Some((sector, offset)) = self.entry_position(dir, index)? else {
+            return Ok(None);
+        };
+        let mut entry = [0; ENTRY_SIZE];
+        entry.copy_from_slice(&self.read_sector(sector)?[offset..offset + ENTRY_SIZE]);
+        Ok(Some(entry))
+    }
+
+    fn write_entry(&mut self, dir: &Dir, index: u32, entry: &RawEntry) -> Result<(), D> {
+        let (sector, offset) = self
+            .entry_position(dir, index)?
+            .ok_or(FatError::Corrupted)?;
+        self.write_sector(sector)?[offset..offset + ENTRY_SIZE].copy_from_slice(entry);
+        Ok(())
+    }
+
+    /// Get the next file or directory of ``dir`` from ``index``, moving it past it.
+    fn next_entry(&mut self, dir: &Dir, index: &mut u32) -> Result<Option<Found>, D> {
+        if self.is_exfat() {
+            self.next_exfat_entry(dir, index)
+        } else {
+            self.next_fat_entry(dir, index)
+        }
+    }
+
+    fn next_fat_entry(&mut self, dir: &Dir, index: &mut u32) -> Result<Option<Found>, D> {
+        let mut long_name = LongName::default();
+        while let Some(entry) = self.read_entry(dir, *index)? {
+            match entry[0] {
+                END => return Ok(None),
+                dir::DELETED => long_name.reset(),
+                _ if entry[11] & 0x3f == dir::LONG_NAME => long_name.push(&entry),
+                _ => {
+                    let short = ShortEntry::parse(&entry);
+                    // The volume label and the dot entries aren’t part of the listing.
+                    if short.info.attributes.contains(Attributes::VOLUME_ID)
+                        || short.name[0] == b'.'
+                    {
+                        long_name.reset();
+                        *index += 1;
+                        continue;
+                    }
+                    let (name, count) = match long_name.finish(&short.name) {
+                        Some(name) => (name.to_vec(), long_name.count + 1),
+                        None => (
+                            name::short_to_string(&short.name, short.case)
+                                .encode_utf16()
+                                .collect(),
+                            1,
+                        ),
+                    };
+                    let location = Location {
+                        dir: *dir,
+                        index: *index + 1 - count,
+                        count,
+                    };
+                    *index += 1;
+                    return Ok(Some(Found {
+                        name,
+                        node: Node {
+                            info: short.info,
+                            location: Some(location),
+                        },
+                    }));
+                }
+            }
+            *index += 1;
+        }
+        Ok(None)
+    }
+
+    fn next_exfat_entry(&mut self, dir: &Dir, index: &mut u32) -> Result<Option<Found>, D> {
+        while let Some(entry) = self.read_entry(dir, *index)? {
+            match entry[0] {
+                END => return Ok(None),
+                dir::EXFAT_FILE => {
+                    let count = entry[1] as u32 + 1;
+                    let mut entries = vec![entry];
+                    for i in 1..count {
+                        entries.push(
+                            self.read_entry(dir, *index + i)?
+                                .ok_or(FatError::Corrupted)?,
+                        );
+                    }
+                    let location = Location {
+                        dir: *dir,
+                        index: *index,
+                        count,
+                    };
+                    *index += count;
+                    // Sets which don’t add up are skipped as if they were deleted.
+                    if let Some(set) = EntrySet::parse(&entries) {
+                        return Ok(Some(Found {
+                            name: set.name,
+                            node: Node {
+                                info: set.info,
+                                location: Some(location),
+                            },
+                        }));
+                    }
+                }
+                _ => *index += 1,
+            }
+        }
+        Ok(None)
+    }
+
+    fn names_match(&self, a: &[u16], b: &str) -> bool {
+        if self.is_exfat() {
+            let b: Vec<u16> = b.encode_utf16().collect();
+            self.upcase.eq(a, &b)
+        } else {
+            name::eq_ignore_case(&name::decode(a), b)
+        }
+    }
+
+    fn find(&mut self, parent: &Node, name: &str) -> Result<Option<Node>, D> {
+        let dir = self.dir(parent);
+        let mut index = 0;
+        while let Some(found) = self.next_entry(&dir, &mut index)? {
+            if self.names_match(&found.name, name) {
+                return Ok(Some(found.node));
+            }
+        }
+        Ok(None)
+    }
+
+    fn resolve(&mut self, path: &str) -> Result<Node, D> {
+        let mut nodes = vec![self.root()];
+        for component in path.split('/') {
+            match component {
+                "" | "." => (),
+                ".." => {
+                    if nodes.len() > 1 {
+                        nodes.pop();
+                    }
+                }
+                component => {
+                    let parent = nodes.last().unwrap();
+                    if !parent.is_dir() {
+                        return Err(FatError::NotADirectory);
+                    }
+                    let node = self.find(parent, component)?.ok_or(FatError::NotFound)?;
+                    nodes.push(node);
+                }
+            }
+        }
+        Ok(nodes.pop().unwrap())
+    }
+
+    /// Get the directory holding ``path`` and the name it has in there.
+    fn resolve_parent<'a>(&mut self, path: &'a str) -> Result<(Node, &'a str), D> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (self.resolve(parent)?, name),
+            None => (self.root(), path),
+        };
+        if !parent.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+        if !name::is_valid(name) {
+            return Err(FatError::InvalidName);
+        }
+        Ok((parent, name))
+    }
+
+    /// Get the 8.3 names used in ``dir``, to pick a new unique one.
+    fn short_names(&mut self, dir: &Dir) -> Result<Vec<[u8; 11]>, D> {
+        let mut names = Vec::new();
+        let mut index = 0;
+        while let Some(entry) = self.read_entry(dir, index)? {
+            match entry[0] {
+                END => break,
+                dir::DELETED => (),
+                _ if entry[11] & 0x3f == dir::LONG_NAME => (),
+                _ => names.push(ShortEntry::parse(&entry).name),
+            }
+            index += 1;
+        }
+        Ok(names)
+    }
+
+    /// Get the entries describing ``info`` under ``name`` in ``parent``.
+    fn encode_entries(
+        &mut self,
+        parent: &Node,
+        name: &str,
+        info: Info,
+    ) -> Result<Vec<RawEntry>, D> {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        if self.is_exfat() {
+            return Ok(EntrySet { name: units, info }.encode(&self.upcase));
+        }
+        let dir = self.dir(parent);
+        let names = self.short_names(&dir)?;
+        if let Some((short, case)) = name::short_name(name)
+            && !names.contains(&short)
+        {
+            return Ok(vec![
+                ShortEntry {
+                    name: short,
+                    case,
+                    info,
+                }
+                .encode(),
+            ]);
+        }
+        let basis = name::short_basis(name);
+        let short = (1..1_000_000)
+            .map(|n| name::with_tail(&basis, n))
+            .find(|short| !names.contains(short))
+            .ok_or(FatError::NoSpace)?;
+        let mut entries = dir::encode_long_name(&units, name::checksum(&short));
+        entries.push(
+            ShortEntry {
+                name: short,
+                case: 0,
+                info,
+            }
+            .encode(),
+        );
+        Ok(entries)
+    }
+
+    /// Store ``entries`` in ``parent``, growing it if there is no room for them.
+    fn add_entries(&mut self, parent: &mut Node, entries: &[RawEntry]) -> Result<Location, D> {
+        let count = entries.len() as u32;
+        let mut index = 0;
+        let mut run = 0;
+        loop {
+            let dir = self.dir(parent);
+            match self.read_entry(&dir, index)? {
+                Some(entry) => {
+                    let free = if self.is_exfat() {
+                        entry[0] & dir::IN_USE == 0
+                    } else {
+                        entry[0] == END || entry[0] == dir::DELETED
+                    };
+                    run = if free { run + 1 } else { 0 };
+                    index += 1;
+                    if run == count {
+                        break;
+                    }
+                }
+                None => self.grow_dir(parent)?,
+            }
+        }
+        let location = Location {
+            dir: self.dir(parent),
+            index: index - count,
+            count,
+        };
+        for (i, entry) in entries.iter().enumerate() {
+            self.write_entry(&location.dir, location.index + i as u32, entry)?;
+        }
+        Ok(location)
+    }
+
+    /// Add a zeroed cluster to the directory ``node``.
+    fn grow_dir(&mut self, node: &mut Node) -> Result<(), D> {
+        if self.dir(node) == Dir::FixedRoot {
+            return Err(FatError::NoSpace);
+        }
+        let clusters = self.chain_length(&node.info)?;
+        self.resize(&mut node.info, clusters + 1)?;
+        let cluster = self
+            .cluster_at(node.info.first_cluster, node.info.contiguous, clusters)?
+            .ok_or(FatError::Corrupted)?;
+        self.zero_cluster(cluster)?;
+        if self.is_exfat() {
+            node.info.size += self.volume.cluster_size() as u64;
+            node.info.valid_size = node.info.size;
+            self.update_entry(node)?;
+        }
+        Ok(())
+    }
+
+    /// Write the entries of ``node`` back with its current information.
+    fn update_entry(&mut self, node: &Node) -> Result<(), D> {
+        let Some(location) = node.location else {
+            return Ok(());
+        };
+        if self.is_exfat() {
+            let mut entries = Vec::with_capacity(location.count as usize);
+            for i in 0..location.count {
+                let entry = self.read_entry(&location.dir, location.index + i)?;
+                entries.push(entry.ok_or(FatError::Corrupted)?);
+            }
+            let mut set = EntrySet::parse(&entries).ok_or(FatError::Corrupted)?;
+            set.info = node.info.clone();
+            for (i, entry) in set.encode(&self.upcase).iter().enumerate() {
+                self.write_entry(&location.dir, location.index + i as u32, entry)?;
+            }
+        } else {
+            let index = location.index + location.count - 1;
+            let entry = self
+                .read_entry(&location.dir, index)?
+                .ok_or(FatError::Corrupted)?;
+            let mut short = ShortEntry::parse(&entry);
+            short.info = node.info.clone();
+            self.write_entry(&location.dir, index, &short.encode())?;
+        }
+        Ok(())
+    }
+
+    /// Mark the entries of ``location`` as deleted.
+    fn remove_entries(&mut self, location: &Location) -> Result<(), D> {
+        for i in location.index..location.index + location.count {
+            let mut entry = self
+                .read_entry(&location.dir, i)?
+                .ok_or(FatError::Corrupted)?;
+            if self.is_exfat() {
+                entry[0] &= !dir::IN_USE;
+            } else {
+                entry[0] = dir::DELETED;
+            }
+            self.write_entry(&location.dir, i, &entry)?;
+        }
+        Ok(())
+    }
+
+    /// Create an empty file or directory at ``path``.
+    fn create(&mut self, path: &str, attributes: Attributes) -> Result<Node, D> {
+        self.check_writable()?;
+        let (mut parent, name) = self.resolve_parent(path)?;
+        if self.find(&parent, name)?.is_some() {
+            return Err(FatError::AlreadyExists);
+        }
+        let now = (self.clock)();
+        let mut info = Info {
+            attributes,
+            created: now,
+            modified: now,
+            first_cluster: 0,
+            size: 0,
+            valid_size: 0,
+            contiguous: false,
+        };
+        if attributes.contains(Attributes::DIRECTORY) {
+            // Directories always have a cluster, with the dot entries on FAT.
+            self.resize(&mut info, 1)?;
+            self.zero_cluster(info.first_cluster)?;
+            if self.is_exfat() {
+                info.size = self.volume.cluster_size() as u64;
+                info.valid_size = info.size;
+            } else {
+                let dir = Dir::Clusters {
+                    first: info.first_cluster,
+                    contiguous: false,
+                    size: 0,
+                };
+                let parent_cluster = match self.dir(&parent) {
+                    Dir::Clusters { first, .. } if parent.location.is_some() => first,
+                    _ => 0,
+                };
+                for (i, (name, cluster)) in [(".", info.first_cluster), ("..", parent_cluster)]
+                    .into_iter()
+                    .enumerate()
+                {
+                    let mut short = [b' '; 11];
+                    short[..name.len()].copy_from_slice(name.as_bytes());
+                    let mut dot_info = info.clone();
+                    dot_info.first_cluster = cluster;
+                    let entry = ShortEntry {
+                        name: short,
+                        case: 0,
+                        info: dot_info,
+                    };
+                    self.write_entry(&dir, i as u32, &entry.encode())?;
+                }
+            }
+        }
+        let entries = self.encode_entries(&parent, name, info.clone())?;
+        let location = match self.add_entries(&mut parent, &entries) {
+            Ok(location) => location,
+            Err(error) => {
+                self.resize(&mut info, 0)?;
+                return Err(error);
+            }
+        };
+        Ok(Node {
+            info,
+            location: Some(location),
+        })
+    }
+
+    fn remove(&mut self, path: &str) -> Result<(), D> {
+        self.check_writable()?;
+        let mut node = self.resolve(path)?;
+        let location = node.location.ok_or(FatError::Invalid)?;
+        if node.is_dir() {
+            let dir = self.dir(&node);
+            if self.next_entry(&dir, &mut 0)?.is_some() {
+                return Err(FatError::NotEmpty);
+            }
+        }
+        self.remove_entries(&location)?;
+        self.resize(&mut node.info, 0)
+    }
+}
+
+/// A FAT filesystem on a block device.
+pub struct FileSystem<D: BlockDevice> {
+    inner: RefCell<Inner<D>>,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    /// Mount the volume on ``device``.
+    pub fn new(mut device: D) -> core::result::Result<FileSystem<D>, FatError<D::Error>> {
+        let block_size = device.block_size();
+        let mut sector = vec![0; block_size];
+        device
+            .read_blocks(0, &mut sector)
+            .map_err(FatError::Device)?;
+        let (offset, volume) = match Volume::parse(&sector) {
+            Some(volume) => (0, volume),
+            None => {
+                let starts: Vec<u64> = boot::partitions(&sector).collect();
+                let mut found = None;
+                for start in starts {
+                    device
+                        .read_blocks(start, &mut sector)
+                        .map_err(FatError::Device)?;
+                    if let Some(volume) = Volume::parse(&sector) {
+                        found = Some((start, volume));
+                        break;
+                    }
+                }
+                found.ok_or(FatError::NoFilesystem)?
+            }
+        };
+        if volume.sector_size != block_size {
+            return Err(FatError::Unsupported);
+        }
+        let kind = volume.kind;
+        let mut inner = Inner {
+            device,
+            cache: Cache::new(block_size, offset, CACHE_SECTORS),
+            volume,
+            upcase: UpcaseTable::ascii(),
+            bitmap: Vec::new(),
+            next_free: 2,
+            free_count: None,
+            info_dirty: false,
+            clock: default_clock,
+        };
+        match kind {
+            FatKind::ExFat => inner.load_exfat()?,
+            FatKind::Fat32 => inner.load_fs_info()?,
+            _ => (),
+        }
+        Ok(FileSystem {
+            inner: RefCell::new(inner),
+        })
+    }
+
+    /// Use ``clock`` to timestamp the files created and modified.
+    pub fn with_clock(self, clock: fn() -> Timestamp) -> FileSystem<D> {
+        self.inner.borrow_mut().clock = clock;
+        self
+    }
+
+    /// Get the flavour of FAT of the volume.
+    pub fn kind(&self) -> FatKind {
+        self.inner.borrow().volume.kind
+    }
+
+    /// Get the size of a cluster, the unit in which space is allocated.
+    pub fn cluster_size(&self) -> usize {
+        self.inner.borrow().volume.cluster_size()
+    }
+
+    /// Get information about the file or directory at ``path``.
+    pub fn metadata(&self, path: &str) -> core::result::Result<Metadata, FatError<D::Error>> {
+        let node = self.inner.borrow_mut().resolve(path)?;
+        Ok(Metadata::new(&node.info))
+    }
+
+    /// Iterate over the entries of the directory at ``path``.
+    pub fn read_dir(&self, path: &str) -> core::result::Result<ReadDir<'_, D>, FatError<D::Error>> {
+        let mut inner = self.inner.borrow_mut();
+        let node = inner.resolve(path)?;
+        if !node.is_dir() {
+            return Err(FatError::NotADirectory);
+        }
+        Ok(ReadDir {
+            fs: self,
+            dir: inner.dir(&node),
+            index: 0,
+            done: false,
+        })
+    }
+
+    fn open_node(&self, path: &str) -> Result<Node, D> {
+        let node = self.inner.borrow_mut().resolve(path)?;
+        if node.is_dir() {
+            return Err(FatError::IsADirectory);
+        }
+        Ok(node)
+    }
+
+    fn create_node(&self, path: &str) -> Result<Node, D> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.resolve(path) {
+            Ok(node) if node.is_dir() => Err(FatError::IsADirectory),
+            Ok(node) => Ok(node),
+            Err(FatError::NotFound) => inner.create(path, Attributes::ARCHIVE),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Open the existing file at ``path`` for reading and writing.
+    pub fn open(&self, path: &str) -> core::result::Result<File<'_, D>, FatError<D::Error>> {
+        Ok(File::new(FsRef::Borrowed(self), self.open_node(path)?))
+    }
+
+    /// Create an empty file at ``path``, or truncate the existing one, and open it for reading
+    /// and writing.
+    pub fn create(&self, path: &str) -> core::result::Result<File<'_, D>, FatError<D::Error>> {
+        let mut file = File::new(FsRef::Borrowed(self), self.create_node(path)?);
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    /// Same as [`FileSystem::open`], but the file keeps the filesystem alive instead of
+    /// borrowing it.
+    pub fn open_shared(
+        self: &Rc<Self>,
+        path: &str,
+    ) -> core::result::Result<File<'static, D>, FatError<D::Error>> {
+        Ok(File::new(
+            FsRef::Shared(self.clone()),
+            self.open_node(path)?,
+        ))
+    }
+
+    /// Same as [`FileSystem::create`], but the file keeps the filesystem alive instead of
+    /// borrowing it.
+    pub fn create_shared(
+        self: &Rc<Self>,
+        path: &str,
+    ) -> core::result::Result<File<'static, D>, FatError<D::Error>> {
+        let mut file = File::new(FsRef::Shared(self.clone()), self.create_node(path)?);
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    /// Create an empty directory at ``path``.
+    pub fn create_dir(&self, path: &str) -> core::result::Result<(), FatError<D::Error>> {
+        self.inner
+            .borrow_mut()
+            .create(path, Attributes::DIRECTORY)
+            .map(|_| ())
+    }
+
+    /// Remove the file or empty directory at ``path``.
+    pub fn remove(&self, path: &str) -> core::result::Result<(), FatError<D::Error>> {
+        self.inner.borrow_mut().remove(path)
+    }
+
+    /// Read the whole file at ``path``.
+    pub fn read(&self, path: &str) -> core::result::Result<Vec<u8>, FatError<D::Error>> {
+        let mut file = self.open(path)?;
+        let mut data = vec![0; file.len() as usize];
+        embedded_io::Read::read_exact(&mut file, &mut data).map_err(|error| match error {
+            embedded_io::ReadExactError::UnexpectedEof => FatError::Corrupted,
+            embedded_io::ReadExactError::Other(error) => error,
+        })?;
+        Ok(data)
+    }
+
+    /// Replace the content of the file at ``path`` with ``data``, creating it if needed.
+    pub fn write(&self, path: &str, data: &[u8]) -> core::result::Result<(), FatError<D::Error>> {
+        let mut file = self.create(path)?;
+        embedded_io::Write::write_all(&mut file, data)?;
+        embedded_io::Write::flush(&mut file)
+    }
+
+    /// Write everything modified back to the device.
+    pub fn flush(&self) -> core::result::Result<(), FatError<D::Error>> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+impl<D: BlockDevice> Drop for FileSystem<D> {
+    fn drop(&mut self) {
+        // There is no way to report a failure from here, call flush() to know about it.
+        let _ = self.inner.get_mut().flush();
+    }
+}
+
+/// Iterator over the entries of a directory, returned by [`FileSystem::read_dir`].
+pub struct ReadDir<'a, D: BlockDevice> {
+    fs: &'a FileSystem<D>,
+    dir: Dir,
+    index: u32,
+    done: bool,
+}
+
+impl<D: BlockDevice> Iterator for ReadDir<'_, D> {
+    type Item = core::result::Result<DirEntry, FatError<D::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let found = self
+            .fs
+            .inner
+            .borrow_mut()
+            .next_entry(&self.dir, &mut self.index);
+        match found {
+            Ok(Some(found)) => Some(Ok(DirEntry {
+                name: name::decode(&found.name),
+                metadata: Metadata::new(&found.node.info),
+            })),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
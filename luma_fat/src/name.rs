@@ -0,0 +1,238 @@
+//! ``name`` module of ``luma_fat``.
+//!
+//! Contains the handling of names: validation, 8.3 short names and their long name checksum,
+//! and the case folding and hashing of exFAT.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Longest name, in UTF-16 units.
+pub(super) const MAX_NAME: usize = 255;
+
+/// Characters which can’t appear in any name.
+const FORBIDDEN: &[char] = &['"', '*', '/', ':', '<', '>', '?', '\\', '|'];
+
+/// Characters which can appear in a long name but not in a short one.
+const LONG_ONLY: &[char] = &['+', ',', ';', '=', '[', ']'];
+
+/// Short name flag telling the base name is in lower case, as set by Windows NT.
+pub(super) const LOWER_BASE: u8 = 0x08;
+
+/// Short name flag telling the extension is in lower case.
+pub(super) const LOWER_EXTENSION: u8 = 0x10;
+
+/// Whether ``name`` can be given to a file or directory.
+pub(super) fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.ends_with(['.', ' '])
+        && name.encode_utf16().count() <= MAX_NAME
+        && !name.chars().any(|c| c < ' ' || FORBIDDEN.contains(&c))
+}
+
+/// Compare two names the way FAT does, ignoring the case.
+pub(super) fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_uppercase)
+        .eq(b.chars().flat_map(char::to_uppercase))
+}
+
+fn is_short_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"!#$%&'()-@^_`{}~".contains(&c)
+}
+
+/// Get the short name ``name`` can be stored as without a long name, along with the flags
+/// telling which of its parts are in lower case.
+pub(super) fn short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 {
+        return None;
+    }
+    let mut flags = 0;
+    for (part, flag) in [(base, LOWER_BASE), (extension, LOWER_EXTENSION)] {
+        // Each part has to be entirely in one case.
+        if part.bytes().any(|c| c.is_ascii_lowercase()) {
+            if part.bytes().any(|c| c.is_ascii_uppercase()) {
+                return None;
+            }
+            flags |= flag;
+        }
+    }
+    let mut short = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        short[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in extension.bytes().enumerate() {
+        short[8 + i] = c.to_ascii_uppercase();
+    }
+    if !short.iter().all(|&c| c == b' ' || is_short_char(c)) || short[0] == b' ' {
+        return None;
+    }
+    // Some names are only ever found inside the first byte to tell entries apart.
+    if short[0] == 0xe5 {
+        short[0] = 0x05;
+    }
+    Some((short, flags))
+}
+
+/// Get the short name to derive a unique one from for ``name``, which needs a long name.
+pub(super) fn short_basis(name: &str) -> [u8; 11] {
+    let name = name.trim_start_matches(['.', ' ']);
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, ""),
+    };
+    let convert = |c: char| {
+        if c == ' ' || c == '.' {
+            None
+        } else if LONG_ONLY.contains(&c) || !c.is_ascii() {
+            Some(b'_')
+        } else {
+            Some(c.to_ascii_uppercase() as u8)
+        }
+    };
+    let mut short = [b' '; 11];
+    for (i, c) in base.chars().filter_map(convert).take(8).enumerate() {
+        short[i] = c;
+    }
+    for (i, c) in extension.chars().filter_map(convert).take(3).enumerate() {
+        short[8 + i] = c;
+    }
+    if short[0] == b' ' {
+        short[0] = b'_';
+    }
+    short
+}
+
+/// Get the ``n``-th unique variant of ``basis``, such as ``LONGNA~1.TXT``.
+pub(super) fn with_tail(basis: &[u8; 11], n: u32) -> [u8; 11] {
+    let mut tail = [0; 8];
+    let mut length = 0;
+    let mut value = n;
+    while value > 0 {
+        tail[7 - length] = b'0' + (value % 10) as u8;
+        value /= 10;
+        length += 1;
+    }
+    tail[7 - length] = b'~';
+    let tail = &tail[7 - length..];
+    let base = basis[..8].iter().position(|&c| c == b' ').unwrap_or(8);
+    let start = base.min(8 - tail.len());
+    let mut short = *basis;
+    short[start..start + tail.len()].copy_from_slice(tail);
+    short[start + tail.len()..8].fill(b' ');
+    short
+}
+
+/// Get the checksum of a short name, stored in each of its long name entries.
+pub(super) fn checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+/// Get the name a short name is displayed as.
+pub(super) fn short_to_string(short: &[u8; 11], flags: u8) -> String {
+    let mut name = String::new();
+    let part = |bytes: &[u8], lower: bool, name: &mut String| {
+        for (i, &c) in bytes.iter().enumerate() {
+            let c = if i == 0 && c == 0x05 { 0xe5 } else { c };
+            let c = if lower { c.to_ascii_lowercase() } else { c };
+            // Other bytes are in the OEM code page, which isn’t known.
+            name.push(if c.is_ascii() { c as char } else { '_' });
+        }
+    };
+    let base = short[..8].trim_ascii_end();
+    let extension = short[8..].trim_ascii_end();
+    part(base, flags & LOWER_BASE != 0, &mut name);
+    if !extension.is_empty() {
+        name.push('.');
+        part(extension, flags & LOWER_EXTENSION != 0, &mut name);
+    }
+    name
+}
+
+/// The upper case table of an exFAT volume, kept as the characters it doesn’t map to
+/// themselves.
+pub(super) struct UpcaseTable {
+    mappings: Vec<(u16, u16)>,
+}
+
+impl UpcaseTable {
+    /// Get the table used when a volume lacks one, which only knows about ASCII.
+    pub fn ascii() -> UpcaseTable {
+        UpcaseTable {
+            mappings: (b'a'..=b'z')
+                .map(|c| (c as u16, c.to_ascii_uppercase() as u16))
+                .collect(),
+        }
+    }
+
+    /// Parse a table as stored on the volume, where runs of identity mappings may be
+    /// compressed as 0xffff followed by their length.
+    pub fn parse(data: &[u8]) -> UpcaseTable {
+        let mut mappings = Vec::new();
+        let mut units = data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]));
+        let mut c = 0u32;
+        while let Some(unit) = units.next() {
+            if c > 0xffff {
+                break;
+            }
+            if unit == 0xffff {
+                c += units.next().unwrap_or(0) as u32;
+                continue;
+            }
+            if unit as u32 != c {
+                mappings.push((c as u16, unit));
+            }
+            c += 1;
+        }
+        UpcaseTable { mappings }
+    }
+
+    /// Get the upper case version of ``c``.
+    pub fn map(&self, c: u16) -> u16 {
+        match self.mappings.binary_search_by_key(&c, |&(from, _)| from) {
+            Ok(i) => self.mappings[i].1,
+            Err(_) => c,
+        }
+    }
+
+    /// Compare two names ignoring the case.
+    pub fn eq(&self, a: &[u16], b: &[u16]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).all(|(&a, &b)| self.map(a) == self.map(b))
+    }
+
+    /// Get the hash of a name stored in its stream extension entry.
+    pub fn hash(&self, name: &[u16]) -> u16 {
+        name.iter()
+            .flat_map(|&c| self.map(c).to_le_bytes())
+            .fold(0u16, |hash, byte| {
+                hash.rotate_right(1).wrapping_add(byte as u16)
+            })
+    }
+}
+
+/// Get the checksum of an exFAT entry set, which skips its own field in the first entry.
+pub(super) fn set_checksum(entries: &[u8]) -> u16 {
+    entries
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 2 && i != 3)
+        .fold(0u16, |sum, (_, &byte)| {
+            sum.rotate_right(1).wrapping_add(byte as u16)
+        })
+}
+
+/// Decode a UTF-16 name, replacing anything invalid.
+pub(super) fn decode(name: &[u16]) -> String {
+    char::decode_utf16(name.iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
@@ -0,0 +1,357 @@
+//! ``table`` module of ``luma_fat``.
+//!
+//! Contains the handling of clusters: following and linking them in the FAT, and allocating
+//! them, which exFAT tracks in a bitmap of its own.
+
+use super::dir::Info;
+use super::{FatError, FatKind, Inner, Result};
+use crate::block::BlockDevice;
+use alloc::vec;
+use alloc::vec::Vec;
+
+impl<D: BlockDevice> Inner<D> {
+    /// Get the entry of ``cluster`` in the FAT, as stored.
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, D> {
+        let sector_size = self.volume.sector_size as u64;
+        let fat_start = self.volume.fat_start;
+        let byte = |inner: &mut Inner<D>, offset: u64| -> Result<u8, D> {
+            let content = inner.read_sector(fat_start + offset / sector_size)?;
+            Ok(content[(offset % sector_size) as usize])
+        };
+        Ok(match self.volume.kind {
+            FatKind::Fat12 => {
+                // Entries are a byte and a half, and may straddle two sectors.
+                let offset = cluster as u64 * 3 / 2;
+                let value = byte(self, offset)? as u32 | (byte(self, offset + 1)? as u32) << 8;
+                if cluster & 1 == 1 {
+                    value >> 4
+                } else {
+                    value & 0xfff
+                }
+            }
+            FatKind::Fat16 => {
+                let offset = cluster as u64 * 2;
+                let content = self.read_sector(fat_start + offset / sector_size)?;
+                super::dir::u16_at(content, (offset % sector_size) as usize) as u32
+            }
+            FatKind::Fat32 | FatKind::ExFat => {
+                let offset = cluster as u64 * 4;
+                let content = self.read_sector(fat_start + offset / sector_size)?;
+                let value = super::dir::u32_at(content, (offset % sector_size) as usize);
+                if self.volume.kind == FatKind::Fat32 {
+                    value & 0x0fff_ffff
+                } else {
+                    value
+                }
+            }
+        })
+    }
+
+    /// Set the entry of ``cluster`` in every copy of the FAT.
+    pub(super) fn set_fat(&mut self, cluster: u32, value: u32) -> Result<(), D> {
+        let sector_size = self.volume.sector_size as u64;
+        for copy in 0..self.volume.fat_count as u64 {
+            let fat_start = self.volume.fat_start + copy * self.volume.fat_sectors;
+            match self.volume.kind {
+                FatKind::Fat12 => {
+                    let offset = cluster as u64 * 3 / 2;
+                    for (i, shift) in [(0, 0), (1, 8)] {
+                        let offset = offset + i;
+                        let content = self.write_sector(fat_start + offset / sector_size)?;
+                        let byte = &mut content[(offset % sector_size) as usize];
+                        let (mask, bits) = if cluster & 1 == 1 {
+                            (0x000f_u32, (value & 0xfff) << 4)
+                        } else {
+                            (0xf000_u32, value & 0xfff)
+                        };
+                        *byte = (*byte as u32 & (mask >> shift) | (bits >> shift) & 0xff) as u8;
+                    }
+                }
+                FatKind::Fat16 => {
+                    let offset = cluster as u64 * 2;
+                    let content = self.write_sector(fat_start + offset / sector_size)?;
+                    let offset = (offset % sector_size) as usize;
+                    content[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+                }
+                FatKind::Fat32 | FatKind::ExFat => {
+                    let fat32 = self.volume.kind == FatKind::Fat32;
+                    let offset = cluster as u64 * 4;
+                    let content = self.write_sector(fat_start + offset / sector_size)?;
+                    let offset = (offset % sector_size) as usize;
+                    let mut value = value;
+                    if fat32 {
+                        // The top four bits are reserved and kept as they are.
+                        let old = super::dir::u32_at(content, offset);
+                        value = old & 0xf000_0000 | value & 0x0fff_ffff;
+                    }
+                    content[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the value ending a chain of clusters.
+    fn end_of_chain(&self) -> u32 {
+        match self.volume.kind {
+            FatKind::Fat12 => 0xfff,
+            FatKind::Fat16 => 0xffff,
+            FatKind::Fat32 => 0x0fff_ffff,
+            FatKind::ExFat => 0xffff_ffff,
+        }
+    }
+
+    /// Get the cluster following ``cluster`` in its chain, if any.
+    pub(super) fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, D> {
+        let value = self.fat_entry(cluster)?;
+        let end = match self.volume.kind {
+            FatKind::Fat12 => 0xff8,
+            FatKind::Fat16 => 0xfff8,
+            FatKind::Fat32 => 0x0fff_fff8,
+            FatKind::ExFat => 0xffff_fff8,
+        };
+        if value >= end {
+            Ok(None)
+        } else if self.volume.is_cluster(value) {
+            Ok(Some(value))
+        } else {
+            Err(FatError::Corrupted)
+        }
+    }
+
+    /// Get the ``n``-th cluster of the chain starting at ``first``, if it is that long.
+    pub(super) fn cluster_at(
+        &mut self,
+        first: u32,
+        contiguous: bool,
+        n: u32,
+    ) -> Result<Option<u32>, D> {
+        if contiguous {
+            let cluster = first + n;
+            return Ok(self.volume.is_cluster(cluster).then_some(cluster));
+        }
+        let mut cluster = first;
+        for _ in 0..n {
+            match self.next_cluster(cluster)? {
+                Some(next) => cluster = next,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(cluster))
+    }
+
+    /// Get the clusters holding ``length`` bytes from ``first``, for the exFAT metadata.  These
+    /// are chained in the FAT, but some formatters leave it empty as they are contiguous.
+    pub(super) fn clusters_of(&mut self, first: u32, length: u64) -> Result<Vec<u32>, D> {
+        let count = length.div_ceil(self.volume.cluster_size() as u64);
+        let mut clusters = Vec::with_capacity(count as usize);
+        let mut cluster = first;
+        for i in 0..count {
+            if !self.volume.is_cluster(cluster) {
+                return Err(FatError::Corrupted);
+            }
+            clusters.push(cluster);
+            if i + 1 < count {
+                cluster = match self.fat_entry(cluster)? {
+                    0 => cluster + 1,
+                    _ => self.next_cluster(cluster)?.ok_or(FatError::Corrupted)?,
+                };
+            }
+        }
+        Ok(clusters)
+    }
+
+    /// Get the amount of clusters allocated to ``info``.
+    pub(super) fn chain_length(&mut self, info: &Info) -> Result<u32, D> {
+        if info.first_cluster == 0 {
+            return Ok(0);
+        }
+        if info.contiguous {
+            let length = info.size.div_ceil(self.volume.cluster_size() as u64);
+            return Ok(length as u32);
+        }
+        let mut length = 1;
+        let mut cluster = info.first_cluster;
+        while let Some(next) = self.next_cluster(cluster)? {
+            cluster = next;
+            length += 1;
+            if length > self.volume.cluster_count {
+                return Err(FatError::Corrupted);
+            }
+        }
+        Ok(length)
+    }
+
+    /// Get the sector and bit of ``cluster`` in the exFAT allocation bitmap.
+    fn bitmap_position(&self, cluster: u32) -> Result<(u64, usize, u8), D> {
+        let index = (cluster - 2) as usize;
+        let byte = index / 8;
+        let cluster_size = self.volume.cluster_size();
+        let sector_size = self.volume.sector_size;
+        let bitmap_cluster = *self
+            .bitmap
+            .get(byte / cluster_size)
+            .ok_or(FatError::Corrupted)?;
+        let sector =
+            self.volume.cluster_sector(bitmap_cluster) + (byte % cluster_size / sector_size) as u64;
+        Ok((sector, byte % sector_size, 1 << (index % 8)))
+    }
+
+    fn is_free(&mut self, cluster: u32) -> Result<bool, D> {
+        if self.volume.kind == FatKind::ExFat {
+            let (sector, offset, bit) = self.bitmap_position(cluster)?;
+            Ok(self.read_sector(sector)?[offset] & bit == 0)
+        } else {
+            Ok(self.fat_entry(cluster)? == 0)
+        }
+    }
+
+    /// Mark ``cluster`` as allocated or free.  On FAT an allocated cluster ends its chain.
+    fn set_allocated(&mut self, cluster: u32, allocated: bool) -> Result<(), D> {
+        if self.volume.kind == FatKind::ExFat {
+            let (sector, offset, bit) = self.bitmap_position(cluster)?;
+            let content = self.write_sector(sector)?;
+            if allocated {
+                content[offset] |= bit;
+            } else {
+                content[offset] &= !bit;
+            }
+        } else {
+            let value = if allocated { self.end_of_chain() } else { 0 };
+            self.set_fat(cluster, value)?;
+        }
+        if let Some(free_count) = &mut self.free_count {
+            *free_count = if allocated {
+                free_count.saturating_sub(1)
+            } else {
+                *free_count + 1
+            };
+        }
+        self.info_dirty = true;
+        Ok(())
+    }
+
+    /// Allocate a free cluster, looking from ``near`` first.
+    fn allocate(&mut self, near: u32) -> Result<u32, D> {
+        let count = self.volume.cluster_count;
+        let start = if self.volume.is_cluster(near) {
+            near
+        } else {
+            self.next_free
+        };
+        for i in 0..count {
+            let cluster = 2 + (start - 2 + i) % count;
+            if self.is_free(cluster)? {
+                self.set_allocated(cluster, true)?;
+                self.next_free = if self.volume.is_cluster(cluster + 1) {
+                    cluster + 1
+                } else {
+                    2
+                };
+                return Ok(cluster);
+            }
+        }
+        Err(FatError::NoSpace)
+    }
+
+    /// Free the chain starting at ``first``.
+    fn free_chain(&mut self, first: u32) -> Result<(), D> {
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            cluster = self.next_cluster(current)?;
+            self.set_allocated(current, false)?;
+            if self.volume.kind == FatKind::ExFat {
+                self.set_fat(current, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Grow or shrink the clusters of ``info`` to ``clusters``.  Its size has to be the one
+    /// before the change, as that is what tells how many contiguous clusters it has on exFAT.
+    pub(super) fn resize(&mut self, info: &mut Info, clusters: u32) -> Result<(), D> {
+        self.check_writable()?;
+        let length = self.chain_length(info)?;
+        if clusters > length {
+            let mut grown = length;
+            if let Err(error) = self.grow(info, &mut grown, clusters) {
+                // Give back what got allocated before running out of space.
+                self.shrink(info, grown, length)?;
+                return Err(error);
+            }
+        } else if clusters < length {
+            self.shrink(info, length, clusters)?;
+        }
+        Ok(())
+    }
+
+    /// Grow the chain of ``info`` to ``clusters``, keeping ``length`` up to date.
+    fn grow(&mut self, info: &mut Info, length: &mut u32, clusters: u32) -> Result<(), D> {
+        let mut last = if *length == 0 {
+            let cluster = self.allocate(self.next_free)?;
+            info.first_cluster = cluster;
+            // Fresh exFAT chains start out contiguous, outside of the FAT.
+            info.contiguous = self.volume.kind == FatKind::ExFat;
+            *length = 1;
+            cluster
+        } else {
+            self.cluster_at(info.first_cluster, info.contiguous, *length - 1)?
+                .ok_or(FatError::Corrupted)?
+        };
+        while *length < clusters {
+            if info.contiguous {
+                let next = last + 1;
+                if self.volume.is_cluster(next) && self.is_free(next)? {
+                    self.set_allocated(next, true)?;
+                    last = next;
+                    *length += 1;
+                    continue;
+                }
+                // The chain can’t stay contiguous, so it has to go in the FAT.
+                for i in 0..*length - 1 {
+                    self.set_fat(info.first_cluster + i, info.first_cluster + i + 1)?;
+                }
+                self.set_fat(last, self.end_of_chain())?;
+                info.contiguous = false;
+            }
+            let cluster = self.allocate(last + 1)?;
+            self.set_fat(cluster, self.end_of_chain())?;
+            self.set_fat(last, cluster)?;
+            last = cluster;
+            *length += 1;
+        }
+        Ok(())
+    }
+
+    fn shrink(&mut self, info: &mut Info, length: u32, clusters: u32) -> Result<(), D> {
+        if info.contiguous {
+            for i in clusters..length {
+                self.set_allocated(info.first_cluster + i, false)?;
+            }
+        } else if clusters == 0 {
+            self.free_chain(info.first_cluster)?;
+        } else {
+            let last = self
+                .cluster_at(info.first_cluster, false, clusters - 1)?
+                .ok_or(FatError::Corrupted)?;
+            if let Some(next) = self.next_cluster(last)? {
+                self.free_chain(next)?;
+            }
+            self.set_fat(last, self.end_of_chain())?;
+        }
+        if clusters == 0 {
+            info.first_cluster = 0;
+            info.contiguous = false;
+        }
+        Ok(())
+    }
+
+    /// Fill ``cluster`` with zeroes.
+    pub(super) fn zero_cluster(&mut self, cluster: u32) -> Result<(), D> {
+        let zeroes = vec![0; self.volume.cluster_size()];
+        let sector = self.volume.cluster_sector(cluster);
+        self.cache
+            .write_direct(&mut self.device, sector, &zeroes)
+            .map_err(FatError::Device)
+    }
+}
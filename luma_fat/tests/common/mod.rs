@@ -0,0 +1,131 @@
+//! Disk image files used as block devices by the tests.
+
+#![allow(dead_code)]
+
+use luma_fat::block::BlockDevice;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read as _};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// A disk image in a temporary file, removed once dropped.
+pub struct Image {
+    path: PathBuf,
+    file: File,
+}
+
+impl Image {
+    /// Create an image of ``size`` bytes, formatted by ``fatfs``.
+    pub fn format(size: u64, kind: fatfs::FatType, cluster_size: u32) -> Image {
+        let image = Image::empty(size);
+        let options = fatfs::FormatVolumeOptions::new()
+            .fat_type(kind)
+            .bytes_per_cluster(cluster_size);
+        fatfs::format_volume(image.open(), options).unwrap();
+        image
+    }
+
+    /// Create an image of ``size`` bytes of zeroes.
+    pub fn empty(size: u64) -> Image {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "luma_fat-{}-{}.img",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(size).unwrap();
+        Image { path, file }
+    }
+
+    /// Create an image holding the gzipped fixture at ``path``.
+    pub fn fixture(path: &str) -> Image {
+        let path = format!("{}/tests/fixtures/{path}", env!("CARGO_MANIFEST_DIR"));
+        let compressed = std::fs::read(path).unwrap();
+        let mut data = Vec::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut data)
+            .unwrap();
+        let image = Image::empty(data.len() as u64);
+        image.write_at(0, &data);
+        image
+    }
+
+    /// Open the file again, for another filesystem implementation to look at it.
+    pub fn open(&self) -> File {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.path)
+            .unwrap()
+    }
+
+    /// Open the image as a block device, with a handle of its own.
+    pub fn device(&self) -> Device {
+        let file = self.open();
+        let blocks = file.metadata().unwrap().len() / SECTOR_SIZE as u64;
+        Device { file, blocks }
+    }
+
+    /// Mount the image with ``fatfs``.
+    pub fn fatfs(&self) -> fatfs::FileSystem<File> {
+        fatfs::FileSystem::new(self.open(), fatfs::FsOptions::new()).unwrap()
+    }
+
+    /// Write ``data`` at ``offset`` in the image.
+    pub fn write_at(&self, offset: u64, data: &[u8]) {
+        self.file.write_all_at(data, offset).unwrap();
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// An image file read and written in sectors.
+pub struct Device {
+    file: File,
+    blocks: u64,
+}
+
+impl BlockDevice for Device {
+    type Error = io::ErrorKind;
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), io::ErrorKind> {
+        self.file
+            .read_exact_at(buffer, block * SECTOR_SIZE as u64)
+            .map_err(|error| error.kind())
+    }
+
+    fn write_blocks(&mut self, block: u64, buffer: &[u8]) -> Result<(), io::ErrorKind> {
+        self.file
+            .write_all_at(buffer, block * SECTOR_SIZE as u64)
+            .map_err(|error| error.kind())
+    }
+}
+
+/// Get ``len`` bytes which differ from one sector to the next.
+pub fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u32 * 31 + seed as u32 + (i as u32 >> 9)) as u8)
+        .collect()
+}
@@ -0,0 +1,201 @@
+//! An exFAT volume of 4 MiB with clusters of 4 KiB, in ``fixtures/exfat.img.gz``, formatted
+//! and populated by another implementation with:
+//!
+//! - ``Contiguous File.bin``, ``pattern(3 × 4096 + 5, 3)``, without a FAT chain,
+//! - ``fragmented.bin``, ``pattern(3 × 4096, 9)``, whose clusters have a hole between them,
+//! - ``prealloc.dat``, two clusters of which only the first 10 bytes are valid,
+//! - ``Ünïcode directory``, holding an empty ``inner.txt``.
+
+mod common;
+
+use common::{Image, pattern};
+use embedded_io::{Read as _, Seek as _, SeekFrom, Write as _};
+use luma_fat::{FatError, FatKind, FileSystem};
+
+const CLUSTER_SIZE: usize = 4096;
+
+fn fixture() -> Image {
+    Image::fixture("exfat.img.gz")
+}
+
+#[test]
+fn contents() {
+    let image = fixture();
+    let fs = FileSystem::new(image.device()).unwrap();
+    assert_eq!(fs.kind(), FatKind::ExFat);
+    assert_eq!(fs.cluster_size(), CLUSTER_SIZE);
+
+    let mut names: Vec<_> = fs
+        .read_dir("/")
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .map(|entry| (entry.name, entry.metadata.len))
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            ("Contiguous File.bin".to_string(), 3 * 4096 + 5),
+            ("fragmented.bin".to_string(), 3 * 4096),
+            ("prealloc.dat".to_string(), 2 * 4096),
+            ("Ünïcode directory".to_string(), 0),
+        ]
+    );
+
+    assert_eq!(
+        fs.read("contiguous file.BIN").unwrap(),
+        pattern(3 * CLUSTER_SIZE + 5, 3)
+    );
+    assert_eq!(
+        fs.read("fragmented.bin").unwrap(),
+        pattern(3 * CLUSTER_SIZE, 9)
+    );
+    // Past its valid length, a file reads as zeroes.
+    let prealloc = fs.read("prealloc.dat").unwrap();
+    assert_eq!(prealloc.len(), 2 * CLUSTER_SIZE);
+    assert_eq!(prealloc[..10], pattern(10, 5));
+    assert!(prealloc[10..].iter().all(|&byte| byte == 0));
+
+    assert!(fs.metadata("ÜNÏCODE DIRECTORY").unwrap().is_dir());
+    let inner: Vec<_> = fs
+        .read_dir("Ünïcode directory")
+        .unwrap()
+        .map(|entry| entry.unwrap().name)
+        .collect();
+    assert_eq!(inner, ["inner.txt"]);
+    assert_eq!(fs.read("ünïcode directory/INNER.TXT").unwrap(), b"");
+}
+
+#[test]
+fn directories() {
+    let image = fixture();
+    {
+        let fs = FileSystem::new(image.device()).unwrap();
+        fs.create_dir("A Long Directory Name").unwrap();
+        // Entry sets of several entries each, filling more than a cluster.
+        for i in 0..60 {
+            let path = format!(
+                "A Long Directory Name/entry number {i} with a name needing three entries.txt"
+            );
+            fs.write(&path, i.to_string().as_bytes()).unwrap();
+        }
+        assert_eq!(fs.read_dir("a long directory name").unwrap().count(), 60);
+        assert_eq!(fs.remove("A Long Directory Name"), Err(FatError::NotEmpty));
+        for i in (0..60).step_by(3) {
+            let path = format!(
+                "A Long Directory Name/entry number {i} with a name needing three entries.txt"
+            );
+            fs.remove(&path).unwrap();
+        }
+        fs.remove("Ünïcode directory/inner.txt").unwrap();
+        fs.remove("Ünïcode directory").unwrap();
+        assert_eq!(
+            fs.metadata("Ünïcode directory").err(),
+            Some(FatError::NotFound)
+        );
+    }
+
+    let fs = FileSystem::new(image.device()).unwrap();
+    assert_eq!(fs.read_dir("").unwrap().count(), 4);
+    let names: Vec<_> = fs
+        .read_dir("A Long Directory Name")
+        .unwrap()
+        .map(|entry| entry.unwrap().name)
+        .collect();
+    assert_eq!(names.len(), 40);
+    assert!(!names.iter().any(|name| name.starts_with("entry number 0 ")));
+    assert_eq!(
+        fs.read("A Long Directory Name/entry number 59 with a name needing three entries.txt")
+            .unwrap(),
+        b"59"
+    );
+}
+
+#[test]
+fn write_and_truncate() {
+    let image = fixture();
+    let mut contiguous = pattern(3 * CLUSTER_SIZE + 5, 3);
+    {
+        let fs = FileSystem::new(image.device()).unwrap();
+        // Growing a file without a FAT chain past a used cluster has to give it one.
+        let mut file = fs.open("Contiguous File.bin").unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(&pattern(2 * CLUSTER_SIZE, 4)).unwrap();
+        drop(file);
+        contiguous.extend(pattern(2 * CLUSTER_SIZE, 4));
+        assert_eq!(fs.read("Contiguous File.bin").unwrap(), contiguous);
+
+        // Writing past the valid length fills the gap with zeroes.
+        let mut file = fs.open("prealloc.dat").unwrap();
+        file.seek(SeekFrom::Start(100)).unwrap();
+        file.write_all(b"xyz").unwrap();
+        drop(file);
+
+        // Shrink the fragmented file to part of its first cluster.
+        let mut file = fs.open("fragmented.bin").unwrap();
+        file.seek(SeekFrom::Start(1000)).unwrap();
+        file.truncate().unwrap();
+        let mut data = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut buffer = [0; 512];
+        loop {
+            let read = file.read(&mut buffer).unwrap();
+            if read == 0 {
+                break;
+            }
+            data.extend_from_slice(&buffer[..read]);
+        }
+        assert_eq!(data, pattern(1000, 9));
+
+        fs.write("new file.bin", &pattern(5 * CLUSTER_SIZE + 1, 6))
+            .unwrap();
+        fs.create("new file.bin").unwrap();
+        assert_eq!(fs.metadata("new file.bin").unwrap().len, 0);
+        fs.write("new file.bin", b"short").unwrap();
+    }
+
+    let fs = FileSystem::new(image.device()).unwrap();
+    assert_eq!(fs.read("Contiguous File.bin").unwrap(), contiguous);
+    let prealloc = fs.read("prealloc.dat").unwrap();
+    assert_eq!(prealloc.len(), 2 * CLUSTER_SIZE);
+    assert_eq!(prealloc[..10], pattern(10, 5));
+    assert!(prealloc[10..100].iter().all(|&byte| byte == 0));
+    assert_eq!(prealloc[100..103], *b"xyz");
+    assert_eq!(fs.read("fragmented.bin").unwrap(), pattern(1000, 9));
+    assert_eq!(fs.read("NEW FILE.BIN").unwrap(), b"short");
+}
+
+#[test]
+fn flush_on_drop() {
+    let image = fixture();
+    let data = pattern(3 * CLUSTER_SIZE + 200, 4);
+    {
+        let fs = FileSystem::new(image.device()).unwrap();
+        fs.create_dir("dir").unwrap();
+        let mut file = fs.create("dir/unflushed.bin").unwrap();
+        file.write_all(&data).unwrap();
+        // Neither the file nor the filesystem get flushed explicitly.
+    }
+
+    let fs = FileSystem::new(image.device()).unwrap();
+    assert_eq!(
+        fs.metadata("dir/unflushed.bin").unwrap().len,
+        data.len() as u64
+    );
+    assert_eq!(fs.read("dir/unflushed.bin").unwrap(), data);
+}
+
+#[test]
+fn no_space() {
+    let image = fixture();
+    let fs = FileSystem::new(image.device()).unwrap();
+    let mut file = fs.create("huge").unwrap();
+    let chunk = vec![1; 64 * 1024];
+    let error = (0..100).find_map(|_| file.write_all(&chunk).err());
+    assert_eq!(error, Some(FatError::NoSpace));
+    drop(file);
+
+    // The space comes back once the file is removed.
+    fs.remove("huge").unwrap();
+    fs.write("after", &vec![2; 2 << 20]).unwrap();
+}
@@ -0,0 +1,331 @@
+//! FAT12, FAT16 and FAT32 volumes formatted by ``fatfs`` in image files, each change being
+//! checked by mounting the image again, and with ``fatfs``.
+
+mod common;
+
+use common::{Image, pattern};
+use embedded_io::{Read as _, Seek as _, SeekFrom, Write as _};
+use luma_fat::{FatError, FatKind, FileSystem};
+use std::io::{Read, Write};
+
+/// The volumes to run every test on, along with their cluster size.
+fn volumes() -> [(Image, FatKind, usize); 3] {
+    [
+        (
+            Image::format(4 << 20, fatfs::FatType::Fat12, 1024),
+            FatKind::Fat12,
+            1024,
+        ),
+        (
+            Image::format(32 << 20, fatfs::FatType::Fat16, 2048),
+            FatKind::Fat16,
+            2048,
+        ),
+        (
+            Image::format(80 << 20, fatfs::FatType::Fat32, 1024),
+            FatKind::Fat32,
+            1024,
+        ),
+    ]
+}
+
+fn read_fatfs(dir: &fatfs::Dir<std::fs::File>, path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    dir.open_file(path).unwrap().read_to_end(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn mount() {
+    for (image, kind, cluster_size) in volumes() {
+        let fs = FileSystem::new(image.device()).unwrap();
+        assert_eq!(fs.kind(), kind);
+        assert_eq!(fs.cluster_size(), cluster_size);
+        assert_eq!(fs.read_dir("").unwrap().count(), 0);
+    }
+
+    let image = Image::empty(1 << 20);
+    assert!(matches!(
+        FileSystem::new(image.device()),
+        Err(FatError::NoFilesystem)
+    ));
+}
+
+#[test]
+fn partition() {
+    let volume = Image::format(32 << 20, fatfs::FatType::Fat16, 2048);
+    let mut data = Vec::new();
+    volume.open().read_to_end(&mut data).unwrap();
+
+    // An MBR with a single FAT16 partition, starting after 1 MiB.
+    let image = Image::empty((1 << 20) + data.len() as u64);
+    let mut entry = [0; 16];
+    entry[4] = 0x0e;
+    entry[8..12].copy_from_slice(&2048u32.to_le_bytes());
+    entry[12..16].copy_from_slice(&((data.len() / 512) as u32).to_le_bytes());
+    image.write_at(446, &entry);
+    image.write_at(510, &[0x55, 0xaa]);
+    image.write_at(1 << 20, &data);
+
+    let fs = FileSystem::new(image.device()).unwrap();
+    assert_eq!(fs.kind(), FatKind::Fat16);
+    fs.write("in partition.txt", b"ok").unwrap();
+    drop(fs);
+
+    let fs = FileSystem::new(image.device()).unwrap();
+    assert_eq!(fs.read("IN PARTITION.TXT").unwrap(), b"ok");
+}
+
+#[test]
+fn long_names() {
+    let names = [
+        "UPPER.TXT",
+        "lower.txt",
+        "A name with spaces and more than eight characters.text",
+        "Ünïcödé name with a really long tail, to need several entries.dat",
+        "two.dots.in.name",
+    ];
+    for (image, kind, _) in volumes() {
+        let fs = FileSystem::new(image.device()).unwrap();
+        for (i, name) in names.iter().enumerate() {
+            fs.write(name, name.as_bytes()).unwrap();
+            assert_eq!(fs.metadata(name).unwrap().len, name.len() as u64);
+            assert_eq!(fs.read_dir("").unwrap().count(), i + 1);
+        }
+        // Lookups ignore the case, and names which already exist are rejected.
+        assert_eq!(fs.read("upper.txt").unwrap(), b"UPPER.TXT");
+        assert_eq!(
+            fs.read("ÜNÏCÖDÉ name with a REALLY long tail, to need several entries.DAT")
+                .unwrap()
+                .len(),
+            names[3].len()
+        );
+        assert_eq!(fs.create_dir("LOWER.txt"), Err(FatError::AlreadyExists));
+        assert!(matches!(fs.create("bad:name"), Err(FatError::InvalidName)));
+        drop(fs);
+
+        let fatfs = image.fatfs();
+        let root = fatfs.root_dir();
+        let mut found: Vec<_> = root
+            .iter()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        found.sort();
+        let mut expected = names.map(String::from).to_vec();
+        expected.sort();
+        assert_eq!(found, expected, "{kind:?}");
+        for name in names {
+            assert_eq!(read_fatfs(&root, name), name.as_bytes());
+        }
+        let lower = root
+            .iter()
+            .map(|entry| entry.unwrap())
+            .find(|entry| entry.file_name() == "lower.txt")
+            .unwrap();
+        assert_eq!(lower.short_file_name(), "LOWER.TXT");
+
+        // And the other way around.
+        let mut file = root.create_file("Written by another driver.bin").unwrap();
+        file.write_all(&pattern(10000, 7)).unwrap();
+        drop(file);
+        drop(root);
+        fatfs.unmount().unwrap();
+
+        let fs = FileSystem::new(image.device()).unwrap();
+        assert_eq!(
+            fs.read("/written by ANOTHER driver.bin").unwrap(),
+            pattern(10000, 7)
+        );
+    }
+}
+
+#[test]
+fn directories() {
+    for (image, kind, _) in volumes() {
+        let fs = FileSystem::new(image.device()).unwrap();
+        fs.create_dir("A Long Directory Name").unwrap();
+        fs.create_dir("a long directory name/Nested").unwrap();
+        // Enough long entries to need several clusters.
+        for i in 0..40 {
+            let path = format!("A Long Directory Name/entry number {i} with a long name.txt");
+            fs.write(&path, i.to_string().as_bytes()).unwrap();
+        }
+
+        let mut names: Vec<_> = fs
+            .read_dir("A Long Directory Name")
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .map(|entry| (entry.name, entry.metadata.is_dir()))
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 41, "{kind:?}");
+        // Upper case sorts first.
+        assert_eq!(names[0], ("Nested".to_string(), true));
+        assert!(names[1..].iter().all(|&(_, is_dir)| !is_dir));
+
+        assert!(
+            fs.metadata("A Long Directory Name/Nested/..")
+                .unwrap()
+                .is_dir()
+        );
+        assert!(
+            fs.metadata("a long directory name/nested/../entry number 7 with a long name.txt")
+                .unwrap()
+                .is_file()
+        );
+        assert_eq!(
+            fs.read_dir("A Long Directory Name/entry number 0 with a long name.txt")
+                .err(),
+            Some(FatError::NotADirectory)
+        );
+        assert!(matches!(
+            fs.open("A Long Directory Name"),
+            Err(FatError::IsADirectory)
+        ));
+        assert_eq!(
+            fs.metadata("A Long Directory Name/missing").err(),
+            Some(FatError::NotFound)
+        );
+
+        // Removing entries leaves holes, which get reused.
+        assert_eq!(fs.remove("A Long Directory Name"), Err(FatError::NotEmpty));
+        for i in (0..40).step_by(2) {
+            let path = format!("A Long Directory Name/entry number {i} with a long name.txt");
+            fs.remove(&path).unwrap();
+        }
+        fs.write("A Long Directory Name/new.txt", b"new").unwrap();
+        fs.remove("A Long Directory Name/Nested/").unwrap();
+        drop(fs);
+
+        let fs = FileSystem::new(image.device()).unwrap();
+        let count = fs
+            .read_dir("A Long Directory Name")
+            .unwrap()
+            .filter(|entry| !entry.as_ref().unwrap().name.starts_with('.'))
+            .count();
+        assert_eq!(count, 21);
+
+        let fatfs = image.fatfs();
+        let dir = fatfs.root_dir().open_dir("A Long Directory Name").unwrap();
+        assert_eq!(dir.iter().count(), 2 + 21);
+        assert_eq!(read_fatfs(&dir, "new.txt"), b"new");
+        assert_eq!(
+            read_fatfs(&dir, "entry number 39 with a long name.txt"),
+            b"39"
+        );
+    }
+}
+
+#[test]
+fn write_and_truncate() {
+    for (image, kind, cluster_size) in volumes() {
+        let fs = FileSystem::new(image.device()).unwrap();
+        let data = pattern(5 * cluster_size + 100, 1);
+        {
+            // Interleave two files, so that their clusters aren’t contiguous.
+            let mut first = fs.create("first.bin").unwrap();
+            let mut second = fs.create("second.bin").unwrap();
+            for chunk in data.chunks(cluster_size / 2 + 3) {
+                first.write_all(chunk).unwrap();
+                second.write_all(chunk).unwrap();
+            }
+        }
+        fs.remove("second.bin").unwrap();
+
+        let mut file = fs.open("first.bin").unwrap();
+        assert_eq!(file.len(), data.len() as u64);
+        file.seek(SeekFrom::Start(cluster_size as u64 - 3)).unwrap();
+        let mut part = vec![0; 1030];
+        file.read_exact(&mut part).unwrap();
+        assert_eq!(part, data[cluster_size - 3..][..1030]);
+
+        // Overwrite across a cluster boundary.
+        file.seek(SeekFrom::Start(2 * cluster_size as u64 - 2))
+            .unwrap();
+        file.write_all(b"boundary").unwrap();
+        // Truncate, then write past the end, leaving a gap of zeroes.
+        file.seek(SeekFrom::Start(3 * cluster_size as u64 + 17))
+            .unwrap();
+        file.truncate().unwrap();
+        assert_eq!(file.len(), 3 * cluster_size as u64 + 17);
+        file.seek(SeekFrom::Current(1000)).unwrap();
+        file.write_all(b"tail").unwrap();
+        drop(file);
+
+        let mut expected = data.clone();
+        expected[2 * cluster_size - 2..][..8].copy_from_slice(b"boundary");
+        expected.truncate(3 * cluster_size + 17);
+        expected.extend_from_slice(&[0; 1000]);
+        expected.extend_from_slice(b"tail");
+        assert_eq!(fs.read("first.bin").unwrap(), expected, "{kind:?}");
+
+        // Growing with set_len() fills with zeroes, and create() truncates.
+        let mut file = fs.open("first.bin").unwrap();
+        file.set_len(expected.len() as u64 + 5).unwrap();
+        drop(file);
+        let grown = fs.read("first.bin").unwrap();
+        assert_eq!(grown[..expected.len()], expected);
+        assert_eq!(grown[expected.len()..], [0; 5]);
+        fs.create("first.bin").unwrap();
+        assert_eq!(fs.metadata("first.bin").unwrap().len, 0);
+        fs.write("first.bin", &expected).unwrap();
+        drop(fs);
+
+        let fatfs = image.fatfs();
+        assert_eq!(read_fatfs(&fatfs.root_dir(), "first.bin"), expected);
+        let free = fatfs.stats().unwrap().free_clusters();
+        fatfs.root_dir().remove("first.bin").unwrap();
+        let freed = fatfs.stats().unwrap().free_clusters() - free;
+        // Nothing leaked, the file owns exactly the clusters it needs.
+        assert_eq!(freed as usize, expected.len().div_ceil(cluster_size));
+    }
+}
+
+#[test]
+fn flush_on_drop() {
+    for (image, kind, cluster_size) in volumes() {
+        let data = pattern(3 * cluster_size + 200, 4);
+        {
+            let fs = FileSystem::new(image.device()).unwrap();
+            fs.create_dir("dir").unwrap();
+            let mut file = fs.create("dir/unflushed.bin").unwrap();
+            file.write_all(&data).unwrap();
+            // Neither the file nor the filesystem get flushed explicitly.
+        }
+
+        let fs = FileSystem::new(image.device()).unwrap();
+        assert_eq!(fs.read("dir/unflushed.bin").unwrap(), data, "{kind:?}");
+        drop(fs);
+
+        let fatfs = image.fatfs();
+        let dir = fatfs.root_dir().open_dir("dir").unwrap();
+        assert_eq!(read_fatfs(&dir, "unflushed.bin"), data);
+    }
+}
+
+#[test]
+fn no_space() {
+    let image = Image::format(4 << 20, fatfs::FatType::Fat12, 4096);
+    let fs = FileSystem::new(image.device()).unwrap();
+    let mut file = fs.create("huge").unwrap();
+    let chunk = vec![1; 64 * 1024];
+    let error = (0..100).find_map(|_| file.write_all(&chunk).err());
+    assert_eq!(error, Some(FatError::NoSpace));
+    drop(file);
+
+    // The space comes back once the file is removed.
+    fs.remove("huge").unwrap();
+    fs.write("after", &vec![2; 3 << 20]).unwrap();
+
+    // The root directory of FAT12 has a fixed size.
+    let mut count = 0;
+    let error = loop {
+        match fs.create_dir(&format!("d{count}")) {
+            Ok(()) => count += 1,
+            Err(error) => break error,
+        }
+    };
+    assert_eq!(error, FatError::NoSpace);
+    assert!(count > 100, "{count}");
+}
@@ -0,0 +1,190 @@
+//! ``adpcm`` module of ``luma_formats::audio``.
+//!
+//! Contains a decoder for Nintendo’s DSP-ADPCM, the 4-bit codec of ``.dsp`` files and of most
+//! ``.brstm`` streams, as well as a parser for the header of ``.dsp`` files.  Nothing in here
+//! touches the hardware.
+
+/// Amount of bytes in a frame, a header byte followed by seven bytes of nibbles.
+pub const BYTES_PER_FRAME: usize = 8;
+
+/// Amount of samples in a frame.
+pub const SAMPLES_PER_FRAME: usize = 14;
+
+const NIBBLES_PER_FRAME: u32 = 16;
+const HEADER_SIZE: usize = 0x60;
+
+/// Errors which can be encountered while parsing a ``.dsp`` file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AdpcmError {
+    /// The file is smaller than its header, or than the data described by it.
+    OutOfBounds,
+    /// The format field isn’t zero, this isn’t ADPCM.
+    UnknownFormat(u16),
+    /// The loop points are outside of the samples, or in the wrong order.
+    BadLoop,
+}
+
+/// The decoder history, which must be carried from a frame to the next one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AdpcmState {
+    pub hist1: i16,
+    pub hist2: i16,
+}
+
+/// Decode a single sample, ``nibble`` being its 4-bit two’s complement value and ``header`` the
+/// first byte of its frame.
+#[inline]
+pub fn decode_sample(header: u8, nibble: u8, coefs: &[i16; 16], state: &mut AdpcmState) -> i16 {
+    let scale = 1i32 << (header & 0xf);
+    let predictor = ((header >> 4) & 7) as usize;
+    let coef1 = coefs[predictor * 2] as i32;
+    let coef2 = coefs[predictor * 2 + 1] as i32;
+
+    let nibble = ((nibble as i32) << 28) >> 28;
+    let prediction = coef1 * state.hist1 as i32 + coef2 * state.hist2 as i32;
+    let sample = (((nibble * scale) << 11) + 1024 + prediction) >> 11;
+    let sample = sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+
+    state.hist2 = state.hist1;
+    state.hist1 = sample;
+    sample
+}
+
+/// Decode whole frames of ``data`` into ``out``, returning the amount of samples written.
+///
+/// Decoding stops at whichever of ``data`` or ``out`` runs out first, a trailing partial frame
+/// getting decoded as far as its bytes go.
+pub fn decode(data: &[u8], coefs: &[i16; 16], state: &mut AdpcmState, out: &mut [i16]) -> usize {
+    let mut written = 0;
+    for frame in data.chunks(BYTES_PER_FRAME) {
+        let header = frame[0];
+        for &byte in &frame[1..] {
+            for nibble in [byte >> 4, byte & 0xf] {
+                let Some(sample) = out.get_mut(written) else {
+                    return written;
+                };
+                *sample = decode_sample(header, nibble, coefs, state);
+                written += 1;
+            }
+        }
+    }
+    written
+}
+
+/// Decode the sample at ``index`` from the start of ``data``, using and updating ``state``
+/// which must hold the history of the two samples before it.
+///
+/// Returns ``None`` past the end of ``data``.
+pub fn decode_at(
+    data: &[u8],
+    index: usize,
+    coefs: &[i16; 16],
+    state: &mut AdpcmState,
+) -> Option<i16> {
+    let frame = (index / SAMPLES_PER_FRAME) * BYTES_PER_FRAME;
+    let nibble = index % SAMPLES_PER_FRAME;
+    let header = *data.get(frame)?;
+    let byte = *data.get(frame + 1 + nibble / 2)?;
+    let nibble = if nibble.is_multiple_of(2) {
+        byte >> 4
+    } else {
+        byte & 0xf
+    };
+    Some(decode_sample(header, nibble, coefs, state))
+}
+
+/// Convert a nibble address, as used in ``.dsp`` headers, to a sample index.
+pub const fn nibble_to_sample(nibble: u32) -> u32 {
+    (nibble / NIBBLES_PER_FRAME) * SAMPLES_PER_FRAME as u32
+        + (nibble % NIBBLES_PER_FRAME).saturating_sub(2)
+}
+
+/// Get the amount of bytes needed to hold ``samples`` samples.
+pub const fn data_size(samples: u32) -> usize {
+    (samples as usize).div_ceil(SAMPLES_PER_FRAME) * BYTES_PER_FRAME
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// The header of a ``.dsp`` file, describing a single channel of DSP-ADPCM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DspHeader {
+    pub samples: u32,
+    pub sample_rate: u32,
+    /// First and last sample of the loop, both inclusive.
+    pub looping: Option<(u32, u32)>,
+    pub coefs: [i16; 16],
+    pub gain: u16,
+    /// History at the start of the samples.
+    pub initial: AdpcmState,
+    /// History at the start of the loop.
+    pub loop_state: AdpcmState,
+}
+
+impl DspHeader {
+    /// Parse the header of a ``.dsp`` file, and return it along with the ADPCM data following it.
+    pub fn parse(data: &[u8]) -> Result<(DspHeader, &[u8]), AdpcmError> {
+        if data.len() < HEADER_SIZE {
+            return Err(AdpcmError::OutOfBounds);
+        }
+        let samples = read_u32(data, 0x00);
+        let sample_rate = read_u32(data, 0x08);
+        let loop_flag = read_u16(data, 0x0c);
+        let format = read_u16(data, 0x0e);
+        if format != 0 {
+            return Err(AdpcmError::UnknownFormat(format));
+        }
+
+        let looping = if loop_flag != 0 {
+            let start = nibble_to_sample(read_u32(data, 0x10));
+            let end = nibble_to_sample(read_u32(data, 0x14));
+            if start > end || end >= samples {
+                return Err(AdpcmError::BadLoop);
+            }
+            Some((start, end))
+        } else {
+            None
+        };
+
+        let mut coefs = [0; 16];
+        for (i, coef) in coefs.iter_mut().enumerate() {
+            *coef = read_u16(data, 0x1c + i * 2) as i16;
+        }
+        let gain = read_u16(data, 0x3c);
+        let initial = AdpcmState {
+            hist1: read_u16(data, 0x40) as i16,
+            hist2: read_u16(data, 0x42) as i16,
+        };
+        let loop_state = AdpcmState {
+            hist1: read_u16(data, 0x46) as i16,
+            hist2: read_u16(data, 0x48) as i16,
+        };
+
+        let size = data_size(samples);
+        let body = data
+            .get(HEADER_SIZE..HEADER_SIZE + size)
+            .ok_or(AdpcmError::OutOfBounds)?;
+
+        let header = DspHeader {
+            samples,
+            sample_rate,
+            looping,
+            coefs,
+            gain,
+            initial,
+            loop_state,
+        };
+        Ok((header, body))
+    }
+}
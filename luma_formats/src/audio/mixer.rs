@@ -0,0 +1,390 @@
+//! ``mixer`` module of ``luma_formats::audio``.
+//!
+//! Contains a software mixer, playing any number of sounds at once with their own volume, pan
+//! and pitch, and producing the interleaved stereo samples expected by the playback of
+//! ``luma_core::audio``.
+//!
+//! Mixing itself only uses integer arithmetic, floating point being limited to computing the
+//! gains and the resampling step of a voice whenever it gets started or changed.  It never
+//! touches the hardware.
+
+use super::adpcm::{self, AdpcmError, AdpcmState, DspHeader};
+use super::vorbis::{Vorbis, VorbisError};
+use super::wav::Wav;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Fractional bits of the resampling position.
+const FRACTION_BITS: u32 = 16;
+const ONE: u32 = 1 << FRACTION_BITS;
+
+/// Gains are Q15, 0x8000 being unity.
+const UNITY: i32 = 1 << 15;
+
+/// Samples mixed at once, on the stack, since mixing usually happens in an interrupt handler
+/// where allocating isn’t an option.
+const CHUNK: usize = 256;
+
+#[derive(Clone, Debug)]
+enum SoundData {
+    /// Interleaved when there are two channels.
+    Pcm16 { samples: Arc<[i16]>, stereo: bool },
+    Adpcm {
+        data: Arc<[u8]>,
+        coefs: [i16; 16],
+        initial: AdpcmState,
+        loop_state: AdpcmState,
+    },
+}
+
+/// A sound which can be played by the [`Mixer`], cheap to clone.
+#[derive(Clone, Debug)]
+pub struct Sound {
+    data: SoundData,
+    frames: usize,
+    sample_rate: u32,
+    /// First and last frame of the loop, both inclusive.
+    looping: Option<(usize, usize)>,
+}
+
+impl Sound {
+    /// Create a sound from 16-bit PCM samples, interleaved if there are two channels.
+    ///
+    /// # Panics:
+    /// This function will panic if there are neither one nor two channels.
+    pub fn pcm16(samples: impl Into<Arc<[i16]>>, channels: u8, sample_rate: u32) -> Sound {
+        assert!(
+            channels == 1 || channels == 2,
+            "Only mono and stereo are supported"
+        );
+        let samples = samples.into();
+        let stereo = channels == 2;
+        Sound {
+            frames: samples.len() / channels as usize,
+            data: SoundData::Pcm16 { samples, stereo },
+            sample_rate,
+            looping: None,
+        }
+    }
+
+    /// Create a mono sound from a ``.dsp`` file, copying its ADPCM data, and looping if the file
+    /// says so.
+    pub fn from_dsp(file: &[u8]) -> Result<Sound, AdpcmError> {
+        let (header, data) = DspHeader::parse(file)?;
+        Ok(Sound {
+            data: SoundData::Adpcm {
+                data: data.into(),
+                coefs: header.coefs,
+                initial: header.initial,
+                loop_state: header.loop_state,
+            },
+            frames: header.samples as usize,
+            sample_rate: header.sample_rate,
+            looping: header
+                .looping
+                .map(|(start, end)| (start as usize, end as usize)),
+        })
+    }
+
+    /// Create a sound from a WAVE file, copying its samples.
+    pub fn from_wav(wav: &Wav) -> Sound {
+        Sound::pcm16(wav.to_pcm16(), wav.channels(), wav.sample_rate())
+    }
+
+    /// Create a sound by decoding a whole Vorbis stream, surround streams being reduced to
+    /// stereo.
+    pub fn from_vorbis(vorbis: &mut Vorbis) -> Result<Sound, VorbisError> {
+        if vorbis.channels() <= 2 {
+            let samples = vorbis.decode_all()?;
+            return Ok(Sound::pcm16(
+                samples,
+                vorbis.channels(),
+                vorbis.sample_rate(),
+            ));
+        }
+        let samples = vorbis.decode_all_stereo()?;
+        Ok(Sound::pcm16(samples, 2, vorbis.sample_rate()))
+    }
+
+    /// Loop between two frames, both inclusive, once the end one is reached.
+    ///
+    /// Looping an ADPCM sound anywhere else than where its file says isn’t supported, since the
+    /// decoder history at the loop start would be wrong.
+    ///
+    /// # Panics:
+    /// This function will panic if the loop isn’t within the sound.
+    pub fn with_loop(mut self, start: usize, end: usize) -> Sound {
+        assert!(start <= end && end < self.frames, "Loop out of bounds");
+        self.looping = Some((start, end));
+        self
+    }
+
+    /// Play the sound once, and stop at its end.
+    pub fn without_loop(mut self) -> Sound {
+        self.looping = None;
+        self
+    }
+
+    /// Get the amount of frames in the sound.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Get the sample rate of the sound.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Get the loop of the sound, if any.
+    pub fn looping(&self) -> Option<(usize, usize)> {
+        self.looping
+    }
+}
+
+/// Identifier of a voice, as returned by [`Mixer::play`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VoiceId(usize);
+
+/// A playing sound, with its position and gains.
+#[derive(Clone, Debug)]
+struct Voice {
+    sound: Sound,
+    /// Next frame to be read from the sound.
+    position: usize,
+    state: AdpcmState,
+    /// The two frames the output gets interpolated between.
+    previous: [i16; 2],
+    next: [i16; 2],
+    fraction: u32,
+    ended: bool,
+    volume: f32,
+    pan: f32,
+    pitch: f32,
+    gains: [i32; 2],
+    step: u32,
+}
+
+impl Voice {
+    fn new(sound: Sound) -> Voice {
+        let state = match sound.data {
+            SoundData::Adpcm { initial, .. } => initial,
+            SoundData::Pcm16 { .. } => AdpcmState::default(),
+        };
+        let mut voice = Voice {
+            sound,
+            position: 0,
+            state,
+            previous: [0; 2],
+            next: [0; 2],
+            fraction: 0,
+            ended: false,
+            volume: 1.0,
+            pan: 0.0,
+            pitch: 1.0,
+            gains: [UNITY; 2],
+            step: 0,
+        };
+        voice.previous = voice.read().unwrap_or([0; 2]);
+        voice.next = voice.read().unwrap_or([0; 2]);
+        voice
+    }
+
+    /// Read the next frame of the sound, following its loop.
+    fn read(&mut self) -> Option<[i16; 2]> {
+        if let Some((start, end)) = self.sound.looping
+            && self.position > end
+        {
+            self.position = start;
+            if let SoundData::Adpcm { loop_state, .. } = self.sound.data {
+                self.state = loop_state;
+            }
+        }
+        if self.position >= self.sound.frames {
+            return None;
+        }
+
+        let frame = match &self.sound.data {
+            SoundData::Pcm16 {
+                samples,
+                stereo: true,
+            } => [samples[self.position * 2], samples[self.position * 2 + 1]],
+            SoundData::Pcm16 {
+                samples,
+                stereo: false,
+            } => [samples[self.position]; 2],
+            SoundData::Adpcm { data, coefs, .. } => {
+                let sample = adpcm::decode_at(data, self.position, coefs, &mut self.state)?;
+                [sample; 2]
+            }
+        };
+        self.position += 1;
+        Some(frame)
+    }
+
+    fn update(&mut self, output_rate: u32) {
+        let volume = self.volume.clamp(0.0, 1.0);
+        let pan = self.pan.clamp(-1.0, 1.0);
+        let left = volume * (1.0 - pan).min(1.0);
+        let right = volume * (1.0 + pan).min(1.0);
+        self.gains = [(left * UNITY as f32) as i32, (right * UNITY as f32) as i32];
+
+        let ratio = self.pitch.max(0.0) * self.sound.sample_rate as f32 / output_rate as f32;
+        self.step = (ratio * ONE as f32) as u32;
+    }
+
+    /// Accumulate the voice into ``out``, returning false once the sound is over.
+    fn mix(&mut self, out: &mut [i32]) -> bool {
+        for frame in out.chunks_exact_mut(2) {
+            // Drop a bit of precision so that the interpolation can’t overflow.
+            let fraction = (self.fraction >> 1) as i32;
+            for (channel, out) in frame.iter_mut().enumerate() {
+                let previous = self.previous[channel] as i32;
+                let next = self.next[channel] as i32;
+                let sample = previous + (((next - previous) * fraction) >> (FRACTION_BITS - 1));
+                *out += (sample * self.gains[channel]) >> 15;
+            }
+
+            self.fraction += self.step;
+            while self.fraction >= ONE {
+                self.fraction -= ONE;
+                if self.ended {
+                    return false;
+                }
+                self.previous = self.next;
+                match self.read() {
+                    Some(next) => self.next = next,
+                    None => {
+                        self.next = [0; 2];
+                        self.ended = true;
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Mixes a fixed amount of voices to interleaved stereo 16-bit samples.
+#[derive(Clone, Debug)]
+pub struct Mixer {
+    voices: Vec<Option<Voice>>,
+    /// Sounds of the voices which ended while mixing, kept so that [`Mixer::mix`] never drops
+    /// the last reference to one, which would free it from an interrupt handler.
+    ///
+    /// Every voice ends at most once before being played again, and playing empties this, so it
+    /// never grows past its initial capacity of one sound per voice.
+    finished: Vec<Sound>,
+    output_rate: u32,
+}
+
+impl Mixer {
+    /// Create a mixer with ``voices`` voices, producing samples at ``output_rate`` Hz.
+    pub fn new(voices: usize, output_rate: u32) -> Mixer {
+        Mixer {
+            voices: (0..voices).map(|_| None).collect(),
+            finished: Vec::with_capacity(voices),
+            output_rate,
+        }
+    }
+
+    /// Drop the sounds of the voices which ended during [`Mixer::mix`], freeing those which
+    /// aren’t referenced anywhere else.
+    ///
+    /// This is done whenever a sound gets played, so only call it to get the memory back early.
+    pub fn release_finished(&mut self) {
+        self.finished.clear();
+    }
+
+    /// Start playing ``sound`` on the first free voice, at full volume, centered and at its
+    /// original pitch.
+    ///
+    /// Returns ``None`` if every voice is busy.
+    pub fn play(&mut self, sound: &Sound) -> Option<VoiceId> {
+        let index = self.voices.iter().position(Option::is_none)?;
+        let voice = VoiceId(index);
+        self.play_on(voice, sound);
+        Some(voice)
+    }
+
+    /// Start playing ``sound`` on the given voice, replacing whatever it was playing.
+    ///
+    /// # Panics:
+    /// This function will panic if the voice doesn’t exist.
+    pub fn play_on(&mut self, voice: VoiceId, sound: &Sound) {
+        self.release_finished();
+        let mut new = Voice::new(sound.clone());
+        new.update(self.output_rate);
+        self.voices[voice.0] = Some(new);
+    }
+
+    /// Get the voice with this index, to be used with [`Mixer::play_on`].
+    ///
+    /// Returns ``None`` if the mixer doesn’t have that many voices.
+    pub fn voice(&self, index: usize) -> Option<VoiceId> {
+        (index < self.voices.len()).then_some(VoiceId(index))
+    }
+
+    /// Stop a voice, making it free again.
+    pub fn stop(&mut self, voice: VoiceId) {
+        self.voices[voice.0] = None;
+    }
+
+    /// Stop every voice.
+    pub fn stop_all(&mut self) {
+        self.voices.iter_mut().for_each(|voice| *voice = None);
+    }
+
+    /// Check whether a voice is still playing.
+    pub fn is_playing(&self, voice: VoiceId) -> bool {
+        self.voices[voice.0].is_some()
+    }
+
+    fn with_voice(&mut self, voice: VoiceId, f: impl FnOnce(&mut Voice)) {
+        let output_rate = self.output_rate;
+        if let Some(voice) = &mut self.voices[voice.0] {
+            f(voice);
+            voice.update(output_rate);
+        }
+    }
+
+    /// Set the volume of a voice, from 0.0 (silent) to 1.0 (the original one).
+    pub fn set_volume(&mut self, voice: VoiceId, volume: f32) {
+        self.with_voice(voice, |voice| voice.volume = volume);
+    }
+
+    /// Set the pan of a voice, from -1.0 (left only) to 1.0 (right only).
+    pub fn set_pan(&mut self, voice: VoiceId, pan: f32) {
+        self.with_voice(voice, |voice| voice.pan = pan);
+    }
+
+    /// Set the pitch of a voice, as a ratio of its playback speed, 1.0 being the original one.
+    pub fn set_pitch(&mut self, voice: VoiceId, pitch: f32) {
+        self.with_voice(voice, |voice| voice.pitch = pitch);
+    }
+
+    /// Mix every voice into ``out``, as interleaved stereo samples, and free the voices whose
+    /// sound ended.
+    ///
+    /// This never allocates nor frees memory, so it can be called from the refill callback of a
+    /// playback.  The sounds of the freed voices are only dropped by
+    /// [`Mixer::release_finished`].
+    pub fn mix(&mut self, out: &mut [i16]) {
+        for out in out.chunks_mut(CHUNK) {
+            let mut accumulator = [0i32; CHUNK];
+            let accumulator = &mut accumulator[..out.len()];
+
+            for slot in &mut self.voices {
+                if let Some(voice) = slot
+                    && !voice.mix(accumulator)
+                    && let Some(voice) = slot.take()
+                {
+                    self.finished.push(voice.sound);
+                }
+            }
+
+            for (sample, &mixed) in out.iter_mut().zip(accumulator.iter()) {
+                *sample = mixed.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! ``audio`` module of ``luma_formats``.
+//!
+//! Contains the audio file formats, decoded to the 16-bit samples the AI plays, and a software
+//! mixer playing them together.
+
+pub mod adpcm;
+pub mod mixer;
+pub mod ogg;
+pub mod vorbis;
+pub mod wav;
+
+/// Sample rates supported by the AI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleRate {
+    Khz32,
+    Khz48,
+}
+
+impl SampleRate {
+    /// Get the sample rate in Hz.
+    pub const fn hz(self) -> u32 {
+        match self {
+            SampleRate::Khz32 => 32000,
+            SampleRate::Khz48 => 48000,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! ``ogg`` module of ``luma_formats::audio``.
+//!
+//! Contains a reader for the Ogg container, reassembling the packets of the first logical stream
+//! of a file held in memory.
+
+use alloc::vec::Vec;
+
+const CAPTURE: &[u8; 4] = b"OggS";
+const HEADER_SIZE: usize = 27;
+
+const CONTINUED: u8 = 0x01;
+const END_OF_STREAM: u8 = 0x04;
+
+/// Errors which can be encountered while reading an Ogg file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OggError {
+    /// A page doesn’t start with the ``OggS`` capture pattern.
+    BadCapture,
+    /// A page uses an unknown version of the format.
+    UnknownVersion(u8),
+    /// The checksum of a page doesn’t match its contents.
+    BadChecksum,
+    /// A page is cut short by the end of the file.
+    OutOfBounds,
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC_TABLE: [u32; 256] = crc_table();
+
+fn crc(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        (crc << 8) ^ CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+    })
+}
+
+/// A page, borrowing its lacing values and body from the file.
+#[derive(Clone, Copy, Debug)]
+struct Page<'a> {
+    flags: u8,
+    granule: i64,
+    serial: u32,
+    lacing: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> Page<'a> {
+    /// Parse and check the page at the start of ``data``, returning it with its total size.
+    fn parse(data: &'a [u8]) -> Result<(Page<'a>, usize), OggError> {
+        let header = data.get(..HEADER_SIZE).ok_or(OggError::OutOfBounds)?;
+        if &header[0..4] != CAPTURE {
+            return Err(OggError::BadCapture);
+        }
+        if header[4] != 0 {
+            return Err(OggError::UnknownVersion(header[4]));
+        }
+        let flags = header[5];
+        let granule = i64::from_le_bytes(header[6..14].try_into().unwrap());
+        let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+        let checksum = u32::from_le_bytes(header[22..26].try_into().unwrap());
+        let segments = header[26] as usize;
+
+        let lacing = data
+            .get(HEADER_SIZE..HEADER_SIZE + segments)
+            .ok_or(OggError::OutOfBounds)?;
+        let body_start = HEADER_SIZE + segments;
+        let body_size: usize = lacing.iter().map(|&size| size as usize).sum();
+        let body = data
+            .get(body_start..body_start + body_size)
+            .ok_or(OggError::OutOfBounds)?;
+
+        // The checksum is computed with its own field zeroed.
+        let mut computed = crc(0, &header[..22]);
+        computed = crc(computed, &[0; 4]);
+        computed = crc(computed, &data[26..body_start + body_size]);
+        if computed != checksum {
+            return Err(OggError::BadChecksum);
+        }
+
+        let page = Page {
+            flags,
+            granule,
+            serial,
+            lacing,
+            body,
+        };
+        Ok((page, body_start + body_size))
+    }
+}
+
+/// A packet, as returned by [`OggReader::next_packet`].
+#[derive(Clone, Copy, Debug)]
+pub struct OggPacket<'a> {
+    pub data: &'a [u8],
+    /// Granule position of the page this packet is the last one to end on, if it is.
+    pub granule: Option<i64>,
+    /// Whether this is the last packet of the stream.
+    pub end_of_stream: bool,
+}
+
+/// Reassembles the packets of the first logical stream of an Ogg file, ignoring any other one.
+#[derive(Clone, Debug)]
+pub struct OggReader<'a> {
+    data: &'a [u8],
+    /// Offset of the next page to parse.
+    offset: usize,
+    serial: Option<u32>,
+    page: Option<Page<'a>>,
+    /// Next segment to read in the current page, and offset of its data in the body.
+    segment: usize,
+    body_offset: usize,
+    packet: Vec<u8>,
+    finished: bool,
+}
+
+impl<'a> OggReader<'a> {
+    /// Start reading an Ogg file.
+    pub fn new(data: &'a [u8]) -> OggReader<'a> {
+        OggReader {
+            data,
+            offset: 0,
+            serial: None,
+            page: None,
+            segment: 0,
+            body_offset: 0,
+            packet: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Go back to the first packet of the file.
+    pub fn rewind(&mut self) {
+        self.offset = 0;
+        self.serial = None;
+        self.page = None;
+        self.finished = false;
+        self.packet.clear();
+    }
+
+    /// Move to the next page of our logical stream, returning false at the end of the file.
+    fn next_page(&mut self) -> Result<bool, OggError> {
+        while self.offset < self.data.len() {
+            let (page, size) = Page::parse(&self.data[self.offset..])?;
+            self.offset += size;
+            if *self.serial.get_or_insert(page.serial) != page.serial {
+                continue;
+            }
+            if page.flags & CONTINUED == 0 {
+                // Anything left over from the previous page was a lost fragment.
+                self.packet.clear();
+            }
+            self.page = Some(page);
+            self.segment = 0;
+            self.body_offset = 0;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Get the next complete packet, or ``None`` at the end of the stream.
+    ///
+    /// The packet is only valid until the next call.  It borrows from the file unless it spans
+    /// several pages, in which case it gets reassembled in a buffer owned by the reader.
+    pub fn next_packet(&mut self) -> Result<Option<OggPacket<'_>>, OggError> {
+        if self.finished {
+            return Ok(None);
+        }
+        self.packet.clear();
+        loop {
+            let page = match self.page {
+                Some(page) if self.segment < page.lacing.len() => page,
+                _ => {
+                    if !self.next_page()? {
+                        self.finished = true;
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+
+            // A lacing value below 255 ends the packet.
+            let start = self.body_offset;
+            let mut complete = false;
+            while let Some(&size) = page.lacing.get(self.segment) {
+                self.segment += 1;
+                self.body_offset += size as usize;
+                if size < 255 {
+                    complete = true;
+                    break;
+                }
+            }
+            let fragment = &page.body[start..self.body_offset];
+            if !complete {
+                self.packet.extend_from_slice(fragment);
+                continue;
+            }
+
+            let last = page.lacing[self.segment..].iter().all(|&size| size == 255);
+            let end_of_stream = last && page.flags & END_OF_STREAM != 0;
+            self.finished = end_of_stream;
+            let data = if self.packet.is_empty() {
+                fragment
+            } else {
+                self.packet.extend_from_slice(fragment);
+                &self.packet
+            };
+            return Ok(Some(OggPacket {
+                data,
+                granule: last.then_some(page.granule),
+                end_of_stream,
+            }));
+        }
+    }
+}
@@ -0,0 +1,1883 @@
+//! ``vorbis`` module of ``luma_formats::audio``.
+//!
+//! Contains a decoder for Vorbis I streams in Ogg files, producing 16-bit samples.  It follows
+//! the specification, floor type 0 excepted since no encoder has produced it in decades.
+
+use super::SampleRate;
+use super::ogg::{OggError, OggPacket, OggReader};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::f64::consts::{FRAC_PI_2, PI};
+
+/// Errors which can be encountered while decoding a Vorbis stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VorbisError {
+    /// The Ogg container is broken.
+    Ogg(OggError),
+    /// The stream ended before its three headers.
+    MissingHeader,
+    /// A header isn’t a valid Vorbis I header.
+    BadHeader,
+    /// The stream uses floor type 0.
+    Unsupported,
+}
+
+impl From<OggError> for VorbisError {
+    fn from(err: OggError) -> Self {
+        VorbisError::Ogg(err)
+    }
+}
+
+/// Reads the bits of a packet, least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader { data, position: 0 }
+    }
+
+    /// Read up to 32 bits, or ``None`` at the end of the packet.
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        if bits == 0 {
+            return Some(0);
+        }
+        let end = self.position + bits as usize;
+        if end > self.data.len() * 8 {
+            // Any further read fails as well.
+            self.position = self.data.len() * 8;
+            return None;
+        }
+        let value = self.peek_bits(bits);
+        self.position = end;
+        Some(value)
+    }
+
+    /// Get the next bits without consuming them, zero past the end of the packet.
+    fn peek_bits(&self, bits: u32) -> u32 {
+        let mut value = 0u64;
+        let first = self.position / 8;
+        let shift = self.position % 8;
+        let bytes = (shift + bits as usize).div_ceil(8);
+        for (i, &byte) in self.data[first..].iter().take(bytes).enumerate() {
+            value |= (byte as u64) << (i * 8);
+        }
+        ((value >> shift) & ((1u64 << bits) - 1)) as u32
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    fn skip(&mut self, bits: u32) {
+        self.position = (self.position + bits as usize).min(self.data.len() * 8);
+    }
+
+    fn read_bool(&mut self) -> Option<bool> {
+        self.read(1).map(|bit| bit != 0)
+    }
+}
+
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+/// Sine accurate enough for the tables computed at setup, since ``core`` has none.
+fn sin(x: f64) -> f64 {
+    // Reduce to [-π, π], then to [-π/2, π/2].
+    let mut x = x % (2.0 * PI);
+    if x > PI {
+        x -= 2.0 * PI;
+    } else if x < -PI {
+        x += 2.0 * PI;
+    }
+    if x > FRAC_PI_2 {
+        x = PI - x;
+    } else if x < -FRAC_PI_2 {
+        x = -PI - x;
+    }
+    let x2 = x * x;
+    let mut term = x;
+    let mut sum = x;
+    for i in 1..12 {
+        term *= -x2 / ((2 * i) as f64 * (2 * i + 1) as f64);
+        sum += term;
+    }
+    sum
+}
+
+fn cos(x: f64) -> f64 {
+    sin(x + FRAC_PI_2)
+}
+
+/// Unpack the floating point format of codebook headers.
+fn float32_unpack(value: u32) -> f32 {
+    let mantissa = (value & 0x1f_ffff) as f64;
+    let exponent = ((value >> 21) & 0x3ff) as i64 - 788;
+    // Always a normal f64, the exponent being within [-788, 235].
+    let scale = f64::from_bits(((exponent + 1023) as u64) << 52);
+    let magnitude = (mantissa * scale) as f32;
+    if value & 0x8000_0000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Largest value whose ``dimensions``-th power doesn’t exceed ``entries``.
+fn lookup1_values(entries: u32, dimensions: u32) -> u32 {
+    let fits = |r: u32| {
+        let mut product = 1u64;
+        for _ in 0..dimensions {
+            product *= r as u64;
+            if product > entries as u64 {
+                return false;
+            }
+        }
+        true
+    };
+    // Binary search, as ``entries`` can be large when ``dimensions`` is one.
+    let mut low = 0;
+    let mut high = entries;
+    while low < high {
+        let middle = high - (high - low) / 2;
+        if fits(middle) {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    low
+}
+
+const LEAF: u32 = 0x8000_0000;
+const FAST_BITS: u32 = 8;
+
+#[derive(Clone, Debug)]
+enum Lookup {
+    None,
+    /// Values computed from the entry number, lookup type 1.
+    Lattice {
+        minimum: f32,
+        delta: f32,
+        sequence: bool,
+        multiplicands: Vec<u16>,
+    },
+    /// One set of values per entry, lookup type 2.
+    Table {
+        minimum: f32,
+        delta: f32,
+        sequence: bool,
+        multiplicands: Vec<u16>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Codebook {
+    dimensions: usize,
+    /// Binary tree, each node having two children which are either a leaf (an entry with
+    /// [`LEAF`] set), another node, or nothing (zero, as the root is never a child).
+    tree: Vec<[u32; 2]>,
+    /// Entry and length of every codeword of up to [`FAST_BITS`] bits, indexed by its bits as
+    /// read from the packet, zero when the codeword is longer.
+    fast: Vec<u32>,
+    lookup: Lookup,
+}
+
+impl Codebook {
+    fn read(reader: &mut BitReader) -> Option<Result<Codebook, VorbisError>> {
+        if reader.read(24)? != 0x56_4342 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+        let dimensions = reader.read(16)? as usize;
+        let entries = reader.read(24)?;
+
+        // Zero stands for an unused entry.
+        let mut lengths = vec![0u8; entries as usize];
+        if reader.read_bool()? {
+            let mut length = reader.read(5)? + 1;
+            let mut entry = 0;
+            while entry < entries {
+                let count = reader.read(ilog(entries - entry))?;
+                if entry + count > entries || length > 32 {
+                    return Some(Err(VorbisError::BadHeader));
+                }
+                lengths[entry as usize..(entry + count) as usize].fill(length as u8);
+                entry += count;
+                length += 1;
+            }
+        } else {
+            let sparse = reader.read_bool()?;
+            for length in lengths.iter_mut() {
+                if !sparse || reader.read_bool()? {
+                    *length = reader.read(5)? as u8 + 1;
+                }
+            }
+        }
+
+        let lookup = match reader.read(4)? {
+            0 => Lookup::None,
+            _ if dimensions == 0 => return Some(Err(VorbisError::BadHeader)),
+            kind @ (1 | 2) => {
+                let minimum = float32_unpack(reader.read(32)?);
+                let delta = float32_unpack(reader.read(32)?);
+                let bits = reader.read(4)? + 1;
+                let sequence = reader.read_bool()?;
+                let count = if kind == 1 {
+                    lookup1_values(entries, dimensions as u32)
+                } else {
+                    entries * dimensions as u32
+                };
+                if count as usize > reader.remaining() / bits as usize {
+                    return None;
+                }
+                let mut multiplicands = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    multiplicands.push(reader.read(bits)? as u16);
+                }
+                if kind == 1 {
+                    Lookup::Lattice {
+                        minimum,
+                        delta,
+                        sequence,
+                        multiplicands,
+                    }
+                } else {
+                    Lookup::Table {
+                        minimum,
+                        delta,
+                        sequence,
+                        multiplicands,
+                    }
+                }
+            }
+            _ => return Some(Err(VorbisError::BadHeader)),
+        };
+
+        let Some((tree, fast)) = build_tree(&lengths) else {
+            return Some(Err(VorbisError::BadHeader));
+        };
+        Some(Ok(Codebook {
+            dimensions,
+            tree,
+            fast,
+            lookup,
+        }))
+    }
+
+    /// Read the next entry of this codebook from the packet.
+    fn decode(&self, reader: &mut BitReader) -> Option<u32> {
+        let fast = self.fast[reader.peek_bits(FAST_BITS) as usize];
+        if fast != 0 {
+            let length = fast & 0xf;
+            reader.read(length)?;
+            return Some(fast >> 4);
+        }
+        let mut node = 0;
+        loop {
+            let child = self.tree.get(node)?[reader.read(1)? as usize];
+            if child & LEAF != 0 {
+                return Some(child & !LEAF);
+            }
+            if child == 0 {
+                return None;
+            }
+            node = child as usize;
+        }
+    }
+
+    /// Read the next entry from the packet and add its vector to ``out``, one value every
+    /// ``stride``.
+    fn decode_vector(&self, reader: &mut BitReader, out: &mut [f32], stride: usize) -> Option<()> {
+        let entry = self.decode(reader)?;
+        let mut last = 0.0;
+        match &self.lookup {
+            Lookup::None => return None,
+            Lookup::Lattice {
+                minimum,
+                delta,
+                sequence,
+                multiplicands,
+            } => {
+                let count = multiplicands.len() as u32;
+                let mut divisor = 1;
+                for i in 0..self.dimensions {
+                    let offset = (entry / divisor) % count;
+                    let value = multiplicands[offset as usize] as f32 * delta + minimum + last;
+                    if let Some(out) = out.get_mut(i * stride) {
+                        *out += value;
+                    }
+                    if *sequence {
+                        last = value;
+                    }
+                    divisor = divisor.wrapping_mul(count);
+                }
+            }
+            Lookup::Table {
+                minimum,
+                delta,
+                sequence,
+                multiplicands,
+            } => {
+                let offset = entry as usize * self.dimensions;
+                for i in 0..self.dimensions {
+                    let value = multiplicands[offset + i] as f32 * delta + minimum + last;
+                    if let Some(out) = out.get_mut(i * stride) {
+                        *out += value;
+                    }
+                    if *sequence {
+                        last = value;
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+/// Assign the codewords of a codebook from their lengths, as mandated by the specification, and
+/// build the decoding tree and fast table.  Returns ``None`` for an over- or underspecified
+/// code.
+#[allow(clippy::type_complexity)]
+fn build_tree(lengths: &[u8]) -> Option<(Vec<[u32; 2]>, Vec<u32>)> {
+    let mut tree = vec![[0u32; 2]];
+    let mut fast = vec![0u32; 1 << FAST_BITS];
+
+    let mut used = lengths
+        .iter()
+        .enumerate()
+        .filter(|&(_, &length)| length != 0);
+    let Some((first, &first_length)) = used.clone().next() else {
+        return Some((tree, fast));
+    };
+
+    // A single entry is always a one-bit codeword, whichever that bit is.
+    if used.clone().nth(1).is_none() {
+        if first_length != 1 {
+            return None;
+        }
+        let leaf = LEAF | first as u32;
+        tree[0] = [leaf, leaf];
+        fast.fill(((first as u32) << 4) | 1);
+        return Some((tree, fast));
+    }
+
+    let mut insert = |entry: usize, codeword: u32, length: u8| -> Option<()> {
+        let mut node = 0;
+        for i in 0..length as u32 {
+            let bit = ((codeword >> (31 - i)) & 1) as usize;
+            if i == length as u32 - 1 {
+                if tree[node][bit] != 0 {
+                    return None;
+                }
+                tree[node][bit] = LEAF | entry as u32;
+            } else {
+                let child = tree[node][bit];
+                if child & LEAF != 0 {
+                    return None;
+                }
+                if child == 0 {
+                    tree.push([0; 2]);
+                    tree[node][bit] = tree.len() as u32 - 1;
+                }
+                node = tree[node][bit] as usize;
+            }
+        }
+
+        if length as u32 <= FAST_BITS {
+            let reversed = codeword.reverse_bits();
+            for high in 0..1u32 << (FAST_BITS - length as u32) {
+                let index = reversed | (high << length);
+                fast[index as usize] = ((entry as u32) << 4) | length as u32;
+            }
+        }
+        Some(())
+    };
+
+    // Codewords are kept aligned to the most significant bit, ``available[i]`` being the
+    // lowest free codeword of length ``i``.
+    let mut available = [0u32; 33];
+    insert(first, 0, first_length)?;
+    for (i, slot) in available
+        .iter_mut()
+        .enumerate()
+        .take(first_length as usize + 1)
+        .skip(1)
+    {
+        *slot = 1 << (32 - i);
+    }
+    for (entry, &length) in used.by_ref().skip(1) {
+        let mut z = length as usize;
+        while z > 0 && available[z] == 0 {
+            z -= 1;
+        }
+        if z == 0 {
+            return None;
+        }
+        let codeword = available[z];
+        available[z] = 0;
+        insert(entry, codeword, length)?;
+        for y in (z + 1..=length as usize).rev() {
+            available[y] = codeword + (1 << (32 - y));
+        }
+    }
+    if available.iter().any(|&codeword| codeword != 0) {
+        return None;
+    }
+    Some((tree, fast))
+}
+
+#[derive(Clone, Debug)]
+struct Floor {
+    partition_classes: Vec<u8>,
+    class_dimensions: [u8; 16],
+    class_subclasses: [u8; 16],
+    class_masterbooks: [u8; 16],
+    /// Book of each subclass, minus one.
+    subclass_books: [[i16; 8]; 16],
+    multiplier: u8,
+    xs: Vec<u16>,
+    /// Indices of ``xs`` sorted by value.
+    sorted: Vec<u8>,
+    /// Low and high neighbors of each point.
+    neighbors: Vec<(u8, u8)>,
+}
+
+impl Floor {
+    fn read(reader: &mut BitReader, codebooks: &[Codebook]) -> Option<Result<Floor, VorbisError>> {
+        match reader.read(16)? {
+            0 => return Some(Err(VorbisError::Unsupported)),
+            1 => (),
+            _ => return Some(Err(VorbisError::BadHeader)),
+        }
+        let books = codebooks.len() as u32;
+
+        let partitions = reader.read(5)?;
+        let mut partition_classes = Vec::with_capacity(partitions as usize);
+        for _ in 0..partitions {
+            partition_classes.push(reader.read(4)? as u8);
+        }
+        let classes = partition_classes.iter().max().map_or(0, |&max| max + 1);
+
+        let mut class_dimensions = [0; 16];
+        let mut class_subclasses = [0; 16];
+        let mut class_masterbooks = [0; 16];
+        let mut subclass_books = [[-1; 8]; 16];
+        for class in 0..classes as usize {
+            class_dimensions[class] = reader.read(3)? as u8 + 1;
+            class_subclasses[class] = reader.read(2)? as u8;
+            if class_subclasses[class] != 0 {
+                class_masterbooks[class] = reader.read(8)? as u8;
+                if class_masterbooks[class] as u32 >= books {
+                    return Some(Err(VorbisError::BadHeader));
+                }
+            }
+            for book in subclass_books[class]
+                .iter_mut()
+                .take(1 << class_subclasses[class])
+            {
+                *book = reader.read(8)? as i16 - 1;
+                if *book >= books as i16 {
+                    return Some(Err(VorbisError::BadHeader));
+                }
+            }
+        }
+
+        let multiplier = reader.read(2)? as u8 + 1;
+        let range_bits = reader.read(4)?;
+        let mut xs = vec![0, 1 << range_bits];
+        for &class in &partition_classes {
+            for _ in 0..class_dimensions[class as usize] {
+                xs.push(reader.read(range_bits)? as u16);
+            }
+        }
+        if xs.len() > 65 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+
+        let mut sorted: Vec<u8> = (0..xs.len() as u8).collect();
+        sorted.sort_by_key(|&i| xs[i as usize]);
+        if sorted
+            .windows(2)
+            .any(|pair| xs[pair[0] as usize] == xs[pair[1] as usize])
+        {
+            return Some(Err(VorbisError::BadHeader));
+        }
+
+        let neighbors = (0..xs.len())
+            .map(|i| {
+                let mut low = 0;
+                let mut high = 1;
+                for j in 0..i {
+                    if xs[j] < xs[i] && xs[j] > xs[low] {
+                        low = j;
+                    }
+                    if xs[j] > xs[i] && xs[j] < xs[high] {
+                        high = j;
+                    }
+                }
+                (low as u8, high as u8)
+            })
+            .collect();
+
+        Some(Ok(Floor {
+            partition_classes,
+            class_dimensions,
+            class_subclasses,
+            class_masterbooks,
+            subclass_books,
+            multiplier,
+            xs,
+            sorted,
+            neighbors,
+        }))
+    }
+
+    fn range(&self) -> i32 {
+        [256, 128, 86, 64][self.multiplier as usize - 1]
+    }
+
+    /// Read the Y values of the floor into ``ys``, returning false if the channel is unused.
+    fn decode(&self, reader: &mut BitReader, codebooks: &[Codebook], ys: &mut Vec<i32>) -> bool {
+        ys.clear();
+        let mut decode = || -> Option<bool> {
+            if !reader.read_bool()? {
+                return Some(false);
+            }
+            let bits = ilog(self.range() as u32 - 1);
+            ys.push(reader.read(bits)? as i32);
+            ys.push(reader.read(bits)? as i32);
+            for &class in &self.partition_classes {
+                let class = class as usize;
+                let bits = self.class_subclasses[class];
+                let mut value = if bits > 0 {
+                    codebooks[self.class_masterbooks[class] as usize].decode(reader)?
+                } else {
+                    0
+                };
+                for _ in 0..self.class_dimensions[class] {
+                    let book = self.subclass_books[class][(value & ((1 << bits) - 1)) as usize];
+                    value >>= bits;
+                    ys.push(if book >= 0 {
+                        codebooks[book as usize].decode(reader)? as i32
+                    } else {
+                        0
+                    });
+                }
+            }
+            Some(true)
+        };
+        // Running out of packet makes the channel unused.
+        decode().unwrap_or(false)
+    }
+
+    /// Turn the Y values into the floor curve, in ``out`` of length n/2.
+    fn synthesize(&self, ys: &mut [i32], used: &mut [bool], out: &mut [f32]) {
+        let range = self.range();
+
+        // Amplitude value synthesis.
+        used[0] = true;
+        used[1] = true;
+        for i in 2..self.xs.len() {
+            let (low, high) = self.neighbors[i];
+            let (low, high) = (low as usize, high as usize);
+            let predicted = render_point(
+                self.xs[low] as i32,
+                ys[low],
+                self.xs[high] as i32,
+                ys[high],
+                self.xs[i] as i32,
+            );
+            let value = ys[i];
+            let high_room = range - predicted;
+            let low_room = predicted;
+            let room = high_room.min(low_room) * 2;
+            if value != 0 {
+                used[low] = true;
+                used[high] = true;
+                used[i] = true;
+                ys[i] = if value >= room {
+                    if high_room > low_room {
+                        value - low_room + predicted
+                    } else {
+                        predicted - value + high_room - 1
+                    }
+                } else if value % 2 == 1 {
+                    predicted - (value + 1) / 2
+                } else {
+                    predicted + value / 2
+                };
+            } else {
+                used[i] = false;
+                ys[i] = predicted;
+            }
+        }
+        for y in ys.iter_mut() {
+            *y = (*y).clamp(0, range - 1);
+        }
+
+        // Curve synthesis.
+        let multiplier = self.multiplier as i32;
+        let first = self.sorted[0] as usize;
+        let mut lx = 0;
+        let mut ly = ys[first] * multiplier;
+        let mut hx = 0;
+        let mut hy = 0;
+        for &i in &self.sorted[1..] {
+            let i = i as usize;
+            if used[i] {
+                hy = ys[i] * multiplier;
+                hx = self.xs[i] as i32;
+                render_line(lx, ly, hx, hy, out);
+                lx = hx;
+                ly = hy;
+            }
+        }
+        if (hx as usize) < out.len() {
+            render_line(hx, hy, out.len() as i32, hy, out);
+        }
+    }
+}
+
+fn render_point(x0: i32, y0: i32, x1: i32, y1: i32, x: i32) -> i32 {
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    let offset = dy.abs() * (x - x0) / adx;
+    if dy < 0 { y0 - offset } else { y0 + offset }
+}
+
+/// Draw a line of inverse dB values from ``x0`` included to ``x1`` excluded.
+fn render_line(x0: i32, y0: i32, x1: i32, y1: i32, out: &mut [f32]) {
+    let dy = y1 - y0;
+    let adx = x1 - x0;
+    let base = dy / adx;
+    let sy = if dy < 0 { base - 1 } else { base + 1 };
+    let ady = dy.abs() - base.abs() * adx;
+    let mut y = y0;
+    let mut err = 0;
+    for x in x0..x1.min(out.len() as i32) {
+        if x > x0 {
+            err += ady;
+            if err >= adx {
+                err -= adx;
+                y += sy;
+            } else {
+                y += base;
+            }
+        }
+        out[x as usize] = INVERSE_DB_TABLE[y as usize];
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Residue {
+    kind: u16,
+    begin: u32,
+    end: u32,
+    partition_size: u32,
+    classifications: u32,
+    classbook: u8,
+    /// Book of each pass of each classification, minus one.
+    books: Vec<[i16; 8]>,
+}
+
+impl Residue {
+    fn read(
+        reader: &mut BitReader,
+        codebooks: &[Codebook],
+    ) -> Option<Result<Residue, VorbisError>> {
+        let kind = reader.read(16)? as u16;
+        if kind > 2 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+        let begin = reader.read(24)?;
+        let end = reader.read(24)?;
+        let partition_size = reader.read(24)? + 1;
+        let classifications = reader.read(6)? + 1;
+        let classbook = reader.read(8)? as u8;
+        if classbook as usize >= codebooks.len() {
+            return Some(Err(VorbisError::BadHeader));
+        }
+
+        let mut cascades = Vec::with_capacity(classifications as usize);
+        for _ in 0..classifications {
+            let low = reader.read(3)?;
+            let high = if reader.read_bool()? {
+                reader.read(5)?
+            } else {
+                0
+            };
+            cascades.push(high << 3 | low);
+        }
+        let mut books = Vec::with_capacity(classifications as usize);
+        for cascade in cascades {
+            let mut passes = [-1; 8];
+            for (pass, book) in passes.iter_mut().enumerate() {
+                if cascade & (1 << pass) != 0 {
+                    let number = reader.read(8)? as usize;
+                    match codebooks.get(number) {
+                        Some(codebook) if !matches!(codebook.lookup, Lookup::None) => {
+                            *book = number as i16
+                        }
+                        _ => return Some(Err(VorbisError::BadHeader)),
+                    }
+                }
+            }
+            books.push(passes);
+        }
+
+        let classwords = codebooks[classbook as usize].dimensions;
+        if classwords == 0 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+
+        Some(Ok(Residue {
+            kind,
+            begin,
+            end,
+            partition_size,
+            classifications,
+            classbook,
+            books,
+        }))
+    }
+
+    /// Decode the residue vectors of the ``channels`` of ``spectra``, ``size`` samples each from
+    /// ``size`` on, all being decoded unless their ``skip`` flag is set.
+    #[allow(clippy::too_many_arguments)]
+    fn decode(
+        &self,
+        reader: &mut BitReader,
+        codebooks: &[Codebook],
+        size: usize,
+        spectra: &mut [Vec<f32>],
+        channels: &[u8],
+        skip: &[bool],
+        scratch: &mut Vec<f32>,
+        classes: &mut Vec<u32>,
+    ) {
+        for &channel in channels {
+            spectra[channel as usize][size..size * 2].fill(0.0);
+        }
+        if self.kind == 2 {
+            if skip.iter().all(|&skip| skip) {
+                return;
+            }
+            // Decode all the channels interleaved, as a single vector.
+            let count = channels.len();
+            scratch.clear();
+            scratch.resize(size * count, 0.0);
+            self.decode_vectors(
+                reader,
+                codebooks,
+                size * count,
+                core::slice::from_mut(scratch),
+                &[0],
+                0,
+                &[false],
+                classes,
+            );
+            for (i, frame) in scratch.chunks_exact(count).enumerate() {
+                for (&channel, &sample) in channels.iter().zip(frame) {
+                    spectra[channel as usize][size + i] = sample;
+                }
+            }
+        } else {
+            self.decode_vectors(
+                reader, codebooks, size, spectra, channels, size, skip, classes,
+            );
+        }
+    }
+
+    /// Decode ``size`` samples into each of the ``channels`` of ``vectors``, starting at
+    /// ``start``.
+    #[allow(clippy::too_many_arguments)]
+    fn decode_vectors(
+        &self,
+        reader: &mut BitReader,
+        codebooks: &[Codebook],
+        size: usize,
+        vectors: &mut [Vec<f32>],
+        channels: &[u8],
+        start: usize,
+        skip: &[bool],
+        classes: &mut Vec<u32>,
+    ) {
+        let begin = (self.begin as usize).min(size);
+        let end = (self.end as usize).min(size);
+        let partition_size = self.partition_size as usize;
+        let partitions = end.saturating_sub(begin) / partition_size;
+        if partitions == 0 {
+            return;
+        }
+        let classbook = &codebooks[self.classbook as usize];
+        let classwords = classbook.dimensions;
+
+        let stride = partitions + classwords;
+        classes.clear();
+        classes.resize(channels.len() * stride, 0);
+
+        // Running out of packet just ends the decoding, leaving the rest at zero.
+        let mut decode = || -> Option<()> {
+            for pass in 0..8 {
+                let mut partition = 0;
+                while partition < partitions {
+                    if pass == 0 {
+                        for (channel, &skip) in skip.iter().enumerate() {
+                            if skip {
+                                continue;
+                            }
+                            let mut temp = classbook.decode(reader)?;
+                            for i in (0..classwords).rev() {
+                                classes[channel * stride + partition + i] =
+                                    temp % self.classifications;
+                                temp /= self.classifications;
+                            }
+                        }
+                    }
+                    for _ in 0..classwords {
+                        if partition >= partitions {
+                            break;
+                        }
+                        for (j, &channel) in channels.iter().enumerate() {
+                            if skip[j] {
+                                continue;
+                            }
+                            let class = classes[j * stride + partition] as usize;
+                            let book = self.books[class][pass];
+                            if book < 0 {
+                                continue;
+                            }
+                            let codebook = &codebooks[book as usize];
+                            let offset = start + begin + partition * partition_size;
+                            let out = &mut vectors[channel as usize][offset..start + size];
+                            self.decode_partition(reader, codebook, out)?;
+                        }
+                        partition += 1;
+                    }
+                }
+            }
+            Some(())
+        };
+        decode();
+    }
+
+    /// Decode a partition at the start of ``out``, which goes on until the end of the vector.
+    fn decode_partition(
+        &self,
+        reader: &mut BitReader,
+        codebook: &Codebook,
+        out: &mut [f32],
+    ) -> Option<()> {
+        let size = self.partition_size as usize;
+        let dimensions = codebook.dimensions;
+        if self.kind == 0 {
+            let step = size / dimensions;
+            for i in 0..step {
+                codebook.decode_vector(reader, &mut out[i..], step)?;
+            }
+        } else {
+            let mut i = 0;
+            while i < size {
+                // A vector crossing the end of the partition spills into the next one.
+                codebook.decode_vector(reader, &mut out[i..], 1)?;
+                i += dimensions;
+            }
+        }
+        Some(())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Mapping {
+    /// Magnitude and angle channels of each coupling step.
+    coupling: Vec<(u8, u8)>,
+    /// Submap of each channel.
+    mux: Vec<u8>,
+    /// Floor and residue of each submap.
+    submaps: Vec<(u8, u8)>,
+}
+
+impl Mapping {
+    fn read(
+        reader: &mut BitReader,
+        channels: u8,
+        floors: usize,
+        residues: usize,
+    ) -> Option<Result<Mapping, VorbisError>> {
+        if reader.read(16)? != 0 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+        let submap_count = if reader.read_bool()? {
+            reader.read(4)? + 1
+        } else {
+            1
+        };
+
+        let mut coupling = Vec::new();
+        if reader.read_bool()? {
+            let steps = reader.read(8)? + 1;
+            let bits = ilog(channels as u32 - 1);
+            for _ in 0..steps {
+                let magnitude = reader.read(bits)?;
+                let angle = reader.read(bits)?;
+                if magnitude == angle || magnitude >= channels as u32 || angle >= channels as u32 {
+                    return Some(Err(VorbisError::BadHeader));
+                }
+                coupling.push((magnitude as u8, angle as u8));
+            }
+        }
+
+        if reader.read(2)? != 0 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+
+        let mut mux = vec![0; channels as usize];
+        if submap_count > 1 {
+            for submap in mux.iter_mut() {
+                *submap = reader.read(4)? as u8;
+                if *submap as u32 >= submap_count {
+                    return Some(Err(VorbisError::BadHeader));
+                }
+            }
+        }
+
+        let mut submaps = Vec::with_capacity(submap_count as usize);
+        for _ in 0..submap_count {
+            reader.skip(8);
+            let floor = reader.read(8)? as u8;
+            let residue = reader.read(8)? as u8;
+            if floor as usize >= floors || residue as usize >= residues {
+                return Some(Err(VorbisError::BadHeader));
+            }
+            submaps.push((floor, residue));
+        }
+
+        Some(Ok(Mapping {
+            coupling,
+            mux,
+            submaps,
+        }))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Mode {
+    long: bool,
+    mapping: u8,
+}
+
+/// Everything which only depends on a block size.
+#[derive(Clone, Debug)]
+struct Block {
+    size: usize,
+    /// Rising half of the window, used over a whole half block.
+    slope: Vec<f32>,
+    /// Twiddles applied before and after the FFT of the IMDCT.
+    pre_twiddle: Vec<(f32, f32)>,
+    post_twiddle: Vec<(f32, f32)>,
+    /// Twiddles and bit reversal of the FFT itself.
+    fft_twiddle: Vec<(f32, f32)>,
+    bit_reverse: Vec<u16>,
+}
+
+impl Block {
+    fn new(size: usize) -> Block {
+        let half = size / 2;
+        let quarter = size / 4;
+        let theta = PI / half as f64;
+
+        let slope = (0..half)
+            .map(|i| {
+                let x = sin((i as f64 + 0.5) / half as f64 * FRAC_PI_2);
+                sin(FRAC_PI_2 * x * x) as f32
+            })
+            .collect();
+        let twiddle = |angle: f64| (cos(angle) as f32, -sin(angle) as f32);
+        let pre_twiddle = (0..quarter)
+            .map(|n| twiddle(theta * (n as f64 + 0.25)))
+            .collect();
+        let post_twiddle = (0..quarter).map(|k| twiddle(theta * k as f64)).collect();
+        let fft_twiddle = (0..quarter / 2)
+            .map(|k| twiddle(2.0 * PI * k as f64 / quarter as f64))
+            .collect();
+        let bits = quarter.trailing_zeros();
+        let bit_reverse = (0..quarter as u32)
+            .map(|i| (i.reverse_bits().checked_shr(32 - bits).unwrap_or(0)) as u16)
+            .collect();
+
+        Block {
+            size,
+            slope,
+            pre_twiddle,
+            post_twiddle,
+            fft_twiddle,
+            bit_reverse,
+        }
+    }
+
+    /// Transform the n/2 coefficients at the start of ``data`` into n samples, in place, using
+    /// ``complex`` and ``dct`` as scratch buffers.
+    fn imdct(&self, data: &mut [f32], complex: &mut Vec<(f32, f32)>, dct: &mut Vec<f32>) {
+        let m = self.size / 2;
+        let quarter = self.size / 4;
+
+        // DCT-IV of size m, through a complex FFT of size m/2.
+        complex.clear();
+        complex.resize(quarter, (0.0, 0.0));
+        for n in 0..quarter {
+            let (re, im) = (data[2 * n], data[m - 1 - 2 * n]);
+            let (c, s) = self.pre_twiddle[n];
+            complex[self.bit_reverse[n] as usize] = (re * c - im * s, re * s + im * c);
+        }
+        let mut span = 1;
+        while span < quarter {
+            let step = quarter / (span * 2);
+            for start in (0..quarter).step_by(span * 2) {
+                for k in 0..span {
+                    let (c, s) = self.fft_twiddle[k * step];
+                    let (are, aim) = complex[start + k];
+                    let (bre, bim) = complex[start + k + span];
+                    let (tre, tim) = (bre * c - bim * s, bre * s + bim * c);
+                    complex[start + k] = (are + tre, aim + tim);
+                    complex[start + k + span] = (are - tre, aim - tim);
+                }
+            }
+            span *= 2;
+        }
+        for k in 0..quarter {
+            let (re, im) = complex[k];
+            let (c, s) = self.post_twiddle[k];
+            data[2 * k] = re * c - im * s;
+            data[m - 1 - 2 * k] = -(re * s + im * c);
+        }
+
+        // Unfold the DCT-IV into the IMDCT.
+        dct.clear();
+        dct.extend_from_slice(&data[..m]);
+        for (i, out) in data[..self.size].iter_mut().enumerate() {
+            *out = if i < m / 2 {
+                dct[i + m / 2]
+            } else if i < 3 * m / 2 {
+                -dct[3 * m / 2 - 1 - i]
+            } else {
+                -dct[i - 3 * m / 2]
+            };
+        }
+    }
+}
+
+/// Decoder of a Vorbis stream held in memory.
+pub struct Vorbis<'a> {
+    reader: OggReader<'a>,
+    channels: u8,
+    sample_rate: u32,
+    blocks: [Block; 2],
+    codebooks: Vec<Codebook>,
+    floors: Vec<Floor>,
+    residues: Vec<Residue>,
+    mappings: Vec<Mapping>,
+    modes: Vec<Mode>,
+    /// Windowed second half of the previous block of each channel, its size, and where its
+    /// window starts falling relatively to its center.
+    previous: Vec<Vec<f32>>,
+    previous_size: usize,
+    previous_fall: usize,
+    primed: bool,
+    /// Frames returned so far, to honour the granule position of the last page.
+    position: u64,
+    /// Decoded interleaved samples not returned yet.
+    pcm: Vec<i16>,
+    pcm_offset: usize,
+    // Buffers reused from a packet to the next.
+    spectra: Vec<Vec<f32>>,
+    ys: Vec<i32>,
+    used: Vec<bool>,
+    scratch: Vec<f32>,
+    classes: Vec<u32>,
+    complex: Vec<(f32, f32)>,
+}
+
+impl<'a> Vorbis<'a> {
+    /// Read the headers of the Vorbis stream in an Ogg file.
+    pub fn new(data: &'a [u8]) -> Result<Vorbis<'a>, VorbisError> {
+        let mut reader = OggReader::new(data);
+
+        let packet = next_header(&mut reader, 1)?;
+        let mut bits = BitReader::new(&packet.data[7..]);
+        let mut identification = || -> Option<(u8, u32, u8, u8)> {
+            if bits.read(32)? != 0 {
+                return None;
+            }
+            let channels = bits.read(8)? as u8;
+            let sample_rate = bits.read(32)?;
+            bits.skip(96);
+            let short = bits.read(4)? as u8;
+            let long = bits.read(4)? as u8;
+            let valid = channels > 0
+                && sample_rate > 0
+                && (6..=13).contains(&short)
+                && (short..=13).contains(&long)
+                && bits.read_bool()?;
+            valid.then_some((channels, sample_rate, short, long))
+        };
+        let (channels, sample_rate, short, long) =
+            identification().ok_or(VorbisError::BadHeader)?;
+
+        // The comments are of no use here.
+        next_header(&mut reader, 3)?;
+
+        let packet = next_header(&mut reader, 5)?;
+        let mut bits = BitReader::new(&packet.data[7..]);
+        let (codebooks, floors, residues, mappings, modes) =
+            read_setup(&mut bits, channels).ok_or(VorbisError::BadHeader)??;
+
+        let long_size = 1 << long;
+        // Allocate every buffer up front, so that decoding doesn’t have to.
+        let classes = residues
+            .iter()
+            .map(|residue| {
+                let classwords = codebooks[residue.classbook as usize].dimensions;
+                let partitions = long_size / 2 / residue.partition_size as usize;
+                channels as usize * (partitions + classwords)
+            })
+            .max()
+            .unwrap_or(0);
+        Ok(Vorbis {
+            reader,
+            channels,
+            sample_rate,
+            blocks: [Block::new(1 << short), Block::new(long_size)],
+            codebooks,
+            floors,
+            residues,
+            mappings,
+            modes,
+            previous: vec![vec![0.0; long_size / 2]; channels as usize],
+            previous_size: 0,
+            previous_fall: 0,
+            primed: false,
+            position: 0,
+            pcm: Vec::with_capacity(long_size / 2 * channels as usize),
+            pcm_offset: 0,
+            spectra: vec![vec![0.0; long_size]; channels as usize],
+            ys: Vec::with_capacity(65),
+            used: vec![false; 65],
+            scratch: Vec::with_capacity(long_size / 2 * channels as usize),
+            classes: Vec::with_capacity(classes),
+            complex: Vec::with_capacity(long_size / 4),
+        })
+    }
+
+    /// Get the amount of channels of the stream.
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Get the sample rate of the stream.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Get the rate of the AI matching the stream, if any, for it to be played without
+    /// resampling.
+    pub fn ai_sample_rate(&self) -> Option<SampleRate> {
+        match self.sample_rate {
+            32000 => Some(SampleRate::Khz32),
+            48000 => Some(SampleRate::Khz48),
+            _ => None,
+        }
+    }
+
+    /// Go back to the start of the stream, to loop it.
+    pub fn rewind(&mut self) -> Result<(), VorbisError> {
+        self.reader.rewind();
+        for kind in [1, 3, 5] {
+            next_header(&mut self.reader, kind)?;
+        }
+        self.primed = false;
+        self.position = 0;
+        self.pcm.clear();
+        self.pcm_offset = 0;
+        Ok(())
+    }
+
+    /// Decode interleaved samples into ``out``, returning how many were written, which is always
+    /// a multiple of the amount of channels, and zero at the end of the stream.
+    ///
+    /// Packets which fail to decode are skipped.
+    pub fn read(&mut self, out: &mut [i16]) -> Result<usize, VorbisError> {
+        let channels = self.channels as usize;
+        let wanted = out.len() - out.len() % channels;
+        let mut written = 0;
+        while written < wanted {
+            if self.pcm_offset == self.pcm.len() && !self.decode_packet()? {
+                break;
+            }
+            let count = (self.pcm.len() - self.pcm_offset).min(wanted - written);
+            out[written..written + count]
+                .copy_from_slice(&self.pcm[self.pcm_offset..self.pcm_offset + count]);
+            self.pcm_offset += count;
+            written += count;
+        }
+        Ok(written)
+    }
+
+    /// Decode interleaved stereo samples into ``out``, duplicating a mono stream and keeping
+    /// only the front left and right channels of a surround one, returning how many were
+    /// written.
+    ///
+    /// Nothing gets allocated unless a packet spans several pages, so this can be called from
+    /// the refill callback of a ``luma_core::audio::Playback`` running at
+    /// [`Vorbis::ai_sample_rate`].
+    pub fn read_stereo(&mut self, out: &mut [i16]) -> Result<usize, VorbisError> {
+        let channels = self.channels as usize;
+        if channels == 2 {
+            return self.read(out);
+        }
+        // Channel order of the specification.
+        let right = match channels {
+            1 => 0,
+            3 | 5..=8 => 2,
+            _ => 1,
+        };
+        let mut written = 0;
+        for frame in out.chunks_exact_mut(2) {
+            if self.pcm_offset == self.pcm.len() && !self.decode_packet()? {
+                break;
+            }
+            let samples = &self.pcm[self.pcm_offset..self.pcm_offset + channels];
+            frame[0] = samples[0];
+            frame[1] = samples[right];
+            self.pcm_offset += channels;
+            written += 2;
+        }
+        Ok(written)
+    }
+
+    /// Decode the whole stream, as interleaved samples.
+    pub fn decode_all(&mut self) -> Result<Vec<i16>, VorbisError> {
+        let mut samples = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let count = self.read(&mut buffer[..4096 - 4096 % self.channels as usize])?;
+            if count == 0 {
+                return Ok(samples);
+            }
+            samples.extend_from_slice(&buffer[..count]);
+        }
+    }
+
+    /// Decode the whole stream, as interleaved stereo samples, see [`Vorbis::read_stereo`].
+    pub fn decode_all_stereo(&mut self) -> Result<Vec<i16>, VorbisError> {
+        let mut samples = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let count = self.read_stereo(&mut buffer)?;
+            if count == 0 {
+                return Ok(samples);
+            }
+            samples.extend_from_slice(&buffer[..count]);
+        }
+    }
+
+    /// Decode the next audio packet into ``pcm``, returning false at the end of the stream.
+    fn decode_packet(&mut self) -> Result<bool, VorbisError> {
+        self.pcm.clear();
+        self.pcm_offset = 0;
+        loop {
+            let Some(packet) = self.reader.next_packet()? else {
+                return Ok(false);
+            };
+            let OggPacket {
+                data,
+                granule,
+                end_of_stream,
+            } = packet;
+
+            let Some(frames) = decode_audio(
+                data,
+                &mut AudioState {
+                    channels: self.channels as usize,
+                    blocks: &self.blocks,
+                    codebooks: &self.codebooks,
+                    floors: &self.floors,
+                    residues: &self.residues,
+                    mappings: &self.mappings,
+                    modes: &self.modes,
+                    previous: &mut self.previous,
+                    previous_size: &mut self.previous_size,
+                    previous_fall: &mut self.previous_fall,
+                    primed: &mut self.primed,
+                    pcm: &mut self.pcm,
+                    spectra: &mut self.spectra,
+                    ys: &mut self.ys,
+                    used: &mut self.used,
+                    scratch: &mut self.scratch,
+                    classes: &mut self.classes,
+                    complex: &mut self.complex,
+                },
+            ) else {
+                continue;
+            };
+
+            // The last page tells how many samples the stream really has.
+            let mut frames = frames as u64;
+            if let Some(granule) = granule
+                && end_of_stream
+                && granule >= 0
+            {
+                frames = frames.min((granule as u64).saturating_sub(self.position));
+                self.pcm.truncate(frames as usize * self.channels as usize);
+            }
+            self.position += frames;
+            if frames > 0 {
+                return Ok(true);
+            }
+            if end_of_stream {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Read the next packet, which must be the Vorbis header of the given kind.
+fn next_header<'b>(reader: &'b mut OggReader, kind: u8) -> Result<OggPacket<'b>, VorbisError> {
+    let packet = reader.next_packet()?.ok_or(VorbisError::MissingHeader)?;
+    if packet.data.len() < 7 || packet.data[0] != kind || &packet.data[1..7] != b"vorbis" {
+        return Err(VorbisError::BadHeader);
+    }
+    Ok(packet)
+}
+
+#[allow(clippy::type_complexity)]
+fn read_setup(
+    bits: &mut BitReader,
+    channels: u8,
+) -> Option<
+    Result<
+        (
+            Vec<Codebook>,
+            Vec<Floor>,
+            Vec<Residue>,
+            Vec<Mapping>,
+            Vec<Mode>,
+        ),
+        VorbisError,
+    >,
+> {
+    let count = bits.read(8)? + 1;
+    let mut codebooks = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        match Codebook::read(bits)? {
+            Ok(codebook) => codebooks.push(codebook),
+            Err(err) => return Some(Err(err)),
+        }
+    }
+
+    // Time domain transforms, placeholders which must be zero.
+    let count = bits.read(6)? + 1;
+    for _ in 0..count {
+        if bits.read(16)? != 0 {
+            return Some(Err(VorbisError::BadHeader));
+        }
+    }
+
+    let count = bits.read(6)? + 1;
+    let mut floors = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        match Floor::read(bits, &codebooks)? {
+            Ok(floor) => floors.push(floor),
+            Err(err) => return Some(Err(err)),
+        }
+    }
+
+    let count = bits.read(6)? + 1;
+    let mut residues = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        match Residue::read(bits, &codebooks)? {
+            Ok(residue) => residues.push(residue),
+            Err(err) => return Some(Err(err)),
+        }
+    }
+
+    let count = bits.read(6)? + 1;
+    let mut mappings = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        match Mapping::read(bits, channels, floors.len(), residues.len())? {
+            Ok(mapping) => mappings.push(mapping),
+            Err(err) => return Some(Err(err)),
+        }
+    }
+
+    let count = bits.read(6)? + 1;
+    let mut modes = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let long = bits.read_bool()?;
+        let window = bits.read(16)?;
+        let transform = bits.read(16)?;
+        let mapping = bits.read(8)? as u8;
+        if window != 0 || transform != 0 || mapping as usize >= mappings.len() {
+            return Some(Err(VorbisError::BadHeader));
+        }
+        modes.push(Mode { long, mapping });
+    }
+
+    if !bits.read_bool()? {
+        return Some(Err(VorbisError::BadHeader));
+    }
+    Some(Ok((codebooks, floors, residues, mappings, modes)))
+}
+
+/// Borrows of everything [`decode_audio`] needs from the decoder, so that the packet can stay
+/// borrowed from the Ogg reader.
+struct AudioState<'b> {
+    channels: usize,
+    blocks: &'b [Block; 2],
+    codebooks: &'b [Codebook],
+    floors: &'b [Floor],
+    residues: &'b [Residue],
+    mappings: &'b [Mapping],
+    modes: &'b [Mode],
+    previous: &'b mut [Vec<f32>],
+    previous_size: &'b mut usize,
+    previous_fall: &'b mut usize,
+    primed: &'b mut bool,
+    pcm: &'b mut Vec<i16>,
+    spectra: &'b mut [Vec<f32>],
+    ys: &'b mut Vec<i32>,
+    used: &'b mut Vec<bool>,
+    scratch: &'b mut Vec<f32>,
+    classes: &'b mut Vec<u32>,
+    complex: &'b mut Vec<(f32, f32)>,
+}
+
+/// Decode an audio packet, appending its interleaved samples to ``pcm`` and returning the amount
+/// of frames, or ``None`` if the packet can’t be decoded.
+fn decode_audio(data: &[u8], state: &mut AudioState) -> Option<usize> {
+    let mut reader = BitReader::new(data);
+    if reader.read_bool()? {
+        return None;
+    }
+    let mode = *state
+        .modes
+        .get(reader.read(ilog(state.modes.len() as u32 - 1))? as usize)?;
+    let mapping = &state.mappings[mode.mapping as usize];
+    let block = &state.blocks[mode.long as usize];
+    let short = &state.blocks[0];
+    let n = block.size;
+    let half = n / 2;
+
+    let (previous_long, next_long) = if mode.long {
+        (reader.read_bool()?, reader.read_bool()?)
+    } else {
+        (false, false)
+    };
+
+    // Where the window rises and falls, and over which slope.
+    let (rise_start, rise_slope) = if mode.long && !previous_long {
+        (n / 4 - short.size / 4, &short.slope)
+    } else {
+        (0, &block.slope)
+    };
+    let (fall_start, fall_slope) = if mode.long && !next_long {
+        (n * 3 / 4 - short.size / 4, &short.slope)
+    } else {
+        (half, &block.slope)
+    };
+
+    // Floors, the curve going straight into the spectrum.
+    let mut unused = [false; 256];
+    for (channel, unused) in unused.iter_mut().enumerate().take(state.channels) {
+        let (floor, _) = mapping.submaps[mapping.mux[channel] as usize];
+        let floor = &state.floors[floor as usize];
+        let spectrum = &mut state.spectra[channel][..half];
+        if floor.decode(&mut reader, state.codebooks, state.ys) {
+            state.used.resize(state.ys.len(), false);
+            floor.synthesize(state.ys, state.used, spectrum);
+        } else {
+            *unused = true;
+        }
+    }
+
+    // A coupled channel needs its residue as soon as either side is used.
+    let mut skip = unused;
+    for &(magnitude, angle) in &mapping.coupling {
+        let (magnitude, angle) = (magnitude as usize, angle as usize);
+        if !skip[magnitude] || !skip[angle] {
+            skip[magnitude] = false;
+            skip[angle] = false;
+        }
+    }
+
+    // Residues, decoded in the second half of the buffers which the IMDCT will overwrite.
+    for (submap, &(_, residue)) in mapping.submaps.iter().enumerate() {
+        let residue = &state.residues[residue as usize];
+        let mut channels = [0; 256];
+        let mut flags = [false; 256];
+        let mut count = 0;
+        for (channel, &mux) in mapping.mux.iter().enumerate() {
+            if mux as usize == submap {
+                channels[count] = channel as u8;
+                flags[count] = skip[channel];
+                count += 1;
+            }
+        }
+        residue.decode(
+            &mut reader,
+            state.codebooks,
+            half,
+            state.spectra,
+            &channels[..count],
+            &flags[..count],
+            state.scratch,
+            state.classes,
+        );
+    }
+
+    // Inverse coupling.
+    for &(magnitude, angle) in mapping.coupling.iter().rev() {
+        let (magnitude, angle) = (magnitude as usize, angle as usize);
+        for i in half..n {
+            let m = state.spectra[magnitude][i];
+            let a = state.spectra[angle][i];
+            let (m, a) = if m > 0.0 {
+                if a > 0.0 { (m, m - a) } else { (m + a, m) }
+            } else if a > 0.0 {
+                (m, m + a)
+            } else {
+                (m - a, m)
+            };
+            state.spectra[magnitude][i] = m;
+            state.spectra[angle][i] = a;
+        }
+    }
+
+    let frames = if *state.primed {
+        *state.previous_size / 4 + n / 4
+    } else {
+        0
+    };
+    let first = state.pcm.len();
+    state.pcm.resize(first + frames * state.channels, 0);
+
+    for (channel, &unused) in unused.iter().enumerate().take(state.channels) {
+        let spectrum = &mut state.spectra[channel];
+
+        // Dot product of the floor and the residue.
+        if unused {
+            spectrum[..half].fill(0.0);
+        } else {
+            let (floor, residue) = spectrum[..n].split_at_mut(half);
+            for (floor, &residue) in floor.iter_mut().zip(residue.iter()) {
+                *floor *= residue;
+            }
+        }
+
+        block.imdct(&mut spectrum[..n], state.complex, state.scratch);
+
+        // Windowing.
+        spectrum[..rise_start].fill(0.0);
+        for (sample, &window) in spectrum[rise_start..].iter_mut().zip(rise_slope.iter()) {
+            *sample *= window;
+        }
+        for (sample, &window) in spectrum[fall_start..]
+            .iter_mut()
+            .zip(fall_slope.iter().rev())
+        {
+            *sample *= window;
+        }
+        spectrum[fall_start + fall_slope.len()..n].fill(0.0);
+
+        // Overlap and add, from the center of the previous block to the center of this one.
+        if *state.primed {
+            let previous = &state.previous[channel][..*state.previous_size / 2];
+            // Index in this block of the center of the previous one.
+            let offset = rise_start as isize - *state.previous_fall as isize;
+            for k in 0..frames {
+                let mut sample = previous.get(k).copied().unwrap_or(0.0);
+                let index = k as isize + offset;
+                if index >= 0 && (index as usize) < half {
+                    sample += spectrum[index as usize];
+                }
+                let sample = sample * 32768.0;
+                state.pcm[first + k * state.channels + channel] = if sample > 32767.0 {
+                    32767
+                } else if sample < -32768.0 {
+                    -32768
+                } else {
+                    sample as i16
+                };
+            }
+        }
+        state.previous[channel][..half].copy_from_slice(&spectrum[half..n]);
+    }
+
+    *state.previous_size = n;
+    *state.previous_fall = fall_start - half;
+    *state.primed = true;
+    Some(frames)
+}
+
+/// Amplitudes of the floor curve, from the specification.
+#[allow(clippy::excessive_precision)]
+static INVERSE_DB_TABLE: [f32; 256] = [
+    1.0649863e-07,
+    1.1341951e-07,
+    1.2079015e-07,
+    1.2863978e-07,
+    1.3699951e-07,
+    1.4590251e-07,
+    1.5538408e-07,
+    1.6548181e-07,
+    1.7623575e-07,
+    1.8768855e-07,
+    1.9988561e-07,
+    2.1287530e-07,
+    2.2670913e-07,
+    2.4144197e-07,
+    2.5713223e-07,
+    2.7384213e-07,
+    2.9163793e-07,
+    3.1059021e-07,
+    3.3077411e-07,
+    3.5226968e-07,
+    3.7516214e-07,
+    3.9954229e-07,
+    4.2550680e-07,
+    4.5315863e-07,
+    4.8260743e-07,
+    5.1396998e-07,
+    5.4737065e-07,
+    5.8294187e-07,
+    6.2082472e-07,
+    6.6116941e-07,
+    7.0413592e-07,
+    7.4989464e-07,
+    7.9862701e-07,
+    8.5052630e-07,
+    9.0579828e-07,
+    9.6466216e-07,
+    1.0273513e-06,
+    1.0941144e-06,
+    1.1652161e-06,
+    1.2409384e-06,
+    1.3215816e-06,
+    1.4074654e-06,
+    1.4989305e-06,
+    1.5963394e-06,
+    1.7000785e-06,
+    1.8105592e-06,
+    1.9282195e-06,
+    2.0535261e-06,
+    2.1869758e-06,
+    2.3290978e-06,
+    2.4804557e-06,
+    2.6416497e-06,
+    2.8133190e-06,
+    2.9961443e-06,
+    3.1908506e-06,
+    3.3982101e-06,
+    3.6190449e-06,
+    3.8542308e-06,
+    4.1047004e-06,
+    4.3714470e-06,
+    4.6555282e-06,
+    4.9580707e-06,
+    5.2802740e-06,
+    5.6234160e-06,
+    5.9888572e-06,
+    6.3780469e-06,
+    6.7925283e-06,
+    7.2339451e-06,
+    7.7040476e-06,
+    8.2047000e-06,
+    8.7378876e-06,
+    9.3057248e-06,
+    9.9104632e-06,
+    1.0554501e-05,
+    1.1240392e-05,
+    1.1970856e-05,
+    1.2748789e-05,
+    1.3577278e-05,
+    1.4459606e-05,
+    1.5399272e-05,
+    1.6400004e-05,
+    1.7465768e-05,
+    1.8600792e-05,
+    1.9809576e-05,
+    2.1096914e-05,
+    2.2467911e-05,
+    2.3928002e-05,
+    2.5482978e-05,
+    2.7139006e-05,
+    2.8902651e-05,
+    3.0780908e-05,
+    3.2781225e-05,
+    3.4911534e-05,
+    3.7180282e-05,
+    3.9596466e-05,
+    4.2169667e-05,
+    4.4910090e-05,
+    4.7828601e-05,
+    5.0936773e-05,
+    5.4246931e-05,
+    5.7772202e-05,
+    6.1526565e-05,
+    6.5524908e-05,
+    6.9783085e-05,
+    7.4317983e-05,
+    7.9147585e-05,
+    8.4291040e-05,
+    8.9768747e-05,
+    9.5602426e-05,
+    0.00010181521,
+    0.00010843174,
+    0.00011547824,
+    0.00012298267,
+    0.00013097477,
+    0.00013948625,
+    0.00014855085,
+    0.00015820453,
+    0.00016848555,
+    0.00017943469,
+    0.00019109536,
+    0.00020351382,
+    0.00021673929,
+    0.00023082423,
+    0.00024582449,
+    0.00026179955,
+    0.00027881276,
+    0.00029693158,
+    0.00031622787,
+    0.00033677814,
+    0.00035866388,
+    0.00038197188,
+    0.00040679456,
+    0.00043323036,
+    0.00046138411,
+    0.00049136745,
+    0.00052329927,
+    0.00055730621,
+    0.00059352311,
+    0.00063209358,
+    0.00067317058,
+    0.00071691700,
+    0.00076350630,
+    0.00081312324,
+    0.00086596457,
+    0.00092223983,
+    0.00098217216,
+    0.0010459992,
+    0.0011139742,
+    0.0011863665,
+    0.0012634633,
+    0.0013455702,
+    0.0014330129,
+    0.0015261382,
+    0.0016253153,
+    0.0017309374,
+    0.0018434235,
+    0.0019632195,
+    0.0020908006,
+    0.0022266726,
+    0.0023713743,
+    0.0025254795,
+    0.0026895994,
+    0.0028643847,
+    0.0030505286,
+    0.0032487691,
+    0.0034598925,
+    0.0036847358,
+    0.0039241906,
+    0.0041792066,
+    0.0044507950,
+    0.0047400328,
+    0.0050480668,
+    0.0053761186,
+    0.0057254891,
+    0.0060975636,
+    0.0064938176,
+    0.0069158225,
+    0.0073652516,
+    0.0078438871,
+    0.0083536271,
+    0.0088964928,
+    0.009474637,
+    0.010090352,
+    0.010746080,
+    0.011444421,
+    0.012188144,
+    0.012980198,
+    0.013823725,
+    0.014722068,
+    0.015678791,
+    0.016697687,
+    0.017782797,
+    0.018938423,
+    0.020169149,
+    0.021479854,
+    0.022875735,
+    0.024362330,
+    0.025945531,
+    0.027631618,
+    0.029427276,
+    0.031339626,
+    0.033376252,
+    0.035545228,
+    0.037855157,
+    0.040315199,
+    0.042935108,
+    0.045725273,
+    0.048696758,
+    0.051861348,
+    0.055231591,
+    0.058820850,
+    0.062643361,
+    0.066714279,
+    0.071049749,
+    0.075666962,
+    0.080584227,
+    0.085821044,
+    0.091398179,
+    0.097337747,
+    0.10366330,
+    0.11039993,
+    0.11757434,
+    0.12521498,
+    0.13335215,
+    0.14201813,
+    0.15124727,
+    0.16107617,
+    0.17154380,
+    0.18269168,
+    0.19456402,
+    0.20720788,
+    0.22067342,
+    0.23501402,
+    0.25028656,
+    0.26655159,
+    0.28387361,
+    0.30232132,
+    0.32196786,
+    0.34289114,
+    0.36517414,
+    0.38890521,
+    0.41417847,
+    0.44109412,
+    0.46975890,
+    0.50028648,
+    0.53279791,
+    0.56742212,
+    0.60429640,
+    0.64356699,
+    0.68538959,
+    0.72993007,
+    0.77736504,
+    0.82788260,
+    0.88168307,
+    0.9389798,
+    1.0,
+];
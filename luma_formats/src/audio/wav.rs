@@ -0,0 +1,146 @@
+//! ``wav`` module of ``luma_formats::audio``.
+//!
+//! Contains a parser for RIFF/WAVE files holding 8-bit or 16-bit PCM, converting their
+//! little-endian samples to native 16-bit ones.
+
+use alloc::vec::Vec;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// Errors which can be encountered while parsing a WAVE file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WavError {
+    /// The file doesn’t start with a RIFF header of type ``WAVE``.
+    NotWave,
+    /// A chunk goes past the end of the file, or the ``fmt `` chunk is too small.
+    OutOfBounds,
+    /// The ``fmt `` or ``data`` chunk is missing.
+    MissingChunk,
+    /// The samples aren’t PCM.
+    UnknownFormat(u16),
+    /// The samples are neither 8-bit nor 16-bit.
+    UnsupportedBits(u16),
+    /// There are neither one nor two channels.
+    UnsupportedChannels(u16),
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// A WAVE file, borrowing its samples.
+#[derive(Clone, Copy, Debug)]
+pub struct Wav<'a> {
+    channels: u8,
+    sample_rate: u32,
+    bits: u8,
+    data: &'a [u8],
+}
+
+impl<'a> Wav<'a> {
+    /// Parse a WAVE file, ignoring every chunk but ``fmt `` and ``data``.
+    pub fn parse(file: &'a [u8]) -> Result<Wav<'a>, WavError> {
+        if file.len() < 12 || &file[0..4] != b"RIFF" || &file[8..12] != b"WAVE" {
+            return Err(WavError::NotWave);
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut offset = 12;
+        while offset + 8 <= file.len() {
+            let id = &file[offset..offset + 4];
+            let size = read_u32(file, offset + 4) as usize;
+            let start = offset + 8;
+            let end = start.checked_add(size).ok_or(WavError::OutOfBounds)?;
+            let body = file.get(start..end).ok_or(WavError::OutOfBounds)?;
+            match id {
+                b"fmt " => format = Some(body),
+                b"data" => data = Some(body),
+                _ => (),
+            }
+            // Chunks are padded to an even size, which the last one may lack.
+            offset = end.checked_add(size & 1).ok_or(WavError::OutOfBounds)?;
+        }
+        let (Some(format), Some(data)) = (format, data) else {
+            return Err(WavError::MissingChunk);
+        };
+
+        if format.len() < 16 {
+            return Err(WavError::OutOfBounds);
+        }
+        let mut tag = read_u16(format, 0);
+        if tag == FORMAT_EXTENSIBLE && format.len() >= 26 {
+            // The real format is the start of the subformat GUID.
+            tag = read_u16(format, 24);
+        }
+        if tag != FORMAT_PCM {
+            return Err(WavError::UnknownFormat(tag));
+        }
+        let channels = read_u16(format, 2);
+        if channels != 1 && channels != 2 {
+            return Err(WavError::UnsupportedChannels(channels));
+        }
+        let bits = read_u16(format, 14);
+        if bits != 8 && bits != 16 {
+            return Err(WavError::UnsupportedBits(bits));
+        }
+
+        // Drop any trailing partial frame.
+        let frame_size = (channels * bits / 8) as usize;
+        let data = &data[..data.len() - data.len() % frame_size];
+
+        Ok(Wav {
+            channels: channels as u8,
+            sample_rate: read_u32(format, 4),
+            bits: bits as u8,
+            data,
+        })
+    }
+
+    /// Get the amount of channels, one or two.
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Get the sample rate of the file.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Get the amount of bits per sample in the file, 8 or 16.
+    pub fn bits_per_sample(&self) -> u8 {
+        self.bits
+    }
+
+    /// Get the amount of frames in the file.
+    pub fn frames(&self) -> usize {
+        self.data.len() / (self.channels as usize * self.bits as usize / 8)
+    }
+
+    /// Iterate over the samples, interleaved if there are two channels, converted to 16-bit.
+    pub fn samples(&self) -> impl Iterator<Item = i16> + 'a {
+        let data = self.data;
+        let bytes = self.bits as usize / 8;
+        data.chunks_exact(bytes).map(move |sample| match *sample {
+            // 8-bit samples are unsigned.
+            [sample] => ((sample as i16) - 128) << 8,
+            [low, high] => i16::from_le_bytes([low, high]),
+            _ => unreachable!(),
+        })
+    }
+
+    /// Convert the samples to native 16-bit ones, interleaved if there are two channels.
+    pub fn to_pcm16(&self) -> Vec<i16> {
+        self.samples().collect()
+    }
+}
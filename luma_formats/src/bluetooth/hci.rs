@@ -0,0 +1,470 @@
+//! ``hci`` module of ``luma_formats::bluetooth``.
+//!
+//! Contains the encoding of the HCI commands sent to the Bluetooth controller, and the decoding
+//! of the events and ACL data it sends back.  Nothing in here does any I/O.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const RESET: u16 = 0x0c03;
+pub const SET_EVENT_FILTER: u16 = 0x0c05;
+pub const READ_STORED_LINK_KEY: u16 = 0x0c0d;
+pub const WRITE_STORED_LINK_KEY: u16 = 0x0c11;
+pub const WRITE_PAGE_TIMEOUT: u16 = 0x0c18;
+pub const WRITE_SCAN_ENABLE: u16 = 0x0c1a;
+pub const READ_BUFFER_SIZE: u16 = 0x1005;
+pub const READ_BD_ADDR: u16 = 0x1009;
+pub const INQUIRY: u16 = 0x0401;
+pub const INQUIRY_CANCEL: u16 = 0x0402;
+pub const CREATE_CONNECTION: u16 = 0x0405;
+pub const DISCONNECT: u16 = 0x0406;
+pub const ACCEPT_CONNECTION_REQUEST: u16 = 0x0409;
+pub const REJECT_CONNECTION_REQUEST: u16 = 0x040a;
+pub const LINK_KEY_REQUEST_REPLY: u16 = 0x040b;
+pub const LINK_KEY_REQUEST_NEGATIVE_REPLY: u16 = 0x040c;
+pub const PIN_CODE_REQUEST_REPLY: u16 = 0x040d;
+pub const AUTHENTICATION_REQUESTED: u16 = 0x0411;
+
+/// Page scan only, so that known devices can connect but nobody can discover us.
+pub const SCAN_PAGE: u8 = 0x02;
+
+/// Packet types allowed on connections we create: DM1, DH1, DM3, DH3, DM5 and DH5.
+const PACKET_TYPES: u16 = 0xcc18;
+
+/// Disconnection reason for a user ended connection.
+pub const REASON_USER_ENDED: u8 = 0x13;
+
+/// Rejection reason for a connection from an unknown device.
+pub const REASON_UNACCEPTABLE_ADDRESS: u8 = 0x0f;
+
+/// The General Inquiry Access Code.
+const GIAC: [u8; 3] = [0x33, 0x8b, 0x9e];
+
+/// A Bluetooth device address, stored in the little-endian order used by HCI.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Address(pub [u8; 6]);
+
+impl Address {
+    /// Create an address from its usual big-endian representation, the one stored in SYSCONF.
+    pub fn from_be_bytes(bytes: [u8; 6]) -> Address {
+        let mut address = bytes;
+        address.reverse();
+        Address(address)
+    }
+
+    fn read(bytes: &[u8]) -> Address {
+        let mut address = [0; 6];
+        address.copy_from_slice(&bytes[..6]);
+        Address(address)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{g:02x}:{e:02x}:{d:02x}:{c:02x}:{b:02x}:{a:02x}")
+    }
+}
+
+/// A link key, as generated when two devices get paired.
+pub type LinkKey = [u8; 16];
+
+/// Encode a command with its parameters.
+pub fn command(opcode: u16, parameters: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(3 + parameters.len());
+    packet.extend_from_slice(&opcode.to_le_bytes());
+    packet.push(parameters.len() as u8);
+    packet.extend_from_slice(parameters);
+    packet
+}
+
+fn command_with_address(opcode: u16, address: Address, rest: &[u8]) -> Vec<u8> {
+    let mut parameters = [0; 32];
+    parameters[..6].copy_from_slice(&address.0);
+    parameters[6..6 + rest.len()].copy_from_slice(rest);
+    command(opcode, &parameters[..6 + rest.len()])
+}
+
+pub fn reset() -> Vec<u8> {
+    command(RESET, &[])
+}
+
+pub fn read_bd_addr() -> Vec<u8> {
+    command(READ_BD_ADDR, &[])
+}
+
+pub fn read_buffer_size() -> Vec<u8> {
+    command(READ_BUFFER_SIZE, &[])
+}
+
+/// Clear every event filter, so that connection requests get reported instead of auto-accepted.
+pub fn clear_event_filters() -> Vec<u8> {
+    command(SET_EVENT_FILTER, &[0])
+}
+
+/// Ask for every link key stored in the controller, which get returned in
+/// [`Event::ReturnLinkKeys`] events.
+pub fn read_all_stored_link_keys() -> Vec<u8> {
+    command_with_address(READ_STORED_LINK_KEY, Address::default(), &[1])
+}
+
+/// Store a link key in the controller, which keeps it across resets.
+pub fn write_stored_link_key(address: Address, key: &LinkKey) -> Vec<u8> {
+    let mut parameters = [0; 23];
+    parameters[0] = 1;
+    parameters[1..7].copy_from_slice(&address.0);
+    parameters[7..].copy_from_slice(key);
+    command(WRITE_STORED_LINK_KEY, &parameters)
+}
+
+/// Set how long to page a device before giving up, in 0.625 ms slots.
+pub fn write_page_timeout(slots: u16) -> Vec<u8> {
+    command(WRITE_PAGE_TIMEOUT, &slots.to_le_bytes())
+}
+
+pub fn write_scan_enable(scan: u8) -> Vec<u8> {
+    command(WRITE_SCAN_ENABLE, &[scan])
+}
+
+/// Look for discoverable devices for ``length`` × 1.28 s.
+pub fn inquiry(length: u8) -> Vec<u8> {
+    command(INQUIRY, &[GIAC[0], GIAC[1], GIAC[2], length, 0])
+}
+
+pub fn inquiry_cancel() -> Vec<u8> {
+    command(INQUIRY_CANCEL, &[])
+}
+
+/// Connect to a device found by an inquiry.
+pub fn create_connection(address: Address, page_scan_mode: u8, clock_offset: u16) -> Vec<u8> {
+    let [types_low, types_high] = PACKET_TYPES.to_le_bytes();
+    let [offset_low, offset_high] = (clock_offset | 0x8000).to_le_bytes();
+    command_with_address(
+        CREATE_CONNECTION,
+        address,
+        &[
+            types_low,
+            types_high,
+            page_scan_mode,
+            0,
+            offset_low,
+            offset_high,
+            1,
+        ],
+    )
+}
+
+pub fn disconnect(handle: u16, reason: u8) -> Vec<u8> {
+    let [low, high] = handle.to_le_bytes();
+    command(DISCONNECT, &[low, high, reason])
+}
+
+/// Accept a connection, taking the master role as the Wii does.
+pub fn accept_connection_request(address: Address) -> Vec<u8> {
+    command_with_address(ACCEPT_CONNECTION_REQUEST, address, &[0])
+}
+
+pub fn reject_connection_request(address: Address, reason: u8) -> Vec<u8> {
+    command_with_address(REJECT_CONNECTION_REQUEST, address, &[reason])
+}
+
+pub fn link_key_request_reply(address: Address, key: &LinkKey) -> Vec<u8> {
+    command_with_address(LINK_KEY_REQUEST_REPLY, address, key)
+}
+
+pub fn link_key_request_negative_reply(address: Address) -> Vec<u8> {
+    command_with_address(LINK_KEY_REQUEST_NEGATIVE_REPLY, address, &[])
+}
+
+/// Answer a PIN code request, ``pin`` being at most 16 bytes.
+pub fn pin_code_request_reply(address: Address, pin: &[u8]) -> Vec<u8> {
+    let mut parameters = [0; 17];
+    parameters[0] = pin.len() as u8;
+    parameters[1..1 + pin.len()].copy_from_slice(pin);
+    command_with_address(PIN_CODE_REQUEST_REPLY, address, &parameters)
+}
+
+pub fn authentication_requested(handle: u16) -> Vec<u8> {
+    command(AUTHENTICATION_REQUESTED, &handle.to_le_bytes())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u24_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], 0])
+}
+
+/// A device found by an inquiry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InquiryResponse {
+    pub address: Address,
+    pub page_scan_mode: u8,
+    pub class: u32,
+    pub clock_offset: u16,
+}
+
+/// The devices reported by a single inquiry result event.
+#[derive(Clone, Copy, Debug)]
+pub struct InquiryResponses<'a> {
+    parameters: &'a [u8],
+}
+
+impl<'a> InquiryResponses<'a> {
+    /// Get the amount of devices.
+    pub fn len(&self) -> usize {
+        self.parameters[0] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get a device, each field being stored as an array covering every device.
+    pub fn get(&self, index: usize) -> Option<InquiryResponse> {
+        let count = self.len();
+        if index >= count {
+            return None;
+        }
+        let fields = &self.parameters[1..];
+        Some(InquiryResponse {
+            address: Address::read(&fields[index * 6..]),
+            page_scan_mode: fields[count * 6 + index],
+            class: u24_at(fields, count * 9 + index * 3),
+            clock_offset: u16_at(fields, count * 12 + index * 2),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = InquiryResponse> + 'a {
+        let responses = *self;
+        (0..self.len()).filter_map(move |index| responses.get(index))
+    }
+}
+
+/// Pairs of something and an address, stored one after the other.
+#[derive(Clone, Copy, Debug)]
+pub struct Records<'a> {
+    records: &'a [u8],
+}
+
+impl<'a> Records<'a> {
+    /// Iterate over the ``(handle, completed packets)`` of a
+    /// [`Event::NumberOfCompletedPackets`].
+    pub fn completed_packets(&self) -> impl Iterator<Item = (u16, u16)> + 'a {
+        self.records
+            .chunks_exact(4)
+            .map(|record| (u16_at(record, 0), u16_at(record, 2)))
+    }
+
+    /// Iterate over the keys of a [`Event::ReturnLinkKeys`].
+    pub fn link_keys(&self) -> impl Iterator<Item = (Address, LinkKey)> + 'a {
+        self.records.chunks_exact(22).map(|record| {
+            let mut key = [0; 16];
+            key.copy_from_slice(&record[6..]);
+            (Address::read(record), key)
+        })
+    }
+}
+
+/// An event sent by the controller.
+#[derive(Clone, Copy, Debug)]
+pub enum Event<'a> {
+    InquiryComplete {
+        status: u8,
+    },
+    InquiryResult(InquiryResponses<'a>),
+    ConnectionComplete {
+        status: u8,
+        handle: u16,
+        address: Address,
+        link_type: u8,
+    },
+    ConnectionRequest {
+        address: Address,
+        class: u32,
+        link_type: u8,
+    },
+    DisconnectionComplete {
+        status: u8,
+        handle: u16,
+        reason: u8,
+    },
+    AuthenticationComplete {
+        status: u8,
+        handle: u16,
+    },
+    CommandComplete {
+        credits: u8,
+        opcode: u16,
+        /// The return parameters, starting with the status in most cases.
+        parameters: &'a [u8],
+    },
+    CommandStatus {
+        status: u8,
+        credits: u8,
+        opcode: u16,
+    },
+    NumberOfCompletedPackets(Records<'a>),
+    ReturnLinkKeys(Records<'a>),
+    PinCodeRequest {
+        address: Address,
+    },
+    LinkKeyRequest {
+        address: Address,
+    },
+    LinkKeyNotification {
+        address: Address,
+        key: LinkKey,
+    },
+    /// Any other event, which nothing here cares about.
+    Other {
+        code: u8,
+        parameters: &'a [u8],
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Decode an event packet, returning ``None`` if it is truncated.
+    pub fn parse(packet: &'a [u8]) -> Option<Event<'a>> {
+        let (&code, rest) = packet.split_first()?;
+        let (&length, rest) = rest.split_first()?;
+        let p = rest.get(..length as usize)?;
+        let need = |length: usize| (p.len() >= length).then_some(());
+
+        Some(match code {
+            0x01 => {
+                need(1)?;
+                Event::InquiryComplete { status: p[0] }
+            }
+            0x02 => {
+                need(1)?;
+                need(1 + p[0] as usize * 14)?;
+                Event::InquiryResult(InquiryResponses { parameters: p })
+            }
+            0x03 => {
+                need(10)?;
+                Event::ConnectionComplete {
+                    status: p[0],
+                    handle: u16_at(p, 1) & 0x0fff,
+                    address: Address::read(&p[3..]),
+                    link_type: p[9],
+                }
+            }
+            0x04 => {
+                need(10)?;
+                Event::ConnectionRequest {
+                    address: Address::read(p),
+                    class: u24_at(p, 6),
+                    link_type: p[9],
+                }
+            }
+            0x05 => {
+                need(4)?;
+                Event::DisconnectionComplete {
+                    status: p[0],
+                    handle: u16_at(p, 1) & 0x0fff,
+                    reason: p[3],
+                }
+            }
+            0x06 => {
+                need(3)?;
+                Event::AuthenticationComplete {
+                    status: p[0],
+                    handle: u16_at(p, 1) & 0x0fff,
+                }
+            }
+            0x0e => {
+                need(3)?;
+                Event::CommandComplete {
+                    credits: p[0],
+                    opcode: u16_at(p, 1),
+                    parameters: &p[3..],
+                }
+            }
+            0x0f => {
+                need(4)?;
+                Event::CommandStatus {
+                    status: p[0],
+                    credits: p[1],
+                    opcode: u16_at(p, 2),
+                }
+            }
+            0x13 => {
+                need(1)?;
+                let records = p[1..].get(..p[0] as usize * 4)?;
+                Event::NumberOfCompletedPackets(Records { records })
+            }
+            0x15 => {
+                need(1)?;
+                let records = p[1..].get(..p[0] as usize * 22)?;
+                Event::ReturnLinkKeys(Records { records })
+            }
+            0x16 => {
+                need(6)?;
+                Event::PinCodeRequest {
+                    address: Address::read(p),
+                }
+            }
+            0x17 => {
+                need(6)?;
+                Event::LinkKeyRequest {
+                    address: Address::read(p),
+                }
+            }
+            0x18 => {
+                need(22)?;
+                let mut key = [0; 16];
+                key.copy_from_slice(&p[6..22]);
+                Event::LinkKeyNotification {
+                    address: Address::read(p),
+                    key,
+                }
+            }
+            code => Event::Other {
+                code,
+                parameters: p,
+            },
+        })
+    }
+}
+
+/// Packet boundary flag of the first fragment of an L2CAP frame.
+const ACL_START: u16 = 0b10 << 12;
+
+/// Packet boundary flag of the following fragments.
+const ACL_CONTINUATION: u16 = 0b01 << 12;
+
+/// Split an L2CAP frame into ACL packets of at most ``mtu`` bytes of data.
+pub fn acl_packets(handle: u16, frame: &[u8], mtu: usize) -> impl Iterator<Item = Vec<u8>> + '_ {
+    frame.chunks(mtu.max(1)).enumerate().map(move |(i, data)| {
+        let flags = if i == 0 { ACL_START } else { ACL_CONTINUATION };
+        let mut packet = Vec::with_capacity(4 + data.len());
+        packet.extend_from_slice(&(handle | flags).to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        packet.extend_from_slice(data);
+        packet
+    })
+}
+
+/// A received ACL packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AclPacket<'a> {
+    pub handle: u16,
+    /// Whether this starts a new L2CAP frame, instead of continuing the previous one.
+    pub start: bool,
+    pub data: &'a [u8],
+}
+
+impl<'a> AclPacket<'a> {
+    /// Decode an ACL packet, returning ``None`` if it is truncated.
+    pub fn parse(packet: &'a [u8]) -> Option<AclPacket<'a>> {
+        let header = u16_at(packet.get(..4)?, 0);
+        let length = u16_at(packet, 2) as usize;
+        Some(AclPacket {
+            handle: header & 0x0fff,
+            start: header & (0b11 << 12) != ACL_CONTINUATION,
+            data: packet[4..].get(..length)?,
+        })
+    }
+}
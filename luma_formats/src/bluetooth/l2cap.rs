@@ -0,0 +1,687 @@
+//! ``l2cap`` module of ``luma_formats::bluetooth``.
+//!
+//! Contains the L2CAP layer on top of an ACL connection: reassembly of frames split over several
+//! ACL packets, the signalling commands, and the channels they open and configure.  Nothing in
+//! here does any I/O, frames to send are returned to the caller instead.
+
+use alloc::vec::Vec;
+
+/// Channel carrying the signalling commands.
+pub const CID_SIGNALLING: u16 = 0x0001;
+
+/// First channel identifier available for dynamically allocated channels.
+const CID_DYNAMIC: u16 = 0x0040;
+
+/// Protocol of the HID control channel.
+pub const PSM_HID_CONTROL: u16 = 0x11;
+
+/// Protocol of the HID interrupt channel, carrying the reports.
+pub const PSM_HID_INTERRUPT: u16 = 0x13;
+
+const COMMAND_REJECT: u8 = 0x01;
+const CONNECTION_REQUEST: u8 = 0x02;
+const CONNECTION_RESPONSE: u8 = 0x03;
+const CONFIGURATION_REQUEST: u8 = 0x04;
+const CONFIGURATION_RESPONSE: u8 = 0x05;
+const DISCONNECTION_REQUEST: u8 = 0x06;
+const DISCONNECTION_RESPONSE: u8 = 0x07;
+const ECHO_REQUEST: u8 = 0x08;
+const ECHO_RESPONSE: u8 = 0x09;
+const INFORMATION_REQUEST: u8 = 0x0a;
+const INFORMATION_RESPONSE: u8 = 0x0b;
+
+pub const RESULT_SUCCESS: u16 = 0x0000;
+pub const RESULT_PENDING: u16 = 0x0001;
+pub const RESULT_PSM_NOT_SUPPORTED: u16 = 0x0002;
+
+/// Information type reported as not supported.
+const INFORMATION_NOT_SUPPORTED: u16 = 0x0001;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Encode a frame sent on channel ``cid``.
+pub fn frame(cid: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(&cid.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Decode a complete frame into its channel and payload, returning ``None`` if it is truncated.
+pub fn parse_frame(frame: &[u8]) -> Option<(u16, &[u8])> {
+    let length = u16_at(frame.get(..4)?, 0) as usize;
+    Some((u16_at(frame, 2), frame[4..].get(..length)?))
+}
+
+/// Glues back together the ACL packets making up a frame.
+#[derive(Debug, Default)]
+pub struct Reassembly {
+    buffer: Vec<u8>,
+}
+
+impl Reassembly {
+    pub fn new() -> Reassembly {
+        Reassembly::default()
+    }
+
+    /// Add the data of an ACL packet, returning the frame once it is complete.
+    pub fn push(&mut self, start: bool, data: &[u8]) -> Option<&[u8]> {
+        if start {
+            self.buffer.clear();
+        } else if self.buffer.is_empty() {
+            // The start of this frame got lost.
+            return None;
+        }
+        self.buffer.extend_from_slice(data);
+
+        let length = 4 + u16_at(self.buffer.get(..4)?, 0) as usize;
+        if self.buffer.len() < length {
+            return None;
+        }
+        // Anything past the end of the frame is garbage, dropped along with it.
+        Some(&self.buffer[..length])
+    }
+
+    /// Forget the current frame, once it has been handled.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+/// A signalling command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal<'a> {
+    CommandReject {
+        id: u8,
+        reason: u16,
+    },
+    ConnectionRequest {
+        id: u8,
+        psm: u16,
+        source: u16,
+    },
+    ConnectionResponse {
+        id: u8,
+        destination: u16,
+        source: u16,
+        result: u16,
+        status: u16,
+    },
+    ConfigurationRequest {
+        id: u8,
+        destination: u16,
+        flags: u16,
+        options: &'a [u8],
+    },
+    ConfigurationResponse {
+        id: u8,
+        source: u16,
+        flags: u16,
+        result: u16,
+        options: &'a [u8],
+    },
+    DisconnectionRequest {
+        id: u8,
+        destination: u16,
+        source: u16,
+    },
+    DisconnectionResponse {
+        id: u8,
+        destination: u16,
+        source: u16,
+    },
+    EchoRequest {
+        id: u8,
+        data: &'a [u8],
+    },
+    EchoResponse {
+        id: u8,
+        data: &'a [u8],
+    },
+    InformationRequest {
+        id: u8,
+        info_type: u16,
+    },
+    InformationResponse {
+        id: u8,
+        info_type: u16,
+        result: u16,
+    },
+    /// A command which nothing here understands.
+    Unknown {
+        code: u8,
+        id: u8,
+    },
+}
+
+impl<'a> Signal<'a> {
+    /// Decode every command found in the payload of a signalling frame.  Truncated commands
+    /// end the iteration.
+    pub fn parse_all(payload: &'a [u8]) -> impl Iterator<Item = Signal<'a>> + 'a {
+        let mut rest = payload;
+        core::iter::from_fn(move || {
+            let header = rest.get(..4)?;
+            let (code, id) = (header[0], header[1]);
+            let data = rest[4..].get(..u16_at(header, 2) as usize)?;
+            rest = &rest[4 + data.len()..];
+            Some(Signal::parse(code, id, data))
+        })
+    }
+
+    fn parse(code: u8, id: u8, data: &'a [u8]) -> Signal<'a> {
+        let field = |index: usize| {
+            data.get(index * 2..index * 2 + 2)
+                .map_or(0, |bytes| u16_at(bytes, 0))
+        };
+        let tail = |offset: usize| data.get(offset..).unwrap_or(&[]);
+        match code {
+            COMMAND_REJECT => Signal::CommandReject {
+                id,
+                reason: field(0),
+            },
+            CONNECTION_REQUEST => Signal::ConnectionRequest {
+                id,
+                psm: field(0),
+                source: field(1),
+            },
+            CONNECTION_RESPONSE => Signal::ConnectionResponse {
+                id,
+                destination: field(0),
+                source: field(1),
+                result: field(2),
+                status: field(3),
+            },
+            CONFIGURATION_REQUEST => Signal::ConfigurationRequest {
+                id,
+                destination: field(0),
+                flags: field(1),
+                options: tail(4),
+            },
+            CONFIGURATION_RESPONSE => Signal::ConfigurationResponse {
+                id,
+                source: field(0),
+                flags: field(1),
+                result: field(2),
+                options: tail(6),
+            },
+            DISCONNECTION_REQUEST => Signal::DisconnectionRequest {
+                id,
+                destination: field(0),
+                source: field(1),
+            },
+            DISCONNECTION_RESPONSE => Signal::DisconnectionResponse {
+                id,
+                destination: field(0),
+                source: field(1),
+            },
+            ECHO_REQUEST => Signal::EchoRequest { id, data },
+            ECHO_RESPONSE => Signal::EchoResponse { id, data },
+            INFORMATION_REQUEST => Signal::InformationRequest {
+                id,
+                info_type: field(0),
+            },
+            INFORMATION_RESPONSE => Signal::InformationResponse {
+                id,
+                info_type: field(0),
+                result: field(1),
+            },
+            code => Signal::Unknown { code, id },
+        }
+    }
+
+    /// Encode this command, to be sent in a signalling frame.
+    pub fn encode(&self) -> Vec<u8> {
+        let (code, id, fields, data): (u8, u8, &[u16], &[u8]) = match *self {
+            Signal::CommandReject { id, reason } => (COMMAND_REJECT, id, &[reason], &[]),
+            Signal::ConnectionRequest { id, psm, source } => {
+                (CONNECTION_REQUEST, id, &[psm, source], &[])
+            }
+            Signal::ConnectionResponse {
+                id,
+                destination,
+                source,
+                result,
+                status,
+            } => (
+                CONNECTION_RESPONSE,
+                id,
+                &[destination, source, result, status],
+                &[],
+            ),
+            Signal::ConfigurationRequest {
+                id,
+                destination,
+                flags,
+                options,
+            } => (CONFIGURATION_REQUEST, id, &[destination, flags], options),
+            Signal::ConfigurationResponse {
+                id,
+                source,
+                flags,
+                result,
+                options,
+            } => (
+                CONFIGURATION_RESPONSE,
+                id,
+                &[source, flags, result],
+                options,
+            ),
+            Signal::DisconnectionRequest {
+                id,
+                destination,
+                source,
+            } => (DISCONNECTION_REQUEST, id, &[destination, source], &[]),
+            Signal::DisconnectionResponse {
+                id,
+                destination,
+                source,
+            } => (DISCONNECTION_RESPONSE, id, &[destination, source], &[]),
+            Signal::EchoRequest { id, data } => (ECHO_REQUEST, id, &[], data),
+            Signal::EchoResponse { id, data } => (ECHO_RESPONSE, id, &[], data),
+            Signal::InformationRequest { id, info_type } => {
+                (INFORMATION_REQUEST, id, &[info_type], &[])
+            }
+            Signal::InformationResponse {
+                id,
+                info_type,
+                result,
+            } => (INFORMATION_RESPONSE, id, &[info_type, result], &[]),
+            Signal::Unknown { id, .. } => (COMMAND_REJECT, id, &[0], &[]),
+        };
+
+        let length = fields.len() * 2 + data.len();
+        let mut command = Vec::with_capacity(4 + length);
+        command.push(code);
+        command.push(id);
+        command.extend_from_slice(&(length as u16).to_le_bytes());
+        for field in fields {
+            command.extend_from_slice(&field.to_le_bytes());
+        }
+        command.extend_from_slice(data);
+        command
+    }
+}
+
+/// Where a channel is at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelState {
+    /// We sent a connection request and are waiting for its response.
+    Connecting,
+    /// Both sides are negotiating the configuration of the channel.
+    Configuring {
+        /// The other side accepted our configuration.
+        ours: bool,
+        /// We accepted the configuration of the other side.
+        theirs: bool,
+    },
+    Open,
+    /// We sent a disconnection request and are waiting for its response.
+    Disconnecting,
+}
+
+/// A channel on top of the connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Channel {
+    pub psm: u16,
+    /// Our identifier of the channel, on which we receive.
+    pub local: u16,
+    /// The identifier of the other side, to which we send.
+    pub remote: u16,
+    pub state: ChannelState,
+}
+
+/// Something which happened to the channels of a connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkEvent<'a> {
+    /// The channel for this protocol got configured by both sides.
+    Opened(u16),
+    /// The channel for this protocol got closed, or failed to open.
+    Closed(u16),
+    /// Data got received on the open channel for this protocol.
+    Data(u16, &'a [u8]),
+}
+
+/// The channels of a connection, along with their signalling.
+#[derive(Debug)]
+pub struct Link {
+    channels: Vec<Channel>,
+    /// Protocols the other side may open channels for.
+    accepted: Vec<u16>,
+    next_id: u8,
+    next_cid: u16,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Link {
+    pub fn new() -> Link {
+        Link {
+            channels: Vec::new(),
+            accepted: Vec::new(),
+            next_id: 1,
+            next_cid: CID_DYNAMIC,
+        }
+    }
+
+    /// Accept channels opened by the other side for these protocols.
+    pub fn with_accepted(mut self, psms: &[u16]) -> Link {
+        self.accepted.extend_from_slice(psms);
+        self
+    }
+
+    fn id(&mut self) -> u8 {
+        let id = self.next_id;
+        // Identifier 0 is invalid.
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    fn cid(&mut self) -> u16 {
+        let cid = self.next_cid;
+        self.next_cid = self.next_cid.checked_add(1).unwrap_or(CID_DYNAMIC);
+        cid
+    }
+
+    fn signal(&mut self, signal: Signal, outgoing: &mut Vec<Vec<u8>>) {
+        outgoing.push(frame(CID_SIGNALLING, &signal.encode()));
+    }
+
+    fn configure(&mut self, remote: u16, outgoing: &mut Vec<Vec<u8>>) {
+        // No options, the defaults are fine for HID.
+        let id = self.id();
+        self.signal(
+            Signal::ConfigurationRequest {
+                id,
+                destination: remote,
+                flags: 0,
+                options: &[],
+            },
+            outgoing,
+        );
+    }
+
+    /// Get the channel for a protocol, if any.
+    pub fn channel(&self, psm: u16) -> Option<&Channel> {
+        self.channels.iter().find(|channel| channel.psm == psm)
+    }
+
+    /// Whether the channel for a protocol is open.
+    pub fn is_open(&self, psm: u16) -> bool {
+        self.channel(psm)
+            .is_some_and(|channel| channel.state == ChannelState::Open)
+    }
+
+    /// Start opening a channel for ``psm``, unless there already is one.
+    pub fn connect(&mut self, psm: u16, outgoing: &mut Vec<Vec<u8>>) {
+        if self.channel(psm).is_some() {
+            return;
+        }
+        let local = self.cid();
+        self.channels.push(Channel {
+            psm,
+            local,
+            remote: 0,
+            state: ChannelState::Connecting,
+        });
+        let id = self.id();
+        self.signal(
+            Signal::ConnectionRequest {
+                id,
+                psm,
+                source: local,
+            },
+            outgoing,
+        );
+    }
+
+    /// Start closing the channel for ``psm``.
+    pub fn disconnect(&mut self, psm: u16, outgoing: &mut Vec<Vec<u8>>) {
+        let Some(index) = self.channels.iter().position(|channel| channel.psm == psm) else {
+            return;
+        };
+        let channel = self.channels[index];
+        if channel.state == ChannelState::Connecting {
+            self.channels.remove(index);
+            return;
+        }
+        self.channels[index].state = ChannelState::Disconnecting;
+        let id = self.id();
+        self.signal(
+            Signal::DisconnectionRequest {
+                id,
+                destination: channel.remote,
+                source: channel.local,
+            },
+            outgoing,
+        );
+    }
+
+    /// Encode a frame carrying ``data`` on the channel for ``psm``, if it is open.
+    pub fn send(&self, psm: u16, data: &[u8]) -> Option<Vec<u8>> {
+        let channel = self.channel(psm)?;
+        (channel.state == ChannelState::Open).then(|| frame(channel.remote, data))
+    }
+
+    /// Handle a complete frame received on the connection, pushing the frames to send back onto
+    /// ``outgoing`` and what happened onto ``events``.
+    pub fn receive<'a>(
+        &mut self,
+        frame: &'a [u8],
+        outgoing: &mut Vec<Vec<u8>>,
+        events: &mut Vec<LinkEvent<'a>>,
+    ) {
+        let Some((cid, payload)) = parse_frame(frame) else {
+            return;
+        };
+        if cid == CID_SIGNALLING {
+            for signal in Signal::parse_all(payload) {
+                self.handle_signal(signal, outgoing, events);
+            }
+        } else if let Some(channel) = self
+            .channels
+            .iter()
+            .find(|channel| channel.local == cid && channel.state == ChannelState::Open)
+        {
+            events.push(LinkEvent::Data(channel.psm, payload));
+        }
+    }
+
+    fn position(&self, local: u16) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.local == local)
+    }
+
+    fn configured(
+        &mut self,
+        index: usize,
+        ours: bool,
+        theirs: bool,
+        events: &mut Vec<LinkEvent<'_>>,
+    ) {
+        let channel = &mut self.channels[index];
+        if let ChannelState::Configuring {
+            ours: done_ours,
+            theirs: done_theirs,
+        } = channel.state
+        {
+            let ours = ours || done_ours;
+            let theirs = theirs || done_theirs;
+            channel.state = if ours && theirs {
+                events.push(LinkEvent::Opened(channel.psm));
+                ChannelState::Open
+            } else {
+                ChannelState::Configuring { ours, theirs }
+            };
+        }
+    }
+
+    fn handle_signal<'a>(
+        &mut self,
+        signal: Signal<'a>,
+        outgoing: &mut Vec<Vec<u8>>,
+        events: &mut Vec<LinkEvent<'a>>,
+    ) {
+        match signal {
+            Signal::ConnectionRequest { id, psm, source } => {
+                let result = if !self.accepted.contains(&psm) {
+                    RESULT_PSM_NOT_SUPPORTED
+                } else {
+                    // The other side wins if both opened the same channel at once.
+                    if let Some(index) = self.channels.iter().position(|c| c.psm == psm) {
+                        self.channels.remove(index);
+                    }
+                    RESULT_SUCCESS
+                };
+                let local = if result == RESULT_SUCCESS {
+                    let local = self.cid();
+                    self.channels.push(Channel {
+                        psm,
+                        local,
+                        remote: source,
+                        state: ChannelState::Configuring {
+                            ours: false,
+                            theirs: false,
+                        },
+                    });
+                    local
+                } else {
+                    0
+                };
+                self.signal(
+                    Signal::ConnectionResponse {
+                        id,
+                        destination: local,
+                        source,
+                        result,
+                        status: 0,
+                    },
+                    outgoing,
+                );
+                if result == RESULT_SUCCESS {
+                    self.configure(source, outgoing);
+                }
+            }
+            Signal::ConnectionResponse {
+                destination,
+                source,
+                result,
+                ..
+            } => {
+                let Some(index) = self.position(source) else {
+                    return;
+                };
+                if self.channels[index].state != ChannelState::Connecting {
+                    return;
+                }
+                match result {
+                    RESULT_SUCCESS => {
+                        self.channels[index].remote = destination;
+                        self.channels[index].state = ChannelState::Configuring {
+                            ours: false,
+                            theirs: false,
+                        };
+                        self.configure(destination, outgoing);
+                    }
+                    RESULT_PENDING => (),
+                    _ => {
+                        let channel = self.channels.remove(index);
+                        events.push(LinkEvent::Closed(channel.psm));
+                    }
+                }
+            }
+            Signal::ConfigurationRequest {
+                id,
+                destination,
+                flags,
+                ..
+            } => {
+                let index = self.position(destination);
+                let source = index.map_or(0, |index| self.channels[index].remote);
+                // Accept whatever got asked for, the other side will only send what fits.
+                self.signal(
+                    Signal::ConfigurationResponse {
+                        id,
+                        source,
+                        flags: 0,
+                        result: RESULT_SUCCESS,
+                        options: &[],
+                    },
+                    outgoing,
+                );
+                // The continuation flag means more options are coming in another request.
+                if let Some(index) = index
+                    && flags & 1 == 0
+                {
+                    self.configured(index, false, true, events);
+                }
+            }
+            Signal::ConfigurationResponse {
+                source,
+                flags,
+                result,
+                ..
+            } => {
+                let Some(index) = self.position(source) else {
+                    return;
+                };
+                if result == RESULT_SUCCESS && flags & 1 == 0 {
+                    self.configured(index, true, false, events);
+                } else if result != RESULT_SUCCESS {
+                    self.disconnect(self.channels[index].psm, outgoing);
+                }
+            }
+            Signal::DisconnectionRequest {
+                id,
+                destination,
+                source,
+            } => {
+                self.signal(
+                    Signal::DisconnectionResponse {
+                        id,
+                        destination,
+                        source,
+                    },
+                    outgoing,
+                );
+                if let Some(index) = self.position(destination) {
+                    let channel = self.channels.remove(index);
+                    events.push(LinkEvent::Closed(channel.psm));
+                }
+            }
+            Signal::DisconnectionResponse { source, .. } => {
+                if let Some(index) = self.position(source) {
+                    let channel = self.channels.remove(index);
+                    events.push(LinkEvent::Closed(channel.psm));
+                }
+            }
+            Signal::EchoRequest { id, data } => {
+                self.signal(Signal::EchoResponse { id, data }, outgoing);
+            }
+            Signal::InformationRequest { id, info_type } => {
+                self.signal(
+                    Signal::InformationResponse {
+                        id,
+                        info_type,
+                        result: INFORMATION_NOT_SUPPORTED,
+                    },
+                    outgoing,
+                );
+            }
+            Signal::Unknown { id, .. } => {
+                // Command not understood.
+                self.signal(Signal::CommandReject { id, reason: 0 }, outgoing);
+            }
+            Signal::CommandReject { .. }
+            | Signal::EchoResponse { .. }
+            | Signal::InformationResponse { .. } => (),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! ``bluetooth`` module of ``luma_formats``.
+//!
+//! Contains the HCI packets exchanged with a Bluetooth controller, and the L2CAP layer above
+//! them.
+
+pub mod hci;
+pub mod l2cap;
@@ -0,0 +1,221 @@
+//! ``dsp`` module of ``luma_formats``.
+//!
+//! Contains the mailboxes of the audio DSP, and the handshake with its ROM loader which DMAs a
+//! microcode to IRAM and runs it.
+//!
+//! The mailboxes only go through the [`DspRegisters`] trait, so the protocol can be driven by
+//! something else than the hardware.
+
+/// CPU to DSP mailbox, bit 15 of the high half is set until the DSP reads the mail.
+pub const MAILBOX_IN_HIGH: u32 = 0x00;
+pub const MAILBOX_IN_LOW: u32 = 0x02;
+
+/// DSP to CPU mailbox, bit 15 of the high half is set until the CPU reads the low half.
+pub const MAILBOX_OUT_HIGH: u32 = 0x04;
+pub const MAILBOX_OUT_LOW: u32 = 0x06;
+
+pub const MAIL_PENDING: u16 = 0x8000;
+
+/// Mail sent by the ROM once it is ready to receive a task.
+pub const ROM_READY: u32 = 0x8071_feed;
+
+/// Commands of the ROM loader, each followed by its argument.
+pub const ROM_IRAM_MRAM_ADDRESS: u32 = 0x80f3_a001;
+pub const ROM_IRAM_ADDRESS: u32 = 0x80f3_c002;
+pub const ROM_IRAM_LENGTH: u32 = 0x80f3_a002;
+pub const ROM_DRAM_LENGTH: u32 = 0x80f3_b002;
+pub const ROM_START: u32 = 0x80f3_d001;
+
+/// Amount of register polls before giving up on the DSP.
+pub const TIMEOUT: u32 = 1_000_000;
+
+/// Errors which can be encountered while talking to the DSP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DspError {
+    /// The DSP didn’t react in time.
+    Timeout,
+    /// The ROM sent something else than its ready mail.
+    UnexpectedMail(u32),
+    /// The DSP hasn’t read the previous mail yet.
+    MailboxFull,
+    /// The operation isn’t possible in the current state.
+    BadState(DspState),
+}
+
+/// Where the DSP is at, from the point of view of its driver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DspState {
+    /// Held in reset, or never touched.
+    Halted,
+    /// Running its ROM, which will accept a task once it sent its ready mail.
+    Booting,
+    /// Running the microcode.
+    Running,
+}
+
+/// A microcode to be loaded in the DSP IRAM by the ROM loader.
+#[derive(Clone, Copy, Debug)]
+pub struct Ucode<'a> {
+    /// The code, whose address and length must be multiples of 32.
+    pub iram: &'a [u8],
+    /// IRAM address the code gets loaded at, in words.
+    pub iram_address: u16,
+    /// IRAM address to start running at, in words.
+    pub entry: u16,
+}
+
+impl<'a> Ucode<'a> {
+    /// Create a microcode loaded at the start of IRAM and entered there, which is the usual case.
+    ///
+    /// # Panics:
+    /// This function will panic if ``iram`` isn’t aligned to 32 bytes, or if its length isn’t a
+    /// multiple of 32.
+    pub fn new(iram: &'a [u8]) -> Ucode<'a> {
+        assert!(
+            (iram.as_ptr() as usize).is_multiple_of(32),
+            "Microcode must be 32-byte aligned"
+        );
+        assert!(
+            iram.len().is_multiple_of(32),
+            "Microcode length must be a multiple of 32"
+        );
+        Ucode {
+            iram,
+            iram_address: 0,
+            entry: 0,
+        }
+    }
+
+    /// Load the code somewhere else in IRAM.
+    pub fn with_iram_address(mut self, address: u16) -> Ucode<'a> {
+        self.iram_address = address;
+        self
+    }
+
+    /// Start running at another address.
+    pub fn with_entry(mut self, entry: u16) -> Ucode<'a> {
+        self.entry = entry;
+        self
+    }
+}
+
+/// Access to the mailbox registers of the DSP.
+pub trait DspRegisters {
+    /// Read the register at ``offset`` from the start of the DSP registers.
+    fn read16(&mut self, offset: u32) -> u16;
+
+    /// Write the register at ``offset`` from the start of the DSP registers.
+    fn write16(&mut self, offset: u32, value: u16);
+
+    /// Let some time pass between two polls of the registers.
+    fn pause(&mut self) {}
+}
+
+/// The two mailboxes of the DSP, accessed through a [`DspRegisters`] implementation.
+#[derive(Debug)]
+pub struct Mailbox<R: DspRegisters> {
+    registers: R,
+    timeout: u32,
+}
+
+impl<R: DspRegisters> Mailbox<R> {
+    /// Use the mailboxes behind ``registers``, giving up after [`TIMEOUT`] polls.
+    pub fn new(registers: R) -> Mailbox<R> {
+        Mailbox {
+            registers,
+            timeout: TIMEOUT,
+        }
+    }
+
+    /// Give up after another amount of polls.
+    pub fn with_timeout(mut self, polls: u32) -> Mailbox<R> {
+        self.timeout = polls;
+        self
+    }
+
+    /// Get the register backend back.
+    pub fn into_registers(self) -> R {
+        self.registers
+    }
+
+    /// Check whether the DSP has read the last mail sent to it.
+    pub fn can_send_mail(&mut self) -> bool {
+        self.registers.read16(MAILBOX_IN_HIGH) & MAIL_PENDING == 0
+    }
+
+    /// Send a mail to the DSP, failing if it hasn’t read the previous one yet.
+    pub fn try_send_mail(&mut self, mail: u32) -> Result<(), DspError> {
+        if !self.can_send_mail() {
+            return Err(DspError::MailboxFull);
+        }
+        self.registers.write16(MAILBOX_IN_HIGH, (mail >> 16) as u16);
+        self.registers.write16(MAILBOX_IN_LOW, mail as u16);
+        Ok(())
+    }
+
+    /// Send a mail to the DSP, waiting for it to read the previous one first.
+    pub fn send_mail(&mut self, mail: u32) -> Result<(), DspError> {
+        self.wait(|mailbox| mailbox.can_send_mail())?;
+        self.try_send_mail(mail)
+    }
+
+    /// Check whether the DSP sent a mail which hasn’t been read yet.
+    pub fn has_mail(&mut self) -> bool {
+        self.registers.read16(MAILBOX_OUT_HIGH) & MAIL_PENDING != 0
+    }
+
+    /// Read the mail sent by the DSP, if any.
+    pub fn recv_mail(&mut self) -> Option<u32> {
+        if !self.has_mail() {
+            return None;
+        }
+        // Reading the low half frees the mailbox, so it must come last.
+        let high = self.registers.read16(MAILBOX_OUT_HIGH) as u32;
+        let low = self.registers.read16(MAILBOX_OUT_LOW) as u32;
+        Some((high << 16) | low)
+    }
+
+    /// Wait for the DSP to send a mail, and read it.
+    pub fn wait_mail(&mut self) -> Result<u32, DspError> {
+        self.wait(|mailbox| mailbox.has_mail())?;
+        self.recv_mail().ok_or(DspError::Timeout)
+    }
+
+    fn wait(&mut self, mut ready: impl FnMut(&mut Self) -> bool) -> Result<(), DspError> {
+        for _ in 0..self.timeout {
+            if ready(self) {
+                return Ok(());
+            }
+            self.registers.pause();
+        }
+        Err(DspError::Timeout)
+    }
+
+    /// Wait for the ROM to be ready, then have it DMA ``ucode`` from the physical ``address``
+    /// its code is at to IRAM and run it.
+    ///
+    /// The code must already be visible to the DSP DMA, and once this returns mails are the
+    /// microcode’s own protocol.
+    pub fn load_ucode(&mut self, ucode: &Ucode, address: u32) -> Result<(), DspError> {
+        match self.wait_mail()? {
+            ROM_READY => (),
+            mail => return Err(DspError::UnexpectedMail(mail)),
+        }
+        let task = [
+            ROM_IRAM_MRAM_ADDRESS,
+            address,
+            ROM_IRAM_ADDRESS,
+            ucode.iram_address as u32,
+            ROM_IRAM_LENGTH,
+            ucode.iram.len() as u32,
+            ROM_DRAM_LENGTH,
+            0,
+            ROM_START,
+            ucode.entry as u32,
+        ];
+        for mail in task {
+            self.send_mail(mail)?;
+        }
+        Ok(())
+    }
+}
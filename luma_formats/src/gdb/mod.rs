@@ -0,0 +1,6 @@
+//! ``gdb`` module of ``luma_formats``.
+//!
+//! Contains the GDB remote serial protocol, as spoken by the debugging stub of
+//! ``luma_runtime``.
+
+pub mod packet;
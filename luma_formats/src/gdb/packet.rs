@@ -0,0 +1,291 @@
+//! ``packet`` module of ``luma_formats::gdb``.
+//!
+//! Contains the framing of the GDB remote serial protocol, ``$data#checksum`` with ``}``
+//! escaping, and the parsing of the commands the stub understands.  Nothing in here does any
+//! I/O, bytes get fed to a [`Decoder`] and replies get [`encode`]d into a buffer.
+
+use alloc::vec::Vec;
+
+/// Largest packet GDB may send, as told in the reply to ``qSupported``.
+pub const MAX_PACKET_SIZE: usize = 0x1000;
+
+/// Sent by GDB outside of any packet to interrupt the program.
+pub const INTERRUPT: u8 = 0x03;
+
+const ESCAPE: u8 = b'}';
+
+/// What a [`Decoder`] got out of the bytes fed to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A whole packet, unescaped, whose checksum matched.
+    Packet(Vec<u8>),
+    /// A whole packet whose checksum didn’t match, to be asked again with a ``-``.
+    BadChecksum,
+    /// GDB got the last reply.
+    Ack,
+    /// GDB wants the last reply again.
+    Nack,
+    /// GDB wants the program to stop.
+    Interrupt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    /// Outside of a packet.
+    Idle,
+    Data,
+    /// Right after an escape in the data.
+    Escaped,
+    /// Between the ``#`` and the two digits of the checksum.
+    Checksum,
+    ChecksumLow(u8),
+}
+
+/// Turns bytes received from GDB into [`Event`]s.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    state: State,
+    data: Vec<u8>,
+    checksum: u8,
+}
+
+impl Default for Decoder {
+    fn default() -> Decoder {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            state: State::Idle,
+            data: Vec::new(),
+            checksum: 0,
+        }
+    }
+
+    /// Feed a byte, getting an [`Event`] if it completed one.
+    pub fn feed(&mut self, byte: u8) -> Option<Event> {
+        match self.state {
+            State::Idle => match byte {
+                b'$' => {
+                    self.data.clear();
+                    self.checksum = 0;
+                    self.state = State::Data;
+                    None
+                }
+                b'+' => Some(Event::Ack),
+                b'-' => Some(Event::Nack),
+                INTERRUPT => Some(Event::Interrupt),
+                _ => None,
+            },
+            State::Data | State::Escaped if byte == b'#' => {
+                self.state = State::Checksum;
+                None
+            }
+            // A packet got cut off by another one.
+            State::Data | State::Escaped if byte == b'$' => {
+                self.state = State::Idle;
+                self.feed(byte)
+            }
+            State::Data | State::Escaped if self.data.len() >= MAX_PACKET_SIZE => {
+                self.state = State::Idle;
+                Some(Event::BadChecksum)
+            }
+            State::Data => {
+                self.checksum = self.checksum.wrapping_add(byte);
+                if byte == ESCAPE {
+                    self.state = State::Escaped;
+                } else {
+                    self.data.push(byte);
+                }
+                None
+            }
+            State::Escaped => {
+                self.checksum = self.checksum.wrapping_add(byte);
+                self.data.push(byte ^ 0x20);
+                self.state = State::Data;
+                None
+            }
+            State::Checksum => match hex_digit(byte) {
+                Some(high) => {
+                    self.state = State::ChecksumLow(high);
+                    None
+                }
+                None => {
+                    self.state = State::Idle;
+                    Some(Event::BadChecksum)
+                }
+            },
+            State::ChecksumLow(high) => {
+                self.state = State::Idle;
+                match hex_digit(byte) {
+                    Some(low) if high << 4 | low == self.checksum => {
+                        Some(Event::Packet(core::mem::take(&mut self.data)))
+                    }
+                    _ => Some(Event::BadChecksum),
+                }
+            }
+        }
+    }
+}
+
+/// Frame ``data`` as a packet at the end of ``out``, escaping what needs to be.
+pub fn encode(data: &[u8], out: &mut Vec<u8>) {
+    let mut checksum = 0u8;
+    out.push(b'$');
+    for &byte in data {
+        if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+            out.extend_from_slice(&[ESCAPE, byte ^ 0x20]);
+            checksum = checksum.wrapping_add(ESCAPE).wrapping_add(byte ^ 0x20);
+        } else {
+            out.push(byte);
+            checksum = checksum.wrapping_add(byte);
+        }
+    }
+    out.push(b'#');
+    push_hex(out, &[checksum]);
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Append ``data`` as lowercase hexadecimal.
+pub fn push_hex(out: &mut Vec<u8>, data: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for &byte in data {
+        out.extend_from_slice(&[DIGITS[(byte >> 4) as usize], DIGITS[(byte & 15) as usize]]);
+    }
+}
+
+/// Decode hexadecimal data, two digits per byte.
+pub fn decode_hex(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks_exact(2)
+        .map(|pair| Some(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+        .collect()
+}
+
+/// Parse a hexadecimal number, as addresses and lengths are given.
+pub fn parse_number(hex: &[u8]) -> Option<u32> {
+    if hex.is_empty() || hex.len() > 8 {
+        return None;
+    }
+    hex.iter().try_fold(0u32, |number, &digit| {
+        Some(number << 4 | hex_digit(digit)? as u32)
+    })
+}
+
+/// Parse ``address,length``.
+fn parse_range(arguments: &[u8]) -> Option<(u32, u32)> {
+    let comma = arguments.iter().position(|&byte| byte == b',')?;
+    let address = parse_number(&arguments[..comma])?;
+    let length = parse_number(&arguments[comma + 1..])?;
+    Some((address, length))
+}
+
+/// Split ``arguments`` at the first ``separator``.
+fn split(arguments: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let at = arguments.iter().position(|&byte| byte == separator)?;
+    Some((&arguments[..at], &arguments[at + 1..]))
+}
+
+/// A command sent by GDB, as much of the protocol as the stub implements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// ``?``: why the program stopped.
+    StopReason,
+    /// ``g``: every register.
+    ReadRegisters,
+    /// ``G``: every register, as encoded by [`Command::ReadRegisters`].
+    WriteRegisters(Vec<u8>),
+    /// ``p``: a single register.
+    ReadRegister(u32),
+    /// ``P``: a single register, in target byte order.
+    WriteRegister(u32, Vec<u8>),
+    /// ``m``: memory.
+    ReadMemory { address: u32, length: u32 },
+    /// ``M`` and ``X``: memory, from hexadecimal or binary data.
+    WriteMemory { address: u32, data: Vec<u8> },
+    /// ``c``: resume, at the given address if any.
+    Continue(Option<u32>),
+    /// ``s``: run a single instruction, at the given address if any.
+    Step(Option<u32>),
+    /// ``Z0``: a software breakpoint.
+    InsertBreakpoint(u32),
+    /// ``z0``: a software breakpoint.
+    RemoveBreakpoint(u32),
+    /// ``D``: stop debugging, and resume.
+    Detach,
+    /// ``k``: stop debugging, no reply expected.
+    Kill,
+    /// ``q``: a general query, without its ``q``.
+    Query(&'a [u8]),
+    /// ``H``: which thread further commands apply to, there only being one.
+    SetThread,
+    /// A command the stub doesn’t implement, or with invalid arguments, to be answered with an
+    /// empty packet.
+    Unsupported,
+}
+
+impl<'a> Command<'a> {
+    /// Parse an unescaped packet.
+    pub fn parse(packet: &'a [u8]) -> Command<'a> {
+        let Some((&kind, arguments)) = packet.split_first() else {
+            return Command::Unsupported;
+        };
+        let command = match kind {
+            b'?' => Some(Command::StopReason),
+            b'g' => Some(Command::ReadRegisters),
+            b'G' => decode_hex(arguments).map(Command::WriteRegisters),
+            b'p' => parse_number(arguments).map(Command::ReadRegister),
+            b'P' => split(arguments, b'=').and_then(|(register, value)| {
+                Some(Command::WriteRegister(
+                    parse_number(register)?,
+                    decode_hex(value)?,
+                ))
+            }),
+            b'm' => parse_range(arguments)
+                .map(|(address, length)| Command::ReadMemory { address, length }),
+            b'M' | b'X' => split(arguments, b':').and_then(|(range, data)| {
+                let (address, length) = parse_range(range)?;
+                let data = match kind {
+                    b'M' => decode_hex(data)?,
+                    _ => data.to_vec(),
+                };
+                (data.len() == length as usize).then_some(Command::WriteMemory { address, data })
+            }),
+            b'c' | b's' => {
+                let address = match arguments {
+                    [] => Some(None),
+                    address => parse_number(address).map(Some),
+                };
+                address.map(|address| match kind {
+                    b'c' => Command::Continue(address),
+                    _ => Command::Step(address),
+                })
+            }
+            b'Z' | b'z' => match split(arguments, b',') {
+                Some((b"0", range)) => parse_range(range).map(|(address, _)| match kind {
+                    b'Z' => Command::InsertBreakpoint(address),
+                    _ => Command::RemoveBreakpoint(address),
+                }),
+                _ => None,
+            },
+            b'D' => Some(Command::Detach),
+            b'k' => Some(Command::Kill),
+            b'q' => Some(Command::Query(arguments)),
+            b'H' => Some(Command::SetThread),
+            _ => None,
+        };
+        command.unwrap_or(Command::Unsupported)
+    }
+}
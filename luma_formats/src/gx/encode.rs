@@ -0,0 +1,647 @@
+//! ``encode`` module of ``luma_formats::gx``.
+//!
+//! Contains conversions between linear RGBA8 images and the tiled layouts of every texture
+//! format, which asset tools can use on the host as well.
+//!
+//! Images are given as ``width × height × 4`` bytes in R, G, B, A order, while encoded textures
+//! and palettes are big-endian, exactly as the GPU reads them from memory.
+
+use super::texture::{TexFmt, TlutFmt};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Convert a colour to its Rec. 601 intensity.
+const fn intensity(r: u8, g: u8, b: u8) -> u8 {
+    ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29 + 128) >> 8) as u8
+}
+
+/// Expand a 3-, 4-, 5- or 6-bit component to 8 bits by replicating its top bits.
+const fn expand(value: u16, bits: u32) -> u8 {
+    let value = (value as u32) << (8 - bits);
+    (value | (value >> bits) | (value >> (2 * bits))) as u8
+}
+
+const fn to_rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
+}
+
+const fn from_rgb565(value: u16) -> [u8; 4] {
+    [
+        expand(value >> 11, 5),
+        expand((value >> 5) & 0x3f, 6),
+        expand(value & 0x1f, 5),
+        0xff,
+    ]
+}
+
+const fn to_rgb5a3(r: u8, g: u8, b: u8, a: u8) -> u16 {
+    if a >= 0xe0 {
+        0x8000 | ((r as u16 >> 3) << 10) | ((g as u16 >> 3) << 5) | (b as u16 >> 3)
+    } else {
+        ((a as u16 >> 5) << 12) | ((r as u16 >> 4) << 8) | ((g as u16 >> 4) << 4) | (b as u16 >> 4)
+    }
+}
+
+const fn from_rgb5a3(value: u16) -> [u8; 4] {
+    if value & 0x8000 != 0 {
+        [
+            expand((value >> 10) & 0x1f, 5),
+            expand((value >> 5) & 0x1f, 5),
+            expand(value & 0x1f, 5),
+            0xff,
+        ]
+    } else {
+        [
+            expand((value >> 8) & 0xf, 4),
+            expand((value >> 4) & 0xf, 4),
+            expand(value & 0xf, 4),
+            expand((value >> 12) & 0x7, 3),
+        ]
+    }
+}
+
+const fn to_ia8(r: u8, g: u8, b: u8, a: u8) -> u16 {
+    ((a as u16) << 8) | intensity(r, g, b) as u16
+}
+
+const fn from_ia8(value: u16) -> [u8; 4] {
+    let i = value as u8;
+    [i, i, i, (value >> 8) as u8]
+}
+
+/// Call ``texel`` for every texel of the image in tile order, including the padding texels past
+/// the right and bottom edges, and pack the returned values of ``bits`` bits each into ``out``.
+fn pack(
+    format: TexFmt,
+    width: usize,
+    height: usize,
+    bits: usize,
+    out: &mut [u8],
+    mut texel: impl FnMut(usize, usize) -> u16,
+) {
+    let (block_width, block_height) = format.block_size();
+    let mut offset = 0;
+    for block_y in (0..height).step_by(block_height) {
+        for block_x in (0..width).step_by(block_width) {
+            for y in block_y..block_y + block_height {
+                for x in block_x..block_x + block_width {
+                    let value = if x < width && y < height {
+                        texel(x, y)
+                    } else {
+                        0
+                    };
+                    match bits {
+                        4 if offset % 2 == 0 => out[offset / 2] = (value as u8) << 4,
+                        4 => out[offset / 2] |= value as u8 & 0xf,
+                        8 => out[offset] = value as u8,
+                        _ => out[offset * 2..offset * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+                    }
+                    offset += 1;
+                }
+            }
+        }
+    }
+}
+
+/// The reverse of [`pack`], calls ``texel`` for every texel inside of the image.
+fn unpack(
+    format: TexFmt,
+    width: usize,
+    height: usize,
+    bits: usize,
+    data: &[u8],
+    mut texel: impl FnMut(usize, usize, u16),
+) {
+    let (block_width, block_height) = format.block_size();
+    let mut offset = 0;
+    for block_y in (0..height).step_by(block_height) {
+        for block_x in (0..width).step_by(block_width) {
+            for y in block_y..block_y + block_height {
+                for x in block_x..block_x + block_width {
+                    let value = match bits {
+                        4 if offset % 2 == 0 => (data[offset / 2] >> 4) as u16,
+                        4 => (data[offset / 2] & 0xf) as u16,
+                        8 => data[offset] as u16,
+                        _ => u16::from_be_bytes([data[offset * 2], data[offset * 2 + 1]]),
+                    };
+                    if x < width && y < height {
+                        texel(x, y, value);
+                    }
+                    offset += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Encode a linear RGBA8 image into ``out``, which must be at least [`TexFmt::size`] bytes.
+///
+/// # Panics:
+/// This function will panic if ``format`` is an indexed format (see [`encode_indexed_into`]),
+/// or if either buffer is too small.
+pub fn encode_into(format: TexFmt, rgba: &[u8], width: usize, height: usize, out: &mut [u8]) {
+    assert!(rgba.len() >= width * height * 4, "Image is too small");
+    assert!(
+        out.len() >= format.size(width, height),
+        "Output buffer is too small"
+    );
+
+    let pixel = |x: usize, y: usize| {
+        let offset = (y * width + x) * 4;
+        [
+            rgba[offset],
+            rgba[offset + 1],
+            rgba[offset + 2],
+            rgba[offset + 3],
+        ]
+    };
+
+    match format {
+        TexFmt::I4 => pack(format, width, height, 4, out, |x, y| {
+            let [r, g, b, _] = pixel(x, y);
+            intensity(r, g, b) as u16 >> 4
+        }),
+        TexFmt::I8 => pack(format, width, height, 8, out, |x, y| {
+            let [r, g, b, _] = pixel(x, y);
+            intensity(r, g, b) as u16
+        }),
+        TexFmt::IA4 => pack(format, width, height, 8, out, |x, y| {
+            let [r, g, b, a] = pixel(x, y);
+            ((a as u16 >> 4) << 4) | (intensity(r, g, b) as u16 >> 4)
+        }),
+        TexFmt::IA8 => pack(format, width, height, 16, out, |x, y| {
+            let [r, g, b, a] = pixel(x, y);
+            to_ia8(r, g, b, a)
+        }),
+        TexFmt::RGB565 => pack(format, width, height, 16, out, |x, y| {
+            let [r, g, b, _] = pixel(x, y);
+            to_rgb565(r, g, b)
+        }),
+        TexFmt::RGB5A3 => pack(format, width, height, 16, out, |x, y| {
+            let [r, g, b, a] = pixel(x, y);
+            to_rgb5a3(r, g, b, a)
+        }),
+        TexFmt::RGBA8 => encode_rgba8(width, height, out, pixel),
+        TexFmt::CMPR => encode_cmpr(width, height, out, pixel),
+        TexFmt::C4 | TexFmt::C8 | TexFmt::C14X2 => {
+            panic!("Indexed formats must be encoded with encode_indexed_into()")
+        }
+    }
+}
+
+/// Encode a linear RGBA8 image, see [`encode_into`].
+pub fn encode(format: TexFmt, rgba: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; format.size(width, height)];
+    encode_into(format, rgba, width, height, &mut out);
+    out
+}
+
+/// Decode a texture back into a linear RGBA8 image.
+///
+/// # Panics:
+/// This function will panic if ``format`` is an indexed format (see [`decode_indexed`]), or if
+/// ``data`` is too small.
+pub fn decode(format: TexFmt, data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    assert!(
+        data.len() >= format.size(width, height),
+        "Texture is too small"
+    );
+
+    let mut rgba = vec![0; width * height * 4];
+    let mut set_pixel = |x: usize, y: usize, pixel: [u8; 4]| {
+        let offset = (y * width + x) * 4;
+        rgba[offset..offset + 4].copy_from_slice(&pixel);
+    };
+
+    match format {
+        TexFmt::I4 => unpack(format, width, height, 4, data, |x, y, value| {
+            let i = expand(value, 4);
+            set_pixel(x, y, [i, i, i, 0xff]);
+        }),
+        TexFmt::I8 => unpack(format, width, height, 8, data, |x, y, value| {
+            let i = value as u8;
+            set_pixel(x, y, [i, i, i, 0xff]);
+        }),
+        TexFmt::IA4 => unpack(format, width, height, 8, data, |x, y, value| {
+            let i = expand(value & 0xf, 4);
+            set_pixel(x, y, [i, i, i, expand(value >> 4, 4)]);
+        }),
+        TexFmt::IA8 => unpack(format, width, height, 16, data, |x, y, value| {
+            set_pixel(x, y, from_ia8(value));
+        }),
+        TexFmt::RGB565 => unpack(format, width, height, 16, data, |x, y, value| {
+            set_pixel(x, y, from_rgb565(value));
+        }),
+        TexFmt::RGB5A3 => unpack(format, width, height, 16, data, |x, y, value| {
+            set_pixel(x, y, from_rgb5a3(value));
+        }),
+        TexFmt::RGBA8 => decode_rgba8(width, height, data, set_pixel),
+        TexFmt::CMPR => decode_cmpr(width, height, data, set_pixel),
+        TexFmt::C4 | TexFmt::C8 | TexFmt::C14X2 => {
+            panic!("Indexed formats must be decoded with decode_indexed()")
+        }
+    }
+    rgba
+}
+
+/// Amount of bits per index of an indexed format, and the largest index it can hold.
+fn index_bits(format: TexFmt) -> (usize, u16) {
+    match format {
+        TexFmt::C4 => (4, 0xf),
+        TexFmt::C8 => (8, 0xff),
+        TexFmt::C14X2 => (16, 0x3fff),
+        _ => panic!("{:?} isn’t an indexed format", format),
+    }
+}
+
+/// Encode a linear image of palette indices into ``out``, which must be at least
+/// [`TexFmt::size`] bytes.
+///
+/// # Panics:
+/// This function will panic if ``format`` isn’t an indexed format, if an index doesn’t fit in
+/// it, or if either buffer is too small.
+pub fn encode_indexed_into(
+    format: TexFmt,
+    indices: &[u16],
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+) {
+    let (bits, max) = index_bits(format);
+    assert!(indices.len() >= width * height, "Image is too small");
+    assert!(
+        out.len() >= format.size(width, height),
+        "Output buffer is too small"
+    );
+
+    pack(format, width, height, bits, out, |x, y| {
+        let index = indices[y * width + x];
+        assert!(index <= max, "Index {} doesn’t fit in {:?}", index, format);
+        index
+    });
+}
+
+/// Encode a linear image of palette indices, see [`encode_indexed_into`].
+pub fn encode_indexed(format: TexFmt, indices: &[u16], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; format.size(width, height)];
+    encode_indexed_into(format, indices, width, height, &mut out);
+    out
+}
+
+/// Decode an indexed texture back into a linear image of palette indices.
+pub fn decode_indexed(format: TexFmt, data: &[u8], width: usize, height: usize) -> Vec<u16> {
+    let (bits, max) = index_bits(format);
+    assert!(
+        data.len() >= format.size(width, height),
+        "Texture is too small"
+    );
+
+    let mut indices = vec![0; width * height];
+    unpack(format, width, height, bits, data, |x, y, value| {
+        indices[y * width + x] = value & max;
+    });
+    indices
+}
+
+/// Encode RGBA8 palette entries into a big-endian TLUT.
+pub fn encode_tlut(format: TlutFmt, rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .flat_map(|pixel| {
+            let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            let value = match format {
+                TlutFmt::IA8 => to_ia8(r, g, b, a),
+                TlutFmt::RGB565 => to_rgb565(r, g, b),
+                TlutFmt::RGB5A3 => to_rgb5a3(r, g, b, a),
+            };
+            value.to_be_bytes()
+        })
+        .collect()
+}
+
+/// Decode a big-endian TLUT back into RGBA8 palette entries.
+pub fn decode_tlut(format: TlutFmt, data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(2)
+        .flat_map(|entry| {
+            let value = u16::from_be_bytes([entry[0], entry[1]]);
+            match format {
+                TlutFmt::IA8 => from_ia8(value),
+                TlutFmt::RGB565 => from_rgb565(value),
+                TlutFmt::RGB5A3 => from_rgb5a3(value),
+            }
+        })
+        .collect()
+}
+
+/// RGBA8 tiles are 4×4 texels, stored as 16 AR pairs followed by 16 GB pairs.
+fn encode_rgba8(
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+    pixel: impl Fn(usize, usize) -> [u8; 4],
+) {
+    let mut offset = 0;
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let (ar, gb) = out[offset..offset + 64].split_at_mut(32);
+            for i in 0..16 {
+                let (x, y) = (block_x + i % 4, block_y + i / 4);
+                let [r, g, b, a] = if x < width && y < height {
+                    pixel(x, y)
+                } else {
+                    [0; 4]
+                };
+                ar[i * 2..i * 2 + 2].copy_from_slice(&[a, r]);
+                gb[i * 2..i * 2 + 2].copy_from_slice(&[g, b]);
+            }
+            offset += 64;
+        }
+    }
+}
+
+fn decode_rgba8(
+    width: usize,
+    height: usize,
+    data: &[u8],
+    mut set_pixel: impl FnMut(usize, usize, [u8; 4]),
+) {
+    let mut offset = 0;
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let (ar, gb) = data[offset..offset + 64].split_at(32);
+            for i in 0..16 {
+                let (x, y) = (block_x + i % 4, block_y + i / 4);
+                if x < width && y < height {
+                    set_pixel(x, y, [ar[i * 2 + 1], gb[i * 2], gb[i * 2 + 1], ar[i * 2]]);
+                }
+            }
+            offset += 64;
+        }
+    }
+}
+
+/// The four colours a CMPR sub-block can pick from, the last one being transparent when the
+/// first endpoint isn’t greater than the second.
+fn cmpr_palette(color0: u16, color1: u16) -> [[u8; 4]; 4] {
+    let c0 = from_rgb565(color0);
+    let c1 = from_rgb565(color1);
+    let mix = |weight0: u32, weight1: u32| {
+        let mut color = [0xff; 4];
+        for i in 0..3 {
+            color[i] =
+                ((c0[i] as u32 * weight0 + c1[i] as u32 * weight1) / (weight0 + weight1)) as u8;
+        }
+        color
+    };
+    if color0 > color1 {
+        [c0, c1, mix(2, 1), mix(1, 2)]
+    } else {
+        [c0, c1, mix(1, 1), [0; 4]]
+    }
+}
+
+fn distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    (0..3)
+        .map(|i| {
+            let delta = a[i] as i32 - b[i] as i32;
+            (delta * delta) as u32
+        })
+        .sum()
+}
+
+/// Compress a 4×4 sub-block, picking the two texels furthest apart as endpoints.
+fn encode_cmpr_subblock(texels: &[[u8; 4]; 16]) -> [u8; 8] {
+    let has_alpha = texels.iter().any(|texel| texel[3] < 0x80);
+    let opaque = || texels.iter().filter(|texel| texel[3] >= 0x80);
+
+    let mut endpoints = ([0; 4], [0; 4]);
+    let mut furthest = 0;
+    for a in opaque() {
+        for b in opaque() {
+            let d = distance(*a, *b);
+            if d >= furthest {
+                furthest = d;
+                endpoints = (*a, *b);
+            }
+        }
+    }
+
+    let mut color0 = to_rgb565(endpoints.0[0], endpoints.0[1], endpoints.0[2]);
+    let mut color1 = to_rgb565(endpoints.1[0], endpoints.1[1], endpoints.1[2]);
+    // Four colour mode wants color0 > color1, three colour mode with transparency the reverse.
+    if (color0 < color1) != has_alpha {
+        core::mem::swap(&mut color0, &mut color1);
+    }
+
+    let palette = cmpr_palette(color0, color1);
+    let usable = if color0 > color1 { 4 } else { 3 };
+    let mut block = [0; 8];
+    block[0..2].copy_from_slice(&color0.to_be_bytes());
+    block[2..4].copy_from_slice(&color1.to_be_bytes());
+    for (i, texel) in texels.iter().enumerate() {
+        let index = if texel[3] < 0x80 {
+            3
+        } else {
+            (0..usable)
+                .min_by_key(|&index| distance(*texel, palette[index]))
+                .unwrap()
+        };
+        block[4 + i / 4] |= (index as u8) << (6 - 2 * (i % 4));
+    }
+    block
+}
+
+/// CMPR tiles are 8×8 texels, made of four DXT1-like 4×4 sub-blocks in Z order.
+fn encode_cmpr(
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+    pixel: impl Fn(usize, usize) -> [u8; 4],
+) {
+    let mut offset = 0;
+    for block_y in (0..height).step_by(8) {
+        for block_x in (0..width).step_by(8) {
+            for (sub_x, sub_y) in [(0, 0), (4, 0), (0, 4), (4, 4)] {
+                let mut texels = [[0; 4]; 16];
+                for (i, texel) in texels.iter_mut().enumerate() {
+                    let x = (block_x + sub_x + i % 4).min(width - 1);
+                    let y = (block_y + sub_y + i / 4).min(height - 1);
+                    *texel = pixel(x, y);
+                }
+                out[offset..offset + 8].copy_from_slice(&encode_cmpr_subblock(&texels));
+                offset += 8;
+            }
+        }
+    }
+}
+
+fn decode_cmpr(
+    width: usize,
+    height: usize,
+    data: &[u8],
+    mut set_pixel: impl FnMut(usize, usize, [u8; 4]),
+) {
+    let mut offset = 0;
+    for block_y in (0..height).step_by(8) {
+        for block_x in (0..width).step_by(8) {
+            for (sub_x, sub_y) in [(0, 0), (4, 0), (0, 4), (4, 4)] {
+                let block = &data[offset..offset + 8];
+                let color0 = u16::from_be_bytes([block[0], block[1]]);
+                let color1 = u16::from_be_bytes([block[2], block[3]]);
+                let palette = cmpr_palette(color0, color1);
+                for i in 0..16 {
+                    let (x, y) = (block_x + sub_x + i % 4, block_y + sub_y + i / 4);
+                    let index = (block[4 + i / 4] >> (6 - 2 * (i % 4))) & 3;
+                    if x < width && y < height {
+                        set_pixel(x, y, palette[index as usize]);
+                    }
+                }
+                offset += 8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey(values: impl Iterator<Item = u8>) -> Vec<u8> {
+        values
+            .flat_map(|value| [value, value, value, 0xff])
+            .collect()
+    }
+
+    #[test]
+    fn i8_tiles() {
+        // A single 8×4 tile is stored row by row.
+        let rgba = grey(0..32);
+        assert_eq!(
+            encode(TexFmt::I8, &rgba, 8, 4),
+            (0..32).collect::<Vec<u8>>()
+        );
+
+        // Two tiles side by side, the left one first.
+        let rgba = grey(0..64);
+        let encoded = encode(TexFmt::I8, &rgba, 16, 4);
+        assert_eq!(&encoded[..8], &[0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(&encoded[8..16], &[16, 17, 18, 19, 20, 21, 22, 23]);
+        assert_eq!(&encoded[32..40], &[8, 9, 10, 11, 12, 13, 14, 15]);
+        assert_eq!(decode(TexFmt::I8, &encoded, 16, 4), rgba);
+    }
+
+    #[test]
+    fn i4_padding() {
+        let rgba = grey([0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88].into_iter());
+        let encoded = encode(TexFmt::I4, &rgba, 3, 3);
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(&encoded[..4], &[0x01, 0x20, 0x00, 0x00]);
+        assert_eq!(&encoded[4..8], &[0x34, 0x50, 0x00, 0x00]);
+        assert_eq!(&encoded[8..12], &[0x67, 0x80, 0x00, 0x00]);
+        assert!(encoded[12..].iter().all(|&byte| byte == 0));
+        assert_eq!(decode(TexFmt::I4, &encoded, 3, 3), rgba);
+    }
+
+    #[test]
+    fn rgba8_tiles() {
+        let rgba: Vec<u8> = (0..16).flat_map(|i| [i, 16 + i, 32 + i, 48 + i]).collect();
+        let encoded = encode(TexFmt::RGBA8, &rgba, 4, 4);
+        assert_eq!(&encoded[..4], &[48, 0, 49, 1]);
+        assert_eq!(&encoded[32..36], &[16, 32, 17, 33]);
+        assert_eq!(decode(TexFmt::RGBA8, &encoded, 4, 4), rgba);
+
+        let rgba: Vec<u8> = (0..5 * 3 * 4).map(|i| i as u8).collect();
+        let encoded = encode(TexFmt::RGBA8, &rgba, 5, 3);
+        assert_eq!(encoded.len(), 2 * 64);
+        assert_eq!(decode(TexFmt::RGBA8, &encoded, 5, 3), rgba);
+    }
+
+    #[test]
+    fn ia8_round_trip() {
+        let rgba: Vec<u8> = (0..=255).flat_map(|i| [i, i, i, 255 - i]).collect();
+        let encoded = encode(TexFmt::IA8, &rgba, 16, 16);
+        assert_eq!(&encoded[..4], &[255, 0, 254, 1]);
+        assert_eq!(decode(TexFmt::IA8, &encoded, 16, 16), rgba);
+    }
+
+    #[test]
+    fn tlut_entries() {
+        let rgba = [
+            0xff, 0x00, 0x00, 0xff, // Opaque red.
+            0xff, 0xff, 0xff, 0xff, // Opaque white.
+            0x00, 0x00, 0xff, 0x80, // Translucent blue.
+        ];
+        assert_eq!(
+            encode_tlut(TlutFmt::RGB565, &rgba),
+            [0xf8, 0x00, 0xff, 0xff, 0x00, 0x1f]
+        );
+        assert_eq!(
+            encode_tlut(TlutFmt::RGB5A3, &rgba),
+            [0xfc, 0x00, 0xff, 0xff, 0x40, 0x0f]
+        );
+        assert_eq!(
+            encode_tlut(TlutFmt::IA8, &rgba),
+            [0xff, 0x4d, 0xff, 0xff, 0x80, 0x1d]
+        );
+    }
+
+    #[test]
+    fn rgb565_and_rgb5a3_are_lossless() {
+        for format in [TlutFmt::RGB565, TlutFmt::RGB5A3] {
+            let entries: Vec<u8> = (0..=u16::MAX)
+                // RGB4A3 with full alpha encodes back as opaque RGB555.
+                .filter(|&value| format != TlutFmt::RGB5A3 || value & 0xf000 != 0x7000)
+                .flat_map(u16::to_be_bytes)
+                .collect();
+            let rgba = decode_tlut(format, &entries);
+            assert_eq!(encode_tlut(format, &rgba), entries, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn indexed() {
+        let indices: Vec<u16> = (0..8 * 8).map(|i| i % 16).collect();
+        let encoded = encode_indexed(TexFmt::C4, &indices, 8, 8);
+        assert_eq!(&encoded[..4], &[0x01, 0x23, 0x45, 0x67]);
+        assert_eq!(decode_indexed(TexFmt::C4, &encoded, 8, 8), indices);
+
+        let indices: Vec<u16> = (0..4 * 4).map(|i| i * 0x3ff).collect();
+        let encoded = encode_indexed(TexFmt::C14X2, &indices, 4, 4);
+        assert_eq!(&encoded[..4], &[0x00, 0x00, 0x03, 0xff]);
+        assert_eq!(decode_indexed(TexFmt::C14X2, &encoded, 4, 4), indices);
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_too_large() {
+        encode_indexed(TexFmt::C4, &[16], 1, 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn indexed_as_direct() {
+        encode(TexFmt::C8, &[0; 4], 1, 1);
+    }
+
+    #[test]
+    fn cmpr() {
+        // Colours which RGB565 represents exactly come back unchanged.
+        let red = [0xff, 0x00, 0x00, 0xff];
+        let blue = [0x00, 0x00, 0xff, 0xff];
+        let rgba: Vec<u8> = (0..8 * 8)
+            .flat_map(|i| if (i % 8) < 4 { red } else { blue })
+            .collect();
+        let encoded = encode(TexFmt::CMPR, &rgba, 8, 8);
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(decode(TexFmt::CMPR, &encoded, 8, 8), rgba);
+
+        // Transparent texels switch the sub-block to three colours and transparent black.
+        let rgba: Vec<u8> = (0..4 * 4)
+            .flat_map(|i| if i < 8 { red } else { [0; 4] })
+            .collect();
+        let encoded = encode(TexFmt::CMPR, &rgba, 4, 4);
+        let color0 = u16::from_be_bytes([encoded[0], encoded[1]]);
+        let color1 = u16::from_be_bytes([encoded[2], encoded[3]]);
+        assert!(color0 <= color1);
+        assert_eq!(decode(TexFmt::CMPR, &encoded, 4, 4), rgba);
+    }
+}
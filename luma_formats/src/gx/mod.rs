@@ -0,0 +1,205 @@
+//! ``gx`` module of ``luma_formats``.
+//!
+//! Contains the commands the Graphics eXecutor, or GX, reads from its FIFO, and the values of
+//! the registers they load.
+
+use alloc::vec::Vec;
+
+pub mod encode;
+pub mod tev;
+pub mod texture;
+pub mod tpl;
+
+// Command FIFO opcodes.
+const LOAD_CP_REG: u8 = 0x08;
+const LOAD_XF_REG: u8 = 0x10;
+const LOAD_BP_REG: u8 = 0x61;
+
+/// Where GX commands get written: the write-gather pipe on the console, or a buffer such as a
+/// display list.  Values are big endian, as the GP reads them.
+pub trait Fifo {
+    /// Append a byte.
+    fn write_u8(&mut self, value: u8);
+
+    /// Append a 16-bit value.
+    fn write_u16(&mut self, value: u16);
+
+    /// Append a 32-bit value.
+    fn write_u32(&mut self, value: u32);
+}
+
+impl Fifo for Vec<u8> {
+    fn write_u8(&mut self, value: u8) {
+        self.push(value);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Load a Blitting Processor register, ``value`` holds the register in its top 8 bits.
+#[inline(always)]
+pub fn load_bp_reg(fifo: &mut impl Fifo, value: u32) {
+    fifo.write_u8(LOAD_BP_REG);
+    fifo.write_u32(value);
+}
+
+/// Load a Command Processor register.
+#[inline(always)]
+pub fn load_cp_reg(fifo: &mut impl Fifo, register: u8, value: u32) {
+    fifo.write_u8(LOAD_CP_REG);
+    fifo.write_u8(register);
+    fifo.write_u32(value);
+}
+
+/// Load a single Transform Unit register.
+#[inline(always)]
+pub fn load_xf_reg(fifo: &mut impl Fifo, register: u16, value: u32) {
+    fifo.write_u8(LOAD_XF_REG);
+    fifo.write_u32(register as u32);
+    fifo.write_u32(value);
+}
+
+/// Load ``values.len()`` consecutive Transform Unit registers, starting at ``register``.
+///
+/// # Panics:
+/// This function will panic if ``values`` is empty or holds more than 16 values.
+pub fn load_xf_regs(fifo: &mut impl Fifo, register: u16, values: &[u32]) {
+    assert!(!values.is_empty() && values.len() <= 16);
+    fifo.write_u8(LOAD_XF_REG);
+    fifo.write_u32((((values.len() - 1) as u32) << 16) | register as u32);
+    for &value in values {
+        fifo.write_u32(value);
+    }
+}
+
+/// The ``genMode`` BP register, which holds the amount of texgens, colour channels and TEV stages
+/// among other things.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct GenMode(u32);
+
+impl GenMode {
+    /// One colour channel, one TEV stage and no texgen.
+    pub const fn new() -> Self {
+        Self(0x0000_0010)
+    }
+
+    /// Rebuild a [`GenMode`] from a value returned by [`GenMode::bits`].
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Get the value of the register, as passed to [`load_bp_reg`].
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Set the number of texture coordinates generated for each vertex, up to 8.
+    pub fn with_num_tex_gens(&mut self, count: u8) -> &mut Self {
+        assert!(count <= 8);
+        self.0 = bitfrob::u32_with_value(0, 3, self.0, count as u32);
+        self
+    }
+
+    /// Set the number of colour channels output by the lighting stage, up to 2.
+    pub fn with_num_chans(&mut self, count: u8) -> &mut Self {
+        assert!(count <= 2);
+        self.0 = bitfrob::u32_with_value(4, 6, self.0, count as u32);
+        self
+    }
+
+    /// Set the number of TEV stages used when rendering, between 1 and 16.
+    pub fn with_num_tev_stages(&mut self, count: u8) -> &mut Self {
+        assert!((1..=16).contains(&count));
+        self.0 = bitfrob::u32_with_value(10, 13, self.0, count as u32 - 1);
+        self
+    }
+}
+
+impl Default for GenMode {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn bp_command() {
+        let mut fifo = Vec::new();
+        load_bp_reg(&mut fifo, 0x4800_1234);
+        assert_eq!(fifo, [0x61, 0x48, 0x00, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn cp_command() {
+        let mut fifo = Vec::new();
+        load_cp_reg(&mut fifo, 0x50, 0x0000_0600);
+        assert_eq!(fifo, [0x08, 0x50, 0x00, 0x00, 0x06, 0x00]);
+    }
+
+    #[test]
+    fn xf_command() {
+        let mut fifo = Vec::new();
+        load_xf_reg(&mut fifo, 0x1009, 2);
+        assert_eq!(fifo, [0x10, 0x00, 0x00, 0x10, 0x09, 0x00, 0x00, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn xf_commands() {
+        let mut fifo = Vec::new();
+        load_xf_regs(&mut fifo, 0x101a, &[0x3f80_0000, 0xbf80_0000, 0x4000_0000]);
+        assert_eq!(
+            fifo,
+            [
+                0x10, 0x00, 0x02, 0x10, 0x1a, 0x3f, 0x80, 0x00, 0x00, 0xbf, 0x80, 0x00, 0x00, 0x40,
+                0x00, 0x00, 0x00,
+            ]
+        );
+
+        // A single register is the same as load_xf_reg().
+        let mut single = Vec::new();
+        load_xf_regs(&mut single, 0x1009, &[2]);
+        let mut expected = vec![];
+        load_xf_reg(&mut expected, 0x1009, 2);
+        assert_eq!(single, expected);
+    }
+
+    #[test]
+    #[should_panic]
+    fn xf_commands_too_long() {
+        load_xf_regs(&mut Vec::new(), 0x1000, &[0; 17]);
+    }
+
+    #[test]
+    fn gen_mode() {
+        let mut gen_mode = GenMode::new();
+        gen_mode
+            .with_num_tex_gens(2)
+            .with_num_chans(1)
+            .with_num_tev_stages(3);
+        assert_eq!(gen_mode.bits(), 0x0000_0812);
+
+        gen_mode
+            .with_num_tev_stages(16)
+            .with_num_tex_gens(8)
+            .with_num_chans(0);
+        assert_eq!(gen_mode.bits(), 0x0000_3c08);
+        assert_eq!(GenMode::from_bits(gen_mode.bits()), gen_mode);
+    }
+
+    #[test]
+    #[should_panic]
+    fn gen_mode_without_stages() {
+        GenMode::new().with_num_tev_stages(0);
+    }
+}
@@ -0,0 +1,462 @@
+//! ``tev`` module of ``luma_formats::gx``.
+//!
+//! Contains the registers of the TExture EnVironment, or TEV, the fixed-function combiner which
+//! computes the colour of each pixel from textures, rasterized colours and constants.
+
+use super::texture::TexMap;
+
+/// One of the sixteen TEV stages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevStage {
+    Stage0 = 0,
+    Stage1 = 1,
+    Stage2 = 2,
+    Stage3 = 3,
+    Stage4 = 4,
+    Stage5 = 5,
+    Stage6 = 6,
+    Stage7 = 7,
+    Stage8 = 8,
+    Stage9 = 9,
+    Stage10 = 10,
+    Stage11 = 11,
+    Stage12 = 12,
+    Stage13 = 13,
+    Stage14 = 14,
+    Stage15 = 15,
+}
+
+/// Inputs of the colour combiner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevColorArg {
+    CPrev = 0x0,
+    APrev = 0x1,
+    C0 = 0x2,
+    A0 = 0x3,
+    C1 = 0x4,
+    A1 = 0x5,
+    C2 = 0x6,
+    A2 = 0x7,
+    TexC = 0x8,
+    TexA = 0x9,
+    RasC = 0xa,
+    RasA = 0xb,
+    One = 0xc,
+    Half = 0xd,
+    Konst = 0xe,
+    Zero = 0xf,
+}
+
+/// Inputs of the alpha combiner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevAlphaArg {
+    APrev = 0x0,
+    A0 = 0x1,
+    A1 = 0x2,
+    A2 = 0x3,
+    TexA = 0x4,
+    RasA = 0x5,
+    Konst = 0x6,
+    Zero = 0x7,
+}
+
+/// Whether the combiner adds or subtracts ``d`` and the interpolated value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevOp {
+    Add = 0,
+    Sub = 1,
+}
+
+/// Bias added to the result of the combiner, before scaling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevBias {
+    Zero = 0,
+    AddHalf = 1,
+    SubHalf = 2,
+}
+
+/// Scale applied to the result of the combiner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevScale {
+    Scale1 = 0,
+    Scale2 = 1,
+    Scale4 = 2,
+    Divide2 = 3,
+}
+
+/// Registers of the TEV, used both as inputs and as destination of the combiners.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TevReg {
+    Prev = 0,
+    Reg0 = 1,
+    Reg1 = 2,
+    Reg2 = 3,
+}
+
+/// Rasterized colour channel fed to a TEV stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ChannelId {
+    Color0A0 = 0,
+    Color1A1 = 1,
+    AlphaBump = 5,
+    AlphaBumpN = 6,
+    ColorZero = 7,
+}
+
+/// Preset TEV configurations, matching the most common uses of a single stage.
+///
+/// Past the first stage, the presets combine the output of the previous stage instead of the
+/// rasterized colour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TevMode {
+    /// Texture × rasterized colour.
+    Modulate,
+    /// Texture blended over the rasterized colour, using the texture alpha.
+    Decal,
+    /// Rasterized colour blended towards white, using the texture colour.
+    Blend,
+    /// Texture only.
+    Replace,
+    /// Rasterized colour only.
+    PassClr,
+}
+
+impl TevMode {
+    /// Get both combiners of this preset for ``stage``, past the first stage the rasterized
+    /// colour is replaced by the output of the previous one.
+    pub fn envs(self, stage: TevStage) -> (TevColorEnv, TevAlphaEnv) {
+        use TevAlphaArg as A;
+        use TevColorArg as C;
+
+        let (prev_color, prev_alpha) = if stage == TevStage::Stage0 {
+            (C::RasC, A::RasA)
+        } else {
+            (C::CPrev, A::APrev)
+        };
+        let (color, alpha) = match self {
+            TevMode::Modulate => (
+                [C::Zero, C::TexC, prev_color, C::Zero],
+                [A::Zero, A::TexA, prev_alpha, A::Zero],
+            ),
+            TevMode::Decal => (
+                [prev_color, C::TexC, C::TexA, C::Zero],
+                [A::Zero, A::Zero, A::Zero, prev_alpha],
+            ),
+            TevMode::Blend => (
+                [prev_color, C::One, C::TexC, C::Zero],
+                [A::Zero, A::TexA, prev_alpha, A::Zero],
+            ),
+            TevMode::Replace => (
+                [C::Zero, C::Zero, C::Zero, C::TexC],
+                [A::Zero, A::Zero, A::Zero, A::TexA],
+            ),
+            TevMode::PassClr => (
+                [C::Zero, C::Zero, C::Zero, prev_color],
+                [A::Zero, A::Zero, A::Zero, prev_alpha],
+            ),
+        };
+
+        let mut color_env = TevColorEnv::new();
+        color_env
+            .with_inputs(color[0], color[1], color[2], color[3])
+            .with_op(
+                TevOp::Add,
+                TevBias::Zero,
+                TevScale::Scale1,
+                true,
+                TevReg::Prev,
+            );
+        let mut alpha_env = TevAlphaEnv::new();
+        alpha_env
+            .with_inputs(alpha[0], alpha[1], alpha[2], alpha[3])
+            .with_op(
+                TevOp::Add,
+                TevBias::Zero,
+                TevScale::Scale1,
+                true,
+                TevReg::Prev,
+            );
+        (color_env, alpha_env)
+    }
+}
+
+/// TEV colour combiner, computes ``dest = (d ± lerp(a, b, c) + bias) × scale``.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct TevColorEnv(u32);
+
+impl TevColorEnv {
+    /// A pass-through of the previous stage, with clamping enabled.
+    pub const fn new() -> Self {
+        Self(0x0008_fff0)
+    }
+
+    pub fn with_inputs(
+        &mut self,
+        a: TevColorArg,
+        b: TevColorArg,
+        c: TevColorArg,
+        d: TevColorArg,
+    ) -> &mut Self {
+        self.0 = bitfrob::u32_with_value(12, 15, self.0, a as u32);
+        self.0 = bitfrob::u32_with_value(8, 11, self.0, b as u32);
+        self.0 = bitfrob::u32_with_value(4, 7, self.0, c as u32);
+        self.0 = bitfrob::u32_with_value(0, 3, self.0, d as u32);
+        self
+    }
+
+    pub fn with_op(
+        &mut self,
+        op: TevOp,
+        bias: TevBias,
+        scale: TevScale,
+        clamp: bool,
+        dest: TevReg,
+    ) -> &mut Self {
+        self.0 = with_op(self.0, op, bias, scale, clamp, dest);
+        self
+    }
+
+    /// Get the BP register loading this combiner into ``stage``.
+    pub const fn bp_reg(&self, stage: TevStage) -> u32 {
+        ((0xc0 + 2 * stage as u32) << 24) | self.0
+    }
+}
+
+impl Default for TevColorEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// TEV alpha combiner, computes ``dest = (d ± lerp(a, b, c) + bias) × scale``.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct TevAlphaEnv(u32);
+
+impl TevAlphaEnv {
+    /// A pass-through of the previous stage, with clamping enabled.
+    pub const fn new() -> Self {
+        Self(0x0008_ff80)
+    }
+
+    pub fn with_inputs(
+        &mut self,
+        a: TevAlphaArg,
+        b: TevAlphaArg,
+        c: TevAlphaArg,
+        d: TevAlphaArg,
+    ) -> &mut Self {
+        self.0 = bitfrob::u32_with_value(13, 15, self.0, a as u32);
+        self.0 = bitfrob::u32_with_value(10, 12, self.0, b as u32);
+        self.0 = bitfrob::u32_with_value(7, 9, self.0, c as u32);
+        self.0 = bitfrob::u32_with_value(4, 6, self.0, d as u32);
+        self
+    }
+
+    pub fn with_op(
+        &mut self,
+        op: TevOp,
+        bias: TevBias,
+        scale: TevScale,
+        clamp: bool,
+        dest: TevReg,
+    ) -> &mut Self {
+        self.0 = with_op(self.0, op, bias, scale, clamp, dest);
+        self
+    }
+
+    /// Select which swap tables are applied to the rasterized and texture colours.
+    pub fn with_swap(&mut self, ras_swap: u8, tex_swap: u8) -> &mut Self {
+        assert!(ras_swap < 4 && tex_swap < 4);
+        self.0 = bitfrob::u32_with_value(0, 1, self.0, ras_swap as u32);
+        self.0 = bitfrob::u32_with_value(2, 3, self.0, tex_swap as u32);
+        self
+    }
+
+    /// Get the BP register loading this combiner into ``stage``.
+    pub const fn bp_reg(&self, stage: TevStage) -> u32 {
+        ((0xc1 + 2 * stage as u32) << 24) | self.0
+    }
+}
+
+impl Default for TevAlphaEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The upper half of both combiners is laid out the same way.
+fn with_op(
+    value: u32,
+    op: TevOp,
+    bias: TevBias,
+    scale: TevScale,
+    clamp: bool,
+    dest: TevReg,
+) -> u32 {
+    let mut value = value;
+    value = bitfrob::u32_with_value(16, 17, value, bias as u32);
+    value = bitfrob::u32_with_bit(18, value, op == TevOp::Sub);
+    value = bitfrob::u32_with_bit(19, value, clamp);
+    value = bitfrob::u32_with_value(20, 21, value, scale as u32);
+    value = bitfrob::u32_with_value(22, 23, value, dest as u32);
+    value
+}
+
+/// Get the ``RAS1_TREF`` BP register holding the order of ``stage``, out of the ``order``
+/// previously loaded into it for the other stage of the pair.
+///
+/// Passing ``None`` as the texture map disables texturing for that stage.
+pub fn tev_order_reg(
+    order: u32,
+    stage: TevStage,
+    tex_coord: u8,
+    tex_map: Option<TexMap>,
+    channel: ChannelId,
+) -> u32 {
+    assert!(tex_coord < 8);
+    let pair = stage as u32 / 2;
+    let shift = if stage as u32 & 1 != 0 { 12 } else { 0 };
+
+    let mut order = order & 0x00ff_ffff;
+    let map = tex_map.map_or(0, |map| map as u32);
+    order = bitfrob::u32_with_value(shift, shift + 2, order, map);
+    order = bitfrob::u32_with_value(shift + 3, shift + 5, order, tex_coord as u32);
+    order = bitfrob::u32_with_bit(shift + 6, order, tex_map.is_some());
+    order = bitfrob::u32_with_value(shift + 7, shift + 9, order, channel as u32);
+    ((0x28 + pair) << 24) | order
+}
+
+/// Get the two BP registers setting one of the TEV registers, using signed 11-bit components in
+/// RGBA order: the RA half first, then the BG half.
+pub fn tev_color_regs(reg: TevReg, color: [i16; 4]) -> (u32, u32) {
+    let [r, g, b, a] = color.map(|component| (component as u32) & 0x7ff);
+    let id = 0xe0 + 2 * reg as u32;
+
+    let mut ra = 0;
+    ra = bitfrob::u32_with_value(0, 10, ra, r);
+    ra = bitfrob::u32_with_value(12, 22, ra, a);
+    let mut bg = 0;
+    bg = bitfrob::u32_with_value(0, 10, bg, b);
+    bg = bitfrob::u32_with_value(12, 22, bg, g);
+
+    ((id << 24) | ra, ((id + 1) << 24) | bg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_env() {
+        let mut env = TevColorEnv::new();
+        env.with_inputs(
+            TevColorArg::C0,
+            TevColorArg::TexC,
+            TevColorArg::TexA,
+            TevColorArg::Konst,
+        )
+        .with_op(
+            TevOp::Sub,
+            TevBias::AddHalf,
+            TevScale::Scale2,
+            false,
+            TevReg::Reg1,
+        );
+        assert_eq!(env.bp_reg(TevStage::Stage0), 0xc095_289e);
+        assert_eq!(env.bp_reg(TevStage::Stage15), 0xde95_289e);
+    }
+
+    #[test]
+    fn alpha_env() {
+        let mut env = TevAlphaEnv::new();
+        env.with_inputs(
+            TevAlphaArg::A0,
+            TevAlphaArg::TexA,
+            TevAlphaArg::RasA,
+            TevAlphaArg::Zero,
+        )
+        .with_op(
+            TevOp::Add,
+            TevBias::Zero,
+            TevScale::Divide2,
+            true,
+            TevReg::Prev,
+        )
+        .with_swap(1, 2);
+        assert_eq!(env.bp_reg(TevStage::Stage1), 0xc338_32f9);
+        assert_eq!(TevAlphaEnv::new().bp_reg(TevStage::Stage0), 0xc108_ff80);
+    }
+
+    #[test]
+    fn first_stage_presets() {
+        let regs = |mode: TevMode| {
+            let (color, alpha) = mode.envs(TevStage::Stage0);
+            (
+                color.bp_reg(TevStage::Stage0),
+                alpha.bp_reg(TevStage::Stage0),
+            )
+        };
+        assert_eq!(regs(TevMode::Modulate), (0xc008_f8af, 0xc108_f2f0));
+        assert_eq!(regs(TevMode::Decal), (0xc008_a89f, 0xc108_ffd0));
+        assert_eq!(regs(TevMode::Blend), (0xc008_ac8f, 0xc108_f2f0));
+        assert_eq!(regs(TevMode::Replace), (0xc008_fff8, 0xc108_ffc0));
+        assert_eq!(regs(TevMode::PassClr), (0xc008_fffa, 0xc108_ffd0));
+    }
+
+    #[test]
+    fn later_stage_presets() {
+        let regs = |mode: TevMode| {
+            let (color, alpha) = mode.envs(TevStage::Stage1);
+            (
+                color.bp_reg(TevStage::Stage1),
+                alpha.bp_reg(TevStage::Stage1),
+            )
+        };
+        assert_eq!(regs(TevMode::Modulate), (0xc208_f80f, 0xc308_f070));
+        assert_eq!(regs(TevMode::Decal), (0xc208_089f, 0xc308_ff80));
+        assert_eq!(regs(TevMode::Blend), (0xc208_0c8f, 0xc308_f070));
+        assert_eq!(regs(TevMode::Replace), (0xc208_fff8, 0xc308_ffc0));
+        assert_eq!(regs(TevMode::PassClr), (0xc208_fff0, 0xc308_ff80));
+    }
+
+    #[test]
+    fn order() {
+        let even = tev_order_reg(
+            0,
+            TevStage::Stage2,
+            1,
+            Some(TexMap::Map3),
+            ChannelId::Color0A0,
+        );
+        assert_eq!(even, 0x2900_004b);
+
+        // The odd stage keeps the even one of the same pair.
+        let odd = tev_order_reg(even, TevStage::Stage3, 0, None, ChannelId::ColorZero);
+        assert_eq!(odd, 0x2938_004b);
+        let even = tev_order_reg(odd, TevStage::Stage2, 0, None, ChannelId::Color1A1);
+        assert_eq!(even, 0x2938_0080);
+    }
+
+    #[test]
+    fn color() {
+        assert_eq!(
+            tev_color_regs(TevReg::Reg0, [0xff, 0x80, 0x00, 0x40]),
+            (0xe204_00ff, 0xe308_0000)
+        );
+        // Negative components are kept as 11-bit two’s complement.
+        assert_eq!(
+            tev_color_regs(TevReg::Prev, [-1, -1024, 1023, 0]),
+            (0xe000_07ff, 0xe140_03ff)
+        );
+    }
+}
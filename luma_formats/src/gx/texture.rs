@@ -0,0 +1,277 @@
+//! ``texture`` module of ``luma_formats::gx``.
+//!
+//! Contains the texture and palette formats, and the layout of texture memory (TMEM).
+
+/// Texture formats understood by the texture unit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TexFmt {
+    /// 4-bit intensity.
+    I4 = 0x0,
+    /// 8-bit intensity.
+    I8 = 0x1,
+    /// 4-bit intensity with 4-bit alpha.
+    IA4 = 0x2,
+    /// 8-bit intensity with 8-bit alpha.
+    IA8 = 0x3,
+    /// 16-bit colour, without alpha.
+    RGB565 = 0x4,
+    /// 16-bit colour, either RGB555 or RGB4A3 depending on the top bit.
+    RGB5A3 = 0x5,
+    /// 32-bit colour, stored as an AR block followed by a GB block.
+    RGBA8 = 0x6,
+    /// 4-bit index into a TLUT.
+    C4 = 0x8,
+    /// 8-bit index into a TLUT.
+    C8 = 0x9,
+    /// 14-bit index into a TLUT.
+    C14X2 = 0xa,
+    /// DXT1-like compressed colour.
+    CMPR = 0xe,
+}
+
+impl TexFmt {
+    /// Convert the hardware (and TPL) format number into a [`TexFmt`].
+    pub fn from_raw(format: u32) -> Option<TexFmt> {
+        Some(match format {
+            0x0 => TexFmt::I4,
+            0x1 => TexFmt::I8,
+            0x2 => TexFmt::IA4,
+            0x3 => TexFmt::IA8,
+            0x4 => TexFmt::RGB565,
+            0x5 => TexFmt::RGB5A3,
+            0x6 => TexFmt::RGBA8,
+            0x8 => TexFmt::C4,
+            0x9 => TexFmt::C8,
+            0xa => TexFmt::C14X2,
+            0xe => TexFmt::CMPR,
+            _ => return None,
+        })
+    }
+
+    /// Width and height in texels of a single 32-byte tile.
+    pub const fn block_size(self) -> (usize, usize) {
+        match self {
+            TexFmt::I4 | TexFmt::C4 | TexFmt::CMPR => (8, 8),
+            TexFmt::I8 | TexFmt::IA4 | TexFmt::C8 => (8, 4),
+            TexFmt::IA8 | TexFmt::RGB565 | TexFmt::RGB5A3 | TexFmt::RGBA8 | TexFmt::C14X2 => (4, 4),
+        }
+    }
+
+    /// Amount of bytes taken by a single tile, 64 for RGBA8 and 32 for everything else.
+    pub const fn block_bytes(self) -> usize {
+        match self {
+            TexFmt::RGBA8 => 64,
+            _ => 32,
+        }
+    }
+
+    /// Whether this format indexes into a TLUT.
+    pub const fn is_indexed(self) -> bool {
+        matches!(self, TexFmt::C4 | TexFmt::C8 | TexFmt::C14X2)
+    }
+
+    /// Amount of bytes needed to store a ``width`` × ``height`` image in this format, once padded
+    /// to whole tiles.
+    pub const fn size(self, width: usize, height: usize) -> usize {
+        let (block_width, block_height) = self.block_size();
+        let blocks_x = width.div_ceil(block_width);
+        let blocks_y = height.div_ceil(block_height);
+        blocks_x * blocks_y * self.block_bytes()
+    }
+
+    /// Amount of bytes needed to store a ``width`` × ``height`` image with ``levels`` mipmap
+    /// levels, each level being half the size of the previous one.
+    pub const fn mipmap_size(self, width: usize, height: usize, levels: u8) -> usize {
+        let mut size = 0;
+        let mut level = 0;
+        while level < levels {
+            let level_width = if width >> level == 0 {
+                1
+            } else {
+                width >> level
+            };
+            let level_height = if height >> level == 0 {
+                1
+            } else {
+                height >> level
+            };
+            size += self.size(level_width, level_height);
+            level += 1;
+        }
+        size
+    }
+}
+
+/// Formats of the entries of a TLUT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TlutFmt {
+    IA8 = 0x0,
+    RGB565 = 0x1,
+    RGB5A3 = 0x2,
+}
+
+impl TlutFmt {
+    /// Convert the hardware (and TPL) palette format number into a [`TlutFmt`].
+    pub fn from_raw(format: u32) -> Option<TlutFmt> {
+        Some(match format {
+            0x0 => TlutFmt::IA8,
+            0x1 => TlutFmt::RGB565,
+            0x2 => TlutFmt::RGB5A3,
+            _ => return None,
+        })
+    }
+}
+
+/// What happens to texture coordinates outside of the [0, 1] range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum WrapMode {
+    Clamp = 0,
+    Repeat = 1,
+    Mirror = 2,
+}
+
+/// Texture filtering, the mipmap variants are only meaningful as a minification filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TexFilter {
+    Near,
+    Linear,
+    NearMipNear,
+    LinMipNear,
+    NearMipLin,
+    LinMipLin,
+}
+
+impl TexFilter {
+    /// The encoding used by the texture unit, which doesn’t follow the declaration order.
+    pub const fn to_hw(self) -> u32 {
+        match self {
+            TexFilter::Near => 0,
+            TexFilter::NearMipNear => 1,
+            TexFilter::NearMipLin => 2,
+            TexFilter::Linear => 4,
+            TexFilter::LinMipNear => 5,
+            TexFilter::LinMipLin => 6,
+        }
+    }
+}
+
+/// One of the eight texture maps a TEV stage can sample from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TexMap {
+    Map0 = 0,
+    Map1 = 1,
+    Map2 = 2,
+    Map3 = 3,
+    Map4 = 4,
+    Map5 = 5,
+    Map6 = 6,
+    Map7 = 7,
+}
+
+impl TexMap {
+    /// Get the BP register of this map, out of the one of the first map, shifted into place.
+    ///
+    /// Texture map registers come in two banks of four, the second one being 0x20 further.
+    pub const fn bp_reg(self, first: u32) -> u32 {
+        let map = self as u32;
+        (first + (map & 3) + if map >= 4 { 0x20 } else { 0 }) << 24
+    }
+}
+
+/// One of the sixteen TLUT slots in TMEM, each able to hold up to 256 entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tlut(u8);
+
+impl Tlut {
+    /// # Panics:
+    /// This function will panic if ``index`` isn’t in the 0..16 range.
+    pub const fn new(index: u8) -> Tlut {
+        assert!(index < 16, "There are only 16 TLUT slots");
+        Tlut(index)
+    }
+
+    /// Offset of this slot into TMEM, the TLUTs live right after the texture caches.
+    pub const fn tmem_offset(self) -> u32 {
+        // Units of 512 bytes, starting at 0x80000.
+        (0xc_0000 + ((self.0 as u32) << 13) - 0x8_0000) >> 9
+    }
+}
+
+// TMEM layout, every texture map gets 32 KiB in both the even and the odd bank, RGBA8 textures
+// use the upper half of TMEM for their odd (GB) tiles.
+const TEXCACHE_32K: u32 = 3;
+
+/// Get the offsets into TMEM of the even and odd tiles of a texture loaded into ``map``.
+pub fn tmem_regions(map: TexMap, format: TexFmt) -> (u32, u32) {
+    let map = map as u32;
+    let even = map << 16;
+    let odd = match format {
+        TexFmt::RGBA8 => 0x8_0000 + (map << 15),
+        _ => even + 0x8000,
+    };
+    (even, odd)
+}
+
+/// Get the value of the ``TX_SETIMAGE1`` or ``TX_SETIMAGE2`` register for a TMEM region.
+pub fn tmem_region_reg(offset: u32) -> u32 {
+    let mut value = 0;
+    value = bitfrob::u32_with_value(0, 14, value, offset >> 5);
+    value = bitfrob::u32_with_value(15, 17, value, TEXCACHE_32K);
+    value = bitfrob::u32_with_value(18, 20, value, TEXCACHE_32K);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(TexFmt::I4.size(9, 9), 4 * 32);
+        assert_eq!(TexFmt::I8.size(8, 4), 32);
+        assert_eq!(TexFmt::RGBA8.size(4, 4), 64);
+        assert_eq!(TexFmt::CMPR.size(16, 16), 4 * 32);
+        assert_eq!(TexFmt::RGBA8.mipmap_size(8, 8, 4), 4 * 64 + 3 * 64);
+        assert_eq!(TexFmt::I8.mipmap_size(16, 2, 2), 2 * 32 + 32);
+    }
+
+    #[test]
+    fn raw_formats() {
+        for format in [TexFmt::I4, TexFmt::RGB5A3, TexFmt::C14X2, TexFmt::CMPR] {
+            assert_eq!(TexFmt::from_raw(format as u32), Some(format));
+        }
+        assert_eq!(TexFmt::from_raw(0x7), None);
+        assert_eq!(TlutFmt::from_raw(0x2), Some(TlutFmt::RGB5A3));
+        assert_eq!(TlutFmt::from_raw(0x3), None);
+    }
+
+    #[test]
+    fn map_registers() {
+        assert_eq!(TexMap::Map0.bp_reg(0x80), 0x8000_0000);
+        assert_eq!(TexMap::Map3.bp_reg(0x88), 0x8b00_0000);
+        assert_eq!(TexMap::Map5.bp_reg(0x80), 0xa100_0000);
+        assert_eq!(TexMap::Map7.bp_reg(0x94), 0xb700_0000);
+    }
+
+    #[test]
+    fn tmem() {
+        assert_eq!(Tlut::new(0).tmem_offset(), 0x200);
+        assert_eq!(Tlut::new(1).tmem_offset(), 0x210);
+        assert_eq!(tmem_regions(TexMap::Map1, TexFmt::I8), (0x1_0000, 0x1_8000));
+        assert_eq!(
+            tmem_regions(TexMap::Map1, TexFmt::RGBA8),
+            (0x1_0000, 0x8_8000)
+        );
+        assert_eq!(tmem_region_reg(0x1_0000), 0x000d_8800);
+    }
+
+    #[test]
+    #[should_panic]
+    fn tlut_out_of_range() {
+        Tlut::new(16);
+    }
+}
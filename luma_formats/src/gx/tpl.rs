@@ -0,0 +1,231 @@
+//! ``tpl`` module of ``luma_formats::gx``.
+//!
+//! Contains a zero-copy parser for TPL files, the texture palette container produced by the
+//! official tools and most texture converters.  It only borrows the file and never touches the
+//! hardware, so it can be used (and fuzzed) on the host as well.  Texture and TLUT objects are
+//! built out of the parsed images by ``luma_core::gx::tpl``.
+
+use super::texture::{TexFilter, TexFmt, TlutFmt, WrapMode};
+
+const MAGIC: u32 = 0x0020_af30;
+const HEADER_SIZE: usize = 0x0c;
+const IMAGE_HEADER_SIZE: usize = 0x24;
+const PALETTE_HEADER_SIZE: usize = 0x0c;
+
+/// Errors which can be encountered while parsing a TPL file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TplError {
+    /// The file doesn’t start with the TPL magic.
+    BadMagic,
+    /// A header or some image data points past the end of the file.
+    OutOfBounds,
+    /// The requested image doesn’t exist.
+    NoSuchImage,
+    /// An image has a width or height of zero, or bigger than 1024.
+    BadSize,
+    /// An image uses an unknown texture format.
+    UnknownFormat(u32),
+    /// A palette uses an unknown TLUT format.
+    UnknownPaletteFormat(u32),
+    /// An indexed image has no palette.
+    MissingPalette,
+    /// A wrap mode or filter has an unknown value.
+    BadSampler,
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, TplError> {
+    data.get(offset).copied().ok_or(TplError::OutOfBounds)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, TplError> {
+    let bytes = data.get(offset..offset + 2).ok_or(TplError::OutOfBounds)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, TplError> {
+    let bytes = data.get(offset..offset + 4).ok_or(TplError::OutOfBounds)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Borrow ``size`` bytes of ``data`` starting at ``offset``, without overflowing.
+fn slice(data: &[u8], offset: u32, size: usize) -> Result<&[u8], TplError> {
+    let offset = offset as usize;
+    let end = offset.checked_add(size).ok_or(TplError::OutOfBounds)?;
+    data.get(offset..end).ok_or(TplError::OutOfBounds)
+}
+
+/// A parsed TPL file, borrowing its data.
+#[derive(Clone, Copy, Debug)]
+pub struct Tpl<'a> {
+    data: &'a [u8],
+    count: u32,
+    table: u32,
+}
+
+impl<'a> Tpl<'a> {
+    /// Validate the header of a TPL file, and the bounds of its image table.
+    pub fn parse(data: &'a [u8]) -> Result<Tpl<'a>, TplError> {
+        if data.len() < HEADER_SIZE {
+            return Err(TplError::OutOfBounds);
+        }
+        if read_u32(data, 0)? != MAGIC {
+            return Err(TplError::BadMagic);
+        }
+        let count = read_u32(data, 4)?;
+        let table = read_u32(data, 8)?;
+
+        let table_size = (count as usize)
+            .checked_mul(8)
+            .ok_or(TplError::OutOfBounds)?;
+        slice(data, table, table_size)?;
+
+        Ok(Tpl { data, count, table })
+    }
+
+    /// Get the amount of images in this file.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Whether this file contains no image at all.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Parse the image at ``index``, along with its palette if it has one.
+    pub fn image(&self, index: usize) -> Result<TplImage<'a>, TplError> {
+        if index >= self.len() {
+            return Err(TplError::NoSuchImage);
+        }
+        let entry = self.table as usize + index * 8;
+        let image_header = read_u32(self.data, entry)?;
+        let palette_header = read_u32(self.data, entry + 4)?;
+
+        let header = slice(self.data, image_header, IMAGE_HEADER_SIZE)?;
+        let height = read_u16(header, 0x00)?;
+        let width = read_u16(header, 0x02)?;
+        let raw_format = read_u32(header, 0x04)?;
+        let data_offset = read_u32(header, 0x08)?;
+        let wrap_s = wrap_mode(read_u32(header, 0x0c)?)?;
+        let wrap_t = wrap_mode(read_u32(header, 0x10)?)?;
+        let min_filter = tex_filter(read_u32(header, 0x14)?)?;
+        let mag_filter = tex_filter(read_u32(header, 0x18)?)?;
+        let lod_bias = f32::from_bits(read_u32(header, 0x1c)?);
+        let edge_lod = read_u8(header, 0x20)? != 0;
+        let min_lod = read_u8(header, 0x21)?;
+        let max_lod = read_u8(header, 0x22)?;
+
+        if !(1..=1024).contains(&width) || !(1..=1024).contains(&height) {
+            return Err(TplError::BadSize);
+        }
+        if !matches!(mag_filter, TexFilter::Near | TexFilter::Linear) {
+            return Err(TplError::BadSampler);
+        }
+        let format = TexFmt::from_raw(raw_format).ok_or(TplError::UnknownFormat(raw_format))?;
+
+        let mipmapped = !matches!(min_filter, TexFilter::Near | TexFilter::Linear);
+        let levels = if mipmapped { max_lod.min(10) + 1 } else { 1 };
+        let size = format.mipmap_size(width as usize, height as usize, levels);
+        let data = slice(self.data, data_offset, size)?;
+
+        let palette = if palette_header != 0 {
+            Some(self.palette(palette_header)?)
+        } else if format.is_indexed() {
+            return Err(TplError::MissingPalette);
+        } else {
+            None
+        };
+
+        Ok(TplImage {
+            width,
+            height,
+            format,
+            data,
+            levels,
+            wrap_s,
+            wrap_t,
+            min_filter,
+            mag_filter,
+            lod_bias,
+            edge_lod,
+            min_lod,
+            max_lod,
+            palette,
+        })
+    }
+
+    /// Iterate over every image of this file.
+    pub fn images(&self) -> impl Iterator<Item = Result<TplImage<'a>, TplError>> + '_ {
+        (0..self.len()).map(|index| self.image(index))
+    }
+
+    fn palette(&self, offset: u32) -> Result<TplPalette<'a>, TplError> {
+        let header = slice(self.data, offset, PALETTE_HEADER_SIZE)?;
+        let entries = read_u16(header, 0x00)?;
+        let raw_format = read_u32(header, 0x04)?;
+        let data_offset = read_u32(header, 0x08)?;
+
+        let format =
+            TlutFmt::from_raw(raw_format).ok_or(TplError::UnknownPaletteFormat(raw_format))?;
+        let data = slice(self.data, data_offset, entries as usize * 2)?;
+
+        Ok(TplPalette { format, data })
+    }
+}
+
+fn wrap_mode(value: u32) -> Result<WrapMode, TplError> {
+    Ok(match value {
+        0 => WrapMode::Clamp,
+        1 => WrapMode::Repeat,
+        2 => WrapMode::Mirror,
+        _ => return Err(TplError::BadSampler),
+    })
+}
+
+fn tex_filter(value: u32) -> Result<TexFilter, TplError> {
+    Ok(match value {
+        0 => TexFilter::Near,
+        1 => TexFilter::Linear,
+        2 => TexFilter::NearMipNear,
+        3 => TexFilter::LinMipNear,
+        4 => TexFilter::NearMipLin,
+        5 => TexFilter::LinMipLin,
+        _ => return Err(TplError::BadSampler),
+    })
+}
+
+/// An image of a TPL file, along with the sampler settings it was authored with.
+#[derive(Clone, Copy, Debug)]
+pub struct TplImage<'a> {
+    pub width: u16,
+    pub height: u16,
+    pub format: TexFmt,
+    /// The tiled texture data, including every mipmap level.
+    pub data: &'a [u8],
+    /// Amount of mipmap levels in ``data``, including the base one.
+    pub levels: u8,
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+    pub min_filter: TexFilter,
+    pub mag_filter: TexFilter,
+    pub lod_bias: f32,
+    pub edge_lod: bool,
+    pub min_lod: u8,
+    pub max_lod: u8,
+    pub palette: Option<TplPalette<'a>>,
+}
+
+/// A palette of a TPL file.
+#[derive(Clone, Copy, Debug)]
+pub struct TplPalette<'a> {
+    pub format: TlutFmt,
+    /// The big-endian entries of this palette.
+    pub data: &'a [u8],
+}
+
+impl<'a> TplPalette<'a> {
+    /// Get the amount of entries in this palette.
+    pub fn entries(&self) -> usize {
+        self.data.len() / 2
+    }
+}
@@ -0,0 +1,62 @@
+//! ``ios`` module of ``luma_formats``.
+//!
+//! Contains the errors IOS answers requests with, and the buffers it can be handed, shared by
+//! the marshalling of the requests of its devices.
+
+/// Errors returned by IOS, or encountered before even reaching it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IosError {
+    /// The caller isn’t allowed to do this.
+    PermissionDenied,
+    /// The resource already exists.
+    AlreadyExists,
+    /// An argument got rejected.
+    Invalid,
+    /// The resource doesn’t exist.
+    NotFound,
+    /// The resource is in use.
+    Busy,
+    /// IOS or this client ran out of memory, or of requests.
+    OutOfMemory,
+    /// Any other negative value returned by IOS.
+    Other(i32),
+}
+
+impl IosError {
+    /// Interpret the result of a request, negative values being errors.
+    pub fn check(result: i32) -> Result<i32, IosError> {
+        match result {
+            0.. => Ok(result),
+            -1 => Err(IosError::PermissionDenied),
+            -2 => Err(IosError::AlreadyExists),
+            -4 => Err(IosError::Invalid),
+            -6 => Err(IosError::NotFound),
+            -8 => Err(IosError::Busy),
+            -22 => Err(IosError::OutOfMemory),
+            code => Err(IosError::Other(code)),
+        }
+    }
+
+    /// Get the value IOS returned for this error.
+    pub fn code(self) -> i32 {
+        match self {
+            IosError::PermissionDenied => -1,
+            IosError::AlreadyExists => -2,
+            IosError::Invalid => -4,
+            IosError::NotFound => -6,
+            IosError::Busy => -8,
+            IosError::OutOfMemory => -22,
+            IosError::Other(code) => code,
+        }
+    }
+}
+
+/// A buffer IOS can read from and write to, which can live on the stack.
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+pub struct Aligned<const N: usize>(pub [u8; N]);
+
+/// Whether IOS can write to ``data`` without touching anything around it.
+pub fn is_aligned(data: &[u8]) -> bool {
+    (data.as_ptr() as usize).is_multiple_of(32) && data.len().is_multiple_of(32)
+}
@@ -0,0 +1,20 @@
+//! ``luma_formats`` holds the data formats spoken by the console and its peripherals, from asset
+//! file formats to the commands of the GPU.
+//!
+//! Everything in here only encodes and decodes bytes and never touches the hardware, so it
+//! builds and gets tested on the host as well.  ``luma_core`` re-exports each module where the
+//! driver using it lives.
+#![no_std]
+
+extern crate alloc;
+
+pub mod audio;
+pub mod bluetooth;
+pub mod dsp;
+pub mod gdb;
+pub mod gx;
+pub mod ios;
+pub mod net;
+pub mod sysconf;
+pub mod usb;
+pub mod wpad;
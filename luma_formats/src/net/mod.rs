@@ -0,0 +1,108 @@
+//! ``net`` module of ``luma_formats``.
+//!
+//! Contains the marshalling of the socket requests of ``/dev/net/ip/top`` in [`socket`], sent
+//! to any [`Device`] so that they can be checked against a mock of IOS, and the errors they
+//! fail with.
+
+use crate::ios::IosError;
+use core::ops::DerefMut;
+use embedded_io::ErrorKind;
+
+pub mod socket;
+
+/// Something to send the requests of ``/dev/net/ip/top`` to, which is IOS itself through a
+/// ``Handle`` of ``luma_core`` but may be a mock of it.
+pub trait Device {
+    /// A buffer IOS can write to, for the outputs which can’t go to the caller’s buffer.
+    type Buffer: DerefMut<Target = [u8]>;
+
+    /// Send ``ioctl`` with a single input and a single output buffer.
+    fn ioctl(&self, ioctl: u32, input: &[u8], output: &mut [u8]) -> Result<i32, IosError>;
+
+    /// Send ``ioctl`` with several input and output buffers.
+    fn ioctlv(
+        &self,
+        ioctl: u32,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<i32, IosError>;
+
+    /// Allocate a zeroed buffer of ``length`` bytes.
+    fn buffer(&self, length: usize) -> Self::Buffer;
+
+    /// Get the address IOS sees ``data`` at, which the pointers it writes into it are relative
+    /// to.
+    fn address(&self, data: &[u8]) -> u32;
+}
+
+/// An error of the network stack, most of them being the errno values of IOS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NetError {
+    /// The network stack didn’t get brought up, or failed to.
+    NotInitialized,
+    /// The console has no address, or the socket isn’t connected.
+    NotConnected,
+    /// The socket is non-blocking and the request would have had to wait.
+    WouldBlock,
+    /// The socket is non-blocking and is still connecting.
+    InProgress,
+    /// The socket is already connected.
+    AlreadyConnected,
+    AddrInUse,
+    AddrNotAvailable,
+    ConnectionRefused,
+    ConnectionReset,
+    ConnectionAborted,
+    TimedOut,
+    HostUnreachable,
+    NetworkUnreachable,
+    NetworkDown,
+    /// The host didn’t resolve to any address.
+    NotFound,
+    Invalid,
+    /// Any other errno value, positive.
+    Socket(i32),
+    /// An error of IOS itself, when opening its devices.
+    Ios(IosError),
+}
+
+impl From<IosError> for NetError {
+    /// Convert the result of a socket request, whose errors are negative errno values.
+    fn from(error: IosError) -> NetError {
+        match -error.code() {
+            3 => NetError::AddrInUse,
+            4 => NetError::AddrNotAvailable,
+            6 => NetError::WouldBlock,
+            7 | 26 => NetError::InProgress,
+            13 => NetError::ConnectionAborted,
+            14 => NetError::ConnectionRefused,
+            15 => NetError::ConnectionReset,
+            23 => NetError::HostUnreachable,
+            28 => NetError::Invalid,
+            30 => NetError::AlreadyConnected,
+            38 => NetError::NetworkDown,
+            40 => NetError::NetworkUnreachable,
+            56 => NetError::NotConnected,
+            76 => NetError::TimedOut,
+            errno => NetError::Socket(errno),
+        }
+    }
+}
+
+impl embedded_io::Error for NetError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            NetError::NotConnected => ErrorKind::NotConnected,
+            NetError::AddrInUse => ErrorKind::AddrInUse,
+            NetError::AddrNotAvailable => ErrorKind::AddrNotAvailable,
+            NetError::ConnectionRefused => ErrorKind::ConnectionRefused,
+            NetError::ConnectionReset => ErrorKind::ConnectionReset,
+            NetError::ConnectionAborted => ErrorKind::ConnectionAborted,
+            NetError::TimedOut => ErrorKind::TimedOut,
+            NetError::NotFound => ErrorKind::NotFound,
+            NetError::Invalid => ErrorKind::InvalidInput,
+            NetError::Ios(IosError::OutOfMemory) => ErrorKind::OutOfMemory,
+            _ => ErrorKind::Other,
+        }
+    }
+}
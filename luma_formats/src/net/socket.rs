@@ -0,0 +1,336 @@
+//! ``socket`` module of ``luma_formats::net``.
+//!
+//! Contains the marshalling of the socket requests of ``/dev/net/ip/top``, sent to any
+//! [`Device`] so that they can be checked against something else than IOS.  Sockets are plain
+//! descriptors here, see ``TcpStream`` of ``luma_core::net`` and the others for ones closed on
+//! drop.
+
+use super::{Device, NetError};
+use crate::ios::{self, Aligned};
+use alloc::vec::Vec;
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+pub const IOCTL_ACCEPT: u32 = 1;
+pub const IOCTL_BIND: u32 = 2;
+pub const IOCTL_CLOSE: u32 = 3;
+pub const IOCTL_CONNECT: u32 = 4;
+pub const IOCTL_FCNTL: u32 = 5;
+pub const IOCTL_GET_PEER_NAME: u32 = 6;
+pub const IOCTL_GET_SOCK_NAME: u32 = 7;
+pub const IOCTL_LISTEN: u32 = 10;
+pub const IOCTL_POLL: u32 = 11;
+pub const IOCTLV_RECV_FROM: u32 = 12;
+pub const IOCTLV_SEND_TO: u32 = 13;
+pub const IOCTL_SHUTDOWN: u32 = 14;
+pub const IOCTL_SOCKET: u32 = 15;
+pub const IOCTL_GET_HOST_ID: u32 = 16;
+pub const IOCTLV_GET_ADDR_INFO: u32 = 24;
+pub const IOCTL_STARTUP: u32 = 31;
+
+pub const AF_INET: u8 = 2;
+pub const SOCK_STREAM: u32 = 1;
+pub const SOCK_DGRAM: u32 = 2;
+
+const F_GETFL: u32 = 3;
+const F_SETFL: u32 = 4;
+const O_NONBLOCK: u32 = 0x04;
+
+/// Events of [`PollFd`], as IOS numbers them.
+pub const POLLIN: u32 = 0x0003;
+pub const POLLPRI: u32 = 0x0004;
+pub const POLLOUT: u32 = 0x0008;
+pub const POLLERR: u32 = 0x0020;
+pub const POLLHUP: u32 = 0x0040;
+pub const POLLNVAL: u32 = 0x0080;
+
+/// Size of an IPv4 address as exchanged with IOS, without the padding of the C structure.
+pub const SOCKADDR_SIZE: usize = 8;
+
+/// Room the address of a request gets, whatever its kind.
+const SOCKADDR_ROOM: usize = 28;
+
+/// Size of the result of [`get_addr_info`]: 35 entries of 32 bytes, then as many addresses of
+/// [`SOCKADDR_ROOM`] bytes.
+pub const ADDR_INFO_SIZE: usize = 0x834;
+const ADDR_INFO_ENTRY_SIZE: usize = 0x20;
+const ADDR_INFO_ENTRIES: usize = 35;
+
+/// Size of the entry of a socket in [`poll`].
+const POLL_FD_SIZE: usize = 12;
+
+/// A socket to wait for in [`poll`], and what happened to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PollFd {
+    pub fd: i32,
+    /// The events to wait for, such as [`POLLIN`].
+    pub events: u32,
+    /// The events which happened, filled by [`poll`].
+    pub revents: u32,
+}
+
+/// Encode an address, as taken by IOS.
+pub fn encode_address(address: SocketAddrV4) -> [u8; SOCKADDR_SIZE] {
+    let mut data = [0; SOCKADDR_SIZE];
+    data[0] = SOCKADDR_SIZE as u8;
+    data[1] = AF_INET;
+    data[2..4].copy_from_slice(&address.port().to_be_bytes());
+    data[4..8].copy_from_slice(&address.ip().octets());
+    data
+}
+
+/// Decode an address returned by IOS, which has to be an IPv4 one.
+pub fn decode_address(data: &[u8]) -> Option<SocketAddrV4> {
+    if data.len() < SOCKADDR_SIZE || data[1] != AF_INET {
+        return None;
+    }
+    let port = u16::from_be_bytes([data[2], data[3]]);
+    let ip = Ipv4Addr::new(data[4], data[5], data[6], data[7]);
+    Some(SocketAddrV4::new(ip, port))
+}
+
+/// Encode words for the input of a request.
+fn words<const N: usize>(words: [u32; N]) -> Aligned<32> {
+    let mut data = Aligned([0; 32]);
+    for (bytes, word) in data.0.chunks_exact_mut(4).zip(words) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    data
+}
+
+/// Encode the input of [`bind`] and [`connect`]: the socket, then the address.
+fn socket_and_address(fd: i32, address: SocketAddrV4) -> Aligned<64> {
+    let mut data = Aligned([0; 64]);
+    data.0[0..4].copy_from_slice(&(fd as u32).to_be_bytes());
+    data.0[4..8].copy_from_slice(&1u32.to_be_bytes());
+    data.0[8..8 + SOCKADDR_SIZE].copy_from_slice(&encode_address(address));
+    data
+}
+
+/// Have IOS start its network stack.
+pub fn startup<D: Device + ?Sized>(device: &D) -> Result<(), NetError> {
+    device.ioctl(IOCTL_STARTUP, &[], &mut [])?;
+    Ok(())
+}
+
+/// Get the address of the console, which is unspecified until it got one.
+pub fn host_id<D: Device + ?Sized>(device: &D) -> Result<Ipv4Addr, NetError> {
+    let address = device.ioctl(IOCTL_GET_HOST_ID, &[], &mut [])?;
+    Ok(Ipv4Addr::from_bits(address as u32))
+}
+
+/// Create an IPv4 socket of ``kind``, either [`SOCK_STREAM`] or [`SOCK_DGRAM`].
+pub fn socket<D: Device + ?Sized>(device: &D, kind: u32) -> Result<i32, NetError> {
+    let input = words([AF_INET as u32, kind, 0]);
+    Ok(device.ioctl(IOCTL_SOCKET, &input.0[..12], &mut [])?)
+}
+
+pub fn close<D: Device + ?Sized>(device: &D, fd: i32) -> Result<(), NetError> {
+    let input = words([fd as u32]);
+    device.ioctl(IOCTL_CLOSE, &input.0[..4], &mut [])?;
+    Ok(())
+}
+
+pub fn bind<D: Device + ?Sized>(
+    device: &D,
+    fd: i32,
+    address: SocketAddrV4,
+) -> Result<(), NetError> {
+    let input = socket_and_address(fd, address);
+    device.ioctl(IOCTL_BIND, &input.0[..8 + SOCKADDR_ROOM], &mut [])?;
+    Ok(())
+}
+
+pub fn connect<D: Device + ?Sized>(
+    device: &D,
+    fd: i32,
+    address: SocketAddrV4,
+) -> Result<(), NetError> {
+    let input = socket_and_address(fd, address);
+    device.ioctl(IOCTL_CONNECT, &input.0[..8 + SOCKADDR_ROOM], &mut [])?;
+    Ok(())
+}
+
+pub fn listen<D: Device + ?Sized>(device: &D, fd: i32, backlog: u32) -> Result<(), NetError> {
+    let input = words([fd as u32, backlog]);
+    device.ioctl(IOCTL_LISTEN, &input.0[..8], &mut [])?;
+    Ok(())
+}
+
+/// Accept a connection, getting its socket and the address of the peer.
+pub fn accept<D: Device + ?Sized>(device: &D, fd: i32) -> Result<(i32, SocketAddrV4), NetError> {
+    let input = words([fd as u32]);
+    let mut address = Aligned([0; 32]);
+    address.0[0] = SOCKADDR_SIZE as u8;
+    address.0[1] = AF_INET;
+    let accepted = device.ioctl(IOCTL_ACCEPT, &input.0[..4], &mut address.0[..SOCKADDR_SIZE])?;
+    let address = decode_address(&address.0).ok_or(NetError::Invalid)?;
+    Ok((accepted, address))
+}
+
+/// Stop reading if ``how`` is 0, writing if 1, or both if 2.
+pub fn shutdown<D: Device + ?Sized>(device: &D, fd: i32, how: u32) -> Result<(), NetError> {
+    let input = words([fd as u32, how]);
+    device.ioctl(IOCTL_SHUTDOWN, &input.0[..8], &mut [])?;
+    Ok(())
+}
+
+fn name<D: Device + ?Sized>(device: &D, ioctl: u32, fd: i32) -> Result<SocketAddrV4, NetError> {
+    let input = words([fd as u32]);
+    let mut address = Aligned([0; 32]);
+    address.0[0] = SOCKADDR_SIZE as u8;
+    device.ioctl(ioctl, &input.0[..4], &mut address.0[..SOCKADDR_SIZE])?;
+    decode_address(&address.0).ok_or(NetError::Invalid)
+}
+
+/// Get the address a socket is bound to.
+pub fn local_address<D: Device + ?Sized>(device: &D, fd: i32) -> Result<SocketAddrV4, NetError> {
+    name(device, IOCTL_GET_SOCK_NAME, fd)
+}
+
+/// Get the address a socket is connected to.
+pub fn peer_address<D: Device + ?Sized>(device: &D, fd: i32) -> Result<SocketAddrV4, NetError> {
+    name(device, IOCTL_GET_PEER_NAME, fd)
+}
+
+/// Make the requests on a socket fail with [`NetError::WouldBlock`] instead of waiting.
+pub fn set_nonblocking<D: Device + ?Sized>(
+    device: &D,
+    fd: i32,
+    nonblocking: bool,
+) -> Result<(), NetError> {
+    let input = words([fd as u32, F_GETFL, 0]);
+    let flags = device.ioctl(IOCTL_FCNTL, &input.0[..12], &mut [])? as u32;
+    let flags = if nonblocking {
+        flags | O_NONBLOCK
+    } else {
+        flags & !O_NONBLOCK
+    };
+    let input = words([fd as u32, F_SETFL, flags]);
+    device.ioctl(IOCTL_FCNTL, &input.0[..12], &mut [])?;
+    Ok(())
+}
+
+/// Send ``data``, to ``to`` if the socket isn’t connected, returning how much got sent.
+pub fn send_to<D: Device + ?Sized>(
+    device: &D,
+    fd: i32,
+    data: &[u8],
+    flags: u32,
+    to: Option<SocketAddrV4>,
+) -> Result<usize, NetError> {
+    let mut parameters = Aligned([0; 64]);
+    parameters.0[0..4].copy_from_slice(&(fd as u32).to_be_bytes());
+    parameters.0[4..8].copy_from_slice(&flags.to_be_bytes());
+    if let Some(to) = to {
+        parameters.0[8..12].copy_from_slice(&1u32.to_be_bytes());
+        parameters.0[12..12 + SOCKADDR_SIZE].copy_from_slice(&encode_address(to));
+    }
+    let sent = device.ioctlv(
+        IOCTLV_SEND_TO,
+        &[data, &parameters.0[..12 + SOCKADDR_ROOM]],
+        &mut [],
+    )?;
+    Ok(sent as usize)
+}
+
+/// Receive into ``buffer``, returning how much got received and from where if the socket
+/// isn’t connected.
+pub fn recv_from<D: Device + ?Sized>(
+    device: &D,
+    fd: i32,
+    buffer: &mut [u8],
+    flags: u32,
+) -> Result<(usize, Option<SocketAddrV4>), NetError> {
+    let parameters = words([fd as u32, flags]);
+    let mut address = Aligned([0; 32]);
+    address.0[0] = SOCKADDR_SIZE as u8;
+    let received = if ios::is_aligned(buffer) {
+        device.ioctlv(
+            IOCTLV_RECV_FROM,
+            &[&parameters.0[..8]],
+            &mut [buffer, &mut address.0[..SOCKADDR_SIZE]],
+        )? as usize
+    } else {
+        let mut bounce = device.buffer(buffer.len());
+        let received = device.ioctlv(
+            IOCTLV_RECV_FROM,
+            &[&parameters.0[..8]],
+            &mut [&mut bounce, &mut address.0[..SOCKADDR_SIZE]],
+        )? as usize;
+        let received = received.min(buffer.len());
+        buffer[..received].copy_from_slice(&bounce[..received]);
+        received
+    };
+    Ok((received, decode_address(&address.0)))
+}
+
+/// Wait up to ``timeout`` milliseconds, or forever if negative, for something to happen to
+/// any of ``sockets``, returning how many got events.
+pub fn poll<D: Device + ?Sized>(
+    device: &D,
+    sockets: &mut [PollFd],
+    timeout: i64,
+) -> Result<usize, NetError> {
+    let mut input = Aligned([0; 32]);
+    input.0[..8].copy_from_slice(&timeout.to_be_bytes());
+    // IOS reads the sockets from the output, and writes what happened back.
+    let mut entries = device.buffer(sockets.len() * POLL_FD_SIZE);
+    for (entry, socket) in entries.chunks_exact_mut(POLL_FD_SIZE).zip(sockets.iter()) {
+        entry[0..4].copy_from_slice(&socket.fd.to_be_bytes());
+        entry[4..8].copy_from_slice(&socket.events.to_be_bytes());
+    }
+    let ready = device.ioctl(IOCTL_POLL, &input.0[..8], &mut entries)?;
+    for (entry, socket) in entries.chunks_exact(POLL_FD_SIZE).zip(sockets.iter_mut()) {
+        socket.revents = u32::from_be_bytes(entry[8..12].try_into().unwrap());
+    }
+    Ok(ready as usize)
+}
+
+/// Resolve ``host`` into its IPv4 addresses, with ``port`` as their port.
+pub fn get_addr_info<D: Device + ?Sized>(
+    device: &D,
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddrV4>, NetError> {
+    let mut node = Vec::with_capacity(host.len() + 1);
+    node.extend_from_slice(host.as_bytes());
+    node.push(0);
+    let mut hints = Aligned([0; 32]);
+    hints.0[4..8].copy_from_slice(&(AF_INET as u32).to_be_bytes());
+
+    let mut result = device.buffer(ADDR_INFO_SIZE);
+    let status = device.ioctlv(
+        IOCTLV_GET_ADDR_INFO,
+        &[&node, &[], &hints.0],
+        &mut [&mut result],
+    )?;
+    if status != 0 {
+        return Err(NetError::NotFound);
+    }
+    let addresses = parse_addr_info(&result, device.address(&result), port);
+    if addresses.is_empty() {
+        return Err(NetError::NotFound);
+    }
+    Ok(addresses)
+}
+
+/// Parse the result of [`get_addr_info`], whose pointers are relative to ``base``.
+fn parse_addr_info(result: &[u8], base: u32, port: u16) -> Vec<SocketAddrV4> {
+    let word = |offset: usize| u32::from_be_bytes(result[offset..offset + 4].try_into().unwrap());
+    let mut addresses = Vec::new();
+    for entry in 0..ADDR_INFO_ENTRIES {
+        let entry = entry * ADDR_INFO_ENTRY_SIZE;
+        let address = word(entry + 0x18).wrapping_sub(base) as usize;
+        let decoded = result
+            .get(address..address.saturating_add(SOCKADDR_SIZE))
+            .and_then(decode_address);
+        if let Some(decoded) = decoded {
+            addresses.push(SocketAddrV4::new(*decoded.ip(), port));
+        }
+        // The entries are a linked list, which IOS lays out in order.
+        if word(entry + 0x1c) == 0 {
+            break;
+        }
+    }
+    addresses
+}
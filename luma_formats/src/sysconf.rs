@@ -0,0 +1,97 @@
+//! ``sysconf`` module of ``luma_formats``.
+//!
+//! Contains a parser for SYSCONF, the file at ``/shared2/sys/SYSCONF`` on the NAND holding the
+//! system settings as named items.  Nothing in here does any I/O.
+
+/// Where SYSCONF is on the NAND.
+pub const PATH: &str = "/shared2/sys/SYSCONF";
+
+/// The types of items, in the top three bits of their first byte.
+const TYPE_BIG_ARRAY: u8 = 1;
+const TYPE_SMALL_ARRAY: u8 = 2;
+const TYPE_BYTE: u8 = 3;
+const TYPE_SHORT: u8 = 4;
+const TYPE_LONG: u8 = 5;
+const TYPE_LONG_LONG: u8 = 6;
+const TYPE_BOOL: u8 = 7;
+
+/// Errors which can be encountered while parsing SYSCONF.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SysConfError {
+    /// The file doesn’t start with ``SCv0``.
+    BadMagic,
+    /// The item table goes past the end of the file.
+    OutOfBounds,
+}
+
+/// The SYSCONF file, borrowing its content.
+#[derive(Clone, Copy, Debug)]
+pub struct SysConf<'a> {
+    data: &'a [u8],
+    count: usize,
+}
+
+impl<'a> SysConf<'a> {
+    /// Parse the header of SYSCONF, the items get found lazily.
+    pub fn parse(data: &'a [u8]) -> Result<SysConf<'a>, SysConfError> {
+        if data.get(..4) != Some(b"SCv0") {
+            return Err(SysConfError::BadMagic);
+        }
+        let count = data.get(4..6).ok_or(SysConfError::OutOfBounds)?;
+        let count = u16::from_be_bytes([count[0], count[1]]) as usize;
+        if data.len() < 6 + count * 2 {
+            return Err(SysConfError::OutOfBounds);
+        }
+        Ok(SysConf { data, count })
+    }
+
+    /// Iterate over the names and values of every item, skipping the malformed ones.
+    pub fn items(&self) -> impl Iterator<Item = (&'a [u8], &'a [u8])> + 'a {
+        let data = self.data;
+        (0..self.count).filter_map(move |index| {
+            let offset = u16::from_be_bytes([data[6 + index * 2], data[7 + index * 2]]) as usize;
+            item(data, offset)
+        })
+    }
+
+    /// Get the value of the item called ``name``, such as ``IPL.LNG`` or ``BT.DINF``.
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.items()
+            .find(|&(item, _)| item == name.as_bytes())
+            .map(|(_, value)| value)
+    }
+
+    /// Get a single byte item.
+    pub fn get_u8(&self, name: &str) -> Option<u8> {
+        self.get(name)?.first().copied()
+    }
+
+    /// Get a 32-bit item.
+    pub fn get_u32(&self, name: &str) -> Option<u32> {
+        let value = self.get(name)?.get(..4)?;
+        Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+    }
+}
+
+fn item(data: &[u8], offset: usize) -> Option<(&[u8], &[u8])> {
+    let header = *data.get(offset)?;
+    let name_length = (header & 0x1f) as usize + 1;
+    let name = data.get(offset + 1..offset + 1 + name_length)?;
+    let rest = offset + 1 + name_length;
+    let (start, length) = match header >> 5 {
+        TYPE_BIG_ARRAY => {
+            let length = data.get(rest..rest + 2)?;
+            (
+                rest + 2,
+                u16::from_be_bytes([length[0], length[1]]) as usize + 1,
+            )
+        }
+        TYPE_SMALL_ARRAY => (rest + 1, *data.get(rest)? as usize + 1),
+        TYPE_BYTE | TYPE_BOOL => (rest, 1),
+        TYPE_SHORT => (rest, 2),
+        TYPE_LONG => (rest, 4),
+        TYPE_LONG_LONG => (rest, 8),
+        _ => return None,
+    };
+    Some((name, data.get(start..start + length)?))
+}
@@ -0,0 +1,5 @@
+//! ``usb`` module of ``luma_formats``.
+//!
+//! Contains the commands of the USB device classes there are drivers for.
+
+pub mod scsi;
@@ -0,0 +1,325 @@
+//! ``scsi`` module of ``luma_formats::usb``.
+//!
+//! Contains the encoding of the SCSI commands sent to mass storage devices, wrapped in the
+//! command blocks of the bulk-only transport, and the decoding of the status and data they send
+//! back.  Nothing in here does any I/O.
+
+pub const TEST_UNIT_READY: u8 = 0x00;
+pub const REQUEST_SENSE: u8 = 0x03;
+pub const INQUIRY: u8 = 0x12;
+pub const MODE_SENSE_6: u8 = 0x1a;
+pub const READ_CAPACITY_10: u8 = 0x25;
+pub const READ_10: u8 = 0x28;
+pub const WRITE_10: u8 = 0x2a;
+
+pub const SENSE_NO_SENSE: u8 = 0x0;
+pub const SENSE_NOT_READY: u8 = 0x2;
+pub const SENSE_MEDIUM_ERROR: u8 = 0x3;
+pub const SENSE_ILLEGAL_REQUEST: u8 = 0x5;
+pub const SENSE_UNIT_ATTENTION: u8 = 0x6;
+pub const SENSE_DATA_PROTECT: u8 = 0x7;
+
+/// Peripheral device types of the devices which can be read in blocks.
+pub const TYPE_DIRECT_ACCESS: u8 = 0x00;
+pub const TYPE_SIMPLIFIED_DIRECT_ACCESS: u8 = 0x0e;
+
+/// Sizes of the data asked for by the commands below.
+pub const INQUIRY_SIZE: usize = 36;
+pub const SENSE_SIZE: usize = 18;
+pub const CAPACITY_SIZE: usize = 8;
+pub const MODE_SENSE_SIZE: usize = 4;
+
+/// Size of a command block wrapper, sent before each command.
+pub const CBW_SIZE: usize = 31;
+
+/// Size of a command status wrapper, received after each command.
+pub const CSW_SIZE: usize = 13;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+
+/// Direction flag of a command block wrapper, set when data goes to the host.
+const CBW_DATA_IN: u8 = 0x80;
+
+/// Which way the data of a command goes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// The command has no data.
+    None,
+    /// From the device to the host.
+    In,
+    /// From the host to the device.
+    Out,
+}
+
+/// A SCSI command, along with the data it transfers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Command {
+    cdb: [u8; 16],
+    length: u8,
+    transfer_length: u32,
+    direction: Direction,
+}
+
+impl Command {
+    fn new(cdb: &[u8], transfer_length: u32, direction: Direction) -> Command {
+        let mut command = Command {
+            cdb: [0; 16],
+            length: cdb.len() as u8,
+            transfer_length,
+            direction,
+        };
+        command.cdb[..cdb.len()].copy_from_slice(cdb);
+        command
+    }
+
+    /// Ask whether the medium is ready to be accessed.
+    pub fn test_unit_ready() -> Command {
+        Command::new(&[TEST_UNIT_READY, 0, 0, 0, 0, 0], 0, Direction::None)
+    }
+
+    /// Ask why the previous command failed, see [`Sense`].
+    pub fn request_sense() -> Command {
+        let cdb = [REQUEST_SENSE, 0, 0, 0, SENSE_SIZE as u8, 0];
+        Command::new(&cdb, SENSE_SIZE as u32, Direction::In)
+    }
+
+    /// Ask what the device is, see [`Inquiry`].
+    pub fn inquiry() -> Command {
+        let cdb = [INQUIRY, 0, 0, 0, INQUIRY_SIZE as u8, 0];
+        Command::new(&cdb, INQUIRY_SIZE as u32, Direction::In)
+    }
+
+    /// Ask for the size of the medium, see [`Capacity`].
+    pub fn read_capacity() -> Command {
+        let cdb = [READ_CAPACITY_10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        Command::new(&cdb, CAPACITY_SIZE as u32, Direction::In)
+    }
+
+    /// Ask for the header of the mode parameters, see [`is_write_protected`].
+    pub fn mode_sense() -> Command {
+        // All pages, of which only the header gets read.
+        let cdb = [MODE_SENSE_6, 0, 0x3f, 0, MODE_SENSE_SIZE as u8, 0];
+        Command::new(&cdb, MODE_SENSE_SIZE as u32, Direction::In)
+    }
+
+    fn transfer(
+        opcode: u8,
+        block: u32,
+        count: u16,
+        block_size: u32,
+        direction: Direction,
+    ) -> Command {
+        let mut cdb = [0; 10];
+        cdb[0] = opcode;
+        cdb[2..6].copy_from_slice(&block.to_be_bytes());
+        cdb[7..9].copy_from_slice(&count.to_be_bytes());
+        Command::new(&cdb, count as u32 * block_size, direction)
+    }
+
+    /// Read ``count`` blocks of ``block_size`` bytes, starting at ``block``.
+    pub fn read(block: u32, count: u16, block_size: u32) -> Command {
+        Command::transfer(READ_10, block, count, block_size, Direction::In)
+    }
+
+    /// Write ``count`` blocks of ``block_size`` bytes, starting at ``block``.
+    pub fn write(block: u32, count: u16, block_size: u32) -> Command {
+        Command::transfer(WRITE_10, block, count, block_size, Direction::Out)
+    }
+
+    /// Get the command descriptor block, as sent to the device.
+    pub fn cdb(&self) -> &[u8] {
+        &self.cdb[..self.length as usize]
+    }
+
+    /// Get how many bytes the command transfers.
+    pub fn transfer_length(&self) -> u32 {
+        self.transfer_length
+    }
+
+    /// Get which way the data of the command goes.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Wrap the command into the block sent to logical unit ``lun`` to start it, which the
+    /// status will be matched against by ``tag``.
+    pub fn wrap(&self, tag: u32, lun: u8) -> [u8; CBW_SIZE] {
+        let mut block = [0; CBW_SIZE];
+        block[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        block[4..8].copy_from_slice(&tag.to_le_bytes());
+        block[8..12].copy_from_slice(&self.transfer_length.to_le_bytes());
+        block[12] = match self.direction {
+            Direction::In => CBW_DATA_IN,
+            _ => 0,
+        };
+        block[13] = lun;
+        block[14] = self.length;
+        block[15..15 + self.length as usize].copy_from_slice(self.cdb());
+        block
+    }
+}
+
+/// The outcome of a command, as reported in its status wrapper.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The command succeeded.
+    Passed,
+    /// The command failed, [`Command::request_sense`] tells why.
+    Failed,
+    /// The device got confused about the protocol, and has to be reset.
+    PhaseError,
+}
+
+/// The status wrapper sent back by the device once a command completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommandStatus {
+    /// The tag of the command block this is about.
+    pub tag: u32,
+    /// How many bytes of the data expected didn’t get transferred.
+    pub residue: u32,
+    pub status: Status,
+}
+
+impl CommandStatus {
+    /// Parse a status wrapper, which has to be exactly [`CSW_SIZE`] bytes with the right
+    /// signature to be valid.
+    pub fn parse(data: &[u8]) -> Option<CommandStatus> {
+        if data.len() != CSW_SIZE {
+            return None;
+        }
+        let word = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        if word(0) != CSW_SIGNATURE {
+            return None;
+        }
+        let status = match data[12] {
+            0 => Status::Passed,
+            1 => Status::Failed,
+            2 => Status::PhaseError,
+            _ => return None,
+        };
+        Some(CommandStatus {
+            tag: word(4),
+            residue: word(8),
+            status,
+        })
+    }
+}
+
+/// What a device says about itself in answer to [`Command::inquiry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Inquiry {
+    /// Whether there is an actual device behind this logical unit.
+    pub connected: bool,
+    /// The kind of device, [`TYPE_DIRECT_ACCESS`] for disks.
+    pub device_type: u8,
+    /// Whether the medium can be taken out, as with card readers.
+    pub removable: bool,
+    pub vendor: [u8; 8],
+    pub product: [u8; 16],
+    pub revision: [u8; 4],
+}
+
+impl Inquiry {
+    /// Parse inquiry data, of which only the standard first 36 bytes are needed.
+    pub fn parse(data: &[u8]) -> Option<Inquiry> {
+        if data.len() < INQUIRY_SIZE {
+            return None;
+        }
+        Some(Inquiry {
+            connected: data[0] >> 5 == 0,
+            device_type: data[0] & 0x1f,
+            removable: data[1] & 0x80 != 0,
+            vendor: data[8..16].try_into().unwrap(),
+            product: data[16..32].try_into().unwrap(),
+            revision: data[32..36].try_into().unwrap(),
+        })
+    }
+
+    /// Whether the device can be read and written in blocks.
+    pub fn is_block_device(&self) -> bool {
+        self.connected
+            && matches!(
+                self.device_type,
+                TYPE_DIRECT_ACCESS | TYPE_SIMPLIFIED_DIRECT_ACCESS
+            )
+    }
+
+    /// Get the name of the vendor, without its padding.
+    pub fn vendor(&self) -> &str {
+        text(&self.vendor)
+    }
+
+    /// Get the name of the product, without its padding.
+    pub fn product(&self) -> &str {
+        text(&self.product)
+    }
+}
+
+/// Get a space padded ASCII field as a string, or an empty one if it isn’t ASCII.
+fn text(field: &[u8]) -> &str {
+    match core::str::from_utf8(field) {
+        Ok(text) if text.is_ascii() => text.trim_end_matches([' ', '\0']),
+        _ => "",
+    }
+}
+
+/// The size of the medium, in answer to [`Command::read_capacity`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capacity {
+    /// The address of the last block, 0xffffffff if there are too many for READ(10).
+    pub last_block: u32,
+    pub block_size: u32,
+}
+
+impl Capacity {
+    pub fn parse(data: &[u8]) -> Option<Capacity> {
+        if data.len() < CAPACITY_SIZE {
+            return None;
+        }
+        Some(Capacity {
+            last_block: u32::from_be_bytes(data[0..4].try_into().unwrap()),
+            block_size: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+        })
+    }
+
+    /// Get the amount of blocks on the medium.
+    pub fn blocks(&self) -> u64 {
+        self.last_block as u64 + 1
+    }
+}
+
+/// Why a command failed, in answer to [`Command::request_sense`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sense {
+    /// The broad category of the error, such as [`SENSE_NOT_READY`].
+    pub key: u8,
+    /// The additional sense code and its qualifier, detailing the error.
+    pub code: u8,
+    pub qualifier: u8,
+}
+
+impl Sense {
+    /// Parse sense data, either in the fixed or the descriptor format.
+    pub fn parse(data: &[u8]) -> Option<Sense> {
+        match data.first()? & 0x7f {
+            0x70 | 0x71 if data.len() >= 14 => Some(Sense {
+                key: data[2] & 0x0f,
+                code: data[12],
+                qualifier: data[13],
+            }),
+            0x72 | 0x73 if data.len() >= 4 => Some(Sense {
+                key: data[1] & 0x0f,
+                code: data[2],
+                qualifier: data[3],
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Whether the mode parameter header returned by [`Command::mode_sense`] says the medium is
+/// write protected.
+pub fn is_write_protected(header: &[u8]) -> Option<bool> {
+    Some(header.get(2)? & 0x80 != 0)
+}
@@ -0,0 +1,5 @@
+//! ``wpad`` module of ``luma_formats``.
+//!
+//! Contains the HID reports exchanged with a Wii Remote.
+
+pub mod report;
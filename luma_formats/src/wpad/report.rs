@@ -0,0 +1,421 @@
+//! ``report`` module of ``luma_formats::wpad``.
+//!
+//! Contains the decoding of the HID input reports sent by a Wii Remote and its extensions, and
+//! the encoding of the output reports sent to it.  Nothing in here does any I/O.
+
+/// Prefix of input reports on the HID interrupt channel.
+pub const INPUT: u8 = 0xa1;
+
+/// Prefix of output reports on the HID interrupt channel.
+pub const OUTPUT: u8 = 0xa2;
+
+pub const REPORT_LEDS: u8 = 0x11;
+pub const REPORT_MODE: u8 = 0x12;
+pub const REPORT_IR_CLOCK: u8 = 0x13;
+pub const REPORT_STATUS_REQUEST: u8 = 0x15;
+pub const REPORT_WRITE: u8 = 0x16;
+pub const REPORT_READ: u8 = 0x17;
+pub const REPORT_IR_ENABLE: u8 = 0x1a;
+
+pub const REPORT_STATUS: u8 = 0x20;
+pub const REPORT_READ_DATA: u8 = 0x21;
+pub const REPORT_ACKNOWLEDGE: u8 = 0x22;
+
+/// Buttons only.
+pub const MODE_BUTTONS: u8 = 0x30;
+/// Buttons and accelerometer.
+pub const MODE_ACCEL: u8 = 0x31;
+/// Buttons, accelerometer and 12 bytes of extended IR.
+pub const MODE_ACCEL_IR: u8 = 0x33;
+/// Buttons, accelerometer and 16 bytes of extension.
+pub const MODE_ACCEL_EXTENSION: u8 = 0x35;
+/// Buttons, accelerometer, 10 bytes of basic IR and 6 bytes of extension.
+pub const MODE_ACCEL_IR_EXTENSION: u8 = 0x37;
+
+/// IR camera modes, as written to its mode register.
+pub const IR_BASIC: u8 = 1;
+pub const IR_EXTENDED: u8 = 3;
+
+/// Flags of the status report.
+pub const STATUS_BATTERY_LOW: u8 = 0x01;
+pub const STATUS_EXTENSION: u8 = 0x02;
+pub const STATUS_SPEAKER: u8 = 0x04;
+pub const STATUS_IR: u8 = 0x08;
+
+bitflags::bitflags! {
+    /// The buttons of a Wii Remote.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct Buttons: u16 {
+        const TWO = 0x0001;
+        const ONE = 0x0002;
+        const B = 0x0004;
+        const A = 0x0008;
+        const MINUS = 0x0010;
+        const HOME = 0x0080;
+        const LEFT = 0x0100;
+        const RIGHT = 0x0200;
+        const DOWN = 0x0400;
+        const UP = 0x0800;
+        const PLUS = 0x1000;
+    }
+}
+
+bitflags::bitflags! {
+    /// The buttons of a Classic Controller.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct ClassicButtons: u16 {
+        const UP = 0x0001;
+        const LEFT = 0x0002;
+        const ZR = 0x0004;
+        const X = 0x0008;
+        const A = 0x0010;
+        const Y = 0x0020;
+        const B = 0x0040;
+        const ZL = 0x0080;
+        const R = 0x0200;
+        const PLUS = 0x0400;
+        const HOME = 0x0800;
+        const MINUS = 0x1000;
+        const L = 0x2000;
+        const DOWN = 0x4000;
+        const RIGHT = 0x8000;
+    }
+}
+
+/// The state of an accelerometer, as 10-bit values with 512 for no acceleration.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Accel {
+    pub x: u16,
+    pub y: u16,
+    pub z: u16,
+}
+
+/// A light source seen by the IR camera, on a 1024×768 grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrDot {
+    pub x: u16,
+    pub y: u16,
+    /// Rough size of the dot, only reported in extended mode.
+    pub size: Option<u8>,
+}
+
+/// The state of a Nunchuk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Nunchuk {
+    /// Stick position, around 128 when centered.
+    pub stick: [u8; 2],
+    pub accel: Accel,
+    pub c: bool,
+    pub z: bool,
+}
+
+/// The state of a Classic Controller.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Classic {
+    /// Left stick position, on 6 bits, around 32 when centered.
+    pub left_stick: [u8; 2],
+    /// Right stick position, on 5 bits, around 16 when centered.
+    pub right_stick: [u8; 2],
+    /// Analog triggers, on 5 bits.
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub buttons: ClassicButtons,
+}
+
+/// The kinds of extension which can be plugged into a Wii Remote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtensionKind {
+    Nunchuk,
+    Classic,
+    /// Any other extension, with its identifier.
+    Unknown([u8; 6]),
+}
+
+impl ExtensionKind {
+    /// Identify an extension from the six bytes at its register 0xfa.
+    pub fn identify(id: [u8; 6]) -> ExtensionKind {
+        match id[2..] {
+            [0xa4, 0x20, 0x00, 0x00] => ExtensionKind::Nunchuk,
+            [0xa4, 0x20, 0x01, 0x01] => ExtensionKind::Classic,
+            _ => ExtensionKind::Unknown(id),
+        }
+    }
+
+    /// Decode the bytes this extension adds to data reports, once it got initialized without
+    /// encryption.
+    pub fn parse(self, data: &[u8]) -> Option<Extension> {
+        let data = data.get(..6)?;
+        Some(match self {
+            ExtensionKind::Nunchuk => Extension::Nunchuk(parse_nunchuk(data)),
+            ExtensionKind::Classic => Extension::Classic(parse_classic(data)),
+            ExtensionKind::Unknown(_) => return None,
+        })
+    }
+}
+
+/// The decoded state of an extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Extension {
+    Nunchuk(Nunchuk),
+    Classic(Classic),
+}
+
+fn parse_nunchuk(data: &[u8]) -> Nunchuk {
+    let low = data[5];
+    Nunchuk {
+        stick: [data[0], data[1]],
+        accel: Accel {
+            x: (data[2] as u16) << 2 | (low >> 2 & 3) as u16,
+            y: (data[3] as u16) << 2 | (low >> 4 & 3) as u16,
+            z: (data[4] as u16) << 2 | (low >> 6 & 3) as u16,
+        },
+        // Buttons are active low.
+        z: low & 0x01 == 0,
+        c: low & 0x02 == 0,
+    }
+}
+
+fn parse_classic(data: &[u8]) -> Classic {
+    let right_x = (data[0] >> 6) << 3 | (data[1] >> 6) << 1 | data[2] >> 7;
+    Classic {
+        left_stick: [data[0] & 0x3f, data[1] & 0x3f],
+        right_stick: [right_x, data[2] & 0x1f],
+        left_trigger: (data[2] >> 5 & 3) << 3 | data[3] >> 5,
+        right_trigger: data[3] & 0x1f,
+        // Buttons are active low.
+        buttons: ClassicButtons::from_bits_truncate(!u16::from_be_bytes([data[4], data[5]])),
+    }
+}
+
+/// Decode the two bytes of buttons starting most reports.
+pub fn parse_buttons(data: &[u8]) -> Buttons {
+    Buttons::from_bits_truncate(u16::from_be_bytes([data[0], data[1]]))
+}
+
+/// Decode the accelerometer, whose lowest bits are hidden in the buttons.
+pub fn parse_accel(buttons: &[u8], accel: &[u8]) -> Accel {
+    Accel {
+        x: (accel[0] as u16) << 2 | (buttons[0] >> 5 & 3) as u16,
+        y: (accel[1] as u16) << 2 | ((buttons[1] >> 5 & 1) as u16) << 1,
+        z: (accel[2] as u16) << 2 | ((buttons[1] >> 6 & 1) as u16) << 1,
+    }
+}
+
+/// Decode the IR camera data, either 10 bytes in basic mode or 12 in extended mode.
+pub fn parse_ir(data: &[u8]) -> [Option<IrDot>; 4] {
+    let mut dots = [None; 4];
+    if data.len() >= 12 {
+        for (dot, bytes) in dots.iter_mut().zip(data.chunks_exact(3)) {
+            if bytes == [0xff; 3] {
+                continue;
+            }
+            *dot = Some(IrDot {
+                x: bytes[0] as u16 | ((bytes[2] >> 4 & 3) as u16) << 8,
+                y: bytes[1] as u16 | ((bytes[2] >> 6 & 3) as u16) << 8,
+                size: Some(bytes[2] & 0x0f),
+            });
+        }
+    } else if data.len() >= 10 {
+        for (pair, bytes) in dots.chunks_exact_mut(2).zip(data.chunks_exact(5)) {
+            let high = bytes[2];
+            let first = IrDot {
+                x: bytes[0] as u16 | ((high >> 4 & 3) as u16) << 8,
+                y: bytes[1] as u16 | ((high >> 6 & 3) as u16) << 8,
+                size: None,
+            };
+            let second = IrDot {
+                x: bytes[3] as u16 | ((high & 3) as u16) << 8,
+                y: bytes[4] as u16 | ((high >> 2 & 3) as u16) << 8,
+                size: None,
+            };
+            // Missing dots are reported as all ones.
+            pair[0] = (first.x != 0x3ff || first.y != 0x3ff).then_some(first);
+            pair[1] = (second.x != 0x3ff || second.y != 0x3ff).then_some(second);
+        }
+    }
+    dots
+}
+
+/// An input report, without its prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Report<'a> {
+    Status {
+        buttons: Buttons,
+        flags: u8,
+        battery: u8,
+    },
+    ReadData {
+        buttons: Buttons,
+        error: u8,
+        /// Address of the first byte, only its lowest 16 bits.
+        address: u16,
+        data: &'a [u8],
+    },
+    Acknowledge {
+        buttons: Buttons,
+        report: u8,
+        error: u8,
+    },
+    Data {
+        buttons: Buttons,
+        accel: Option<Accel>,
+        ir: &'a [u8],
+        extension: &'a [u8],
+    },
+    /// Any other report.
+    Other(u8),
+}
+
+impl<'a> Report<'a> {
+    /// Decode an input report, prefix included, returning ``None`` if it is truncated.
+    pub fn parse(report: &'a [u8]) -> Option<Report<'a>> {
+        let (&INPUT, report) = report.split_first()? else {
+            return None;
+        };
+        let (&id, data) = report.split_first()?;
+        if id == 0x3d {
+            // The only report without buttons, which nothing here asks for.
+            return Some(Report::Other(id));
+        }
+        let buttons_data = data.get(..2);
+        let buttons = buttons_data.map(parse_buttons).unwrap_or_default();
+        let need = |length: usize| data.get(..length);
+
+        Some(match id {
+            REPORT_STATUS => {
+                let data = need(6)?;
+                Report::Status {
+                    buttons,
+                    flags: data[2],
+                    battery: data[5],
+                }
+            }
+            REPORT_READ_DATA => {
+                let data = need(21)?;
+                let size = (data[2] >> 4) as usize + 1;
+                Report::ReadData {
+                    buttons,
+                    error: data[2] & 0x0f,
+                    address: u16::from_be_bytes([data[3], data[4]]),
+                    data: &data[5..5 + size],
+                }
+            }
+            REPORT_ACKNOWLEDGE => {
+                let data = need(4)?;
+                Report::Acknowledge {
+                    buttons,
+                    report: data[2],
+                    error: data[3],
+                }
+            }
+            0x30..=0x37 => {
+                // Sizes of the accelerometer, IR and extension parts of each mode.
+                let (accel, ir, extension) = match id {
+                    0x30 => (0, 0, 0),
+                    0x31 => (3, 0, 0),
+                    0x32 => (0, 0, 8),
+                    0x33 => (3, 12, 0),
+                    0x34 => (0, 0, 19),
+                    0x35 => (3, 0, 16),
+                    0x36 => (0, 10, 9),
+                    _ => (3, 10, 6),
+                };
+                let data = need(2 + accel + ir + extension)?;
+                let rest = &data[2 + accel..];
+                Report::Data {
+                    buttons,
+                    accel: (accel != 0).then(|| parse_accel(data, &data[2..])),
+                    ir: &rest[..ir],
+                    extension: &rest[ir..],
+                }
+            }
+            id => Report::Other(id),
+        })
+    }
+}
+
+/// Encode an output report, the lowest bit of its first byte always being the rumble.
+fn output<const N: usize>(id: u8, data: [u8; N], rumble: bool) -> [u8; N] {
+    let mut report = data;
+    report[0] = OUTPUT;
+    report[1] = id;
+    report[2] |= rumble as u8;
+    report
+}
+
+/// Light up the four LEDs according to the lowest four bits of ``leds``.
+pub fn set_leds(leds: u8, rumble: bool) -> [u8; 3] {
+    output(REPORT_LEDS, [0, 0, (leds & 0xf) << 4], rumble)
+}
+
+/// Ask for data reports of ``mode``, sent on every change or continuously.
+pub fn set_mode(mode: u8, continuous: bool, rumble: bool) -> [u8; 4] {
+    output(
+        REPORT_MODE,
+        [0, 0, if continuous { 0x04 } else { 0 }, mode],
+        rumble,
+    )
+}
+
+/// Ask for a status report.
+pub fn request_status(rumble: bool) -> [u8; 3] {
+    output(REPORT_STATUS_REQUEST, [0; 3], rumble)
+}
+
+/// Start or stop the IR camera, which takes two reports.
+pub fn set_ir(enabled: bool, rumble: bool) -> [[u8; 3]; 2] {
+    let flag = if enabled { 0x04 } else { 0 };
+    [
+        output(REPORT_IR_CLOCK, [0, 0, flag], rumble),
+        output(REPORT_IR_ENABLE, [0, 0, flag], rumble),
+    ]
+}
+
+/// Write at most 16 bytes to the registers of the remote or its extension.
+///
+/// # Panics:
+/// This function will panic if ``data`` is longer than 16 bytes.
+pub fn write_registers(address: u32, data: &[u8], rumble: bool) -> [u8; 23] {
+    let mut report = [0; 23];
+    report[2] = 0x04;
+    report[3..6].copy_from_slice(&address.to_be_bytes()[1..]);
+    report[6] = data.len() as u8;
+    report[7..7 + data.len()].copy_from_slice(data);
+    output(REPORT_WRITE, report, rumble)
+}
+
+/// Read ``size`` bytes from the registers of the remote or its extension, which come back in
+/// [`Report::ReadData`] reports of at most 16 bytes.
+pub fn read_registers(address: u32, size: u16, rumble: bool) -> [u8; 8] {
+    let mut report = [0; 8];
+    report[2] = 0x04;
+    report[3..6].copy_from_slice(&address.to_be_bytes()[1..]);
+    report[6..8].copy_from_slice(&size.to_be_bytes());
+    output(REPORT_READ, report, rumble)
+}
+
+/// Writes initializing an extension without encryption.
+pub const EXTENSION_INIT: [(u32, u8); 2] = [(0xa4_00f0, 0x55), (0xa4_00fb, 0x00)];
+
+/// Register holding the identifier of the extension.
+pub const EXTENSION_ID: u32 = 0xa4_00fa;
+
+/// Register writes starting the IR camera in ``mode``, with a sensitivity suited to the sensor
+/// bar.
+pub fn ir_init(mode: u8) -> [(u32, &'static [u8]); 5] {
+    let mode: &'static [u8] = if mode == IR_EXTENDED {
+        &[IR_EXTENDED]
+    } else {
+        &[IR_BASIC]
+    };
+    [
+        (0xb0_0030, &[0x08]),
+        (
+            0xb0_0000,
+            &[0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x90, 0x00, 0x41],
+        ),
+        (0xb0_001a, &[0x40, 0x00]),
+        (0xb0_0033, mode),
+        (0xb0_0030, &[0x08]),
+    ]
+}
@@ -0,0 +1,203 @@
+//! Decoding of a DSP-ADPCM block with hand-picked coefficients, whose samples can be worked out
+//! by hand, and parsing of ``.dsp`` headers.
+
+use luma_formats::audio::adpcm::{
+    self, AdpcmError, AdpcmState, BYTES_PER_FRAME, DspHeader, SAMPLES_PER_FRAME,
+};
+
+/// Predictor 0 ignores the history, 1 adds the previous sample, and 2 extrapolates linearly
+/// from the two previous ones.
+const COEFS: [i16; 16] = [0, 0, 2048, 0, 4096, -2048, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// Four frames, one per line of [`SAMPLES`].
+const BLOCK: [u8; 32] = [
+    0x02, 0x17, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x10, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1f, //
+    0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0x1c, 0x77, 0x88, 0x80, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[rustfmt::skip]
+const SAMPLES: [i16; 56] = [
+    // Scaled by 4: 1, 7, -1 and -8.
+    4, 28, -4, -32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    // Adding 1 to the previous sample, then -1.
+    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 12,
+    // Carrying on the slope of the last two samples.
+    11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, -1, -2,
+    // Scaled by 4096 and added to the previous sample, saturating both ways.
+    28670, 32767, -1, -32768, -32768, -32768, -32768, -32768, -32768, -32768, -32768, -32768,
+    -32768, -32768,
+];
+
+#[test]
+fn decode() {
+    let mut state = AdpcmState::default();
+    let mut out = [0; 56];
+    assert_eq!(adpcm::decode(&BLOCK, &COEFS, &mut state, &mut out), 56);
+    assert_eq!(out, SAMPLES);
+    assert_eq!(
+        state,
+        AdpcmState {
+            hist1: -32768,
+            hist2: -32768
+        }
+    );
+
+    // Frame by frame, carrying the history over.
+    let mut state = AdpcmState::default();
+    for (frame, samples) in BLOCK
+        .chunks(BYTES_PER_FRAME)
+        .zip(SAMPLES.chunks(SAMPLES_PER_FRAME))
+    {
+        let mut out = [0; SAMPLES_PER_FRAME];
+        assert_eq!(
+            adpcm::decode(frame, &COEFS, &mut state, &mut out),
+            SAMPLES_PER_FRAME
+        );
+        assert_eq!(out, samples);
+    }
+
+    // Starting from some history.
+    let mut state = AdpcmState {
+        hist1: 100,
+        hist2: 90,
+    };
+    let mut out = [0; 3];
+    adpcm::decode(&[0x20, 0x00, 0x10], &COEFS, &mut state, &mut out);
+    assert_eq!(out, [110, 120, 131]);
+}
+
+#[test]
+fn decode_partial() {
+    // Stopping where the output ends.
+    let mut state = AdpcmState::default();
+    let mut out = [0; 20];
+    assert_eq!(adpcm::decode(&BLOCK, &COEFS, &mut state, &mut out), 20);
+    assert_eq!(out, SAMPLES[..20]);
+    assert_eq!(state, AdpcmState { hist1: 6, hist2: 5 });
+
+    // And where the data does, in the middle of a frame.
+    let mut state = AdpcmState::default();
+    let mut out = [0x55; 56];
+    assert_eq!(
+        adpcm::decode(&BLOCK[..11], &COEFS, &mut state, &mut out),
+        18
+    );
+    assert_eq!(out[..18], SAMPLES[..18]);
+    assert!(out[18..].iter().all(|&sample| sample == 0x55));
+
+    assert_eq!(adpcm::decode(&[], &COEFS, &mut state, &mut out), 0);
+}
+
+#[test]
+fn decode_at() {
+    let mut state = AdpcmState::default();
+    for (index, &sample) in SAMPLES.iter().enumerate() {
+        assert_eq!(
+            adpcm::decode_at(&BLOCK, index, &COEFS, &mut state),
+            Some(sample),
+            "{index}"
+        );
+    }
+    assert_eq!(adpcm::decode_at(&BLOCK, 56, &COEFS, &mut state), None);
+    assert_eq!(adpcm::decode_at(&BLOCK[..9], 15, &COEFS, &mut state), None);
+}
+
+#[test]
+fn addresses() {
+    // Each frame starts with the two nibbles of its header.
+    assert_eq!(adpcm::nibble_to_sample(0x02), 0);
+    assert_eq!(adpcm::nibble_to_sample(0x0f), 13);
+    assert_eq!(adpcm::nibble_to_sample(0x12), 14);
+    assert_eq!(adpcm::nibble_to_sample(0x37), 47);
+
+    assert_eq!(adpcm::data_size(0), 0);
+    assert_eq!(adpcm::data_size(1), 8);
+    assert_eq!(adpcm::data_size(14), 8);
+    assert_eq!(adpcm::data_size(15), 16);
+    assert_eq!(adpcm::data_size(56), 32);
+}
+
+/// Build a ``.dsp`` file holding [`BLOCK`], looping between the given nibble addresses if any.
+fn dsp_file(looping: Option<(u32, u32)>) -> Vec<u8> {
+    let mut file = vec![0; 0x60];
+    file[0x00..0x04].copy_from_slice(&56u32.to_be_bytes());
+    file[0x04..0x08].copy_from_slice(&66u32.to_be_bytes());
+    file[0x08..0x0c].copy_from_slice(&32000u32.to_be_bytes());
+    if let Some((start, end)) = looping {
+        file[0x0d] = 1;
+        file[0x10..0x14].copy_from_slice(&start.to_be_bytes());
+        file[0x14..0x18].copy_from_slice(&end.to_be_bytes());
+    }
+    for (i, coef) in COEFS.iter().enumerate() {
+        file[0x1c + i * 2..0x1e + i * 2].copy_from_slice(&coef.to_be_bytes());
+    }
+    file[0x40..0x42].copy_from_slice(&(-5i16).to_be_bytes());
+    file[0x42..0x44].copy_from_slice(&7i16.to_be_bytes());
+    file[0x46..0x48].copy_from_slice(&13i16.to_be_bytes());
+    file[0x48..0x4a].copy_from_slice(&12i16.to_be_bytes());
+    file.extend_from_slice(&BLOCK);
+    file
+}
+
+#[test]
+fn dsp_header() {
+    let file = dsp_file(Some((0x12, 0x37)));
+    let (header, data) = DspHeader::parse(&file).unwrap();
+    assert_eq!(
+        header,
+        DspHeader {
+            samples: 56,
+            sample_rate: 32000,
+            looping: Some((14, 47)),
+            coefs: COEFS,
+            gain: 0,
+            initial: AdpcmState {
+                hist1: -5,
+                hist2: 7
+            },
+            loop_state: AdpcmState {
+                hist1: 13,
+                hist2: 12
+            },
+        }
+    );
+    assert_eq!(data, BLOCK);
+
+    // Trailing data isn’t part of the samples.
+    let mut longer = dsp_file(None);
+    longer.extend_from_slice(&[0xff; 8]);
+    let (header, data) = DspHeader::parse(&longer).unwrap();
+    assert_eq!(header.looping, None);
+    assert_eq!(data, BLOCK);
+}
+
+#[test]
+fn dsp_header_errors() {
+    let file = dsp_file(None);
+    assert_eq!(
+        DspHeader::parse(&file[..0x5f]).unwrap_err(),
+        AdpcmError::OutOfBounds
+    );
+    assert_eq!(
+        DspHeader::parse(&file[..file.len() - 1]).unwrap_err(),
+        AdpcmError::OutOfBounds
+    );
+
+    let mut pcm = file.clone();
+    pcm[0x0f] = 2;
+    assert_eq!(
+        DspHeader::parse(&pcm).unwrap_err(),
+        AdpcmError::UnknownFormat(2)
+    );
+
+    // Backwards, or ending past the last sample.
+    for looping in [(0x37, 0x12), (0x02, 0x42)] {
+        assert_eq!(
+            DspHeader::parse(&dsp_file(Some(looping))).unwrap_err(),
+            AdpcmError::BadLoop,
+            "{looping:x?}"
+        );
+    }
+}
@@ -0,0 +1,324 @@
+//! Decoding of packets as captured between a Wii and a Wii Remote, from the HCI events of the
+//! controller up to the L2CAP signalling commands.
+
+use luma_formats::bluetooth::hci::{self, AclPacket, Address, Event};
+use luma_formats::bluetooth::l2cap::{self, CID_SIGNALLING, PSM_HID_CONTROL, Reassembly, Signal};
+
+/// The remote, 00:1f:32:8a:5c:e4, in the little-endian order of HCI.
+const REMOTE: Address = Address([0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00]);
+
+/// A status report of the remote, on the HID interrupt channel we gave it.
+const STATUS_FRAME: [u8; 12] = [
+    0x08, 0x00, 0x41, 0x00, 0xa1, 0x20, 0x00, 0x00, 0x02, 0x00, 0x00, 0xc8,
+];
+
+#[test]
+fn address() {
+    let address = Address::from_be_bytes([0x00, 0x1f, 0x32, 0x8a, 0x5c, 0xe4]);
+    assert_eq!(address, REMOTE);
+    assert_eq!(format!("{address}"), "00:1f:32:8a:5c:e4");
+}
+
+#[test]
+fn command_events() {
+    let event = Event::parse(&[0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00]).unwrap();
+    assert!(matches!(
+        event,
+        Event::CommandComplete {
+            credits: 1,
+            opcode: hci::RESET,
+            parameters: [0x00],
+        }
+    ));
+
+    let packet = [
+        0x0e, 0x0a, 0x01, 0x09, 0x10, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+    ];
+    let Some(Event::CommandComplete {
+        opcode: hci::READ_BD_ADDR,
+        parameters,
+        ..
+    }) = Event::parse(&packet)
+    else {
+        panic!("not a command complete event");
+    };
+    assert_eq!(parameters, [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+
+    let event = Event::parse(&[0x0f, 0x04, 0x00, 0x01, 0x01, 0x04]).unwrap();
+    assert!(matches!(
+        event,
+        Event::CommandStatus {
+            status: 0,
+            credits: 1,
+            opcode: hci::INQUIRY,
+        }
+    ));
+}
+
+#[test]
+fn inquiry_result() {
+    let packet = [
+        0x02, 0x0f, 0x01, 0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00, 0x01, 0x00, 0x00, 0x04, 0x25, 0x00,
+        0x3b, 0x45,
+    ];
+    let Some(Event::InquiryResult(responses)) = Event::parse(&packet) else {
+        panic!("not an inquiry result");
+    };
+    assert_eq!(responses.len(), 1);
+    let response = responses.iter().next().unwrap();
+    assert_eq!(response.address, REMOTE);
+    assert_eq!(response.page_scan_mode, 1);
+    assert_eq!(response.class, 0x00_2504);
+    assert_eq!(response.clock_offset, 0x453b);
+    assert!(responses.get(1).is_none());
+
+    // Announcing two devices, but only holding one.
+    let mut packet = packet;
+    packet[2] = 2;
+    assert!(Event::parse(&packet).is_none());
+}
+
+#[test]
+fn connection_events() {
+    let packet = [
+        0x04, 0x0a, 0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00, 0x04, 0x25, 0x00, 0x01,
+    ];
+    let event = Event::parse(&packet).unwrap();
+    assert!(matches!(
+        event,
+        Event::ConnectionRequest {
+            address: REMOTE,
+            class: 0x00_2504,
+            link_type: 1,
+        }
+    ));
+
+    let packet = [
+        0x03, 0x0b, 0x00, 0x0b, 0x20, 0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00, 0x01, 0x00,
+    ];
+    let event = Event::parse(&packet).unwrap();
+    // The flags above the handle get dropped.
+    assert!(matches!(
+        event,
+        Event::ConnectionComplete {
+            status: 0,
+            handle: 0x000b,
+            address: REMOTE,
+            link_type: 1,
+        }
+    ));
+
+    let event = Event::parse(&[0x05, 0x04, 0x00, 0x0b, 0x00, 0x13]).unwrap();
+    assert!(matches!(
+        event,
+        Event::DisconnectionComplete {
+            status: 0,
+            handle: 0x000b,
+            reason: hci::REASON_USER_ENDED,
+        }
+    ));
+
+    let packet = [0x13, 0x05, 0x01, 0x0b, 0x00, 0x02, 0x00];
+    let Some(Event::NumberOfCompletedPackets(records)) = Event::parse(&packet) else {
+        panic!("not a number of completed packets event");
+    };
+    assert_eq!(
+        records.completed_packets().collect::<Vec<_>>(),
+        [(0x000b, 2)]
+    );
+}
+
+#[test]
+fn pairing_events() {
+    let packet = [0x16, 0x06, 0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00];
+    let event = Event::parse(&packet).unwrap();
+    assert!(matches!(event, Event::PinCodeRequest { address: REMOTE }));
+
+    let key: [u8; 16] = core::array::from_fn(|i| i as u8 * 0x11);
+    let mut packet = vec![0x18, 0x17, 0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00];
+    packet.extend_from_slice(&key);
+    // The key type.
+    packet.push(0x00);
+    let event = Event::parse(&packet).unwrap();
+    assert!(matches!(
+        event,
+        Event::LinkKeyNotification { address: REMOTE, key: k } if k == key
+    ));
+
+    let mut packet = vec![0x15, 0x17, 0x01, 0xe4, 0x5c, 0x8a, 0x32, 0x1f, 0x00];
+    packet.extend_from_slice(&key);
+    let Some(Event::ReturnLinkKeys(records)) = Event::parse(&packet) else {
+        panic!("not a return link keys event");
+    };
+    assert_eq!(records.link_keys().collect::<Vec<_>>(), [(REMOTE, key)]);
+}
+
+#[test]
+fn other_and_truncated_events() {
+    // Encryption change, which nothing here handles.
+    let event = Event::parse(&[0x08, 0x04, 0x00, 0x0b, 0x00, 0x01]).unwrap();
+    assert!(matches!(
+        event,
+        Event::Other {
+            code: 0x08,
+            parameters: [0x00, 0x0b, 0x00, 0x01],
+        }
+    ));
+
+    assert!(Event::parse(&[]).is_none());
+    assert!(Event::parse(&[0x0e]).is_none());
+    // Shorter than its declared length.
+    assert!(Event::parse(&[0x0e, 0x04, 0x01, 0x03, 0x0c]).is_none());
+    // Too short for its code.
+    assert!(Event::parse(&[0x0f, 0x02, 0x00, 0x01]).is_none());
+    assert!(Event::parse(&[0x03, 0x03, 0x00, 0x0b, 0x00]).is_none());
+}
+
+#[test]
+fn acl_packets() {
+    let mut packet = vec![0x0b, 0x20, 0x0c, 0x00];
+    packet.extend_from_slice(&STATUS_FRAME);
+    let acl = AclPacket::parse(&packet).unwrap();
+    assert_eq!(
+        acl,
+        AclPacket {
+            handle: 0x000b,
+            start: true,
+            data: &STATUS_FRAME,
+        }
+    );
+
+    let acl = AclPacket::parse(&[0x0b, 0x10, 0x02, 0x00, 0xaa, 0xbb]).unwrap();
+    assert_eq!(acl.handle, 0x000b);
+    assert!(!acl.start);
+    assert_eq!(acl.data, [0xaa, 0xbb]);
+
+    assert!(AclPacket::parse(&packet[..3]).is_none());
+    assert!(AclPacket::parse(&packet[..15]).is_none());
+
+    // Splitting a frame and parsing the pieces back.
+    let pieces: Vec<_> = hci::acl_packets(0x000b, &STATUS_FRAME, 5).collect();
+    assert_eq!(pieces.len(), 3);
+    assert_eq!(pieces[0][..4], [0x0b, 0x20, 0x05, 0x00]);
+    assert_eq!(pieces[2][..4], [0x0b, 0x10, 0x02, 0x00]);
+    let data: Vec<u8> = pieces
+        .iter()
+        .flat_map(|piece| AclPacket::parse(piece).unwrap().data.to_vec())
+        .collect();
+    assert_eq!(data, STATUS_FRAME);
+}
+
+#[test]
+fn reassembly() {
+    let mut reassembly = Reassembly::new();
+    // A continuation whose start got lost.
+    assert!(reassembly.push(false, &STATUS_FRAME[5..]).is_none());
+
+    assert!(reassembly.push(true, &STATUS_FRAME[..2]).is_none());
+    assert!(reassembly.push(false, &STATUS_FRAME[2..5]).is_none());
+    assert_eq!(
+        reassembly.push(false, &STATUS_FRAME[5..]),
+        Some(&STATUS_FRAME[..])
+    );
+    reassembly.clear();
+
+    // A new start drops what was there, and trailing garbage gets ignored.
+    assert!(reassembly.push(true, &[0x20, 0x00, 0x41]).is_none());
+    let mut packet = STATUS_FRAME.to_vec();
+    packet.extend_from_slice(&[0xde, 0xad]);
+    let frame = reassembly.push(true, &packet).unwrap();
+    assert_eq!(frame, STATUS_FRAME);
+
+    let (cid, payload) = l2cap::parse_frame(frame).unwrap();
+    assert_eq!(cid, 0x0041);
+    assert_eq!(payload, &STATUS_FRAME[4..]);
+    assert!(l2cap::parse_frame(&STATUS_FRAME[..11]).is_none());
+    assert_eq!(l2cap::frame(cid, payload), STATUS_FRAME);
+}
+
+#[test]
+fn signals() {
+    // A connection request for the HID control channel, followed by a configuration request
+    // with an MTU option, as sent by a remote in a single signalling frame.
+    let frame = [
+        0x14, 0x00, 0x01, 0x00, 0x02, 0x01, 0x04, 0x00, 0x11, 0x00, 0x40, 0x00, 0x04, 0x02, 0x08,
+        0x00, 0x41, 0x00, 0x00, 0x00, 0x01, 0x02, 0xb9, 0x00,
+    ];
+    let (cid, payload) = l2cap::parse_frame(&frame).unwrap();
+    assert_eq!(cid, CID_SIGNALLING);
+    let signals: Vec<_> = Signal::parse_all(payload).collect();
+    assert_eq!(
+        signals,
+        [
+            Signal::ConnectionRequest {
+                id: 1,
+                psm: PSM_HID_CONTROL,
+                source: 0x0040,
+            },
+            Signal::ConfigurationRequest {
+                id: 2,
+                destination: 0x0041,
+                flags: 0,
+                options: &[0x01, 0x02, 0xb9, 0x00],
+            },
+        ]
+    );
+
+    // A truncated command ends the iteration.
+    assert_eq!(Signal::parse_all(&payload[..14]).count(), 1);
+
+    let payload = [
+        0x03, 0x05, 0x08, 0x00, 0x41, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x06, 0x02,
+        0x00, 0x02, 0x00, 0x42, 0x07, 0x00, 0x00,
+    ];
+    let signals: Vec<_> = Signal::parse_all(&payload).collect();
+    assert_eq!(
+        signals,
+        [
+            Signal::ConnectionResponse {
+                id: 5,
+                destination: 0x0041,
+                source: 0x0040,
+                result: l2cap::RESULT_SUCCESS,
+                status: 0,
+            },
+            Signal::InformationRequest {
+                id: 6,
+                info_type: 2,
+            },
+            Signal::Unknown { code: 0x42, id: 7 },
+        ]
+    );
+    // Only the header of the last command is there.
+    assert_eq!(Signal::parse_all(&payload[..21]).count(), 2);
+}
+
+#[test]
+fn signals_round_trip() {
+    let signals = [
+        Signal::CommandReject { id: 1, reason: 2 },
+        Signal::ConfigurationResponse {
+            id: 3,
+            source: 0x0040,
+            flags: 0,
+            result: 0,
+            options: &[0x01, 0x02, 0x00, 0x02],
+        },
+        Signal::DisconnectionRequest {
+            id: 4,
+            destination: 0x0041,
+            source: 0x0040,
+        },
+        Signal::EchoResponse {
+            id: 5,
+            data: b"echo",
+        },
+        Signal::InformationResponse {
+            id: 6,
+            info_type: 2,
+            result: 1,
+        },
+    ];
+    let payload: Vec<u8> = signals.iter().flat_map(Signal::encode).collect();
+    assert_eq!(Signal::parse_all(&payload).collect::<Vec<_>>(), signals);
+}
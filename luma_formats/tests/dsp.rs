@@ -0,0 +1,200 @@
+//! The DSP mailboxes and the ROM loader handshake, driven against a mock of the mailbox
+//! registers playing the part of the DSP ROM.
+
+use luma_formats::dsp::{
+    self, DspError, DspRegisters, MAIL_PENDING, MAILBOX_IN_HIGH, MAILBOX_IN_LOW, MAILBOX_OUT_HIGH,
+    MAILBOX_OUT_LOW, Mailbox, ROM_READY, Ucode,
+};
+use std::collections::VecDeque;
+
+/// The mailbox registers of a DSP which sends ``outgoing``, whose mails all have their pending
+/// bit set like those of the DSP, and takes ``read_delay`` polls to read each mail sent to it.
+#[derive(Default)]
+struct Mock {
+    outgoing: VecDeque<u32>,
+    /// Polls before the next outgoing mail shows up.
+    send_delay: u32,
+    read_delay: u32,
+    /// Polls left before the DSP reads the last mail sent to it.
+    reading: u32,
+    high: Option<u16>,
+    received: Vec<u32>,
+    pauses: u32,
+}
+
+impl DspRegisters for Mock {
+    fn read16(&mut self, offset: u32) -> u16 {
+        match offset {
+            MAILBOX_IN_HIGH if self.reading > 0 => {
+                self.reading -= 1;
+                MAIL_PENDING
+            }
+            MAILBOX_IN_HIGH => 0,
+            MAILBOX_OUT_HIGH if self.send_delay > 0 => {
+                self.send_delay -= 1;
+                0
+            }
+            MAILBOX_OUT_HIGH => self.outgoing.front().map_or(0, |&mail| (mail >> 16) as u16),
+            MAILBOX_OUT_LOW => self.outgoing.pop_front().unwrap() as u16,
+            offset => panic!("Read of register {offset:#x}"),
+        }
+    }
+
+    fn write16(&mut self, offset: u32, value: u16) {
+        match offset {
+            MAILBOX_IN_HIGH => {
+                assert_eq!(self.high, None, "High half written twice");
+                assert_eq!(self.reading, 0, "Mail sent while the mailbox is full");
+                self.high = Some(value);
+            }
+            MAILBOX_IN_LOW => {
+                let high = self.high.take().expect("Low half written first");
+                self.received.push(((high as u32) << 16) | value as u32);
+                self.reading = self.read_delay;
+            }
+            offset => panic!("Write of register {offset:#x}"),
+        }
+    }
+
+    fn pause(&mut self) {
+        self.pauses += 1;
+    }
+}
+
+#[repr(align(32))]
+struct Code([u8; 64]);
+
+static CODE: Code = Code([0; 64]);
+
+/// The mails of the ROM task loading ``CODE`` from 0x0123_4560, at 0x10 and entered at 0x20.
+const TASK: [u32; 10] = [
+    dsp::ROM_IRAM_MRAM_ADDRESS,
+    0x0123_4560,
+    dsp::ROM_IRAM_ADDRESS,
+    0x10,
+    dsp::ROM_IRAM_LENGTH,
+    64,
+    dsp::ROM_DRAM_LENGTH,
+    0,
+    dsp::ROM_START,
+    0x20,
+];
+
+fn ucode() -> Ucode<'static> {
+    Ucode::new(&CODE.0).with_iram_address(0x10).with_entry(0x20)
+}
+
+/// Drive ``mock``, giving up after 100 polls.
+fn mock_mailbox(mock: Mock) -> Mailbox<Mock> {
+    Mailbox::new(mock).with_timeout(100)
+}
+
+#[test]
+fn load_ucode() {
+    let mut mailbox = mock_mailbox(Mock {
+        outgoing: VecDeque::from([ROM_READY]),
+        send_delay: 30,
+        read_delay: 50,
+        ..Mock::default()
+    });
+    mailbox.load_ucode(&ucode(), 0x0123_4560).unwrap();
+    let mock = mailbox.into_registers();
+    assert_eq!(mock.received, TASK);
+    assert!(mock.outgoing.is_empty());
+    // Waiting for the ready mail, then for each mail but the first to be read.
+    assert_eq!(mock.pauses, 30 + 9 * 50);
+}
+
+#[test]
+fn unexpected_mail() {
+    let mut mailbox = mock_mailbox(Mock {
+        outgoing: VecDeque::from([0xdcd1_0000, ROM_READY]),
+        ..Mock::default()
+    });
+    assert_eq!(
+        mailbox.load_ucode(&ucode(), 0x0123_4560),
+        Err(DspError::UnexpectedMail(0xdcd1_0000))
+    );
+    // Nothing got sent to whatever is running.
+    assert!(mailbox.into_registers().received.is_empty());
+}
+
+#[test]
+fn no_ready_mail() {
+    let mut mailbox = mock_mailbox(Mock::default());
+    assert_eq!(
+        mailbox.load_ucode(&ucode(), 0x0123_4560),
+        Err(DspError::Timeout)
+    );
+    let mock = mailbox.into_registers();
+    assert_eq!(mock.pauses, 100);
+    assert!(mock.received.is_empty());
+
+    // Showing up too late.
+    let mut mailbox = mock_mailbox(Mock {
+        outgoing: VecDeque::from([ROM_READY]),
+        send_delay: 100,
+        ..Mock::default()
+    });
+    assert_eq!(
+        mailbox.load_ucode(&ucode(), 0x0123_4560),
+        Err(DspError::Timeout)
+    );
+}
+
+#[test]
+fn task_not_read() {
+    let mut mailbox = mock_mailbox(Mock {
+        outgoing: VecDeque::from([ROM_READY]),
+        read_delay: u32::MAX,
+        ..Mock::default()
+    });
+    assert_eq!(
+        mailbox.load_ucode(&ucode(), 0x0123_4560),
+        Err(DspError::Timeout)
+    );
+    // Only the first mail made it, the second one waited for it to be read.
+    let mock = mailbox.into_registers();
+    assert_eq!(mock.received, TASK[..1]);
+    assert_eq!(mock.pauses, 100);
+}
+
+#[test]
+fn mails() {
+    let mut mailbox = mock_mailbox(Mock {
+        outgoing: VecDeque::from([0x8012_5678, 0x8000_0001]),
+        read_delay: 3,
+        ..Mock::default()
+    });
+    assert!(mailbox.has_mail());
+    assert_eq!(mailbox.recv_mail(), Some(0x8012_5678));
+    assert_eq!(mailbox.wait_mail(), Ok(0x8000_0001));
+    assert!(!mailbox.has_mail());
+    assert_eq!(mailbox.recv_mail(), None);
+    assert_eq!(mailbox.wait_mail(), Err(DspError::Timeout));
+
+    assert!(mailbox.can_send_mail());
+    mailbox.try_send_mail(0xcafe_0001).unwrap();
+    // The DSP takes three polls to read it.
+    assert!(!mailbox.can_send_mail());
+    assert_eq!(
+        mailbox.try_send_mail(0xcafe_0002),
+        Err(DspError::MailboxFull)
+    );
+    mailbox.send_mail(0xcafe_0003).unwrap();
+    let mock = mailbox.into_registers();
+    assert_eq!(mock.received, [0xcafe_0001, 0xcafe_0003]);
+    assert_eq!(mock.pauses, 100 + 1);
+}
+
+#[test]
+#[should_panic = "Microcode must be 32-byte aligned"]
+fn misaligned_ucode() {
+    let _ = Ucode::new(&CODE.0[1..33]);
+}
+
+#[test]
+#[should_panic = "Microcode length must be a multiple of 32"]
+fn truncated_ucode() {
+    let _ = Ucode::new(&CODE.0[..48]);
+}
@@ -0,0 +1,228 @@
+//! Framing and parsing of the GDB remote serial protocol, with packets as GDB sends them while
+//! attaching and debugging.
+
+use luma_formats::gdb::packet::{self, Command, Decoder, Event, INTERRUPT, MAX_PACKET_SIZE};
+
+/// Feed every byte of ``bytes``, collecting the events they completed.
+fn feed(decoder: &mut Decoder, bytes: &[u8]) -> Vec<Event> {
+    bytes
+        .iter()
+        .filter_map(|&byte| decoder.feed(byte))
+        .collect()
+}
+
+fn packet(data: &[u8]) -> Event {
+    Event::Packet(data.to_vec())
+}
+
+#[test]
+fn packets() {
+    let mut decoder = Decoder::new();
+    assert_eq!(feed(&mut decoder, b"$g#67"), [packet(b"g")]);
+    // Checksums in either case, and acknowledgements around the packets.
+    assert_eq!(
+        feed(&mut decoder, b"+$m80003100,4#59-$OK#9A+"),
+        [
+            Event::Ack,
+            packet(b"m80003100,4"),
+            Event::Nack,
+            packet(b"OK"),
+            Event::Ack,
+        ]
+    );
+    // Split at any point.
+    let bytes = b"$qSupported:swbreak+;hwbreak+#d5";
+    for at in 0..bytes.len() {
+        let mut events = feed(&mut decoder, &bytes[..at]);
+        events.extend(feed(&mut decoder, &bytes[at..]));
+        assert_eq!(events, [packet(b"qSupported:swbreak+;hwbreak+")], "{at}");
+    }
+    // An empty packet.
+    assert_eq!(feed(&mut decoder, b"$#00"), [packet(b"")]);
+}
+
+#[test]
+fn bad_checksums() {
+    let mut decoder = Decoder::new();
+    assert_eq!(feed(&mut decoder, b"$g#68"), [Event::BadChecksum]);
+    assert_eq!(feed(&mut decoder, b"$g#x"), [Event::BadChecksum]);
+    assert_eq!(feed(&mut decoder, b"$g#6x"), [Event::BadChecksum]);
+    // The decoder starts afresh after each of them.
+    assert_eq!(feed(&mut decoder, b"$g#67"), [packet(b"g")]);
+}
+
+#[test]
+fn interrupts_and_noise() {
+    let mut decoder = Decoder::new();
+    assert_eq!(feed(&mut decoder, &[INTERRUPT]), [Event::Interrupt]);
+    // Garbage between packets gets ignored, while anything in a packet is data.
+    assert_eq!(feed(&mut decoder, b"garbage\r\n"), []);
+    assert_eq!(feed(&mut decoder, b"$+-\x03#5b"), [packet(b"+-\x03")]);
+    // A packet cut off by another one gets dropped.
+    assert_eq!(feed(&mut decoder, b"$m8000$g#67"), [packet(b"g")]);
+    assert_eq!(feed(&mut decoder, b"$m8000}$g#67"), [packet(b"g")]);
+}
+
+#[test]
+fn escapes() {
+    let mut decoder = Decoder::new();
+    // Writing ``#$A``, whose first two bytes have to be escaped, and are checksummed escaped.
+    let bytes = b"$X80001000,3:}\x03}\x04A#bc";
+    assert_eq!(feed(&mut decoder, bytes), [packet(b"X80001000,3:#$A")]);
+    // A checksum right after an escape.
+    assert_eq!(feed(&mut decoder, b"$}#7d"), [packet(b"")]);
+}
+
+#[test]
+fn too_large() {
+    let mut decoder = Decoder::new();
+    let mut bytes = vec![b'$'];
+    bytes.extend(std::iter::repeat_n(b'a', MAX_PACKET_SIZE));
+    let checksum = (MAX_PACKET_SIZE * b'a' as usize) as u8;
+    let mut largest = bytes.clone();
+    largest.push(b'#');
+    packet::push_hex(&mut largest, &[checksum]);
+    assert_eq!(
+        feed(&mut decoder, &largest),
+        [packet(&vec![b'a'; MAX_PACKET_SIZE])]
+    );
+
+    bytes.push(b'a');
+    assert_eq!(feed(&mut decoder, &bytes), [Event::BadChecksum]);
+    // The rest of the packet is noise.
+    assert_eq!(feed(&mut decoder, b"aaa#00$g#67"), [packet(b"g")]);
+}
+
+#[test]
+fn encode() {
+    let mut out = Vec::new();
+    packet::encode(b"OK", &mut out);
+    assert_eq!(out, b"$OK#9a");
+
+    out.clear();
+    packet::encode(b"", &mut out);
+    assert_eq!(out, b"$#00");
+
+    // Every byte GDB treats specially gets escaped, and decodes back.
+    let data = b"a$b#c}d*e\x03";
+    out.clear();
+    packet::encode(data, &mut out);
+    assert_eq!(&out[..16], b"$a}\x04b}\x03c}]d}\x0ae\x03#");
+    let mut decoder = Decoder::new();
+    assert_eq!(feed(&mut decoder, &out), [packet(data)]);
+
+    // Appended to what is there already.
+    let mut out = b"+".to_vec();
+    packet::encode(b"S05", &mut out);
+    assert_eq!(out, b"+$S05#b8");
+}
+
+#[test]
+fn hex() {
+    let mut out = b"T".to_vec();
+    packet::push_hex(&mut out, &[0x05, 0xab, 0x00, 0xff]);
+    assert_eq!(out, b"T05ab00ff");
+
+    assert_eq!(
+        packet::decode_hex(b"05aB00Ff"),
+        Some(vec![0x05, 0xab, 0x00, 0xff])
+    );
+    assert_eq!(packet::decode_hex(b""), Some(vec![]));
+    assert_eq!(packet::decode_hex(b"05a"), None);
+    assert_eq!(packet::decode_hex(b"0g"), None);
+
+    assert_eq!(packet::parse_number(b"80003100"), Some(0x8000_3100));
+    assert_eq!(packet::parse_number(b"FfF"), Some(0xfff));
+    assert_eq!(packet::parse_number(b"0"), Some(0));
+    assert_eq!(packet::parse_number(b""), None);
+    assert_eq!(packet::parse_number(b"100000000"), None);
+    assert_eq!(packet::parse_number(b"-1"), None);
+}
+
+#[test]
+fn commands() {
+    let commands: &[(&[u8], Command)] = &[
+        (b"?", Command::StopReason),
+        (b"g", Command::ReadRegisters),
+        (b"G0001ff", Command::WriteRegisters(vec![0x00, 0x01, 0xff])),
+        (b"p40", Command::ReadRegister(0x40)),
+        (
+            b"P40=80004000",
+            Command::WriteRegister(0x40, vec![0x80, 0x00, 0x40, 0x00]),
+        ),
+        (
+            b"m80003100,4",
+            Command::ReadMemory {
+                address: 0x8000_3100,
+                length: 4,
+            },
+        ),
+        (
+            b"M80001000,2:6000",
+            Command::WriteMemory {
+                address: 0x8000_1000,
+                data: vec![0x60, 0x00],
+            },
+        ),
+        (
+            b"X80001000,3:#$A",
+            Command::WriteMemory {
+                address: 0x8000_1000,
+                data: b"#$A".to_vec(),
+            },
+        ),
+        (b"c", Command::Continue(None)),
+        (b"c80004000", Command::Continue(Some(0x8000_4000))),
+        (b"s", Command::Step(None)),
+        (b"s80004004", Command::Step(Some(0x8000_4004))),
+        (b"Z0,80004000,4", Command::InsertBreakpoint(0x8000_4000)),
+        (b"z0,80004000,4", Command::RemoveBreakpoint(0x8000_4000)),
+        (b"D", Command::Detach),
+        (b"k", Command::Kill),
+        (
+            b"qSupported:swbreak+",
+            Command::Query(b"Supported:swbreak+"),
+        ),
+        (b"Hg0", Command::SetThread),
+    ];
+    for (packet, command) in commands {
+        assert_eq!(
+            Command::parse(packet),
+            *command,
+            "{}",
+            String::from_utf8_lossy(packet)
+        );
+    }
+}
+
+#[test]
+fn unsupported_commands() {
+    let packets: &[&[u8]] = &[
+        b"",
+        // Not implemented.
+        b"vCont?",
+        b"Z1,80004000,4",
+        b"!",
+        // Invalid arguments.
+        b"G0",
+        b"pxyz",
+        b"P40",
+        b"P40=8",
+        b"m80003100",
+        b"m,4",
+        b"M80001000,3:6000",
+        b"X80001000,1:ab",
+        b"M80001000,2",
+        b"cnot",
+        b"Z0,80004000",
+        b"Z0",
+    ];
+    for packet in packets {
+        assert_eq!(
+            Command::parse(packet),
+            Command::Unsupported,
+            "{}",
+            String::from_utf8_lossy(packet)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Mixing of PCM and ADPCM voices with their volume, pan and pitch, mostly at the output sample
+//! rate so that each output frame comes from a single frame of every sound, and saturating where
+//! they add up past 16 bits.
+
+use luma_formats::audio::mixer::{Mixer, Sound};
+
+const RATE: u32 = 32000;
+
+/// Mix ``frames`` stereo frames.
+fn mix(mixer: &mut Mixer, frames: usize) -> Vec<i16> {
+    let mut out = vec![0x55; frames * 2];
+    mixer.mix(&mut out);
+    out
+}
+
+fn stereo(frames: &[(i16, i16)]) -> Vec<i16> {
+    frames
+        .iter()
+        .flat_map(|&(left, right)| [left, right])
+        .collect()
+}
+
+#[test]
+fn single_voice() {
+    let mut mixer = Mixer::new(2, RATE);
+    let sound = Sound::pcm16([1000, -2000, 3000], 1, RATE);
+    let voice = mixer.play(&sound).unwrap();
+    assert!(mixer.is_playing(voice));
+    // The voice ends with its last frame, and the rest is silent.
+    assert_eq!(
+        mix(&mut mixer, 5),
+        stereo(&[(1000, 1000), (-2000, -2000), (3000, 3000), (0, 0), (0, 0)])
+    );
+    assert!(!mixer.is_playing(voice));
+    assert_eq!(mix(&mut mixer, 2), [0; 4]);
+
+    let sound = Sound::pcm16([100, -100, 200, -200], 2, RATE);
+    assert_eq!(sound.frames(), 2);
+    mixer.play(&sound).unwrap();
+    assert_eq!(
+        mix(&mut mixer, 3),
+        stereo(&[(100, -100), (200, -200), (0, 0)])
+    );
+}
+
+#[test]
+fn volume_and_pan() {
+    let mut mixer = Mixer::new(1, RATE);
+    let sound = Sound::pcm16([8000, -8000, 8000, -8000, 8000], 1, RATE);
+    let voice = mixer.play(&sound).unwrap();
+
+    mixer.set_volume(voice, 0.5);
+    assert_eq!(mix(&mut mixer, 1), stereo(&[(4000, 4000)]));
+    // Panning attenuates the other side only.
+    mixer.set_pan(voice, 1.0);
+    assert_eq!(mix(&mut mixer, 1), stereo(&[(0, -4000)]));
+    mixer.set_pan(voice, -0.5);
+    assert_eq!(mix(&mut mixer, 1), stereo(&[(4000, 2000)]));
+    // Both get clamped to their range.
+    mixer.set_volume(voice, 3.0);
+    mixer.set_pan(voice, -2.0);
+    assert_eq!(mix(&mut mixer, 1), stereo(&[(-8000, 0)]));
+    mixer.set_volume(voice, -1.0);
+    assert_eq!(mix(&mut mixer, 1), stereo(&[(0, 0)]));
+}
+
+#[test]
+fn several_voices() {
+    let mut mixer = Mixer::new(3, RATE);
+    let low = Sound::pcm16([1000, 2000, 3000, 4000], 1, RATE);
+    let high = Sound::pcm16([-300, 300, -600, 600], 2, RATE);
+    let first = mixer.play(&low).unwrap();
+    let second = mixer.play(&high).unwrap();
+    let third = mixer.play(&low).unwrap();
+    // Every voice is busy.
+    assert_eq!(mixer.play(&low), None);
+
+    mixer.set_volume(first, 0.5);
+    mixer.set_pan(third, 1.0);
+    assert_eq!(mix(&mut mixer, 2), stereo(&[(200, 1800), (400, 3600)]));
+
+    // A stopped voice is free again, and gets replaced.
+    mixer.stop(second);
+    assert!(!mixer.is_playing(second));
+    assert_eq!(mixer.play(&high), Some(second));
+    mixer.stop_all();
+    assert!(!mixer.is_playing(first) && !mixer.is_playing(third));
+    assert_eq!(mix(&mut mixer, 1), [0; 2]);
+}
+
+#[test]
+fn saturation() {
+    let mut mixer = Mixer::new(2, RATE);
+    let loud = Sound::pcm16([30000, -30000, 20000, i16::MIN], 2, RATE);
+    mixer.play(&loud).unwrap();
+    mixer.play(&loud).unwrap();
+    assert_eq!(
+        mix(&mut mixer, 2),
+        stereo(&[(i16::MAX, i16::MIN), (i16::MAX, i16::MIN)])
+    );
+
+    // What goes past 16 bits only gets clamped once every voice is added.
+    let mut mixer = Mixer::new(3, RATE);
+    mixer.play(&Sound::pcm16([30000], 1, RATE)).unwrap();
+    mixer.play(&Sound::pcm16([30000], 1, RATE)).unwrap();
+    mixer.play(&Sound::pcm16([-30000], 1, RATE)).unwrap();
+    assert_eq!(mix(&mut mixer, 1), stereo(&[(30000, 30000)]));
+}
+
+#[test]
+fn pitch() {
+    let samples: Vec<i16> = (0..8).map(|i| i * 1000).collect();
+    let sound = Sound::pcm16(samples, 1, RATE);
+
+    // Twice as fast skips every other frame.
+    let mut mixer = Mixer::new(1, RATE);
+    let voice = mixer.play(&sound).unwrap();
+    mixer.set_pitch(voice, 2.0);
+    assert_eq!(
+        mix(&mut mixer, 5),
+        stereo(&[(0, 0), (2000, 2000), (4000, 4000), (6000, 6000), (0, 0)])
+    );
+
+    // Half as fast interpolates between frames.
+    let voice = mixer.play(&sound).unwrap();
+    mixer.set_pitch(voice, 0.5);
+    assert_eq!(
+        mix(&mut mixer, 4),
+        stereo(&[(0, 0), (500, 500), (1000, 1000), (1500, 1500)])
+    );
+
+    // So does a sound at half the output rate.
+    let mut mixer = Mixer::new(1, RATE * 2);
+    mixer.play(&sound).unwrap();
+    assert_eq!(
+        mix(&mut mixer, 3),
+        stereo(&[(0, 0), (500, 500), (1000, 1000)])
+    );
+}
+
+#[test]
+fn looping() {
+    let sound = Sound::pcm16([1, 2, 3, 4], 1, RATE).with_loop(1, 2);
+    assert_eq!(sound.looping(), Some((1, 2)));
+    let mut mixer = Mixer::new(1, RATE);
+    let voice = mixer.play(&sound).unwrap();
+    let out = mix(&mut mixer, 7);
+    let left: Vec<i16> = out.iter().step_by(2).copied().collect();
+    assert_eq!(left, [1, 2, 3, 2, 3, 2, 3]);
+    assert!(mixer.is_playing(voice));
+
+    let once = sound.without_loop();
+    assert_eq!(once.looping(), None);
+    mixer.play_on(voice, &once);
+    let out = mix(&mut mixer, 5);
+    let left: Vec<i16> = out.iter().step_by(2).copied().collect();
+    assert_eq!(left, [1, 2, 3, 4, 0]);
+    assert!(!mixer.is_playing(voice));
+}
+
+#[test]
+#[should_panic = "Loop out of bounds"]
+fn loop_out_of_bounds() {
+    let _ = Sound::pcm16([1, 2, 3], 1, RATE).with_loop(1, 3);
+}
+
+/// Build a ``.dsp`` file of two frames, looping over the second one, whose samples are 4, 28,
+/// -4, -32 and ten zeroes, then 1 to 13 and 12.
+fn dsp_file() -> Vec<u8> {
+    let mut file = vec![0; 0x60];
+    file[0x00..0x04].copy_from_slice(&28u32.to_be_bytes());
+    file[0x08..0x0c].copy_from_slice(&RATE.to_be_bytes());
+    file[0x0d] = 1;
+    file[0x10..0x14].copy_from_slice(&0x12u32.to_be_bytes());
+    file[0x14..0x18].copy_from_slice(&0x1fu32.to_be_bytes());
+    // Predictor 1 adds the previous sample.
+    file[0x20..0x22].copy_from_slice(&2048i16.to_be_bytes());
+    file.extend_from_slice(&[0x02, 0x17, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    file.extend_from_slice(&[0x10, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1f]);
+    file
+}
+
+#[test]
+fn adpcm() {
+    let sound = Sound::from_dsp(&dsp_file()).unwrap();
+    assert_eq!(sound.frames(), 28);
+    assert_eq!(sound.sample_rate(), RATE);
+    assert_eq!(sound.looping(), Some((14, 27)));
+
+    let mut mixer = Mixer::new(1, RATE);
+    let voice = mixer.play(&sound).unwrap();
+    mixer.set_volume(voice, 0.5);
+    let out = mix(&mut mixer, 42);
+    let (left, right): (Vec<i16>, Vec<i16>) = out
+        .chunks_exact(2)
+        .map(|frame| (frame[0], frame[1]))
+        .unzip();
+    assert_eq!(left, right);
+    let mut expected = vec![2, 14, -2, -16, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    let second = [0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 6];
+    // The loop starts again from the history the file gives, zero here.
+    expected.extend_from_slice(&second);
+    expected.extend_from_slice(&second);
+    assert_eq!(left, expected);
+}
@@ -0,0 +1,151 @@
+//! Reading of Ogg files: ``fixtures/stereo.ogg``, a Vorbis stream of seven pages and nineteen
+//! packets, one of which spans two pages, and small files built page by page.
+
+use luma_formats::audio::ogg::{OggError, OggReader};
+
+const FIXTURE: &[u8] = include_bytes!("fixtures/stereo.ogg");
+
+/// Size and granule position of every packet of the fixture.
+const PACKETS: [(usize, Option<i64>); 19] = [
+    (30, Some(0)),
+    (20, None),
+    (423, Some(0)),
+    (178, None),
+    (596, None),
+    (518, Some(0)),
+    (7, None),
+    (559, None),
+    (148, None),
+    (460, None),
+    (469, Some(0)),
+    (349, None),
+    (433, None),
+    (133, None),
+    (215, Some(0)),
+    (82, Some(0)),
+    (431, None),
+    (27, None),
+    (391, Some(2897)),
+];
+
+/// Offset of the second page of the fixture.
+const SECOND_PAGE: usize = 58;
+
+fn crc(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04c1_1db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Build a page holding the given segments, the last one ending a packet unless it is 255 bytes.
+fn page(serial: u32, flags: u8, granule: i64, segments: &[&[u8]]) -> Vec<u8> {
+    let mut page = b"OggS\0".to_vec();
+    page.push(flags);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&serial.to_le_bytes());
+    page.extend_from_slice(&[0; 8]);
+    page.push(segments.len() as u8);
+    page.extend(segments.iter().map(|segment| segment.len() as u8));
+    for segment in segments {
+        page.extend_from_slice(segment);
+    }
+    let checksum = crc(&page);
+    page[22..26].copy_from_slice(&checksum.to_le_bytes());
+    page
+}
+
+#[test]
+fn fixture_packets() {
+    let mut reader = OggReader::new(FIXTURE);
+    for (i, &(size, granule)) in PACKETS.iter().enumerate() {
+        let packet = reader.next_packet().unwrap().unwrap();
+        assert_eq!(packet.data.len(), size, "packet {i}");
+        assert_eq!(packet.granule, granule, "packet {i}");
+        assert_eq!(packet.end_of_stream, i == PACKETS.len() - 1, "packet {i}");
+        if i < 3 {
+            assert_eq!(&packet.data[1..7], b"vorbis");
+            assert_eq!(packet.data[0], [1, 3, 5][i]);
+        }
+    }
+    assert!(reader.next_packet().unwrap().is_none());
+    assert!(reader.next_packet().unwrap().is_none());
+
+    reader.rewind();
+    assert_eq!(reader.next_packet().unwrap().unwrap().data.len(), 30);
+}
+
+#[test]
+fn packets_across_pages() {
+    let long = [0xaa; 255];
+    let mut file = page(1, 0x02, 0, &[b"first", &long]);
+    // A page of another logical stream, to be skipped.
+    file.extend(page(2, 0x02, 0, &[b"other"]));
+    file.extend(page(1, 0x01, 0, &[&long, b"end"]));
+    // A fragment which never gets completed, since the next page isn’t a continuation.
+    file.extend(page(1, 0x00, 7, &[b"a", &long]));
+    file.extend(page(1, 0x04, 9, &[b"last"]));
+
+    let mut reader = OggReader::new(&file);
+    let packet = reader.next_packet().unwrap().unwrap();
+    assert_eq!(packet.data, b"first");
+    // The last packet to end on its page, even though another one starts after it.
+    assert_eq!(packet.granule, Some(0));
+
+    let packet = reader.next_packet().unwrap().unwrap();
+    assert_eq!(packet.data.len(), 255 * 2 + 3);
+    assert!(packet.data[..510].iter().all(|&byte| byte == 0xaa));
+    assert_eq!(&packet.data[510..], b"end");
+    assert_eq!(packet.granule, Some(0));
+
+    let packet = reader.next_packet().unwrap().unwrap();
+    assert_eq!(packet.data, b"a");
+    assert_eq!(packet.granule, Some(7));
+
+    let packet = reader.next_packet().unwrap().unwrap();
+    assert_eq!(packet.data, b"last");
+    assert_eq!(packet.granule, Some(9));
+    assert!(packet.end_of_stream);
+    assert!(reader.next_packet().unwrap().is_none());
+}
+
+#[test]
+fn truncated() {
+    for size in [10, SECOND_PAGE - 1, SECOND_PAGE + 30] {
+        let mut reader = OggReader::new(&FIXTURE[..size]);
+        let mut result = reader.next_packet();
+        while let Ok(Some(_)) = result {
+            result = reader.next_packet();
+        }
+        assert_eq!(result.err(), Some(OggError::OutOfBounds), "size {size}");
+    }
+}
+
+#[test]
+fn corrupt() {
+    let mut file = FIXTURE.to_vec();
+    file[40] ^= 0x10;
+    let mut reader = OggReader::new(&file);
+    assert_eq!(reader.next_packet().err(), Some(OggError::BadChecksum));
+
+    let mut file = FIXTURE.to_vec();
+    file[0] = b'P';
+    let mut reader = OggReader::new(&file);
+    assert_eq!(reader.next_packet().err(), Some(OggError::BadCapture));
+
+    let mut file = FIXTURE.to_vec();
+    file[4] = 1;
+    let mut reader = OggReader::new(&file);
+    assert_eq!(
+        reader.next_packet().err(),
+        Some(OggError::UnknownVersion(1))
+    );
+}
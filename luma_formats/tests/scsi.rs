@@ -0,0 +1,222 @@
+//! Encoding of the SCSI commands sent to a USB flash drive and decoding of its answers, shaped
+//! after those of a SanDisk Cruzer Blade.
+
+use luma_formats::usb::scsi::{
+    self, Capacity, Command, CommandStatus, Direction, Inquiry, Sense, Status,
+};
+
+#[test]
+fn wrap_read() {
+    let command = Command::read(0x0012_3456, 8, 512);
+    assert_eq!(
+        command.cdb(),
+        [0x28, 0, 0x00, 0x12, 0x34, 0x56, 0, 0x00, 0x08, 0]
+    );
+    assert_eq!(command.transfer_length(), 4096);
+    assert_eq!(command.direction(), Direction::In);
+
+    let block = command.wrap(0xdead_beef, 0);
+    assert_eq!(
+        block[..25],
+        [
+            0x55, 0x53, 0x42, 0x43, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x10, 0x00, 0x00, 0x80, 0x00,
+            0x0a, 0x28, 0x00, 0x00, 0x12, 0x34, 0x56, 0x00, 0x00, 0x08, 0x00,
+        ]
+    );
+    // The rest of the command block stays zeroed.
+    assert!(block[25..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn wrap_write_and_no_data() {
+    let command = Command::write(0xffff_fffe, 1, 4096);
+    assert_eq!(command.direction(), Direction::Out);
+    let block = command.wrap(7, 1);
+    assert_eq!(
+        block[4..15],
+        [7, 0, 0, 0, 0x00, 0x10, 0, 0, 0x00, 0x01, 0x0a]
+    );
+    assert_eq!(
+        block[15..25],
+        [0x2a, 0, 0xff, 0xff, 0xff, 0xfe, 0, 0x00, 0x01, 0]
+    );
+
+    let command = Command::test_unit_ready();
+    assert_eq!(command.cdb(), [0; 6]);
+    assert_eq!(command.direction(), Direction::None);
+    let block = command.wrap(8, 0);
+    assert_eq!(block[8..15], [0, 0, 0, 0, 0x00, 0x00, 0x06]);
+    assert!(block[15..].iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn data_commands() {
+    let commands = [
+        (Command::request_sense(), scsi::SENSE_SIZE),
+        (Command::inquiry(), scsi::INQUIRY_SIZE),
+        (Command::read_capacity(), scsi::CAPACITY_SIZE),
+        (Command::mode_sense(), scsi::MODE_SENSE_SIZE),
+    ];
+    for (command, size) in commands {
+        assert_eq!(command.direction(), Direction::In);
+        assert_eq!(command.transfer_length(), size as u32);
+        let block = command.wrap(1, 0);
+        assert_eq!(block[8..12], (size as u32).to_le_bytes());
+        assert_eq!(block[12], 0x80);
+        assert_eq!(block[15..15 + block[14] as usize], *command.cdb());
+    }
+    assert_eq!(Command::inquiry().cdb(), [0x12, 0, 0, 0, 36, 0]);
+    assert_eq!(Command::request_sense().cdb(), [0x03, 0, 0, 0, 18, 0]);
+    assert_eq!(
+        Command::read_capacity().cdb(),
+        [0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(Command::mode_sense().cdb(), [0x1a, 0, 0x3f, 0, 4, 0]);
+}
+
+#[test]
+fn command_status() {
+    let mut data = [
+        0x55, 0x53, 0x42, 0x53, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x02, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(
+        CommandStatus::parse(&data),
+        Some(CommandStatus {
+            tag: 0xdead_beef,
+            residue: 512,
+            status: Status::Passed,
+        })
+    );
+    data[12] = 1;
+    assert_eq!(CommandStatus::parse(&data).unwrap().status, Status::Failed);
+    data[12] = 2;
+    assert_eq!(
+        CommandStatus::parse(&data).unwrap().status,
+        Status::PhaseError
+    );
+
+    data[12] = 3;
+    assert_eq!(CommandStatus::parse(&data), None);
+    data[12] = 0;
+    assert_eq!(CommandStatus::parse(&data[..12]), None);
+    let mut longer = data.to_vec();
+    longer.push(0);
+    assert_eq!(CommandStatus::parse(&longer), None);
+    // The signature of a command block, not a status.
+    data[3] = 0x43;
+    assert_eq!(CommandStatus::parse(&data), None);
+}
+
+#[test]
+fn inquiry() {
+    let mut data = vec![0x00, 0x80, 0x06, 0x02, 0x1f, 0x00, 0x00, 0x00];
+    data.extend_from_slice(b"SanDisk Cruzer Blade    1.00");
+    let inquiry = Inquiry::parse(&data).unwrap();
+    assert!(inquiry.connected);
+    assert_eq!(inquiry.device_type, scsi::TYPE_DIRECT_ACCESS);
+    assert!(inquiry.removable);
+    assert!(inquiry.is_block_device());
+    assert_eq!(inquiry.vendor(), "SanDisk");
+    assert_eq!(inquiry.product(), "Cruzer Blade");
+    assert_eq!(inquiry.revision, *b"1.00");
+    assert_eq!(Inquiry::parse(&data[..35]), None);
+
+    // Vendor specific data past the standard part gets ignored.
+    data.extend_from_slice(&[0xff; 60]);
+    assert_eq!(Inquiry::parse(&data), Some(inquiry));
+
+    // A logical unit without any device behind it.
+    data[0] = 0x7f;
+    let inquiry = Inquiry::parse(&data).unwrap();
+    assert!(!inquiry.connected);
+    assert!(!inquiry.is_block_device());
+
+    // A CD-ROM drive, padded with NULs and with a name which isn’t ASCII.
+    data[0] = 0x05;
+    data[8..16].copy_from_slice(b"Drive\0\0\0");
+    data[16..32].copy_from_slice(&[0xe9; 16]);
+    let inquiry = Inquiry::parse(&data).unwrap();
+    assert!(!inquiry.is_block_device());
+    assert_eq!(inquiry.vendor(), "Drive");
+    assert_eq!(inquiry.product(), "");
+}
+
+#[test]
+fn read_capacity() {
+    let data = [0x00, 0xee, 0xff, 0xff, 0x00, 0x00, 0x02, 0x00];
+    let capacity = Capacity::parse(&data).unwrap();
+    assert_eq!(
+        capacity,
+        Capacity {
+            last_block: 0x00ee_ffff,
+            block_size: 512,
+        }
+    );
+    assert_eq!(capacity.blocks(), 0x00ef_0000);
+    assert_eq!(Capacity::parse(&data[..7]), None);
+
+    // Too many blocks for READ(10), which doesn’t overflow the count.
+    let capacity = Capacity::parse(&[0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x10, 0x00]).unwrap();
+    assert_eq!(capacity.blocks(), 1 << 32);
+    assert_eq!(capacity.block_size, 4096);
+}
+
+#[test]
+fn sense() {
+    // Fixed format: medium not present.
+    let mut data = [0; scsi::SENSE_SIZE];
+    data[0] = 0xf0;
+    data[2] = 0x02;
+    data[7] = 10;
+    data[12] = 0x3a;
+    assert_eq!(
+        Sense::parse(&data),
+        Some(Sense {
+            key: scsi::SENSE_NOT_READY,
+            code: 0x3a,
+            qualifier: 0x00,
+        })
+    );
+    // Deferred errors, and flags above the key.
+    data[0] = 0x71;
+    data[2] = 0x20 | scsi::SENSE_DATA_PROTECT;
+    data[12] = 0x27;
+    data[13] = 0x01;
+    assert_eq!(
+        Sense::parse(&data),
+        Some(Sense {
+            key: scsi::SENSE_DATA_PROTECT,
+            code: 0x27,
+            qualifier: 0x01,
+        })
+    );
+    assert_eq!(Sense::parse(&data[..13]), None);
+
+    // Descriptor format: medium changed.
+    let data = [0x72, 0x06, 0x28, 0x00, 0, 0, 0, 0];
+    assert_eq!(
+        Sense::parse(&data),
+        Some(Sense {
+            key: scsi::SENSE_UNIT_ATTENTION,
+            code: 0x28,
+            qualifier: 0x00,
+        })
+    );
+    assert_eq!(Sense::parse(&data[..3]), None);
+
+    assert_eq!(Sense::parse(&[0x00; 18]), None);
+    assert_eq!(Sense::parse(&[]), None);
+}
+
+#[test]
+fn write_protection() {
+    assert_eq!(
+        scsi::is_write_protected(&[0x03, 0x00, 0x80, 0x00]),
+        Some(true)
+    );
+    assert_eq!(
+        scsi::is_write_protected(&[0x03, 0x00, 0x00, 0x00]),
+        Some(false)
+    );
+    assert_eq!(scsi::is_write_protected(&[0x03, 0x00]), None);
+}
@@ -0,0 +1,380 @@
+//! Marshalling of the socket requests, checked against a mock of ``/dev/net/ip/top`` which
+//! records every request and answers them as IOS would.
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+use luma_formats::ios::IosError;
+use luma_formats::net::socket::{self, PollFd};
+use luma_formats::net::{Device, NetError};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Where the mock pretends its buffers are, as IOS sees them.
+const BASE: u32 = 0x1000_4000;
+
+/// A request as the mock received it.
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    ioctl: u32,
+    inputs: Vec<Vec<u8>>,
+    /// The size of each output.
+    outputs: Vec<usize>,
+}
+
+/// How the mock answers a request: what it writes into each output, then what it returns.
+struct Reply {
+    outputs: Vec<Vec<u8>>,
+    result: i32,
+}
+
+fn reply(result: i32) -> Reply {
+    Reply {
+        outputs: Vec::new(),
+        result,
+    }
+}
+
+fn reply_with(result: i32, outputs: &[&[u8]]) -> Reply {
+    Reply {
+        outputs: outputs.iter().map(|output| output.to_vec()).collect(),
+        result,
+    }
+}
+
+#[derive(Default)]
+struct Mock {
+    requests: RefCell<Vec<Request>>,
+    replies: RefCell<VecDeque<Reply>>,
+    /// How many buffers got asked for.
+    buffers: RefCell<usize>,
+}
+
+impl Mock {
+    fn new(replies: impl IntoIterator<Item = Reply>) -> Mock {
+        Mock {
+            replies: RefCell::new(replies.into_iter().collect()),
+            ..Mock::default()
+        }
+    }
+
+    fn answer(&self, ioctl: u32, inputs: &[&[u8]], outputs: &mut [&mut [u8]]) -> i32 {
+        self.requests.borrow_mut().push(Request {
+            ioctl,
+            inputs: inputs.iter().map(|input| input.to_vec()).collect(),
+            outputs: outputs.iter().map(|output| output.len()).collect(),
+        });
+        let reply = self
+            .replies
+            .borrow_mut()
+            .pop_front()
+            .expect("no reply left");
+        for (output, data) in outputs.iter_mut().zip(reply.outputs) {
+            output[..data.len()].copy_from_slice(&data);
+        }
+        reply.result
+    }
+
+    fn requests(&self) -> Vec<Request> {
+        assert!(self.replies.borrow().is_empty(), "replies left");
+        self.requests.take()
+    }
+}
+
+impl Device for Mock {
+    type Buffer = Vec<u8>;
+
+    fn ioctl(&self, ioctl: u32, input: &[u8], output: &mut [u8]) -> Result<i32, IosError> {
+        IosError::check(self.answer(ioctl, &[input], &mut [output]))
+    }
+
+    fn ioctlv(
+        &self,
+        ioctl: u32,
+        inputs: &[&[u8]],
+        outputs: &mut [&mut [u8]],
+    ) -> Result<i32, IosError> {
+        IosError::check(self.answer(ioctl, inputs, outputs))
+    }
+
+    fn buffer(&self, length: usize) -> Vec<u8> {
+        *self.buffers.borrow_mut() += 1;
+        vec![0; length]
+    }
+
+    fn address(&self, _data: &[u8]) -> u32 {
+        BASE
+    }
+}
+
+fn ioctl(ioctl: u32, input: &[u8], output: usize) -> Request {
+    Request {
+        ioctl,
+        inputs: vec![input.to_vec()],
+        outputs: vec![output],
+    }
+}
+
+/// Encode words as IOS takes them.
+fn words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+const PEER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 4299);
+const PEER_BYTES: [u8; 8] = [8, 2, 0x10, 0xcb, 192, 168, 1, 20];
+
+#[test]
+fn addresses() {
+    assert_eq!(socket::encode_address(PEER), PEER_BYTES);
+    assert_eq!(socket::decode_address(&PEER_BYTES), Some(PEER));
+    // Trailing padding gets ignored, but not an address of another family.
+    assert_eq!(
+        socket::decode_address(&[8, 2, 0, 80, 1, 2, 3, 4, 0, 0])
+            .unwrap()
+            .port(),
+        80
+    );
+    assert_eq!(socket::decode_address(&[28, 23, 0, 80, 0, 0, 0, 0]), None);
+    assert_eq!(socket::decode_address(&PEER_BYTES[..7]), None);
+}
+
+#[test]
+fn errors() {
+    let mock = Mock::new([reply(-6), reply(-56), reply(-1000)]);
+    assert_eq!(socket::connect(&mock, 3, PEER), Err(NetError::WouldBlock));
+    assert_eq!(socket::listen(&mock, 3, 1), Err(NetError::NotConnected));
+    assert_eq!(socket::close(&mock, 3), Err(NetError::Socket(1000)));
+    assert_eq!(
+        embedded_io::Error::kind(&NetError::ConnectionReset),
+        embedded_io::ErrorKind::ConnectionReset
+    );
+}
+
+#[test]
+fn setup() {
+    let mock = Mock::new([reply(0), reply(0x0a00_0002), reply(3), reply(0), reply(0)]);
+    socket::startup(&mock).unwrap();
+    assert_eq!(socket::host_id(&mock).unwrap(), Ipv4Addr::new(10, 0, 0, 2));
+    assert_eq!(socket::socket(&mock, socket::SOCK_STREAM).unwrap(), 3);
+    let any = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 8080);
+    socket::bind(&mock, 3, any).unwrap();
+    socket::listen(&mock, 3, 8).unwrap();
+
+    let mut bind = words(&[3, 1]);
+    bind.extend_from_slice(&[8, 2, 0x1f, 0x90, 0, 0, 0, 0]);
+    bind.resize(36, 0);
+    assert_eq!(
+        mock.requests(),
+        [
+            ioctl(socket::IOCTL_STARTUP, &[], 0),
+            ioctl(socket::IOCTL_GET_HOST_ID, &[], 0),
+            ioctl(socket::IOCTL_SOCKET, &words(&[2, 1, 0]), 0),
+            ioctl(socket::IOCTL_BIND, &bind, 0),
+            ioctl(socket::IOCTL_LISTEN, &words(&[3, 8]), 0),
+        ]
+    );
+}
+
+#[test]
+fn connections() {
+    let mock = Mock::new([
+        reply(0),
+        reply_with(4, &[&PEER_BYTES]),
+        reply_with(0, &[&PEER_BYTES]),
+        reply_with(0, &[&[8, 2, 0x1f, 0x90, 10, 0, 0, 2]]),
+        reply(0),
+        // A peer which isn’t IPv4.
+        reply_with(4, &[&[28, 23]]),
+    ]);
+    socket::connect(&mock, 3, PEER).unwrap();
+    assert_eq!(socket::accept(&mock, 3).unwrap(), (4, PEER));
+    assert_eq!(socket::peer_address(&mock, 4).unwrap(), PEER);
+    assert_eq!(
+        socket::local_address(&mock, 4).unwrap(),
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 8080)
+    );
+    socket::shutdown(&mock, 4, 2).unwrap();
+    assert_eq!(socket::accept(&mock, 3), Err(NetError::Invalid));
+
+    let mut connect = words(&[3, 1]);
+    connect.extend_from_slice(&PEER_BYTES);
+    connect.resize(36, 0);
+    let requests = mock.requests();
+    assert_eq!(requests[0], ioctl(socket::IOCTL_CONNECT, &connect, 0));
+    assert_eq!(requests[1], ioctl(socket::IOCTL_ACCEPT, &words(&[3]), 8));
+    assert_eq!(
+        requests[2],
+        ioctl(socket::IOCTL_GET_PEER_NAME, &words(&[4]), 8)
+    );
+    assert_eq!(
+        requests[3],
+        ioctl(socket::IOCTL_GET_SOCK_NAME, &words(&[4]), 8)
+    );
+    assert_eq!(
+        requests[4],
+        ioctl(socket::IOCTL_SHUTDOWN, &words(&[4, 2]), 0)
+    );
+}
+
+#[test]
+fn nonblocking() {
+    let mock = Mock::new([reply(0x02), reply(0), reply(0x06), reply(0)]);
+    socket::set_nonblocking(&mock, 5, true).unwrap();
+    socket::set_nonblocking(&mock, 5, false).unwrap();
+    assert_eq!(
+        mock.requests(),
+        [
+            ioctl(socket::IOCTL_FCNTL, &words(&[5, 3, 0]), 0),
+            ioctl(socket::IOCTL_FCNTL, &words(&[5, 4, 0x06]), 0),
+            ioctl(socket::IOCTL_FCNTL, &words(&[5, 3, 0]), 0),
+            ioctl(socket::IOCTL_FCNTL, &words(&[5, 4, 0x02]), 0),
+        ]
+    );
+}
+
+#[test]
+fn send_to() {
+    let mock = Mock::new([reply(5), reply(3)]);
+    assert_eq!(socket::send_to(&mock, 6, b"hello", 0, None).unwrap(), 5);
+    assert_eq!(
+        socket::send_to(&mock, 7, b"udp", 0x10, Some(PEER)).unwrap(),
+        3
+    );
+
+    let requests = mock.requests();
+    let mut parameters = words(&[6, 0]);
+    parameters.resize(40, 0);
+    assert_eq!(
+        requests[0],
+        Request {
+            ioctl: socket::IOCTLV_SEND_TO,
+            inputs: vec![b"hello".to_vec(), parameters],
+            outputs: vec![],
+        }
+    );
+    let mut parameters = words(&[7, 0x10, 1]);
+    parameters.extend_from_slice(&PEER_BYTES);
+    parameters.resize(40, 0);
+    assert_eq!(requests[1].inputs[1], parameters);
+}
+
+/// A buffer IOS can write to directly.
+#[repr(C, align(32))]
+struct Aligned([u8; 64]);
+
+#[test]
+fn recv_from() {
+    let mock = Mock::new([
+        reply_with(3, &[b"abc", &PEER_BYTES]),
+        reply_with(4, &[b"defg"]),
+    ]);
+
+    // Straight into an aligned buffer.
+    let mut aligned = Aligned([0; 64]);
+    let (received, from) = socket::recv_from(&mock, 7, &mut aligned.0, 0).unwrap();
+    assert_eq!((received, from), (3, Some(PEER)));
+    assert_eq!(aligned.0[..3], *b"abc");
+    assert_eq!(*mock.buffers.borrow(), 0);
+
+    // Through a buffer of the device otherwise, and without an address once connected.
+    let mut buffer = [0; 10];
+    let (received, from) = socket::recv_from(&mock, 6, &mut buffer[1..], 0x02).unwrap();
+    assert_eq!((received, from), (4, None));
+    assert_eq!(buffer[..6], *b"\0defg\0");
+    assert_eq!(*mock.buffers.borrow(), 1);
+
+    let requests = mock.requests();
+    assert_eq!(
+        requests[0],
+        Request {
+            ioctl: socket::IOCTLV_RECV_FROM,
+            inputs: vec![words(&[7, 0])],
+            outputs: vec![64, 8],
+        }
+    );
+    assert_eq!(requests[1].inputs, [words(&[6, 0x02])]);
+    assert_eq!(requests[1].outputs, [9, 8]);
+}
+
+#[test]
+fn poll() {
+    let mut entries = words(&[3, socket::POLLIN, socket::POLLIN | socket::POLLHUP]);
+    entries.extend(words(&[4, socket::POLLOUT, 0]));
+    let mock = Mock::new([reply_with(1, &[&entries])]);
+    let mut sockets = [
+        PollFd {
+            fd: 3,
+            events: socket::POLLIN,
+            revents: 0,
+        },
+        PollFd {
+            fd: 4,
+            events: socket::POLLOUT,
+            revents: 0xff,
+        },
+    ];
+    assert_eq!(socket::poll(&mock, &mut sockets, 1500).unwrap(), 1);
+    assert_eq!(sockets[0].revents, socket::POLLIN | socket::POLLHUP);
+    assert_eq!(sockets[1].revents, 0);
+    assert_eq!(*mock.buffers.borrow(), 1);
+
+    let timeout = 1500i64.to_be_bytes();
+    assert_eq!(mock.requests(), [ioctl(socket::IOCTL_POLL, &timeout, 24)]);
+}
+
+/// Lay out the result of a lookup as IOS does: the entries first, each pointing to its address
+/// and to the next entry, with the addresses following them.
+fn addr_info(addresses: &[[u8; 4]]) -> Vec<u8> {
+    let mut result = vec![0; socket::ADDR_INFO_SIZE];
+    let addresses_start = 35 * 0x20;
+    for (i, ip) in addresses.iter().enumerate() {
+        let entry = i * 0x20;
+        let address = addresses_start + i * 28;
+        result[entry + 0x04..entry + 0x08].copy_from_slice(&2u32.to_be_bytes());
+        result[entry + 0x18..entry + 0x1c].copy_from_slice(&(BASE + address as u32).to_be_bytes());
+        if i + 1 < addresses.len() {
+            let next = BASE + (entry + 0x20) as u32;
+            result[entry + 0x1c..entry + 0x20].copy_from_slice(&next.to_be_bytes());
+        }
+        result[address..address + 4].copy_from_slice(&[8, 2, 0, 0]);
+        result[address + 4..address + 8].copy_from_slice(ip);
+    }
+    result
+}
+
+#[test]
+fn get_addr_info() {
+    let mut pointing_outside = addr_info(&[[1, 1, 1, 1]]);
+    pointing_outside[0x18..0x1c].copy_from_slice(&(BASE - 8).to_be_bytes());
+    let mock = Mock::new([
+        reply_with(0, &[&addr_info(&[[93, 184, 216, 34], [93, 184, 216, 35]])]),
+        reply(-1),
+        reply_with(0, &[&pointing_outside]),
+    ]);
+    assert_eq!(
+        socket::get_addr_info(&mock, "example.com", 80).unwrap(),
+        [
+            SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 34), 80),
+            SocketAddrV4::new(Ipv4Addr::new(93, 184, 216, 35), 80),
+        ]
+    );
+    // Failing with a status, or without any address which makes sense.
+    assert_eq!(
+        socket::get_addr_info(&mock, "nowhere", 80),
+        Err(NetError::Socket(1))
+    );
+    assert_eq!(
+        socket::get_addr_info(&mock, "nowhere", 80),
+        Err(NetError::NotFound)
+    );
+
+    let requests = mock.requests();
+    let mut hints = vec![0; 32];
+    hints[4..8].copy_from_slice(&2u32.to_be_bytes());
+    assert_eq!(
+        requests[0],
+        Request {
+            ioctl: socket::IOCTLV_GET_ADDR_INFO,
+            inputs: vec![b"example.com\0".to_vec(), vec![], hints],
+            outputs: vec![socket::ADDR_INFO_SIZE],
+        }
+    );
+}
@@ -0,0 +1,132 @@
+//! Parsing of SYSCONF files built with an item of each type, and rejection of truncated ones.
+
+use luma_formats::sysconf::{SysConf, SysConfError};
+
+const BIG_ARRAY: u8 = 1;
+const SMALL_ARRAY: u8 = 2;
+const BYTE: u8 = 3;
+const SHORT: u8 = 4;
+const LONG: u8 = 5;
+const LONG_LONG: u8 = 6;
+const BOOL: u8 = 7;
+
+/// Build a SYSCONF file holding ``items``, as their type, name and value.
+fn sysconf(items: &[(u8, &str, &[u8])]) -> Vec<u8> {
+    let table = 6 + items.len() * 2;
+    let mut data = b"SCv0".to_vec();
+    data.extend_from_slice(&(items.len() as u16).to_be_bytes());
+    data.resize(table, 0);
+    for (index, &(kind, name, value)) in items.iter().enumerate() {
+        let offset = (data.len() as u16).to_be_bytes();
+        data[6 + index * 2..8 + index * 2].copy_from_slice(&offset);
+        data.push(kind << 5 | (name.len() as u8 - 1));
+        data.extend_from_slice(name.as_bytes());
+        match kind {
+            BIG_ARRAY => data.extend_from_slice(&(value.len() as u16 - 1).to_be_bytes()),
+            SMALL_ARRAY => data.push(value.len() as u8 - 1),
+            _ => (),
+        }
+        data.extend_from_slice(value);
+    }
+    data
+}
+
+fn settings() -> Vec<u8> {
+    sysconf(&[
+        (BYTE, "IPL.LNG", &[1]),
+        (LONG, "IPL.CB", &[0x12, 0x34, 0x56, 0x78]),
+        (BOOL, "IPL.AR", &[1]),
+        (SHORT, "IPL.DH", &[0x01, 0x02]),
+        (LONG_LONG, "IPL.CD2", &[1, 2, 3, 4, 5, 6, 7, 8]),
+        (SMALL_ARRAY, "IPL.NIK", b"Luma\0"),
+        (BIG_ARRAY, "BT.DINF", &[0x5a; 0x461]),
+    ])
+}
+
+#[test]
+fn lookup() {
+    let data = settings();
+    let sysconf = SysConf::parse(&data).unwrap();
+    let names: Vec<&[u8]> = sysconf.items().map(|(name, _)| name).collect();
+    assert_eq!(
+        names,
+        [
+            &b"IPL.LNG"[..],
+            b"IPL.CB",
+            b"IPL.AR",
+            b"IPL.DH",
+            b"IPL.CD2",
+            b"IPL.NIK",
+            b"BT.DINF"
+        ]
+    );
+
+    assert_eq!(sysconf.get_u8("IPL.LNG"), Some(1));
+    assert_eq!(sysconf.get_u32("IPL.CB"), Some(0x1234_5678));
+    assert_eq!(sysconf.get("IPL.AR"), Some(&[1][..]));
+    assert_eq!(sysconf.get("IPL.DH"), Some(&[1, 2][..]));
+    assert_eq!(sysconf.get_u32("IPL.CD2"), Some(0x0102_0304));
+    assert_eq!(sysconf.get("IPL.NIK"), Some(&b"Luma\0"[..]));
+    assert_eq!(sysconf.get("BT.DINF"), Some(&[0x5a; 0x461][..]));
+
+    // Names are matched exactly, and values too short for their type aren’t read.
+    assert_eq!(sysconf.get("IPL"), None);
+    assert_eq!(sysconf.get("ipl.lng"), None);
+    assert_eq!(sysconf.get("IPL.LNGX"), None);
+    assert_eq!(sysconf.get_u32("IPL.LNG"), None);
+    assert_eq!(sysconf.get_u8("BT.MOD"), None);
+}
+
+#[test]
+fn empty() {
+    let data = sysconf(&[]);
+    assert_eq!(data.len(), 6);
+    let sysconf = SysConf::parse(&data).unwrap();
+    assert_eq!(sysconf.items().count(), 0);
+    assert_eq!(sysconf.get("IPL.LNG"), None);
+}
+
+#[test]
+fn truncated() {
+    let data = settings();
+    assert_eq!(SysConf::parse(&[]).unwrap_err(), SysConfError::BadMagic);
+    assert_eq!(
+        SysConf::parse(&data[..3]).unwrap_err(),
+        SysConfError::BadMagic
+    );
+    // Cut within the item count, or the table of offsets.
+    for len in 4..6 + 7 * 2 {
+        assert_eq!(
+            SysConf::parse(&data[..len]).unwrap_err(),
+            SysConfError::OutOfBounds,
+            "{len}"
+        );
+    }
+
+    // Cut within the items, those which don’t fit anymore get skipped.
+    let table = 6 + 7 * 2;
+    let sysconf = SysConf::parse(&data[..table]).unwrap();
+    assert_eq!(sysconf.items().count(), 0);
+    let sysconf = SysConf::parse(&data[..data.len() - 1]).unwrap();
+    assert_eq!(sysconf.items().count(), 6);
+    assert_eq!(sysconf.get("BT.DINF"), None);
+    assert_eq!(sysconf.get_u8("IPL.LNG"), Some(1));
+}
+
+#[test]
+fn malformed() {
+    let mut data = b"SCv1".to_vec();
+    data.extend_from_slice(&settings()[4..]);
+    assert_eq!(SysConf::parse(&data).unwrap_err(), SysConfError::BadMagic);
+
+    // An item of an unknown type, and an offset past the end of the file, get skipped.
+    let mut data = settings();
+    let first = u16::from_be_bytes([data[6], data[7]]) as usize;
+    data[first] &= 0x1f;
+    data[8..10].copy_from_slice(&u16::MAX.to_be_bytes());
+    let sysconf = SysConf::parse(&data).unwrap();
+    assert_eq!(sysconf.items().count(), 5);
+    assert_eq!(sysconf.get("IPL.LNG"), None);
+    assert_eq!(sysconf.get("IPL.CB"), None);
+    assert_eq!(sysconf.get_u8("IPL.AR"), Some(1));
+}
@@ -0,0 +1,137 @@
+//! Parsing of ``fixtures/textures.tpl``, which holds two 8×8 images:
+//!
+//! - an RGBA8 one with four mipmap levels, whose texel (x, y) is ``(x × 32, y × 32, 0x80, 0xff)``
+//!   in its base level,
+//! - a C4 one whose texel (x, y) is ``(x + y) % 16``, with a palette of 16 RGB5A3 entries.
+
+use luma_formats::gx::encode::{decode, decode_indexed};
+use luma_formats::gx::texture::{TexFilter, TexFmt, TlutFmt, WrapMode};
+use luma_formats::gx::tpl::{Tpl, TplError};
+
+const FIXTURE: &[u8] = include_bytes!("fixtures/textures.tpl");
+
+#[test]
+fn rgba8_image() {
+    let tpl = Tpl::parse(FIXTURE).unwrap();
+    assert_eq!(tpl.len(), 2);
+
+    let image = tpl.image(0).unwrap();
+    assert_eq!((image.width, image.height), (8, 8));
+    assert_eq!(image.format, TexFmt::RGBA8);
+    assert_eq!(image.levels, 4);
+    assert_eq!(image.data.len(), TexFmt::RGBA8.mipmap_size(8, 8, 4));
+    assert_eq!(
+        (image.wrap_s, image.wrap_t),
+        (WrapMode::Clamp, WrapMode::Mirror)
+    );
+    assert_eq!(image.min_filter, TexFilter::LinMipLin);
+    assert_eq!(image.mag_filter, TexFilter::Linear);
+    assert_eq!(image.lod_bias, 0.5);
+    assert!(image.edge_lod);
+    assert_eq!((image.min_lod, image.max_lod), (0, 3));
+    assert!(image.palette.is_none());
+
+    let expected: Vec<u8> = (0..64)
+        .flat_map(|i| [(i % 8) as u8 * 32, (i / 8) as u8 * 32, 0x80, 0xff])
+        .collect();
+    assert_eq!(decode(TexFmt::RGBA8, image.data, 8, 8), expected);
+}
+
+#[test]
+fn indexed_image() {
+    let tpl = Tpl::parse(FIXTURE).unwrap();
+    let image = tpl.image(1).unwrap();
+    assert_eq!(image.format, TexFmt::C4);
+    assert_eq!(image.levels, 1);
+    assert_eq!(
+        (image.wrap_s, image.wrap_t),
+        (WrapMode::Repeat, WrapMode::Repeat)
+    );
+    assert_eq!(image.min_filter, TexFilter::Near);
+
+    let expected: Vec<u16> = (0..64).map(|i| (i % 8 + i / 8) % 16).collect();
+    assert_eq!(decode_indexed(TexFmt::C4, image.data, 8, 8), expected);
+
+    let palette = image.palette.unwrap();
+    assert_eq!(palette.format, TlutFmt::RGB5A3);
+    assert_eq!(palette.entries(), 16);
+    assert_eq!(&palette.data[..4], &[0x80, 0x1f, 0x84, 0x1d]);
+}
+
+#[test]
+fn images() {
+    let tpl = Tpl::parse(FIXTURE).unwrap();
+    let formats: Vec<TexFmt> = tpl.images().map(|image| image.unwrap().format).collect();
+    assert_eq!(formats, [TexFmt::RGBA8, TexFmt::C4]);
+    assert_eq!(tpl.image(2).unwrap_err(), TplError::NoSuchImage);
+}
+
+/// A corruption of the fixture.
+type Patch = fn(&mut Vec<u8>);
+
+/// Parse the first image of the fixture, with ``patch`` applied to it.
+fn first_image(patch: impl FnOnce(&mut Vec<u8>)) -> Result<TexFmt, TplError> {
+    let mut data = FIXTURE.to_vec();
+    patch(&mut data);
+    Ok(Tpl::parse(&data)?.image(0)?.format)
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+#[test]
+fn truncated() {
+    assert_eq!(
+        Tpl::parse(&FIXTURE[..8]).unwrap_err(),
+        TplError::OutOfBounds
+    );
+    assert_eq!(
+        Tpl::parse(&FIXTURE[..0x10]).unwrap_err(),
+        TplError::OutOfBounds
+    );
+    // Headers are all there, but not the texture data.
+    let tpl = Tpl::parse(&FIXTURE[..0x100]).unwrap();
+    assert_eq!(tpl.image(0).unwrap_err(), TplError::OutOfBounds);
+    assert_eq!(tpl.image(1).unwrap_err(), TplError::OutOfBounds);
+}
+
+#[test]
+fn corrupt() {
+    let cases: [(Patch, TplError); 9] = [
+        (|data| data[1] = 0, TplError::BadMagic),
+        // More images than the table can hold.
+        (|data| put_u32(data, 4, u32::MAX), TplError::OutOfBounds),
+        // Image header past the end.
+        (
+            |data| put_u32(data, 0x0c, 0xffff_fff0),
+            TplError::OutOfBounds,
+        ),
+        // Texture data offset past the end.
+        (|data| put_u32(data, 0x28, u32::MAX), TplError::OutOfBounds),
+        // Width of zero.
+        (|data| data[0x23] = 0, TplError::BadSize),
+        (|data| put_u32(data, 0x24, 7), TplError::UnknownFormat(7)),
+        (|data| put_u32(data, 0x2c, 3), TplError::BadSampler),
+        // Mipmaps as a magnification filter.
+        (|data| put_u32(data, 0x38, 2), TplError::BadSampler),
+        // An indexed format without a palette.
+        (|data| put_u32(data, 0x24, 9), TplError::MissingPalette),
+    ];
+    for (patch, error) in cases {
+        assert_eq!(first_image(patch), Err(error));
+    }
+}
+
+#[test]
+fn corrupt_palette() {
+    let mut data = FIXTURE.to_vec();
+    put_u32(&mut data, 0x6c, 3);
+    let tpl = Tpl::parse(&data).unwrap();
+    assert_eq!(tpl.image(1).unwrap_err(), TplError::UnknownPaletteFormat(3));
+
+    let mut data = FIXTURE.to_vec();
+    data[0x68..0x6a].copy_from_slice(&0x100u16.to_be_bytes());
+    let tpl = Tpl::parse(&data).unwrap();
+    assert_eq!(tpl.image(1).unwrap_err(), TplError::OutOfBounds);
+}
@@ -0,0 +1,94 @@
+//! Decoding of ``fixtures/stereo.ogg``, a stereo Vorbis stream at 44100 Hz using channel
+//! coupling, two floors and two residues, against ``fixtures/stereo.pcm``, its interleaved
+//! samples as decoded by a reference implementation, in little-endian 16-bit.
+
+use luma_formats::audio::ogg::OggError;
+use luma_formats::audio::vorbis::{Vorbis, VorbisError};
+
+const FIXTURE: &[u8] = include_bytes!("fixtures/stereo.ogg");
+const REFERENCE: &[u8] = include_bytes!("fixtures/stereo.pcm");
+
+/// Offset of the second page of the fixture, holding the comment and setup headers.
+const SECOND_PAGE: usize = 58;
+
+fn reference() -> Vec<i16> {
+    REFERENCE
+        .chunks_exact(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect()
+}
+
+/// Check that ``samples`` match the reference, up to rounding.
+fn assert_matches_reference(samples: &[i16]) {
+    let reference = reference();
+    assert_eq!(samples.len(), reference.len());
+    for (i, (&sample, &expected)) in samples.iter().zip(&reference).enumerate() {
+        assert!(
+            (sample as i32 - expected as i32).abs() <= 1,
+            "sample {i}: {sample} instead of {expected}"
+        );
+    }
+}
+
+#[test]
+fn headers() {
+    let vorbis = Vorbis::new(FIXTURE).unwrap();
+    assert_eq!(vorbis.channels(), 2);
+    assert_eq!(vorbis.sample_rate(), 44100);
+    assert_eq!(vorbis.ai_sample_rate(), None);
+}
+
+#[test]
+fn decode_all() {
+    let mut vorbis = Vorbis::new(FIXTURE).unwrap();
+    let samples = vorbis.decode_all().unwrap();
+    // The last page trims the stream to its granule position.
+    assert_eq!(samples.len(), 2897 * 2);
+    assert_matches_reference(&samples);
+}
+
+#[test]
+fn small_reads() {
+    let mut vorbis = Vorbis::new(FIXTURE).unwrap();
+    let mut samples = Vec::new();
+    // An odd buffer size, which only ever gets whole frames.
+    let mut buffer = [0; 77];
+    loop {
+        let count = vorbis.read(&mut buffer).unwrap();
+        assert_eq!(count % 2, 0);
+        if count == 0 {
+            break;
+        }
+        samples.extend_from_slice(&buffer[..count]);
+    }
+    assert_matches_reference(&samples);
+}
+
+#[test]
+fn stereo_and_rewind() {
+    let mut vorbis = Vorbis::new(FIXTURE).unwrap();
+    let first = vorbis.decode_all().unwrap();
+    assert_eq!(vorbis.read(&mut [0; 16]), Ok(0));
+
+    vorbis.rewind().unwrap();
+    assert_eq!(vorbis.decode_all_stereo().unwrap(), first);
+}
+
+#[test]
+fn broken_stream() {
+    assert_eq!(
+        Vorbis::new(&FIXTURE[..SECOND_PAGE]).err(),
+        Some(VorbisError::MissingHeader)
+    );
+    assert_eq!(
+        Vorbis::new(&FIXTURE[..SECOND_PAGE + 100]).err(),
+        Some(VorbisError::Ogg(OggError::OutOfBounds))
+    );
+
+    let mut file = FIXTURE.to_vec();
+    file[SECOND_PAGE + 200] ^= 1;
+    assert_eq!(
+        Vorbis::new(&file).err(),
+        Some(VorbisError::Ogg(OggError::BadChecksum))
+    );
+}
@@ -0,0 +1,103 @@
+//! Parsing of two WAVE files:
+//!
+//! - ``fixtures/stereo16.wav``, a 16-bit extensible one at 22050 Hz, whose frame i is
+//!   ``(i × 1000 - 24000, -i × 683)``, with an odd-sized ``LIST`` chunk before its samples,
+//! - ``fixtures/mono8.wav``, an 8-bit one at 8000 Hz, whose sample i is ``i × 8``, followed by
+//!   a ``cue `` chunk.
+
+use luma_formats::audio::wav::{Wav, WavError};
+
+const STEREO16: &[u8] = include_bytes!("fixtures/stereo16.wav");
+const MONO8: &[u8] = include_bytes!("fixtures/mono8.wav");
+
+/// Offset of the size of the ``LIST`` chunk in ``STEREO16``.
+const STEREO16_LIST_SIZE: usize = 64;
+/// Offset of the size of the ``data`` chunk in ``MONO8``.
+const MONO8_DATA_SIZE: usize = 40;
+
+fn patched(file: &[u8], offset: usize, bytes: &[u8]) -> Vec<u8> {
+    let mut file = file.to_vec();
+    file[offset..offset + bytes.len()].copy_from_slice(bytes);
+    file
+}
+
+#[test]
+fn stereo16() {
+    let wav = Wav::parse(STEREO16).unwrap();
+    assert_eq!(wav.channels(), 2);
+    assert_eq!(wav.sample_rate(), 22050);
+    assert_eq!(wav.bits_per_sample(), 16);
+    assert_eq!(wav.frames(), 48);
+
+    let expected: Vec<i16> = (0..48i32)
+        .flat_map(|i| [(i * 1000 - 24000) as i16, (-i * 683) as i16])
+        .collect();
+    assert_eq!(wav.to_pcm16(), expected);
+}
+
+#[test]
+fn mono8() {
+    let wav = Wav::parse(MONO8).unwrap();
+    assert_eq!(wav.channels(), 1);
+    assert_eq!(wav.sample_rate(), 8000);
+    assert_eq!(wav.bits_per_sample(), 8);
+    assert_eq!(wav.frames(), 33);
+
+    let expected: Vec<i16> = (0..33)
+        .map(|i| ((((i * 8) & 0xff) as i16) - 128) << 8)
+        .collect();
+    assert_eq!(wav.to_pcm16(), expected);
+}
+
+#[test]
+fn partial_frame() {
+    // Drop the last byte of the samples, so that the last frame is incomplete.
+    let file = patched(STEREO16, STEREO16.len() - 192 - 4, &191u32.to_le_bytes());
+    let wav = Wav::parse(&file[..file.len() - 1]).unwrap();
+    assert_eq!(wav.frames(), 47);
+    assert_eq!(wav.to_pcm16().len(), 94);
+}
+
+#[test]
+fn not_wave() {
+    assert_eq!(Wav::parse(&STEREO16[..11]).err(), Some(WavError::NotWave));
+    let file = patched(STEREO16, 8, b"AVI ");
+    assert_eq!(Wav::parse(&file).err(), Some(WavError::NotWave));
+}
+
+#[test]
+fn chunk_out_of_bounds() {
+    assert_eq!(
+        Wav::parse(&MONO8[..MONO8_DATA_SIZE + 20]).err(),
+        Some(WavError::OutOfBounds)
+    );
+    for size in [0xffff_fff0, u32::MAX] {
+        let file = patched(STEREO16, STEREO16_LIST_SIZE, &u32::to_le_bytes(size));
+        assert_eq!(Wav::parse(&file).err(), Some(WavError::OutOfBounds));
+    }
+}
+
+#[test]
+fn missing_chunk() {
+    let file = patched(MONO8, MONO8_DATA_SIZE - 4, b"junk");
+    assert_eq!(Wav::parse(&file).err(), Some(WavError::MissingChunk));
+    let file = patched(MONO8, 12, b"junk");
+    assert_eq!(Wav::parse(&file).err(), Some(WavError::MissingChunk));
+}
+
+#[test]
+fn unsupported_format() {
+    let file = patched(MONO8, 20, &3u16.to_le_bytes());
+    assert_eq!(Wav::parse(&file).err(), Some(WavError::UnknownFormat(3)));
+    // The subformat of an extensible file.
+    let file = patched(STEREO16, 44, &3u16.to_le_bytes());
+    assert_eq!(Wav::parse(&file).err(), Some(WavError::UnknownFormat(3)));
+
+    let file = patched(MONO8, 22, &6u16.to_le_bytes());
+    assert_eq!(
+        Wav::parse(&file).err(),
+        Some(WavError::UnsupportedChannels(6))
+    );
+    let file = patched(MONO8, 34, &24u16.to_le_bytes());
+    assert_eq!(Wav::parse(&file).err(), Some(WavError::UnsupportedBits(24)));
+}
@@ -0,0 +1,215 @@
+//! Decoding of input reports as captured from a Wii Remote with a Nunchuk plugged in, and
+//! encoding of the output reports sent to it.
+
+use luma_formats::wpad::report::{
+    self, Accel, Buttons, Extension, ExtensionKind, IrDot, Nunchuk, Report,
+};
+
+/// The six bytes a Nunchuk reports: its stick slightly up-right, the C button released and
+/// the Z button pressed.
+const NUNCHUK: [u8; 6] = [0x80, 0x7f, 0x85, 0x84, 0xb3, 0x16];
+
+#[test]
+fn status() {
+    let report = [0xa1, 0x20, 0x00, 0x08, 0x02, 0x00, 0x00, 0xc8];
+    assert_eq!(
+        Report::parse(&report),
+        Some(Report::Status {
+            buttons: Buttons::A,
+            flags: report::STATUS_EXTENSION,
+            battery: 0xc8,
+        })
+    );
+    assert_eq!(Report::parse(&report[..7]), None);
+}
+
+#[test]
+fn read_data() {
+    // The identifier of the extension, read from 0xa400fa.
+    let mut report = vec![0xa1, 0x21, 0x00, 0x00, 0x50, 0x00, 0xfa];
+    report.extend_from_slice(&[0x00, 0x00, 0xa4, 0x20, 0x00, 0x00]);
+    report.extend_from_slice(&[0; 10]);
+    let Some(Report::ReadData {
+        buttons,
+        error,
+        address,
+        data,
+    }) = Report::parse(&report)
+    else {
+        panic!("not a read data report");
+    };
+    assert_eq!(buttons, Buttons::empty());
+    assert_eq!(error, 0);
+    assert_eq!(address, 0x00fa);
+    assert_eq!(
+        ExtensionKind::identify(data.try_into().unwrap()),
+        ExtensionKind::Nunchuk
+    );
+    assert_eq!(Report::parse(&report[..22]), None);
+}
+
+#[test]
+fn acknowledge() {
+    let report = [0xa1, 0x22, 0x10, 0x00, 0x16, 0x00];
+    assert_eq!(
+        Report::parse(&report),
+        Some(Report::Acknowledge {
+            buttons: Buttons::PLUS,
+            report: report::REPORT_WRITE,
+            error: 0,
+        })
+    );
+}
+
+#[test]
+fn accel_and_extended_ir() {
+    // The lowest bits of the accelerometer hide in the bits of the buttons nothing uses.
+    let mut report = vec![0xa1, 0x33, 0x40, 0x28, 0x82, 0x7f, 0x9a];
+    report.extend_from_slice(&[0x5f, 0x2e, 0x36]);
+    report.extend_from_slice(&[0xff; 9]);
+    let Some(Report::Data {
+        buttons,
+        accel,
+        ir,
+        extension,
+    }) = Report::parse(&report)
+    else {
+        panic!("not a data report");
+    };
+    assert_eq!(buttons, Buttons::A);
+    assert_eq!(
+        accel,
+        Some(Accel {
+            x: 0x20a,
+            y: 0x1fe,
+            z: 0x268,
+        })
+    );
+    assert!(extension.is_empty());
+    assert_eq!(
+        report::parse_ir(ir),
+        [
+            Some(IrDot {
+                x: 0x35f,
+                y: 0x02e,
+                size: Some(6),
+            }),
+            None,
+            None,
+            None,
+        ]
+    );
+    assert_eq!(Report::parse(&report[..report.len() - 1]), None);
+}
+
+#[test]
+fn basic_ir_and_nunchuk() {
+    let mut report = vec![0xa1, 0x37, 0x01, 0x00, 0x80, 0x80, 0x80];
+    // Two dots then two missing ones, sharing their high bits.
+    report.extend_from_slice(&[0x10, 0x20, 0x9c, 0x30, 0x40]);
+    report.extend_from_slice(&[0xff; 5]);
+    report.extend_from_slice(&NUNCHUK);
+    let Some(Report::Data {
+        buttons,
+        accel,
+        ir,
+        extension,
+    }) = Report::parse(&report)
+    else {
+        panic!("not a data report");
+    };
+    assert_eq!(buttons, Buttons::LEFT);
+    assert_eq!(
+        accel,
+        Some(Accel {
+            x: 0x200,
+            y: 0x200,
+            z: 0x200,
+        })
+    );
+    assert_eq!(
+        report::parse_ir(ir),
+        [
+            Some(IrDot {
+                x: 0x110,
+                y: 0x220,
+                size: None,
+            }),
+            Some(IrDot {
+                x: 0x030,
+                y: 0x340,
+                size: None,
+            }),
+            None,
+            None,
+        ]
+    );
+    assert_eq!(
+        ExtensionKind::Nunchuk.parse(extension),
+        Some(Extension::Nunchuk(Nunchuk {
+            stick: [0x80, 0x7f],
+            accel: Accel {
+                x: 0x215,
+                y: 0x211,
+                z: 0x2cc,
+            },
+            c: false,
+            z: true,
+        }))
+    );
+    assert_eq!(ExtensionKind::Unknown([0; 6]).parse(extension), None);
+    assert_eq!(ExtensionKind::Nunchuk.parse(&extension[..5]), None);
+}
+
+#[test]
+fn buttons_only() {
+    let report = [0xa1, 0x30, 0x11, 0x80];
+    let Some(Report::Data {
+        buttons,
+        accel: None,
+        ir: [],
+        extension: [],
+    }) = Report::parse(&report)
+    else {
+        panic!("not a data report");
+    };
+    assert_eq!(buttons, Buttons::PLUS | Buttons::LEFT | Buttons::HOME);
+}
+
+#[test]
+fn other_reports() {
+    assert_eq!(
+        Report::parse(&[0xa1, 0x3d, 0x00]),
+        Some(Report::Other(0x3d))
+    );
+    assert_eq!(
+        Report::parse(&[0xa1, 0x3f, 0x00, 0x00]),
+        Some(Report::Other(0x3f))
+    );
+    // Not an input report.
+    assert_eq!(Report::parse(&[0xa2, 0x20, 0, 0, 0, 0, 0, 0]), None);
+    assert_eq!(Report::parse(&[0xa1]), None);
+    assert_eq!(Report::parse(&[]), None);
+}
+
+#[test]
+fn output_reports() {
+    assert_eq!(report::set_leds(0b1001, true), [0xa2, 0x11, 0x91]);
+    assert_eq!(
+        report::set_mode(report::MODE_ACCEL_IR_EXTENSION, true, false),
+        [0xa2, 0x12, 0x04, 0x37]
+    );
+    assert_eq!(report::request_status(false), [0xa2, 0x15, 0x00]);
+    assert_eq!(
+        report::set_ir(true, false),
+        [[0xa2, 0x13, 0x04], [0xa2, 0x1a, 0x04]]
+    );
+    assert_eq!(
+        report::read_registers(report::EXTENSION_ID, 6, false),
+        [0xa2, 0x17, 0x04, 0xa4, 0x00, 0xfa, 0x00, 0x06]
+    );
+
+    let write = report::write_registers(0xa4_00f0, &[0x55], false);
+    assert_eq!(write[..8], [0xa2, 0x16, 0x04, 0xa4, 0x00, 0xf0, 0x01, 0x55]);
+    assert!(write[8..].iter().all(|&byte| byte == 0));
+}
@@ -0,0 +1,73 @@
+//! ``luma_romfs`` is the read-only filesystem image format used to bundle assets into
+//! executables.
+//!
+//! Images are packed on the host with a [`Builder`], or from a whole directory with
+//! ``pack_dir`` when the ``std`` feature is enabled, usually from a build script:
+//!
+//! ```ignore
+//! fn main() {
+//!     let image = luma_romfs::pack_dir("assets").unwrap();
+//!     let out = std::env::var("OUT_DIR").unwrap();
+//!     std::fs::write(format!("{out}/assets.romfs"), image).unwrap();
+//!     println!("cargo:rerun-if-changed=assets");
+//! }
+//! ```
+//!
+//! They are then read in place with a [`RomFs`], which hands out file contents as slices of
+//! the image itself.  Nothing in here depends on the console, so both sides work on the host.
+//!
+//! # Format:
+//!
+//! All integers are big endian.  The image starts with a 16 bytes header:
+//!
+//! | Offset | Size | Contents                                    |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 4    | [`MAGIC`]                                   |
+//! | 4      | 2    | [`VERSION`]                                 |
+//! | 6      | 2    | Reserved, zero                              |
+//! | 8      | 4    | Amount of entries                           |
+//! | 12     | 4    | Size of the name table                      |
+//!
+//! It is followed by the entries, 16 bytes each, then by the name table holding the UTF-8
+//! names of the entries back to back, then by the contents of the files:
+//!
+//! | Offset | Size | Contents                                                        |
+//! |--------|------|-----------------------------------------------------------------|
+//! | 0      | 4    | Offset of the name in the name table                            |
+//! | 4      | 2    | Length of the name                                              |
+//! | 6      | 1    | Kind, [`KIND_FILE`] or [`KIND_DIR`]                             |
+//! | 7      | 1    | Reserved, zero                                                  |
+//! | 8      | 4    | Offset of the contents in the image, or index of the first child |
+//! | 12     | 4    | Size of the contents, or amount of children                     |
+//!
+//! The first entry is the root directory, with an empty name.  The children of a directory
+//! follow each other, sorted by name, and the contents of each file are aligned to
+//! [`ALIGNMENT`] bytes from the start of the image.
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+mod pack;
+mod read;
+
+#[cfg(feature = "std")]
+pub use pack::pack_dir;
+pub use pack::{Builder, PackError};
+pub use read::{Entries, Entry, RomFs, RomFsError};
+
+/// Magic number at the start of every image.
+pub const MAGIC: [u8; 4] = *b"RMFS";
+
+/// Version of the format described above.
+pub const VERSION: u16 = 1;
+
+/// Alignment of the contents of the files, a cache line of the console.
+pub const ALIGNMENT: usize = 32;
+
+pub const KIND_FILE: u8 = 0;
+pub const KIND_DIR: u8 = 1;
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
@@ -0,0 +1,222 @@
+//! ``pack`` module of ``luma_romfs``.
+//!
+//! Contains the packing of images out of files kept in memory, or out of a directory of the
+//! host.
+
+use crate::{ALIGNMENT, ENTRY_SIZE, HEADER_SIZE, KIND_DIR, KIND_FILE, MAGIC, VERSION};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Why a file couldn’t be added, or an image couldn’t be packed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackError {
+    /// The path is empty, or one of its components is ``.``, ``..`` or too long.
+    InvalidPath,
+    /// A file or directory already exists at the path.
+    AlreadyExists,
+    /// One of the parents of the path is a file.
+    NotADirectory,
+    /// The image would be larger than 4 GiB.
+    TooLarge,
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackError::InvalidPath => write!(f, "invalid path"),
+            PackError::AlreadyExists => write!(f, "entry already exists"),
+            PackError::NotADirectory => write!(f, "parent is not a directory"),
+            PackError::TooLarge => write!(f, "image too large"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PackError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for crate::RomFsError {}
+
+enum Node {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Node>),
+}
+
+/// An image being put together, from paths whose components are separated by slashes.
+#[derive(Default)]
+pub struct Builder {
+    root: BTreeMap<String, Node>,
+}
+
+/// An entry being laid out, see the format in the crate documentation.
+struct Packed<'a> {
+    name: &'a str,
+    kind: u8,
+    start: usize,
+    count: usize,
+    data: &'a [u8],
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Get the directory which should hold ``path``, creating it as needed, along with the
+    /// name of the entry in it.
+    fn parent<'p>(
+        &mut self,
+        path: &'p str,
+    ) -> Result<(&mut BTreeMap<String, Node>, &'p str), PackError> {
+        let mut components = path.split('/').filter(|component| !component.is_empty());
+        let mut name = components.next().ok_or(PackError::InvalidPath)?;
+        let mut dir = &mut self.root;
+        for component in components {
+            if matches!(name, "." | "..") || name.len() > u16::MAX as usize {
+                return Err(PackError::InvalidPath);
+            }
+            let node = dir
+                .entry(String::from(name))
+                .or_insert_with(|| Node::Dir(BTreeMap::new()));
+            dir = match node {
+                Node::Dir(children) => children,
+                Node::File(_) => return Err(PackError::NotADirectory),
+            };
+            name = component;
+        }
+        if matches!(name, "." | "..") || name.len() > u16::MAX as usize {
+            return Err(PackError::InvalidPath);
+        }
+        Ok((dir, name))
+    }
+
+    /// Add a file at ``path``, creating its parent directories as needed.
+    pub fn add_file(&mut self, path: &str, data: impl Into<Vec<u8>>) -> Result<(), PackError> {
+        let (dir, name) = self.parent(path)?;
+        if dir.contains_key(name) {
+            return Err(PackError::AlreadyExists);
+        }
+        dir.insert(String::from(name), Node::File(data.into()));
+        Ok(())
+    }
+
+    /// Add a directory at ``path``, creating its parent directories as needed.  Adding one
+    /// which already exists does nothing.
+    pub fn add_dir(&mut self, path: &str) -> Result<(), PackError> {
+        let (dir, name) = self.parent(path)?;
+        match dir.get(name) {
+            Some(Node::Dir(_)) => Ok(()),
+            Some(Node::File(_)) => Err(PackError::AlreadyExists),
+            None => {
+                dir.insert(String::from(name), Node::Dir(BTreeMap::new()));
+                Ok(())
+            }
+        }
+    }
+
+    /// Pack everything added so far into an image.
+    pub fn build(&self) -> Result<Vec<u8>, PackError> {
+        let mut entries = Vec::from([Packed {
+            name: "",
+            kind: KIND_DIR,
+            start: 0,
+            count: 0,
+            data: &[],
+        }]);
+
+        // Breadth first, so that the children of each directory follow each other.
+        let mut queue = VecDeque::from([(0, &self.root)]);
+        while let Some((index, children)) = queue.pop_front() {
+            entries[index].start = entries.len();
+            entries[index].count = children.len();
+            for (name, node) in children {
+                let (kind, data) = match node {
+                    Node::File(data) => (KIND_FILE, data.as_slice()),
+                    Node::Dir(children) => {
+                        queue.push_back((entries.len(), children));
+                        (KIND_DIR, &[][..])
+                    }
+                };
+                entries.push(Packed {
+                    name,
+                    kind,
+                    start: 0,
+                    count: 0,
+                    data,
+                });
+            }
+        }
+
+        let names_size: usize = entries.iter().map(|entry| entry.name.len()).sum();
+        let mut size = HEADER_SIZE + entries.len() * ENTRY_SIZE + names_size;
+        for entry in entries.iter_mut().filter(|entry| entry.kind == KIND_FILE) {
+            entry.start = size.next_multiple_of(ALIGNMENT);
+            entry.count = entry.data.len();
+            size = entry.start + entry.count;
+        }
+        if size > u32::MAX as usize {
+            return Err(PackError::TooLarge);
+        }
+
+        let mut image = Vec::with_capacity(size);
+        image.extend_from_slice(&MAGIC);
+        image.extend_from_slice(&VERSION.to_be_bytes());
+        image.extend_from_slice(&[0; 2]);
+        image.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        image.extend_from_slice(&(names_size as u32).to_be_bytes());
+        let mut name_offset = 0;
+        for entry in &entries {
+            image.extend_from_slice(&(name_offset as u32).to_be_bytes());
+            image.extend_from_slice(&(entry.name.len() as u16).to_be_bytes());
+            image.extend_from_slice(&[entry.kind, 0]);
+            image.extend_from_slice(&(entry.start as u32).to_be_bytes());
+            image.extend_from_slice(&(entry.count as u32).to_be_bytes());
+            name_offset += entry.name.len();
+        }
+        for entry in &entries {
+            image.extend_from_slice(entry.name.as_bytes());
+        }
+        for entry in entries.iter().filter(|entry| entry.kind == KIND_FILE) {
+            image.resize(entry.start, 0);
+            image.extend_from_slice(entry.data);
+        }
+        Ok(image)
+    }
+}
+
+/// Pack the whole contents of the directory at ``path`` into an image, following symbolic
+/// links.
+#[cfg(feature = "std")]
+pub fn pack_dir(path: impl AsRef<std::path::Path>) -> std::io::Result<Vec<u8>> {
+    let mut builder = Builder::new();
+    add_dir_contents(&mut builder, path.as_ref(), "")?;
+    builder.build().map_err(std::io::Error::other)
+}
+
+#[cfg(feature = "std")]
+fn add_dir_contents(
+    builder: &mut Builder,
+    dir: &std::path::Path,
+    prefix: &str,
+) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "file name is not UTF-8"))?;
+        let path = alloc::format!("{prefix}/{name}");
+        if std::fs::metadata(entry.path())?.is_dir() {
+            builder.add_dir(&path).map_err(Error::other)?;
+            add_dir_contents(builder, &entry.path(), &path)?;
+        } else {
+            let data = std::fs::read(entry.path())?;
+            builder.add_file(&path, data).map_err(Error::other)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,277 @@
+//! ``read`` module of ``luma_romfs``.
+//!
+//! Contains the reading of images in place, without copying anything out of them.
+
+use crate::{ENTRY_SIZE, HEADER_SIZE, KIND_DIR, KIND_FILE, MAGIC, VERSION};
+use core::cmp::Ordering;
+use core::fmt::{self, Debug};
+
+/// Why an image couldn’t be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RomFsError {
+    /// The data doesn’t start with [`MAGIC`], as when there is no image at all.
+    BadMagic,
+    /// The image is of another version of the format.
+    UnsupportedVersion,
+    /// Something in the image points outside of it.
+    Corrupted,
+}
+
+impl fmt::Display for RomFsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomFsError::BadMagic => write!(f, "not a romfs image"),
+            RomFsError::UnsupportedVersion => write!(f, "unsupported romfs version"),
+            RomFsError::Corrupted => write!(f, "corrupted romfs image"),
+        }
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// An entry as stored in the image.
+struct Raw {
+    name_offset: usize,
+    name_length: usize,
+    kind: u8,
+    /// Offset of the contents of a file, or index of the first child of a directory.
+    start: usize,
+    /// Size of the contents of a file, or amount of children of a directory.
+    count: usize,
+}
+
+/// An image, checked once when parsed so that walking it later can’t fail.
+#[derive(Clone, Copy)]
+pub struct RomFs<'a> {
+    image: &'a [u8],
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> RomFs<'a> {
+    /// Parse an image, checking that every entry stays within it.
+    pub fn parse(image: &'a [u8]) -> Result<RomFs<'a>, RomFsError> {
+        if image.len() < HEADER_SIZE || image[0..4] != MAGIC {
+            return Err(RomFsError::BadMagic);
+        }
+        if u16_at(image, 4) != VERSION {
+            return Err(RomFsError::UnsupportedVersion);
+        }
+        let count = u32_at(image, 8) as usize;
+        let names_size = u32_at(image, 12) as usize;
+        let names_start = count
+            .checked_mul(ENTRY_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .ok_or(RomFsError::Corrupted)?;
+        let names_end = names_start
+            .checked_add(names_size)
+            .ok_or(RomFsError::Corrupted)?;
+        if count == 0 || names_end > image.len() {
+            return Err(RomFsError::Corrupted);
+        }
+
+        let fs = RomFs {
+            image,
+            entries: &image[HEADER_SIZE..names_start],
+            names: &image[names_start..names_end],
+        };
+        if fs.raw(0).kind != KIND_DIR {
+            return Err(RomFsError::Corrupted);
+        }
+        for index in 0..count {
+            fs.check(index)?;
+        }
+        Ok(fs)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    fn raw(&self, index: usize) -> Raw {
+        let entry = &self.entries[index * ENTRY_SIZE..][..ENTRY_SIZE];
+        Raw {
+            name_offset: u32_at(entry, 0) as usize,
+            name_length: u16_at(entry, 4) as usize,
+            kind: entry[6],
+            start: u32_at(entry, 8) as usize,
+            count: u32_at(entry, 12) as usize,
+        }
+    }
+
+    fn check(&self, index: usize) -> Result<(), RomFsError> {
+        let raw = self.raw(index);
+        let name = raw
+            .name_offset
+            .checked_add(raw.name_length)
+            .and_then(|end| self.names.get(raw.name_offset..end))
+            .ok_or(RomFsError::Corrupted)?;
+        core::str::from_utf8(name).map_err(|_| RomFsError::Corrupted)?;
+        let end = raw
+            .start
+            .checked_add(raw.count)
+            .ok_or(RomFsError::Corrupted)?;
+        let valid = match raw.kind {
+            KIND_FILE => end <= self.image.len(),
+            // Children come after their parent, so walking down the tree always ends.
+            KIND_DIR => raw.count == 0 || (raw.start > index && end <= self.len()),
+            _ => false,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(RomFsError::Corrupted)
+        }
+    }
+
+    fn name(&self, index: usize) -> &'a str {
+        let raw = self.raw(index);
+        let name = &self.names[raw.name_offset..raw.name_offset + raw.name_length];
+        // Checked in parse().
+        core::str::from_utf8(name).unwrap_or_default()
+    }
+
+    fn entry(&self, index: usize) -> Entry<'a> {
+        Entry { fs: *self, index }
+    }
+
+    /// Get the whole image.
+    pub fn image(&self) -> &'a [u8] {
+        self.image
+    }
+
+    /// Get the root directory.
+    pub fn root(&self) -> Entry<'a> {
+        self.entry(0)
+    }
+
+    /// Find the entry at ``path``, whose components are separated by slashes.  Empty
+    /// components are skipped, so both ``a/b`` and ``/a//b/`` are the same entry.
+    pub fn lookup(&self, path: &str) -> Option<Entry<'a>> {
+        path.split('/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self.root(), |entry, component| entry.find(component))
+    }
+
+    /// Get the contents of the file at ``path``.
+    pub fn read(&self, path: &str) -> Option<&'a [u8]> {
+        self.lookup(path)
+            .filter(Entry::is_file)
+            .map(|entry| entry.data())
+    }
+}
+
+impl Debug for RomFs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RomFs")
+            .field("size", &self.image.len())
+            .field("entries", &self.len())
+            .finish()
+    }
+}
+
+/// A file or a directory of an image.
+#[derive(Clone, Copy)]
+pub struct Entry<'a> {
+    fs: RomFs<'a>,
+    index: usize,
+}
+
+impl<'a> Entry<'a> {
+    /// Get the name of the entry, which is empty for the root directory.
+    pub fn name(&self) -> &'a str {
+        self.fs.name(self.index)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.fs.raw(self.index).kind == KIND_DIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    /// Get the contents of a file, straight from the image, or nothing for a directory.
+    pub fn data(&self) -> &'a [u8] {
+        let raw = self.fs.raw(self.index);
+        match raw.kind {
+            KIND_FILE => &self.fs.image[raw.start..raw.start + raw.count],
+            _ => &[],
+        }
+    }
+
+    /// Iterate over the children of a directory, sorted by name, or nothing for a file.
+    pub fn entries(&self) -> Entries<'a> {
+        let raw = self.fs.raw(self.index);
+        let (next, end) = match raw.kind {
+            KIND_DIR => (raw.start, raw.start + raw.count),
+            _ => (0, 0),
+        };
+        Entries {
+            fs: self.fs,
+            next,
+            end,
+        }
+    }
+
+    /// Find the child of a directory called ``name``.
+    pub fn find(&self, name: &str) -> Option<Entry<'a>> {
+        let Entries {
+            fs,
+            next: mut low,
+            end: mut high,
+        } = self.entries();
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match fs.name(middle).as_bytes().cmp(name.as_bytes()) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Some(fs.entry(middle)),
+            }
+        }
+        None
+    }
+}
+
+impl Debug for Entry<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Entry")
+            .field("name", &self.name())
+            .field("dir", &self.is_dir())
+            .field("len", &self.data().len())
+            .finish()
+    }
+}
+
+/// Iterator over the children of a directory, see [`Entry::entries`].
+#[derive(Clone, Debug)]
+pub struct Entries<'a> {
+    fs: RomFs<'a>,
+    next: usize,
+    end: usize,
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Entry<'a>;
+
+    fn next(&mut self) -> Option<Entry<'a>> {
+        if self.next == self.end {
+            return None;
+        }
+        self.next += 1;
+        Some(self.fs.entry(self.next - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.next;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Entries<'_> {}
@@ -0,0 +1,81 @@
+//! Packing of a directory of the host, built in a temporary directory for each test.
+
+use luma_romfs::RomFs;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A temporary directory, removed with its contents once dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "luma_romfs-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::create_dir(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn write(&self, path: &str, data: &[u8]) {
+        let path = self.0.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn pack_dir() {
+    let dir = TempDir::new();
+    dir.write("textures/font.tpl", &[0x00, 0x20, 0xaf, 0x30]);
+    dir.write("textures/cursor.tpl", &[1; 100]);
+    dir.write("music.ogg", b"OggS");
+    dir.write("levels/1/map.bin", &[]);
+    fs::create_dir(dir.0.join("empty")).unwrap();
+    std::os::unix::fs::symlink(dir.0.join("textures"), dir.0.join("linked")).unwrap();
+
+    let image = luma_romfs::pack_dir(&dir.0).unwrap();
+    let fs = RomFs::parse(&image).unwrap();
+    let names: Vec<_> = fs.root().entries().map(|entry| entry.name()).collect();
+    assert_eq!(
+        names,
+        ["empty", "levels", "linked", "music.ogg", "textures"]
+    );
+    assert_eq!(fs.read("music.ogg").unwrap(), b"OggS");
+    assert_eq!(fs.read("levels/1/map.bin").unwrap(), b"");
+    assert_eq!(fs.lookup("empty").unwrap().entries().count(), 0);
+    for textures in ["textures", "linked"] {
+        let names: Vec<_> = fs
+            .lookup(textures)
+            .unwrap()
+            .entries()
+            .map(|entry| entry.name())
+            .collect();
+        assert_eq!(names, ["cursor.tpl", "font.tpl"]);
+        assert_eq!(
+            fs.read(&format!("{textures}/font.tpl")).unwrap(),
+            [0x00, 0x20, 0xaf, 0x30]
+        );
+        assert_eq!(
+            fs.read(&format!("{textures}/cursor.tpl")).unwrap(),
+            [1; 100]
+        );
+    }
+}
+
+#[test]
+fn missing_dir() {
+    let dir = TempDir::new();
+    let error = luma_romfs::pack_dir(dir.0.join("missing")).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+}
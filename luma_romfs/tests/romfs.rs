@@ -0,0 +1,255 @@
+//! Round trips of images packed by a [`Builder`] through [`RomFs`], and rejection of images
+//! which are truncated or corrupted in every way ``RomFs::parse`` checks for.
+
+use luma_romfs::{ALIGNMENT, Builder, KIND_FILE, PackError, RomFs, RomFsError};
+
+/// Get ``len`` bytes which don’t repeat too soon.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i as u32 * 7 + seed as u32) as u8)
+        .collect()
+}
+
+/// Pack an image with the entries below, by index in the image:
+///
+/// 0. the root directory,
+/// 1. ``a.txt``,
+/// 2. ``dir``, holding 5 and 6,
+/// 3. ``empty``, a directory,
+/// 4. ``z``, an empty file,
+/// 5. ``dir/b.bin``,
+/// 6. ``dir/sub``, holding 7,
+/// 7. ``dir/sub/ünïcode``.
+fn image() -> Vec<u8> {
+    let mut builder = Builder::new();
+    builder
+        .add_file("dir/sub/ünïcode", pattern(100, 3))
+        .unwrap();
+    builder.add_file("/a.txt", "hello").unwrap();
+    builder.add_dir("empty").unwrap();
+    builder.add_file("z", []).unwrap();
+    builder.add_file("dir/b.bin", pattern(1000, 1)).unwrap();
+    builder.build().unwrap()
+}
+
+/// Get the offset of the entry at ``index`` in an image.
+fn entry(index: usize) -> usize {
+    16 + index * 16
+}
+
+#[test]
+fn round_trip() {
+    let image = image();
+    let fs = RomFs::parse(&image).unwrap();
+    assert_eq!(fs.image(), image);
+
+    assert_eq!(fs.read("a.txt").unwrap(), b"hello");
+    assert_eq!(fs.read("/dir//b.bin/").unwrap(), pattern(1000, 1));
+    assert_eq!(fs.read("dir/sub/ünïcode").unwrap(), pattern(100, 3));
+    assert_eq!(fs.read("z").unwrap(), b"");
+    // Directories have no contents, and lookups are exact.
+    assert_eq!(fs.read("dir"), None);
+    assert_eq!(fs.read("A.TXT"), None);
+    assert_eq!(fs.read("a.txt/b"), None);
+    assert!(fs.lookup("dir/missing").is_none());
+
+    let root = fs.root();
+    assert_eq!(root.name(), "");
+    assert!(root.is_dir());
+    assert_eq!(fs.lookup("/").unwrap().name(), "");
+    let names: Vec<_> = root.entries().map(|entry| entry.name()).collect();
+    assert_eq!(names, ["a.txt", "dir", "empty", "z"]);
+    assert_eq!(root.entries().len(), 4);
+
+    let dir = root.find("dir").unwrap();
+    assert!(dir.is_dir());
+    assert_eq!(dir.data(), b"");
+    let children: Vec<_> = dir
+        .entries()
+        .map(|entry| (entry.name(), entry.is_dir()))
+        .collect();
+    assert_eq!(children, [("b.bin", false), ("sub", true)]);
+    assert_eq!(fs.lookup("empty").unwrap().entries().count(), 0);
+
+    let file = fs.lookup("a.txt").unwrap();
+    assert!(file.is_file());
+    assert_eq!(file.entries().count(), 0);
+    assert!(file.find("a.txt").is_none());
+
+    // The contents of every file are aligned from the start of the image, and stay in place.
+    for path in ["a.txt", "z", "dir/b.bin", "dir/sub/ünïcode"] {
+        let data = fs.read(path).unwrap();
+        let offset = data.as_ptr() as usize - image.as_ptr() as usize;
+        assert_eq!(offset % ALIGNMENT, 0, "{path}");
+    }
+}
+
+#[test]
+fn empty_image() {
+    let image = Builder::new().build().unwrap();
+    assert_eq!(image.len(), 32);
+    let fs = RomFs::parse(&image).unwrap();
+    assert_eq!(fs.root().entries().count(), 0);
+    assert!(fs.lookup("anything").is_none());
+}
+
+#[test]
+fn many_entries() {
+    let mut builder = Builder::new();
+    for i in 0..300 {
+        builder
+            .add_file(&format!("dir {}/file {i:03}", i % 7), pattern(i, i as u8))
+            .unwrap();
+    }
+    let image = builder.build().unwrap();
+    let fs = RomFs::parse(&image).unwrap();
+    assert_eq!(fs.root().entries().len(), 7);
+    for i in 0..300 {
+        let path = format!("dir {}/file {i:03}", i % 7);
+        assert_eq!(fs.read(&path).unwrap(), pattern(i, i as u8), "{path}");
+    }
+    let names: Vec<_> = fs
+        .lookup("dir 3")
+        .unwrap()
+        .entries()
+        .map(|entry| entry.name())
+        .collect();
+    assert!(names.is_sorted());
+    assert_eq!(names.len(), 43);
+}
+
+#[test]
+fn builder_errors() {
+    let mut builder = Builder::new();
+    builder.add_file("dir/file", "data").unwrap();
+
+    for path in ["", "/", "//", ".", "dir/..", "../file", "dir/./file"] {
+        assert_eq!(
+            builder.add_file(path, "x"),
+            Err(PackError::InvalidPath),
+            "{path}"
+        );
+        assert_eq!(builder.add_dir(path), Err(PackError::InvalidPath), "{path}");
+    }
+    let long = "n".repeat(u16::MAX as usize + 1);
+    assert_eq!(builder.add_file(&long, "x"), Err(PackError::InvalidPath));
+    assert_eq!(
+        builder.add_file(&format!("{long}/file"), "x"),
+        Err(PackError::InvalidPath)
+    );
+
+    assert_eq!(
+        builder.add_file("dir/file", "again"),
+        Err(PackError::AlreadyExists)
+    );
+    assert_eq!(builder.add_file("dir", "x"), Err(PackError::AlreadyExists));
+    assert_eq!(builder.add_dir("dir/file"), Err(PackError::AlreadyExists));
+    assert_eq!(
+        builder.add_file("dir/file/below", "x"),
+        Err(PackError::NotADirectory)
+    );
+    assert_eq!(
+        builder.add_dir("dir/file/below"),
+        Err(PackError::NotADirectory)
+    );
+    // Adding an existing directory again is fine.
+    builder.add_dir("dir").unwrap();
+    builder.add_dir("/dir/").unwrap();
+
+    // Nothing of the failed additions made it in.
+    let image = builder.build().unwrap();
+    let fs = RomFs::parse(&image).unwrap();
+    assert_eq!(fs.root().entries().len(), 1);
+    assert_eq!(fs.lookup("dir").unwrap().entries().len(), 1);
+    assert_eq!(fs.read("dir/file").unwrap(), b"data");
+}
+
+#[test]
+fn truncated() {
+    let image = image();
+    assert_eq!(RomFs::parse(&[]).unwrap_err(), RomFsError::BadMagic);
+    assert_eq!(
+        RomFs::parse(&image[..15]).unwrap_err(),
+        RomFsError::BadMagic
+    );
+    // The last file ends the image, so losing any byte loses part of an entry, a name or some
+    // contents.
+    for len in 16..image.len() {
+        assert_eq!(
+            RomFs::parse(&image[..len]).unwrap_err(),
+            RomFsError::Corrupted,
+            "{len}"
+        );
+    }
+    // Trailing data gets ignored.
+    let mut longer = image.clone();
+    longer.extend_from_slice(&[0xff; 100]);
+    assert_eq!(
+        RomFs::parse(&longer).unwrap().read("a.txt").unwrap(),
+        b"hello"
+    );
+}
+
+/// Check that corrupting ``image`` with ``corrupt`` makes it get rejected with ``error``.
+fn assert_rejected(error: RomFsError, corrupt: impl FnOnce(&mut Vec<u8>)) {
+    let mut image = image();
+    corrupt(&mut image);
+    assert_eq!(RomFs::parse(&image).unwrap_err(), error);
+}
+
+fn set_u32(image: &mut [u8], offset: usize, value: u32) {
+    image[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+#[test]
+fn bad_header() {
+    assert_rejected(RomFsError::BadMagic, |image| image[0] = b'X');
+    assert_rejected(RomFsError::UnsupportedVersion, |image| image[5] = 2);
+    // No root directory.
+    assert_rejected(RomFsError::Corrupted, |image| set_u32(image, 8, 0));
+    // More entries, or a larger name table, than fit in the image.
+    assert_rejected(RomFsError::Corrupted, |image| set_u32(image, 8, u32::MAX));
+    assert_rejected(RomFsError::Corrupted, |image| set_u32(image, 12, u32::MAX));
+}
+
+#[test]
+fn bad_entries() {
+    // The root directory has to be a directory.
+    assert_rejected(RomFsError::Corrupted, |image| {
+        image[entry(0) + 6] = KIND_FILE
+    });
+    // An unknown kind.
+    assert_rejected(RomFsError::Corrupted, |image| image[entry(1) + 6] = 2);
+    // A name past the end of the name table.
+    assert_rejected(RomFsError::Corrupted, |image| {
+        set_u32(image, entry(7), 1000)
+    });
+    assert_rejected(RomFsError::Corrupted, |image| {
+        image[entry(7) + 4..entry(7) + 6].copy_from_slice(&u16::MAX.to_be_bytes())
+    });
+    // A name which isn’t UTF-8.
+    assert_rejected(RomFsError::Corrupted, |image| {
+        let names = entry(8);
+        image[names] = 0xff;
+    });
+    // Contents past the end of the image, or overflowing its offset.
+    assert_rejected(RomFsError::Corrupted, |image| {
+        let len = image.len() as u32;
+        set_u32(image, entry(5) + 12, len)
+    });
+    assert_rejected(RomFsError::Corrupted, |image| {
+        set_u32(image, entry(5) + 8, u32::MAX);
+        set_u32(image, entry(5) + 12, u32::MAX);
+    });
+    // Children past the last entry.
+    assert_rejected(RomFsError::Corrupted, |image| {
+        set_u32(image, entry(6) + 12, 2)
+    });
+    // Children before their parent, or the directory itself, which would loop forever.
+    assert_rejected(RomFsError::Corrupted, |image| {
+        set_u32(image, entry(2) + 8, 1)
+    });
+    assert_rejected(RomFsError::Corrupted, |image| {
+        set_u32(image, entry(6) + 8, 6)
+    });
+}
@@ -0,0 +1,121 @@
+//! ``gecko`` module of ``luma_runtime::gdb``.
+//!
+//! Contains a driver for the USB Gecko, an FTDI serial adapter plugged in a memory card slot
+//! and talked to one byte at a time through the EXI, by polling.
+
+use core::convert::Infallible;
+use embedded_io::{ErrorType, Read, Write};
+use luma_core::io::{read32, write32};
+use luma_core::mem::PhysAddr;
+
+/// Base of the registers of each EXI channel, of which there are five.
+const EXI_BASE: PhysAddr = PhysAddr::new(0x0d00_6800);
+const EXI_CHANNEL_SIZE: u32 = 0x14;
+
+const EXI_CSR: u32 = 0x00;
+const EXI_CR: u32 = 0x0c;
+const EXI_DATA: u32 = 0x10;
+
+/// Select the first device of the channel, at 32 MHz.
+const CSR_SELECT: u32 = 0x80 | 5 << 4;
+
+/// Exchange two bytes both ways, then start.
+const CR_EXCHANGE: u32 = 1 << 4 | 2 << 2 | 1;
+const CR_START: u32 = 1;
+
+const COMMAND_IDENTIFY: u32 = 0x9000_0000;
+const COMMAND_RECEIVE: u32 = 0xa000_0000;
+const COMMAND_SEND: u32 = 0xb000_0000;
+
+/// What the adapter answers to [`COMMAND_IDENTIFY`].
+const IDENTIFIER: u32 = 0x0470_0000;
+
+/// Set in the answer once a byte got sent, or when one got received.
+const SENT: u32 = 0x0400_0000;
+const RECEIVED: u32 = 0x0800_0000;
+
+/// Which memory card slot the adapter is in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+/// A USB Gecko, read and written through the ``embedded-io`` traits.
+///
+/// Reads wait for at least one byte to come, writes for every byte to be taken.
+#[derive(Debug)]
+pub struct UsbGecko {
+    base: PhysAddr,
+}
+
+impl UsbGecko {
+    /// Find the adapter in ``slot``, if it is there.
+    pub fn new(slot: Slot) -> Option<UsbGecko> {
+        let gecko = UsbGecko {
+            base: EXI_BASE + slot as u32 * EXI_CHANNEL_SIZE,
+        };
+        (gecko.exchange(COMMAND_IDENTIFY) == IDENTIFIER).then_some(gecko)
+    }
+
+    /// Exchange a command for its answer.
+    fn exchange(&self, command: u32) -> u32 {
+        write32(self.base + EXI_CSR, CSR_SELECT);
+        write32(self.base + EXI_DATA, command);
+        write32(self.base + EXI_CR, CR_EXCHANGE);
+        while read32(self.base + EXI_CR) & CR_START != 0 {}
+        let answer = read32(self.base + EXI_DATA);
+        write32(self.base + EXI_CSR, 0);
+        answer
+    }
+
+    /// Try to send a byte, which fails if the adapter has no room for it.
+    pub fn try_send(&self, byte: u8) -> bool {
+        self.exchange(COMMAND_SEND | (byte as u32) << 20) & SENT != 0
+    }
+
+    /// Try to receive a byte, if one came.
+    pub fn try_receive(&self) -> Option<u8> {
+        let answer = self.exchange(COMMAND_RECEIVE);
+        (answer & RECEIVED != 0).then_some((answer >> 16) as u8)
+    }
+}
+
+impl ErrorType for UsbGecko {
+    type Error = Infallible;
+}
+
+impl Read for UsbGecko {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Infallible> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        buffer[0] = loop {
+            if let Some(byte) = self.try_receive() {
+                break byte;
+            }
+        };
+        let mut read = 1;
+        while read < buffer.len() {
+            match self.try_receive() {
+                Some(byte) => buffer[read] = byte,
+                None => break,
+            }
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+impl Write for UsbGecko {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Infallible> {
+        for &byte in data {
+            while !self.try_send(byte) {}
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
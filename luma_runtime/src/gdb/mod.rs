@@ -0,0 +1,424 @@
+//! ``gdb`` module of ``luma_runtime``.
+//!
+//! Contains a stub of the GDB remote serial protocol, so that an app can be debugged on the
+//! console itself over a [`UsbGecko`] or a TCP connection, as it is in Dolphin.
+//!
+//! Once [`init`] got called, the program stops whenever it hits a breakpoint, finishes a
+//! single step, faults, or calls [`breakpoint`], and then waits for GDB to tell it what to do.
+//! Interrupting the program from GDB doesn’t work, nothing listening while it runs.
+//!
+//! The stub implements reading and writing registers and memory, software breakpoints,
+//! single-stepping and continuing, which is all GDB needs.  Registers are laid out as GDB
+//! expects for a plain ``powerpc`` target: the GPRs, the FPRs, then PC, MSR, CR, LR, CTR,
+//! XER and FPSCR.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use embedded_io::{Read, Write};
+use luma_core::cache::{DCFlushRange, ICInvalidateRange};
+use luma_core::exception::{self, Exception, ExceptionFrame, ExceptionHandler};
+use luma_core::interrupt::Mutex;
+use luma_core::mem::{MEM1_SIZE, MEM1_START, MEM2_SIZE, MEM2_START};
+
+mod gecko;
+
+pub use gecko::{Slot, UsbGecko};
+pub use luma_formats::gdb::packet;
+use packet::{Command, Decoder, Event};
+
+/// The ``trap`` instruction, breakpoints get patched in with.
+const TRAP: u32 = 0x7fe0_0008;
+
+/// Set in SRR1 when a program exception came from a trap.
+const SRR1_TRAP: u32 = 0x0002_0000;
+
+/// Single-step trace enable.
+const MSR_SE: u32 = 0x0000_0400;
+
+/// Register numbers of GDB past the GPRs and FPRs.
+const REGISTER_PC: u32 = 64;
+const REGISTER_FPSCR: u32 = 70;
+
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 7;
+const SIGSEGV: u8 = 11;
+
+/// The exceptions the stub stops on.
+const EXCEPTIONS: [Exception; 5] = [
+    Exception::Program,
+    Exception::Trace,
+    Exception::Dsi,
+    Exception::Isi,
+    Exception::Alignment,
+];
+
+/// The memory GDB may access: MEM1 and MEM2, cached and uncached, as their start and size.
+const MEMORY: [(u32, u32); 4] = [
+    (MEM1_START.cached().value(), MEM1_SIZE),
+    (MEM2_START.cached().value(), MEM2_SIZE),
+    (MEM1_START.uncached().value(), MEM1_SIZE),
+    (MEM2_START.uncached().value(), MEM2_SIZE),
+];
+
+/// Something GDB is at the other end of, which any ``embedded-io`` stream is, such as a
+/// [`UsbGecko`] or a ``luma_core::net::TcpStream``.
+pub trait Connection: Send {
+    /// Wait for a byte, getting ``None`` once the connection is gone.
+    fn receive(&mut self) -> Option<u8>;
+
+    /// Send ``data``, returning whether all of it went through.
+    fn send(&mut self, data: &[u8]) -> bool;
+}
+
+impl<T: Read + Write + Send> Connection for T {
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0];
+        match self.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            _ => None,
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> bool {
+        self.write_all(data).is_ok() && self.flush().is_ok()
+    }
+}
+
+static STUB: Mutex<Option<Stub>> = Mutex::new(None);
+
+/// Start debugging over ``connection``, replacing the handlers of the exceptions the stub
+/// stops on until GDB detaches.
+///
+/// Nothing gets sent until the program stops, [`breakpoint`] is the way to wait for GDB.
+pub fn init(connection: impl Connection + 'static) {
+    let previous = STUB.lock(|stub| stub.take().map(|stub| stub.previous));
+    let previous = previous.unwrap_or_else(|| {
+        EXCEPTIONS.map(|exception| exception::set_handler(exception, Some(on_exception)))
+    });
+    STUB.lock(|stub| {
+        *stub = Some(Stub {
+            connection: Box::new(connection),
+            decoder: Decoder::new(),
+            breakpoints: Vec::new(),
+            previous,
+        })
+    });
+}
+
+/// Stop, so that GDB gets control.
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { asm!("trap") };
+}
+
+fn on_exception(exception: Exception, frame: &mut ExceptionFrame) {
+    STUB.lock(|stub| {
+        if let Some(state) = stub
+            && !state.stopped(exception, frame)
+        {
+            state.detach();
+            *stub = None;
+        }
+    });
+}
+
+/// Set where the program resumes: at ``address`` if given, or past the trap at ``skip`` if it
+/// is still there.
+fn resume(frame: &mut ExceptionFrame, address: Option<u32>, skip: Option<u32>) {
+    match address {
+        Some(address) => frame.srr0 = address,
+        None if skip == Some(frame.srr0) => frame.srr0 += 4,
+        None => (),
+    }
+}
+
+/// Whether ``length`` bytes at ``address`` can be accessed without faulting.
+fn is_accessible(address: u32, length: u32) -> bool {
+    let Some(end) = address.checked_add(length) else {
+        return false;
+    };
+    MEMORY
+        .iter()
+        .any(|&(start, size)| address >= start && end <= start + size)
+}
+
+/// Make writes to ``length`` bytes at ``address`` visible to instruction fetches.
+fn sync_instructions(address: u32, length: u32) {
+    unsafe {
+        DCFlushRange(address as *const u32, length);
+        ICInvalidateRange(address as *const u32, length);
+    }
+}
+
+/// Get the value of a register, in target byte order.
+fn read_register(frame: &ExceptionFrame, register: u32) -> Option<[u8; 8]> {
+    let word = |value: u32| {
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&value.to_be_bytes());
+        bytes
+    };
+    Some(match register {
+        0..=31 => word(frame.gpr[register as usize]),
+        32..=63 => frame.fpr[register as usize - 32].to_bits().to_be_bytes(),
+        REGISTER_PC => word(frame.srr0),
+        65 => word(frame.srr1),
+        66 => word(frame.cr),
+        67 => word(frame.lr),
+        68 => word(frame.ctr),
+        69 => word(frame.xer),
+        REGISTER_FPSCR => word(frame.fpscr as u32),
+        _ => return None,
+    })
+}
+
+/// Get how many bytes a register takes.
+fn register_size(register: u32) -> usize {
+    match register {
+        32..=63 => 8,
+        _ => 4,
+    }
+}
+
+/// Set the value of a register from ``value``, in target byte order.
+fn write_register(frame: &mut ExceptionFrame, register: u32, value: &[u8]) -> bool {
+    if register > REGISTER_FPSCR || value.len() != register_size(register) {
+        return false;
+    }
+    if let 32..=63 = register {
+        let value = u64::from_be_bytes(value.try_into().unwrap());
+        frame.fpr[register as usize - 32] = f64::from_bits(value);
+        return true;
+    }
+    let value = u32::from_be_bytes(value.try_into().unwrap());
+    match register {
+        0..=31 => frame.gpr[register as usize] = value,
+        REGISTER_PC => frame.srr0 = value,
+        65 => frame.srr1 = value,
+        66 => frame.cr = value,
+        67 => frame.lr = value,
+        68 => frame.ctr = value,
+        69 => frame.xer = value,
+        _ => frame.fpscr = value as u64,
+    }
+    true
+}
+
+/// A software breakpoint, with the instruction it replaced.
+struct Breakpoint {
+    address: u32,
+    instruction: u32,
+}
+
+struct Stub {
+    connection: Box<dyn Connection>,
+    decoder: Decoder,
+    breakpoints: Vec<Breakpoint>,
+    /// The handlers replaced by [`init`], put back on detach.
+    previous: [Option<ExceptionHandler>; 5],
+}
+
+impl Stub {
+    /// Wait for a packet, acknowledging it, or get ``None`` once the connection is gone.
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        loop {
+            match self.decoder.feed(self.connection.receive()?) {
+                Some(Event::Packet(packet)) => {
+                    self.connection.send(b"+");
+                    return Some(packet);
+                }
+                Some(Event::BadChecksum) => {
+                    self.connection.send(b"-");
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Send a packet until GDB acknowledges it, returning whether the connection is still there.
+    fn send(&mut self, data: &[u8]) -> bool {
+        let mut packet = Vec::with_capacity(data.len() + 4);
+        packet::encode(data, &mut packet);
+        loop {
+            if !self.connection.send(&packet) {
+                return false;
+            }
+            loop {
+                let Some(byte) = self.connection.receive() else {
+                    return false;
+                };
+                match self.decoder.feed(byte) {
+                    Some(Event::Ack) => return true,
+                    Some(Event::Nack) => break,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    /// Talk to GDB until it resumes the program, returning false if it detached.
+    fn stopped(&mut self, exception: Exception, frame: &mut ExceptionFrame) -> bool {
+        frame.srr1 &= !MSR_SE;
+        let trap = exception == Exception::Program && frame.srr1 & SRR1_TRAP != 0;
+        let signal = match exception {
+            Exception::Program if !trap => SIGILL,
+            Exception::Dsi | Exception::Isi => SIGSEGV,
+            Exception::Alignment => SIGBUS,
+            _ => SIGTRAP,
+        };
+        // A trap compiled in, as by breakpoint(), gets stepped over on resume.
+        let stopped_at = frame.srr0;
+        let skip = (trap
+            && !self.breakpoints.iter().any(|b| b.address == stopped_at)
+            && is_accessible(stopped_at, 4)
+            && unsafe { (stopped_at as *const u32).read_volatile() } == TRAP)
+            .then_some(stopped_at);
+
+        let mut stop_reply = Vec::new();
+        stop_reply.push(b'S');
+        packet::push_hex(&mut stop_reply, &[signal]);
+        if !self.send(&stop_reply) {
+            return false;
+        }
+        loop {
+            let Some(packet) = self.receive() else {
+                return false;
+            };
+            let mut reply = Vec::new();
+            match Command::parse(&packet) {
+                Command::StopReason => reply = stop_reply.clone(),
+                Command::ReadRegisters => {
+                    for register in 0..=REGISTER_FPSCR {
+                        let value = read_register(frame, register).unwrap();
+                        packet::push_hex(&mut reply, &value[..register_size(register)]);
+                    }
+                }
+                Command::WriteRegisters(values) => {
+                    let mut values = values.as_slice();
+                    for register in 0..=REGISTER_FPSCR {
+                        let size = register_size(register);
+                        if values.len() < size {
+                            break;
+                        }
+                        write_register(frame, register, &values[..size]);
+                        values = &values[size..];
+                    }
+                    reply.extend_from_slice(b"OK");
+                }
+                Command::ReadRegister(register) => match read_register(frame, register) {
+                    Some(value) => packet::push_hex(&mut reply, &value[..register_size(register)]),
+                    None => reply.extend_from_slice(b"E01"),
+                },
+                Command::WriteRegister(register, value) => {
+                    if write_register(frame, register, &value) {
+                        reply.extend_from_slice(b"OK");
+                    } else {
+                        reply.extend_from_slice(b"E01");
+                    }
+                }
+                Command::ReadMemory { address, length } => {
+                    let length = length.min(packet::MAX_PACKET_SIZE as u32 / 2);
+                    if is_accessible(address, length) {
+                        for offset in 0..length {
+                            let byte = unsafe { ((address + offset) as *const u8).read_volatile() };
+                            packet::push_hex(&mut reply, &[byte]);
+                        }
+                    } else {
+                        reply.extend_from_slice(b"E14");
+                    }
+                }
+                Command::WriteMemory { address, data } => {
+                    if is_accessible(address, data.len() as u32) {
+                        for (offset, &byte) in data.iter().enumerate() {
+                            unsafe {
+                                ((address as usize + offset) as *mut u8).write_volatile(byte)
+                            };
+                        }
+                        sync_instructions(address, data.len() as u32);
+                        reply.extend_from_slice(b"OK");
+                    } else {
+                        reply.extend_from_slice(b"E14");
+                    }
+                }
+                Command::InsertBreakpoint(address) => {
+                    if self.insert_breakpoint(address) {
+                        reply.extend_from_slice(b"OK");
+                    } else {
+                        reply.extend_from_slice(b"E14");
+                    }
+                }
+                Command::RemoveBreakpoint(address) => {
+                    self.remove_breakpoint(address);
+                    reply.extend_from_slice(b"OK");
+                }
+                Command::Continue(address) => {
+                    resume(frame, address, skip);
+                    return true;
+                }
+                Command::Step(address) => {
+                    resume(frame, address, skip);
+                    frame.srr1 |= MSR_SE;
+                    return true;
+                }
+                Command::Detach => {
+                    self.send(b"OK");
+                    resume(frame, None, skip);
+                    return false;
+                }
+                Command::Kill => return false,
+                Command::Query(query) => {
+                    if query.starts_with(b"Supported") {
+                        reply.extend_from_slice(b"PacketSize=");
+                        packet::push_hex(
+                            &mut reply,
+                            &(packet::MAX_PACKET_SIZE as u16).to_be_bytes(),
+                        );
+                    } else if query == b"Attached" {
+                        reply.push(b'1');
+                    }
+                }
+                Command::SetThread => reply.extend_from_slice(b"OK"),
+                Command::Unsupported => (),
+            }
+            if !self.send(&reply) {
+                return false;
+            }
+        }
+    }
+
+    fn insert_breakpoint(&mut self, address: u32) -> bool {
+        if !address.is_multiple_of(4) || !is_accessible(address, 4) {
+            return false;
+        }
+        if self.breakpoints.iter().any(|b| b.address == address) {
+            return true;
+        }
+        let instruction = address as *mut u32;
+        self.breakpoints.push(Breakpoint {
+            address,
+            instruction: unsafe { instruction.read_volatile() },
+        });
+        unsafe { instruction.write_volatile(TRAP) };
+        sync_instructions(address, 4);
+        true
+    }
+
+    fn remove_breakpoint(&mut self, address: u32) {
+        if let Some(index) = self.breakpoints.iter().position(|b| b.address == address) {
+            let breakpoint = self.breakpoints.swap_remove(index);
+            unsafe { (address as *mut u32).write_volatile(breakpoint.instruction) };
+            sync_instructions(address, 4);
+        }
+    }
+
+    /// Remove every breakpoint and put the previous handlers back.
+    fn detach(&mut self) {
+        while let Some(breakpoint) = self.breakpoints.first() {
+            self.remove_breakpoint(breakpoint.address);
+        }
+        for (exception, handler) in EXCEPTIONS.into_iter().zip(self.previous) {
+            exception::set_handler(exception, handler);
+        }
+    }
+}
@@ -10,13 +10,16 @@
 
 extern crate alloc;
 
+// GDB Remote Stub
+pub mod gdb;
+
 use core::arch::global_asm;
 use core::fmt::Write;
 use core::panic::PanicInfo;
-use linked_list_allocator::LockedHeap;
 #[allow(unused_imports)]
 use luma_core::cache::*;
-use luma_core::println;
+use luma_core::heap::{self, Heap};
+use luma_core::{exception, interrupt, println};
 
 // Import linker symbols for allocator initialization.
 unsafe extern "C" {
@@ -24,9 +27,9 @@ unsafe extern "C" {
     pub static __stack_end: usize;
 }
 
-// Global Allocator based on ``linked_list_allocator``.
+// Global Allocator over the MEM1 and MEM2 heaps.
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Heap = Heap;
 
 // crt0 Implementation
 global_asm!(include_str!("../asm/crt0.S"));
@@ -40,19 +43,15 @@ fn start<T>(user_main: fn() -> T, _argc: isize, _argv: *const *const u8, _sigpip
 where
     T: Termination,
 {
-    // Coerce the linker symbols to usize for allocator init.
-    let stack_addr = unsafe { &__stack_addr } as *const _ as usize;
-    let stack_end = unsafe { &__stack_end } as *const _ as usize;
-
-    // Subtract the top of the stack from the bottom to get size.
-    let out_size = stack_addr - stack_end;
+    // Coerce the linker symbol to a pointer for allocator init, MEM1 being free past the stack.
+    let stack_addr = unsafe { &__stack_addr } as *const _ as *mut u8;
 
     // Setup the allocator before the user_main is called.
-    unsafe {
-        ALLOCATOR
-            .lock()
-            .init(stack_addr as *mut u8, 24 * 1024 * 1024 - out_size);
-    }
+    unsafe { heap::init(stack_addr) };
+
+    // Install the exception vectors, and start dispatching external interrupts.
+    exception::init();
+    interrupt::init();
 
     // Jump to user defined main function.
     user_main();
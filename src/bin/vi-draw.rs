@@ -85,11 +85,13 @@ fn main() {
     for row in xfb.iter_mut() {
         row.fill(0xff80);
     }
+    xfb.flush();
 
     // Then draw to it as fast as we can.
     let mut i = 0;
     loop {
         paint_pixels(xfb, 20, i);
+        xfb.flush();
         i += 1;
     }
 }